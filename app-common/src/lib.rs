@@ -0,0 +1,22 @@
+//! Common components of the DICOM-rs command line tools.
+//!
+//! This crate is not meant to be used outside of this project.
+//!
+//! - The `tls` module provides command line options
+//!   for setting up TLS secure transport connections
+//!   (requires the `tls` feature).
+//! - The [`query`] module parses query terms
+//!   (such as `PatientName=Doe^*`)
+//!   into query/retrieve identifiers.
+//! - The [`matching`] module matches query identifiers
+//!   against stored data sets.
+//! - The [`uid`] module generates new unique identifiers.
+
+pub mod matching;
+pub mod query;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod uid;
+
+#[cfg(feature = "tls")]
+pub use tls::{TlsClientOptions, TlsServerOptions};
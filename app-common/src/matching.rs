@@ -0,0 +1,508 @@
+//! Module for matching query identifiers against stored data sets.
+//!
+//! This implements the attribute matching rules of the query services
+//! (see PS3.4 C.2.2.2),
+//! shared by the service class providers of the project,
+//! such as `dicom-qrscp` and `dicom-mwlscp`:
+//!
+//! - _Universal matching_: an empty key matches any value,
+//!   even if the attribute is missing.
+//! - _Single value matching_:
+//!   the value must be equal to the key value.
+//!   Person names are compared without regard to case.
+//! - _Wild card matching_: `*` matches any sequence of characters
+//!   and `?` matches any single character,
+//!   in attributes with a textual value representation.
+//! - _Range matching_: `«start»-«end»`, `«start»-` and `-«end»`
+//!   match dates, times and date-times in the given range, inclusive.
+//! - _List of UID matching_: a key with multiple UIDs
+//!   matches any of them.
+//! - _Sequence matching_: the key item matches
+//!   if any of the items in the attribute matches all of its keys.
+
+use dicom_core::chrono::NaiveDateTime;
+use dicom_core::header::Header;
+use dicom_core::value::range::{
+    parse_date_range, parse_datetime_range_custom, parse_time_range, AsRange, DateTimeRange,
+    IgnoreTimeZone,
+};
+use dicom_core::value::{DataSetSequence, PreciseDateTime, Value};
+use dicom_core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom_dictionary_std::tags;
+use dicom_object::mem::InMemElement;
+use dicom_object::InMemDicomObject;
+
+/// Attributes of an identifier which are not matched against the data set.
+const NON_MATCHING_KEYS: &[Tag] = &[tags::SPECIFIC_CHARACTER_SET, tags::QUERY_RETRIEVE_LEVEL];
+
+/// Check whether the data set matches all keys of the given identifier.
+///
+/// The Specific Character Set and the Query/Retrieve Level
+/// are not matched.
+pub fn matches(candidate: &InMemDicomObject, identifier: &InMemDicomObject) -> bool {
+    identifier.iter().all(|key| {
+        let tag = key.tag();
+        tag.element() == 0
+            || NON_MATCHING_KEYS.contains(&tag)
+            || matches_key(candidate.get(tag), key)
+    })
+}
+
+/// Build the response identifier for a data set which matched the query,
+/// comprising the values of all keys in the identifier.
+///
+/// Keys without a value in the data set are returned empty.
+/// For sequence keys,
+/// only the items matching the key item are returned.
+/// The Query/Retrieve Level is copied from the identifier,
+/// and the Specific Character Set of the data set is always included.
+pub fn build_response(
+    candidate: &InMemDicomObject,
+    identifier: &InMemDicomObject,
+) -> InMemDicomObject {
+    let mut response = InMemDicomObject::new_empty();
+    if let Some(charset) = candidate.get(tags::SPECIFIC_CHARACTER_SET) {
+        response.put(charset.clone());
+    }
+
+    for key in identifier {
+        let tag = key.tag();
+        if tag.element() == 0 || tag == tags::SPECIFIC_CHARACTER_SET {
+            continue;
+        }
+        if tag == tags::QUERY_RETRIEVE_LEVEL {
+            response.put(key.clone());
+            continue;
+        }
+        match (key.value(), candidate.get(tag)) {
+            (Value::Sequence(sequence), Some(element)) => {
+                let Some(items) = element.items() else {
+                    response.put(DataElement::empty(tag, VR::SQ));
+                    continue;
+                };
+                let items: Vec<_> = match sequence.items().first() {
+                    Some(key_item) if !is_empty(key_item) => items
+                        .iter()
+                        .filter(|item| matches(item, key_item))
+                        .map(|item| build_response(item, key_item))
+                        .collect(),
+                    _ => items.to_vec(),
+                };
+                response.put(DataElement::new(tag, VR::SQ, DataSetSequence::from(items)));
+            }
+            (_, Some(element)) => {
+                response.put(element.clone());
+            }
+            (_, None) => {
+                response.put(DataElement::empty(tag, key.vr()));
+            }
+        }
+    }
+    response
+}
+
+/// Check whether an attribute of the data set (if present)
+/// matches the given key.
+fn matches_key(candidate: Option<&InMemElement>, key: &InMemElement) -> bool {
+    match key.value() {
+        Value::Sequence(sequence) => match sequence.items().first() {
+            // universal sequence matching
+            None => true,
+            Some(key_item) if is_empty(key_item) => true,
+            Some(key_item) => candidate
+                .and_then(|element| element.items())
+                .is_some_and(|items| items.iter().any(|item| matches(item, key_item))),
+        },
+        Value::PixelSequence(_) => true,
+        Value::Primitive(value) => {
+            let vr = key.vr();
+            let patterns = key_values(value);
+            if patterns
+                .iter()
+                .all(|pattern| is_universal(vr, pattern.as_str()))
+            {
+                return true;
+            }
+            let Some(candidate) = candidate else {
+                return false;
+            };
+            let values = candidate_values(candidate);
+            patterns.iter().any(|pattern| {
+                values
+                    .iter()
+                    .any(|value| matches_value(vr, pattern.as_str(), value.as_str()))
+            })
+        }
+    }
+}
+
+fn is_empty(item: &InMemDicomObject) -> bool {
+    item.iter().next().is_none()
+}
+
+/// Whether the value representation admits wild card matching.
+fn has_wild_cards(vr: VR) -> bool {
+    matches!(
+        vr,
+        VR::AE | VR::CS | VR::LO | VR::LT | VR::PN | VR::SH | VR::ST | VR::UC | VR::UR | VR::UT
+    )
+}
+
+/// Whether the key value matches any value.
+fn is_universal(vr: VR, pattern: &str) -> bool {
+    pattern.is_empty() || (has_wild_cards(vr) && pattern.chars().all(|c| c == '*'))
+}
+
+/// Collect the values of a key, without padding.
+fn key_values(value: &PrimitiveValue) -> Vec<String> {
+    value
+        .to_multi_str()
+        .iter()
+        .map(|v| trim_value(v).to_string())
+        .collect()
+}
+
+/// Collect the values of an attribute of the data set, without padding.
+fn candidate_values(element: &InMemElement) -> Vec<String> {
+    element
+        .to_multi_str()
+        .map(|values| values.iter().map(|v| trim_value(v).to_string()).collect())
+        .unwrap_or_default()
+}
+
+fn trim_value(value: &str) -> &str {
+    value.trim_matches(|c: char| c == ' ' || c == '\0')
+}
+
+/// Match a single value of a data set against a single key value.
+fn matches_value(vr: VR, pattern: &str, value: &str) -> bool {
+    match vr {
+        VR::DA | VR::TM | VR::DT if pattern.contains('-') => {
+            matches_range(vr, pattern, value).unwrap_or(pattern == value)
+        }
+        VR::PN if has_wild_card_chars(pattern) => {
+            matches_wild_card(&pattern.to_lowercase(), &value.to_lowercase())
+        }
+        VR::PN => pattern.to_lowercase() == value.to_lowercase(),
+        vr if has_wild_cards(vr) && has_wild_card_chars(pattern) => {
+            matches_wild_card(pattern, value)
+        }
+        _ => pattern == value,
+    }
+}
+
+fn has_wild_card_chars(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Match a date, time or date-time against a range.
+///
+/// Returns `None` if the key is not a valid range,
+/// in which case single value matching applies.
+/// Values which cannot be parsed do not match.
+fn matches_range(vr: VR, pattern: &str, value: &str) -> Option<bool> {
+    let value = PrimitiveValue::from(value);
+    match vr {
+        VR::DA => {
+            let range = parse_date_range(pattern.as_bytes()).ok()?;
+            let Ok(Ok(date)) = value.to_date().map(|date| date.earliest()) else {
+                return Some(false);
+            };
+            Some(in_range(&date, range.start(), range.end()))
+        }
+        VR::TM => {
+            let range = parse_time_range(pattern.as_bytes()).ok()?;
+            let Ok(Ok(time)) = value.to_time().map(|time| time.earliest()) else {
+                return Some(false);
+            };
+            Some(in_range(&time, range.start(), range.end()))
+        }
+        VR::DT => {
+            let range = parse_datetime_range_custom::<IgnoreTimeZone>(pattern.as_bytes()).ok()?;
+            let Ok(Ok(datetime)) = value.to_datetime().map(|datetime| datetime.earliest()) else {
+                return Some(false);
+            };
+            let datetime = naive_datetime(datetime);
+            let (start, end) = match range {
+                DateTimeRange::Naive { start, end } => (start, end),
+                DateTimeRange::TimeZone { start, end } => (
+                    start.map(|start| start.naive_local()),
+                    end.map(|end| end.naive_local()),
+                ),
+            };
+            Some(in_range(&datetime, start.as_ref(), end.as_ref()))
+        }
+        _ => None,
+    }
+}
+
+fn naive_datetime(datetime: PreciseDateTime) -> NaiveDateTime {
+    match datetime {
+        PreciseDateTime::Naive(datetime) => datetime,
+        PreciseDateTime::TimeZone(datetime) => datetime.naive_local(),
+    }
+}
+
+fn in_range<T: PartialOrd>(value: &T, start: Option<&T>, end: Option<&T>) -> bool {
+    start.map_or(true, |start| value >= start) && end.map_or(true, |end| value <= end)
+}
+
+/// Match a value against a pattern with wild cards,
+/// where `*` matches any sequence of characters
+/// and `?` matches any single character.
+fn matches_wild_card(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    let (mut p, mut v) = (0, 0);
+    // position of the last `*` in the pattern
+    // and of the value when it was reached
+    let mut backtrack = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    // let the last `*` match one more character
+                    p = star + 1;
+                    v = matched + 1;
+                    backtrack = Some((star, v));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_response, matches, matches_wild_card};
+    use dicom_core::value::DataSetSequence;
+    use dicom_core::{dicom_value, DataElement, PrimitiveValue, VR};
+    use dicom_dictionary_std::tags;
+    use dicom_object::InMemDicomObject;
+
+    fn patient() -> InMemDicomObject {
+        InMemDicomObject::from_element_iter([
+            DataElement::new(tags::PATIENT_NAME, VR::PN, "Doe^John"),
+            DataElement::new(tags::PATIENT_ID, VR::LO, "P0001"),
+            DataElement::new(tags::STUDY_DATE, VR::DA, "20240315"),
+            DataElement::new(tags::STUDY_TIME, VR::TM, "101530.25"),
+            DataElement::new(tags::STUDY_INSTANCE_UID, VR::UI, "1.2.3.4"),
+            DataElement::new(
+                tags::MODALITIES_IN_STUDY,
+                VR::CS,
+                dicom_value!(Strs, ["CT", "SR"]),
+            ),
+            DataElement::new(
+                tags::SCHEDULED_PROCEDURE_STEP_SEQUENCE,
+                VR::SQ,
+                DataSetSequence::from(vec![
+                    InMemDicomObject::from_element_iter([
+                        DataElement::new(tags::MODALITY, VR::CS, "MR"),
+                        DataElement::new(tags::SCHEDULED_STATION_AE_TITLE, VR::AE, "MR1"),
+                    ]),
+                    InMemDicomObject::from_element_iter([
+                        DataElement::new(tags::MODALITY, VR::CS, "CT"),
+                        DataElement::new(tags::SCHEDULED_STATION_AE_TITLE, VR::AE, "CT1"),
+                    ]),
+                ]),
+            ),
+        ])
+    }
+
+    fn query(
+        elements: impl IntoIterator<Item = (dicom_core::Tag, VR, &'static str)>,
+    ) -> InMemDicomObject {
+        InMemDicomObject::from_element_iter(
+            elements
+                .into_iter()
+                .map(|(tag, vr, value)| DataElement::new(tag, vr, PrimitiveValue::from(value))),
+        )
+    }
+
+    #[test]
+    fn universal_and_single_value_matching() {
+        let candidate = patient();
+        assert!(matches(&candidate, &query([])));
+        assert!(matches(
+            &candidate,
+            &query([(tags::PATIENT_ID, VR::LO, "")])
+        ));
+        assert!(matches(
+            &candidate,
+            &query([(tags::ACCESSION_NUMBER, VR::SH, "")])
+        ));
+        assert!(matches(
+            &candidate,
+            &query([(tags::PATIENT_ID, VR::LO, "P0001")])
+        ));
+        assert!(!matches(
+            &candidate,
+            &query([(tags::PATIENT_ID, VR::LO, "P0002")])
+        ));
+        assert!(!matches(
+            &candidate,
+            &query([(tags::ACCESSION_NUMBER, VR::SH, "A1")])
+        ));
+        // person names are case insensitive
+        assert!(matches(
+            &candidate,
+            &query([(tags::PATIENT_NAME, VR::PN, "DOE^JOHN")])
+        ));
+        // multi-valued attributes match on any value
+        assert!(matches(
+            &candidate,
+            &query([(tags::MODALITIES_IN_STUDY, VR::CS, "SR")])
+        ));
+        // the query/retrieve level is not a matching key
+        assert!(matches(
+            &candidate,
+            &query([(tags::QUERY_RETRIEVE_LEVEL, VR::CS, "STUDY")])
+        ));
+    }
+
+    #[test]
+    fn wild_card_matching() {
+        let candidate = patient();
+        assert!(matches(
+            &candidate,
+            &query([(tags::PATIENT_NAME, VR::PN, "doe*")])
+        ));
+        assert!(matches(
+            &candidate,
+            &query([(tags::PATIENT_ID, VR::LO, "P?00*")])
+        ));
+        assert!(matches(
+            &candidate,
+            &query([(tags::ACCESSION_NUMBER, VR::SH, "*")])
+        ));
+        assert!(!matches(
+            &candidate,
+            &query([(tags::PATIENT_ID, VR::LO, "Q*")])
+        ));
+        // not applicable to UIDs
+        assert!(!matches(
+            &candidate,
+            &query([(tags::STUDY_INSTANCE_UID, VR::UI, "1.2.*")])
+        ));
+
+        assert!(matches_wild_card("*a*b?", "xxaybz"));
+        assert!(matches_wild_card("a**", "a"));
+        assert!(!matches_wild_card("a*b", "acbd"));
+        assert!(!matches_wild_card("?", ""));
+    }
+
+    #[test]
+    fn range_matching() {
+        let candidate = patient();
+        for (range, expected) in [
+            ("20240301-20240331", true),
+            ("20240315-20240315", true),
+            ("20240316-", false),
+            ("-20240315", true),
+            ("2023-2024", true),
+            ("20240101-20240314", false),
+        ] {
+            assert_eq!(
+                matches(&candidate, &query([(tags::STUDY_DATE, VR::DA, range)])),
+                expected,
+                "{}",
+                range
+            );
+        }
+        assert!(matches(
+            &candidate,
+            &query([(tags::STUDY_TIME, VR::TM, "10-11")])
+        ));
+        assert!(!matches(
+            &candidate,
+            &query([(tags::STUDY_TIME, VR::TM, "1016-")])
+        ));
+
+        let candidate = query([(tags::ACQUISITION_DATE_TIME, VR::DT, "20240315101530")]);
+        assert!(matches(
+            &candidate,
+            &query([(tags::ACQUISITION_DATE_TIME, VR::DT, "20240315-20240316")])
+        ));
+        assert!(!matches(
+            &candidate,
+            &query([(tags::ACQUISITION_DATE_TIME, VR::DT, "2024031511-")])
+        ));
+    }
+
+    #[test]
+    fn uid_list_matching() {
+        let candidate = patient();
+        let key = |[first, second]: [&str; 2]| {
+            InMemDicomObject::from_element_iter([DataElement::new(
+                tags::STUDY_INSTANCE_UID,
+                VR::UI,
+                dicom_value!(Strs, [first, second]),
+            )])
+        };
+        assert!(matches(&candidate, &key(["1.2.3.5", "1.2.3.4"])));
+        assert!(!matches(&candidate, &key(["1.2.3.5", "1.2.3.6"])));
+    }
+
+    #[test]
+    fn sequence_matching() {
+        let candidate = patient();
+        let key = |modality: &'static str, ae_title: &'static str| {
+            InMemDicomObject::from_element_iter([DataElement::new(
+                tags::SCHEDULED_PROCEDURE_STEP_SEQUENCE,
+                VR::SQ,
+                DataSetSequence::from(vec![query([
+                    (tags::MODALITY, VR::CS, modality),
+                    (tags::SCHEDULED_STATION_AE_TITLE, VR::AE, ae_title),
+                ])]),
+            )])
+        };
+        assert!(matches(&candidate, &key("CT", "")));
+        assert!(matches(&candidate, &key("MR", "MR1")));
+        // all keys must match in the same item
+        assert!(!matches(&candidate, &key("MR", "CT1")));
+        assert!(!matches(&candidate, &key("US", "")));
+
+        // only the matching items are returned
+        let response = build_response(&candidate, &key("CT", ""));
+        let items = response
+            .get(tags::SCHEDULED_PROCEDURE_STEP_SEQUENCE)
+            .and_then(|e| e.items())
+            .unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(
+            items[0]
+                .get(tags::SCHEDULED_STATION_AE_TITLE)
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "CT1"
+        );
+    }
+
+    #[test]
+    fn response_with_requested_keys() {
+        let candidate = patient();
+        let identifier = query([
+            (tags::QUERY_RETRIEVE_LEVEL, VR::CS, "STUDY"),
+            (tags::PATIENT_NAME, VR::PN, "Doe*"),
+            (tags::STUDY_INSTANCE_UID, VR::UI, ""),
+            (tags::ACCESSION_NUMBER, VR::SH, ""),
+        ]);
+        let response = build_response(&candidate, &identifier);
+        let value = |tag| response.get(tag).unwrap().to_str().unwrap().into_owned();
+        assert_eq!(value(tags::QUERY_RETRIEVE_LEVEL), "STUDY");
+        assert_eq!(value(tags::PATIENT_NAME), "Doe^John");
+        assert_eq!(value(tags::STUDY_INSTANCE_UID), "1.2.3.4");
+        assert_eq!(value(tags::ACCESSION_NUMBER), "");
+        assert!(response.get(tags::PATIENT_ID).is_none());
+    }
+}
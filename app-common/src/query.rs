@@ -1,4 +1,7 @@
 //! Module for parsing query text pieces into DICOM queries.
+//!
+//! This is the query syntax shared by the query/retrieve tools,
+//! such as `dicom-findscu` and `dicom-movescu`.
 
 use std::str::FromStr;
 
@@ -42,6 +45,8 @@ impl FromStr for TermQuery {
     }
 }
 
+/// Apply a sequence of term queries of the form `«tag»=«value»`
+/// to the given query object.
 pub fn parse_queries<T>(base: InMemDicomObject, qs: &[T]) -> Result<InMemDicomObject, Whatever>
 where
     T: AsRef<str>,
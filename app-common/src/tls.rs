@@ -0,0 +1,292 @@
+//! Command line options for TLS secure transport connections.
+//!
+//! Certificates and private keys are read from PEM files.
+//! Tools acting as an association requester
+//! should include [`TlsClientOptions`] in their arguments,
+//! whereas association acceptors should include [`TlsServerOptions`].
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::Args;
+use rustls::{
+    crypto::CryptoProvider,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    ClientConfig, RootCertStore, ServerConfig,
+};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+#[non_exhaustive]
+pub enum Error {
+    #[snafu(display("missing option `{}` for TLS", option))]
+    MissingOption { option: &'static str },
+
+    #[snafu(display("could not read PEM file {}", path.display()))]
+    ReadPem {
+        path: PathBuf,
+        source: rustls::pki_types::pem::Error,
+    },
+
+    #[snafu(display("no certificates found in {}", path.display()))]
+    NoCertificates { path: PathBuf },
+
+    /// invalid trusted certificate
+    AddTrustedCertificate { source: rustls::Error },
+
+    /// could not set up the client certificate verifier
+    ClientVerifier {
+        source: rustls::server::VerifierBuilderError,
+    },
+
+    /// invalid TLS configuration
+    Config { source: rustls::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// TLS options for a node requesting associations (SCU).
+#[derive(Debug, Clone, Args)]
+pub struct TlsClientOptions {
+    /// Connect to the SCP over TLS secure transport
+    #[arg(long = "tls")]
+    pub tls: bool,
+    /// PEM file with the certificate authorities
+    /// trusted for verifying the SCP's certificate
+    #[arg(
+        long = "tls-ca",
+        value_name = "FILE",
+        requires = "tls",
+        required_if_eq("tls", "true")
+    )]
+    pub ca_file: Option<PathBuf>,
+    /// PEM file with the certificate chain of this node,
+    /// for authenticating to the SCP (mutual TLS)
+    #[arg(
+        long = "tls-cert",
+        value_name = "FILE",
+        requires = "tls",
+        requires = "key_file"
+    )]
+    pub cert_file: Option<PathBuf>,
+    /// PEM file with the private key of this node's certificate
+    #[arg(
+        long = "tls-key",
+        value_name = "FILE",
+        requires = "tls",
+        requires = "cert_file"
+    )]
+    pub key_file: Option<PathBuf>,
+    /// Name to verify the SCP's certificate against
+    /// [default: the host in the SCP address]
+    #[arg(long = "tls-server-name", value_name = "NAME", requires = "tls")]
+    pub server_name: Option<String>,
+}
+
+impl TlsClientOptions {
+    /// Build the TLS client configuration,
+    /// or `None` if TLS was not requested.
+    pub fn client_config(&self) -> Result<Option<Arc<ClientConfig>>> {
+        if !self.tls {
+            return Ok(None);
+        }
+        let ca_file = self
+            .ca_file
+            .as_ref()
+            .context(MissingOptionSnafu { option: "--tls-ca" })?;
+
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .context(ConfigSnafu)?
+            .with_root_certificates(read_trusted_certificates(ca_file)?);
+
+        let config = match (&self.cert_file, &self.key_file) {
+            (Some(cert_file), Some(key_file)) => builder
+                .with_client_auth_cert(read_certificates(cert_file)?, read_key(key_file)?)
+                .context(ConfigSnafu)?,
+            _ => builder.with_no_client_auth(),
+        };
+        Ok(Some(Arc::new(config)))
+    }
+
+    /// Determine the name to verify the server's certificate against,
+    /// given the address of the SCP
+    /// (optionally prefixed by an AE title, as in `STORE-SCP@pacs:104`).
+    pub fn server_name<'a>(&'a self, addr: &'a str) -> &'a str {
+        if let Some(server_name) = &self.server_name {
+            return server_name;
+        }
+        let host = addr.split_once('@').map(|(_, host)| host).unwrap_or(addr);
+        let host = match host.rsplit_once(':') {
+            Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+            _ => host,
+        };
+        host.trim_start_matches('[').trim_end_matches(']')
+    }
+}
+
+/// TLS options for a node accepting associations (SCP).
+#[derive(Debug, Clone, Args)]
+pub struct TlsServerOptions {
+    /// Accept associations over TLS secure transport
+    #[arg(long = "tls")]
+    pub tls: bool,
+    /// PEM file with the certificate chain of this node
+    #[arg(
+        long = "tls-cert",
+        value_name = "FILE",
+        requires = "tls",
+        required_if_eq("tls", "true")
+    )]
+    pub cert_file: Option<PathBuf>,
+    /// PEM file with the private key of this node's certificate
+    #[arg(
+        long = "tls-key",
+        value_name = "FILE",
+        requires = "tls",
+        required_if_eq("tls", "true")
+    )]
+    pub key_file: Option<PathBuf>,
+    /// PEM file with the certificate authorities
+    /// trusted for verifying the certificates of SCUs.
+    /// If given, SCUs are required to present a certificate (mutual TLS)
+    #[arg(long = "tls-ca", value_name = "FILE", requires = "tls")]
+    pub ca_file: Option<PathBuf>,
+}
+
+impl TlsServerOptions {
+    /// Build the TLS server configuration,
+    /// or `None` if TLS was not requested.
+    pub fn server_config(&self) -> Result<Option<Arc<ServerConfig>>> {
+        if !self.tls {
+            return Ok(None);
+        }
+        let cert_file = self.cert_file.as_ref().context(MissingOptionSnafu {
+            option: "--tls-cert",
+        })?;
+        let key_file = self.key_file.as_ref().context(MissingOptionSnafu {
+            option: "--tls-key",
+        })?;
+
+        let builder = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .context(ConfigSnafu)?;
+        let builder = match &self.ca_file {
+            Some(ca_file) => builder.with_client_cert_verifier(
+                WebPkiClientVerifier::builder_with_provider(
+                    read_trusted_certificates(ca_file)?,
+                    provider(),
+                )
+                .build()
+                .context(ClientVerifierSnafu)?,
+            ),
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(read_certificates(cert_file)?, read_key(key_file)?)
+            .context(ConfigSnafu)?;
+        Ok(Some(Arc::new(config)))
+    }
+}
+
+/// The cryptography provider for all TLS configurations,
+/// set explicitly so as to not depend on the process-wide default.
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn read_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|it| it.collect::<Result<Vec<_>, _>>())
+        .context(ReadPemSnafu { path })?;
+    ensure!(!certificates.is_empty(), NoCertificatesSnafu { path });
+    Ok(certificates)
+}
+
+fn read_trusted_certificates(path: &Path) -> Result<Arc<RootCertStore>> {
+    let mut roots = RootCertStore::empty();
+    for certificate in read_certificates(path)? {
+        roots.add(certificate).context(AddTrustedCertificateSnafu)?;
+    }
+    Ok(Arc::new(roots))
+}
+
+fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).context(ReadPemSnafu { path })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TlsClientOptions, TlsServerOptions};
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    struct Client {
+        #[command(flatten)]
+        tls: TlsClientOptions,
+    }
+
+    #[derive(Debug, Parser)]
+    struct Server {
+        #[command(flatten)]
+        tls: TlsServerOptions,
+    }
+
+    #[test]
+    fn verify_cli() {
+        use clap::CommandFactory;
+        Client::command().debug_assert();
+        Server::command().debug_assert();
+    }
+
+    #[test]
+    fn tls_options_are_checked() {
+        assert!(Client::try_parse_from(["client"]).is_ok());
+        assert!(Client::try_parse_from(["client", "--tls"]).is_err());
+        assert!(Client::try_parse_from(["client", "--tls-ca", "ca.pem"]).is_err());
+        assert!(Client::try_parse_from(["client", "--tls", "--tls-ca", "ca.pem"]).is_ok());
+        assert!(Client::try_parse_from([
+            "client",
+            "--tls",
+            "--tls-ca",
+            "ca.pem",
+            "--tls-cert",
+            "scu.pem"
+        ])
+        .is_err());
+
+        assert!(Server::try_parse_from(["server", "--tls", "--tls-cert", "scp.pem"]).is_err());
+        assert!(Server::try_parse_from([
+            "server",
+            "--tls",
+            "--tls-cert",
+            "scp.pem",
+            "--tls-key",
+            "scp.key"
+        ])
+        .is_ok());
+    }
+
+    #[test]
+    fn server_name_from_address() {
+        let tls = Client::parse_from(["client"]).tls;
+        assert_eq!(tls.server_name("pacs.example.com:104"), "pacs.example.com");
+        assert_eq!(
+            tls.server_name("STORE-SCP@pacs.example.com:104"),
+            "pacs.example.com"
+        );
+        assert_eq!(tls.server_name("10.0.0.100:2762"), "10.0.0.100");
+        assert_eq!(tls.server_name("[::1]:2762"), "::1");
+
+        let tls = Client::parse_from([
+            "client",
+            "--tls",
+            "--tls-ca",
+            "ca.pem",
+            "--tls-server-name",
+            "pacs",
+        ])
+        .tls;
+        assert_eq!(tls.server_name("10.0.0.100:2762"), "pacs");
+    }
+}
@@ -0,0 +1,33 @@
+//! Generation of new unique identifiers.
+//!
+//! The UIDs are derived from random (version 4) UUIDs
+//! under the `2.25` root,
+//! as described in PS3.5 B.2,
+//! so that no organizational UID root is needed.
+
+/// Generate a new UID,
+/// such as a SOP Instance UID or a Transaction UID.
+pub fn new_uid() -> String {
+    let mut uuid: u128 = rand::random();
+    // UUID version 4
+    uuid = (uuid & !(0xF << 76)) | (0x4 << 76);
+    // RFC 4122 variant
+    uuid = (uuid & !(0x3 << 62)) | (0x2 << 62);
+    format!("2.25.{}", uuid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::new_uid;
+
+    #[test]
+    fn new_uids_are_valid_and_distinct() {
+        let uid = new_uid();
+        assert!(uid.starts_with("2.25."));
+        assert!(uid.len() <= 64);
+        assert!(uid[5..].bytes().all(|c| c.is_ascii_digit()));
+        assert!(!uid[5..].starts_with('0'));
+
+        assert_ne!(uid, new_uid());
+    }
+}
@@ -0,0 +1,726 @@
+//! DICOM Storage Commitment SCU
+//!
+//! This library implements the messages of the
+//! Storage Commitment Push Model SOP class (PS3.4 Annex J).
+//! A storage commitment request is sent to the SCP
+//! in an N-ACTION request ([`request_commitment`]).
+//! The SCP verifies which of the instances referenced it holds
+//! and later reports the result in an N-EVENT-REPORT request,
+//! which arrives either on the same association ([`receive_result`])
+//! or on a new association initiated by the SCP ([`accept_result`]).
+//!
+//! The data set types and [`send_result`] are also used by `dicom-storescp`
+//! to answer storage commitment requests.
+//!
+//! This crate is not meant to be used outside of this project.
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use dicom_core::{dicom_value, value::DataSetSequence, DataElement, Tag, VR};
+use dicom_dictionary_std::{tags, uids};
+use dicom_encoding::{transfer_syntax::TransferSyntaxIndex, TransferSyntax};
+use dicom_object::InMemDicomObject;
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use dicom_ul::{
+    association::{
+        client::{ClientAssociation, ClientAssociationOptions, CloseSocket},
+        server::{AcceptAny, AccessControl, ServerAssociationOptions},
+    },
+    dimse::{Command, CommandField, Message, NActionRQ, NEventReportRQ, NEventReportRSP, Status},
+    pdu::PresentationContextResult,
+};
+use snafu::{OptionExt, ResultExt, Snafu};
+
+/// The Action Type ID of the _Request Storage Commitment_ action
+pub const REQUEST_STORAGE_COMMITMENT: u16 = 1;
+/// The Event Type ID of a result in which all instances were committed
+pub const STORAGE_COMMITMENT_REQUEST_SUCCESSFUL: u16 = 1;
+/// The Event Type ID of a result with one or more failures
+pub const STORAGE_COMMITMENT_REQUEST_COMPLETE_FAILURES_EXIST: u16 = 2;
+
+/// The values of Failure Reason (0008,1197)
+/// for instances which could not be committed.
+pub mod failure_reason {
+    /// A general failure in processing the operation was encountered
+    pub const PROCESSING_FAILURE: u16 = 0x0110;
+    /// One or more of the elements in the Referenced SOP Instance Sequence
+    /// was not available
+    pub const NO_SUCH_OBJECT_INSTANCE: u16 = 0x0112;
+    /// The SCP does not currently have enough resources
+    /// to store the requested SOP instance(s)
+    pub const RESOURCE_LIMITATION: u16 = 0x0213;
+    /// Storage commitment has been requested for a SOP instance
+    /// with a SOP class that is not supported by the SCP
+    pub const REFERENCED_SOP_CLASS_NOT_SUPPORTED: u16 = 0x0122;
+    /// The SOP class of an element in the Referenced SOP Instance Sequence
+    /// did not correspond to the SOP class registered for this SOP instance
+    pub const CLASS_INSTANCE_CONFLICT: u16 = 0x0119;
+    /// The Transaction UID of the storage commitment request
+    /// is already in use
+    pub const DUPLICATE_TRANSACTION_UID: u16 = 0x0131;
+}
+
+#[derive(Debug, Snafu)]
+#[non_exhaustive]
+pub enum Error {
+    /// missing attribute in storage commitment data set
+    #[snafu(display("missing attribute {}", name))]
+    MissingAttribute { name: &'static str },
+
+    /// invalid attribute in storage commitment data set
+    #[snafu(display("invalid value of attribute {}", name))]
+    InvalidAttribute {
+        name: &'static str,
+        source: dicom_core::value::ConvertValueError,
+    },
+
+    /// no presentation context accepted for storage commitment
+    NoPresentationContext,
+
+    /// unsupported transfer syntax
+    #[snafu(display("unsupported transfer syntax {}", uid))]
+    UnsupportedTransferSyntax { uid: String },
+
+    /// could not encode storage commitment data set
+    WriteDataSet {
+        source: Box<dicom_object::WriteError>,
+    },
+
+    /// could not decode storage commitment data set
+    ReadDataSet {
+        source: Box<dicom_object::ReadError>,
+    },
+
+    /// missing data set in message
+    #[snafu(display("missing data set in {:?} message", command_field))]
+    MissingDataSet { command_field: CommandField },
+
+    /// could not send message
+    Send {
+        source: Box<dicom_ul::association::client::Error>,
+    },
+
+    /// could not receive message
+    Receive {
+        source: Box<dicom_ul::association::client::Error>,
+    },
+
+    /// could not accept association or exchange messages in it
+    Accept {
+        source: Box<dicom_ul::association::server::Error>,
+    },
+
+    /// unexpected message
+    #[snafu(display("unexpected {:?} message", command_field))]
+    UnexpectedMessage { command_field: CommandField },
+
+    /// association released without a storage commitment result
+    NoResult,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A reference to a SOP instance.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SopReference {
+    /// The Referenced SOP Class UID
+    pub sop_class_uid: String,
+    /// The Referenced SOP Instance UID
+    pub sop_instance_uid: String,
+}
+
+impl SopReference {
+    /// Create a new reference to a SOP instance.
+    pub fn new(sop_class_uid: impl Into<String>, sop_instance_uid: impl Into<String>) -> Self {
+        SopReference {
+            sop_class_uid: sop_class_uid.into(),
+            sop_instance_uid: sop_instance_uid.into(),
+        }
+    }
+
+    fn to_item(&self) -> InMemDicomObject {
+        InMemDicomObject::from_element_iter([
+            DataElement::new(
+                tags::REFERENCED_SOP_CLASS_UID,
+                VR::UI,
+                dicom_value!(Str, self.sop_class_uid.as_str()),
+            ),
+            DataElement::new(
+                tags::REFERENCED_SOP_INSTANCE_UID,
+                VR::UI,
+                dicom_value!(Str, self.sop_instance_uid.as_str()),
+            ),
+        ])
+    }
+
+    fn from_item(item: &InMemDicomObject) -> Result<Self> {
+        Ok(SopReference {
+            sop_class_uid: get_str(
+                item,
+                tags::REFERENCED_SOP_CLASS_UID,
+                "ReferencedSOPClassUID",
+            )?,
+            sop_instance_uid: get_str(
+                item,
+                tags::REFERENCED_SOP_INSTANCE_UID,
+                "ReferencedSOPInstanceUID",
+            )?,
+        })
+    }
+}
+
+/// A reference to a SOP instance which could not be committed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FailedSopReference {
+    /// The SOP instance referenced
+    pub reference: SopReference,
+    /// The reason for the failure,
+    /// one of the values in [`failure_reason`]
+    pub failure_reason: u16,
+}
+
+/// A storage commitment request,
+/// as conveyed in the data set of an N-ACTION request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitmentRequest {
+    /// The Transaction UID,
+    /// which identifies the request in the result
+    pub transaction_uid: String,
+    /// The SOP instances for which storage commitment is requested
+    pub references: Vec<SopReference>,
+}
+
+impl CommitmentRequest {
+    /// Build the data set of the N-ACTION request.
+    pub fn to_data_set(&self) -> InMemDicomObject {
+        InMemDicomObject::from_element_iter([
+            DataElement::new(
+                tags::TRANSACTION_UID,
+                VR::UI,
+                dicom_value!(Str, self.transaction_uid.as_str()),
+            ),
+            DataElement::new(
+                tags::REFERENCED_SOP_SEQUENCE,
+                VR::SQ,
+                DataSetSequence::from(
+                    self.references
+                        .iter()
+                        .map(SopReference::to_item)
+                        .collect::<Vec<_>>(),
+                ),
+            ),
+        ])
+    }
+
+    /// Read a storage commitment request
+    /// from the data set of an N-ACTION request.
+    pub fn from_data_set(obj: &InMemDicomObject) -> Result<Self> {
+        let transaction_uid = get_str(obj, tags::TRANSACTION_UID, "TransactionUID")?;
+        let references = obj
+            .get(tags::REFERENCED_SOP_SEQUENCE)
+            .and_then(|e| e.items())
+            .context(MissingAttributeSnafu {
+                name: "ReferencedSOPSequence",
+            })?
+            .iter()
+            .map(SopReference::from_item)
+            .collect::<Result<_>>()?;
+        Ok(CommitmentRequest {
+            transaction_uid,
+            references,
+        })
+    }
+}
+
+/// The result of a storage commitment request,
+/// as conveyed in the data set of an N-EVENT-REPORT request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitmentResult {
+    /// The Transaction UID of the original request
+    pub transaction_uid: String,
+    /// The SOP instances which were committed
+    pub committed: Vec<SopReference>,
+    /// The SOP instances which could not be committed
+    pub failed: Vec<FailedSopReference>,
+    /// The AE title from which the committed instances
+    /// can be retrieved, if known
+    pub retrieve_ae_title: Option<String>,
+}
+
+impl CommitmentResult {
+    /// Whether all instances referenced in the request were committed.
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// The Event Type ID of the N-EVENT-REPORT request
+    /// conveying this result.
+    pub fn event_type_id(&self) -> u16 {
+        if self.is_success() {
+            STORAGE_COMMITMENT_REQUEST_SUCCESSFUL
+        } else {
+            STORAGE_COMMITMENT_REQUEST_COMPLETE_FAILURES_EXIST
+        }
+    }
+
+    /// Build the data set of the N-EVENT-REPORT request.
+    pub fn to_data_set(&self) -> InMemDicomObject {
+        let mut obj = InMemDicomObject::from_element_iter([DataElement::new(
+            tags::TRANSACTION_UID,
+            VR::UI,
+            dicom_value!(Str, self.transaction_uid.as_str()),
+        )]);
+        if let Some(ae_title) = &self.retrieve_ae_title {
+            obj.put(DataElement::new(
+                tags::RETRIEVE_AE_TITLE,
+                VR::AE,
+                dicom_value!(Str, ae_title.as_str()),
+            ));
+        }
+        if !self.committed.is_empty() {
+            obj.put(DataElement::new(
+                tags::REFERENCED_SOP_SEQUENCE,
+                VR::SQ,
+                DataSetSequence::from(
+                    self.committed
+                        .iter()
+                        .map(SopReference::to_item)
+                        .collect::<Vec<_>>(),
+                ),
+            ));
+        }
+        if !self.failed.is_empty() {
+            obj.put(DataElement::new(
+                tags::FAILED_SOP_SEQUENCE,
+                VR::SQ,
+                DataSetSequence::from(
+                    self.failed
+                        .iter()
+                        .map(|failed| {
+                            let mut item = failed.reference.to_item();
+                            item.put(DataElement::new(
+                                tags::FAILURE_REASON,
+                                VR::US,
+                                dicom_value!(U16, [failed.failure_reason]),
+                            ));
+                            item
+                        })
+                        .collect::<Vec<_>>(),
+                ),
+            ));
+        }
+        obj
+    }
+
+    /// Read a storage commitment result
+    /// from the data set of an N-EVENT-REPORT request.
+    pub fn from_data_set(obj: &InMemDicomObject) -> Result<Self> {
+        let transaction_uid = get_str(obj, tags::TRANSACTION_UID, "TransactionUID")?;
+        let retrieve_ae_title = obj
+            .get(tags::RETRIEVE_AE_TITLE)
+            .map(|e| {
+                e.to_str().context(InvalidAttributeSnafu {
+                    name: "RetrieveAETitle",
+                })
+            })
+            .transpose()?
+            .map(|ae_title| ae_title.trim().to_string());
+        let committed = items(obj, tags::REFERENCED_SOP_SEQUENCE)
+            .iter()
+            .map(SopReference::from_item)
+            .collect::<Result<_>>()?;
+        let failed = items(obj, tags::FAILED_SOP_SEQUENCE)
+            .iter()
+            .map(|item| {
+                let failure_reason = item
+                    .get(tags::FAILURE_REASON)
+                    .context(MissingAttributeSnafu {
+                        name: "FailureReason",
+                    })?
+                    .to_int::<u16>()
+                    .context(InvalidAttributeSnafu {
+                        name: "FailureReason",
+                    })?;
+                Ok(FailedSopReference {
+                    reference: SopReference::from_item(item)?,
+                    failure_reason,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(CommitmentResult {
+            transaction_uid,
+            committed,
+            failed,
+            retrieve_ae_title,
+        })
+    }
+}
+
+fn get_str(obj: &InMemDicomObject, tag: Tag, name: &'static str) -> Result<String> {
+    let value = obj
+        .get(tag)
+        .context(MissingAttributeSnafu { name })?
+        .to_str()
+        .context(InvalidAttributeSnafu { name })?;
+    Ok(value.trim_end_matches(['\0', ' ']).to_string())
+}
+
+fn items(obj: &InMemDicomObject, tag: Tag) -> &[InMemDicomObject] {
+    obj.get(tag).and_then(|e| e.items()).unwrap_or_default()
+}
+
+/// Encode a data set in the transfer syntax of the given presentation context.
+fn encode(obj: &InMemDicomObject, pc: &PresentationContextResult) -> Result<(u8, Vec<u8>)> {
+    let ts = transfer_syntax(pc)?;
+    let mut data = Vec::new();
+    obj.write_dataset_with_ts(&mut data, ts)
+        .map_err(Box::from)
+        .context(WriteDataSetSnafu)?;
+    Ok((pc.id, data))
+}
+
+/// Read a storage commitment result from an N-EVENT-REPORT message.
+fn decode_result(
+    message: &Message,
+    presentation_contexts: &[PresentationContextResult],
+) -> Result<CommitmentResult> {
+    let data = message.data.as_deref().context(MissingDataSetSnafu {
+        command_field: message.command.command_field(),
+    })?;
+    let pc = presentation_contexts
+        .iter()
+        .find(|pc| pc.id == message.presentation_context_id)
+        .context(NoPresentationContextSnafu)?;
+    let obj = InMemDicomObject::read_dataset_with_ts(data, transfer_syntax(pc)?)
+        .map_err(Box::from)
+        .context(ReadDataSetSnafu)?;
+    CommitmentResult::from_data_set(&obj)
+}
+
+fn transfer_syntax(pc: &PresentationContextResult) -> Result<&'static TransferSyntax> {
+    TransferSyntaxRegistry
+        .get(&pc.transfer_syntax)
+        .context(UnsupportedTransferSyntaxSnafu {
+            uid: pc.transfer_syntax.clone(),
+        })
+}
+
+/// Create the response to an N-EVENT-REPORT request
+/// conveying a storage commitment result.
+fn event_report_response(rq: &NEventReportRQ, status: Status) -> Command {
+    Command::NEventReportRSP(NEventReportRSP {
+        message_id_being_responded_to: rq.message_id,
+        affected_sop_class_uid: Some(rq.affected_sop_class_uid.clone()),
+        affected_sop_instance_uid: Some(rq.affected_sop_instance_uid.clone()),
+        event_type_id: Some(rq.event_type_id),
+        status,
+    })
+}
+
+/// Find the presentation context
+/// of the Storage Commitment Push Model SOP class
+/// in a client association.
+fn commitment_context<S>(association: &ClientAssociation<S>) -> Result<PresentationContextResult>
+where
+    S: Read + Write + CloseSocket,
+{
+    association
+        .presentation_contexts()
+        .iter()
+        .find(|pc| association.abstract_syntax(pc.id) == Some(uids::STORAGE_COMMITMENT_PUSH_MODEL))
+        .cloned()
+        .context(NoPresentationContextSnafu)
+}
+
+/// Request storage commitment of the SOP instances in `request`
+/// through the given association,
+/// and wait for the N-ACTION response.
+///
+/// The association must have a presentation context
+/// for the Storage Commitment Push Model SOP class.
+/// A successful status only means that the request was accepted:
+/// the result of the request is reported afterwards,
+/// see [`receive_result`] and [`accept_result`].
+pub fn request_commitment<S>(
+    association: &mut ClientAssociation<S>,
+    request: &CommitmentRequest,
+) -> Result<Status>
+where
+    S: Read + Write + CloseSocket,
+{
+    let pc = commitment_context(association)?;
+    let (pc_id, data) = encode(&request.to_data_set(), &pc)?;
+    let command = Command::NActionRQ(NActionRQ {
+        message_id: association.next_message_id(),
+        requested_sop_class_uid: uids::STORAGE_COMMITMENT_PUSH_MODEL.to_string(),
+        requested_sop_instance_uid: uids::STORAGE_COMMITMENT_PUSH_MODEL_INSTANCE.to_string(),
+        action_type_id: REQUEST_STORAGE_COMMITMENT,
+    });
+    association
+        .send_message(pc_id, &command, Some(&data))
+        .map_err(Box::from)
+        .context(SendSnafu)?;
+
+    let message = association
+        .receive_message()
+        .map_err(Box::from)
+        .context(ReceiveSnafu)?;
+    match message.command {
+        Command::NActionRSP(rsp) => Ok(rsp.status),
+        command => UnexpectedMessageSnafu {
+            command_field: command.command_field(),
+        }
+        .fail(),
+    }
+}
+
+/// Wait for the result of a storage commitment request
+/// on the same association in which it was requested,
+/// and respond to the N-EVENT-REPORT request.
+///
+/// No role selection is needed for this,
+/// since the SCP reports the result in its usual role
+/// (PS3.4 J.3).
+/// Role selection only applies to results reported on a new association,
+/// which are received with [`accept_result`] instead.
+pub fn receive_result<S>(association: &mut ClientAssociation<S>) -> Result<CommitmentResult>
+where
+    S: Read + Write + CloseSocket,
+{
+    let message = association
+        .receive_message()
+        .map_err(Box::from)
+        .context(ReceiveSnafu)?;
+    let rq = match &message.command {
+        Command::NEventReportRQ(rq)
+            if rq.affected_sop_class_uid == uids::STORAGE_COMMITMENT_PUSH_MODEL =>
+        {
+            rq
+        }
+        command => {
+            return UnexpectedMessageSnafu {
+                command_field: command.command_field(),
+            }
+            .fail()
+        }
+    };
+    let result = decode_result(&message, association.presentation_contexts());
+    let status = if result.is_ok() {
+        Status::SUCCESS
+    } else {
+        Status::PROCESSING_FAILURE
+    };
+    association
+        .send_message(
+            message.presentation_context_id,
+            &event_report_response(rq, status),
+            None,
+        )
+        .map_err(Box::from)
+        .context(SendSnafu)?;
+    result
+}
+
+/// Create server association options
+/// for accepting storage commitment results on a new association,
+/// as initiated by the storage commitment SCP.
+pub fn result_acceptor_options<'a>() -> ServerAssociationOptions<'a, AcceptAny> {
+    ServerAssociationOptions::new()
+        .accept_any()
+        .with_abstract_syntax(uids::STORAGE_COMMITMENT_PUSH_MODEL)
+        .with_role_selection(uids::STORAGE_COMMITMENT_PUSH_MODEL, false, true)
+}
+
+/// Accept an association from the storage commitment SCP
+/// over the given connection,
+/// and receive the result of a storage commitment request,
+/// responding to the N-EVENT-REPORT request.
+///
+/// The options should accept the Storage Commitment Push Model SOP class
+/// and grant the SCP role to the association requestor,
+/// such as those created by [`result_acceptor_options`].
+/// This function returns once the association is released.
+/// If more than one result is reported in the association,
+/// only the first one is returned.
+pub fn accept_result<A>(
+    stream: TcpStream,
+    options: &ServerAssociationOptions<'_, A>,
+) -> Result<CommitmentResult>
+where
+    A: AccessControl,
+{
+    let mut association = options
+        .establish(stream)
+        .map_err(Box::from)
+        .context(AcceptSnafu)?;
+
+    let mut out = None;
+    loop {
+        let message = match association.receive_message() {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            // the result was already received,
+            // no need to fail if the SCP aborts afterwards
+            Err(_) if out.is_some() => break,
+            Err(e) => return Err(Box::from(e)).context(AcceptSnafu),
+        };
+        let rq = match &message.command {
+            Command::NEventReportRQ(rq)
+                if rq.affected_sop_class_uid == uids::STORAGE_COMMITMENT_PUSH_MODEL =>
+            {
+                rq
+            }
+            command => {
+                return UnexpectedMessageSnafu {
+                    command_field: command.command_field(),
+                }
+                .fail()
+            }
+        };
+        let result = decode_result(&message, association.presentation_contexts());
+        let status = if result.is_ok() {
+            Status::SUCCESS
+        } else {
+            Status::PROCESSING_FAILURE
+        };
+        association
+            .send_message(
+                message.presentation_context_id,
+                &event_report_response(rq, status),
+                None,
+            )
+            .map_err(Box::from)
+            .context(AcceptSnafu)?;
+        let result = result?;
+        out.get_or_insert(result);
+    }
+    out.context(NoResultSnafu)
+}
+
+/// Create client association options
+/// for reporting storage commitment results on a new association,
+/// as the storage commitment SCP.
+pub fn result_sender_options<'a>() -> ClientAssociationOptions<'a> {
+    ClientAssociationOptions::new()
+        .with_presentation_context(
+            uids::STORAGE_COMMITMENT_PUSH_MODEL,
+            vec![
+                uids::EXPLICIT_VR_LITTLE_ENDIAN,
+                uids::IMPLICIT_VR_LITTLE_ENDIAN,
+            ],
+        )
+        .with_role_selection(uids::STORAGE_COMMITMENT_PUSH_MODEL, false, true)
+}
+
+/// Report the result of a storage commitment request
+/// to the storage commitment SCU,
+/// and wait for the N-EVENT-REPORT response.
+///
+/// This is done by the storage commitment SCP,
+/// either on a new association
+/// (such as one established with [`result_sender_options`])
+/// or on the association of the request.
+pub fn send_result<S>(
+    association: &mut ClientAssociation<S>,
+    result: &CommitmentResult,
+) -> Result<Status>
+where
+    S: Read + Write + CloseSocket,
+{
+    let pc = commitment_context(association)?;
+    let (pc_id, data) = encode(&result.to_data_set(), &pc)?;
+    let command = Command::NEventReportRQ(NEventReportRQ {
+        message_id: association.next_message_id(),
+        affected_sop_class_uid: uids::STORAGE_COMMITMENT_PUSH_MODEL.to_string(),
+        affected_sop_instance_uid: uids::STORAGE_COMMITMENT_PUSH_MODEL_INSTANCE.to_string(),
+        event_type_id: result.event_type_id(),
+    });
+    association
+        .send_message(pc_id, &command, Some(&data))
+        .map_err(Box::from)
+        .context(SendSnafu)?;
+
+    let message = association
+        .receive_message()
+        .map_err(Box::from)
+        .context(ReceiveSnafu)?;
+    match message.command {
+        Command::NEventReportRSP(rsp) => Ok(rsp.status),
+        command => UnexpectedMessageSnafu {
+            command_field: command.command_field(),
+        }
+        .fail(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN;
+
+    #[test]
+    fn request_data_set_roundtrip() {
+        let request = CommitmentRequest {
+            transaction_uid: "2.25.1234".to_string(),
+            references: vec![
+                SopReference::new(uids::CT_IMAGE_STORAGE, "1.2.3.4.1"),
+                SopReference::new(uids::CT_IMAGE_STORAGE, "1.2.3.4.2"),
+            ],
+        };
+        let obj = request.to_data_set();
+
+        // encode and decode to account for UID padding
+        let mut data = Vec::new();
+        obj.write_dataset_with_ts(&mut data, &IMPLICIT_VR_LITTLE_ENDIAN.erased())
+            .unwrap();
+        let obj =
+            InMemDicomObject::read_dataset_with_ts(&data[..], &IMPLICIT_VR_LITTLE_ENDIAN.erased())
+                .unwrap();
+        assert_eq!(CommitmentRequest::from_data_set(&obj).unwrap(), request);
+    }
+
+    #[test]
+    fn result_data_set_roundtrip() {
+        let result = CommitmentResult {
+            transaction_uid: "2.25.1234".to_string(),
+            committed: vec![SopReference::new(uids::CT_IMAGE_STORAGE, "1.2.3.4.1")],
+            failed: vec![FailedSopReference {
+                reference: SopReference::new(uids::CT_IMAGE_STORAGE, "1.2.3.4.2"),
+                failure_reason: failure_reason::NO_SUCH_OBJECT_INSTANCE,
+            }],
+            retrieve_ae_title: Some("ARCHIVE".to_string()),
+        };
+        assert!(!result.is_success());
+        assert_eq!(
+            result.event_type_id(),
+            STORAGE_COMMITMENT_REQUEST_COMPLETE_FAILURES_EXIST
+        );
+
+        let obj = result.to_data_set();
+        let mut data = Vec::new();
+        obj.write_dataset_with_ts(&mut data, &IMPLICIT_VR_LITTLE_ENDIAN.erased())
+            .unwrap();
+        let obj =
+            InMemDicomObject::read_dataset_with_ts(&data[..], &IMPLICIT_VR_LITTLE_ENDIAN.erased())
+                .unwrap();
+        assert_eq!(CommitmentResult::from_data_set(&obj).unwrap(), result);
+    }
+
+    #[test]
+    fn successful_result_has_no_failed_sop_sequence() {
+        let result = CommitmentResult {
+            transaction_uid: "2.25.1234".to_string(),
+            committed: vec![SopReference::new(uids::CT_IMAGE_STORAGE, "1.2.3.4.1")],
+            failed: vec![],
+            retrieve_ae_title: None,
+        };
+        assert_eq!(
+            result.event_type_id(),
+            STORAGE_COMMITMENT_REQUEST_SUCCESSFUL
+        );
+        let obj = result.to_data_set();
+        assert!(obj.get(tags::FAILED_SOP_SEQUENCE).is_none());
+        assert!(obj.get(tags::RETRIEVE_AE_TITLE).is_none());
+        assert_eq!(CommitmentResult::from_data_set(&obj).unwrap(), result);
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    collections::HashSet,
+    net::{Ipv4Addr, SocketAddrV4, TcpListener},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use dicom_app_common::uid::new_uid;
+use dicom_commitscu::{
+    accept_result, receive_result, request_commitment, result_acceptor_options, CommitmentRequest,
+    CommitmentResult, SopReference,
+};
+use dicom_core::Tag;
+use dicom_dictionary_std::uids;
+use dicom_ul::{dimse::StatusType, ClientAssociationOptions};
+use snafu::{whatever, Report, ResultExt, Whatever};
+use tracing::{debug, error, info, warn, Level};
+use walkdir::WalkDir;
+
+/// DICOM Storage Commitment SCU
+#[derive(Debug, Parser)]
+#[command(version)]
+struct App {
+    /// socket address to Storage Commitment SCP,
+    /// optionally with AE title
+    /// (example: "ARCHIVE@127.0.0.1:1045")
+    addr: String,
+    /// the DICOM files (or directories) of the instances to commit
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+    /// verbose mode
+    #[arg(short = 'v', long = "verbose")]
+    verbose: bool,
+    /// the calling AE title
+    #[arg(long = "calling-ae-title", default_value = "COMMIT-SCU")]
+    calling_ae_title: String,
+    /// the called Application Entity title,
+    /// overrides AE title in address if present [default: ANY-SCP]
+    #[arg(long = "called-ae-title")]
+    called_ae_title: Option<String>,
+    /// the maximum PDU length
+    #[arg(
+        long = "max-pdu-length",
+        default_value = "16384",
+        value_parser(clap::value_parser!(u32).range(4096..=131_072))
+    )]
+    max_pdu_length: u32,
+    /// the Transaction UID of the request [default: a new UID]
+    #[arg(long = "transaction-uid")]
+    transaction_uid: Option<String>,
+    /// receive the result on a new association,
+    /// listening on the given port,
+    /// instead of on the association of the request
+    #[arg(long = "report-port", value_name = "PORT")]
+    report_port: Option<u16>,
+    /// how long to wait for the result, in seconds
+    #[arg(long = "timeout", default_value = "60")]
+    timeout: u64,
+}
+
+fn main() {
+    run().unwrap_or_else(|e| {
+        error!("{}", Report::from_error(e));
+        std::process::exit(-2);
+    })
+}
+
+fn run() -> Result<(), Whatever> {
+    let App {
+        addr,
+        files,
+        verbose,
+        calling_ae_title,
+        called_ae_title,
+        max_pdu_length,
+        transaction_uid,
+        report_port,
+        timeout,
+    } = App::parse();
+
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(if verbose { Level::DEBUG } else { Level::INFO })
+            .finish(),
+    )
+    .whatever_context("Could not set up global logging subscriber")
+    .unwrap_or_else(|e: Whatever| {
+        eprintln!("[ERROR] {}", Report::from_error(e));
+    });
+
+    let timeout = Duration::from_secs(timeout);
+    let request = CommitmentRequest {
+        transaction_uid: transaction_uid.unwrap_or_else(new_uid),
+        references: collect_references(files, verbose),
+    };
+    if request.references.is_empty() {
+        whatever!("No DICOM instances to commit");
+    }
+    info!(
+        "Requesting storage commitment of {} instance(s) (transaction {})",
+        request.references.len(),
+        request.transaction_uid
+    );
+
+    // listen for the result before requesting,
+    // so that it is not missed if the SCP reports right away
+    let listener = report_port
+        .map(|port| {
+            TcpListener::bind(SocketAddrV4::new(Ipv4Addr::from(0), port))
+                .whatever_context("Could not listen for the result")
+        })
+        .transpose()?;
+
+    let mut association_opt = ClientAssociationOptions::new()
+        .with_abstract_syntax(uids::STORAGE_COMMITMENT_PUSH_MODEL)
+        .calling_ae_title(calling_ae_title.clone())
+        .max_pdu_length(max_pdu_length)
+        .read_timeout(timeout);
+    if let Some(called_ae_title) = called_ae_title {
+        association_opt = association_opt.called_ae_title(called_ae_title);
+    }
+    let mut association = association_opt
+        .establish_with(&addr)
+        .whatever_context("Could not establish association with SCP")?;
+
+    let status = request_commitment(&mut association, &request)
+        .whatever_context("Could not request storage commitment")?;
+    debug!("N-ACTION status: {:04X}H", status.code());
+    if status.status_type() != StatusType::Success {
+        let _ = association.abort();
+        whatever!(
+            "Storage commitment request refused (status code {:04X}H)",
+            status.code()
+        );
+    }
+
+    let result = if let Some(listener) = listener {
+        let _ = association.release();
+        info!(
+            "Waiting for the result on {}",
+            listener
+                .local_addr()
+                .whatever_context("Could not listen for the result")?
+        );
+        wait_for_result(
+            &listener,
+            &calling_ae_title,
+            &request.transaction_uid,
+            timeout,
+        )?
+    } else {
+        info!("Waiting for the result");
+        let result =
+            receive_result(&mut association).whatever_context("Could not receive result")?;
+        let _ = association.release();
+        if result.transaction_uid != request.transaction_uid {
+            whatever!(
+                "Received result of another transaction ({})",
+                result.transaction_uid
+            );
+        }
+        result
+    };
+
+    report(&result)
+}
+
+/// Read the SOP instance references from the file meta groups
+/// of the given files and of the files in the given directories.
+fn collect_references(files: Vec<PathBuf>, verbose: bool) -> Vec<SopReference> {
+    let mut references = Vec::new();
+    let mut seen = HashSet::new();
+    for file in files {
+        let paths: Vec<_> = if file.is_dir() {
+            WalkDir::new(file.as_path())
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|f| !f.file_type().is_dir())
+                .map(|f| f.into_path())
+                .collect()
+        } else {
+            vec![file]
+        };
+        for path in paths {
+            match read_reference(&path) {
+                Ok(reference) => {
+                    if verbose {
+                        debug!("{}: {}", path.display(), reference.sop_instance_uid);
+                    }
+                    if seen.insert(reference.sop_instance_uid.clone()) {
+                        references.push(reference);
+                    }
+                }
+                Err(e) => warn!("Skipping {}: {}", path.display(), Report::from_error(e)),
+            }
+        }
+    }
+    references
+}
+
+fn read_reference(path: &Path) -> Result<SopReference, Whatever> {
+    let file = dicom_object::OpenFileOptions::new()
+        .read_until(Tag(0x0001, 0x0000))
+        .open_file(path)
+        .whatever_context("could not read DICOM file")?;
+    let meta = file.meta();
+    Ok(SopReference::new(
+        meta.media_storage_sop_class_uid(),
+        meta.media_storage_sop_instance_uid(),
+    ))
+}
+
+/// Accept associations from the SCP
+/// until the result of the transaction is reported
+/// or the timeout expires.
+fn wait_for_result(
+    listener: &TcpListener,
+    ae_title: &str,
+    transaction_uid: &str,
+    timeout: Duration,
+) -> Result<CommitmentResult, Whatever> {
+    let options = result_acceptor_options()
+        .ae_title(ae_title)
+        .timeout(timeout);
+    let deadline = Instant::now() + timeout;
+    listener
+        .set_nonblocking(true)
+        .whatever_context("Could not listen for the result")?;
+    loop {
+        match listener.accept() {
+            Ok((stream, peer_addr)) => {
+                debug!("Connection from {}", peer_addr);
+                stream
+                    .set_nonblocking(false)
+                    .whatever_context("Could not accept connection")?;
+                match accept_result(stream, &options) {
+                    Ok(result) if result.transaction_uid == transaction_uid => return Ok(result),
+                    Ok(result) => warn!(
+                        "Ignoring result of another transaction ({})",
+                        result.transaction_uid
+                    ),
+                    Err(e) => warn!("{}", Report::from_error(e)),
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    whatever!("Timed out waiting for the result");
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => return Err(e).whatever_context("Could not accept connection"),
+        }
+    }
+}
+
+/// Log the result of the request,
+/// failing if any instance was not committed.
+fn report(result: &CommitmentResult) -> Result<(), Whatever> {
+    if let Some(ae_title) = &result.retrieve_ae_title {
+        debug!("Retrieve AE title: {}", ae_title);
+    }
+    for reference in &result.committed {
+        info!("✓ Committed {}", reference.sop_instance_uid);
+    }
+    for failed in &result.failed {
+        warn!(
+            "✗ Not committed {} (failure reason {:04X}H)",
+            failed.reference.sop_instance_uid, failed.failure_reason
+        );
+    }
+    if !result.is_success() {
+        whatever!(
+            "{} of {} instance(s) not committed",
+            result.failed.len(),
+            result.failed.len() + result.committed.len()
+        );
+    }
+    info!("All {} instance(s) committed", result.committed.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::App;
+    use clap::CommandFactory;
+
+    #[test]
+    fn verify_cli() {
+        App::command().debug_assert();
+    }
+}
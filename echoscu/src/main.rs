@@ -1,12 +1,15 @@
+use std::io::{Read, Write};
+
 use clap::Parser;
-use dicom_core::{dicom_value, DataElement, VR};
-use dicom_dictionary_std::{tags, uids};
-use dicom_object::{mem::InMemDicomObject, StandardDataDictionary};
+#[cfg(feature = "tls")]
+use dicom_app_common::TlsClientOptions;
+use dicom_dictionary_std::uids;
+use dicom_object::mem::InMemDicomObject;
+use dicom_transfer_syntax_registry::entries;
 use dicom_ul::{
-    association::client::ClientAssociationOptions,
-    pdu::{self, PDataValueType, Pdu},
+    association::client::{ClientAssociation, ClientAssociationOptions, CloseSocket},
+    dimse::{write_command, CEchoRQ, Command, StatusType},
 };
-use pdu::PDataValue;
 use snafu::{prelude::*, Whatever};
 use tracing::{debug, error, info, warn, Level};
 
@@ -31,6 +34,9 @@ struct App {
     /// overrides AE title in address if present [default: ANY-SCP]
     #[arg(long = "called-ae-title")]
     called_ae_title: Option<String>,
+    #[cfg(feature = "tls")]
+    #[command(flatten)]
+    tls: TlsClientOptions,
 }
 
 fn main() {
@@ -47,6 +53,8 @@ fn run() -> Result<(), Whatever> {
         message_id,
         called_ae_title,
         calling_ae_title,
+        #[cfg(feature = "tls")]
+        tls,
     } = App::parse();
 
     tracing::subscriber::set_global_default(
@@ -60,15 +68,40 @@ fn run() -> Result<(), Whatever> {
     });
 
     let mut association_opt = ClientAssociationOptions::new()
-        .with_abstract_syntax("1.2.840.10008.1.1")
+        .with_abstract_syntax(uids::VERIFICATION)
         .calling_ae_title(calling_ae_title);
     if let Some(called_ae_title) = called_ae_title {
         association_opt = association_opt.called_ae_title(called_ae_title);
     }
+
+    #[cfg(feature = "tls")]
+    if let Some(tls_config) = tls
+        .client_config()
+        .whatever_context("Could not set up TLS")?
+    {
+        let mut association = association_opt
+            .tls_config(tls_config)
+            .server_name(tls.server_name(&addr))
+            .establish_with_tls(&addr)
+            .whatever_context("Could not establish association with SCP")?;
+        return echo(&mut association, &addr, message_id, verbose);
+    }
+
     let mut association = association_opt
         .establish_with(&addr)
         .whatever_context("Could not establish association with SCP")?;
+    echo(&mut association, &addr, message_id, verbose)
+}
 
+fn echo<S>(
+    association: &mut ClientAssociation<S>,
+    addr: &str,
+    message_id: u16,
+    verbose: bool,
+) -> Result<(), Whatever>
+where
+    S: Read + Write + CloseSocket,
+{
     let pc = association
         .presentation_contexts()
         .first()
@@ -79,25 +112,15 @@ fn run() -> Result<(), Whatever> {
         debug!("Association with {} successful", addr);
     }
 
-    // commands are always in implicit VR LE
-    let ts = dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased();
-
-    let obj = create_echo_command(message_id);
-
-    let mut data = Vec::new();
-
-    obj.write_dataset_with_ts(&mut data, &ts)
-        .whatever_context("Failed to construct C-ECHO request")?;
-
     association
-        .send(&Pdu::PData {
-            data: vec![PDataValue {
-                presentation_context_id: pc.id,
-                value_type: PDataValueType::Command,
-                is_last: true,
-                data,
-            }],
-        })
+        .send_message(
+            pc.id,
+            &Command::CEchoRQ(CEchoRQ {
+                message_id,
+                affected_sop_class_uid: uids::VERIFICATION.to_string(),
+            }),
+            None,
+        )
         .whatever_context("Failed to send C-ECHO request")?;
 
     if verbose {
@@ -107,90 +130,63 @@ fn run() -> Result<(), Whatever> {
         );
     }
 
-    let pdu = association
-        .receive()
+    let message = association
+        .receive_message()
         .whatever_context("Could not receive response from SCP")?;
 
-    match pdu {
-        Pdu::PData { data } => {
-            let data_value = &data[0];
-            let v = &data_value.data;
-
-            let obj = InMemDicomObject::read_dataset_with_ts(v.as_slice(), &ts)
-                .whatever_context("Failed to read response dataset from SCP")?;
-            if verbose {
-                dicom_dump::dump_object(&obj)
-                    .whatever_context("Failed to output DICOM response")?;
-            }
+    if verbose {
+        let obj = InMemDicomObject::read_dataset_with_ts(
+            &write_command(&message.command, message.data.is_some())[..],
+            &entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
+        )
+        .whatever_context("Failed to read response command from SCP")?;
+        dicom_dump::dump_object(&obj).whatever_context("Failed to output DICOM response")?;
+    }
 
+    match message.command {
+        Command::CEchoRSP(rsp) => {
             // check status
-            let status = obj
-                .element(tags::STATUS)
-                .whatever_context("Missing Status code in response")?
-                .to_int::<u16>()
-                .whatever_context("Status code in response is not a valid integer")?;
+            let status = rsp.status;
             if verbose {
-                debug!("Status: {:04X}H", status);
+                debug!("Status: {:04X}H", status.code());
             }
-            match status {
-                // Success
-                0 => {
+            match status.status_type() {
+                StatusType::Success => {
                     if verbose {
                         info!("✓ C-ECHO successful");
                     }
                 }
-                // Warning
-                1 | 0x0107 | 0x0116 | 0xB000..=0xBFFF => {
-                    warn!("Possible issue in C-ECHO (status code {:04X}H)", status);
+                StatusType::Warning => {
+                    warn!(
+                        "Possible issue in C-ECHO (status code {:04X}H)",
+                        status.code()
+                    );
                 }
-                0xFF00 | 0xFF01 => {
+                StatusType::Pending => {
                     warn!(
                         "Possible issue in C-ECHO: status is pending (status code {:04X}H)",
-                        status
+                        status.code()
                     );
                 }
-                0xFE00 => {
+                StatusType::Cancel => {
                     warn!("Operation cancelled");
                 }
-                _ => {
-                    error!("C-ECHO failed (status code {:04X}H)", status);
+                StatusType::Failure => {
+                    error!("C-ECHO failed (status code {:04X}H)", status.code());
                 }
             }
 
             // msg ID response, should be equal to sent msg ID
-            let got_msg_id: u16 = obj
-                .element(tags::MESSAGE_ID_BEING_RESPONDED_TO)
-                .whatever_context("Could not retrieve Message ID from response")?
-                .to_int()
-                .whatever_context("Message ID is not a valid integer")?;
-
-            if message_id != got_msg_id {
+            if message_id != rsp.message_id_being_responded_to {
                 whatever!("Message ID mismatch");
             }
         }
-        pdu => whatever!("Unexpected PDU {:?}", pdu),
+        command => whatever!("Unexpected command {:?}", command),
     }
 
     Ok(())
 }
 
-fn create_echo_command(message_id: u16) -> InMemDicomObject<StandardDataDictionary> {
-    InMemDicomObject::command_from_element_iter([
-        // service
-        DataElement::new(tags::AFFECTED_SOP_CLASS_UID, VR::UI, uids::VERIFICATION),
-        // command
-        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x0030])),
-        // message ID
-        DataElement::new(tags::MESSAGE_ID, VR::US, dicom_value!(U16, [message_id])),
-        // data set type
-        DataElement::new(
-            tags::COMMAND_DATA_SET_TYPE,
-            VR::US,
-            dicom_value!(U16, [0x0101]),
-        ),
-    ])
-}
-
 #[cfg(test)]
 mod tests {
     use crate::App;
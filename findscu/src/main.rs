@@ -1,24 +1,25 @@
 use clap::Parser;
-use dicom_core::dicom_value;
+use dicom_app_common::query::parse_queries;
+#[cfg(feature = "tls")]
+use dicom_app_common::TlsClientOptions;
 use dicom_core::{DataElement, PrimitiveValue, VR};
 use dicom_dictionary_std::{tags, uids};
 use dicom_dump::DumpOptions;
 use dicom_encoding::transfer_syntax;
-use dicom_object::{mem::InMemDicomObject, open_file, StandardDataDictionary};
+use dicom_object::{mem::InMemDicomObject, open_file};
 use dicom_transfer_syntax_registry::{entries, TransferSyntaxRegistry};
-use dicom_ul::pdu::Pdu;
 use dicom_ul::{
-    association::ClientAssociationOptions,
-    pdu::{PDataValue, PDataValueType},
+    association::{client::CloseSocket, ClientAssociation, ClientAssociationOptions},
+    dimse::{write_command, CCancelRQ, CFindRQ, Command, Priority, Status},
 };
-use query::parse_queries;
+use output::{Output, OutputFormat};
 use snafu::prelude::*;
-use std::io::{stderr, BufRead as _, Read};
+use std::io::{stderr, BufRead as _, Read, Write};
 use std::path::PathBuf;
 use tracing::{debug, error, info, warn, Level};
 use transfer_syntax::TransferSyntaxIndex;
 
-mod query;
+mod output;
 
 /// DICOM C-FIND SCU
 #[derive(Debug, Parser)]
@@ -66,6 +67,27 @@ struct App {
         conflicts_with = "patient"
     )]
     mwl: bool,
+
+    /// the format in which to write the matched identifiers
+    #[arg(short = 'o', long = "output", value_enum, default_value = "dump")]
+    output: OutputFormat,
+    /// the attributes to write as CSV columns,
+    /// separated by commas (example: "PatientID,StudyDate")
+    /// [default: the attributes in the query]
+    #[arg(long = "columns", value_delimiter = ',')]
+    columns: Vec<String>,
+    /// the directory in which to write DICOM files
+    /// when using the "dcm" output format
+    #[arg(long = "out-dir", default_value = ".")]
+    out_dir: PathBuf,
+    /// cancel the query (C-CANCEL)
+    /// once this many matches have arrived
+    #[arg(long = "cancel-after", value_name = "N")]
+    cancel_after: Option<usize>,
+
+    #[cfg(feature = "tls")]
+    #[command(flatten)]
+    tls: TlsClientOptions,
 }
 
 fn main() {
@@ -82,6 +104,12 @@ enum Error {
         source: dicom_ul::association::client::Error,
     },
 
+    /// Could not set up TLS
+    #[cfg(feature = "tls")]
+    InitTls {
+        source: dicom_app_common::tls::Error,
+    },
+
     /// Could not construct DICOM command
     CreateCommand { source: dicom_object::ReadError },
 
@@ -91,6 +119,9 @@ enum Error {
     /// Could not dump DICOM output
     DumpOutput { source: std::io::Error },
 
+    /// Could not write output
+    WriteOutput { source: std::io::Error },
+
     #[snafu(whatever, display("{}", message))]
     Other {
         message: String,
@@ -186,11 +217,19 @@ fn run() -> Result<(), Error> {
         patient,
         study,
         mwl,
+        output,
+        columns,
+        out_dir,
+        cancel_after,
+        #[cfg(feature = "tls")]
+        tls,
     } = App::parse();
 
+    // keep standard output for the matches
     tracing::subscriber::set_global_default(
         tracing_subscriber::FmtSubscriber::builder()
             .with_max_level(if verbose { Level::DEBUG } else { Level::INFO })
+            .with_writer(std::io::stderr)
             .finish(),
     )
     .unwrap_or_else(|e| {
@@ -212,6 +251,8 @@ fn run() -> Result<(), Error> {
         _ => unreachable!("Unexpected flag combination"),
     };
 
+    let output = Output::new(output, &columns, &dcm_query, out_dir, abstract_syntax)?;
+
     if verbose {
         info!("Establishing association with '{}'...", &addr);
     }
@@ -225,8 +266,45 @@ fn run() -> Result<(), Error> {
         scu_opt = scu_opt.called_ae_title(called_ae_title);
     }
 
-    let mut scu = scu_opt.establish_with(&addr).context(InitScuSnafu)?;
+    #[cfg(feature = "tls")]
+    if let Some(tls_config) = tls.client_config().context(InitTlsSnafu)? {
+        let scu = scu_opt
+            .tls_config(tls_config)
+            .server_name(tls.server_name(&addr))
+            .establish_with_tls(&addr)
+            .context(InitScuSnafu)?;
+        return find(
+            scu,
+            abstract_syntax,
+            &dcm_query,
+            output,
+            cancel_after,
+            verbose,
+        );
+    }
+
+    let scu = scu_opt.establish_with(&addr).context(InitScuSnafu)?;
+    find(
+        scu,
+        abstract_syntax,
+        &dcm_query,
+        output,
+        cancel_after,
+        verbose,
+    )
+}
 
+fn find<S>(
+    mut scu: ClientAssociation<S>,
+    abstract_syntax: &str,
+    dcm_query: &InMemDicomObject,
+    mut output: Output,
+    cancel_after: Option<usize>,
+    verbose: bool,
+) -> Result<(), Error>
+where
+    S: Read + Write + CloseSocket,
+{
     if verbose {
         info!("Association established");
     }
@@ -252,194 +330,127 @@ fn run() -> Result<(), Error> {
         debug!("Transfer Syntax: {}", ts.name());
     }
 
-    let cmd = find_req_command(abstract_syntax, 1);
-
-    let mut cmd_data = Vec::with_capacity(128);
-    cmd.write_dataset_with_ts(&mut cmd_data, &entries::IMPLICIT_VR_LITTLE_ENDIAN.erased())
-        .whatever_context("Failed to write command")?;
+    let message_id = scu.next_message_id();
+    let cmd = find_req_command(abstract_syntax, message_id);
 
     let mut iod_data = Vec::with_capacity(128);
     dcm_query
         .write_dataset_with_ts(&mut iod_data, ts)
         .whatever_context("failed to write identifier dataset")?;
 
-    let nbytes = cmd_data.len() + iod_data.len();
-
     if verbose {
-        debug!("Sending query ({} B)...", nbytes);
+        debug!("Sending query ({} B)...", iod_data.len());
     }
 
-    let pdu = Pdu::PData {
-        data: vec![PDataValue {
-            presentation_context_id: pc_selected_id,
-            value_type: PDataValueType::Command,
-            is_last: true,
-            data: cmd_data,
-        }],
-    };
-    scu.send(&pdu).whatever_context("Could not send command")?;
-
-    let pdu = Pdu::PData {
-        data: vec![PDataValue {
-            presentation_context_id: pc_selected_id,
-            value_type: PDataValueType::Data,
-            is_last: true,
-            data: iod_data,
-        }],
-    };
-    scu.send(&pdu)
+    scu.send_message(pc_selected_id, &cmd, Some(&iod_data))
         .whatever_context("Could not send C-Find request")?;
 
     if verbose {
         debug!("Awaiting response...");
     }
 
+    output.begin()?;
     let mut i = 0;
+    let mut canceled = false;
     loop {
-        let rsp_pdu = scu
-            .receive()
+        let rsp = scu
+            .receive_message()
             .whatever_context("Failed to receive response from remote node")?;
 
-        match rsp_pdu {
-            Pdu::PData { data } => {
-                if data.is_empty() {
-                    error!("Empty PData response");
-                    break;
-                } else if ![1, 2].contains(&data.len()) {
-                    warn!(
-                        "Unexpected number of PDataValue parts: {} (allowed 1 or 2)",
-                        data.len()
-                    );
-                    break;
-                }
+        let Command::CFindRSP(rsp_cmd) = &rsp.command else {
+            error!("Unexpected SCP response: {:?}", rsp.command);
+            let _ = scu.abort();
+            std::process::exit(-2);
+        };
+        if verbose {
+            let cmd_obj = InMemDicomObject::read_dataset_with_ts(
+                &write_command(&rsp.command, rsp.data.is_some())[..],
+                &entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
+            )
+            .context(ReadCommandSnafu)?;
+            eprintln!("Match #{} Response command:", i);
+            DumpOptions::new()
+                .dump_object_to(stderr(), &cmd_obj)
+                .context(DumpOutputSnafu)?;
+        }
+        let status = rsp_cmd.status;
+        if status.is_success() {
+            if verbose {
+                debug!("Matching is complete");
+            }
+            if i == 0 {
+                info!("No results matching query");
+            }
+            break;
+        } else if status == Status::CANCEL {
+            info!("Query canceled after {} matches", i);
+            break;
+        } else if status.is_pending() {
+            if verbose {
+                debug!("Operation pending: {:x}", status.code());
+            }
 
-                let data_value = &data[0];
+            if canceled {
+                // matches sent before the SCP saw the cancel request
+                debug!("Ignoring match after cancel request");
+                continue;
+            }
 
-                let cmd_obj = InMemDicomObject::read_dataset_with_ts(
-                    &data_value.data[..],
-                    &entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
-                )
-                .context(ReadCommandSnafu)?;
-                if verbose {
-                    eprintln!("Match #{} Response command:", i);
-                    DumpOptions::new()
-                        .dump_object_to(stderr(), &cmd_obj)
-                        .context(DumpOutputSnafu)?;
-                }
-                let status = cmd_obj
-                    .get(tags::STATUS)
-                    .whatever_context("status code from response is missing")?
-                    .to_int::<u16>()
-                    .whatever_context("failed to read status code")?;
-                if status == 0 {
+            // fetch DICOM data
+            let Some(response_data) = &rsp.data else {
+                warn!("Pending response without identifier");
+                break;
+            };
+            let dcm = InMemDicomObject::read_dataset_with_ts(&response_data[..], ts)
+                .whatever_context("Could not read response data set")?;
+
+            output.write(i, &dcm)?;
+
+            // check DICOM status in response data,
+            // as some implementations might report status code 0
+            // upon sending the response data
+            if let Some(status) = dcm.get(tags::STATUS) {
+                let status = status.to_int::<u16>().ok();
+                if status == Some(0) {
                     if verbose {
                         debug!("Matching is complete");
                     }
-                    if i == 0 {
-                        info!("No results matching query");
-                    }
-                    break;
-                } else if status == 0xFF00 || status == 0xFF01 {
-                    if verbose {
-                        debug!("Operation pending: {:x}", status);
-                    }
-
-                    // fetch DICOM data
-                    // Some worklist servers sends both command and data in the same PData
-                    // So there is no need to download another PData
-                    let dcm = if let Some(second_pdata) = data.get(1) {
-                        InMemDicomObject::read_dataset_with_ts(second_pdata.data.as_slice(), ts)
-                            .whatever_context("Could not read response data set")?
-                    } else {
-                        let mut rsp = scu.receive_pdata();
-                        let mut response_data = Vec::new();
-                        rsp.read_to_end(&mut response_data)
-                            .whatever_context("Failed to read response data")?;
-
-                        InMemDicomObject::read_dataset_with_ts(&response_data[..], ts)
-                            .whatever_context("Could not read response data set")?
-                    };
-
-                    println!(
-                        "------------------------ Match #{} ------------------------",
-                        i
-                    );
-                    DumpOptions::new()
-                        .dump_object(&dcm)
-                        .context(DumpOutputSnafu)?;
-
-                    // check DICOM status in response data,
-                    // as some implementations might report status code 0
-                    // upon sending the response data
-                    if let Some(status) = dcm.get(tags::STATUS) {
-                        let status = status.to_int::<u16>().ok();
-                        if status == Some(0) {
-                            if verbose {
-                                debug!("Matching is complete");
-                            }
-                            break;
-                        }
-                    }
-
-                    i += 1;
-                } else {
-                    warn!("Operation failed (status code {})", status);
                     break;
                 }
             }
 
-            pdu @ Pdu::Unknown { .. }
-            | pdu @ Pdu::AssociationRQ { .. }
-            | pdu @ Pdu::AssociationAC { .. }
-            | pdu @ Pdu::AssociationRJ { .. }
-            | pdu @ Pdu::ReleaseRQ
-            | pdu @ Pdu::ReleaseRP
-            | pdu @ Pdu::AbortRQ { .. } => {
-                error!("Unexpected SCP response: {:?}", pdu);
-                let _ = scu.abort();
-                std::process::exit(-2);
+            i += 1;
+
+            if cancel_after == Some(i) {
+                if verbose {
+                    debug!("Sending C-CANCEL after {} matches", i);
+                }
+                scu.send_message(
+                    pc_selected_id,
+                    &Command::CCancelRQ(CCancelRQ {
+                        message_id_being_responded_to: message_id,
+                    }),
+                    None,
+                )
+                .whatever_context("Could not send C-Cancel request")?;
+                canceled = true;
             }
+        } else {
+            warn!("Operation failed (status code {})", status.code());
+            break;
         }
     }
     let _ = scu.release();
 
-    Ok(())
+    output.finish()
 }
 
-fn find_req_command(
-    sop_class_uid: &str,
-    message_id: u16,
-) -> InMemDicomObject<StandardDataDictionary> {
-    InMemDicomObject::command_from_element_iter([
-        // SOP Class UID
-        DataElement::new(
-            tags::AFFECTED_SOP_CLASS_UID,
-            VR::UI,
-            PrimitiveValue::from(sop_class_uid),
-        ),
-        // command field
-        DataElement::new(
-            tags::COMMAND_FIELD,
-            VR::US,
-            // 0020H: C-FIND-RQ message
-            dicom_value!(U16, [0x0020]),
-        ),
-        // message ID
-        DataElement::new(tags::MESSAGE_ID, VR::US, dicom_value!(U16, [message_id])),
-        //priority
-        DataElement::new(
-            tags::PRIORITY,
-            VR::US,
-            // medium
-            dicom_value!(U16, [0x0000]),
-        ),
-        // data set type
-        DataElement::new(
-            tags::COMMAND_DATA_SET_TYPE,
-            VR::US,
-            dicom_value!(U16, [0x0001]),
-        ),
-    ])
+fn find_req_command(sop_class_uid: &str, message_id: u16) -> Command {
+    Command::CFindRQ(CFindRQ {
+        message_id,
+        affected_sop_class_uid: sop_class_uid.to_string(),
+        priority: Priority::Medium,
+    })
 }
 
 #[cfg(test)]
@@ -0,0 +1,250 @@
+//! Output of the matched identifiers.
+//!
+//! Besides the human-oriented dump,
+//! identifiers can be written as a single DICOM JSON array,
+//! as CSV with one row per match,
+//! or as individual DICOM files.
+use std::borrow::Cow;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+use dicom_app_common::uid::new_uid;
+use dicom_core::{dictionary::DataDictionaryEntry, ops::AttributeSelector, DataDictionary, Tag};
+use dicom_dictionary_std::{tags, uids, StandardDataDictionary};
+use dicom_dump::DumpOptions;
+use dicom_object::{mem::InMemDicomObject, meta::FileMetaTableBuilder};
+use snafu::prelude::*;
+
+use crate::{DumpOutputSnafu, Error, WriteOutputSnafu};
+
+/// The format in which matched identifiers are written
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// a human readable dump of each identifier
+    Dump,
+    /// a DICOM JSON array of all identifiers
+    Json,
+    /// comma separated values, one row per identifier
+    Csv,
+    /// one DICOM file per identifier
+    Dcm,
+}
+
+/// A CSV column: a header and the attribute it takes its values from
+#[derive(Debug, Clone)]
+pub(crate) struct Column {
+    header: String,
+    selector: AttributeSelector,
+}
+
+/// Writer of matched identifiers in one of the output formats
+#[derive(Debug)]
+pub(crate) enum Output {
+    Dump,
+    Json {
+        count: usize,
+    },
+    Csv {
+        columns: Vec<Column>,
+    },
+    Dcm {
+        out_dir: PathBuf,
+        sop_class_uid: String,
+    },
+}
+
+impl Output {
+    /// Create the output in the given format.
+    ///
+    /// CSV columns are given as attribute selectors
+    /// (e.g. `PatientID` or `0040A730[0].CodeValue`),
+    /// defaulting to the top level attributes of the query.
+    /// DICOM files are written to `out_dir`,
+    /// with the information model as their SOP class
+    /// unless the identifier says otherwise.
+    pub(crate) fn new(
+        format: OutputFormat,
+        columns: &[String],
+        query: &InMemDicomObject,
+        out_dir: PathBuf,
+        sop_class_uid: &str,
+    ) -> Result<Self, Error> {
+        Ok(match format {
+            OutputFormat::Dump => Output::Dump,
+            OutputFormat::Json => Output::Json { count: 0 },
+            OutputFormat::Csv => {
+                let columns = if columns.is_empty() {
+                    default_columns(query)
+                } else {
+                    columns
+                        .iter()
+                        .map(|text| {
+                            let selector = StandardDataDictionary
+                                .parse_selector(text)
+                                .with_whatever_context(|_| {
+                                    format!("Invalid column selector `{}`", text)
+                                })?;
+                            Ok(Column {
+                                header: text.clone(),
+                                selector,
+                            })
+                        })
+                        .collect::<Result<_, Error>>()?
+                };
+                Output::Csv { columns }
+            }
+            OutputFormat::Dcm => {
+                std::fs::create_dir_all(&out_dir)
+                    .whatever_context("Could not create output directory")?;
+                Output::Dcm {
+                    out_dir,
+                    sop_class_uid: sop_class_uid.to_string(),
+                }
+            }
+        })
+    }
+
+    /// Write what precedes the first identifier.
+    pub(crate) fn begin(&mut self) -> Result<(), Error> {
+        let mut stdout = std::io::stdout().lock();
+        match self {
+            Output::Json { .. } => write!(stdout, "[").context(WriteOutputSnafu),
+            Output::Csv { columns } => {
+                let row: Vec<_> = columns.iter().map(|c| c.header.as_str()).collect();
+                write_csv_row(&mut stdout, &row).context(WriteOutputSnafu)
+            }
+            Output::Dump | Output::Dcm { .. } => Ok(()),
+        }
+    }
+
+    /// Write the identifier of match number `i`.
+    pub(crate) fn write(&mut self, i: usize, dcm: &InMemDicomObject) -> Result<(), Error> {
+        match self {
+            Output::Dump => {
+                println!(
+                    "------------------------ Match #{} ------------------------",
+                    i
+                );
+                DumpOptions::new().dump_object(dcm).context(DumpOutputSnafu)
+            }
+            Output::Json { count } => {
+                let mut stdout = std::io::stdout().lock();
+                if *count > 0 {
+                    write!(stdout, ",").context(WriteOutputSnafu)?;
+                }
+                dicom_json::to_writer(&mut stdout, dcm)
+                    .whatever_context("Could not write identifier as DICOM JSON")?;
+                *count += 1;
+                Ok(())
+            }
+            Output::Csv { columns } => {
+                let values: Vec<_> = columns
+                    .iter()
+                    .map(|column| {
+                        dcm.value_at(column.selector.clone())
+                            .ok()
+                            .and_then(|v| v.to_str().ok())
+                            .unwrap_or_default()
+                    })
+                    .collect();
+                let row: Vec<_> = values.iter().map(|v| v.as_ref()).collect();
+                write_csv_row(&mut std::io::stdout().lock(), &row).context(WriteOutputSnafu)
+            }
+            Output::Dcm {
+                out_dir,
+                sop_class_uid,
+            } => {
+                let path = out_dir.join(format!("rsp{:04}.dcm", i));
+                // the identifier usually does not have a SOP class or instance,
+                // in which case they are made up for the file meta group
+                let meta = FileMetaTableBuilder::new()
+                    .media_storage_sop_class_uid(sop_class_uid.as_str())
+                    .media_storage_sop_instance_uid(new_uid())
+                    .transfer_syntax(uids::EXPLICIT_VR_LITTLE_ENDIAN);
+                dcm.clone()
+                    .with_meta(meta)
+                    .whatever_context("Could not create file meta group")?
+                    .write_to_file(&path)
+                    .with_whatever_context(|_| format!("Could not write {}", path.display()))?;
+                tracing::debug!("Written {}", path.display());
+                Ok(())
+            }
+        }
+    }
+
+    /// Write what follows the last identifier.
+    pub(crate) fn finish(&mut self) -> Result<(), Error> {
+        let mut stdout = std::io::stdout().lock();
+        match self {
+            Output::Json { .. } => writeln!(stdout, "]").context(WriteOutputSnafu)?,
+            Output::Dump | Output::Csv { .. } | Output::Dcm { .. } => {}
+        }
+        stdout.flush().context(WriteOutputSnafu)
+    }
+}
+
+/// Make one column per top level attribute of the query,
+/// save for those which only control how the query is done.
+fn default_columns(query: &InMemDicomObject) -> Vec<Column> {
+    query
+        .iter()
+        .map(|elem| elem.header().tag)
+        .filter(|tag| *tag != tags::QUERY_RETRIEVE_LEVEL && *tag != tags::SPECIFIC_CHARACTER_SET)
+        .filter(|tag| {
+            query
+                .get(*tag)
+                .map(|e| e.value().primitive().is_some())
+                .unwrap_or(false)
+        })
+        .map(|tag: Tag| Column {
+            header: StandardDataDictionary
+                .by_tag(tag)
+                .map(|entry| entry.alias().to_string())
+                .unwrap_or_else(|| tag.to_string()),
+            selector: AttributeSelector::from(tag),
+        })
+        .collect()
+}
+
+/// Write a CSV row, quoting the fields which need it.
+fn write_csv_row(to: &mut impl Write, fields: &[&str]) -> std::io::Result<()> {
+    let fields: Vec<Cow<str>> = fields.iter().map(|field| csv_field(field)).collect();
+    writeln!(to, "{}", fields.join(","))
+}
+
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom_core::{DataElement, PrimitiveValue, VR};
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("1.2.3"), "1.2.3");
+        assert_eq!(csv_field("Doe^John"), "Doe^John");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn default_columns_come_from_query() {
+        let query = InMemDicomObject::from_element_iter([
+            DataElement::new(tags::QUERY_RETRIEVE_LEVEL, VR::CS, "STUDY"),
+            DataElement::new(tags::PATIENT_ID, VR::LO, PrimitiveValue::Empty),
+            DataElement::new(tags::STUDY_INSTANCE_UID, VR::UI, PrimitiveValue::Empty),
+        ]);
+        let headers: Vec<_> = default_columns(&query)
+            .into_iter()
+            .map(|c| c.header)
+            .collect();
+        assert_eq!(headers, vec!["PatientID", "StudyInstanceUID"]);
+    }
+}
@@ -0,0 +1,680 @@
+use clap::{Parser, ValueEnum};
+use dicom_app_common::query::parse_queries;
+#[cfg(feature = "tls")]
+use dicom_app_common::TlsClientOptions;
+use dicom_core::{DataElement, PrimitiveValue, VR};
+use dicom_dictionary_std::{tags, uids};
+use dicom_encoding::transfer_syntax::TransferSyntaxIndex;
+use dicom_object::{mem::InMemDicomObject, open_file};
+use dicom_storescp::{
+    create_cstore_response, create_file_header, create_temp_file,
+    layout::{place_instance, Collision, PathTemplate},
+    transfer::ABSTRACT_SYNTAXES,
+};
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use dicom_ul::{
+    association::{client::CloseSocket, ClientAssociation, ClientAssociationOptions},
+    dimse::{
+        CCancelRQ, CGetRQ, CStoreRQ, CStoreRSP, Command, Message, Priority, Status, SubOperations,
+    },
+};
+use snafu::prelude::*;
+use std::io::{BufRead as _, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use tracing::{debug, error, info, warn, Level};
+
+/// How often to check for interrupts while waiting for the SCP
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// DICOM C-GET SCU
+#[derive(Debug, Parser)]
+#[command(version)]
+struct App {
+    /// socket address to GET SCP (example: "127.0.0.1:1045")
+    addr: String,
+    /// a DICOM file representing the query object
+    file: Option<PathBuf>,
+    /// a file containing lines of queries
+    #[arg(long)]
+    query_file: Option<PathBuf>,
+    /// a sequence of queries
+    #[arg(short('q'))]
+    query: Vec<String>,
+
+    /// verbose mode
+    #[arg(short = 'v', long = "verbose")]
+    verbose: bool,
+    /// the calling AE title
+    #[arg(long = "calling-ae-title", default_value = "GET-SCU")]
+    calling_ae_title: String,
+    /// the called AE title
+    #[arg(long = "called-ae-title")]
+    called_ae_title: Option<String>,
+    /// the maximum PDU length
+    #[arg(
+        long = "max-pdu-length",
+        default_value = "16384",
+        value_parser(clap::value_parser!(u32).range(4096..=131_072))
+    )]
+    max_pdu_length: u32,
+
+    /// use patient root information model
+    #[arg(short = 'P', long, conflicts_with = "study")]
+    patient: bool,
+    /// use study root information model (default)
+    #[arg(short = 'S', long, conflicts_with = "patient")]
+    study: bool,
+    /// the query/retrieve level
+    /// (default: the level in the query,
+    /// otherwise "patient" with `-P` and "study" with `-S`)
+    #[arg(short = 'L', long, value_enum)]
+    level: Option<RetrieveLevel>,
+
+    /// output directory for the retrieved instances
+    #[arg(short = 'o', long = "out-dir", default_value = ".")]
+    out_dir: PathBuf,
+    /// only accept native/uncompressed transfer syntaxes
+    /// for the retrieved instances
+    #[arg(long)]
+    uncompressed_only: bool,
+
+    #[cfg(feature = "tls")]
+    #[command(flatten)]
+    tls: TlsClientOptions,
+}
+
+/// A query/retrieve level
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum RetrieveLevel {
+    Patient,
+    Study,
+    Series,
+    Image,
+}
+
+impl RetrieveLevel {
+    /// The value of the Query/Retrieve Level attribute
+    fn as_str(self) -> &'static str {
+        match self {
+            RetrieveLevel::Patient => "PATIENT",
+            RetrieveLevel::Study => "STUDY",
+            RetrieveLevel::Series => "SERIES",
+            RetrieveLevel::Image => "IMAGE",
+        }
+    }
+}
+
+fn main() {
+    run().unwrap_or_else(|err| {
+        error!("{}", snafu::Report::from_error(err));
+        std::process::exit(-2);
+    });
+}
+
+#[derive(Debug, Snafu)]
+enum Error {
+    /// Could not initialize SCU
+    InitScu {
+        source: dicom_ul::association::client::Error,
+    },
+
+    /// Could not set up TLS
+    #[cfg(feature = "tls")]
+    InitTls {
+        source: dicom_app_common::tls::Error,
+    },
+
+    /// Could not construct DICOM command
+    CreateCommand { source: dicom_object::ReadError },
+
+    /// Could not listen for interrupt signals
+    InitSignal { source: std::io::Error },
+
+    #[snafu(whatever, display("{}", message))]
+    Other {
+        message: String,
+        #[snafu(source(from(Box<dyn std::error::Error + 'static>, Some)))]
+        source: Option<Box<dyn std::error::Error + 'static>>,
+    },
+}
+
+fn build_query(
+    file: Option<PathBuf>,
+    query_file: Option<PathBuf>,
+    q: Vec<String>,
+    patient: bool,
+    level: Option<RetrieveLevel>,
+    verbose: bool,
+) -> Result<InMemDicomObject, Error> {
+    // read query file if provided
+    let (base_query_obj, mut has_base) = if let Some(file) = file {
+        if verbose {
+            info!("Opening file '{}'...", file.display());
+        }
+
+        (
+            open_file(file).context(CreateCommandSnafu)?.into_inner(),
+            true,
+        )
+    } else {
+        (InMemDicomObject::new_empty(), false)
+    };
+
+    // read queries from query text file
+    let mut obj = base_query_obj;
+    if let Some(query_file) = query_file {
+        // read text file line by line
+        let mut queries = Vec::new();
+        let file = std::fs::File::open(query_file).whatever_context("Could not open query file")?;
+        let reader = std::io::BufReader::new(file);
+        for line in reader.lines() {
+            let line = line.whatever_context("Could not read line from query file")?;
+            {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+            }
+            queries.push(line);
+        }
+
+        obj = parse_queries(obj, &queries)
+            .whatever_context("Could not build query object from query file")?;
+        has_base = true;
+    }
+
+    // read query options from command line
+
+    if q.is_empty() && !has_base {
+        whatever!("Query not specified");
+    }
+
+    let mut obj =
+        parse_queries(obj, &q).whatever_context("Could not build query object from terms")?;
+
+    // an explicit level takes precedence,
+    // otherwise infer it if not defined in the query
+    let level = match level {
+        Some(level) => Some(level.as_str()),
+        None if obj.get(tags::QUERY_RETRIEVE_LEVEL).is_none() => {
+            Some(if patient { "PATIENT" } else { "STUDY" })
+        }
+        None => None,
+    };
+    if let Some(level) = level {
+        // (0008,0052) CS QueryRetrieveLevel
+        obj.put(DataElement::new(
+            tags::QUERY_RETRIEVE_LEVEL,
+            VR::CS,
+            PrimitiveValue::from(level),
+        ));
+    }
+
+    Ok(obj)
+}
+
+/// Listen for Ctrl+C in the background,
+/// returning a flag which is set on the first interrupt.
+/// A second interrupt terminates the process right away.
+fn watch_interrupt() -> Result<Arc<AtomicBool>, Error> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .context(InitSignalSnafu)?;
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+    std::thread::spawn(move || {
+        runtime.block_on(async move {
+            if tokio::signal::ctrl_c().await.is_err() {
+                return;
+            }
+            flag.store(true, Ordering::SeqCst);
+            warn!("Interrupted, canceling the retrieval (press Ctrl+C again to abort)");
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(-1);
+            }
+        })
+    });
+    Ok(interrupted)
+}
+
+fn run() -> Result<(), Error> {
+    let App {
+        addr,
+        file,
+        query_file,
+        query,
+        verbose,
+        calling_ae_title,
+        called_ae_title,
+        max_pdu_length,
+        patient,
+        study: _,
+        level,
+        out_dir,
+        uncompressed_only,
+        #[cfg(feature = "tls")]
+        tls,
+    } = App::parse();
+
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(if verbose { Level::DEBUG } else { Level::INFO })
+            .finish(),
+    )
+    .unwrap_or_else(|e| {
+        error!("{}", snafu::Report::from_error(e));
+    });
+
+    let dcm_query = build_query(file, query_file, query, patient, level, verbose)?;
+
+    let abstract_syntax = if patient {
+        // Patient Root Query/Retrieve Information Model - GET
+        uids::PATIENT_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_GET
+    } else {
+        // Study Root Query/Retrieve Information Model - GET (default)
+        uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_GET
+    };
+
+    std::fs::create_dir_all(&out_dir).whatever_context("Could not create output directory")?;
+
+    if verbose {
+        info!("Establishing association with '{}'...", &addr);
+    }
+
+    let mut scu_opt = ClientAssociationOptions::new()
+        .with_abstract_syntax(abstract_syntax)
+        .calling_ae_title(calling_ae_title)
+        .max_pdu_length(max_pdu_length);
+
+    // the instances arrive as C-STORE sub-operations on this association,
+    // so this node must also be the SCP of the storage SOP classes
+    // native transfer syntaxes are proposed first,
+    // as some acceptors pick the first one which they support
+    let mut transfer_syntaxes = vec![
+        uids::EXPLICIT_VR_LITTLE_ENDIAN,
+        uids::IMPLICIT_VR_LITTLE_ENDIAN,
+    ];
+    if !uncompressed_only {
+        for ts in TransferSyntaxRegistry.iter() {
+            if !ts.is_unsupported() && !transfer_syntaxes.contains(&ts.uid()) {
+                transfer_syntaxes.push(ts.uid());
+            }
+        }
+    }
+    for uid in ABSTRACT_SYNTAXES {
+        scu_opt = scu_opt
+            .with_presentation_context(*uid, transfer_syntaxes.clone())
+            .with_role_selection(*uid, false, true);
+    }
+
+    if let Some(called_ae_title) = called_ae_title {
+        scu_opt = scu_opt.called_ae_title(called_ae_title);
+    }
+
+    let interrupted = watch_interrupt()?;
+
+    #[cfg_attr(not(feature = "tls"), allow(unused_labels))]
+    let status = 'retrieve: {
+        #[cfg(feature = "tls")]
+        if let Some(tls_config) = tls.client_config().context(InitTlsSnafu)? {
+            let scu = scu_opt
+                .tls_config(tls_config)
+                .server_name(tls.server_name(&addr))
+                .establish_with_tls(&addr)
+                .context(InitScuSnafu)?;
+            break 'retrieve retrieve(
+                scu,
+                abstract_syntax,
+                &dcm_query,
+                &out_dir,
+                &interrupted,
+                verbose,
+            )?;
+        }
+        let scu = scu_opt.establish_with(&addr).context(InitScuSnafu)?;
+        retrieve(
+            scu,
+            abstract_syntax,
+            &dcm_query,
+            &out_dir,
+            &interrupted,
+            verbose,
+        )?
+    };
+
+    if status.is_failure() || status.is_cancel() {
+        std::process::exit(-1);
+    }
+    Ok(())
+}
+
+/// Describe the sub-operation counters of a C-GET response.
+fn describe_sub_operations(sub_operations: &SubOperations) -> String {
+    let count = |counter: Option<u16>| {
+        counter
+            .map(|c| c.to_string())
+            .unwrap_or_else(|| "?".to_string())
+    };
+    format!(
+        "{} remaining, {} completed, {} failed, {} warning",
+        count(sub_operations.remaining),
+        count(sub_operations.completed),
+        count(sub_operations.failed),
+        count(sub_operations.warning),
+    )
+}
+
+/// Send the C-GET request and save the instances
+/// received in its sub-operations,
+/// returning the final status.
+fn retrieve<S>(
+    mut scu: ClientAssociation<S>,
+    abstract_syntax: &str,
+    dcm_query: &InMemDicomObject,
+    out_dir: &Path,
+    interrupted: &AtomicBool,
+    verbose: bool,
+) -> Result<Status, Error>
+where
+    S: Read + Write + CloseSocket,
+{
+    if verbose {
+        info!("Association established");
+    }
+
+    let pc_selected = if let Some(pc_selected) = scu
+        .presentation_contexts()
+        .iter()
+        .find(|pc| scu.abstract_syntax(pc.id) == Some(abstract_syntax))
+    {
+        pc_selected
+    } else {
+        error!("Could not choose a presentation context");
+        let _ = scu.abort();
+        std::process::exit(-2);
+    };
+    let pc_selected_id = pc_selected.id;
+
+    let ts = if let Some(ts) = TransferSyntaxRegistry.get(&pc_selected.transfer_syntax) {
+        ts
+    } else {
+        error!("Poorly negotiated transfer syntax");
+        let _ = scu.abort();
+        std::process::exit(-2);
+    };
+
+    if verbose {
+        debug!("Transfer Syntax: {}", ts.name());
+    }
+
+    let storage_accepted = scu
+        .presentation_contexts()
+        .iter()
+        .any(|pc| pc.id != pc_selected_id && scu.roles(pc.id).is_some_and(|roles| roles.scp));
+    if !storage_accepted {
+        warn!("No storage SOP class was accepted with the SCP role, instances may not be received");
+    }
+
+    let message_id = scu.next_message_id();
+    let cmd = Command::CGetRQ(CGetRQ {
+        message_id,
+        affected_sop_class_uid: abstract_syntax.to_string(),
+        priority: Priority::Medium,
+    });
+
+    let mut iod_data = Vec::with_capacity(128);
+    dcm_query
+        .write_dataset_with_ts(&mut iod_data, ts)
+        .whatever_context("failed to write identifier dataset")?;
+
+    if verbose {
+        debug!("Sending query ({} B)...", iod_data.len());
+    }
+
+    scu.send_message(pc_selected_id, &cmd, Some(&iod_data))
+        .whatever_context("Could not send C-Get request")?;
+
+    if verbose {
+        debug!("Awaiting response...");
+    }
+
+    // wake up regularly while waiting,
+    // so that the request is canceled even if the SCP sends nothing
+    if let Err(e) = scu.inner_stream().set_read_timeout(Some(POLL_INTERVAL)) {
+        debug!("Could not set read timeout: {}", e);
+    }
+
+    let mut cancel_sent = false;
+    let status = loop {
+        if !cancel_sent && interrupted.load(Ordering::SeqCst) {
+            let cancel = Command::CCancelRQ(CCancelRQ {
+                message_id_being_responded_to: message_id,
+            });
+            scu.send_message(pc_selected_id, &cancel, None)
+                .whatever_context("Could not send C-Cancel request")?;
+            cancel_sent = true;
+        }
+
+        let rsp = match scu.receive_message() {
+            Ok(rsp) => rsp,
+            Err(e) if is_timeout(&e) => continue,
+            Err(e) => return Err(e).whatever_context("Failed to receive message from remote node"),
+        };
+
+        match &rsp.command {
+            Command::CStoreRQ(rq) => {
+                let response = store_instance(&scu, rq, &rsp, out_dir);
+                scu.send_message(rsp.presentation_context_id, &response, None)
+                    .whatever_context("Could not send C-Store response")?;
+            }
+            Command::CGetRSP(rsp_cmd) => {
+                if verbose {
+                    debug!("Response command: {:?}", rsp_cmd);
+                }
+                let status = rsp_cmd.status;
+                let sub_operations = describe_sub_operations(&rsp_cmd.sub_operations);
+                if status.is_pending() {
+                    info!("Pending: {}", sub_operations);
+                } else {
+                    report_completion(status, &sub_operations, &rsp, ts)?;
+                    break status;
+                }
+            }
+            command => {
+                warn!("Ignoring unexpected command {:?}", command.command_field());
+            }
+        }
+    };
+    let _ = scu.inner_stream().set_read_timeout(None);
+    let _ = scu.release();
+
+    Ok(status)
+}
+
+/// Whether receiving failed only because nothing arrived in time.
+fn is_timeout(error: &dicom_ul::association::client::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<std::io::Error>() {
+            return matches!(
+                error.kind(),
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+            );
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// Save the instance of a C-STORE sub-operation to the output directory,
+/// returning the response to send back.
+fn store_instance<S>(
+    scu: &ClientAssociation<S>,
+    rq: &CStoreRQ,
+    message: &Message,
+    out_dir: &Path,
+) -> Command
+where
+    S: Read + Write + CloseSocket,
+{
+    let failure = |status| {
+        Command::CStoreRSP(CStoreRSP {
+            message_id_being_responded_to: rq.message_id,
+            affected_sop_class_uid: rq.affected_sop_class_uid.clone(),
+            affected_sop_instance_uid: rq.affected_sop_instance_uid.clone(),
+            status,
+        })
+    };
+
+    let Some(pc) = scu
+        .presentation_contexts()
+        .iter()
+        .find(|pc| pc.id == message.presentation_context_id)
+    else {
+        warn!("Received C-STORE request in unknown presentation context");
+        return failure(Status::SOP_CLASS_NOT_SUPPORTED);
+    };
+    let Some(data) = &message.data else {
+        warn!("Missing data set in C-STORE request");
+        return failure(Status::PROCESSING_FAILURE);
+    };
+
+    // the file is named after the SOP instance UID,
+    // which is sanitized so that it stays in the output directory
+    let result = create_file_header(rq, &pc.transfer_syntax).and_then(|header| {
+        let mut file =
+            create_temp_file(out_dir, &header).whatever_context("could not create DICOM file")?;
+        file.write_all(data)
+            .whatever_context("could not save DICOM object to file")?;
+        // the temporary file is removed if it could not be moved
+        let received = file.into_temp_path();
+        place_instance(
+            out_dir,
+            &PathTemplate::default(),
+            Collision::Overwrite,
+            rq,
+            None,
+            &received,
+        )
+    });
+    match result {
+        Ok(path) => {
+            if let Some(path) = path {
+                info!("Stored {}", path.display());
+            }
+            create_cstore_response(rq)
+        }
+        Err(e) => {
+            error!("{}", snafu::Report::from_error(e));
+            failure(Status::PROCESSING_FAILURE)
+        }
+    }
+}
+
+/// Report the final status of the C-GET operation,
+/// along with the instances which could not be retrieved.
+fn report_completion(
+    status: Status,
+    sub_operations: &str,
+    rsp: &Message,
+    ts: &dicom_encoding::TransferSyntax,
+) -> Result<(), Error> {
+    if status.is_success() {
+        info!("Completed: {}", sub_operations);
+    } else if status.is_warning() {
+        warn!(
+            "Completed with warning (status code {:04X}): {}",
+            status.code(),
+            sub_operations
+        );
+    } else if status.is_cancel() {
+        warn!("Canceled: {}", sub_operations);
+    } else {
+        error!(
+            "Failed (status code {:04X}): {}",
+            status.code(),
+            sub_operations
+        );
+    }
+
+    if let Some(data) = &rsp.data {
+        let identifier = InMemDicomObject::read_dataset_with_ts(&data[..], ts)
+            .whatever_context("Could not read response data set")?;
+        if let Some(failed) = identifier.get(tags::FAILED_SOP_INSTANCE_UID_LIST) {
+            let failed = failed
+                .to_multi_str()
+                .whatever_context("Could not read failed SOP instance UID list")?;
+            for uid in failed.iter() {
+                warn!("Failed to retrieve {}", uid);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use dicom_ul::mock::{MockEvent, MockResponse, MockScp};
+
+    #[test]
+    fn verify_cli() {
+        App::command().debug_assert();
+    }
+
+    #[test]
+    fn cancels_while_scp_is_silent() {
+        let abstract_syntax = uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_GET;
+        let scp = MockScp::new()
+            .with_abstract_syntax(abstract_syntax)
+            .respond(MockResponse::status(Status::CANCEL).delay(Duration::from_secs(1)))
+            .spawn()
+            .unwrap();
+        let scu = ClientAssociationOptions::new()
+            .with_abstract_syntax(abstract_syntax)
+            .establish(scp.addr())
+            .unwrap();
+        let query = InMemDicomObject::from_element_iter([DataElement::new(
+            tags::QUERY_RETRIEVE_LEVEL,
+            VR::CS,
+            "STUDY",
+        )]);
+
+        let interrupted = AtomicBool::new(true);
+        let status = retrieve(
+            scu,
+            abstract_syntax,
+            &query,
+            Path::new("."),
+            &interrupted,
+            false,
+        )
+        .unwrap();
+        assert_eq!(status, Status::CANCEL);
+
+        // the request was canceled before the SCP responded
+        let events = scp.shutdown();
+        let received: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                MockEvent::Received(message) => Some(&message.command),
+                _ => None,
+            })
+            .collect();
+        assert!(
+            matches!(
+                received.as_slice(),
+                [Command::CGetRQ(_), Command::CCancelRQ(_)]
+            ),
+            "{:?}",
+            received
+        );
+    }
+}
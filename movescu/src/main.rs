@@ -0,0 +1,492 @@
+use clap::{Parser, ValueEnum};
+use dicom_app_common::query::parse_queries;
+#[cfg(feature = "tls")]
+use dicom_app_common::TlsClientOptions;
+use dicom_core::{DataElement, PrimitiveValue, VR};
+use dicom_dictionary_std::{tags, uids};
+use dicom_encoding::transfer_syntax::TransferSyntaxIndex;
+use dicom_object::{mem::InMemDicomObject, open_file};
+use dicom_storescp::{store_server, StoreOptions};
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use dicom_ul::{
+    association::{client::CloseSocket, ClientAssociation, ClientAssociationOptions},
+    dimse::{CMoveRQ, Command, Priority, Status, SubOperations},
+    scp::ShutdownHandle,
+};
+use snafu::prelude::*;
+use std::io::{BufRead as _, Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4, TcpListener};
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+use tracing::{debug, error, info, warn, Level};
+
+/// DICOM C-MOVE SCU
+#[derive(Debug, Parser)]
+#[command(version)]
+struct App {
+    /// socket address to MOVE SCP (example: "127.0.0.1:1045")
+    addr: String,
+    /// a DICOM file representing the query object
+    file: Option<PathBuf>,
+    /// a file containing lines of queries
+    #[arg(long)]
+    query_file: Option<PathBuf>,
+    /// a sequence of queries
+    #[arg(short('q'))]
+    query: Vec<String>,
+
+    /// verbose mode
+    #[arg(short = 'v', long = "verbose")]
+    verbose: bool,
+    /// the calling AE title
+    #[arg(long = "calling-ae-title", default_value = "MOVE-SCU")]
+    calling_ae_title: String,
+    /// the called AE title
+    #[arg(long = "called-ae-title")]
+    called_ae_title: Option<String>,
+    /// the maximum PDU length
+    #[arg(
+        long = "max-pdu-length",
+        default_value = "16384",
+        value_parser(clap::value_parser!(u32).range(4096..=131_072))
+    )]
+    max_pdu_length: u32,
+    /// the AE title of the destination of the retrieved instances
+    /// (default: the calling AE title)
+    #[arg(long = "move-destination")]
+    move_destination: Option<String>,
+
+    /// use patient root information model
+    #[arg(short = 'P', long, conflicts_with = "study")]
+    patient: bool,
+    /// use study root information model (default)
+    #[arg(short = 'S', long, conflicts_with = "patient")]
+    study: bool,
+    /// the query/retrieve level
+    /// (default: the level in the query,
+    /// otherwise "patient" with `-P` and "study" with `-S`)
+    #[arg(short = 'L', long, value_enum)]
+    level: Option<RetrieveLevel>,
+
+    /// run a storage SCP on this port
+    /// to receive the retrieved instances
+    #[arg(long = "store-port")]
+    store_port: Option<u16>,
+    /// output directory for the instances received by the storage SCP
+    #[arg(short = 'o', long = "out-dir", default_value = ".")]
+    out_dir: PathBuf,
+
+    #[cfg(feature = "tls")]
+    #[command(flatten)]
+    tls: TlsClientOptions,
+}
+
+/// A query/retrieve level
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum RetrieveLevel {
+    Patient,
+    Study,
+    Series,
+    Image,
+}
+
+impl RetrieveLevel {
+    /// The value of the Query/Retrieve Level attribute
+    fn as_str(self) -> &'static str {
+        match self {
+            RetrieveLevel::Patient => "PATIENT",
+            RetrieveLevel::Study => "STUDY",
+            RetrieveLevel::Series => "SERIES",
+            RetrieveLevel::Image => "IMAGE",
+        }
+    }
+}
+
+fn main() {
+    run().unwrap_or_else(|err| {
+        error!("{}", snafu::Report::from_error(err));
+        std::process::exit(-2);
+    });
+}
+
+#[derive(Debug, Snafu)]
+enum Error {
+    /// Could not initialize SCU
+    InitScu {
+        source: dicom_ul::association::client::Error,
+    },
+
+    /// Could not set up TLS
+    #[cfg(feature = "tls")]
+    InitTls {
+        source: dicom_app_common::tls::Error,
+    },
+
+    /// Could not construct DICOM command
+    CreateCommand { source: dicom_object::ReadError },
+
+    /// Could not start the storage SCP
+    InitStoreScp { source: std::io::Error },
+
+    #[snafu(whatever, display("{}", message))]
+    Other {
+        message: String,
+        #[snafu(source(from(Box<dyn std::error::Error + 'static>, Some)))]
+        source: Option<Box<dyn std::error::Error + 'static>>,
+    },
+}
+
+fn build_query(
+    file: Option<PathBuf>,
+    query_file: Option<PathBuf>,
+    q: Vec<String>,
+    patient: bool,
+    level: Option<RetrieveLevel>,
+    verbose: bool,
+) -> Result<InMemDicomObject, Error> {
+    // read query file if provided
+    let (base_query_obj, mut has_base) = if let Some(file) = file {
+        if verbose {
+            info!("Opening file '{}'...", file.display());
+        }
+
+        (
+            open_file(file).context(CreateCommandSnafu)?.into_inner(),
+            true,
+        )
+    } else {
+        (InMemDicomObject::new_empty(), false)
+    };
+
+    // read queries from query text file
+    let mut obj = base_query_obj;
+    if let Some(query_file) = query_file {
+        // read text file line by line
+        let mut queries = Vec::new();
+        let file = std::fs::File::open(query_file).whatever_context("Could not open query file")?;
+        let reader = std::io::BufReader::new(file);
+        for line in reader.lines() {
+            let line = line.whatever_context("Could not read line from query file")?;
+            {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+            }
+            queries.push(line);
+        }
+
+        obj = parse_queries(obj, &queries)
+            .whatever_context("Could not build query object from query file")?;
+        has_base = true;
+    }
+
+    // read query options from command line
+
+    if q.is_empty() && !has_base {
+        whatever!("Query not specified");
+    }
+
+    let mut obj =
+        parse_queries(obj, &q).whatever_context("Could not build query object from terms")?;
+
+    // an explicit level takes precedence,
+    // otherwise infer it if not defined in the query
+    let level = match level {
+        Some(level) => Some(level.as_str()),
+        None if obj.get(tags::QUERY_RETRIEVE_LEVEL).is_none() => {
+            Some(if patient { "PATIENT" } else { "STUDY" })
+        }
+        None => None,
+    };
+    if let Some(level) = level {
+        // (0008,0052) CS QueryRetrieveLevel
+        obj.put(DataElement::new(
+            tags::QUERY_RETRIEVE_LEVEL,
+            VR::CS,
+            PrimitiveValue::from(level),
+        ));
+    }
+
+    Ok(obj)
+}
+
+/// Accept storage associations on the given listener in the background,
+/// saving the received instances as `dicom-storescp` would.
+///
+/// Returns the handle for shutting down the storage SCP
+/// and the thread in which it runs,
+/// which ends once the ongoing associations are over.
+fn spawn_store_scp(
+    listener: TcpListener,
+    options: StoreOptions,
+) -> (ShutdownHandle, JoinHandle<()>) {
+    let server = store_server(options);
+    let shutdown = server.shutdown_handle();
+    let handle = std::thread::spawn(move || {
+        if let Err(e) = server.serve(listener) {
+            error!("{}", snafu::Report::from_error(e));
+        }
+    });
+    (shutdown, handle)
+}
+
+fn run() -> Result<(), Error> {
+    let App {
+        addr,
+        file,
+        query_file,
+        query,
+        verbose,
+        calling_ae_title,
+        called_ae_title,
+        max_pdu_length,
+        move_destination,
+        patient,
+        study: _,
+        level,
+        store_port,
+        out_dir,
+        #[cfg(feature = "tls")]
+        tls,
+    } = App::parse();
+
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(if verbose { Level::DEBUG } else { Level::INFO })
+            .finish(),
+    )
+    .unwrap_or_else(|e| {
+        error!("{}", snafu::Report::from_error(e));
+    });
+
+    let dcm_query = build_query(file, query_file, query, patient, level, verbose)?;
+
+    let abstract_syntax = if patient {
+        // Patient Root Query/Retrieve Information Model - MOVE
+        uids::PATIENT_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_MOVE
+    } else {
+        // Study Root Query/Retrieve Information Model - MOVE (default)
+        uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_MOVE
+    };
+
+    let move_destination = move_destination.unwrap_or_else(|| calling_ae_title.clone());
+
+    // start the storage SCP before requesting the transfer
+    let store_scp = if let Some(store_port) = store_port {
+        std::fs::create_dir_all(&out_dir).whatever_context("Could not create output directory")?;
+        let listen_addr = SocketAddrV4::new(Ipv4Addr::from(0), store_port);
+        let listener = TcpListener::bind(listen_addr).context(InitStoreScpSnafu)?;
+        info!("{} listening on: tcp://{}", &move_destination, listen_addr);
+        Some(spawn_store_scp(
+            listener,
+            StoreOptions {
+                verbose,
+                ae_title: move_destination.clone(),
+                strict: false,
+                uncompressed_only: false,
+                promiscuous: false,
+                max_pdu_length,
+                out_dir,
+                path_template: Default::default(),
+                on_collision: Default::default(),
+                actions: Default::default(),
+                instances: Default::default(),
+                commitment_on_new_association: false,
+                peers: Default::default(),
+            },
+        ))
+    } else {
+        None
+    };
+
+    if verbose {
+        info!("Establishing association with '{}'...", &addr);
+    }
+
+    let mut scu_opt = ClientAssociationOptions::new()
+        .with_abstract_syntax(abstract_syntax)
+        .calling_ae_title(calling_ae_title)
+        .max_pdu_length(max_pdu_length);
+
+    if let Some(called_ae_title) = called_ae_title {
+        scu_opt = scu_opt.called_ae_title(called_ae_title);
+    }
+
+    #[cfg_attr(not(feature = "tls"), allow(unused_labels))]
+    let status = 'retrieve: {
+        #[cfg(feature = "tls")]
+        if let Some(tls_config) = tls.client_config().context(InitTlsSnafu)? {
+            let scu = scu_opt
+                .tls_config(tls_config)
+                .server_name(tls.server_name(&addr))
+                .establish_with_tls(&addr)
+                .context(InitScuSnafu)?;
+            break 'retrieve retrieve(
+                scu,
+                abstract_syntax,
+                &move_destination,
+                &dcm_query,
+                verbose,
+            )?;
+        }
+        let scu = scu_opt.establish_with(&addr).context(InitScuSnafu)?;
+        retrieve(scu, abstract_syntax, &move_destination, &dcm_query, verbose)?
+    };
+
+    // let the storage SCP finish receiving the last instances
+    if let Some((shutdown, handle)) = store_scp {
+        shutdown.shutdown();
+        let _ = handle.join();
+    }
+
+    if status.is_failure() || status.is_cancel() {
+        std::process::exit(-1);
+    }
+    Ok(())
+}
+
+/// Describe the sub-operation counters of a C-MOVE response.
+fn describe_sub_operations(sub_operations: &SubOperations) -> String {
+    let count = |counter: Option<u16>| {
+        counter
+            .map(|c| c.to_string())
+            .unwrap_or_else(|| "?".to_string())
+    };
+    format!(
+        "{} remaining, {} completed, {} failed, {} warning",
+        count(sub_operations.remaining),
+        count(sub_operations.completed),
+        count(sub_operations.failed),
+        count(sub_operations.warning),
+    )
+}
+
+/// Send the C-MOVE request and report on its progress,
+/// returning the final status.
+fn retrieve<S>(
+    mut scu: ClientAssociation<S>,
+    abstract_syntax: &str,
+    move_destination: &str,
+    dcm_query: &InMemDicomObject,
+    verbose: bool,
+) -> Result<Status, Error>
+where
+    S: Read + Write + CloseSocket,
+{
+    if verbose {
+        info!("Association established");
+    }
+
+    let pc_selected = if let Some(pc_selected) = scu.presentation_contexts().first() {
+        pc_selected
+    } else {
+        error!("Could not choose a presentation context");
+        let _ = scu.abort();
+        std::process::exit(-2);
+    };
+    let pc_selected_id = pc_selected.id;
+
+    let ts = if let Some(ts) = TransferSyntaxRegistry.get(&pc_selected.transfer_syntax) {
+        ts
+    } else {
+        error!("Poorly negotiated transfer syntax");
+        let _ = scu.abort();
+        std::process::exit(-2);
+    };
+
+    if verbose {
+        debug!("Transfer Syntax: {}", ts.name());
+    }
+
+    let cmd = Command::CMoveRQ(CMoveRQ {
+        message_id: scu.next_message_id(),
+        affected_sop_class_uid: abstract_syntax.to_string(),
+        priority: Priority::Medium,
+        move_destination: move_destination.to_string(),
+    });
+
+    let mut iod_data = Vec::with_capacity(128);
+    dcm_query
+        .write_dataset_with_ts(&mut iod_data, ts)
+        .whatever_context("failed to write identifier dataset")?;
+
+    if verbose {
+        debug!("Sending query ({} B)...", iod_data.len());
+    }
+
+    scu.send_message(pc_selected_id, &cmd, Some(&iod_data))
+        .whatever_context("Could not send C-Move request")?;
+
+    if verbose {
+        debug!("Awaiting response...");
+    }
+
+    let status = loop {
+        let rsp = scu
+            .receive_message()
+            .whatever_context("Failed to receive response from remote node")?;
+
+        let Command::CMoveRSP(rsp_cmd) = &rsp.command else {
+            error!("Unexpected SCP response: {:?}", rsp.command);
+            let _ = scu.abort();
+            std::process::exit(-2);
+        };
+        if verbose {
+            debug!("Response command: {:?}", rsp_cmd);
+        }
+        let status = rsp_cmd.status;
+        let sub_operations = describe_sub_operations(&rsp_cmd.sub_operations);
+        if status.is_pending() {
+            info!("Pending: {}", sub_operations);
+            continue;
+        }
+
+        if status.is_success() {
+            info!("Completed: {}", sub_operations);
+        } else if status.is_warning() {
+            warn!(
+                "Completed with warning (status code {:04X}): {}",
+                status.code(),
+                sub_operations
+            );
+        } else if status.is_cancel() {
+            warn!("Canceled: {}", sub_operations);
+        } else {
+            error!(
+                "Failed (status code {:04X}): {}",
+                status.code(),
+                sub_operations
+            );
+        }
+
+        // report the instances which could not be retrieved
+        if let Some(data) = &rsp.data {
+            let identifier = InMemDicomObject::read_dataset_with_ts(&data[..], ts)
+                .whatever_context("Could not read response data set")?;
+            if let Some(failed) = identifier.get(tags::FAILED_SOP_INSTANCE_UID_LIST) {
+                let failed = failed
+                    .to_multi_str()
+                    .whatever_context("Could not read failed SOP instance UID list")?;
+                for uid in failed.iter() {
+                    warn!("Failed to retrieve {}", uid);
+                }
+            }
+        }
+        break status;
+    };
+    let _ = scu.release();
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::App;
+    use clap::CommandFactory;
+
+    #[test]
+    fn verify_cli() {
+        App::command().debug_assert();
+    }
+}
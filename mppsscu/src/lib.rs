@@ -0,0 +1,844 @@
+//! DICOM Modality Performed Procedure Step SCU
+//!
+//! This library implements the requesting side
+//! of the Modality Performed Procedure Step SOP class (PS3.4 Annex F).
+//! A performed procedure step is created in the `IN PROGRESS` state
+//! with an N-CREATE request ([`create`]),
+//! and later updated to `COMPLETED` or `DISCONTINUED`
+//! with an N-SET request ([`set`]).
+//!
+//! The attributes of both requests are built by [`ProcedureStep`]
+//! from the instances acquired in the procedure step,
+//! which are collected in [`PerformedInstances`].
+//!
+//! This crate is not meant to be used outside of this project.
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use dicom_core::{dicom_value, value::DataSetSequence, DataElement, PrimitiveValue, Tag, VR};
+use dicom_dictionary_std::{tags, uids};
+use dicom_encoding::{transfer_syntax::TransferSyntaxIndex, TransferSyntax};
+use dicom_object::InMemDicomObject;
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use dicom_ul::{
+    association::client::{ClientAssociation, CloseSocket},
+    dimse::{Command, CommandField, NCreateRQ, NSetRQ, Status},
+    pdu::PresentationContextResult,
+};
+use snafu::{OptionExt, ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+#[non_exhaustive]
+pub enum Error {
+    /// missing attribute in instance
+    #[snafu(display("missing attribute {}", name))]
+    MissingAttribute { name: &'static str },
+
+    /// invalid attribute in instance
+    #[snafu(display("invalid value of attribute {}", name))]
+    InvalidAttribute {
+        name: &'static str,
+        source: dicom_core::value::ConvertValueError,
+    },
+
+    /// no presentation context accepted
+    /// for Modality Performed Procedure Step
+    NoPresentationContext,
+
+    /// unsupported transfer syntax
+    #[snafu(display("unsupported transfer syntax {}", uid))]
+    UnsupportedTransferSyntax { uid: String },
+
+    /// could not encode data set
+    WriteDataSet {
+        source: Box<dicom_object::WriteError>,
+    },
+
+    /// could not send message
+    Send {
+        source: Box<dicom_ul::association::client::Error>,
+    },
+
+    /// could not receive message
+    Receive {
+        source: Box<dicom_ul::association::client::Error>,
+    },
+
+    /// unexpected message
+    #[snafu(display("unexpected {:?} message", command_field))]
+    UnexpectedMessage { command_field: CommandField },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The status of a performed procedure step,
+/// as in Performed Procedure Step Status (0040,0252).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum StepStatus {
+    /// The procedure step has started
+    InProgress,
+    /// The procedure step has been completed
+    Completed,
+    /// The procedure step was stopped before completion
+    Discontinued,
+}
+
+impl StepStatus {
+    /// The defined term of this status.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StepStatus::InProgress => "IN PROGRESS",
+            StepStatus::Completed => "COMPLETED",
+            StepStatus::Discontinued => "DISCONTINUED",
+        }
+    }
+}
+
+/// Patient level attributes copied from the instances
+const PATIENT_TAGS: &[(Tag, VR)] = &[
+    (tags::PATIENT_NAME, VR::PN),
+    (tags::PATIENT_ID, VR::LO),
+    (tags::PATIENT_BIRTH_DATE, VR::DA),
+    (tags::PATIENT_SEX, VR::CS),
+];
+
+/// Study level attributes copied from the instances
+const STUDY_TAGS: &[(Tag, VR)] = &[
+    (tags::STUDY_INSTANCE_UID, VR::UI),
+    (tags::STUDY_ID, VR::SH),
+    (tags::ACCESSION_NUMBER, VR::SH),
+    (tags::MODALITY, VR::CS),
+    (tags::REQUEST_ATTRIBUTES_SEQUENCE, VR::SQ),
+];
+
+/// Series level attributes copied from the instances
+const SERIES_TAGS: &[(Tag, VR)] = &[
+    (tags::PERFORMING_PHYSICIAN_NAME, VR::PN),
+    (tags::PROTOCOL_NAME, VR::LO),
+    (tags::OPERATORS_NAME, VR::PN),
+    (tags::SERIES_DESCRIPTION, VR::LO),
+];
+
+/// The attributes of the items in the Scheduled Step Attributes Sequence
+/// copied from the Request Attributes Sequence
+const SCHEDULED_STEP_TAGS: &[(Tag, VR)] = &[
+    (tags::REQUESTED_PROCEDURE_ID, VR::SH),
+    (tags::REQUESTED_PROCEDURE_DESCRIPTION, VR::LO),
+    (tags::SCHEDULED_PROCEDURE_STEP_ID, VR::SH),
+    (tags::SCHEDULED_PROCEDURE_STEP_DESCRIPTION, VR::LO),
+    (tags::SCHEDULED_PROTOCOL_CODE_SEQUENCE, VR::SQ),
+];
+
+#[derive(Debug, Clone)]
+struct Series {
+    attributes: InMemDicomObject,
+    images: Vec<InMemDicomObject>,
+    non_images: Vec<InMemDicomObject>,
+}
+
+/// The instances acquired in a performed procedure step,
+/// from which the attributes of the procedure step are derived.
+///
+/// Only the attributes needed for the procedure step
+/// are kept from each instance.
+#[derive(Debug, Clone)]
+pub struct PerformedInstances {
+    /// patient and study attributes of the first instance
+    patient_study: InMemDicomObject,
+    /// series by Series Instance UID
+    series: BTreeMap<String, Series>,
+}
+
+impl Default for PerformedInstances {
+    fn default() -> Self {
+        PerformedInstances {
+            patient_study: InMemDicomObject::new_empty(),
+            series: BTreeMap::new(),
+        }
+    }
+}
+
+impl PerformedInstances {
+    /// Create an empty set of instances.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an instance to the procedure step.
+    ///
+    /// Instances with Rows (0028,0010) are referenced as images,
+    /// all others as non-image composite instances.
+    /// The patient and study attributes
+    /// are taken from the first instance added.
+    pub fn add(&mut self, obj: &InMemDicomObject) -> Result<()> {
+        let sop_class_uid = get_str(obj, tags::SOP_CLASS_UID, "SOPClassUID")?;
+        let sop_instance_uid = get_str(obj, tags::SOP_INSTANCE_UID, "SOPInstanceUID")?;
+        let series_instance_uid = get_str(obj, tags::SERIES_INSTANCE_UID, "SeriesInstanceUID")?;
+
+        if self.series.is_empty() {
+            self.patient_study = copy_attributes(obj, PATIENT_TAGS.iter().chain(STUDY_TAGS));
+        }
+        let series = self
+            .series
+            .entry(series_instance_uid)
+            .or_insert_with(|| Series {
+                attributes: copy_attributes(obj, SERIES_TAGS),
+                images: Vec::new(),
+                non_images: Vec::new(),
+            });
+        let reference = InMemDicomObject::from_element_iter([
+            DataElement::new(
+                tags::REFERENCED_SOP_CLASS_UID,
+                VR::UI,
+                dicom_value!(Str, sop_class_uid),
+            ),
+            DataElement::new(
+                tags::REFERENCED_SOP_INSTANCE_UID,
+                VR::UI,
+                dicom_value!(Str, sop_instance_uid),
+            ),
+        ]);
+        if obj.get(tags::ROWS).is_some() {
+            series.images.push(reference);
+        } else {
+            series.non_images.push(reference);
+        }
+        Ok(())
+    }
+
+    /// Whether no instances were added.
+    pub fn is_empty(&self) -> bool {
+        self.series.is_empty()
+    }
+
+    /// The number of instances added.
+    pub fn len(&self) -> usize {
+        self.series
+            .values()
+            .map(|s| s.images.len() + s.non_images.len())
+            .sum()
+    }
+
+    /// The number of distinct series of the instances added.
+    pub fn series_count(&self) -> usize {
+        self.series.len()
+    }
+
+    /// Build the Performed Series Sequence (0040,0340),
+    /// with one item per series.
+    ///
+    /// Protocol Name is required in each item,
+    /// so the Series Description is used in its place
+    /// if the instances do not have one.
+    pub fn performed_series_sequence(
+        &self,
+        retrieve_ae_title: Option<&str>,
+    ) -> DataSetSequence<InMemDicomObject> {
+        self.series
+            .iter()
+            .map(|(series_instance_uid, series)| {
+                let mut item = InMemDicomObject::new_empty();
+                for (tag, vr) in SERIES_TAGS {
+                    item.put(attribute_or_empty(&series.attributes, *tag, *vr));
+                }
+                if is_empty(&series.attributes, tags::PROTOCOL_NAME) {
+                    if let Some(description) = series.attributes.get(tags::SERIES_DESCRIPTION) {
+                        item.put(DataElement::new(
+                            tags::PROTOCOL_NAME,
+                            VR::LO,
+                            description.value().clone(),
+                        ));
+                    }
+                }
+                item.put(DataElement::new(
+                    tags::SERIES_INSTANCE_UID,
+                    VR::UI,
+                    dicom_value!(Str, series_instance_uid.as_str()),
+                ));
+                item.put(match retrieve_ae_title {
+                    Some(ae_title) => DataElement::new(
+                        tags::RETRIEVE_AE_TITLE,
+                        VR::AE,
+                        dicom_value!(Str, ae_title),
+                    ),
+                    None => empty(tags::RETRIEVE_AE_TITLE, VR::AE),
+                });
+                item.put(DataElement::new(
+                    tags::REFERENCED_IMAGE_SEQUENCE,
+                    VR::SQ,
+                    DataSetSequence::from(series.images.clone()),
+                ));
+                item.put(DataElement::new(
+                    tags::REFERENCED_NON_IMAGE_COMPOSITE_SOP_INSTANCE_SEQUENCE,
+                    VR::SQ,
+                    DataSetSequence::from(series.non_images.clone()),
+                ));
+                item
+            })
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
+
+/// A performed procedure step,
+/// from which the data sets of the N-CREATE and N-SET requests are built.
+#[derive(Debug, Clone, Default)]
+pub struct ProcedureStep {
+    /// The Performed Procedure Step ID
+    pub id: String,
+    /// The AE title of the modality (Performed Station AE Title)
+    pub station_ae_title: String,
+    /// The Performed Station Name, if any
+    pub station_name: Option<String>,
+    /// The Performed Procedure Step Description, if any
+    pub description: Option<String>,
+    /// The start date of the procedure step (`YYYYMMDD`)
+    pub start_date: String,
+    /// The start time of the procedure step (`HHMMSS`)
+    pub start_time: String,
+    /// The end date of the procedure step (`YYYYMMDD`),
+    /// only reported in the N-SET request
+    pub end_date: Option<String>,
+    /// The end time of the procedure step (`HHMMSS`),
+    /// only reported in the N-SET request
+    pub end_time: Option<String>,
+    /// The AE title from which the instances can be retrieved, if known
+    pub retrieve_ae_title: Option<String>,
+    /// The instances acquired in the procedure step
+    pub instances: PerformedInstances,
+}
+
+impl ProcedureStep {
+    /// Build the data set of the N-CREATE request,
+    /// creating the procedure step in the `IN PROGRESS` state.
+    ///
+    /// All attributes required by the SCU in an N-CREATE request
+    /// are included,
+    /// empty if they cannot be obtained from the instances.
+    /// The Scheduled Step Attributes Sequence is built
+    /// from the Request Attributes Sequence of the instances,
+    /// if present,
+    /// and otherwise describes an unscheduled procedure step.
+    pub fn create_data_set(&self) -> InMemDicomObject {
+        let source = &self.instances.patient_study;
+        let mut obj = InMemDicomObject::new_empty();
+
+        // Scheduled Step Attributes Sequence
+        let requests = source
+            .get(tags::REQUEST_ATTRIBUTES_SEQUENCE)
+            .and_then(|e| e.items())
+            .filter(|items| !items.is_empty())
+            .map(|items| items.to_vec())
+            .unwrap_or_else(|| vec![InMemDicomObject::new_empty()]);
+        let scheduled_steps: Vec<_> = requests
+            .iter()
+            .map(|request| {
+                let mut item = InMemDicomObject::new_empty();
+                item.put(attribute_or_empty(source, tags::STUDY_INSTANCE_UID, VR::UI));
+                item.put(empty(tags::REFERENCED_STUDY_SEQUENCE, VR::SQ));
+                item.put(attribute_or_empty(source, tags::ACCESSION_NUMBER, VR::SH));
+                for (tag, vr) in SCHEDULED_STEP_TAGS {
+                    item.put(attribute_or_empty(request, *tag, *vr));
+                }
+                item
+            })
+            .collect();
+        obj.put(DataElement::new(
+            tags::SCHEDULED_STEP_ATTRIBUTES_SEQUENCE,
+            VR::SQ,
+            DataSetSequence::from(scheduled_steps),
+        ));
+
+        // patient
+        for (tag, vr) in PATIENT_TAGS {
+            obj.put(attribute_or_empty(source, *tag, *vr));
+        }
+        obj.put(empty(tags::REFERENCED_PATIENT_SEQUENCE, VR::SQ));
+
+        // performed procedure step information
+        obj.put(DataElement::new(
+            tags::PERFORMED_PROCEDURE_STEP_ID,
+            VR::SH,
+            dicom_value!(Str, self.id.as_str()),
+        ));
+        obj.put(DataElement::new(
+            tags::PERFORMED_STATION_AE_TITLE,
+            VR::AE,
+            dicom_value!(Str, self.station_ae_title.as_str()),
+        ));
+        obj.put(string_or_empty(
+            tags::PERFORMED_STATION_NAME,
+            VR::SH,
+            self.station_name.as_deref(),
+        ));
+        obj.put(empty(tags::PERFORMED_LOCATION, VR::SH));
+        obj.put(DataElement::new(
+            tags::PERFORMED_PROCEDURE_STEP_START_DATE,
+            VR::DA,
+            dicom_value!(Str, self.start_date.as_str()),
+        ));
+        obj.put(DataElement::new(
+            tags::PERFORMED_PROCEDURE_STEP_START_TIME,
+            VR::TM,
+            dicom_value!(Str, self.start_time.as_str()),
+        ));
+        obj.put(DataElement::new(
+            tags::PERFORMED_PROCEDURE_STEP_STATUS,
+            VR::CS,
+            dicom_value!(Str, StepStatus::InProgress.as_str()),
+        ));
+        obj.put(string_or_empty(
+            tags::PERFORMED_PROCEDURE_STEP_DESCRIPTION,
+            VR::LO,
+            self.description.as_deref(),
+        ));
+        obj.put(empty(tags::PERFORMED_PROCEDURE_TYPE_DESCRIPTION, VR::LO));
+        obj.put(empty(tags::PROCEDURE_CODE_SEQUENCE, VR::SQ));
+        obj.put(empty(tags::PERFORMED_PROCEDURE_STEP_END_DATE, VR::DA));
+        obj.put(empty(tags::PERFORMED_PROCEDURE_STEP_END_TIME, VR::TM));
+
+        // image acquisition results
+        obj.put(attribute_or_empty(source, tags::MODALITY, VR::CS));
+        obj.put(attribute_or_empty(source, tags::STUDY_ID, VR::SH));
+        obj.put(empty(tags::PERFORMED_PROTOCOL_CODE_SEQUENCE, VR::SQ));
+        obj.put(DataElement::new(
+            tags::PERFORMED_SERIES_SEQUENCE,
+            VR::SQ,
+            self.instances
+                .performed_series_sequence(self.retrieve_ae_title.as_deref()),
+        ));
+        obj
+    }
+
+    /// Build the data set of the N-SET request
+    /// updating the procedure step to the given status,
+    /// along with its end date and time
+    /// and the series acquired.
+    pub fn set_data_set(&self, status: StepStatus) -> InMemDicomObject {
+        InMemDicomObject::from_element_iter([
+            DataElement::new(
+                tags::PERFORMED_PROCEDURE_STEP_STATUS,
+                VR::CS,
+                dicom_value!(Str, status.as_str()),
+            ),
+            string_or_empty(
+                tags::PERFORMED_PROCEDURE_STEP_END_DATE,
+                VR::DA,
+                self.end_date.as_deref(),
+            ),
+            string_or_empty(
+                tags::PERFORMED_PROCEDURE_STEP_END_TIME,
+                VR::TM,
+                self.end_time.as_deref(),
+            ),
+            DataElement::new(
+                tags::PERFORMED_SERIES_SEQUENCE,
+                VR::SQ,
+                self.instances
+                    .performed_series_sequence(self.retrieve_ae_title.as_deref()),
+            ),
+        ])
+    }
+}
+
+fn get_str(obj: &InMemDicomObject, tag: Tag, name: &'static str) -> Result<String> {
+    let value = obj
+        .get(tag)
+        .context(MissingAttributeSnafu { name })?
+        .to_str()
+        .context(InvalidAttributeSnafu { name })?;
+    Ok(value.trim_end_matches(['\0', ' ']).to_string())
+}
+
+fn copy_attributes<'a>(
+    obj: &InMemDicomObject,
+    attributes: impl IntoIterator<Item = &'a (Tag, VR)>,
+) -> InMemDicomObject {
+    InMemDicomObject::from_element_iter(
+        attributes
+            .into_iter()
+            .filter_map(|(tag, _)| obj.get(*tag).cloned()),
+    )
+}
+
+fn is_empty(obj: &InMemDicomObject, tag: Tag) -> bool {
+    obj.get(tag)
+        .and_then(|e| e.to_str().ok())
+        .map_or(true, |value| value.trim().is_empty())
+}
+
+/// An element with no value,
+/// for type 2 attributes which are not known.
+fn empty(tag: Tag, vr: VR) -> DataElement<InMemDicomObject> {
+    if vr == VR::SQ {
+        DataElement::new(tag, vr, DataSetSequence::empty())
+    } else {
+        DataElement::new(tag, vr, PrimitiveValue::Empty)
+    }
+}
+
+fn attribute_or_empty(obj: &InMemDicomObject, tag: Tag, vr: VR) -> DataElement<InMemDicomObject> {
+    obj.get(tag).cloned().unwrap_or_else(|| empty(tag, vr))
+}
+
+fn string_or_empty(tag: Tag, vr: VR, value: Option<&str>) -> DataElement<InMemDicomObject> {
+    match value {
+        Some(value) => DataElement::new(tag, vr, dicom_value!(Str, value)),
+        None => empty(tag, vr),
+    }
+}
+
+/// The outcome of an N-CREATE or N-SET request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    /// The status of the response
+    pub status: Status,
+    /// The SOP Instance UID of the performed procedure step,
+    /// as returned by the SCP
+    /// or as requested if not returned
+    pub sop_instance_uid: Option<String>,
+}
+
+/// Find the presentation context
+/// of the Modality Performed Procedure Step SOP class.
+fn mpps_context<S>(association: &ClientAssociation<S>) -> Result<PresentationContextResult>
+where
+    S: Read + Write + CloseSocket,
+{
+    association
+        .presentation_contexts()
+        .iter()
+        .find(|pc| {
+            association.abstract_syntax(pc.id) == Some(uids::MODALITY_PERFORMED_PROCEDURE_STEP)
+        })
+        .cloned()
+        .context(NoPresentationContextSnafu)
+}
+
+fn encode(obj: &InMemDicomObject, pc: &PresentationContextResult) -> Result<Vec<u8>> {
+    let ts: &TransferSyntax = TransferSyntaxRegistry.get(&pc.transfer_syntax).context(
+        UnsupportedTransferSyntaxSnafu {
+            uid: pc.transfer_syntax.clone(),
+        },
+    )?;
+    let mut data = Vec::new();
+    obj.write_dataset_with_ts(&mut data, ts)
+        .map_err(Box::from)
+        .context(WriteDataSetSnafu)?;
+    Ok(data)
+}
+
+/// Create a performed procedure step through the given association
+/// with an N-CREATE request,
+/// and wait for the response.
+///
+/// The data set is usually built with [`ProcedureStep::create_data_set`].
+/// If no SOP Instance UID is given,
+/// the SCP assigns one,
+/// which is then returned in the response.
+pub fn create<S>(
+    association: &mut ClientAssociation<S>,
+    sop_instance_uid: Option<&str>,
+    data_set: &InMemDicomObject,
+) -> Result<Response>
+where
+    S: Read + Write + CloseSocket,
+{
+    let pc = mpps_context(association)?;
+    let data = encode(data_set, &pc)?;
+    let command = Command::NCreateRQ(NCreateRQ {
+        message_id: association.next_message_id(),
+        affected_sop_class_uid: uids::MODALITY_PERFORMED_PROCEDURE_STEP.to_string(),
+        affected_sop_instance_uid: sop_instance_uid.map(String::from),
+    });
+    association
+        .send_message(pc.id, &command, Some(&data))
+        .map_err(Box::from)
+        .context(SendSnafu)?;
+
+    let message = association
+        .receive_message()
+        .map_err(Box::from)
+        .context(ReceiveSnafu)?;
+    match message.command {
+        Command::NCreateRSP(rsp) => Ok(Response {
+            status: rsp.status,
+            sop_instance_uid: rsp
+                .affected_sop_instance_uid
+                .or_else(|| sop_instance_uid.map(String::from)),
+        }),
+        command => UnexpectedMessageSnafu {
+            command_field: command.command_field(),
+        }
+        .fail(),
+    }
+}
+
+/// Update the performed procedure step with the given SOP Instance UID
+/// through the given association with an N-SET request,
+/// and wait for the response.
+///
+/// The data set is usually built with [`ProcedureStep::set_data_set`].
+pub fn set<S>(
+    association: &mut ClientAssociation<S>,
+    sop_instance_uid: &str,
+    data_set: &InMemDicomObject,
+) -> Result<Response>
+where
+    S: Read + Write + CloseSocket,
+{
+    let pc = mpps_context(association)?;
+    let data = encode(data_set, &pc)?;
+    let command = Command::NSetRQ(NSetRQ {
+        message_id: association.next_message_id(),
+        requested_sop_class_uid: uids::MODALITY_PERFORMED_PROCEDURE_STEP.to_string(),
+        requested_sop_instance_uid: sop_instance_uid.to_string(),
+    });
+    association
+        .send_message(pc.id, &command, Some(&data))
+        .map_err(Box::from)
+        .context(SendSnafu)?;
+
+    let message = association
+        .receive_message()
+        .map_err(Box::from)
+        .context(ReceiveSnafu)?;
+    match message.command {
+        Command::NSetRSP(rsp) => Ok(Response {
+            status: rsp.status,
+            sop_instance_uid: rsp
+                .affected_sop_instance_uid
+                .or_else(|| Some(sop_instance_uid.to_string())),
+        }),
+        command => UnexpectedMessageSnafu {
+            command_field: command.command_field(),
+        }
+        .fail(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom_core::header::HasLength;
+    use dicom_ul::{
+        mock::{MockEvent, MockResponse, MockScp, MockScpHandle},
+        ClientAssociationOptions,
+    };
+    use std::net::TcpStream;
+
+    fn instance(series: &str, sop_instance_uid: &str, image: bool) -> InMemDicomObject {
+        let mut obj = InMemDicomObject::from_element_iter([
+            DataElement::new(
+                tags::SOP_CLASS_UID,
+                VR::UI,
+                dicom_value!(Str, uids::CT_IMAGE_STORAGE),
+            ),
+            DataElement::new(
+                tags::SOP_INSTANCE_UID,
+                VR::UI,
+                dicom_value!(Str, sop_instance_uid),
+            ),
+            DataElement::new(tags::SERIES_INSTANCE_UID, VR::UI, dicom_value!(Str, series)),
+            DataElement::new(tags::STUDY_INSTANCE_UID, VR::UI, dicom_value!(Str, "1.2.3")),
+            DataElement::new(tags::PATIENT_NAME, VR::PN, dicom_value!(Str, "Doe^John")),
+            DataElement::new(tags::PATIENT_ID, VR::LO, dicom_value!(Str, "P1")),
+            DataElement::new(tags::MODALITY, VR::CS, dicom_value!(Str, "CT")),
+            DataElement::new(tags::SERIES_DESCRIPTION, VR::LO, dicom_value!(Str, "Head")),
+        ]);
+        if image {
+            obj.put(DataElement::new(
+                tags::ROWS,
+                VR::US,
+                dicom_value!(U16, [512]),
+            ));
+        }
+        obj
+    }
+
+    fn step() -> ProcedureStep {
+        let mut instances = PerformedInstances::new();
+        instances
+            .add(&instance("1.2.3.1", "1.2.3.1.1", true))
+            .unwrap();
+        instances
+            .add(&instance("1.2.3.1", "1.2.3.1.2", true))
+            .unwrap();
+        instances
+            .add(&instance("1.2.3.2", "1.2.3.2.1", false))
+            .unwrap();
+        ProcedureStep {
+            id: "PPS1".to_string(),
+            station_ae_title: "MODALITY".to_string(),
+            start_date: "20240101".to_string(),
+            start_time: "120000".to_string(),
+            instances,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn instances_are_grouped_by_series() {
+        let step = step();
+        assert_eq!(step.instances.len(), 3);
+        assert_eq!(step.instances.series_count(), 2);
+
+        let sequence = step.instances.performed_series_sequence(Some("ARCHIVE"));
+        let items = sequence.items();
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            items[0]
+                .get(tags::SERIES_INSTANCE_UID)
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "1.2.3.1"
+        );
+        assert_eq!(
+            items[0]
+                .get(tags::REFERENCED_IMAGE_SEQUENCE)
+                .unwrap()
+                .items()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            items[1]
+                .get(tags::REFERENCED_NON_IMAGE_COMPOSITE_SOP_INSTANCE_SEQUENCE)
+                .unwrap()
+                .items()
+                .unwrap()
+                .len(),
+            1
+        );
+        // protocol name falls back to the series description
+        assert_eq!(
+            items[0].get(tags::PROTOCOL_NAME).unwrap().to_str().unwrap(),
+            "Head"
+        );
+        assert_eq!(
+            items[0]
+                .get(tags::RETRIEVE_AE_TITLE)
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "ARCHIVE"
+        );
+    }
+
+    #[test]
+    fn create_data_set_has_required_attributes() {
+        let obj = step().create_data_set();
+        assert_eq!(
+            obj.get(tags::PERFORMED_PROCEDURE_STEP_STATUS)
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "IN PROGRESS"
+        );
+        assert_eq!(obj.get(tags::PATIENT_ID).unwrap().to_str().unwrap(), "P1");
+        assert_eq!(obj.get(tags::MODALITY).unwrap().to_str().unwrap(), "CT");
+        assert!(obj.get(tags::PATIENT_BIRTH_DATE).unwrap().is_empty());
+        let scheduled = obj
+            .get(tags::SCHEDULED_STEP_ATTRIBUTES_SEQUENCE)
+            .unwrap()
+            .items()
+            .unwrap();
+        assert_eq!(scheduled.len(), 1);
+        assert_eq!(
+            scheduled[0]
+                .get(tags::STUDY_INSTANCE_UID)
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "1.2.3"
+        );
+        assert!(scheduled[0]
+            .get(tags::SCHEDULED_PROCEDURE_STEP_ID)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            obj.get(tags::PERFORMED_SERIES_SEQUENCE)
+                .unwrap()
+                .items()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn set_data_set_has_final_status() {
+        let mut step = step();
+        step.end_date = Some("20240101".to_string());
+        step.end_time = Some("121500".to_string());
+        let obj = step.set_data_set(StepStatus::Discontinued);
+        assert_eq!(
+            obj.get(tags::PERFORMED_PROCEDURE_STEP_STATUS)
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "DISCONTINUED"
+        );
+        assert_eq!(
+            obj.get(tags::PERFORMED_PROCEDURE_STEP_END_TIME)
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "121500"
+        );
+        assert!(obj.get(tags::PATIENT_ID).is_none());
+    }
+
+    /// Associate with a mock SCP of performed procedure steps.
+    fn associate(scp: &MockScpHandle) -> ClientAssociation<TcpStream> {
+        ClientAssociationOptions::new()
+            .with_presentation_context(
+                uids::MODALITY_PERFORMED_PROCEDURE_STEP,
+                vec![uids::IMPLICIT_VR_LITTLE_ENDIAN],
+            )
+            .establish(scp.addr())
+            .unwrap()
+    }
+
+    #[test]
+    fn create_reports_failure_status() {
+        let scp = MockScp::new()
+            .with_abstract_syntax(uids::MODALITY_PERFORMED_PROCEDURE_STEP)
+            .respond(MockResponse::status(Status::PROCESSING_FAILURE))
+            .spawn()
+            .unwrap();
+        let mut association = associate(&scp);
+
+        let response =
+            create(&mut association, Some("1.2.3.9"), &step().create_data_set()).unwrap();
+        assert_eq!(
+            response,
+            Response {
+                status: Status::PROCESSING_FAILURE,
+                sop_instance_uid: Some("1.2.3.9".to_string()),
+            }
+        );
+        association.release().unwrap();
+        scp.shutdown();
+    }
+
+    #[test]
+    fn set_fails_when_scp_aborts() {
+        let scp = MockScp::new()
+            .with_abstract_syntax(uids::MODALITY_PERFORMED_PROCEDURE_STEP)
+            .respond(MockResponse::abort())
+            .spawn()
+            .unwrap();
+        let mut association = associate(&scp);
+
+        let result = set(
+            &mut association,
+            "1.2.3.9",
+            &step().set_data_set(StepStatus::Completed),
+        );
+        assert!(matches!(result, Err(Error::Receive { .. })), "{:?}", result);
+        assert_eq!(scp.shutdown().last(), Some(&MockEvent::Aborted));
+    }
+}
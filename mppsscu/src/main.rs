@@ -0,0 +1,254 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use dicom_app_common::uid::new_uid;
+use dicom_dictionary_std::{tags, uids};
+use dicom_mppsscu::{create, set, PerformedInstances, ProcedureStep, Response, StepStatus};
+use dicom_ul::{dimse::StatusType, ClientAssociationOptions};
+use snafu::{whatever, Report, ResultExt, Whatever};
+use tracing::{debug, error, info, warn, Level};
+use walkdir::WalkDir;
+
+/// DICOM Modality Performed Procedure Step SCU
+#[derive(Debug, Parser)]
+#[command(version)]
+struct App {
+    /// socket address to MPPS SCP,
+    /// optionally with AE title
+    /// (example: "RIS@127.0.0.1:1045")
+    addr: String,
+    /// the DICOM files (or directories) of the instances
+    /// acquired in the procedure step
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+    /// verbose mode
+    #[arg(short = 'v', long = "verbose")]
+    verbose: bool,
+    /// the calling AE title,
+    /// also reported as the Performed Station AE Title
+    #[arg(long = "calling-ae-title", default_value = "MPPS-SCU")]
+    calling_ae_title: String,
+    /// the called Application Entity title,
+    /// overrides AE title in address if present [default: ANY-SCP]
+    #[arg(long = "called-ae-title")]
+    called_ae_title: Option<String>,
+    /// the maximum PDU length
+    #[arg(
+        long = "max-pdu-length",
+        default_value = "16384",
+        value_parser(clap::value_parser!(u32).range(4096..=131_072))
+    )]
+    max_pdu_length: u32,
+
+    /// the status to report:
+    /// "in-progress" creates the procedure step (N-CREATE),
+    /// the others update it (N-SET)
+    #[arg(long = "status", value_enum, default_value = "in-progress")]
+    status: Status,
+    /// the SOP Instance UID of the procedure step
+    /// [default: a new UID when creating it]
+    #[arg(long = "mpps-uid", required_if_eq_any([("status", "completed"), ("status", "discontinued")]))]
+    mpps_uid: Option<String>,
+    /// the Performed Procedure Step ID
+    /// [default: the current date and time]
+    #[arg(long = "pps-id")]
+    pps_id: Option<String>,
+    /// the Performed Procedure Step Description
+    #[arg(long = "description")]
+    description: Option<String>,
+    /// the Performed Station Name
+    #[arg(long = "station-name")]
+    station_name: Option<String>,
+    /// the AE title from which the instances can be retrieved
+    #[arg(long = "retrieve-ae-title")]
+    retrieve_ae_title: Option<String>,
+}
+
+/// A performed procedure step status
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum Status {
+    InProgress,
+    Completed,
+    Discontinued,
+}
+
+impl From<Status> for StepStatus {
+    fn from(status: Status) -> Self {
+        match status {
+            Status::InProgress => StepStatus::InProgress,
+            Status::Completed => StepStatus::Completed,
+            Status::Discontinued => StepStatus::Discontinued,
+        }
+    }
+}
+
+fn main() {
+    run().unwrap_or_else(|e| {
+        error!("{}", Report::from_error(e));
+        std::process::exit(-2);
+    })
+}
+
+fn run() -> Result<(), Whatever> {
+    let App {
+        addr,
+        files,
+        verbose,
+        calling_ae_title,
+        called_ae_title,
+        max_pdu_length,
+        status,
+        mpps_uid,
+        pps_id,
+        description,
+        station_name,
+        retrieve_ae_title,
+    } = App::parse();
+
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(if verbose { Level::DEBUG } else { Level::INFO })
+            .finish(),
+    )
+    .whatever_context("Could not set up global logging subscriber")
+    .unwrap_or_else(|e: Whatever| {
+        eprintln!("[ERROR] {}", Report::from_error(e));
+    });
+
+    let instances = collect_instances(files, verbose);
+    if instances.is_empty() {
+        whatever!("No DICOM instances in the procedure step");
+    }
+    info!(
+        "{} instance(s) in {} series",
+        instances.len(),
+        instances.series_count()
+    );
+
+    let now = chrono::Local::now();
+    let date = now.format("%Y%m%d").to_string();
+    let time = now.format("%H%M%S").to_string();
+    let step = ProcedureStep {
+        id: pps_id.unwrap_or_else(|| format!("{}{}", date, time)),
+        station_ae_title: calling_ae_title.clone(),
+        station_name,
+        description,
+        start_date: date.clone(),
+        start_time: time.clone(),
+        end_date: Some(date),
+        end_time: Some(time),
+        retrieve_ae_title,
+        instances,
+    };
+
+    let mut association_opt = ClientAssociationOptions::new()
+        .with_abstract_syntax(uids::MODALITY_PERFORMED_PROCEDURE_STEP)
+        .calling_ae_title(calling_ae_title)
+        .max_pdu_length(max_pdu_length);
+    if let Some(called_ae_title) = called_ae_title {
+        association_opt = association_opt.called_ae_title(called_ae_title);
+    }
+    let mut association = association_opt
+        .establish_with(&addr)
+        .whatever_context("Could not establish association with SCP")?;
+
+    let step_status = StepStatus::from(status);
+    let response = if step_status == StepStatus::InProgress {
+        let mpps_uid = mpps_uid.unwrap_or_else(new_uid);
+        let data_set = step.create_data_set();
+        if verbose {
+            debug!("N-CREATE data set: {:?}", data_set);
+        }
+        create(&mut association, Some(&mpps_uid), &data_set)
+            .whatever_context("Could not create performed procedure step")?
+    } else {
+        // required by clap for this status
+        let mpps_uid = mpps_uid.unwrap();
+        let data_set = step.set_data_set(step_status);
+        if verbose {
+            debug!("N-SET data set: {:?}", data_set);
+        }
+        set(&mut association, &mpps_uid, &data_set)
+            .whatever_context("Could not update performed procedure step")?
+    };
+    let _ = association.release();
+
+    report(&response, step_status)
+}
+
+/// Read the instances of the given files
+/// and of the files in the given directories,
+/// without their pixel data.
+fn collect_instances(files: Vec<PathBuf>, verbose: bool) -> PerformedInstances {
+    let mut instances = PerformedInstances::new();
+    for file in files {
+        let paths: Vec<_> = if file.is_dir() {
+            WalkDir::new(file.as_path())
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|f| !f.file_type().is_dir())
+                .map(|f| f.into_path())
+                .collect()
+        } else {
+            vec![file]
+        };
+        for path in paths {
+            if verbose {
+                debug!("Opening file '{}'...", path.display());
+            }
+            if let Err(e) = add_instance(&mut instances, &path) {
+                warn!("Skipping {}: {}", path.display(), Report::from_error(e));
+            }
+        }
+    }
+    instances
+}
+
+fn add_instance(instances: &mut PerformedInstances, path: &Path) -> Result<(), Whatever> {
+    let file = dicom_object::OpenFileOptions::new()
+        .read_until(tags::PIXEL_DATA)
+        .open_file(path)
+        .whatever_context("could not read DICOM file")?;
+    instances
+        .add(&file)
+        .whatever_context("could not read instance attributes")
+}
+
+/// Log the status of the response
+/// and the SOP Instance UID of the procedure step,
+/// failing if the SCP reported a failure.
+fn report(response: &Response, status: StepStatus) -> Result<(), Whatever> {
+    let sop_instance_uid = response.sop_instance_uid.as_deref().unwrap_or("(none)");
+    let code = response.status.code();
+    match response.status.status_type() {
+        StatusType::Success => {
+            info!("✓ Procedure step is {}", status.as_str());
+        }
+        StatusType::Warning => {
+            warn!(
+                "Procedure step is {}, with warning (status code {:04X}H)",
+                status.as_str(),
+                code
+            );
+        }
+        _ => whatever!(
+            "Request for procedure step {} failed (status code {:04X}H)",
+            sop_instance_uid,
+            code
+        ),
+    }
+    info!("Status: {:04X}H", code);
+    info!("Affected SOP Instance UID: {}", sop_instance_uid);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::App;
+    use clap::CommandFactory;
+
+    #[test]
+    fn verify_cli() {
+        App::command().debug_assert();
+    }
+}
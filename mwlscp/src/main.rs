@@ -0,0 +1,187 @@
+use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    path::PathBuf,
+};
+
+use clap::Parser;
+use dicom_app_common::matching::{build_response, matches};
+use dicom_dictionary_std::uids;
+use dicom_encoding::transfer_syntax::TransferSyntaxIndex;
+use dicom_object::InMemDicomObject;
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use dicom_ul::{
+    dimse::{CFindRQ, Status},
+    scp::{FindOperation, RequestContext, ScpServer, ServiceRegistry},
+    ServerAssociationOptions,
+};
+use snafu::Report;
+use tracing::{error, info, warn, Level};
+
+mod worklist;
+
+use worklist::Worklist;
+
+/// Failure (C000H): unable to process
+const UNABLE_TO_PROCESS: Status = Status(0xC000);
+
+/// DICOM Modality Worklist SCP
+#[derive(Debug, Parser)]
+#[command(version)]
+struct App {
+    /// The directory of worklist entries (DICOM or DICOM JSON files)
+    dir: PathBuf,
+    /// Verbose mode
+    #[arg(short = 'v', long = "verbose")]
+    verbose: bool,
+    /// Calling Application Entity title
+    #[arg(long = "calling-ae-title", default_value = "MWL-SCP")]
+    calling_ae_title: String,
+    /// Enforce max pdu length
+    #[arg(short = 's', long = "strict")]
+    strict: bool,
+    /// Maximum PDU length
+    #[arg(
+        short = 'm',
+        long = "max-pdu-length",
+        default_value = "16384",
+        value_parser(clap::value_parser!(u32).range(4096..=131_072))
+    )]
+    max_pdu_length: u32,
+    /// Which port to listen on
+    #[arg(short, default_value = "11111")]
+    port: u16,
+}
+
+fn main() {
+    run(App::parse()).unwrap_or_else(|e| {
+        error!("{:?}", e);
+        std::process::exit(-2);
+    });
+}
+
+fn run(args: App) -> Result<(), Box<dyn std::error::Error>> {
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(if args.verbose {
+                Level::DEBUG
+            } else {
+                Level::INFO
+            })
+            .finish(),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!(
+            "Could not set up global logger: {}",
+            snafu::Report::from_error(e)
+        );
+    });
+
+    // check the directory early
+    let worklist = Worklist::new(&args.dir);
+    let items = worklist.items()?;
+    info!(
+        "Serving {} worklist item(s) in {}",
+        items.len(),
+        args.dir.display()
+    );
+
+    let services = ServiceRegistry::new()
+        .with_echo(|_: &RequestContext<'_>| Status::SUCCESS)
+        .with_find(
+            uids::MODALITY_WORKLIST_INFORMATION_MODEL_FIND,
+            move |context: &RequestContext<'_>,
+                  _: &CFindRQ,
+                  identifier: &[u8],
+                  operation: &mut dyn FindOperation| {
+                find(&worklist, context, identifier, operation)
+            },
+        );
+    let options = ServerAssociationOptions::new()
+        .accept_any()
+        .ae_title(args.calling_ae_title.clone())
+        .strict(args.strict)
+        .max_pdu_length(args.max_pdu_length);
+
+    let listen_addr = SocketAddrV4::new(Ipv4Addr::from(0), args.port);
+    let listener = std::net::TcpListener::bind(listen_addr)?;
+    info!(
+        "{} listening on: tcp://{}",
+        &args.calling_ae_title, listen_addr
+    );
+
+    ScpServer::new(options, services).serve(listener)?;
+    Ok(())
+}
+
+/// Answer a worklist query
+/// with the worklist items currently in the directory.
+fn find(
+    worklist: &Worklist,
+    context: &RequestContext<'_>,
+    identifier: &[u8],
+    operation: &mut dyn FindOperation,
+) -> Status {
+    match send_matches(worklist, context, identifier, operation) {
+        Ok(count) => {
+            info!(
+                "C-FIND from {}: {} match(es)",
+                context.calling_ae_title, count
+            );
+            Status::SUCCESS
+        }
+        Err(status) => status,
+    }
+}
+
+/// Send the worklist items matching the query as they are found,
+/// returning how many were sent.
+fn send_matches(
+    worklist: &Worklist,
+    context: &RequestContext<'_>,
+    identifier: &[u8],
+    operation: &mut dyn FindOperation,
+) -> Result<usize, Status> {
+    let ts = TransferSyntaxRegistry
+        .get(context.transfer_syntax)
+        .ok_or(UNABLE_TO_PROCESS)?;
+    let identifier = InMemDicomObject::read_dataset_with_ts(identifier, ts).map_err(|e| {
+        warn!("Could not read identifier: {}", Report::from_error(e));
+        UNABLE_TO_PROCESS
+    })?;
+    let items = worklist.items().map_err(|e| {
+        warn!("{}", Report::from_error(e));
+        UNABLE_TO_PROCESS
+    })?;
+
+    let mut count = 0;
+    for item in items.iter().filter(|item| matches(item, &identifier)) {
+        if operation.is_canceled() {
+            info!("C-FIND from {} canceled", context.calling_ae_title);
+            return Err(Status::CANCEL);
+        }
+        let mut data = Vec::new();
+        build_response(item, &identifier)
+            .write_dataset_with_ts(&mut data, ts)
+            .map_err(|e| {
+                warn!("Could not write response: {}", Report::from_error(e));
+                UNABLE_TO_PROCESS
+            })?;
+        operation.send_match(&data).map_err(|e| {
+            warn!("Could not send match: {}", Report::from_error(e));
+            UNABLE_TO_PROCESS
+        })?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::App;
+    use clap::CommandFactory;
+
+    #[test]
+    fn verify_cli() {
+        App::command().debug_assert();
+    }
+}
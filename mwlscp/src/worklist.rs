@@ -0,0 +1,276 @@
+//! Loading of worklist items from files.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+
+use dicom_core::value::DataSetSequence;
+use dicom_core::{DataElement, VR};
+use dicom_dictionary_std::tags;
+use dicom_object::{open_file, InMemDicomObject};
+use snafu::{Report, ResultExt, Whatever};
+use tracing::{debug, warn};
+
+/// The worklist items in the files of a directory.
+///
+/// Files with the `.json` extension are read as DICOM JSON,
+/// containing either a single data set or an array of data sets.
+/// All other files are read as DICOM files.
+/// Files which cannot be read are skipped with a warning.
+///
+/// Data sets with more than one item
+/// in the Scheduled Procedure Step Sequence
+/// are split into one worklist item per scheduled procedure step.
+///
+/// The items of each file are kept in memory,
+/// and only read again when the file is modified,
+/// so that the directory is only listed on every query.
+#[derive(Debug)]
+pub struct Worklist {
+    dir: PathBuf,
+    files: Mutex<HashMap<PathBuf, CachedFile>>,
+}
+
+/// The worklist items of a file when it was last read
+#[derive(Debug)]
+struct CachedFile {
+    modified: Option<SystemTime>,
+    len: u64,
+    items: Vec<InMemDicomObject>,
+}
+
+impl Worklist {
+    /// Serve the worklist items in the given directory.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Worklist {
+            dir: dir.into(),
+            files: Mutex::default(),
+        }
+    }
+
+    /// Obtain the worklist items currently in the directory,
+    /// reading the files which were added or modified since the last time.
+    pub fn items(&self) -> Result<Vec<InMemDicomObject>, Whatever> {
+        let entries = std::fs::read_dir(&self.dir).with_whatever_context(|_| {
+            format!("could not read directory {}", self.dir.display())
+        })?;
+        let mut files = self.files.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut current = HashMap::new();
+        for entry in entries {
+            let path = entry
+                .whatever_context("could not read directory entry")?
+                .path();
+            let Ok(metadata) = std::fs::metadata(&path) else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            let modified = metadata.modified().ok();
+            let len = metadata.len();
+            let file = match files.remove(&path) {
+                Some(file) if file.modified == modified && file.len == len => file,
+                _ => {
+                    debug!("Reading {}", path.display());
+                    let items = match load_file(&path) {
+                        Ok(objects) => objects
+                            .into_iter()
+                            .flat_map(split_procedure_steps)
+                            .collect(),
+                        Err(e) => {
+                            warn!("Skipping {}: {}", path.display(), Report::from_error(e));
+                            Vec::new()
+                        }
+                    };
+                    CachedFile {
+                        modified,
+                        len,
+                        items,
+                    }
+                }
+            };
+            current.insert(path, file);
+        }
+        // files no longer in the directory are forgotten
+        *files = current;
+        Ok(files
+            .values()
+            .flat_map(|file| file.items.iter().cloned())
+            .collect())
+    }
+}
+
+fn load_file(path: &Path) -> Result<Vec<InMemDicomObject>, Whatever> {
+    let is_json = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    if !is_json {
+        let file = open_file(path).whatever_context("could not read DICOM file")?;
+        return Ok(vec![file.into_inner()]);
+    }
+
+    let contents = std::fs::read(path).whatever_context("could not read file")?;
+    let value: serde_json::Value =
+        serde_json::from_slice(&contents).whatever_context("could not parse JSON")?;
+    let values = match value {
+        serde_json::Value::Array(values) => values,
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .map(|value| dicom_json::from_value(value).whatever_context("could not read DICOM JSON"))
+        .collect()
+}
+
+/// Turn each item of the Scheduled Procedure Step Sequence
+/// into a separate worklist item.
+fn split_procedure_steps(obj: InMemDicomObject) -> Vec<InMemDicomObject> {
+    let steps = match obj
+        .get(tags::SCHEDULED_PROCEDURE_STEP_SEQUENCE)
+        .and_then(|e| e.items())
+    {
+        Some(steps) if steps.len() > 1 => steps.to_vec(),
+        _ => return vec![obj],
+    };
+    steps
+        .into_iter()
+        .map(|step| {
+            let mut item = obj.clone();
+            item.put(DataElement::new(
+                tags::SCHEDULED_PROCEDURE_STEP_SEQUENCE,
+                VR::SQ,
+                DataSetSequence::from(vec![step]),
+            ));
+            item
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom_dictionary_std::uids;
+    use dicom_object::FileMetaTableBuilder;
+
+    fn step(modality: &str) -> InMemDicomObject {
+        InMemDicomObject::from_element_iter([DataElement::new(tags::MODALITY, VR::CS, modality)])
+    }
+
+    fn entry(patient_id: &str, steps: Vec<InMemDicomObject>) -> InMemDicomObject {
+        InMemDicomObject::from_element_iter([
+            DataElement::new(tags::PATIENT_ID, VR::LO, patient_id),
+            DataElement::new(
+                tags::SCHEDULED_PROCEDURE_STEP_SEQUENCE,
+                VR::SQ,
+                DataSetSequence::from(steps),
+            ),
+        ])
+    }
+
+    fn write_dicom(path: &Path, obj: InMemDicomObject) {
+        obj.with_meta(
+            FileMetaTableBuilder::new()
+                .media_storage_sop_class_uid(uids::MODALITY_WORKLIST_INFORMATION_MODEL_FIND)
+                .media_storage_sop_instance_uid("1.2.3.4")
+                .transfer_syntax(uids::EXPLICIT_VR_LITTLE_ENDIAN),
+        )
+        .unwrap()
+        .write_to_file(path)
+        .unwrap();
+    }
+
+    fn patient_ids(items: &[InMemDicomObject]) -> Vec<String> {
+        let mut ids: Vec<_> = items
+            .iter()
+            .map(|item| {
+                item.element(tags::PATIENT_ID)
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .into_owned()
+            })
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn loads_dicom_and_json_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_dicom(&dir.path().join("p1.dcm"), entry("P1", vec![step("CT")]));
+        std::fs::write(
+            dir.path().join("more.JSON"),
+            r#"[
+                {"00100020": {"vr": "LO", "Value": ["P2"]}},
+                {"00100020": {"vr": "LO", "Value": ["P3"]}}
+            ]"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("p4.json"),
+            r#"{"00100020": {"vr": "LO", "Value": ["P4"]}}"#,
+        )
+        .unwrap();
+        // skipped
+        std::fs::write(dir.path().join("notes.txt"), "not DICOM").unwrap();
+        std::fs::write(dir.path().join("broken.json"), "{").unwrap();
+        std::fs::create_dir(dir.path().join("old")).unwrap();
+        write_dicom(&dir.path().join("old").join("p5.dcm"), entry("P5", vec![]));
+
+        let items = Worklist::new(dir.path()).items().unwrap();
+        assert_eq!(patient_ids(&items), vec!["P1", "P2", "P3", "P4"]);
+    }
+
+    #[test]
+    fn splits_procedure_steps() {
+        let items = split_procedure_steps(entry("P1", vec![step("CT"), step("MR")]));
+        let modalities: Vec<_> = items
+            .iter()
+            .map(|item| {
+                let steps = item
+                    .element(tags::SCHEDULED_PROCEDURE_STEP_SEQUENCE)
+                    .unwrap()
+                    .items()
+                    .unwrap();
+                assert_eq!(steps.len(), 1);
+                assert_eq!(
+                    item.element(tags::PATIENT_ID).unwrap().to_str().unwrap(),
+                    "P1"
+                );
+                steps[0]
+                    .element(tags::MODALITY)
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .into_owned()
+            })
+            .collect();
+        assert_eq!(modalities, vec!["CT", "MR"]);
+
+        // left as is
+        assert_eq!(
+            split_procedure_steps(entry("P2", vec![step("US")])).len(),
+            1
+        );
+        let none =
+            InMemDicomObject::from_element_iter([DataElement::new(tags::PATIENT_ID, VR::LO, "P3")]);
+        assert_eq!(split_procedure_steps(none).len(), 1);
+    }
+
+    #[test]
+    fn reloads_changed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let worklist = Worklist::new(dir.path());
+        let p1 = dir.path().join("p1.json");
+        std::fs::write(&p1, r#"{"00100020": {"vr": "LO", "Value": ["P1"]}}"#).unwrap();
+        assert_eq!(patient_ids(&worklist.items().unwrap()), vec!["P1"]);
+
+        let p2 = dir.path().join("p2.json");
+        std::fs::write(&p2, r#"{"00100020": {"vr": "LO", "Value": ["P2"]}}"#).unwrap();
+        assert_eq!(patient_ids(&worklist.items().unwrap()), vec!["P1", "P2"]);
+
+        std::fs::write(&p1, r#"{"00100020": {"vr": "LO", "Value": ["P1B"]}}"#).unwrap();
+        std::fs::remove_file(&p2).unwrap();
+        assert_eq!(patient_ids(&worklist.items().unwrap()), vec!["P1B"]);
+    }
+}
@@ -0,0 +1,518 @@
+//! Index of the DICOM files served by the provider.
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use dicom_app_common::matching::{build_response, matches};
+use dicom_core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom_dictionary_std::{tags, uids};
+use dicom_object::{InMemDicomObject, OpenFileOptions};
+use dicom_ul::dimse::Status;
+use tracing::{debug, warn};
+use walkdir::WalkDir;
+
+/// Failure (A900H): identifier does not match SOP class
+pub const IDENTIFIER_DOES_NOT_MATCH_SOP_CLASS: Status = Status(0xA900);
+
+/// A query/retrieve information model
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InformationModel {
+    PatientRoot,
+    StudyRoot,
+}
+
+impl InformationModel {
+    /// The information model of a FIND, MOVE or GET SOP class.
+    pub fn from_sop_class(uid: &str) -> Option<Self> {
+        match uid {
+            uids::PATIENT_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_FIND
+            | uids::PATIENT_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_MOVE
+            | uids::PATIENT_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_GET => {
+                Some(InformationModel::PatientRoot)
+            }
+            uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_FIND
+            | uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_MOVE
+            | uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_GET => {
+                Some(InformationModel::StudyRoot)
+            }
+            _ => None,
+        }
+    }
+
+    /// The levels of the information model, from top to bottom.
+    fn levels(self) -> &'static [QueryLevel] {
+        match self {
+            InformationModel::PatientRoot => &[
+                QueryLevel::Patient,
+                QueryLevel::Study,
+                QueryLevel::Series,
+                QueryLevel::Image,
+            ],
+            InformationModel::StudyRoot => {
+                &[QueryLevel::Study, QueryLevel::Series, QueryLevel::Image]
+            }
+        }
+    }
+}
+
+/// A query/retrieve level
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum QueryLevel {
+    Patient,
+    Study,
+    Series,
+    Image,
+}
+
+impl QueryLevel {
+    /// The level in the Query/Retrieve Level attribute of an identifier.
+    fn from_identifier(identifier: &InMemDicomObject) -> Option<Self> {
+        let level = identifier.get(tags::QUERY_RETRIEVE_LEVEL)?.to_str().ok()?;
+        match level.trim() {
+            "PATIENT" => Some(QueryLevel::Patient),
+            "STUDY" => Some(QueryLevel::Study),
+            "SERIES" => Some(QueryLevel::Series),
+            "IMAGE" => Some(QueryLevel::Image),
+            _ => None,
+        }
+    }
+
+    /// The unique key of the level.
+    fn unique_key(self) -> Tag {
+        match self {
+            QueryLevel::Patient => tags::PATIENT_ID,
+            QueryLevel::Study => tags::STUDY_INSTANCE_UID,
+            QueryLevel::Series => tags::SERIES_INSTANCE_UID,
+            QueryLevel::Image => tags::SOP_INSTANCE_UID,
+        }
+    }
+}
+
+/// An indexed DICOM file
+#[derive(Debug)]
+pub struct Entry {
+    /// the path to the file
+    pub path: PathBuf,
+    /// the SOP class of the instance
+    pub sop_class_uid: String,
+    /// the SOP instance UID
+    pub sop_instance_uid: String,
+    /// the transfer syntax of the file
+    pub transfer_syntax: String,
+    /// the attributes of the instance, up to the pixel data
+    attributes: InMemDicomObject,
+}
+
+/// An in-memory index of the DICOM files in a directory.
+#[derive(Debug, Default)]
+pub struct Index {
+    entries: Vec<Entry>,
+}
+
+impl Index {
+    /// Index all DICOM files in the given directory and its subdirectories.
+    ///
+    /// Files which cannot be read as DICOM files are skipped.
+    pub fn scan(dir: &Path) -> Self {
+        let mut entries = Vec::new();
+        for file in WalkDir::new(dir)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|f| f.file_type().is_file())
+        {
+            let path = file.into_path();
+            match OpenFileOptions::new()
+                .read_until(tags::PIXEL_DATA)
+                .open_file(&path)
+            {
+                Ok(obj) => {
+                    let meta = obj.meta();
+                    debug!("Indexed {}", path.display());
+                    entries.push(Entry {
+                        sop_class_uid: meta.media_storage_sop_class_uid().to_string(),
+                        sop_instance_uid: meta.media_storage_sop_instance_uid().to_string(),
+                        transfer_syntax: meta.transfer_syntax().to_string(),
+                        attributes: obj.into_inner(),
+                        path,
+                    });
+                }
+                Err(e) => {
+                    warn!("Could not index {}: {}", path.display(), e);
+                }
+            }
+        }
+        Index { entries }
+    }
+
+    /// The number of files indexed.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The distinct SOP classes of the files indexed,
+    /// each with the distinct transfer syntaxes of its files.
+    pub fn sop_classes(&self) -> HashMap<&str, BTreeSet<&str>> {
+        let mut sop_classes: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+        for entry in &self.entries {
+            sop_classes
+                .entry(&entry.sop_class_uid)
+                .or_default()
+                .insert(&entry.transfer_syntax);
+        }
+        sop_classes
+    }
+
+    /// Find the records matching a C-FIND identifier,
+    /// returning the response identifiers.
+    pub fn find(
+        &self,
+        model: InformationModel,
+        identifier: &InMemDicomObject,
+    ) -> Result<Vec<InMemDicomObject>, Status> {
+        let level = Self::query_level(model, identifier)?;
+        Ok(self
+            .matching_records(model, level, identifier)
+            .into_iter()
+            .map(|(record, _)| {
+                let mut response = build_response(&record, identifier);
+                // the unique key is always returned
+                if response.get(level.unique_key()).is_none() {
+                    if let Some(key) = record.get(level.unique_key()) {
+                        response.put(key.clone());
+                    }
+                }
+                response
+            })
+            .collect())
+    }
+
+    /// Find the instances to retrieve
+    /// for a C-MOVE or C-GET identifier.
+    pub fn retrieve(
+        &self,
+        model: InformationModel,
+        identifier: &InMemDicomObject,
+    ) -> Result<Vec<&Entry>, Status> {
+        let level = Self::query_level(model, identifier)?;
+        Ok(self
+            .matching_records(model, level, identifier)
+            .into_iter()
+            .flat_map(|(_, entries)| entries)
+            .collect())
+    }
+
+    fn query_level(
+        model: InformationModel,
+        identifier: &InMemDicomObject,
+    ) -> Result<QueryLevel, Status> {
+        QueryLevel::from_identifier(identifier)
+            .filter(|level| model.levels().contains(level))
+            .ok_or(IDENTIFIER_DOES_NOT_MATCH_SOP_CLASS)
+    }
+
+    /// Group the indexed files into records of the given level
+    /// (patients, studies, series or instances)
+    /// and keep the ones matching the identifier,
+    /// along with the files of each record.
+    fn matching_records(
+        &self,
+        model: InformationModel,
+        level: QueryLevel,
+        identifier: &InMemDicomObject,
+    ) -> Vec<(InMemDicomObject, Vec<&Entry>)> {
+        let levels = model.levels();
+        let depth = levels.iter().position(|l| *l == level).unwrap_or(0) + 1;
+
+        let mut groups: Vec<Vec<&Entry>> = Vec::new();
+        let mut positions: HashMap<Vec<String>, usize> = HashMap::new();
+        for entry in &self.entries {
+            let key: Vec<String> = levels[..depth]
+                .iter()
+                .map(|level| entry.value(level.unique_key()))
+                .collect();
+            let position = *positions.entry(key).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[position].push(entry);
+        }
+
+        groups
+            .into_iter()
+            .filter_map(|entries| {
+                let record = record_attributes(level, &entries);
+                matches(&record, identifier).then_some((record, entries))
+            })
+            .collect()
+    }
+}
+
+impl Entry {
+    /// The value of an attribute, without padding,
+    /// or an empty string if missing.
+    fn value(&self, tag: Tag) -> String {
+        self.attributes
+            .get(tag)
+            .and_then(|e| e.to_str().ok())
+            .map(|v| v.trim_end_matches([' ', '\0']).to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Build the attributes of a record from its files,
+/// including the attributes which describe the record as a whole,
+/// such as the number of related instances.
+fn record_attributes(level: QueryLevel, entries: &[&Entry]) -> InMemDicomObject {
+    let mut record = entries[0].attributes.clone();
+    let count = |tag| {
+        entries
+            .iter()
+            .map(|e| e.value(tag))
+            .collect::<BTreeSet<_>>()
+            .len()
+    };
+    let mut put_count = |tag, value: usize| {
+        record.put(DataElement::new(
+            tag,
+            VR::IS,
+            PrimitiveValue::from(value.to_string()),
+        ));
+    };
+    match level {
+        QueryLevel::Patient => {
+            put_count(
+                tags::NUMBER_OF_PATIENT_RELATED_STUDIES,
+                count(tags::STUDY_INSTANCE_UID),
+            );
+            put_count(
+                tags::NUMBER_OF_PATIENT_RELATED_SERIES,
+                count(tags::SERIES_INSTANCE_UID),
+            );
+            put_count(tags::NUMBER_OF_PATIENT_RELATED_INSTANCES, entries.len());
+        }
+        QueryLevel::Study => {
+            put_count(
+                tags::NUMBER_OF_STUDY_RELATED_SERIES,
+                count(tags::SERIES_INSTANCE_UID),
+            );
+            put_count(tags::NUMBER_OF_STUDY_RELATED_INSTANCES, entries.len());
+            let modalities: BTreeSet<_> = entries
+                .iter()
+                .map(|e| e.value(tags::MODALITY))
+                .filter(|m| !m.is_empty())
+                .collect();
+            record.put(DataElement::new(
+                tags::MODALITIES_IN_STUDY,
+                VR::CS,
+                PrimitiveValue::Strs(modalities.into_iter().collect()),
+            ));
+        }
+        QueryLevel::Series => {
+            put_count(tags::NUMBER_OF_SERIES_RELATED_INSTANCES, entries.len());
+        }
+        QueryLevel::Image => {}
+    }
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom_core::dicom_value;
+
+    /// An instance of a CT image with the given patient and UIDs.
+    fn entry(patient_id: &str, study: &str, series: &str, sop_instance_uid: &str) -> Entry {
+        let attributes = InMemDicomObject::from_element_iter([
+            DataElement::new(tags::PATIENT_ID, VR::LO, PrimitiveValue::from(patient_id)),
+            DataElement::new(
+                tags::STUDY_INSTANCE_UID,
+                VR::UI,
+                PrimitiveValue::from(study),
+            ),
+            DataElement::new(
+                tags::SERIES_INSTANCE_UID,
+                VR::UI,
+                PrimitiveValue::from(series),
+            ),
+            DataElement::new(
+                tags::SOP_INSTANCE_UID,
+                VR::UI,
+                PrimitiveValue::from(sop_instance_uid),
+            ),
+            DataElement::new(tags::MODALITY, VR::CS, PrimitiveValue::from("CT")),
+        ]);
+        Entry {
+            path: PathBuf::from(format!("{}.dcm", sop_instance_uid)),
+            sop_class_uid: uids::CT_IMAGE_STORAGE.to_string(),
+            sop_instance_uid: sop_instance_uid.to_string(),
+            transfer_syntax: uids::EXPLICIT_VR_LITTLE_ENDIAN.to_string(),
+            attributes,
+        }
+    }
+
+    /// Two patients, the first with two studies,
+    /// the first of which has two series.
+    fn index() -> Index {
+        Index {
+            entries: vec![
+                entry("P1", "1.1", "1.1.1", "1.1.1.1"),
+                entry("P1", "1.1", "1.1.1", "1.1.1.2"),
+                entry("P1", "1.1", "1.1.2", "1.1.2.1"),
+                entry("P1", "1.2", "1.2.1", "1.2.1.1"),
+                entry("P2", "2.1", "2.1.1", "2.1.1.1"),
+            ],
+        }
+    }
+
+    fn identifier(level: &str, keys: &[(Tag, VR, &str)]) -> InMemDicomObject {
+        let mut identifier = InMemDicomObject::from_element_iter([DataElement::new(
+            tags::QUERY_RETRIEVE_LEVEL,
+            VR::CS,
+            PrimitiveValue::from(level),
+        )]);
+        for (tag, vr, value) in keys {
+            identifier.put(DataElement::new(*tag, *vr, PrimitiveValue::from(*value)));
+        }
+        identifier
+    }
+
+    fn values(matches: &[InMemDicomObject], tag: Tag) -> Vec<String> {
+        matches
+            .iter()
+            .map(|m| m.get(tag).unwrap().to_str().unwrap().to_string())
+            .collect()
+    }
+
+    fn instances(entries: &[&Entry]) -> Vec<String> {
+        entries.iter().map(|e| e.sop_instance_uid.clone()).collect()
+    }
+
+    #[test]
+    fn finds_patients() {
+        let query = identifier(
+            "PATIENT",
+            &[
+                (tags::PATIENT_ID, VR::LO, ""),
+                (tags::NUMBER_OF_PATIENT_RELATED_STUDIES, VR::IS, ""),
+                (tags::NUMBER_OF_PATIENT_RELATED_INSTANCES, VR::IS, ""),
+            ],
+        );
+        let matches = index().find(InformationModel::PatientRoot, &query).unwrap();
+        assert_eq!(values(&matches, tags::PATIENT_ID), vec!["P1", "P2"]);
+        assert_eq!(
+            values(&matches, tags::NUMBER_OF_PATIENT_RELATED_STUDIES),
+            vec!["2", "1"]
+        );
+        assert_eq!(
+            values(&matches, tags::NUMBER_OF_PATIENT_RELATED_INSTANCES),
+            vec!["4", "1"]
+        );
+
+        // the patient level is not part of the study root model
+        assert_eq!(
+            index().find(InformationModel::StudyRoot, &query),
+            Err(IDENTIFIER_DOES_NOT_MATCH_SOP_CLASS)
+        );
+    }
+
+    #[test]
+    fn finds_studies() {
+        let query = identifier(
+            "STUDY",
+            &[
+                (tags::PATIENT_ID, VR::LO, "P1"),
+                (tags::NUMBER_OF_STUDY_RELATED_SERIES, VR::IS, ""),
+            ],
+        );
+        let matches = index().find(InformationModel::StudyRoot, &query).unwrap();
+        // the unique key is returned even if not asked for
+        assert_eq!(
+            values(&matches, tags::STUDY_INSTANCE_UID),
+            vec!["1.1", "1.2"]
+        );
+        assert_eq!(
+            values(&matches, tags::NUMBER_OF_STUDY_RELATED_SERIES),
+            vec!["2", "1"]
+        );
+    }
+
+    #[test]
+    fn finds_series() {
+        let query = identifier(
+            "SERIES",
+            &[
+                (tags::STUDY_INSTANCE_UID, VR::UI, "1.1"),
+                (tags::SERIES_INSTANCE_UID, VR::UI, ""),
+                (tags::NUMBER_OF_SERIES_RELATED_INSTANCES, VR::IS, ""),
+            ],
+        );
+        let matches = index().find(InformationModel::StudyRoot, &query).unwrap();
+        assert_eq!(
+            values(&matches, tags::SERIES_INSTANCE_UID),
+            vec!["1.1.1", "1.1.2"]
+        );
+        assert_eq!(
+            values(&matches, tags::NUMBER_OF_SERIES_RELATED_INSTANCES),
+            vec!["2", "1"]
+        );
+    }
+
+    #[test]
+    fn finds_images() {
+        let query = identifier(
+            "IMAGE",
+            &[
+                (tags::SERIES_INSTANCE_UID, VR::UI, "1.1.1"),
+                (tags::SOP_INSTANCE_UID, VR::UI, ""),
+            ],
+        );
+        let matches = index().find(InformationModel::PatientRoot, &query).unwrap();
+        assert_eq!(
+            values(&matches, tags::SOP_INSTANCE_UID),
+            vec!["1.1.1.1", "1.1.1.2"]
+        );
+    }
+
+    #[test]
+    fn retrieves_instances_at_each_level() {
+        let index = index();
+        let retrieve = |level, keys: &[(Tag, VR, &str)]| {
+            instances(
+                &index
+                    .retrieve(InformationModel::PatientRoot, &identifier(level, keys))
+                    .unwrap(),
+            )
+        };
+        assert_eq!(
+            retrieve("PATIENT", &[(tags::PATIENT_ID, VR::LO, "P2")]),
+            vec!["2.1.1.1"]
+        );
+        assert_eq!(
+            retrieve("STUDY", &[(tags::STUDY_INSTANCE_UID, VR::UI, "1.1")]),
+            vec!["1.1.1.1", "1.1.1.2", "1.1.2.1"]
+        );
+        assert_eq!(
+            retrieve("SERIES", &[(tags::SERIES_INSTANCE_UID, VR::UI, "1.1.2")]),
+            vec!["1.1.2.1"]
+        );
+
+        // a list of UIDs matches any of them
+        let mut query = identifier("IMAGE", &[]);
+        query.put(DataElement::new(
+            tags::SOP_INSTANCE_UID,
+            VR::UI,
+            dicom_value!(Strs, ["1.1.1.2", "1.2.1.1"]),
+        ));
+        assert_eq!(
+            instances(
+                &index
+                    .retrieve(InformationModel::PatientRoot, &query)
+                    .unwrap()
+            ),
+            vec!["1.1.1.2", "1.2.1.1"]
+        );
+        assert_eq!(
+            retrieve("SERIES", &[(tags::SERIES_INSTANCE_UID, VR::UI, "9.9")]),
+            Vec::<String>::new()
+        );
+    }
+}
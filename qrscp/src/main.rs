@@ -0,0 +1,154 @@
+use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use clap::Parser;
+use dicom_dictionary_std::uids;
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use dicom_ul::{
+    association::{
+        peers::{Peer, PeerRegistry},
+        server::{AcceptAny, TransferSyntaxPolicy},
+    },
+    scp::ScpServer,
+    FullAeAddr, ServerAssociationOptions,
+};
+use tracing::{error, info, Level};
+
+mod index;
+mod service;
+
+use index::Index;
+use service::{services, Provider};
+
+/// DICOM Query/Retrieve SCP
+#[derive(Debug, Parser)]
+#[command(version)]
+struct App {
+    /// The directory of DICOM files to serve
+    dir: PathBuf,
+    /// Verbose mode
+    #[arg(short = 'v', long = "verbose")]
+    verbose: bool,
+    /// Calling Application Entity title
+    #[arg(long = "calling-ae-title", default_value = "QR-SCP")]
+    calling_ae_title: String,
+    /// Enforce max pdu length
+    #[arg(short = 's', long = "strict")]
+    strict: bool,
+    /// Maximum PDU length
+    #[arg(
+        short = 'm',
+        long = "max-pdu-length",
+        default_value = "16384",
+        value_parser(clap::value_parser!(u32).range(4096..=131_072))
+    )]
+    max_pdu_length: u32,
+    /// Which port to listen on
+    #[arg(short, default_value = "11111")]
+    port: u16,
+    /// A known move destination (`AE@host:port`),
+    /// can be used multiple times
+    #[arg(long = "peer", value_name = "AE@HOST:PORT")]
+    peer: Vec<FullAeAddr<String>>,
+    /// A file (`.toml` or `.json`) with the known move destinations
+    #[arg(long = "peers", value_name = "FILE")]
+    peers_file: Option<PathBuf>,
+}
+
+fn main() {
+    run(App::parse()).unwrap_or_else(|e| {
+        error!("{:?}", e);
+        std::process::exit(-2);
+    });
+}
+
+fn run(args: App) -> Result<(), Box<dyn std::error::Error>> {
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(if args.verbose {
+                Level::DEBUG
+            } else {
+                Level::INFO
+            })
+            .finish(),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!(
+            "Could not set up global logger: {}",
+            snafu::Report::from_error(e)
+        );
+    });
+
+    let mut peers = match &args.peers_file {
+        Some(path) => PeerRegistry::load(path)?,
+        None => PeerRegistry::new(),
+    };
+    for addr in args.peer {
+        let (ae_title, address) = addr.into_parts();
+        peers.insert(Peer::new(ae_title).address(address));
+    }
+
+    let index = Index::scan(&args.dir);
+    info!("Indexed {} file(s) in {}", index.len(), args.dir.display());
+
+    let options = association_options(&index)
+        .ae_title(args.calling_ae_title.clone())
+        .strict(args.strict)
+        .max_pdu_length(args.max_pdu_length);
+
+    let provider = Arc::new(Provider {
+        index,
+        peers,
+        ae_title: args.calling_ae_title.clone(),
+        max_pdu_length: args.max_pdu_length,
+    });
+
+    let listen_addr = SocketAddrV4::new(Ipv4Addr::from(0), args.port);
+    let listener = std::net::TcpListener::bind(listen_addr)?;
+    info!(
+        "{} listening on: tcp://{}",
+        &args.calling_ae_title, listen_addr
+    );
+
+    ScpServer::new(options, services(provider)).serve(listener)?;
+    Ok(())
+}
+
+/// The options for accepting associations,
+/// besides the query/retrieve SOP classes of the services.
+fn association_options(index: &Index) -> ServerAssociationOptions<'static, AcceptAny> {
+    let mut options = ServerAssociationOptions::new().accept_any();
+    for ts in TransferSyntaxRegistry.iter() {
+        if !ts.is_unsupported() {
+            options = options.with_transfer_syntax(ts.uid());
+        }
+    }
+    // accept the storage SOP classes of the files served,
+    // so that C-GET requestors may receive them,
+    // preferably in the transfer syntaxes in which they are stored
+    for (sop_class_uid, transfer_syntaxes) in index.sop_classes() {
+        let mut policy = TransferSyntaxPolicy::new().with_abstract_syntax(sop_class_uid);
+        for ts in transfer_syntaxes {
+            policy = policy.prefer(ts);
+        }
+        options = options
+            .with_abstract_syntax(sop_class_uid.to_string())
+            .with_role_selection(sop_class_uid.to_string(), true, true)
+            .with_transfer_syntax_policy(policy.prefer(uids::EXPLICIT_VR_LITTLE_ENDIAN));
+    }
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::App;
+    use clap::CommandFactory;
+
+    #[test]
+    fn verify_cli() {
+        App::command().debug_assert();
+    }
+}
@@ -0,0 +1,841 @@
+//! Query/retrieve services provided over the files indexed.
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use dicom_core::{DataElement, PrimitiveValue, VR};
+use dicom_dictionary_std::{tags, uids};
+use dicom_encoding::transfer_syntax::TransferSyntaxIndex;
+use dicom_object::{open_file, InMemDicomObject};
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use dicom_ul::{
+    association::{client::CloseSocket, peers::PeerRegistry, ClientAssociation},
+    dimse::{CFindRQ, CGetRQ, CMoveRQ, CStoreRQ, Command, Status, SubOperations},
+    pdu::PresentationContextResultReason,
+    scp::{
+        FindOperation, GetOperation, RequestContext, RetrieveOperation, RetrieveOutcome,
+        ServiceRegistry, StorageContext,
+    },
+    ClientAssociationOptions,
+};
+use snafu::{OptionExt, Report, ResultExt, Whatever};
+use tracing::{info, warn};
+
+use crate::index::{Entry, Index, InformationModel};
+
+/// Failure (A702H): refused, out of resources,
+/// unable to perform sub-operations
+const UNABLE_TO_PERFORM_SUB_OPERATIONS: Status = Status(0xA702);
+/// Failure (A801H): refused, move destination unknown
+const MOVE_DESTINATION_UNKNOWN: Status = Status(0xA801);
+/// Warning (B000H): sub-operations complete, one or more failures
+const SUB_OPERATIONS_COMPLETE_WITH_FAILURES: Status = Status(0xB000);
+/// Failure (C000H): unable to process
+const UNABLE_TO_PROCESS: Status = Status(0xC000);
+
+/// The query/retrieve SOP classes supported, by service
+static FIND_SOP_CLASSES: &[&str] = &[
+    uids::PATIENT_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_FIND,
+    uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_FIND,
+];
+static MOVE_SOP_CLASSES: &[&str] = &[
+    uids::PATIENT_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_MOVE,
+    uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_MOVE,
+];
+static GET_SOP_CLASSES: &[&str] = &[
+    uids::PATIENT_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_GET,
+    uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_GET,
+];
+
+/// The query/retrieve service provider,
+/// shared by all associations.
+#[derive(Debug)]
+pub struct Provider {
+    /// the index of the files served
+    pub index: Index,
+    /// the known move destinations
+    pub peers: PeerRegistry,
+    /// the application entity title of this node
+    pub ae_title: String,
+    /// the maximum PDU length for the associations
+    /// with move destinations
+    pub max_pdu_length: u32,
+}
+
+/// Register the verification and query/retrieve services of the provider.
+pub fn services(provider: Arc<Provider>) -> ServiceRegistry {
+    let mut services = ServiceRegistry::new().with_echo(|_: &RequestContext<'_>| Status::SUCCESS);
+    for uid in FIND_SOP_CLASSES {
+        let provider = provider.clone();
+        services = services.with_find(
+            *uid,
+            move |context: &RequestContext<'_>,
+                  rq: &CFindRQ,
+                  identifier: &[u8],
+                  operation: &mut dyn FindOperation| {
+                provider.find(context, rq, identifier, operation)
+            },
+        );
+    }
+    for uid in MOVE_SOP_CLASSES {
+        let provider = provider.clone();
+        services = services.with_move(
+            *uid,
+            move |context: &RequestContext<'_>,
+                  rq: &CMoveRQ,
+                  identifier: &[u8],
+                  operation: &mut dyn RetrieveOperation| {
+                provider.move_instances(context, rq, identifier, operation)
+            },
+        );
+    }
+    for uid in GET_SOP_CLASSES {
+        let provider = provider.clone();
+        services = services.with_get(
+            *uid,
+            move |context: &RequestContext<'_>,
+                  rq: &CGetRQ,
+                  identifier: &[u8],
+                  operation: &mut dyn GetOperation| {
+                provider.get_instances(context, rq, identifier, operation)
+            },
+        );
+    }
+    services
+}
+
+impl Provider {
+    fn find(
+        &self,
+        context: &RequestContext<'_>,
+        rq: &CFindRQ,
+        identifier: &[u8],
+        operation: &mut dyn FindOperation,
+    ) -> Status {
+        let matches = match self.matches(context, rq, identifier) {
+            Ok(matches) => matches,
+            Err(status) => return status,
+        };
+        info!("C-FIND: {} match(es)", matches.len());
+
+        for m in &matches {
+            if operation.is_canceled() {
+                info!("C-FIND canceled");
+                return Status::CANCEL;
+            }
+            let data = match encode(m, context.transfer_syntax) {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!("{}", Report::from_error(e));
+                    return UNABLE_TO_PROCESS;
+                }
+            };
+            if let Err(e) = operation.send_match(&data) {
+                warn!("Could not send match: {}", Report::from_error(e));
+                return UNABLE_TO_PROCESS;
+            }
+        }
+        Status::SUCCESS
+    }
+
+    /// Query the index for the matches of a C-FIND request.
+    fn matches(
+        &self,
+        context: &RequestContext<'_>,
+        rq: &CFindRQ,
+        identifier: &[u8],
+    ) -> Result<Vec<InMemDicomObject>, Status> {
+        let identifier = decode(identifier, context.transfer_syntax)?;
+        let model = InformationModel::from_sop_class(&rq.affected_sop_class_uid)
+            .ok_or(Status::SOP_CLASS_NOT_SUPPORTED)?;
+        let wants_retrieve_ae_title = identifier.get(tags::RETRIEVE_AE_TITLE).is_some();
+        let mut matches = self.index.find(model, &identifier)?;
+        if wants_retrieve_ae_title {
+            for m in &mut matches {
+                m.put(DataElement::new(
+                    tags::RETRIEVE_AE_TITLE,
+                    VR::AE,
+                    PrimitiveValue::from(self.ae_title.as_str()),
+                ));
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Resolve the instances to retrieve for a C-MOVE or C-GET request.
+    fn instances_to_retrieve(
+        &self,
+        context: &RequestContext<'_>,
+        sop_class_uid: &str,
+        identifier: &[u8],
+    ) -> Result<Vec<&Entry>, Status> {
+        let identifier = decode(identifier, context.transfer_syntax)?;
+        let model = InformationModel::from_sop_class(sop_class_uid)
+            .ok_or(Status::SOP_CLASS_NOT_SUPPORTED)?;
+        self.index.retrieve(model, &identifier)
+    }
+
+    fn move_instances(
+        &self,
+        context: &RequestContext<'_>,
+        rq: &CMoveRQ,
+        identifier: &[u8],
+        operation: &mut dyn RetrieveOperation,
+    ) -> RetrieveOutcome {
+        let entries =
+            match self.instances_to_retrieve(context, &rq.affected_sop_class_uid, identifier) {
+                Ok(entries) => entries,
+                Err(status) => return RetrieveOutcome::status(status),
+            };
+        let Some(destination) = self.peers.resolve(&rq.move_destination) else {
+            warn!("Unknown move destination {}", rq.move_destination);
+            return RetrieveOutcome::status(MOVE_DESTINATION_UNKNOWN);
+        };
+        info!("C-MOVE: {} instance(s) to {}", entries.len(), destination);
+        let mut progress = Progress::new(entries.len());
+        if entries.is_empty() {
+            return progress.finish(false, context.transfer_syntax);
+        }
+
+        // propose each SOP class in each transfer syntax of its files,
+        // with the uncompressed ones as an alternative
+        let mut options = ClientAssociationOptions::new()
+            .calling_ae_title(self.ae_title.as_str())
+            .called_ae_title(destination.ae_title())
+            .max_pdu_length(self.max_pdu_length);
+        let contexts: BTreeSet<(&str, &str)> = entries
+            .iter()
+            .map(|e| (e.sop_class_uid.as_str(), e.transfer_syntax.as_str()))
+            .collect();
+        for (sop_class_uid, transfer_syntax) in contexts {
+            let mut transfer_syntaxes = vec![transfer_syntax];
+            for ts in [
+                uids::EXPLICIT_VR_LITTLE_ENDIAN,
+                uids::IMPLICIT_VR_LITTLE_ENDIAN,
+            ] {
+                if ts != transfer_syntax {
+                    transfer_syntaxes.push(ts);
+                }
+            }
+            options = options.with_presentation_context(sop_class_uid, transfer_syntaxes);
+        }
+        let mut scu = match options.establish(destination.socket_addr().as_str()) {
+            Ok(scu) => scu,
+            Err(e) => {
+                warn!(
+                    "Could not establish association with {}: {}",
+                    destination,
+                    Report::from_error(e)
+                );
+                return RetrieveOutcome::status(UNABLE_TO_PERFORM_SUB_OPERATIONS);
+            }
+        };
+        let contexts = client_storage_contexts(&scu);
+
+        let mut canceled = false;
+        let mut entries = entries.into_iter();
+        while let Some(entry) = entries.next() {
+            if operation.is_canceled() {
+                canceled = true;
+                break;
+            }
+            let status = match prepare_instance(&contexts, entry) {
+                Ok((pc_id, data)) => {
+                    let store_rq = CStoreRQ {
+                        message_id: scu.next_message_id(),
+                        affected_sop_class_uid: entry.sop_class_uid.clone(),
+                        affected_sop_instance_uid: entry.sop_instance_uid.clone(),
+                        priority: rq.priority,
+                        move_originator_ae_title: Some(context.calling_ae_title.to_string()),
+                        move_originator_message_id: Some(rq.message_id),
+                    };
+                    match store(&mut scu, pc_id, store_rq, &data) {
+                        Ok(status) => status,
+                        Err(e) => {
+                            // the association with the destination is unusable,
+                            // so the remaining sub-operations fail as well
+                            warn!("{}", Report::from_error(e));
+                            progress.fail(std::iter::once(entry).chain(entries.by_ref()));
+                            break;
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("{}", Report::from_error(e));
+                    UNABLE_TO_PROCESS
+                }
+            };
+            progress.record(&entry.sop_instance_uid, status);
+            if progress.remaining > 0 {
+                if let Err(e) = operation.report(progress.sub_operations(true)) {
+                    warn!("Could not report C-MOVE progress: {}", e);
+                    progress.fail(entries.by_ref());
+                    break;
+                }
+            }
+        }
+        let _ = scu.release();
+
+        progress.finish(canceled, context.transfer_syntax)
+    }
+
+    fn get_instances(
+        &self,
+        context: &RequestContext<'_>,
+        rq: &CGetRQ,
+        identifier: &[u8],
+        operation: &mut dyn GetOperation,
+    ) -> RetrieveOutcome {
+        let entries =
+            match self.instances_to_retrieve(context, &rq.affected_sop_class_uid, identifier) {
+                Ok(entries) => entries,
+                Err(status) => return RetrieveOutcome::status(status),
+            };
+        info!("C-GET: {} instance(s)", entries.len());
+
+        // the requestor must take the SCP role in the storage contexts
+        let contexts = operation.storage_contexts();
+
+        let mut progress = Progress::new(entries.len());
+        let mut canceled = false;
+        let mut entries = entries.into_iter();
+        while let Some(entry) = entries.next() {
+            if operation.is_canceled() {
+                canceled = true;
+                break;
+            }
+            let status = match prepare_instance(&contexts, entry) {
+                Ok((pc_id, data)) => {
+                    let store_rq = CStoreRQ {
+                        message_id: operation.next_message_id(),
+                        affected_sop_class_uid: entry.sop_class_uid.clone(),
+                        affected_sop_instance_uid: entry.sop_instance_uid.clone(),
+                        priority: rq.priority,
+                        move_originator_ae_title: None,
+                        move_originator_message_id: None,
+                    };
+                    match operation.store(pc_id, store_rq, &data) {
+                        Ok(status) => status,
+                        Err(e) => {
+                            // the requestor is unreachable,
+                            // so the remaining sub-operations fail as well
+                            warn!("Could not send C-STORE request: {}", e);
+                            progress.fail(std::iter::once(entry).chain(entries.by_ref()));
+                            break;
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("{}", Report::from_error(e));
+                    UNABLE_TO_PROCESS
+                }
+            };
+            progress.record(&entry.sop_instance_uid, status);
+            if progress.remaining > 0 {
+                if let Err(e) = operation.report(progress.sub_operations(true)) {
+                    warn!("Could not report C-GET progress: {}", e);
+                    progress.fail(entries.by_ref());
+                    break;
+                }
+            }
+        }
+
+        progress.finish(canceled, context.transfer_syntax)
+    }
+}
+
+/// Decode a query or retrieve identifier
+/// in the transfer syntax of its presentation context.
+fn decode(identifier: &[u8], ts: &str) -> Result<InMemDicomObject, Status> {
+    let ts = TransferSyntaxRegistry.get(ts).ok_or(UNABLE_TO_PROCESS)?;
+    InMemDicomObject::read_dataset_with_ts(identifier, ts).map_err(|e| {
+        warn!("Could not read identifier: {}", Report::from_error(e));
+        UNABLE_TO_PROCESS
+    })
+}
+
+/// Encode a response identifier
+/// in the transfer syntax of its presentation context.
+fn encode(identifier: &InMemDicomObject, ts: &str) -> Result<Vec<u8>, Whatever> {
+    let ts = TransferSyntaxRegistry
+        .get(ts)
+        .whatever_context("unsupported transfer syntax")?;
+    let mut data = Vec::new();
+    identifier
+        .write_dataset_with_ts(&mut data, ts)
+        .whatever_context("could not write identifier")?;
+    Ok(data)
+}
+
+/// The progress of the sub-operations of a C-MOVE or C-GET request.
+#[derive(Debug)]
+struct Progress {
+    remaining: usize,
+    completed: usize,
+    failed: usize,
+    warning: usize,
+    failed_instances: Vec<String>,
+}
+
+impl Progress {
+    fn new(total: usize) -> Self {
+        Progress {
+            remaining: total,
+            completed: 0,
+            failed: 0,
+            warning: 0,
+            failed_instances: Vec::new(),
+        }
+    }
+
+    /// Record the outcome of a C-STORE sub-operation.
+    fn record(&mut self, sop_instance_uid: &str, status: Status) {
+        self.remaining -= 1;
+        if status.is_success() {
+            self.completed += 1;
+        } else if status.is_warning() {
+            self.warning += 1;
+        } else {
+            self.failed += 1;
+            self.failed_instances.push(sop_instance_uid.to_string());
+        }
+    }
+
+    /// Record the given instances as failed
+    /// when their sub-operations cannot be carried out.
+    fn fail<'a>(&mut self, entries: impl IntoIterator<Item = &'a Entry>) {
+        for entry in entries {
+            self.record(&entry.sop_instance_uid, UNABLE_TO_PROCESS);
+        }
+    }
+
+    fn sub_operations(&self, with_remaining: bool) -> SubOperations {
+        let count = |n: usize| Some(u16::try_from(n).unwrap_or(u16::MAX));
+        SubOperations {
+            remaining: if with_remaining {
+                count(self.remaining)
+            } else {
+                None
+            },
+            completed: count(self.completed),
+            failed: count(self.failed),
+            warning: count(self.warning),
+        }
+    }
+
+    /// Produce the final response,
+    /// with the failed SOP instance UID list (if any)
+    /// in its identifier.
+    ///
+    /// The status is a failure if all sub-operations failed,
+    /// and a warning if only some of them did.
+    fn finish(self, canceled: bool, ts: &str) -> RetrieveOutcome {
+        let status = if canceled {
+            Status::CANCEL
+        } else if self.failed > 0 && self.completed == 0 && self.warning == 0 {
+            UNABLE_TO_PERFORM_SUB_OPERATIONS
+        } else if self.failed > 0 || self.warning > 0 {
+            SUB_OPERATIONS_COMPLETE_WITH_FAILURES
+        } else {
+            Status::SUCCESS
+        };
+        let sub_operations = self.sub_operations(canceled);
+        let identifier = if self.failed_instances.is_empty() {
+            None
+        } else {
+            let identifier = InMemDicomObject::from_element_iter([DataElement::new(
+                tags::FAILED_SOP_INSTANCE_UID_LIST,
+                VR::UI,
+                PrimitiveValue::Strs(self.failed_instances.into()),
+            )]);
+            encode(&identifier, ts)
+                .map_err(|e| warn!("{}", Report::from_error(e)))
+                .ok()
+        };
+        RetrieveOutcome {
+            status,
+            sub_operations,
+            identifier,
+        }
+    }
+}
+
+/// Collect the storage presentation contexts
+/// accepted by the move destination.
+fn client_storage_contexts<S>(scu: &ClientAssociation<S>) -> Vec<StorageContext>
+where
+    S: std::io::Read + std::io::Write + CloseSocket,
+{
+    scu.presentation_contexts()
+        .iter()
+        .filter(|pc| pc.reason == PresentationContextResultReason::Acceptance)
+        .filter_map(|pc| {
+            Some(StorageContext {
+                id: pc.id,
+                abstract_syntax: scu.abstract_syntax(pc.id)?.to_string(),
+                transfer_syntax: pc.transfer_syntax.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Choose a presentation context for storing the given instance
+/// and encode its data set in the transfer syntax of that context.
+///
+/// A context in the file's own transfer syntax is preferred.
+/// Otherwise, the data set is re-encoded
+/// if neither transfer syntax requires a codec.
+fn prepare_instance(contexts: &[StorageContext], entry: &Entry) -> Result<(u8, Vec<u8>), Whatever> {
+    let file_ts = TransferSyntaxRegistry
+        .get(&entry.transfer_syntax)
+        .whatever_context("unsupported file transfer syntax")?;
+    let candidates = || {
+        contexts
+            .iter()
+            .filter(|pc| pc.abstract_syntax == entry.sop_class_uid)
+    };
+    let pc = candidates()
+        .find(|pc| pc.transfer_syntax == entry.transfer_syntax)
+        .or_else(|| {
+            candidates().find(|pc| {
+                file_ts.is_codec_free()
+                    && TransferSyntaxRegistry
+                        .get(&pc.transfer_syntax)
+                        .is_some_and(|ts| ts.is_codec_free())
+            })
+        })
+        .with_whatever_context(|| {
+            format!(
+                "no suitable presentation context for {} in {}",
+                entry.sop_class_uid, entry.transfer_syntax
+            )
+        })?;
+    let ts = TransferSyntaxRegistry
+        .get(&pc.transfer_syntax)
+        .whatever_context("unsupported transfer syntax")?;
+
+    let file = open_file(&entry.path)
+        .with_whatever_context(|_| format!("could not open {}", entry.path.display()))?;
+    let mut data = Vec::new();
+    file.write_dataset_with_ts(&mut data, ts)
+        .whatever_context("could not write data set")?;
+    Ok((pc.id, data))
+}
+
+/// Send a C-STORE request to the move destination
+/// and wait for its response.
+fn store<S>(
+    scu: &mut ClientAssociation<S>,
+    presentation_context_id: u8,
+    rq: CStoreRQ,
+    data: &[u8],
+) -> Result<Status, Whatever>
+where
+    S: std::io::Read + std::io::Write + CloseSocket,
+{
+    let message_id = rq.message_id;
+    scu.send_message(presentation_context_id, &Command::CStoreRQ(rq), Some(data))
+        .whatever_context("failed to send C-STORE request")?;
+    loop {
+        let message = scu
+            .receive_message()
+            .whatever_context("failed to receive C-STORE response")?;
+        match message.command {
+            Command::CStoreRSP(rsp) if rsp.message_id_being_responded_to == message_id => {
+                return Ok(rsp.status);
+            }
+            command => {
+                warn!(
+                    "Ignoring unexpected {:?} from move destination",
+                    command.command_field()
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{SocketAddr, TcpListener};
+    use std::path::Path;
+    use std::time::Duration;
+
+    use dicom_object::FileMetaTableBuilder;
+    use dicom_ul::{
+        dimse::{CCancelRQ, CStoreRSP, Priority},
+        mock::{MockResponse, MockScp},
+        scp::{ScpServer, ShutdownHandle},
+    };
+
+    const STUDY: &str = "1.2.3.1";
+
+    /// Write a CT image of the study to the given directory.
+    fn write_instance(dir: &Path, sop_instance_uid: &str) {
+        InMemDicomObject::from_element_iter([
+            DataElement::new(tags::SOP_CLASS_UID, VR::UI, uids::CT_IMAGE_STORAGE),
+            DataElement::new(tags::SOP_INSTANCE_UID, VR::UI, sop_instance_uid),
+            DataElement::new(tags::PATIENT_ID, VR::LO, "P1"),
+            DataElement::new(tags::STUDY_INSTANCE_UID, VR::UI, STUDY),
+            DataElement::new(tags::SERIES_INSTANCE_UID, VR::UI, "1.2.3.1.1"),
+            DataElement::new(tags::MODALITY, VR::CS, "CT"),
+        ])
+        .with_meta(
+            FileMetaTableBuilder::new()
+                .media_storage_sop_class_uid(uids::CT_IMAGE_STORAGE)
+                .media_storage_sop_instance_uid(sop_instance_uid)
+                .transfer_syntax(uids::EXPLICIT_VR_LITTLE_ENDIAN),
+        )
+        .unwrap()
+        .write_to_file(dir.join(format!("{}.dcm", sop_instance_uid)))
+        .unwrap();
+    }
+
+    /// Serve a study of three instances in the background,
+    /// with `STORE` at the given address as a move destination.
+    fn spawn_scp(
+        dir: &Path,
+        destination: Option<SocketAddr>,
+    ) -> (std::thread::JoinHandle<()>, ShutdownHandle, SocketAddr) {
+        for i in 1..=3 {
+            write_instance(dir, &format!("1.2.3.1.1.{}", i));
+        }
+        let index = Index::scan(dir);
+        assert_eq!(index.len(), 3);
+        let mut peers = PeerRegistry::new();
+        if let Some(addr) = destination {
+            peers
+                .insert(dicom_ul::association::peers::Peer::new("STORE").address(addr.to_string()));
+        }
+        let provider = Provider {
+            index,
+            peers,
+            ae_title: "QR-SCP".to_string(),
+            max_pdu_length: 16384,
+        };
+        let server = ScpServer::new(
+            crate::association_options(&provider.index).ae_title("QR-SCP"),
+            services(Arc::new(provider)),
+        );
+
+        let listener = TcpListener::bind("localhost:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let handle = std::thread::spawn(move || server.serve(listener).unwrap());
+        (handle, shutdown, addr)
+    }
+
+    /// A study level identifier in implicit VR little endian.
+    fn study_identifier() -> Vec<u8> {
+        let identifier = InMemDicomObject::from_element_iter([
+            DataElement::new(tags::QUERY_RETRIEVE_LEVEL, VR::CS, "STUDY"),
+            DataElement::new(tags::STUDY_INSTANCE_UID, VR::UI, STUDY),
+        ]);
+        encode(&identifier, uids::IMPLICIT_VR_LITTLE_ENDIAN).unwrap()
+    }
+
+    /// Send a C-GET (or a C-MOVE to `STORE`) request for the study
+    /// and answer the C-STORE sub-operations with the given statuses,
+    /// sending a C-CANCEL request right after the request if asked to.
+    /// Returns the sub-operations of the pending responses
+    /// and the final status, sub-operations and identifier.
+    fn retrieve(
+        addr: SocketAddr,
+        get: bool,
+        statuses: &[Status],
+        cancel: bool,
+    ) -> (Vec<SubOperations>, Status, SubOperations, Option<Vec<u8>>) {
+        let sop_class_uid = if get {
+            uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_GET
+        } else {
+            uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_MOVE
+        };
+        let mut association = ClientAssociationOptions::new()
+            .calling_ae_title("QR-SCU")
+            .called_ae_title("QR-SCP")
+            .with_presentation_context(sop_class_uid, vec![uids::IMPLICIT_VR_LITTLE_ENDIAN])
+            .with_presentation_context(
+                uids::CT_IMAGE_STORAGE,
+                vec![uids::EXPLICIT_VR_LITTLE_ENDIAN],
+            )
+            .with_role_selection(uids::CT_IMAGE_STORAGE, false, true)
+            .establish(addr)
+            .unwrap();
+        let message_id = association.next_message_id();
+        let command = if get {
+            Command::CGetRQ(CGetRQ {
+                message_id,
+                affected_sop_class_uid: sop_class_uid.to_string(),
+                priority: Priority::Medium,
+            })
+        } else {
+            Command::CMoveRQ(CMoveRQ {
+                message_id,
+                affected_sop_class_uid: sop_class_uid.to_string(),
+                priority: Priority::Medium,
+                move_destination: "STORE".to_string(),
+            })
+        };
+        association
+            .send_message(1, &command, Some(&study_identifier()))
+            .unwrap();
+        if cancel {
+            let cancel = Command::CCancelRQ(CCancelRQ {
+                message_id_being_responded_to: message_id,
+            });
+            association.send_message(1, &cancel, None).unwrap();
+        }
+
+        let mut statuses = statuses.iter();
+        let mut pending = Vec::new();
+        loop {
+            let message = association.receive_message().unwrap();
+            let (status, sub_operations) = match message.command {
+                Command::CStoreRQ(rq) => {
+                    let rsp = Command::CStoreRSP(CStoreRSP {
+                        message_id_being_responded_to: rq.message_id,
+                        affected_sop_class_uid: rq.affected_sop_class_uid,
+                        affected_sop_instance_uid: rq.affected_sop_instance_uid,
+                        status: *statuses.next().unwrap(),
+                    });
+                    association
+                        .send_message(message.presentation_context_id, &rsp, None)
+                        .unwrap();
+                    continue;
+                }
+                Command::CGetRSP(rsp) => (rsp.status, rsp.sub_operations),
+                Command::CMoveRSP(rsp) => (rsp.status, rsp.sub_operations),
+                command => panic!("unexpected {:?}", command),
+            };
+            if status == Status::PENDING {
+                pending.push(sub_operations);
+            } else {
+                association.release().unwrap();
+                return (pending, status, sub_operations, message.data);
+            }
+        }
+    }
+
+    fn failed_instances(identifier: &[u8]) -> Vec<String> {
+        decode(identifier, uids::IMPLICIT_VR_LITTLE_ENDIAN)
+            .unwrap()
+            .get(tags::FAILED_SOP_INSTANCE_UID_LIST)
+            .unwrap()
+            .to_multi_str()
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn get_reports_failed_sub_operations() {
+        let dir = tempfile::tempdir().unwrap();
+        let (handle, shutdown, addr) = spawn_scp(dir.path(), None);
+
+        let statuses = [Status::SUCCESS, Status::PROCESSING_FAILURE, Status::SUCCESS];
+        let (pending, status, sub_operations, identifier) = retrieve(addr, true, &statuses, false);
+        assert_eq!(
+            pending.iter().map(|s| s.remaining).collect::<Vec<_>>(),
+            vec![Some(2), Some(1)]
+        );
+        assert_eq!(status, SUB_OPERATIONS_COMPLETE_WITH_FAILURES);
+        assert_eq!(sub_operations.remaining, None);
+        assert_eq!(sub_operations.completed, Some(2));
+        assert_eq!(sub_operations.failed, Some(1));
+        assert_eq!(sub_operations.warning, Some(0));
+        assert_eq!(failed_instances(&identifier.unwrap()), vec!["1.2.3.1.1.2"]);
+
+        shutdown.shutdown();
+        handle.join().expect("SCP panicked");
+    }
+
+    /// Sub-operations which could not be attempted count as failed.
+    #[test]
+    fn sub_operations_left_behind_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 1..=3 {
+            write_instance(dir.path(), &format!("1.2.3.1.1.{}", i));
+        }
+        let index = Index::scan(dir.path());
+        let identifier = decode(&study_identifier(), uids::IMPLICIT_VR_LITTLE_ENDIAN).unwrap();
+        let entries = index
+            .retrieve(InformationModel::StudyRoot, &identifier)
+            .unwrap();
+
+        let mut progress = Progress::new(entries.len());
+        progress.record(&entries[0].sop_instance_uid, Status::SUCCESS);
+        progress.fail(entries[1..].iter().copied());
+        let outcome = progress.finish(false, uids::IMPLICIT_VR_LITTLE_ENDIAN);
+        assert_eq!(outcome.status, SUB_OPERATIONS_COMPLETE_WITH_FAILURES);
+        assert_eq!(outcome.sub_operations.completed, Some(1));
+        assert_eq!(outcome.sub_operations.failed, Some(2));
+        assert_eq!(
+            failed_instances(&outcome.identifier.unwrap()),
+            vec!["1.2.3.1.1.2", "1.2.3.1.1.3"]
+        );
+
+        let mut progress = Progress::new(entries.len());
+        progress.fail(entries.iter().copied());
+        let outcome = progress.finish(false, uids::IMPLICIT_VR_LITTLE_ENDIAN);
+        assert_eq!(outcome.status, UNABLE_TO_PERFORM_SUB_OPERATIONS);
+        assert_eq!(outcome.sub_operations.completed, Some(0));
+        assert_eq!(outcome.sub_operations.failed, Some(3));
+    }
+
+    #[test]
+    fn move_reports_failed_sub_operations() {
+        let destination = MockScp::new()
+            .ae_title("STORE")
+            .with_abstract_syntax(uids::CT_IMAGE_STORAGE)
+            .respond(MockResponse::success())
+            .respond(MockResponse::status(Status::PROCESSING_FAILURE))
+            .spawn()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let (handle, shutdown, addr) = spawn_scp(dir.path(), Some(destination.addr()));
+
+        let (pending, status, sub_operations, identifier) = retrieve(addr, false, &[], false);
+        assert_eq!(pending.len(), 2);
+        assert_eq!(status, SUB_OPERATIONS_COMPLETE_WITH_FAILURES);
+        assert_eq!(sub_operations.completed, Some(2));
+        assert_eq!(sub_operations.failed, Some(1));
+        assert_eq!(failed_instances(&identifier.unwrap()), vec!["1.2.3.1.1.2"]);
+
+        shutdown.shutdown();
+        handle.join().expect("SCP panicked");
+        destination.shutdown();
+    }
+
+    #[test]
+    fn move_to_unknown_destination_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let (handle, shutdown, addr) = spawn_scp(dir.path(), None);
+
+        let (pending, status, _, _) = retrieve(addr, false, &[], false);
+        assert!(pending.is_empty());
+        assert_eq!(status, MOVE_DESTINATION_UNKNOWN);
+
+        shutdown.shutdown();
+        handle.join().expect("SCP panicked");
+    }
+
+    #[test]
+    fn move_stops_when_canceled() {
+        let destination = MockScp::new()
+            .ae_title("STORE")
+            .with_abstract_syntax(uids::CT_IMAGE_STORAGE)
+            .respond(MockResponse::success().delay(Duration::from_millis(100)))
+            .spawn()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let (handle, shutdown, addr) = spawn_scp(dir.path(), Some(destination.addr()));
+
+        let (_, status, sub_operations, _) = retrieve(addr, false, &[], true);
+        assert_eq!(status, Status::CANCEL);
+        let remaining = sub_operations.remaining.unwrap();
+        assert!(remaining > 0);
+        assert_eq!(remaining + sub_operations.completed.unwrap(), 3);
+
+        shutdown.shutdown();
+        handle.join().expect("SCP panicked");
+        destination.shutdown();
+    }
+}
@@ -0,0 +1,543 @@
+//! Actions on the received instances.
+//!
+//! Before an instance is stored,
+//! it can be transcoded to another transfer syntax.
+//! Once stored, an external command can be started for it,
+//! which runs while the association carries on,
+//! and when the association ends,
+//! another command can be run with all the files stored in it.
+//! Failures in storing an instance, transcoding it,
+//! or starting its command
+//! are reported in the status of its C-STORE response,
+//! as a warning if the instance was stored nonetheless.
+//! The command itself is not awaited before responding,
+//! so its exit status is only logged.
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::JoinHandle;
+
+use dicom_core::Tag;
+use dicom_dictionary_std::tags;
+use dicom_ul::dimse::{CStoreRQ, Status};
+use snafu::{ensure, ensure_whatever, OptionExt, Report, ResultExt, Whatever};
+use tracing::{info, warn};
+
+use crate::layout::{
+    attribute_value, needs_data_set, parse_parts, place_instance, EmptyCommandSnafu,
+    ParseTemplateError, Part, PartialArgumentSnafu, UnclosedQuoteSnafu, UnexpectedAttributeSnafu,
+};
+use crate::StoreOptions;
+
+/// The path of the stored file
+const PATH: &str = "path";
+/// The paths of all files stored during the association
+const FILES: &str = "files";
+/// The AE title of the SCU
+const CALLING_AE_TITLE: &str = "calling_ae_title";
+
+/// Warning (B000H): the instance was stored,
+/// but the actions on it did not all succeed
+const STORED_WITH_WARNINGS: Status = Status(0xB000);
+
+/// The actions to take on the received instances.
+#[derive(Debug, Default, Clone)]
+pub struct PostReceiveActions {
+    /// The UID of the transfer syntax
+    /// to transcode instances to before storing them.
+    ///
+    /// Each instance is decoded and encoded as a whole in memory,
+    /// so transcoding needs memory for at least twice its pixel data.
+    /// Instances which cannot be transcoded are stored as received.
+    pub transcode: Option<String>,
+    /// The command to run for each stored instance
+    pub exec: Option<CommandTemplate>,
+    /// The command to run when an association ends,
+    /// if any instances were stored during it
+    pub exec_on_end: Option<CommandTemplate>,
+}
+
+/// A template of an external command.
+///
+/// The command is split into arguments at whitespace,
+/// save for text between double quotes.
+/// Each argument may contain values between braces,
+/// which are either attributes of the instance,
+/// as in a [path template](crate::layout::PathTemplate),
+/// or one of these variables:
+///
+/// - `{path}`: the path of the stored file
+///   (only in commands for each instance)
+/// - `{files}`: the paths of all files stored during the association,
+///   one argument each (only in commands for the end of an association)
+/// - `{calling_ae_title}`: the AE title of the SCU
+///
+/// The command is run directly, not through a shell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandTemplate {
+    text: String,
+    args: Vec<Vec<Part>>,
+}
+
+impl CommandTemplate {
+    /// Parse a command to run for each stored instance.
+    pub fn per_instance(text: &str) -> Result<Self, ParseTemplateError> {
+        Self::parse(text, &[PATH, CALLING_AE_TITLE], true)
+    }
+
+    /// Parse a command to run when an association ends.
+    pub fn on_association_end(text: &str) -> Result<Self, ParseTemplateError> {
+        Self::parse(text, &[FILES, CALLING_AE_TITLE], false)
+    }
+
+    fn parse(text: &str, variables: &[&str], attributes: bool) -> Result<Self, ParseTemplateError> {
+        let words = split_words(text)?;
+        ensure!(!words.is_empty(), EmptyCommandSnafu);
+        let args = words
+            .iter()
+            .map(|word| {
+                let parts = parse_parts(word, variables)?;
+                ensure!(
+                    attributes || !parts.iter().any(|p| matches!(p, Part::Attribute { .. })),
+                    UnexpectedAttributeSnafu
+                );
+                ensure!(
+                    parts.len() == 1 || !parts.contains(&Part::Variable(FILES.to_string())),
+                    PartialArgumentSnafu { name: FILES }
+                );
+                Ok(parts)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(CommandTemplate {
+            text: text.to_string(),
+            args,
+        })
+    }
+
+    /// Whether the command needs attributes
+    /// other than those in the C-STORE request command.
+    fn needs_data_set(&self) -> bool {
+        self.args.iter().any(|parts| needs_data_set(parts))
+    }
+
+    /// Create the command,
+    /// replacing variables with the values given by `variable`
+    /// and attributes with the values given by `attribute`.
+    fn build(
+        &self,
+        variable: impl Fn(&str) -> Vec<OsString>,
+        attribute: impl Fn(Tag) -> Option<String>,
+    ) -> Command {
+        let mut args = Vec::new();
+        for parts in &self.args {
+            if let [Part::Variable(name)] = parts.as_slice() {
+                // may expand to any number of arguments
+                args.extend(variable(name));
+                continue;
+            }
+            let mut arg = OsString::new();
+            for part in parts {
+                match part {
+                    Part::Literal(literal) => arg.push(literal),
+                    Part::Attribute { tag, width } => {
+                        let value = attribute(*tag).unwrap_or_default();
+                        let value = value.trim();
+                        match width {
+                            Some(width) => arg.push(format!("{:0>width$}", value, width = *width)),
+                            None => arg.push(value),
+                        }
+                    }
+                    Part::Variable(name) => {
+                        let values = variable(name);
+                        arg.push(values.join(&OsString::from(" ")));
+                    }
+                }
+            }
+            args.push(arg);
+        }
+        let mut command = Command::new(&args[0]);
+        command.args(&args[1..]);
+        command
+    }
+}
+
+impl std::fmt::Display for CommandTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Split a command into words at whitespace outside of double quotes.
+fn split_words(text: &str) -> Result<Vec<String>, ParseTemplateError> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                // make empty quotes an empty argument
+                word.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    ensure!(!quoted, UnclosedQuoteSnafu);
+    words.extend(word);
+    Ok(words)
+}
+
+/// A received instance after taking the actions on it
+#[derive(Debug)]
+pub(crate) struct Processed {
+    /// The status of the C-STORE response
+    pub status: Status,
+    /// The path of the stored file, if it was kept
+    path: Option<PathBuf>,
+    /// The command started for the stored file
+    command: Option<Child>,
+}
+
+/// The instances stored during an association,
+/// and the commands still running for them.
+#[derive(Debug, Default)]
+pub(crate) struct Received {
+    files: Vec<PathBuf>,
+    /// The commands to wait for,
+    /// sent to the worker started along with the first command
+    commands: Option<Sender<(PathBuf, Child)>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Received {
+    /// Record a processed instance,
+    /// waiting for its command in the background.
+    pub(crate) fn add(&mut self, processed: Processed) {
+        let Some(path) = processed.path else {
+            return;
+        };
+        if let Some(child) = processed.command {
+            let commands = self.commands.get_or_insert_with(|| {
+                let (sender, receiver) = channel::<(PathBuf, Child)>();
+                let worker = std::thread::spawn(move || {
+                    for (path, child) in receiver {
+                        if let Err(e) = wait(child) {
+                            warn!(
+                                "Command for {} failed: {}",
+                                path.display(),
+                                Report::from_error(e)
+                            );
+                        }
+                    }
+                });
+                self.worker = Some(worker);
+                sender
+            });
+            // the worker only stops once the sender is dropped
+            let _ = commands.send((path.clone(), child));
+        }
+        self.files.push(path);
+    }
+
+    /// Wait for the commands started for the stored instances to finish.
+    fn wait_commands(&mut self) {
+        self.commands = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Store an instance received into the given temporary file,
+/// taking the configured actions on it.
+///
+/// An instance which could not be read
+/// is discarded with the status _Cannot understand_ (C000H),
+/// and one which could not be moved to its place
+/// is discarded with the status _Out of resources_ (A700H).
+/// If the instance could not be transcoded
+/// or the command for it could not be started,
+/// it is stored nonetheless with a warning status (B000H).
+///
+/// The command is only started here,
+/// so that the C-STORE response does not have to wait for it.
+/// The instance is then to be [added](Received::add) to those received,
+/// which waits for the command in the background.
+pub(crate) fn process_instance(
+    options: &StoreOptions,
+    rq: &CStoreRQ,
+    calling_ae_title: &str,
+    received: &Path,
+) -> Processed {
+    let StoreOptions {
+        out_dir,
+        path_template,
+        on_collision,
+        actions,
+        ..
+    } = options;
+    let sop_instance_uid = &rq.affected_sop_instance_uid;
+    let discard = |e: Whatever, message: &str, status: Status| {
+        warn!(
+            "{} {}: {}",
+            message,
+            sop_instance_uid,
+            Report::from_error(e)
+        );
+        let _ = std::fs::remove_file(received);
+        Processed {
+            status,
+            path: None,
+            command: None,
+        }
+    };
+
+    let mut status = Status::SUCCESS;
+    if let Some(ts) = &actions.transcode {
+        if let Err(e) = transcode(received, ts) {
+            warn!(
+                "Could not transcode {}, storing it as received: {}",
+                sop_instance_uid,
+                Report::from_error(e)
+            );
+            status = STORED_WITH_WARNINGS;
+        }
+    }
+
+    let obj = if path_template.needs_data_set()
+        || actions.exec.as_ref().is_some_and(|c| c.needs_data_set())
+    {
+        match dicom_object::OpenFileOptions::new()
+            .read_until(tags::PIXEL_DATA)
+            .open_file(received)
+            .whatever_context("could not read received instance")
+        {
+            Ok(obj) => Some(obj.into_inner()),
+            Err(e) => return discard(e, "Could not read", Status::CANNOT_UNDERSTAND),
+        }
+    } else {
+        None
+    };
+
+    let path = match place_instance(
+        out_dir,
+        path_template,
+        *on_collision,
+        rq,
+        obj.as_ref(),
+        received,
+    ) {
+        Ok(Some(path)) => path,
+        Ok(None) => {
+            info!("Skipped {}, already stored", sop_instance_uid);
+            return Processed {
+                status: Status::SUCCESS,
+                path: None,
+                command: None,
+            };
+        }
+        Err(e) => return discard(e, "Could not store", Status::OUT_OF_RESOURCES),
+    };
+    info!("Stored {}", path.display());
+    options.instances.insert(sop_instance_uid, &path);
+
+    let mut command = None;
+    if let Some(exec) = &actions.exec {
+        let mut exec = exec.build(
+            |name| match name {
+                PATH => vec![path.clone().into_os_string()],
+                CALLING_AE_TITLE => vec![calling_ae_title.into()],
+                _ => Vec::new(),
+            },
+            |tag| attribute_value(rq, obj.as_ref(), tag),
+        );
+        match start(&mut exec, None) {
+            Ok(child) => command = Some(child),
+            Err(e) => {
+                warn!(
+                    "Command for {} failed: {}",
+                    path.display(),
+                    Report::from_error(e)
+                );
+                status = STORED_WITH_WARNINGS;
+            }
+        }
+    }
+
+    Processed {
+        status,
+        path: Some(path),
+        command,
+    }
+}
+
+/// Run the command for the end of an association, if there is one,
+/// with the files stored during the association,
+/// after the commands for each of them have finished.
+///
+/// The paths of the files are also written to the standard input
+/// of the command, one per line.
+pub(crate) fn end_association(
+    actions: &PostReceiveActions,
+    calling_ae_title: &str,
+    mut received: Received,
+) {
+    received.wait_commands();
+    let files = &received.files;
+    let Some(exec) = &actions.exec_on_end else {
+        return;
+    };
+    if files.is_empty() {
+        return;
+    }
+    let mut command = exec.build(
+        |name| match name {
+            FILES => files.iter().map(|f| f.clone().into_os_string()).collect(),
+            CALLING_AE_TITLE => vec![calling_ae_title.into()],
+            _ => Vec::new(),
+        },
+        |_| None,
+    );
+    let list: String = files.iter().map(|f| format!("{}\n", f.display())).collect();
+    match start(&mut command, Some(list.as_bytes())).and_then(wait) {
+        Ok(()) => info!(
+            "Ran end of association command for {} file(s) from {}",
+            files.len(),
+            calling_ae_title
+        ),
+        Err(e) => warn!(
+            "End of association command failed: {}",
+            Report::from_error(e)
+        ),
+    }
+}
+
+/// Start a command,
+/// writing the given input to its standard input.
+fn start(command: &mut Command, input: Option<&[u8]>) -> Result<Child, Whatever> {
+    command.stdin(if input.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    });
+    let mut child = command
+        .spawn()
+        .with_whatever_context(|_| format!("could not run {:?}", command.get_program()))?;
+    if let Some(input) = input {
+        let mut stdin = child.stdin.take().whatever_context("no standard input")?;
+        // the command does not have to read its input
+        let _ = stdin.write_all(input);
+    }
+    Ok(child)
+}
+
+/// Wait for a command to finish,
+/// failing if it does not exit successfully.
+fn wait(mut child: Child) -> Result<(), Whatever> {
+    let status = child
+        .wait()
+        .whatever_context("could not wait for command")?;
+    ensure_whatever!(status.success(), "command exited with {}", status);
+    Ok(())
+}
+
+/// Transcode the instance in the given file
+/// to the given transfer syntax, if it is not in it already.
+///
+/// The transcoded instance replaces the file only once fully written,
+/// so the file is left as it was if transcoding fails.
+#[cfg(feature = "transcode")]
+fn transcode(path: &Path, ts_uid: &str) -> Result<(), Whatever> {
+    use dicom_encoding::transfer_syntax::TransferSyntaxIndex;
+    use dicom_pixeldata::Transcode;
+    use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+
+    let mut obj = dicom_object::open_file(path).whatever_context("could not read instance")?;
+    if obj.meta().transfer_syntax() == ts_uid {
+        return Ok(());
+    }
+    let ts = TransferSyntaxRegistry
+        .get(ts_uid)
+        .with_whatever_context(|| format!("unknown transfer syntax {}", ts_uid))?;
+    obj.transcode(ts)
+        .whatever_context("could not transcode instance")?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut file = tempfile::Builder::new()
+        .prefix(".")
+        .suffix(".part")
+        .tempfile_in(dir)
+        .whatever_context("could not create file for transcoded instance")?;
+    obj.write_all(&mut file)
+        .whatever_context("could not write transcoded instance")?;
+    file.persist(path)
+        .whatever_context("could not replace instance with transcoded instance")?;
+    Ok(())
+}
+
+#[cfg(not(feature = "transcode"))]
+fn transcode(_path: &Path, _ts_uid: &str) -> Result<(), Whatever> {
+    snafu::whatever!("transcoding support is not enabled")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: &Command) -> Vec<String> {
+        std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn builds_command_per_instance() {
+        let template = CommandTemplate::per_instance(
+            r#"forward --to "PACS B" {path} --tag {PatientID}-{InstanceNumber:03}"#,
+        )
+        .unwrap();
+        assert!(template.needs_data_set());
+        let command = template.build(
+            |name| match name {
+                PATH => vec!["out/1.dcm".into()],
+                _ => Vec::new(),
+            },
+            |tag| match tag {
+                tags::PATIENT_ID => Some("P1 ".to_string()),
+                tags::INSTANCE_NUMBER => Some("7".to_string()),
+                _ => None,
+            },
+        );
+        assert_eq!(
+            args(&command),
+            vec!["forward", "--to", "PACS B", "out/1.dcm", "--tag", "P1-007"]
+        );
+    }
+
+    #[test]
+    fn expands_files_at_association_end() {
+        let template =
+            CommandTemplate::on_association_end("notify {calling_ae_title} {files}").unwrap();
+        let command = template.build(
+            |name| match name {
+                FILES => vec!["a.dcm".into(), "b.dcm".into()],
+                CALLING_AE_TITLE => vec!["MODALITY".into()],
+                _ => Vec::new(),
+            },
+            |_| None,
+        );
+        assert_eq!(args(&command), vec!["notify", "MODALITY", "a.dcm", "b.dcm"]);
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        assert!(CommandTemplate::per_instance("").is_err());
+        assert!(CommandTemplate::per_instance("echo \"{path}").is_err());
+        assert!(CommandTemplate::per_instance("echo {files}").is_err());
+        assert!(CommandTemplate::on_association_end("echo {PatientID}").is_err());
+        assert!(CommandTemplate::on_association_end("echo --files={files}").is_err());
+    }
+}
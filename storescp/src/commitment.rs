@@ -0,0 +1,222 @@
+//! Storage commitment (Push Model) support.
+//!
+//! Storage commitment requests are answered
+//! by looking for the referenced instances in the output directory.
+//! The result is reported on the association of the request,
+//! unless configured to be reported on a new association to the requestor.
+//! Results which the requestor did not respond to
+//! before the association ended
+//! are reported again on a new association.
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+
+use crate::layout::InstanceIndex;
+use crate::StoreOptions;
+use dicom_commitscu::{
+    failure_reason, result_sender_options, send_result, CommitmentRequest, CommitmentResult,
+    FailedSopReference, REQUEST_STORAGE_COMMITMENT,
+};
+use dicom_core::Tag;
+use dicom_dictionary_std::uids;
+use dicom_encoding::transfer_syntax::TransferSyntaxIndex;
+use dicom_object::InMemDicomObject;
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use dicom_ul::{
+    dimse::{NActionRQ, Status},
+    scp::EventReport,
+};
+use snafu::{OptionExt, Report, ResultExt, Whatever};
+use tracing::{info, warn};
+
+/// Read the storage commitment request in an N-ACTION request,
+/// or obtain the status with which it should be refused.
+pub(crate) fn read_request(
+    rq: &NActionRQ,
+    data: &[u8],
+    transfer_syntax: &str,
+) -> Result<CommitmentRequest, Status> {
+    if rq.requested_sop_class_uid != uids::STORAGE_COMMITMENT_PUSH_MODEL {
+        return Err(Status::SOP_CLASS_NOT_SUPPORTED);
+    }
+    if rq.action_type_id != REQUEST_STORAGE_COMMITMENT {
+        return Err(Status::NO_SUCH_ACTION_TYPE);
+    }
+    let ts = TransferSyntaxRegistry
+        .get(transfer_syntax)
+        .ok_or(Status::PROCESSING_FAILURE)?;
+    InMemDicomObject::read_dataset_with_ts(data, ts)
+        .whatever_context::<_, Whatever>("could not read data set")
+        .and_then(|obj| {
+            CommitmentRequest::from_data_set(&obj)
+                .whatever_context("invalid storage commitment request")
+        })
+        .map_err(|e| {
+            warn!("{}", Report::from_error(e));
+            Status::PROCESSING_FAILURE
+        })
+}
+
+/// Check which of the instances referenced in the request
+/// are stored in the output directory,
+/// looking them up in the index of stored instances.
+pub(crate) fn verify(
+    out_dir: &Path,
+    instances: &InstanceIndex,
+    request: &CommitmentRequest,
+    ae_title: &str,
+) -> CommitmentResult {
+    let paths = instances.lookup(
+        out_dir,
+        request
+            .references
+            .iter()
+            .map(|r| r.sop_instance_uid.as_str()),
+    );
+    let mut committed = Vec::new();
+    let mut failed = Vec::new();
+    for (reference, path) in request.references.iter().zip(paths) {
+        // the file may have been replaced or removed since it was stored
+        let sop_class_uid = path
+            .and_then(|path| {
+                dicom_object::OpenFileOptions::new()
+                    .read_until(Tag(0x0001, 0x0000))
+                    .open_file(path)
+                    .ok()
+            })
+            .filter(|file| {
+                file.meta().media_storage_sop_instance_uid() == reference.sop_instance_uid
+            })
+            .map(|file| file.meta().media_storage_sop_class_uid().to_string());
+        let failure_reason = match sop_class_uid {
+            Some(sop_class_uid) if sop_class_uid == reference.sop_class_uid => {
+                committed.push(reference.clone());
+                continue;
+            }
+            Some(_) => failure_reason::CLASS_INSTANCE_CONFLICT,
+            None => failure_reason::NO_SUCH_OBJECT_INSTANCE,
+        };
+        failed.push(FailedSopReference {
+            reference: reference.clone(),
+            failure_reason,
+        });
+    }
+    info!(
+        "Storage commitment {}: {} committed, {} failed",
+        request.transaction_uid,
+        committed.len(),
+        failed.len()
+    );
+
+    CommitmentResult {
+        transaction_uid: request.transaction_uid.clone(),
+        committed,
+        failed,
+        retrieve_ae_title: Some(ae_title.to_string()),
+    }
+}
+
+/// Create the event report of the given result,
+/// with its data set encoded in the given transfer syntax.
+pub(crate) fn event_report(
+    result: &CommitmentResult,
+    transfer_syntax: &str,
+) -> Result<EventReport, Whatever> {
+    let ts = TransferSyntaxRegistry
+        .get(transfer_syntax)
+        .whatever_context("unsupported transfer syntax")?;
+    let mut data = Vec::new();
+    result
+        .to_data_set()
+        .write_dataset_with_ts(&mut data, ts)
+        .whatever_context("could not write storage commitment result")?;
+    Ok(EventReport {
+        affected_sop_class_uid: uids::STORAGE_COMMITMENT_PUSH_MODEL.to_string(),
+        affected_sop_instance_uid: uids::STORAGE_COMMITMENT_PUSH_MODEL_INSTANCE.to_string(),
+        event_type_id: result.event_type_id(),
+        data: Some(data),
+    })
+}
+
+/// Wait in a separate thread for the response
+/// to the event report of the given result
+/// sent on the association of the request,
+/// reporting the result on a new association instead
+/// if the association ended without a response.
+pub(crate) fn await_report_response(
+    result: CommitmentResult,
+    response: Receiver<Status>,
+    requestor_ae_title: &str,
+    options: &StoreOptions,
+) {
+    let requestor_ae_title = requestor_ae_title.to_string();
+    let options = options.clone();
+    std::thread::spawn(move || match response.recv() {
+        Ok(status) => log_report_status(status),
+        Err(_) => {
+            if let Err(e) = report_on_new_association(&result, &requestor_ae_title, &options) {
+                warn!("{}", Report::from_error(e));
+            }
+        }
+    });
+}
+
+/// Report the result of a storage commitment request
+/// on a new association to the requestor in a separate thread,
+/// logging any failure.
+pub(crate) fn report_in_background(
+    result: CommitmentResult,
+    requestor_ae_title: &str,
+    options: &StoreOptions,
+) {
+    let requestor_ae_title = requestor_ae_title.to_string();
+    let options = options.clone();
+    std::thread::spawn(move || {
+        if let Err(e) = report_on_new_association(&result, &requestor_ae_title, &options) {
+            warn!("{}", Report::from_error(e));
+        }
+    });
+}
+
+/// Report the result of a storage commitment request
+/// on a new association to the requestor,
+/// which must be a known peer.
+fn report_on_new_association(
+    result: &CommitmentResult,
+    requestor_ae_title: &str,
+    options: &StoreOptions,
+) -> Result<(), Whatever> {
+    let destination = options
+        .peers
+        .resolve(requestor_ae_title)
+        .with_whatever_context(|| {
+            format!(
+                "unknown storage commitment requestor {}",
+                requestor_ae_title
+            )
+        })?;
+    let mut association = result_sender_options()
+        .calling_ae_title(options.ae_title.as_str())
+        .called_ae_title(destination.ae_title())
+        .max_pdu_length(options.max_pdu_length)
+        .establish(destination.socket_addr().as_str())
+        .with_whatever_context(|_| {
+            format!("could not establish association with {}", destination)
+        })?;
+    let status = send_result(&mut association, result)
+        .whatever_context("could not report storage commitment result")?;
+    let _ = association.release();
+    log_report_status(status);
+    Ok(())
+}
+
+/// Log the status of an N-EVENT-REPORT response.
+fn log_report_status(status: Status) {
+    if status == Status::SUCCESS {
+        info!("Storage commitment result delivered");
+    } else {
+        warn!(
+            "Storage commitment result not accepted (status code {:04X}H)",
+            status.code()
+        );
+    }
+}
@@ -0,0 +1,586 @@
+//! Storage layout of the received instances.
+//!
+//! The path of each instance in the output directory
+//! is given by a [`PathTemplate`],
+//! such as `{PatientID}/{StudyInstanceUID}/{InstanceNumber:04}.dcm`,
+//! where each attribute is replaced by its value in the instance.
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use clap::ValueEnum;
+use dicom_core::{DataDictionary, Tag};
+use dicom_dictionary_std::{tags, StandardDataDictionary};
+use dicom_object::InMemDicomObject;
+use dicom_ul::dimse::CStoreRQ;
+use snafu::{ensure, OptionExt, ResultExt, Snafu, Whatever};
+use walkdir::WalkDir;
+
+/// The text in place of attributes which are missing or empty
+const UNKNOWN: &str = "unknown";
+
+/// An error parsing a path or command template
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+#[non_exhaustive]
+pub enum ParseTemplateError {
+    /// Unclosed `{{` in template
+    UnclosedBrace,
+    /// Unexpected `}}` in template
+    UnexpectedBrace,
+    /// Unknown attribute `{name}`
+    UnknownAttribute { name: String },
+    /// Invalid format `{format}` (expected zero padding width, such as `04`)
+    InvalidFormat { format: String },
+    /// Path template must not have `..` or `.` components
+    RelativeComponent,
+    /// Path template must not be empty
+    EmptyTemplate,
+    /// Unclosed quote in command
+    UnclosedQuote,
+    /// Command must not be empty
+    EmptyCommand,
+    /// Attributes are not available in this command
+    UnexpectedAttribute,
+    /// `{{{name}}}` must be a whole argument
+    PartialArgument { name: String },
+}
+
+/// A piece of a template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Part {
+    Literal(String),
+    Attribute {
+        tag: Tag,
+        width: Option<usize>,
+    },
+    /// a value other than an attribute, such as the path of the file
+    Variable(String),
+}
+
+/// A template for the path of a received instance
+/// relative to the output directory.
+///
+/// Attributes are written between braces,
+/// either by keyword (`{PatientID}`) or by tag (`{0010,0020}`),
+/// optionally followed by a zero padding width (`{InstanceNumber:04}`).
+/// Each `/` in the template separates a directory.
+/// Values are sanitized so that they make valid file names,
+/// and missing or empty values are replaced with `unknown`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathTemplate {
+    text: String,
+    parts: Vec<Part>,
+}
+
+impl Default for PathTemplate {
+    /// Name the files by SOP Instance UID,
+    /// directly in the output directory.
+    fn default() -> Self {
+        "{SOPInstanceUID}.dcm".parse().unwrap()
+    }
+}
+
+impl fmt::Display for PathTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl FromStr for PathTemplate {
+    type Err = ParseTemplateError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        ensure!(!text.trim().is_empty(), EmptyTemplateSnafu);
+        ensure!(
+            text.split('/').all(|c| c != "." && c != ".."),
+            RelativeComponentSnafu
+        );
+        Ok(PathTemplate {
+            text: text.to_string(),
+            parts: parse_parts(text, &[])?,
+        })
+    }
+}
+
+/// Split a template into literal text and values between braces,
+/// which are either one of the given variable names or attributes.
+pub(crate) fn parse_parts(text: &str, variables: &[&str]) -> Result<Vec<Part>, ParseTemplateError> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        match rest.find(['{', '}']) {
+            Some(i) if rest[i..].starts_with('}') => return UnexpectedBraceSnafu.fail(),
+            Some(i) => {
+                if i > 0 {
+                    parts.push(Part::Literal(rest[..i].to_string()));
+                }
+                let end = rest[i..].find('}').context(UnclosedBraceSnafu)? + i;
+                let name = &rest[i + 1..end];
+                if variables.contains(&name) {
+                    parts.push(Part::Variable(name.to_string()));
+                } else {
+                    parts.push(parse_attribute(name)?);
+                }
+                rest = &rest[end + 1..];
+            }
+            None => {
+                parts.push(Part::Literal(rest.to_string()));
+                rest = "";
+            }
+        }
+    }
+    Ok(parts)
+}
+
+fn parse_attribute(text: &str) -> Result<Part, ParseTemplateError> {
+    // tags may be written as `gggg,eeee`,
+    // so only the last colon starts the format
+    let (name, format) = match text.rsplit_once(':') {
+        Some((name, format)) => (name, Some(format)),
+        None => (text, None),
+    };
+    ensure!(!name.contains('{'), UnclosedBraceSnafu);
+    let tag = StandardDataDictionary
+        .parse_tag(name.trim())
+        .context(UnknownAttributeSnafu { name })?;
+    let width = format
+        .map(|format| {
+            format
+                .strip_prefix('0')
+                .and_then(|width| width.parse().ok())
+                .context(InvalidFormatSnafu { format })
+        })
+        .transpose()?;
+    Ok(Part::Attribute { tag, width })
+}
+
+impl PathTemplate {
+    /// Whether resolving the template needs attributes
+    /// other than those in the C-STORE request command.
+    pub fn needs_data_set(&self) -> bool {
+        needs_data_set(&self.parts)
+    }
+
+    /// Resolve the path relative to the output directory,
+    /// obtaining the value of each attribute with `lookup`.
+    pub fn resolve(&self, lookup: impl Fn(Tag) -> Option<String>) -> PathBuf {
+        let mut text = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => text.push_str(literal),
+                Part::Attribute { tag, width } => {
+                    let value = lookup(*tag).unwrap_or_default();
+                    let mut value = sanitize(&value);
+                    if let Some(width) = width {
+                        value = format!("{:0>width$}", value, width = *width);
+                    }
+                    text.push_str(&value);
+                }
+                // path templates have no variables
+                Part::Variable(_) => {}
+            }
+        }
+        // leading, trailing, or repeated slashes do not make empty components
+        text.split('/').filter(|c| !c.is_empty()).collect()
+    }
+}
+
+/// Whether any of the attributes in the template
+/// are not in the C-STORE request command.
+pub(crate) fn needs_data_set(parts: &[Part]) -> bool {
+    parts.iter().any(|part| match part {
+        Part::Attribute { tag, .. } => {
+            *tag != tags::SOP_INSTANCE_UID && *tag != tags::SOP_CLASS_UID
+        }
+        Part::Literal(_) | Part::Variable(_) => false,
+    })
+}
+
+/// Obtain the value of an attribute of a received instance,
+/// from the C-STORE request command if it is there
+/// or from the data set otherwise.
+pub(crate) fn attribute_value(
+    rq: &CStoreRQ,
+    obj: Option<&InMemDicomObject>,
+    tag: Tag,
+) -> Option<String> {
+    match tag {
+        tags::SOP_INSTANCE_UID => Some(rq.affected_sop_instance_uid.clone()),
+        tags::SOP_CLASS_UID => Some(rq.affected_sop_class_uid.clone()),
+        tag => obj?
+            .element_opt(tag)
+            .ok()??
+            .to_str()
+            .ok()
+            .map(|v| v.into_owned()),
+    }
+}
+
+/// Turn an attribute value into a valid file name component.
+fn sanitize(value: &str) -> String {
+    let value: String = value
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // file names ending in dots or spaces are trouble on some file systems
+    let value = value.trim_end_matches(['.', ' ']);
+    if value.is_empty() || value.chars().all(|c| c == '.') {
+        UNKNOWN.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// What to do when a received instance
+/// resolves to the path of an existing file
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Collision {
+    /// replace the existing file
+    #[default]
+    Overwrite,
+    /// keep the existing file and discard the received instance
+    Skip,
+    /// add a numeric suffix to the file name (e.g. `1_1.dcm`)
+    Suffix,
+}
+
+/// Move a received instance to its place in the output directory,
+/// as given by the path template.
+///
+/// The data set of the instance must be given
+/// if the template [needs it](PathTemplate::needs_data_set).
+/// Returns the path of the stored file,
+/// or `None` if the instance was discarded
+/// because a file was already there.
+pub fn place_instance(
+    out_dir: &Path,
+    template: &PathTemplate,
+    on_collision: Collision,
+    rq: &CStoreRQ,
+    obj: Option<&InMemDicomObject>,
+    received: &Path,
+) -> Result<Option<PathBuf>, Whatever> {
+    let relative_path = template.resolve(|tag| attribute_value(rq, obj, tag));
+    let path = out_dir.join(relative_path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).whatever_context("could not create directory")?;
+    }
+
+    // unless overwriting, the path is claimed before moving the instance there,
+    // so that instances received at the same time do not replace each other
+    let claimed = on_collision != Collision::Overwrite;
+    let path = match on_collision {
+        Collision::Overwrite => path,
+        Collision::Skip => {
+            if !claim(&path)? {
+                std::fs::remove_file(received)
+                    .whatever_context("could not discard received instance")?;
+                return Ok(None);
+            }
+            path
+        }
+        Collision::Suffix => {
+            let mut candidate = path.clone();
+            let mut i = 0;
+            while !claim(&candidate)? {
+                i += 1;
+                candidate = with_suffix(&path, i);
+            }
+            candidate
+        }
+    };
+    if let Err(e) = std::fs::rename(received, &path) {
+        if claimed {
+            // do not leave the empty file behind
+            let _ = std::fs::remove_file(&path);
+        }
+        return Err(e).whatever_context("could not move received instance");
+    }
+    Ok(Some(path))
+}
+
+/// Create an empty file at the given path
+/// if there is no file there already.
+///
+/// Returns whether the file was created.
+fn claim(path: &Path) -> Result<bool, Whatever> {
+    match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+    {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e).with_whatever_context(|_| format!("could not create {}", path.display())),
+    }
+}
+
+/// Add a numeric suffix to the file name (`name_1.ext`).
+fn with_suffix(path: &Path, i: u32) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    path.with_file_name(format!("{}_{}{}", stem, i, extension))
+}
+
+/// An index of the instances stored in the output directory,
+/// from SOP instance UID to file path,
+/// shared by all associations.
+///
+/// The output directory is only scanned when the index is first used,
+/// after which instances are added to the index as they are stored.
+#[derive(Debug, Clone, Default)]
+pub struct InstanceIndex(Arc<Mutex<Option<HashMap<String, PathBuf>>>>);
+
+impl InstanceIndex {
+    /// Record an instance stored at the given path.
+    pub(crate) fn insert(&self, sop_instance_uid: &str, path: &Path) {
+        let mut index = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        // otherwise the instance will be found when scanning
+        if let Some(index) = index.as_mut() {
+            index.insert(sop_instance_uid.to_string(), path.to_path_buf());
+        }
+    }
+
+    /// Find the files of the given instances,
+    /// scanning the output directory if the index was not used before.
+    pub(crate) fn lookup<'a>(
+        &self,
+        out_dir: &Path,
+        sop_instance_uids: impl IntoIterator<Item = &'a str>,
+    ) -> Vec<Option<PathBuf>> {
+        let mut index = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let index = index.get_or_insert_with(|| scan(out_dir));
+        sop_instance_uids
+            .into_iter()
+            .map(|uid| index.get(uid).cloned())
+            .collect()
+    }
+}
+
+/// Find all instances in the output directory by their file meta group,
+/// regardless of their name or place in the directory.
+fn scan(out_dir: &Path) -> HashMap<String, PathBuf> {
+    WalkDir::new(out_dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|f| f.file_type().is_file())
+        // skip instances still being received
+        .filter(|f| f.path().extension().map_or(true, |e| e != "part"))
+        .filter_map(|entry| {
+            let file = dicom_object::OpenFileOptions::new()
+                .read_until(Tag(0x0001, 0x0000))
+                .open_file(entry.path())
+                .ok()?;
+            Some((
+                file.meta().media_storage_sop_instance_uid().to_string(),
+                entry.into_path(),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom_dictionary_std::uids;
+
+    fn lookup(tag: Tag) -> Option<String> {
+        match tag {
+            tags::PATIENT_ID => Some("PAT/01".to_string()),
+            tags::STUDY_INSTANCE_UID => Some("1.2.3".to_string()),
+            tags::MODALITY => Some("CT".to_string()),
+            tags::SERIES_NUMBER => Some("2".to_string()),
+            tags::INSTANCE_NUMBER => Some("7".to_string()),
+            tags::PATIENT_NAME => Some("Doe^John ".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn resolves_attributes() {
+        let template: PathTemplate =
+            "{PatientID}/{StudyInstanceUID}/{Modality}_{SeriesNumber}/{InstanceNumber:04}.dcm"
+                .parse()
+                .unwrap();
+        assert!(template.needs_data_set());
+        assert_eq!(
+            template.resolve(lookup),
+            Path::new("PAT_01/1.2.3/CT_2/0007.dcm")
+        );
+
+        let template: PathTemplate = "/{(0010,0010)}/{AccessionNumber}//x.dcm".parse().unwrap();
+        assert_eq!(
+            template.resolve(lookup),
+            Path::new("Doe^John/unknown/x.dcm")
+        );
+    }
+
+    #[test]
+    fn default_template_uses_sop_instance_uid() {
+        let template = PathTemplate::default();
+        assert!(!template.needs_data_set());
+        assert_eq!(
+            template.resolve(|tag| (tag == tags::SOP_INSTANCE_UID).then(|| "1.2.3.4".to_string())),
+            Path::new("1.2.3.4.dcm")
+        );
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        for text in [
+            "",
+            "{PatientID",
+            "PatientID}",
+            "{NotAnAttribute}.dcm",
+            "{InstanceNumber:4}.dcm",
+            "../{PatientID}.dcm",
+        ] {
+            assert!(text.parse::<PathTemplate>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn sanitizes_values() {
+        assert_eq!(sanitize("a:b*c?"), "a_b_c_");
+        assert_eq!(sanitize("ORIGINAL\\PRIMARY"), "ORIGINAL_PRIMARY");
+        assert_eq!(sanitize(" .. "), UNKNOWN);
+        assert_eq!(sanitize("name."), "name");
+        assert_eq!(sanitize(""), UNKNOWN);
+    }
+
+    fn write_instance(path: &Path, sop_instance_uid: &str) {
+        InMemDicomObject::new_empty()
+            .with_meta(
+                dicom_object::FileMetaTableBuilder::new()
+                    .media_storage_sop_class_uid(uids::SECONDARY_CAPTURE_IMAGE_STORAGE)
+                    .media_storage_sop_instance_uid(sop_instance_uid)
+                    .transfer_syntax(uids::EXPLICIT_VR_LITTLE_ENDIAN),
+            )
+            .unwrap()
+            .write_to_file(path)
+            .unwrap();
+    }
+
+    /// Receive an instance with the given content into a temporary file
+    /// and place it in the output directory.
+    fn place(dir: &Path, on_collision: Collision, content: &str) -> Option<PathBuf> {
+        let received = dir.join(format!(".{}.part", content));
+        std::fs::write(&received, content).unwrap();
+        let rq = CStoreRQ {
+            message_id: 1,
+            affected_sop_class_uid: uids::SECONDARY_CAPTURE_IMAGE_STORAGE.to_string(),
+            affected_sop_instance_uid: "1.2.3".to_string(),
+            priority: Default::default(),
+            move_originator_ae_title: None,
+            move_originator_message_id: None,
+        };
+        let path = place_instance(
+            dir,
+            &PathTemplate::default(),
+            on_collision,
+            &rq,
+            None,
+            &received,
+        )
+        .unwrap();
+        assert!(!received.exists());
+        path
+    }
+
+    fn read(path: PathBuf) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn overwrites_on_collision() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = place(dir.path(), Collision::Overwrite, "first").unwrap();
+        let second = place(dir.path(), Collision::Overwrite, "second").unwrap();
+        assert_eq!(first, dir.path().join("1.2.3.dcm"));
+        assert_eq!(second, first);
+        assert_eq!(read(first), "second");
+    }
+
+    #[test]
+    fn skips_on_collision() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = place(dir.path(), Collision::Skip, "first").unwrap();
+        assert_eq!(place(dir.path(), Collision::Skip, "second"), None);
+        assert_eq!(read(first), "first");
+    }
+
+    #[test]
+    fn adds_suffix_on_collision() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = place(dir.path(), Collision::Suffix, "first").unwrap();
+        let second = place(dir.path(), Collision::Suffix, "second").unwrap();
+        let third = place(dir.path(), Collision::Suffix, "third").unwrap();
+        assert_eq!(second, dir.path().join("1.2.3_1.dcm"));
+        assert_eq!(third, dir.path().join("1.2.3_2.dcm"));
+        assert_eq!(read(first), "first");
+        assert_eq!(read(second), "second");
+        assert_eq!(read(third), "third");
+    }
+
+    #[test]
+    fn releases_claim_when_move_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let rq = CStoreRQ {
+            message_id: 1,
+            affected_sop_class_uid: uids::SECONDARY_CAPTURE_IMAGE_STORAGE.to_string(),
+            affected_sop_instance_uid: "1.2.3".to_string(),
+            priority: Default::default(),
+            move_originator_ae_title: None,
+            move_originator_message_id: None,
+        };
+        for on_collision in [Collision::Skip, Collision::Suffix] {
+            // the received file is gone, so it cannot be moved
+            let result = place_instance(
+                dir.path(),
+                &PathTemplate::default(),
+                on_collision,
+                &rq,
+                None,
+                &dir.path().join("missing.part"),
+            );
+            assert!(result.is_err());
+            assert!(!dir.path().join("1.2.3.dcm").exists());
+        }
+    }
+
+    #[test]
+    fn index_finds_stored_instances() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("a")).unwrap();
+        let scanned = dir.path().join("a").join("x.dcm");
+        write_instance(&scanned, "1.2.3.1");
+        std::fs::write(dir.path().join("notes.txt"), "not DICOM").unwrap();
+        write_instance(&dir.path().join(".y.part"), "1.2.3.3");
+
+        let index = InstanceIndex::default();
+        assert_eq!(
+            index.lookup(dir.path(), ["1.2.3.1", "1.2.3.2", "1.2.3.3"]),
+            vec![Some(scanned), None, None]
+        );
+
+        // found without scanning again
+        let stored = dir.path().join("y.dcm");
+        write_instance(&stored, "1.2.3.2");
+        index.insert("1.2.3.2", &stored);
+        assert_eq!(index.lookup(dir.path(), ["1.2.3.2"]), vec![Some(stored)]);
+    }
+}
@@ -0,0 +1,125 @@
+//! DICOM C-STORE SCP
+//!
+//! This library exposes the storage logic of `dicom-storescp`,
+//! so that other tools of the project can receive instances
+//! in the same way,
+//! such as `dicom-movescu` with its embedded storage SCP
+//! and `dicom-getscu`, which receives instances over its own association.
+//! Associations are served through the [SCP framework](dicom_ul::scp)
+//! by the server created with [`store_server`]
+//! (or `tls_store_server` with the `tls` feature).
+//! Storage commitment requests (Push Model)
+//! are answered based on the instances in the output directory.
+//! Where each instance is stored in the output directory
+//! is given by a [path template](layout::PathTemplate).
+//! Received instances may also be transcoded
+//! or handed over to external commands,
+//! as configured in the [post-receive actions](actions::PostReceiveActions).
+//!
+//! This crate is not meant to be used outside of this project.
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use actions::PostReceiveActions;
+use dicom_object::FileMetaTableBuilder;
+use dicom_ul::{
+    association::peers::PeerRegistry,
+    dimse::{CStoreRQ, CStoreRSP, Command, Status},
+};
+use layout::{Collision, InstanceIndex, PathTemplate};
+use snafu::{ResultExt, Whatever};
+use tempfile::NamedTempFile;
+
+pub mod actions;
+mod commitment;
+pub mod layout;
+mod server;
+pub mod transfer;
+
+pub use server::store_server;
+#[cfg(feature = "tls")]
+pub use server::tls_store_server;
+
+/// Options for accepting and handling storage associations.
+#[derive(Debug, Clone)]
+pub struct StoreOptions {
+    /// Verbose mode
+    pub verbose: bool,
+    /// The application entity title of this node
+    pub ae_title: String,
+    /// Enforce max pdu length
+    pub strict: bool,
+    /// Only accept native/uncompressed transfer syntaxes
+    pub uncompressed_only: bool,
+    /// Accept unknown SOP classes
+    pub promiscuous: bool,
+    /// Maximum PDU length
+    pub max_pdu_length: u32,
+    /// Output directory for incoming objects
+    pub out_dir: PathBuf,
+    /// The path of each incoming object relative to the output directory
+    pub path_template: PathTemplate,
+    /// What to do when an incoming object resolves to an existing file
+    pub on_collision: Collision,
+    /// What to do with the incoming objects besides storing them
+    pub actions: PostReceiveActions,
+    /// The instances stored in the output directory,
+    /// to answer storage commitment requests
+    pub instances: InstanceIndex,
+    /// Report storage commitment results on a new association to the requestor,
+    /// rather than on the association of the request
+    pub commitment_on_new_association: bool,
+    /// The known peers,
+    /// to which storage commitment results are reported
+    /// when they are not reported on the association of the request
+    pub peers: PeerRegistry,
+}
+
+/// Create a successful C-STORE response to the given request.
+pub fn create_cstore_response(rq: &CStoreRQ) -> Command {
+    create_cstore_response_with_status(rq, Status::SUCCESS)
+}
+
+/// Create a C-STORE response to the given request with the given status.
+pub fn create_cstore_response_with_status(rq: &CStoreRQ, status: Status) -> Command {
+    Command::CStoreRSP(CStoreRSP {
+        message_id_being_responded_to: rq.message_id,
+        affected_sop_class_uid: rq.affected_sop_class_uid.clone(),
+        affected_sop_instance_uid: rq.affected_sop_instance_uid.clone(),
+        status,
+    })
+}
+
+/// Encode the preamble, magic code and file meta group
+/// of the DICOM file for the instance in a C-STORE request.
+///
+/// The data set received from the SCU
+/// can then be written right after these bytes.
+pub fn create_file_header(rq: &CStoreRQ, transfer_syntax: &str) -> Result<Vec<u8>, Whatever> {
+    let file_meta = FileMetaTableBuilder::new()
+        .media_storage_sop_class_uid(rq.affected_sop_class_uid.as_str())
+        .media_storage_sop_instance_uid(rq.affected_sop_instance_uid.as_str())
+        .transfer_syntax(transfer_syntax)
+        .build()
+        .whatever_context("failed to build DICOM meta file information")?;
+    let mut header = vec![0; 128];
+    header.extend_from_slice(b"DICM");
+    file_meta
+        .write(&mut header)
+        .whatever_context("failed to write DICOM meta file information")?;
+    Ok(header)
+}
+
+/// Create a uniquely named temporary file in the output directory
+/// to receive an instance, starting with the given file header.
+///
+/// The file is named `.*.part`,
+/// and is removed when dropped unless it was moved elsewhere.
+pub fn create_temp_file(out_dir: &Path, header: &[u8]) -> std::io::Result<NamedTempFile> {
+    let mut file = tempfile::Builder::new()
+        .prefix(".")
+        .suffix(".part")
+        .tempfile_in(out_dir)?;
+    file.write_all(header)?;
+    Ok(file)
+}
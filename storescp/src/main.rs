@@ -4,18 +4,23 @@ use std::{
 };
 
 use clap::Parser;
-use dicom_core::{dicom_value, DataElement, VR};
-use dicom_dictionary_std::tags;
-use dicom_object::{InMemDicomObject, StandardDataDictionary};
-use snafu::Report;
+#[cfg(feature = "tls")]
+use dicom_app_common::TlsServerOptions;
+use dicom_encoding::transfer_syntax::TransferSyntaxIndex;
+#[cfg(feature = "tls")]
+use dicom_storescp::tls_store_server;
+use dicom_storescp::{
+    actions::{CommandTemplate, PostReceiveActions},
+    layout::{Collision, PathTemplate},
+    store_server, StoreOptions,
+};
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use dicom_ul::{
+    association::peers::{Peer, PeerRegistry},
+    FullAeAddr,
+};
 use tracing::{error, info, Level};
 
-mod store_async;
-mod store_sync;
-mod transfer;
-use store_async::run_store_async;
-use store_sync::run_store_sync;
-
 /// DICOM C-STORE SCP
 #[derive(Debug, Parser)]
 #[command(version)]
@@ -46,126 +51,110 @@ struct App {
     /// Output directory for incoming objects
     #[arg(short = 'o', default_value = ".")]
     out_dir: PathBuf,
+    /// Path of each incoming object relative to the output directory,
+    /// with attributes between braces
+    /// (example: "{PatientID}/{StudyInstanceUID}/{InstanceNumber:04}.dcm")
+    #[arg(long = "path-template", default_value_t = PathTemplate::default())]
+    path_template: PathTemplate,
+    /// What to do when an incoming object resolves to an existing file
+    #[arg(long = "on-collision", value_enum, default_value = "overwrite")]
+    on_collision: Collision,
+    /// Transcode incoming objects to the transfer syntax with this UID
+    /// before storing them
+    /// (each object is decoded whole in memory;
+    /// objects which cannot be transcoded are stored as received)
+    #[arg(long = "transcode", value_name = "UID", value_parser = parse_transfer_syntax)]
+    // hide option if transcoding is disabled
+    #[cfg_attr(not(feature = "transcode"), arg(hide(true)))]
+    transcode: Option<String>,
+    /// Command to run in the background for each stored object,
+    /// with `{path}`, `{calling_ae_title}`, or attributes as arguments
+    /// (example: "forward.sh {path} {StudyInstanceUID}")
+    #[arg(long = "exec", value_name = "COMMAND", value_parser = CommandTemplate::per_instance)]
+    exec: Option<CommandTemplate>,
+    /// Command to run when an association ends,
+    /// with `{files}` (the stored files) or `{calling_ae_title}` as arguments
+    #[arg(
+        long = "exec-on-end",
+        value_name = "COMMAND",
+        value_parser = CommandTemplate::on_association_end
+    )]
+    exec_on_end: Option<CommandTemplate>,
     /// Which port to listen on
     #[arg(short, default_value = "11111")]
     port: u16,
     /// Run in non-blocking mode (spins up an async task to handle each incoming stream)
     #[arg(short, long)]
     non_blocking: bool,
+    /// Report storage commitment results on a new association to the requestor,
+    /// rather than on the association of the request
+    #[arg(long = "commitment-new-association")]
+    commitment_new_association: bool,
+    /// A known storage commitment requestor (`AE@host:port`),
+    /// to which results are reported on a new association,
+    /// can be used multiple times
+    #[arg(long = "peer", value_name = "AE@HOST:PORT")]
+    peer: Vec<FullAeAddr<String>>,
+    /// A file (`.toml` or `.json`) with the known storage commitment requestors
+    #[arg(long = "peers", value_name = "FILE")]
+    peers_file: Option<PathBuf>,
+    #[cfg(feature = "tls")]
+    #[command(flatten)]
+    tls: TlsServerOptions,
 }
 
-fn create_cstore_response(
-    message_id: u16,
-    sop_class_uid: &str,
-    sop_instance_uid: &str,
-) -> InMemDicomObject<StandardDataDictionary> {
-    InMemDicomObject::command_from_element_iter([
-        DataElement::new(
-            tags::AFFECTED_SOP_CLASS_UID,
-            VR::UI,
-            dicom_value!(Str, sop_class_uid),
-        ),
-        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x8001])),
-        DataElement::new(
-            tags::MESSAGE_ID_BEING_RESPONDED_TO,
-            VR::US,
-            dicom_value!(U16, [message_id]),
-        ),
-        DataElement::new(
-            tags::COMMAND_DATA_SET_TYPE,
-            VR::US,
-            dicom_value!(U16, [0x0101]),
-        ),
-        DataElement::new(tags::STATUS, VR::US, dicom_value!(U16, [0x0000])),
-        DataElement::new(
-            tags::AFFECTED_SOP_INSTANCE_UID,
-            VR::UI,
-            dicom_value!(Str, sop_instance_uid),
-        ),
-    ])
-}
-
-fn create_cecho_response(message_id: u16) -> InMemDicomObject<StandardDataDictionary> {
-    InMemDicomObject::command_from_element_iter([
-        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x8030])),
-        DataElement::new(
-            tags::MESSAGE_ID_BEING_RESPONDED_TO,
-            VR::US,
-            dicom_value!(U16, [message_id]),
-        ),
-        DataElement::new(
-            tags::COMMAND_DATA_SET_TYPE,
-            VR::US,
-            dicom_value!(U16, [0x0101]),
-        ),
-        DataElement::new(tags::STATUS, VR::US, dicom_value!(U16, [0x0000])),
-    ])
+impl App {
+    /// The options for handling each incoming association.
+    fn store_options(&self) -> Result<StoreOptions, Box<dyn std::error::Error>> {
+        let mut peers = match &self.peers_file {
+            Some(path) => PeerRegistry::load(path)?,
+            None => PeerRegistry::new(),
+        };
+        for addr in &self.peer {
+            peers.insert(Peer::new(addr.ae_title()).address(addr.socket_addr().as_str()));
+        }
+        Ok(StoreOptions {
+            verbose: self.verbose,
+            ae_title: self.calling_ae_title.clone(),
+            strict: self.strict,
+            uncompressed_only: self.uncompressed_only,
+            promiscuous: self.promiscuous,
+            max_pdu_length: self.max_pdu_length,
+            out_dir: self.out_dir.clone(),
+            path_template: self.path_template.clone(),
+            on_collision: self.on_collision,
+            actions: PostReceiveActions {
+                transcode: self.transcode.clone(),
+                exec: self.exec.clone(),
+                exec_on_end: self.exec_on_end.clone(),
+            },
+            instances: Default::default(),
+            commitment_on_new_association: self.commitment_new_association,
+            peers,
+        })
+    }
 }
 
-fn main() {
-    let app = App::parse();
-    if app.non_blocking {
-        tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-            .unwrap()
-            .block_on(async move {
-                run_async(app).await.unwrap_or_else(|e| {
-                    error!("{:?}", e);
-                    std::process::exit(-2);
-                });
-            });
-    } else {
-        run_sync(app).unwrap_or_else(|e| {
-            error!("{:?}", e);
-            std::process::exit(-2);
-        });
+/// Check that the transfer syntax is known and can be encoded.
+fn parse_transfer_syntax(uid: &str) -> Result<String, String> {
+    if cfg!(not(feature = "transcode")) {
+        return Err("transcoding support is not enabled".to_string());
+    }
+    match TransferSyntaxRegistry.get(uid) {
+        Some(ts) if ts.is_fully_supported() => Ok(ts.uid().to_string()),
+        Some(ts) => Err(format!("cannot encode in {}", ts.name())),
+        None => Err("unknown transfer syntax".to_string()),
     }
 }
 
-async fn run_async(args: App) -> Result<(), Box<dyn std::error::Error>> {
-    use std::sync::Arc;
-    let args = Arc::new(args);
-    tracing::subscriber::set_global_default(
-        tracing_subscriber::FmtSubscriber::builder()
-            .with_max_level(if args.verbose {
-                Level::DEBUG
-            } else {
-                Level::INFO
-            })
-            .finish(),
-    )
-    .unwrap_or_else(|e| {
-        eprintln!(
-            "Could not set up global logger: {}",
-            snafu::Report::from_error(e)
-        );
-    });
-
-    std::fs::create_dir_all(&args.out_dir).unwrap_or_else(|e| {
-        error!("Could not create output directory: {}", e);
+fn main() {
+    run(App::parse()).unwrap_or_else(|e| {
+        error!("{:?}", e);
         std::process::exit(-2);
     });
-
-    let listen_addr = SocketAddrV4::new(Ipv4Addr::from(0), args.port);
-    let listener = tokio::net::TcpListener::bind(listen_addr).await?;
-    info!(
-        "{} listening on: tcp://{}",
-        &args.calling_ae_title, listen_addr
-    );
-
-    loop {
-        let (socket, _addr) = listener.accept().await?;
-        let args = args.clone();
-        tokio::task::spawn(async move {
-            if let Err(e) = run_store_async(socket, &args).await {
-                error!("{}", Report::from_error(e));
-            }
-        });
-    }
 }
 
-fn run_sync(args: App) -> Result<(), Box<dyn std::error::Error>> {
+fn run(args: App) -> Result<(), Box<dyn std::error::Error>> {
     tracing::subscriber::set_global_default(
         tracing_subscriber::FmtSubscriber::builder()
             .with_max_level(if args.verbose {
@@ -187,27 +176,38 @@ fn run_sync(args: App) -> Result<(), Box<dyn std::error::Error>> {
         std::process::exit(-2);
     });
 
-    let listen_addr = SocketAddrV4::new(Ipv4Addr::from(0), args.port);
-    let listener = std::net::TcpListener::bind(listen_addr)?;
-    info!(
-        "{} listening on: tcp://{}",
-        &args.calling_ae_title, listen_addr
-    );
+    let options = args.store_options()?;
+    #[cfg(feature = "tls")]
+    let (server, scheme) = match args.tls.server_config()? {
+        Some(tls_config) => (tls_store_server(options, tls_config), "tls"),
+        None => (store_server(options), "tcp"),
+    };
+    #[cfg(not(feature = "tls"))]
+    let (server, scheme) = (store_server(options), "tcp");
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(scu_stream) => {
-                if let Err(e) = run_store_sync(scu_stream, &args) {
-                    error!("{}", snafu::Report::from_error(e));
-                }
-            }
-            Err(e) => {
-                error!("{}", snafu::Report::from_error(e));
-            }
-        }
+    let listen_addr = SocketAddrV4::new(Ipv4Addr::from(0), args.port);
+    if args.non_blocking {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?
+            .block_on(async move {
+                let listener = tokio::net::TcpListener::bind(listen_addr).await?;
+                info!(
+                    "{} listening on: {}://{}",
+                    &args.calling_ae_title, scheme, listen_addr
+                );
+                server.serve_async(listener).await?;
+                Ok(())
+            })
+    } else {
+        let listener = std::net::TcpListener::bind(listen_addr)?;
+        info!(
+            "{} listening on: {}://{}",
+            &args.calling_ae_title, scheme, listen_addr
+        );
+        server.serve(listener)?;
+        Ok(())
     }
-
-    Ok(())
 }
 
 #[cfg(test)]
@@ -0,0 +1,221 @@
+//! The storage SCP,
+//! built on the SCP framework of `dicom-ul`.
+use std::collections::HashMap;
+use std::io::{BufWriter, Read};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use dicom_dictionary_std::uids;
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+#[cfg(feature = "tls")]
+use dicom_ul::rustls::ServerConfig;
+use dicom_ul::{
+    association::server::AcceptAny,
+    dimse::{CStoreRQ, NActionRQ, Status},
+    scp::{AssociationContext, EventReports, RequestContext, ScpServer, ServiceRegistry},
+    ServerAssociationOptions,
+};
+use snafu::{Report, ResultExt, Whatever};
+use tempfile::TempPath;
+use tracing::warn;
+
+use crate::{actions, commitment, create_file_header, create_temp_file, transfer, StoreOptions};
+
+/// Create the server which accepts storage associations
+/// and saves the received instances to the output directory,
+/// as configured in the given options.
+pub fn store_server(options: StoreOptions) -> ScpServer<AcceptAny> {
+    ScpServer::new(association_options(&options), services(options))
+}
+
+/// Create the server which accepts storage associations over TLS
+/// with the given configuration,
+/// and otherwise works like the one of [`store_server`].
+#[cfg(feature = "tls")]
+pub fn tls_store_server(
+    options: StoreOptions,
+    tls_config: Arc<ServerConfig>,
+) -> ScpServer<AcceptAny> {
+    ScpServer::new(
+        association_options(&options).tls_config(tls_config),
+        services(options),
+    )
+}
+
+/// The options for accepting storage associations.
+fn association_options(options: &StoreOptions) -> ServerAssociationOptions<'static, AcceptAny> {
+    let mut association_options = ServerAssociationOptions::new()
+        .accept_any()
+        .ae_title(options.ae_title.clone())
+        .strict(options.strict)
+        .max_pdu_length(options.max_pdu_length)
+        .promiscuous(options.promiscuous)
+        .accept_related_general_sop_classes(true);
+
+    if options.uncompressed_only {
+        association_options = association_options
+            .with_transfer_syntax("1.2.840.10008.1.2")
+            .with_transfer_syntax("1.2.840.10008.1.2.1");
+    } else {
+        for ts in TransferSyntaxRegistry.iter() {
+            if !ts.is_unsupported() {
+                association_options = association_options.with_transfer_syntax(ts.uid());
+            }
+        }
+    };
+    // the requestor may take the SCP role
+    // to receive the storage commitment result on the same association
+    association_options.with_role_selection(uids::STORAGE_COMMITMENT_PUSH_MODEL, true, true)
+}
+
+/// The handlers of the storage SCP.
+fn services(options: StoreOptions) -> ServiceRegistry {
+    let promiscuous = options.promiscuous;
+    let service = Arc::new(StoreService {
+        options,
+        received: Mutex::default(),
+    });
+    let store = {
+        let service = service.clone();
+        move |context: &RequestContext<'_>, rq: &CStoreRQ, data: &mut dyn Read| {
+            service.store(context, rq, data)
+        }
+    };
+    let mut services = ServiceRegistry::new().with_echo(|_: &RequestContext<'_>| Status::SUCCESS);
+    for uid in transfer::ABSTRACT_SYNTAXES {
+        if *uid != uids::VERIFICATION {
+            services = services.with_store(*uid, store.clone());
+        }
+    }
+    if promiscuous {
+        services = services.with_default_store(store);
+    }
+    let commit = service.clone();
+    services
+        .with_action(
+            uids::STORAGE_COMMITMENT_PUSH_MODEL,
+            move |context: &RequestContext<'_>,
+                  rq: &NActionRQ,
+                  data: &[u8],
+                  events: &mut EventReports| {
+                commit.commit(context, rq, data, events)
+            },
+        )
+        .with_association_end(move |context: &AssociationContext<'_>| {
+            service.association_ended(context)
+        })
+}
+
+/// The state of the storage SCP across associations.
+#[derive(Debug)]
+struct StoreService {
+    options: StoreOptions,
+    /// The instances received in each ongoing association,
+    /// by association ID
+    received: Mutex<HashMap<u64, actions::Received>>,
+}
+
+impl StoreService {
+    /// Receive and store the instance in a C-STORE request,
+    /// returning the status of the response.
+    fn store(&self, context: &RequestContext<'_>, rq: &CStoreRQ, data: &mut dyn Read) -> Status {
+        // receive the instance into a temporary file,
+        // then move it to the path given by the template
+        let file_path = match self.receive_instance(rq, context.transfer_syntax, data) {
+            Ok(file_path) => file_path,
+            Err(e) => {
+                warn!(
+                    "Could not store {}: {}",
+                    rq.affected_sop_instance_uid,
+                    Report::from_error(e)
+                );
+                return Status::OUT_OF_RESOURCES;
+            }
+        };
+        let processed =
+            actions::process_instance(&self.options, rq, context.calling_ae_title, &file_path);
+        let status = processed.status;
+        self.received
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(context.association_id)
+            .or_default()
+            .add(processed);
+        status
+    }
+
+    /// Write the data set of a C-STORE request
+    /// to a new temporary file in the output directory as it is received,
+    /// so that memory usage does not depend on the size of the instance.
+    fn receive_instance(
+        &self,
+        rq: &CStoreRQ,
+        transfer_syntax: &str,
+        data: &mut dyn Read,
+    ) -> Result<TempPath, Whatever> {
+        let header = create_file_header(rq, transfer_syntax)?;
+        let file = create_temp_file(&self.options.out_dir, &header)
+            .whatever_context("could not create file")?;
+        let mut writer = BufWriter::new(file);
+        std::io::copy(data, &mut writer).whatever_context("could not receive instance")?;
+        let file = writer
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .whatever_context("could not write file")?;
+        Ok(file.into_temp_path())
+    }
+
+    /// Answer a storage commitment request,
+    /// reporting its result on the same association
+    /// unless configured to report it on a new one.
+    fn commit(
+        &self,
+        context: &RequestContext<'_>,
+        rq: &NActionRQ,
+        data: &[u8],
+        events: &mut EventReports,
+    ) -> Status {
+        let request = match commitment::read_request(rq, data, context.transfer_syntax) {
+            Ok(request) => request,
+            Err(status) => return status,
+        };
+        let result = commitment::verify(
+            &self.options.out_dir,
+            &self.options.instances,
+            &request,
+            &self.options.ae_title,
+        );
+        if self.options.commitment_on_new_association {
+            commitment::report_in_background(result, context.calling_ae_title, &self.options);
+            return Status::SUCCESS;
+        }
+        let report = match commitment::event_report(&result, context.transfer_syntax) {
+            Ok(report) => report,
+            Err(e) => {
+                warn!("{}", Report::from_error(e));
+                return Status::PROCESSING_FAILURE;
+            }
+        };
+        commitment::await_report_response(
+            result,
+            events.send(report),
+            context.calling_ae_title,
+            &self.options,
+        );
+        Status::SUCCESS
+    }
+
+    /// Run the command for the end of the association
+    /// with the instances received in it.
+    fn association_ended(&self, context: &AssociationContext<'_>) {
+        let received = self
+            .received
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&context.association_id)
+            .unwrap_or_default();
+        let actions = self.options.actions.clone();
+        let calling_ae_title = context.calling_ae_title.to_string();
+        // the association is over, so there is no need to wait for the commands
+        std::thread::spawn(move || actions::end_association(&actions, &calling_ae_title, received));
+    }
+}
@@ -1,10 +1,16 @@
 use clap::Parser;
-use dicom_core::{dicom_value, header::Tag, DataElement, VR};
-use dicom_dictionary_std::{tags, uids};
+#[cfg(feature = "tls")]
+use dicom_app_common::TlsClientOptions;
+use dicom_core::header::Tag;
+use dicom_dictionary_std::uids;
 use dicom_encoding::transfer_syntax;
 use dicom_encoding::TransferSyntax;
-use dicom_object::{mem::InMemDicomObject, DefaultDicomObject, StandardDataDictionary};
+use dicom_object::DefaultDicomObject;
 use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use dicom_ul::{
+    dimse::{CStoreRQ, Command, Priority},
+    ClientAssociationOptions,
+};
 use indicatif::{ProgressBar, ProgressStyle};
 use snafu::prelude::*;
 use snafu::{Report, Whatever};
@@ -14,7 +20,7 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::Mutex;
-use tracing::{debug, error, info, warn, Level};
+use tracing::{error, info, warn, Level};
 use transfer_syntax::TransferSyntaxIndex;
 use walkdir::WalkDir;
 
@@ -98,6 +104,9 @@ struct App {
     /// Dispatch these many service users to send files in parallel
     #[arg(short = 'c', long = "concurrency")]
     concurrency: Option<usize>,
+    #[cfg(feature = "tls")]
+    #[command(flatten)]
+    tls: TlsClientOptions,
 }
 
 struct DicomFile {
@@ -122,15 +131,14 @@ enum Error {
         source: Box<dicom_ul::association::client::Error>,
     },
 
-    /// Could not construct DICOM command
-    CreateCommand {
-        source: Box<dicom_object::WriteError>,
+    /// Could not set up TLS
+    #[cfg(feature = "tls")]
+    Tls {
+        source: dicom_app_common::tls::Error,
     },
 
     /// Unsupported file transfer syntax {uid}
-    UnsupportedFileTransferSyntax {
-        uid: std::borrow::Cow<'static, str>,
-    },
+    UnsupportedFileTransferSyntax { uid: std::borrow::Cow<'static, str> },
 
     /// Unsupported file
     FileNotSupported,
@@ -152,20 +160,6 @@ enum Error {
     WriteDataset {
         source: Box<dicom_object::WriteError>,
     },
-    ReadDataset {
-        source: dicom_object::ReadError,
-    },
-    MissingAttribute {
-        tag: Tag,
-        source: dicom_object::AccessError,
-    },
-    ConvertField {
-        tag: Tag,
-        source: dicom_core::value::ConvertValueError,
-    },
-    WriteIO {
-        source: std::io::Error,
-    },
 }
 
 fn main() {
@@ -257,7 +251,7 @@ fn check_files(
 }
 
 fn run(app: App) -> Result<(), Error> {
-    use crate::store_sync::{get_scu, send_file};
+    use crate::store_sync::store_files;
     let App {
         addr,
         files,
@@ -274,6 +268,8 @@ fn run(app: App) -> Result<(), Error> {
         saml_assertion,
         jwt,
         concurrency: _,
+        #[cfg(feature = "tls")]
+        tls,
     } = app;
 
     // never transcode if the feature is disabled
@@ -294,10 +290,9 @@ fn run(app: App) -> Result<(), Error> {
     if verbose {
         info!("Establishing association with '{}'...", &addr);
     }
-    let (mut dicom_files, presentation_contexts) = check_files(files, verbose, never_transcode);
+    let (dicom_files, presentation_contexts) = check_files(files, verbose, never_transcode);
 
-    let mut scu = get_scu(
-        addr,
+    let scu_init = scu_options(
         calling_ae_title,
         called_ae_title,
         max_pdu_length,
@@ -306,75 +301,43 @@ fn run(app: App) -> Result<(), Error> {
         kerberos_service_ticket,
         saml_assertion,
         jwt,
-        presentation_contexts,
-    )?;
-
-    if verbose {
-        info!("Association established");
-    }
-
-    for file in &mut dicom_files {
-        // identify the right transfer syntax to use
-        let r: Result<_, Error> =
-            check_presentation_contexts(file, scu.presentation_contexts(), never_transcode);
-        match r {
-            Ok((pc, ts)) => {
-                if verbose {
-                    debug!(
-                        "{}: Selected presentation context: {:?}",
-                        file.file.display(),
-                        pc
-                    );
-                }
-                file.pc_selected = Some(pc);
-                file.ts_selected = Some(ts);
-            }
-            Err(e) => {
-                error!("{}", Report::from_error(e));
-                if fail_first {
-                    let _ = scu.abort();
-                    std::process::exit(-2);
-                }
-            }
-        }
-    }
-
-    let progress_bar;
-    if !verbose {
-        progress_bar = Some(ProgressBar::new(dicom_files.len() as u64));
-        if let Some(pb) = progress_bar.as_ref() {
-            pb.set_style(
-                ProgressStyle::default_bar()
-                    .template("[{elapsed_precise}] {bar:40} {pos}/{len} {wide_msg}")
-                    .expect("Invalid progress bar template"),
-            );
-            pb.enable_steady_tick(Duration::new(0, 480_000_000));
-        };
-    } else {
-        progress_bar = None;
-    }
-
-    for file in dicom_files {
-        scu = send_file(
+        &presentation_contexts,
+    );
+
+    #[cfg(feature = "tls")]
+    if let Some(tls_config) = tls.client_config().context(TlsSnafu)? {
+        let scu = scu_init
+            .tls_config(tls_config)
+            .server_name(tls.server_name(&addr))
+            .establish_with_tls(&addr)
+            .map_err(Box::from)
+            .context(ScuSnafu)?;
+        return store_files(
             scu,
-            file,
+            dicom_files,
             message_id,
-            progress_bar.as_ref(),
+            never_transcode,
             verbose,
             fail_first,
-        )?;
+        );
     }
 
-    if let Some(pb) = progress_bar {
-        pb.finish_with_message("done")
-    };
-
-    scu.release().map_err(Box::from).context(ScuSnafu)?;
-    Ok(())
+    let scu = scu_init
+        .establish_with(&addr)
+        .map_err(Box::from)
+        .context(ScuSnafu)?;
+    store_files(
+        scu,
+        dicom_files,
+        message_id,
+        never_transcode,
+        verbose,
+        fail_first,
+    )
 }
 
 async fn run_async() -> Result<(), Error> {
-    use crate::store_async::{get_scu, send_file};
+    use crate::store_async::store_files;
     let App {
         addr,
         files,
@@ -391,6 +354,8 @@ async fn run_async() -> Result<(), Error> {
         saml_assertion,
         jwt,
         concurrency,
+        #[cfg(feature = "tls")]
+        tls,
     } = App::parse();
 
     // never transcode if the feature is disabled
@@ -435,6 +400,11 @@ async fn run_async() -> Result<(), Error> {
         progress_bar = None;
     }
 
+    #[cfg(feature = "tls")]
+    let tls_config = tls.client_config().context(TlsSnafu)?;
+    #[cfg(feature = "tls")]
+    let server_name = tls.server_name(&addr).to_string();
+
     for _ in 0..concurrency.unwrap_or(1) {
         let pbx = progress_bar.clone();
         let d_files = dicom_files.clone();
@@ -447,9 +417,12 @@ async fn run_async() -> Result<(), Error> {
         let password = password.clone();
         let called_ae_title = called_ae_title.clone();
         let calling_ae_title = calling_ae_title.clone();
+        #[cfg(feature = "tls")]
+        let tls_config = tls_config.clone();
+        #[cfg(feature = "tls")]
+        let server_name = server_name.clone();
         tasks.spawn(async move {
-            let mut scu = get_scu(
-                addr,
+            let scu_init = scu_options(
                 calling_ae_title,
                 called_ae_title,
                 max_pdu_length,
@@ -458,47 +431,44 @@ async fn run_async() -> Result<(), Error> {
                 kerberos_service_ticket,
                 saml_assertion,
                 jwt,
-                pc,
-            )
-            .await?;
-            loop {
-                let file = {
-                    let mut files = d_files.lock().await;
-                    files.pop()
-                };
-                let mut file = match file {
-                    Some(file) => file,
-                    None => break,
-                };
-                let r: Result<_, Error> = check_presentation_contexts(
-                    &file,
-                    scu.presentation_contexts(),
+                &pc,
+            );
+            #[cfg(feature = "tls")]
+            if let Some(tls_config) = tls_config {
+                let scu = scu_init
+                    .tls_config(tls_config)
+                    .server_name(server_name)
+                    .establish_with_tls_async(&addr)
+                    .await
+                    .map_err(Box::from)
+                    .context(ScuSnafu)?;
+                return store_files(
+                    scu,
+                    d_files,
+                    pbx,
+                    message_id,
                     never_transcode,
-                );
-                match r {
-                    Ok((pc, ts)) => {
-                        if verbose {
-                            debug!(
-                                "{}: Selected presentation context: {:?}",
-                                file.file.display(),
-                                pc
-                            );
-                        }
-                        file.pc_selected = Some(pc);
-                        file.ts_selected = Some(ts);
-                    }
-                    Err(e) => {
-                        error!("{}", Report::from_error(e));
-                        if fail_first {
-                            let _ = scu.abort().await;
-                            std::process::exit(-2);
-                        }
-                    }
-                }
-                scu = send_file(scu, file, message_id, pbx.as_ref(), verbose, fail_first).await?;
+                    verbose,
+                    fail_first,
+                )
+                .await;
             }
-            let _ = scu.release().await;
-            Ok::<(), Error>(())
+
+            let scu = scu_init
+                .establish_with_async(&addr)
+                .await
+                .map_err(Box::from)
+                .context(ScuSnafu)?;
+            store_files(
+                scu,
+                d_files,
+                pbx,
+                message_id,
+                never_transcode,
+                verbose,
+                fail_first,
+            )
+            .await
         });
     }
     while let Some(result) = tasks.join_next().await {
@@ -516,37 +486,66 @@ async fn run_async() -> Result<(), Error> {
 
     Ok(())
 }
+#[allow(clippy::too_many_arguments)]
+fn scu_options(
+    calling_ae_title: String,
+    called_ae_title: Option<String>,
+    max_pdu_length: u32,
+    username: Option<String>,
+    password: Option<String>,
+    kerberos_service_ticket: Option<String>,
+    saml_assertion: Option<String>,
+    jwt: Option<String>,
+    presentation_contexts: &HashSet<(String, String)>,
+) -> ClientAssociationOptions<'_> {
+    let mut scu_init = ClientAssociationOptions::new()
+        .calling_ae_title(calling_ae_title)
+        .max_pdu_length(max_pdu_length);
+
+    for (storage_sop_class_uid, transfer_syntax) in presentation_contexts {
+        scu_init = scu_init.with_presentation_context(storage_sop_class_uid, vec![transfer_syntax]);
+    }
+
+    if let Some(called_ae_title) = called_ae_title {
+        scu_init = scu_init.called_ae_title(called_ae_title);
+    }
+
+    if let Some(username) = username {
+        scu_init = scu_init.username(username);
+    }
+
+    if let Some(password) = password {
+        scu_init = scu_init.password(password);
+    }
+
+    if let Some(kerberos_service_ticket) = kerberos_service_ticket {
+        scu_init = scu_init.kerberos_service_ticket(kerberos_service_ticket);
+    }
+
+    if let Some(saml_assertion) = saml_assertion {
+        scu_init = scu_init.saml_assertion(saml_assertion);
+    }
+
+    if let Some(jwt) = jwt {
+        scu_init = scu_init.jwt(jwt);
+    }
+
+    scu_init
+}
+
 fn store_req_command(
     storage_sop_class_uid: &str,
     storage_sop_instance_uid: &str,
     message_id: u16,
-) -> InMemDicomObject<StandardDataDictionary> {
-    InMemDicomObject::command_from_element_iter([
-        // SOP Class UID
-        DataElement::new(
-            tags::AFFECTED_SOP_CLASS_UID,
-            VR::UI,
-            dicom_value!(Str, storage_sop_class_uid),
-        ),
-        // command field
-        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x0001])),
-        // message ID
-        DataElement::new(tags::MESSAGE_ID, VR::US, dicom_value!(U16, [message_id])),
-        //priority
-        DataElement::new(tags::PRIORITY, VR::US, dicom_value!(U16, [0x0000])),
-        // data set type
-        DataElement::new(
-            tags::COMMAND_DATA_SET_TYPE,
-            VR::US,
-            dicom_value!(U16, [0x0000]),
-        ),
-        // affected SOP Instance UID
-        DataElement::new(
-            tags::AFFECTED_SOP_INSTANCE_UID,
-            VR::UI,
-            dicom_value!(Str, storage_sop_instance_uid),
-        ),
-    ])
+) -> Command {
+    Command::CStoreRQ(CStoreRQ {
+        message_id,
+        affected_sop_class_uid: storage_sop_class_uid.to_string(),
+        affected_sop_instance_uid: storage_sop_instance_uid.to_string(),
+        priority: Priority::Medium,
+        move_originator_ae_title: None,
+        move_originator_message_id: None,
+    })
 }
 
 fn check_file(file: &Path) -> Result<DicomFile, Error> {
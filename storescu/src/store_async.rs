@@ -1,95 +1,98 @@
-use std::{collections::HashSet, sync::Arc};
+use std::sync::Arc;
 
-use dicom_dictionary_std::tags;
 use dicom_encoding::TransferSyntaxIndex;
-use dicom_object::{open_file, InMemDicomObject};
+use dicom_object::open_file;
 use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
 use dicom_ul::{
-    pdu::{PDataValue, PDataValueType},
-    ClientAssociation, ClientAssociationOptions, Pdu,
+    association::AsyncClientAssociation,
+    dimse::{Command, StatusType},
 };
 use indicatif::ProgressBar;
-use snafu::{OptionExt, ResultExt};
-use tokio::{io::AsyncWriteExt, net::TcpStream};
+use snafu::{OptionExt, Report, ResultExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::Mutex,
+};
 use tracing::{debug, error, info, warn};
 
 use crate::{
-    into_ts, store_req_command, ConvertFieldSnafu, CreateCommandSnafu, DicomFile, Error,
-    MissingAttributeSnafu, ReadDatasetSnafu, ReadFilePathSnafu, ScuSnafu,
-    UnsupportedFileTransferSyntaxSnafu, WriteDatasetSnafu,
+    check_presentation_contexts, into_ts, store_req_command, DicomFile, Error, ReadFilePathSnafu,
+    ScuSnafu, UnsupportedFileTransferSyntaxSnafu, WriteDatasetSnafu,
 };
 
-#[allow(clippy::too_many_arguments)]
-pub async fn get_scu(
-    addr: String,
-    calling_ae_title: String,
-    called_ae_title: Option<String>,
-    max_pdu_length: u32,
-    username: Option<String>,
-    password: Option<String>,
-    kerberos_service_ticket: Option<String>,
-    saml_assertion: Option<String>,
-    jwt: Option<String>,
-    presentation_contexts: HashSet<(String, String)>,
-) -> Result<ClientAssociation<TcpStream>, Error> {
-    let mut scu_init = ClientAssociationOptions::new()
-        .calling_ae_title(calling_ae_title)
-        .max_pdu_length(max_pdu_length);
-
-    for (storage_sop_class_uid, transfer_syntax) in &presentation_contexts {
-        scu_init = scu_init.with_presentation_context(storage_sop_class_uid, vec![transfer_syntax]);
-    }
-
-    if let Some(called_ae_title) = called_ae_title {
-        scu_init = scu_init.called_ae_title(called_ae_title);
-    }
-
-    if let Some(username) = username {
-        scu_init = scu_init.username(username);
-    }
-
-    if let Some(password) = password {
-        scu_init = scu_init.password(password);
-    }
-
-    if let Some(kerberos_service_ticket) = kerberos_service_ticket {
-        scu_init = scu_init.kerberos_service_ticket(kerberos_service_ticket);
-    }
-
-    if let Some(saml_assertion) = saml_assertion {
-        scu_init = scu_init.saml_assertion(saml_assertion);
-    }
-
-    if let Some(jwt) = jwt {
-        scu_init = scu_init.jwt(jwt);
+/// Send files from the shared queue through the given association
+/// until the queue is exhausted.
+pub async fn store_files<S>(
+    mut scu: AsyncClientAssociation<S>,
+    dicom_files: Arc<Mutex<Vec<DicomFile>>>,
+    progress_bar: Option<Arc<Mutex<ProgressBar>>>,
+    message_id: u16,
+    never_transcode: bool,
+    verbose: bool,
+    fail_first: bool,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        let file = {
+            let mut files = dicom_files.lock().await;
+            files.pop()
+        };
+        let mut file = match file {
+            Some(file) => file,
+            None => break,
+        };
+        let r: Result<_, Error> =
+            check_presentation_contexts(&file, scu.presentation_contexts(), never_transcode);
+        match r {
+            Ok((pc, ts)) => {
+                if verbose {
+                    debug!(
+                        "{}: Selected presentation context: {:?}",
+                        file.file.display(),
+                        pc
+                    );
+                }
+                file.pc_selected = Some(pc);
+                file.ts_selected = Some(ts);
+            }
+            Err(e) => {
+                error!("{}", Report::from_error(e));
+                if fail_first {
+                    let _ = scu.abort().await;
+                    std::process::exit(-2);
+                }
+            }
+        }
+        scu = send_file(
+            scu,
+            file,
+            message_id,
+            progress_bar.as_ref(),
+            verbose,
+            fail_first,
+        )
+        .await?;
     }
-
-    scu_init
-        .establish_with_async(&addr)
-        .await
-        .map_err(Box::from)
-        .context(ScuSnafu)
+    let _ = scu.release().await;
+    Ok(())
 }
 
-pub async fn send_file(
-    mut scu: ClientAssociation<TcpStream>,
+pub async fn send_file<S>(
+    mut scu: AsyncClientAssociation<S>,
     file: DicomFile,
     message_id: u16,
-    progress_bar: Option<&Arc<tokio::sync::Mutex<ProgressBar>>>,
+    progress_bar: Option<&Arc<Mutex<ProgressBar>>>,
     verbose: bool,
     fail_first: bool,
-) -> Result<ClientAssociation<TcpStream>, Error> {
+) -> Result<AsyncClientAssociation<S>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
     if let (Some(pc_selected), Some(ts_uid_selected)) = (file.pc_selected, file.ts_selected) {
         let cmd = store_req_command(&file.sop_class_uid, &file.sop_instance_uid, message_id);
 
-        let mut cmd_data = Vec::with_capacity(128);
-        cmd.write_dataset_with_ts(
-            &mut cmd_data,
-            &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
-        )
-        .map_err(Box::from)
-        .context(CreateCommandSnafu)?;
-
         let mut object_data = Vec::with_capacity(2048);
         let dicom_file = open_file(&file.file)
             .map_err(Box::from)
@@ -110,7 +113,7 @@ pub async fn send_file(
             .map_err(Box::from)
             .context(WriteDatasetSnafu)?;
 
-        let nbytes = cmd_data.len() + object_data.len();
+        let nbytes = object_data.len();
 
         if verbose {
             info!(
@@ -123,92 +126,52 @@ pub async fn send_file(
             );
         }
 
-        if nbytes < scu.acceptor_max_pdu_length().saturating_sub(100) as usize {
-            let pdu = Pdu::PData {
-                data: vec![
-                    PDataValue {
-                        presentation_context_id: pc_selected.id,
-                        value_type: PDataValueType::Command,
-                        is_last: true,
-                        data: cmd_data,
-                    },
-                    PDataValue {
-                        presentation_context_id: pc_selected.id,
-                        value_type: PDataValueType::Data,
-                        is_last: true,
-                        data: object_data,
-                    },
-                ],
-            };
-
-            scu.send(&pdu).await.map_err(Box::from).context(ScuSnafu)?;
-        } else {
-            let pdu = Pdu::PData {
-                data: vec![PDataValue {
-                    presentation_context_id: pc_selected.id,
-                    value_type: PDataValueType::Command,
-                    is_last: true,
-                    data: cmd_data,
-                }],
-            };
-
-            scu.send(&pdu).await.map_err(Box::from).context(ScuSnafu)?;
-
-            {
-                let mut pdata = scu.send_pdata(pc_selected.id).await;
-                pdata.write_all(&object_data).await.unwrap();
-                //.whatever_context("Failed to send C-STORE-RQ P-Data")?;
-            }
-        }
+        scu.send_message(pc_selected.id, &cmd, Some(&object_data))
+            .await
+            .map_err(Box::from)
+            .context(ScuSnafu)?;
 
         if verbose {
             debug!("Awaiting response...");
         }
 
-        let rsp_pdu = scu.receive().await.map_err(Box::from).context(ScuSnafu)?;
-
-        match rsp_pdu {
-            Pdu::PData { data } => {
-                let data_value = &data[0];
+        let rsp = scu
+            .receive_message()
+            .await
+            .map_err(Box::from)
+            .context(ScuSnafu)?;
 
-                let cmd_obj = InMemDicomObject::read_dataset_with_ts(
-                    &data_value.data[..],
-                    &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
-                )
-                .context(ReadDatasetSnafu)?;
+        match rsp.command {
+            Command::CStoreRSP(rsp) => {
                 if verbose {
-                    debug!("Full response: {:?}", cmd_obj);
+                    debug!("Full response: {:?}", rsp);
                 }
-                let status = cmd_obj
-                    .element(tags::STATUS)
-                    .context(MissingAttributeSnafu { tag: tags::STATUS })?
-                    .to_int::<u16>()
-                    .context(ConvertFieldSnafu { tag: tags::STATUS })?;
+                let status = rsp.status;
                 let storage_sop_instance_uid = file
                     .sop_instance_uid
                     .trim_end_matches(|c: char| c.is_whitespace() || c == '\0');
 
-                match status {
-                    // Success
-                    0 => {
+                match status.status_type() {
+                    StatusType::Success => {
                         if verbose {
                             info!("Successfully stored instance {}", storage_sop_instance_uid);
                         }
                     }
-                    // Warning
-                    1 | 0x0107 | 0x0116 | 0xB000..=0xBFFF => {
+                    StatusType::Warning => {
                         warn!(
                             "Possible issue storing instance `{}` (status code {:04X}H)",
-                            storage_sop_instance_uid, status
+                            storage_sop_instance_uid,
+                            status.code()
                         );
                     }
-                    0xFF00 | 0xFF01 => {
+                    StatusType::Pending => {
                         warn!(
                             "Possible issue storing instance `{}`: status is pending (status code {:04X}H)",
-                            storage_sop_instance_uid, status
+                            storage_sop_instance_uid,
+                            status.code()
                         );
                     }
-                    0xFE00 => {
+                    StatusType::Cancel => {
                         error!(
                             "Could not store instance `{}`: operation cancelled",
                             storage_sop_instance_uid
@@ -218,10 +181,11 @@ pub async fn send_file(
                             std::process::exit(-2);
                         }
                     }
-                    _ => {
+                    StatusType::Failure => {
                         error!(
                             "Failed to store instance `{}` (status code {:04X}H)",
-                            storage_sop_instance_uid, status
+                            storage_sop_instance_uid,
+                            status.code()
                         );
                         if fail_first {
                             let _ = scu.abort().await;
@@ -231,14 +195,8 @@ pub async fn send_file(
                 }
             }
 
-            pdu @ Pdu::Unknown { .. }
-            | pdu @ Pdu::AssociationRQ { .. }
-            | pdu @ Pdu::AssociationAC { .. }
-            | pdu @ Pdu::AssociationRJ { .. }
-            | pdu @ Pdu::ReleaseRQ
-            | pdu @ Pdu::ReleaseRP
-            | pdu @ Pdu::AbortRQ { .. } => {
-                error!("Unexpected SCP response: {:?}", pdu);
+            command => {
+                error!("Unexpected SCP response: {:?}", command);
                 let _ = scu.abort().await;
                 std::process::exit(-2);
             }
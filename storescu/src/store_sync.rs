@@ -1,96 +1,117 @@
-use std::{collections::HashSet, io::Write, net::TcpStream};
+use std::{
+    io::{Read, Write},
+    time::Duration,
+};
 
-use dicom_dictionary_std::tags;
 use dicom_encoding::TransferSyntaxIndex;
-use dicom_object::{open_file, InMemDicomObject};
+use dicom_object::open_file;
 use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
 use dicom_ul::{
-    pdu::{PDataValue, PDataValueType},
-    ClientAssociation, ClientAssociationOptions, Pdu,
+    association::client::CloseSocket,
+    dimse::{Command, StatusType},
+    ClientAssociation,
 };
-use indicatif::ProgressBar;
-use snafu::{OptionExt, ResultExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use snafu::{OptionExt, Report, ResultExt};
 use tracing::{debug, error, info, warn};
 
 use crate::{
-    into_ts, store_req_command, ConvertFieldSnafu, CreateCommandSnafu, DicomFile, Error,
-    MissingAttributeSnafu, ReadDatasetSnafu, ReadFilePathSnafu, ScuSnafu,
-    UnsupportedFileTransferSyntaxSnafu, WriteDatasetSnafu, WriteIOSnafu,
+    check_presentation_contexts, into_ts, store_req_command, DicomFile, Error, ReadFilePathSnafu,
+    ScuSnafu, UnsupportedFileTransferSyntaxSnafu, WriteDatasetSnafu,
 };
 
-#[allow(clippy::too_many_arguments)]
-pub fn get_scu(
-    addr: String,
-    calling_ae_title: String,
-    called_ae_title: Option<String>,
-    max_pdu_length: u32,
-    username: Option<String>,
-    password: Option<String>,
-    kerberos_service_ticket: Option<String>,
-    saml_assertion: Option<String>,
-    jwt: Option<String>,
-    presentation_contexts: HashSet<(String, String)>,
-) -> Result<ClientAssociation<TcpStream>, Error> {
-    let mut scu_init = ClientAssociationOptions::new()
-        .calling_ae_title(calling_ae_title)
-        .max_pdu_length(max_pdu_length);
-
-    for (storage_sop_class_uid, transfer_syntax) in &presentation_contexts {
-        scu_init = scu_init.with_presentation_context(storage_sop_class_uid, vec![transfer_syntax]);
-    }
-
-    if let Some(called_ae_title) = called_ae_title {
-        scu_init = scu_init.called_ae_title(called_ae_title);
-    }
-
-    if let Some(username) = username {
-        scu_init = scu_init.username(username);
+pub fn store_files<S>(
+    mut scu: ClientAssociation<S>,
+    mut dicom_files: Vec<DicomFile>,
+    message_id: u16,
+    never_transcode: bool,
+    verbose: bool,
+    fail_first: bool,
+) -> Result<(), Error>
+where
+    S: Read + Write + CloseSocket,
+{
+    if verbose {
+        info!("Association established");
     }
 
-    if let Some(password) = password {
-        scu_init = scu_init.password(password);
+    for file in &mut dicom_files {
+        // identify the right transfer syntax to use
+        let r: Result<_, Error> =
+            check_presentation_contexts(file, scu.presentation_contexts(), never_transcode);
+        match r {
+            Ok((pc, ts)) => {
+                if verbose {
+                    debug!(
+                        "{}: Selected presentation context: {:?}",
+                        file.file.display(),
+                        pc
+                    );
+                }
+                file.pc_selected = Some(pc);
+                file.ts_selected = Some(ts);
+            }
+            Err(e) => {
+                error!("{}", Report::from_error(e));
+                if fail_first {
+                    let _ = scu.abort();
+                    std::process::exit(-2);
+                }
+            }
+        }
     }
 
-    if let Some(kerberos_service_ticket) = kerberos_service_ticket {
-        scu_init = scu_init.kerberos_service_ticket(kerberos_service_ticket);
+    let progress_bar;
+    if !verbose {
+        progress_bar = Some(ProgressBar::new(dicom_files.len() as u64));
+        if let Some(pb) = progress_bar.as_ref() {
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template("[{elapsed_precise}] {bar:40} {pos}/{len} {wide_msg}")
+                    .expect("Invalid progress bar template"),
+            );
+            pb.enable_steady_tick(Duration::new(0, 480_000_000));
+        };
+    } else {
+        progress_bar = None;
     }
 
-    if let Some(saml_assertion) = saml_assertion {
-        scu_init = scu_init.saml_assertion(saml_assertion);
+    for file in dicom_files {
+        scu = send_file(
+            scu,
+            file,
+            message_id,
+            progress_bar.as_ref(),
+            verbose,
+            fail_first,
+        )?;
     }
 
-    if let Some(jwt) = jwt {
-        scu_init = scu_init.jwt(jwt);
-    }
+    if let Some(pb) = progress_bar {
+        pb.finish_with_message("done")
+    };
 
-    scu_init
-        .establish_with(&addr)
-        .map_err(Box::from)
-        .context(ScuSnafu)
+    scu.release().map_err(Box::from).context(ScuSnafu)?;
+    Ok(())
 }
 
-pub fn send_file(
-    mut scu: ClientAssociation<TcpStream>,
+pub fn send_file<S>(
+    mut scu: ClientAssociation<S>,
     file: DicomFile,
     message_id: u16,
     progress_bar: Option<&ProgressBar>,
     verbose: bool,
     fail_first: bool,
-) -> Result<ClientAssociation<TcpStream>, Error> {
+) -> Result<ClientAssociation<S>, Error>
+where
+    S: Read + Write + CloseSocket,
+{
     if let (Some(pc_selected), Some(ts_uid_selected)) = (file.pc_selected, file.ts_selected) {
         if let Some(pb) = &progress_bar {
             pb.set_message(file.sop_instance_uid.clone());
         }
         let cmd = store_req_command(&file.sop_class_uid, &file.sop_instance_uid, message_id);
 
-        let mut cmd_data = Vec::with_capacity(128);
-        cmd.write_dataset_with_ts(
-            &mut cmd_data,
-            &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
-        )
-        .map_err(Box::from)
-        .context(CreateCommandSnafu)?;
-
         let mut object_data = Vec::with_capacity(2048);
         let dicom_file = open_file(&file.file)
             .map_err(Box::from)
@@ -111,7 +132,7 @@ pub fn send_file(
             .map_err(Box::from)
             .context(WriteDatasetSnafu)?;
 
-        let nbytes = cmd_data.len() + object_data.len();
+        let nbytes = object_data.len();
 
         if verbose {
             info!(
@@ -124,91 +145,47 @@ pub fn send_file(
             );
         }
 
-        if nbytes < scu.acceptor_max_pdu_length().saturating_sub(100) as usize {
-            let pdu = Pdu::PData {
-                data: vec![
-                    PDataValue {
-                        presentation_context_id: pc_selected.id,
-                        value_type: PDataValueType::Command,
-                        is_last: true,
-                        data: cmd_data,
-                    },
-                    PDataValue {
-                        presentation_context_id: pc_selected.id,
-                        value_type: PDataValueType::Data,
-                        is_last: true,
-                        data: object_data,
-                    },
-                ],
-            };
-
-            scu.send(&pdu).map_err(Box::from).context(ScuSnafu)?;
-        } else {
-            let pdu = Pdu::PData {
-                data: vec![PDataValue {
-                    presentation_context_id: pc_selected.id,
-                    value_type: PDataValueType::Command,
-                    is_last: true,
-                    data: cmd_data,
-                }],
-            };
-
-            scu.send(&pdu).map_err(Box::from).context(ScuSnafu)?;
-
-            {
-                let mut pdata = scu.send_pdata(pc_selected.id);
-                pdata.write_all(&object_data).context(WriteIOSnafu)?;
-            }
-        }
+        scu.send_message(pc_selected.id, &cmd, Some(&object_data))
+            .map_err(Box::from)
+            .context(ScuSnafu)?;
 
         if verbose {
             debug!("Awaiting response...");
         }
 
-        let rsp_pdu = scu.receive().map_err(Box::from).context(ScuSnafu)?;
-
-        match rsp_pdu {
-            Pdu::PData { data } => {
-                let data_value = &data[0];
+        let rsp = scu.receive_message().map_err(Box::from).context(ScuSnafu)?;
 
-                let cmd_obj = InMemDicomObject::read_dataset_with_ts(
-                    &data_value.data[..],
-                    &dicom_transfer_syntax_registry::entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
-                )
-                .context(ReadDatasetSnafu)?;
+        match rsp.command {
+            Command::CStoreRSP(rsp) => {
                 if verbose {
-                    debug!("Full response: {:?}", cmd_obj);
+                    debug!("Full response: {:?}", rsp);
                 }
-                let status = cmd_obj
-                    .element(tags::STATUS)
-                    .context(MissingAttributeSnafu { tag: tags::STATUS })?
-                    .to_int::<u16>()
-                    .context(ConvertFieldSnafu { tag: tags::STATUS })?;
+                let status = rsp.status;
                 let storage_sop_instance_uid = file
                     .sop_instance_uid
                     .trim_end_matches(|c: char| c.is_whitespace() || c == '\0');
 
-                match status {
-                    // Success
-                    0 => {
+                match status.status_type() {
+                    StatusType::Success => {
                         if verbose {
                             info!("Successfully stored instance {}", storage_sop_instance_uid);
                         }
                     }
-                    // Warning
-                    1 | 0x0107 | 0x0116 | 0xB000..=0xBFFF => {
+                    StatusType::Warning => {
                         warn!(
                             "Possible issue storing instance `{}` (status code {:04X}H)",
-                            storage_sop_instance_uid, status
+                            storage_sop_instance_uid,
+                            status.code()
                         );
                     }
-                    0xFF00 | 0xFF01 => {
+                    StatusType::Pending => {
                         warn!(
                             "Possible issue storing instance `{}`: status is pending (status code {:04X}H)",
-                            storage_sop_instance_uid, status
+                            storage_sop_instance_uid,
+                            status.code()
                         );
                     }
-                    0xFE00 => {
+                    StatusType::Cancel => {
                         error!(
                             "Could not store instance `{}`: operation cancelled",
                             storage_sop_instance_uid
@@ -218,10 +195,11 @@ pub fn send_file(
                             std::process::exit(-2);
                         }
                     }
-                    _ => {
+                    StatusType::Failure => {
                         error!(
                             "Failed to store instance `{}` (status code {:04X}H)",
-                            storage_sop_instance_uid, status
+                            storage_sop_instance_uid,
+                            status.code()
                         );
                         if fail_first {
                             let _ = scu.abort();
@@ -231,14 +209,8 @@ pub fn send_file(
                 }
             }
 
-            pdu @ Pdu::Unknown { .. }
-            | pdu @ Pdu::AssociationRQ { .. }
-            | pdu @ Pdu::AssociationAC { .. }
-            | pdu @ Pdu::AssociationRJ { .. }
-            | pdu @ Pdu::ReleaseRQ
-            | pdu @ Pdu::ReleaseRP
-            | pdu @ Pdu::AbortRQ { .. } => {
-                error!("Unexpected SCP response: {:?}", pdu);
+            command => {
+                error!("Unexpected SCP response: {:?}", command);
                 let _ = scu.abort();
                 std::process::exit(-2);
             }
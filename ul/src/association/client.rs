@@ -7,18 +7,29 @@
 use bytes::BytesMut;
 use std::{
     borrow::Cow,
+    collections::VecDeque,
     convert::TryInto,
     io::{BufRead, BufReader, Cursor, Read, Write},
     net::{TcpStream, ToSocketAddrs},
+    sync::Arc,
     time::Duration,
 };
 
+#[cfg(feature = "tls")]
+use rustls::pki_types::ServerName;
+#[cfg(feature = "tls")]
+use snafu::OptionExt;
+#[cfg(feature = "tls")]
+use std::convert::TryFrom;
+
 use crate::{
+    dimse::{command_pdu, Command, Message, MessageAssembler},
     pdu::{
-        read_pdu, write_pdu, AbortRQSource, AssociationAC, AssociationRJ, AssociationRQ, Pdu,
-        PresentationContextProposed, PresentationContextResult, PresentationContextResultReason,
-        ReadPduSnafu, UserIdentity, UserIdentityType, UserVariableItem, DEFAULT_MAX_PDU,
-        MAXIMUM_PDU_SIZE,
+        read_pdu, write_pdu, AbortRQSource, AssociationAC, AssociationRJ, AssociationRQ,
+        AsyncOperationsWindow, Pdu, PresentationContextProposed, PresentationContextResult,
+        PresentationContextResultReason, ReadPduSnafu, RoleSelection,
+        SopClassCommonExtendedNegotiation, UserIdentity, UserIdentityType, UserVariableItem,
+        DEFAULT_MAX_PDU, MAXIMUM_PDU_SIZE,
     },
     AeAddr, IMPLEMENTATION_CLASS_UID, IMPLEMENTATION_VERSION_NAME,
 };
@@ -27,8 +38,12 @@ use snafu::{ensure, Backtrace, ResultExt, Snafu};
 use bytes::Buf;
 
 use super::{
+    find_async_operations_window, find_role_selection, find_user_identity_server_response,
+    observer::{Direction, ObserverHandle, PduObserver},
     pdata::{PDataReader, PDataWriter},
+    state::{Action, Event, Side, State, StateMachine, DEFAULT_ARTIM_TIMEOUT},
     uid::trim_uid,
+    NegotiatedContext, Roles,
 };
 
 #[derive(Debug, Snafu)]
@@ -133,6 +148,52 @@ pub enum Error {
 
     #[snafu(display("Connection closed by peer"))]
     ConnectionClosed,
+
+    /// association released by the acceptor
+    Released { backtrace: Backtrace },
+
+    #[snafu(display("{} is not allowed in association state {}", event, state))]
+    #[non_exhaustive]
+    InvalidState {
+        /// the state of the association
+        state: State,
+        /// the event which was not allowed
+        event: Event,
+        backtrace: Backtrace,
+    },
+
+    /// failed to decode DIMSE message
+    #[non_exhaustive]
+    ReadMessage {
+        #[snafu(backtrace)]
+        source: crate::dimse::ReadError,
+    },
+
+    /// missing TLS client configuration
+    #[cfg(feature = "tls")]
+    MissingTlsConfig { backtrace: Backtrace },
+
+    #[cfg(feature = "tls")]
+    #[snafu(display("invalid TLS server name `{}`", name))]
+    InvalidServerName {
+        name: String,
+        source: rustls::pki_types::InvalidDnsNameError,
+        backtrace: Backtrace,
+    },
+
+    /// could not create TLS session
+    #[cfg(feature = "tls")]
+    TlsSession {
+        source: rustls::Error,
+        backtrace: Backtrace,
+    },
+
+    /// TLS handshake failed
+    #[cfg(feature = "tls")]
+    TlsHandshake {
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -148,6 +209,27 @@ pub fn get_client_pdu<R>(
     max_pdu_length: u32,
     strict: bool,
 ) -> Result<Pdu>
+where
+    R: Read,
+{
+    read_client_pdu(
+        reader,
+        read_buffer,
+        max_pdu_length,
+        strict,
+        &ObserverHandle::default(),
+    )
+}
+
+/// Get a PDU from a reader,
+/// notifying the given observer.
+fn read_client_pdu<R>(
+    reader: &mut R,
+    read_buffer: &mut BytesMut,
+    max_pdu_length: u32,
+    strict: bool,
+    observer: &ObserverHandle,
+) -> Result<Pdu>
 where
     R: Read,
 {
@@ -157,7 +239,9 @@ where
         // try to read a PDU according to what's in the buffer
         match read_pdu(&mut buf, max_pdu_length, strict).context(ReceiveResponseSnafu)? {
             Some(pdu) => {
-                read_buffer.advance(buf.position() as usize);
+                let len = buf.position() as usize;
+                observer.observe(Direction::Received, &read_buffer[..len]);
+                read_buffer.advance(len);
                 break pdu;
             }
             None => {
@@ -259,6 +343,12 @@ pub struct ClientAssociationOptions<'a> {
     application_context_name: Cow<'a, str>,
     /// the list of requested presentation contexts
     presentation_contexts: Vec<(Cow<'a, str>, Vec<Cow<'a, str>>)>,
+    /// the SCP/SCU role selections to propose
+    role_selections: Vec<RoleSelection>,
+    /// the asynchronous operations window to propose
+    async_operations_window: Option<AsyncOperationsWindow>,
+    /// the SOP class common extended negotiation sub-items to send
+    common_extended_negotiations: Vec<SopClassCommonExtendedNegotiation>,
     /// the expected protocol version
     protocol_version: u16,
     /// the maximum PDU length requested for receiving PDUs
@@ -275,12 +365,24 @@ pub struct ClientAssociationOptions<'a> {
     saml_assertion: Option<Cow<'a, str>>,
     /// User identity JWT
     jwt: Option<Cow<'a, str>>,
+    /// Whether to request a positive response to the user identity
+    user_identity_positive_response: bool,
     /// TCP read timeout
     read_timeout: Option<Duration>,
     /// TCP write timeout
     write_timeout: Option<Duration>,
     /// TCP connection timeout
     connection_timeout: Option<Duration>,
+    /// Timeout for the peer to close the connection after the association ends
+    artim_timeout: Duration,
+    /// Observer of the PDUs sent and received
+    pdu_observer: ObserverHandle,
+    /// TLS client configuration
+    #[cfg(feature = "tls")]
+    tls_config: Option<Arc<rustls::ClientConfig>>,
+    /// Server name to verify the server's certificate against
+    #[cfg(feature = "tls")]
+    server_name: Option<Cow<'a, str>>,
 }
 
 impl Default for ClientAssociationOptions<'_> {
@@ -294,6 +396,9 @@ impl Default for ClientAssociationOptions<'_> {
             application_context_name: "1.2.840.10008.3.1.1.1".into(),
             // the list of requested presentation contexts
             presentation_contexts: Vec::new(),
+            role_selections: Vec::new(),
+            async_operations_window: None,
+            common_extended_negotiations: Vec::new(),
             protocol_version: 1,
             max_pdu_length: DEFAULT_MAX_PDU,
             strict: true,
@@ -302,9 +407,16 @@ impl Default for ClientAssociationOptions<'_> {
             kerberos_service_ticket: None,
             saml_assertion: None,
             jwt: None,
+            user_identity_positive_response: false,
             read_timeout: None,
             write_timeout: None,
             connection_timeout: None,
+            artim_timeout: DEFAULT_ARTIM_TIMEOUT,
+            pdu_observer: ObserverHandle::default(),
+            #[cfg(feature = "tls")]
+            tls_config: None,
+            #[cfg(feature = "tls")]
+            server_name: None,
         }
     }
 }
@@ -377,6 +489,86 @@ impl<'a> ClientAssociationOptions<'a> {
         self.with_presentation_context(abstract_syntax_uid.into(), default_transfer_syntaxes)
     }
 
+    /// Propose SCP/SCU role selection for the given SOP class,
+    /// stating whether this node wishes to act as an SCU,
+    /// as an SCP, or both.
+    ///
+    /// Without role selection,
+    /// this node may only act as the SCU of the association.
+    /// Proposing the SCP role is necessary, for instance,
+    /// to receive C-STORE sub-operations of a C-GET request.
+    /// The roles accepted are available through
+    /// [`ClientAssociation::roles`] once the association is established.
+    pub fn with_role_selection<T>(
+        mut self,
+        sop_class_uid: T,
+        scu_role: bool,
+        scp_role: bool,
+    ) -> Self
+    where
+        T: Into<Cow<'a, str>>,
+    {
+        self.role_selections.push(RoleSelection {
+            sop_class_uid: trim_uid(sop_class_uid.into()).into_owned(),
+            scu_role,
+            scp_role,
+        });
+        self
+    }
+
+    /// Propose an asynchronous operations window,
+    /// so that up to `max_operations_invoked` requests
+    /// may be outstanding at the same time in this association,
+    /// and up to `max_operations_performed` requests
+    /// may be performed by this node at the same time.
+    /// A value of 0 stands for an unlimited number of operations.
+    ///
+    /// The association acceptor may narrow down the window or ignore it,
+    /// in which case only one operation may be outstanding at a time.
+    /// The window accepted is available through
+    /// [`ClientAssociation::async_operations_window`]
+    /// once the association is established.
+    pub fn async_operations_window(
+        mut self,
+        max_operations_invoked: u16,
+        max_operations_performed: u16,
+    ) -> Self {
+        self.async_operations_window = Some(AsyncOperationsWindow {
+            max_operations_invoked,
+            max_operations_performed,
+        });
+        self
+    }
+
+    /// Describe a SOP class to the association acceptor
+    /// via SOP class common extended negotiation,
+    /// stating the service class to which it belongs
+    /// and the general SOP classes which it specializes.
+    ///
+    /// This allows the association acceptor
+    /// to accept SOP classes which it does not know of,
+    /// such as private or new storage SOP classes.
+    pub fn with_sop_class_common_extended_negotiation<T>(
+        mut self,
+        sop_class_uid: T,
+        service_class_uid: T,
+        related_general_sop_class_uids: Vec<T>,
+    ) -> Self
+    where
+        T: Into<Cow<'a, str>>,
+    {
+        self.common_extended_negotiations
+            .push(SopClassCommonExtendedNegotiation {
+                sop_class_uid: trim_uid(sop_class_uid.into()).into_owned(),
+                service_class_uid: trim_uid(service_class_uid.into()).into_owned(),
+                related_general_sop_class_uids: related_general_sop_class_uids
+                    .into_iter()
+                    .map(|uid| trim_uid(uid.into()).into_owned())
+                    .collect(),
+            });
+        self
+    }
+
     /// Override the maximum PDU length
     /// that this application entity will admit.
     pub fn max_pdu_length(mut self, value: u32) -> Self {
@@ -501,6 +693,17 @@ impl<'a> ClientAssociationOptions<'a> {
         self
     }
 
+    /// Request the association acceptor to respond to the user identity
+    /// once it is verified.
+    ///
+    /// The response can be retrieved from the association with
+    /// [`user_identity_server_response`](ClientAssociation::user_identity_server_response).
+    /// The default is not to request a response.
+    pub fn user_identity_positive_response(mut self, requested: bool) -> Self {
+        self.user_identity_positive_response = requested;
+        self
+    }
+
     /// Initiate the TCP connection to the given address
     /// and request a new DICOM association,
     /// negotiating the presentation contexts in the process.
@@ -545,6 +748,42 @@ impl<'a> ClientAssociationOptions<'a> {
         }
     }
 
+    /// Request a new DICOM association
+    /// over an already open connection to the association acceptor,
+    /// negotiating the presentation contexts in the process.
+    ///
+    /// The connection can be any bidirectional byte stream,
+    /// such as a Unix domain socket or an in-memory pipe.
+    /// The socket timeouts in these options are not applied to the stream,
+    /// so they should be configured beforehand if needed.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use dicom_ul::association::client::ClientAssociationOptions;
+    /// # #[cfg(unix)]
+    /// # fn run() -> Result<(), Box<dyn std::error::Error>> {
+    /// let stream = std::os::unix::net::UnixStream::connect("/run/dicom/storage.sock")?;
+    /// let association = ClientAssociationOptions::new()
+    ///     .with_abstract_syntax("1.2.840.10008.1.1")
+    ///     .called_ae_title("MY-STORAGE")
+    ///     .establish_stream(stream)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn establish_stream<S>(self, stream: S) -> Result<ClientAssociation<S>>
+    where
+        S: Read + Write + CloseSocket,
+    {
+        // fail if no presentation contexts were provided: they represent intent,
+        // should not be omitted by the user
+        ensure!(
+            !self.presentation_contexts.is_empty(),
+            MissingAbstractSyntaxSnafu
+        );
+        self.negotiate(stream, None)
+    }
+
     /// Set the read timeout for the underlying TCP socket
     ///
     /// This is used to set both the read and write timeout.
@@ -571,18 +810,195 @@ impl<'a> ClientAssociationOptions<'a> {
         }
     }
 
-    fn establish_impl<T>(
+    /// Set the value of the ARTIM timer,
+    /// which is how long to wait for the acceptor
+    /// to close the connection after the association is aborted,
+    /// before closing it unilaterally.
+    ///
+    /// The default is [`DEFAULT_ARTIM_TIMEOUT`].
+    pub fn artim_timeout(self, timeout: Duration) -> Self {
+        Self {
+            artim_timeout: timeout,
+            ..self
+        }
+    }
+
+    /// Set an observer to be called for every PDU
+    /// sent and received in the association,
+    /// such as a [`CaptureWriter`](super::observer::CaptureWriter).
+    pub fn pdu_observer(self, observer: Arc<dyn PduObserver>) -> Self {
+        Self {
+            pdu_observer: ObserverHandle::new(observer),
+            ..self
+        }
+    }
+
+    /// Set the TLS client configuration
+    /// for establishing associations over a secure transport connection
+    /// (see [`establish_tls`](Self::establish_tls)).
+    ///
+    /// For mutual authentication,
+    /// the configuration should also contain the client certificate
+    /// to present to the server.
+    #[cfg(feature = "tls")]
+    pub fn tls_config(self, config: impl Into<Arc<rustls::ClientConfig>>) -> Self {
+        Self {
+            tls_config: Some(config.into()),
+            ..self
+        }
+    }
+
+    /// Set the server name to verify the server's TLS certificate against.
+    ///
+    /// If not set,
+    /// the IP address of the server is used instead.
+    #[cfg(feature = "tls")]
+    pub fn server_name<T>(self, server_name: T) -> Self
+    where
+        T: Into<Cow<'a, str>>,
+    {
+        Self {
+            server_name: Some(server_name.into()),
+            ..self
+        }
+    }
+
+    /// Initiate the TCP connection to the given address,
+    /// perform a TLS handshake,
+    /// and request a new DICOM association over the secure connection,
+    /// negotiating the presentation contexts in the process.
+    ///
+    /// A TLS configuration must have been set
+    /// via [`tls_config`](Self::tls_config).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use dicom_ul::association::client::ClientAssociationOptions;
+    /// # use std::sync::Arc;
+    /// # fn run(tls_config: Arc<rustls::ClientConfig>) -> Result<(), Box<dyn std::error::Error>> {
+    /// let association = ClientAssociationOptions::new()
+    ///     .with_abstract_syntax("1.2.840.10008.1.1")
+    ///     .tls_config(tls_config)
+    ///     .server_name("pacs.example.com")
+    ///     .establish_tls("pacs.example.com:2762")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "tls")]
+    pub fn establish_tls<A: ToSocketAddrs>(
         self,
-        ae_address: AeAddr<T>,
-    ) -> Result<ClientAssociation<std::net::TcpStream>>
+        address: A,
+    ) -> Result<ClientAssociation<TlsStream>> {
+        self.establish_tls_impl(AeAddr::new_socket_addr(address))
+    }
+
+    /// Initiate the TCP connection to the given address,
+    /// perform a TLS handshake,
+    /// and request a new DICOM association over the secure connection,
+    /// negotiating the presentation contexts in the process.
+    ///
+    /// This method allows you to specify the called AE title
+    /// alongside with the socket address.
+    /// See [`establish_with`](Self::establish_with) for more details.
+    #[cfg(feature = "tls")]
+    #[allow(unreachable_patterns)]
+    pub fn establish_with_tls(self, ae_address: &str) -> Result<ClientAssociation<TlsStream>> {
+        match ae_address.try_into() {
+            Ok(ae_address) => self.establish_tls_impl(ae_address),
+            Err(_) => self.establish_tls_impl(AeAddr::new_socket_addr(ae_address)),
+        }
+    }
+
+    fn establish_impl<T>(self, ae_address: AeAddr<T>) -> Result<ClientAssociation<TcpStream>>
+    where
+        T: ToSocketAddrs,
+    {
+        let socket = self.connect(&ae_address)?;
+        self.negotiate(socket, ae_address.ae_title())
+    }
+
+    #[cfg(feature = "tls")]
+    fn establish_tls_impl<T>(self, ae_address: AeAddr<T>) -> Result<ClientAssociation<TlsStream>>
+    where
+        T: ToSocketAddrs,
+    {
+        let tls_config = self.tls_config.clone().context(MissingTlsConfigSnafu)?;
+        let socket = self.connect(&ae_address)?;
+        let server_name = match &self.server_name {
+            Some(name) => {
+                ServerName::try_from(name.to_string()).context(InvalidServerNameSnafu {
+                    name: name.as_ref(),
+                })?
+            }
+            None => ServerName::IpAddress(socket.peer_addr().context(ConnectSnafu)?.ip().into()),
+        };
+        let connection =
+            rustls::ClientConnection::new(tls_config, server_name).context(TlsSessionSnafu)?;
+        let mut stream = rustls::StreamOwned::new(connection, socket);
+        while stream.conn.is_handshaking() {
+            stream
+                .conn
+                .complete_io(&mut stream.sock)
+                .context(TlsHandshakeSnafu)?;
+        }
+        self.negotiate(stream, ae_address.ae_title())
+    }
+
+    /// Open the TCP connection to the given address
+    /// and apply the socket timeouts.
+    fn connect<T>(&self, ae_address: &AeAddr<T>) -> Result<TcpStream>
     where
         T: ToSocketAddrs,
+    {
+        // fail if no presentation contexts were provided: they represent intent,
+        // should not be omitted by the user
+        ensure!(
+            !self.presentation_contexts.is_empty(),
+            MissingAbstractSyntaxSnafu
+        );
+
+        let conn_result: Result<TcpStream> = if let Some(timeout) = self.connection_timeout {
+            let addresses = ae_address.to_socket_addrs().context(ToAddressSnafu)?;
+
+            let mut result: Result<TcpStream, std::io::Error> =
+                Result::Err(std::io::Error::from(std::io::ErrorKind::AddrNotAvailable));
+
+            for address in addresses {
+                result = std::net::TcpStream::connect_timeout(&address, timeout);
+                if result.is_ok() {
+                    break;
+                }
+            }
+            result.context(ConnectSnafu)
+        } else {
+            std::net::TcpStream::connect(ae_address).context(ConnectSnafu)
+        };
+
+        let socket = conn_result?;
+        socket
+            .set_read_timeout(self.read_timeout)
+            .context(SetReadTimeoutSnafu)?;
+        socket
+            .set_write_timeout(self.write_timeout)
+            .context(SetWriteTimeoutSnafu)?;
+        Ok(socket)
+    }
+
+    /// Request a new DICOM association through an open connection,
+    /// negotiating the presentation contexts in the process.
+    fn negotiate<S>(self, mut socket: S, ae_title: Option<&str>) -> Result<ClientAssociation<S>>
+    where
+        S: Read + Write + CloseSocket,
     {
         let ClientAssociationOptions {
             calling_ae_title,
             called_ae_title,
             application_context_name,
             presentation_contexts,
+            role_selections,
+            async_operations_window,
+            common_extended_negotiations,
             protocol_version,
             max_pdu_length,
             strict,
@@ -591,20 +1007,16 @@ impl<'a> ClientAssociationOptions<'a> {
             kerberos_service_ticket,
             saml_assertion,
             jwt,
+            user_identity_positive_response,
             read_timeout,
             write_timeout,
-            connection_timeout,
+            artim_timeout,
+            pdu_observer,
+            ..
         } = self;
 
-        // fail if no presentation contexts were provided: they represent intent,
-        // should not be omitted by the user
-        ensure!(
-            !presentation_contexts.is_empty(),
-            MissingAbstractSyntaxSnafu
-        );
-
         // choose called AE title
-        let called_ae_title: &str = match (&called_ae_title, ae_address.ae_title()) {
+        let called_ae_title: &str = match (&called_ae_title, ae_title) {
             (Some(aec), Some(_)) => {
                 tracing::warn!(
                     "Option `called_ae_title` overrides the AE title to `{}`",
@@ -638,6 +1050,7 @@ impl<'a> ClientAssociationOptions<'a> {
         ];
 
         if let Some(user_identity) = Self::determine_user_identity(
+            user_identity_positive_response,
             username,
             password,
             kerberos_service_ticket,
@@ -647,6 +1060,25 @@ impl<'a> ClientAssociationOptions<'a> {
             user_variables.push(UserVariableItem::UserIdentityItem(user_identity));
         }
 
+        user_variables
+            .extend(async_operations_window.map(UserVariableItem::AsyncOperationsWindowSubItem));
+        user_variables.extend(
+            role_selections
+                .iter()
+                .cloned()
+                .map(UserVariableItem::RoleSelectionSubItem),
+        );
+        user_variables.extend(
+            common_extended_negotiations
+                .into_iter()
+                .map(UserVariableItem::SopClassCommonExtendedNegotiationSubItem),
+        );
+
+        let proposed_contexts: Vec<_> = presentation_contexts
+            .iter()
+            .map(|pc| (pc.id, pc.abstract_syntax.clone()))
+            .collect();
+
         let msg = Pdu::AssociationRQ(AssociationRQ {
             protocol_version,
             calling_ae_title: calling_ae_title.to_string(),
@@ -656,34 +1088,17 @@ impl<'a> ClientAssociationOptions<'a> {
             user_variables,
         });
 
-        let conn_result: Result<TcpStream> = if let Some(timeout) = connection_timeout {
-            let addresses = ae_address.to_socket_addrs().context(ToAddressSnafu)?;
-
-            let mut result: Result<TcpStream, std::io::Error> =
-                Result::Err(std::io::Error::from(std::io::ErrorKind::AddrNotAvailable));
-
-            for address in addresses {
-                result = std::net::TcpStream::connect_timeout(&address, timeout);
-                if result.is_ok() {
-                    break;
-                }
-            }
-            result.context(ConnectSnafu)
-        } else {
-            std::net::TcpStream::connect(ae_address).context(ConnectSnafu)
-        };
+        // the transport connection is already open at this point
+        let mut state = StateMachine::new(Side::Requestor);
+        state.handle(Event::AssociateRequest);
+        state.handle(Event::TransportConnectConfirm);
 
-        let mut socket = conn_result?;
-        socket
-            .set_read_timeout(read_timeout)
-            .context(SetReadTimeoutSnafu)?;
-        socket
-            .set_write_timeout(write_timeout)
-            .context(SetWriteTimeoutSnafu)?;
         let mut buffer: Vec<u8> = Vec::with_capacity(max_pdu_length as usize);
         // send request
 
         write_pdu(&mut buffer, &msg).context(SendRequestSnafu)?;
+
+        pdu_observer.observe(Direction::Sent, &buffer);
         socket.write_all(&buffer).context(WireSendSnafu)?;
         buffer.clear();
 
@@ -691,7 +1106,13 @@ impl<'a> ClientAssociationOptions<'a> {
         // more data may live in `buf` which may be lost,
         // corrupting the PDU reader stream.
         let mut buf = BytesMut::with_capacity(MAXIMUM_PDU_SIZE as usize);
-        let msg = get_client_pdu(&mut socket, &mut buf, MAXIMUM_PDU_SIZE, self.strict)?;
+        let msg = read_client_pdu(
+            &mut socket,
+            &mut buf,
+            MAXIMUM_PDU_SIZE,
+            strict,
+            &pdu_observer,
+        )?;
         if !buf.is_empty() {
             tracing::warn!(
                 "Received more data than expected in the first PDU, further issues may arise"
@@ -742,12 +1163,28 @@ impl<'a> ClientAssociationOptions<'a> {
                             source: AbortRQSource::ServiceUser,
                         },
                     );
+                    pdu_observer.observe(Direction::Sent, &buffer);
                     let _ = socket.write_all(&buffer);
                     buffer.clear();
                     return NoAcceptedPresentationContextsSnafu.fail();
                 }
+                let negotiated_contexts = negotiated_contexts(
+                    &proposed_contexts,
+                    &presentation_contexts,
+                    &role_selections,
+                    &user_variables,
+                );
+                // the acceptor should only reply to a proposed window
+                let async_operations_window = async_operations_window
+                    .and(find_async_operations_window(&user_variables))
+                    .unwrap_or_default();
+                state.handle(Event::AssociateAcReceived);
                 Ok(ClientAssociation {
                     presentation_contexts,
+                    negotiated_contexts,
+                    async_operations_window,
+                    outstanding_requests: Vec::new(),
+                    received_messages: VecDeque::new(),
                     requestor_max_pdu_length: max_pdu_length,
                     acceptor_max_pdu_length,
                     socket,
@@ -756,7 +1193,12 @@ impl<'a> ClientAssociationOptions<'a> {
                     read_buffer: BytesMut::with_capacity(MAXIMUM_PDU_SIZE as usize),
                     read_timeout,
                     write_timeout,
+                    artim_timeout,
+                    state,
                     user_variables,
+                    message_id: 1,
+                    message_assembler: MessageAssembler::default(),
+                    pdu_observer,
                 })
             }
             Pdu::AssociationRJ(association_rj) => RejectedSnafu { association_rj }.fail(),
@@ -772,6 +1214,7 @@ impl<'a> ClientAssociationOptions<'a> {
                         source: AbortRQSource::ServiceUser,
                     },
                 );
+                pdu_observer.observe(Direction::Sent, &buffer);
                 let _ = socket.write_all(&buffer);
                 UnexpectedResponseSnafu { pdu }.fail()
             }
@@ -783,6 +1226,7 @@ impl<'a> ClientAssociationOptions<'a> {
                         source: AbortRQSource::ServiceUser,
                     },
                 );
+                pdu_observer.observe(Direction::Sent, &buffer);
                 let _ = socket.write_all(&buffer);
                 UnknownResponseSnafu { pdu }.fail()
             }
@@ -790,6 +1234,7 @@ impl<'a> ClientAssociationOptions<'a> {
     }
 
     fn determine_user_identity<T>(
+        positive_response_requested: bool,
         username: Option<T>,
         password: Option<T>,
         kerberos_service_ticket: Option<T>,
@@ -802,14 +1247,14 @@ impl<'a> ClientAssociationOptions<'a> {
         if let Some(username) = username {
             if let Some(password) = password {
                 return Some(UserIdentity::new(
-                    false,
+                    positive_response_requested,
                     UserIdentityType::UsernamePassword,
                     username.into().as_bytes().to_vec(),
                     password.into().as_bytes().to_vec(),
                 ));
             } else {
                 return Some(UserIdentity::new(
-                    false,
+                    positive_response_requested,
                     UserIdentityType::Username,
                     username.into().as_bytes().to_vec(),
                     vec![],
@@ -819,7 +1264,7 @@ impl<'a> ClientAssociationOptions<'a> {
 
         if let Some(kerberos_service_ticket) = kerberos_service_ticket {
             return Some(UserIdentity::new(
-                false,
+                positive_response_requested,
                 UserIdentityType::KerberosServiceTicket,
                 kerberos_service_ticket.into().as_bytes().to_vec(),
                 vec![],
@@ -828,7 +1273,7 @@ impl<'a> ClientAssociationOptions<'a> {
 
         if let Some(saml_assertion) = saml_assertion {
             return Some(UserIdentity::new(
-                false,
+                positive_response_requested,
                 UserIdentityType::SamlAssertion,
                 saml_assertion.into().as_bytes().to_vec(),
                 vec![],
@@ -837,7 +1282,7 @@ impl<'a> ClientAssociationOptions<'a> {
 
         if let Some(jwt) = jwt {
             return Some(UserIdentity::new(
-                false,
+                positive_response_requested,
                 UserIdentityType::Jwt,
                 jwt.into().as_bytes().to_vec(),
                 vec![],
@@ -851,12 +1296,56 @@ impl<'a> ClientAssociationOptions<'a> {
 /// Trait to close underlying socket
 pub trait CloseSocket {
     fn close(&mut self) -> std::io::Result<()>;
+
+    /// Set the timeout for subsequent reads from the socket,
+    /// as used for the ARTIM timer.
+    ///
+    /// The default implementation reports that timeouts are not supported,
+    /// in which case the association does not wait for the peer
+    /// to close the connection before closing it.
+    fn set_read_timeout(&mut self, _timeout: Option<Duration>) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
 }
 
 impl CloseSocket for std::net::TcpStream {
     fn close(&mut self) -> std::io::Result<()> {
         self.shutdown(std::net::Shutdown::Both)
     }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl CloseSocket for std::os::unix::net::UnixStream {
+    fn close(&mut self) -> std::io::Result<()> {
+        self.shutdown(std::net::Shutdown::Both)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> std::io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// A TLS stream over TCP,
+/// as used by associations established via
+/// [`establish_tls`](ClientAssociationOptions::establish_tls).
+#[cfg(feature = "tls")]
+pub type TlsStream = rustls::StreamOwned<rustls::ClientConnection, TcpStream>;
+
+#[cfg(feature = "tls")]
+impl CloseSocket for TlsStream {
+    fn close(&mut self) -> std::io::Result<()> {
+        self.conn.send_close_notify();
+        let _ = self.conn.complete_io(&mut self.sock);
+        self.sock.shutdown(std::net::Shutdown::Both)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
 }
 
 /// Trait to release association
@@ -864,9 +1353,64 @@ pub trait Release {
     fn release(&mut self) -> Result<()>;
 }
 
-impl Release for ClientAssociation<std::net::TcpStream> {
+impl<S> Release for ClientAssociation<S>
+where
+    S: Read + Write + CloseSocket,
+{
     fn release(&mut self) -> Result<()> {
-        self.release_impl()
+        self.release_impl(&mut |_| {})
+    }
+}
+
+/// Collect the details of the accepted presentation contexts,
+/// including the roles resulting from SCP/SCU role selection.
+fn negotiated_contexts(
+    proposed_contexts: &[(u8, String)],
+    accepted_contexts: &[PresentationContextResult],
+    proposed_roles: &[RoleSelection],
+    user_variables: &[UserVariableItem],
+) -> Vec<NegotiatedContext> {
+    accepted_contexts
+        .iter()
+        .filter_map(|pc| {
+            let (_, abstract_syntax) = proposed_contexts.iter().find(|(id, _)| *id == pc.id)?;
+            let proposed = proposed_roles
+                .iter()
+                .find(|role_selection| role_selection.sop_class_uid == *abstract_syntax);
+            let replied = find_role_selection(user_variables, abstract_syntax);
+            let roles = match (proposed, replied) {
+                (Some(proposed), Some(replied)) => Roles {
+                    scu: proposed.scu_role && replied.scu_role,
+                    scp: proposed.scp_role && replied.scp_role,
+                },
+                _ => Roles::default(),
+            };
+            Some(NegotiatedContext {
+                id: pc.id,
+                abstract_syntax: abstract_syntax.clone(),
+                roles,
+            })
+        })
+        .collect()
+}
+
+/// Check whether the message is a response to the request
+/// with the given message ID.
+fn is_response_to(message: &Message, message_id: u16) -> bool {
+    message.command.status().is_some()
+        && message.command.message_id_being_responded_to() == Some(message_id)
+}
+
+/// Stop tracking an outstanding request
+/// if the message is its final response.
+fn track_response(outstanding_requests: &mut Vec<u16>, message: &Message) {
+    if let (Some(message_id), Some(status)) = (
+        message.command.message_id_being_responded_to(),
+        message.command.status(),
+    ) {
+        if !status.is_pending() {
+            outstanding_requests.retain(|id| *id != message_id);
+        }
     }
 }
 
@@ -882,10 +1426,10 @@ impl Release for ClientAssociation<std::net::TcpStream> {
 /// When the value falls out of scope,
 /// the program will automatically try to gracefully release the association
 /// through a standard C-RELEASE message exchange,
-/// then shut down the underlying TCP connection.
+/// then shut down the underlying connection.
 ///
-/// This may either be sync or async depending on which method was called to
-/// establish the association.
+/// Associations established via `establish_async`
+/// are instead of type [`AsyncClientAssociation`](non_blocking::AsyncClientAssociation).
 #[derive(Debug)]
 pub struct ClientAssociation<S>
 where
@@ -895,11 +1439,19 @@ where
     /// The presentation contexts accorded with the acceptor application entity,
     /// without the rejected ones.
     presentation_contexts: Vec<PresentationContextResult>,
+    /// Details about the accepted presentation contexts
+    negotiated_contexts: Vec<NegotiatedContext>,
+    /// The negotiated asynchronous operations window
+    async_operations_window: AsyncOperationsWindow,
+    /// The message IDs of the requests awaiting a final response
+    outstanding_requests: Vec<u16>,
+    /// DIMSE messages received but not yet retrieved
+    received_messages: VecDeque<Message>,
     /// The maximum PDU length that this application entity is expecting to receive
     requestor_max_pdu_length: u32,
     /// The maximum PDU length that the remote application entity accepts
     acceptor_max_pdu_length: u32,
-    /// The TCP stream to the other DICOM node
+    /// The stream to the other DICOM node
     socket: S,
     /// Buffer to assemble PDU before sending it on wire
     buffer: Vec<u8>,
@@ -909,10 +1461,20 @@ where
     read_timeout: Option<Duration>,
     /// Timeout for individual socket Writes.
     write_timeout: Option<Duration>,
+    /// Timeout for the peer to close the connection after the association ends
+    artim_timeout: Duration,
+    /// The upper layer protocol state machine
+    state: StateMachine,
     /// Buffer to assemble PDU before parsing
     read_buffer: BytesMut,
     /// User variables that were taken from the server
     user_variables: Vec<UserVariableItem>,
+    /// The message ID to use in the next DIMSE request
+    message_id: u16,
+    /// DIMSE message fragments received so far
+    message_assembler: MessageAssembler,
+    /// Observer of the PDUs sent and received
+    pdu_observer: ObserverHandle,
 }
 
 impl<S: CloseSocket> ClientAssociation<S>
@@ -929,11 +1491,49 @@ where
         self.write_timeout
     }
 
+    /// Retrieve the current state of the upper layer protocol machine.
+    pub fn state(&self) -> State {
+        self.state.state()
+    }
+
     /// Retrieve the list of negotiated presentation contexts.
     pub fn presentation_contexts(&self) -> &[PresentationContextResult] {
         &self.presentation_contexts
     }
 
+    /// Obtain the abstract syntax of an accepted presentation context.
+    pub fn abstract_syntax(&self, presentation_context_id: u8) -> Option<&str> {
+        self.negotiated_contexts
+            .iter()
+            .find(|pc| pc.id == presentation_context_id)
+            .map(|pc| pc.abstract_syntax.as_str())
+    }
+
+    /// Obtain the roles which this node may take
+    /// in an accepted presentation context.
+    ///
+    /// When this node is allowed to take the SCP role,
+    /// it may receive requests from the association acceptor
+    /// through that presentation context.
+    pub fn roles(&self, presentation_context_id: u8) -> Option<Roles> {
+        self.negotiated_contexts
+            .iter()
+            .find(|pc| pc.id == presentation_context_id)
+            .map(|pc| pc.roles)
+    }
+
+    /// Retrieve the asynchronous operations window of the association,
+    /// from the perspective of this node.
+    pub fn async_operations_window(&self) -> AsyncOperationsWindow {
+        self.async_operations_window
+    }
+
+    /// Retrieve the message IDs of the requests sent via `send_request`
+    /// which are still awaiting a final response.
+    pub fn outstanding_requests(&self) -> &[u16] {
+        &self.outstanding_requests
+    }
+
     /// Retrieve the maximum PDU length
     /// admitted by the association acceptor.
     pub fn acceptor_max_pdu_length(&self) -> u32 {
@@ -957,13 +1557,37 @@ where
     pub fn user_variables(&self) -> &[UserVariableItem] {
         &self.user_variables
     }
+
+    /// Retrieve the server response to the user identity,
+    /// if the association acceptor sent one.
+    ///
+    /// The acceptor only responds if a positive response was requested
+    /// and the identity was verified.
+    pub fn user_identity_server_response(&self) -> Option<&[u8]> {
+        find_user_identity_server_response(&self.user_variables)
+    }
+
+    /// Obtain a new message ID for a DIMSE request
+    /// to be sent through this association.
+    ///
+    /// Message IDs start at 1 and are incremented on each call.
+    pub fn next_message_id(&mut self) -> u16 {
+        let message_id = self.message_id;
+        self.message_id = self.message_id.wrapping_add(1);
+        message_id
+    }
 }
 
-impl ClientAssociation<std::net::TcpStream>
+impl<S> ClientAssociation<S>
 where
-    ClientAssociation<std::net::TcpStream>: Release,
+    S: Read + Write + CloseSocket,
 {
     /// Send a PDU message to the other intervenient.
+    ///
+    /// Fails without sending anything
+    /// if the PDU is not allowed in the current state of the association.
+    /// Sending an A-ABORT waits up to the ARTIM timeout
+    /// for the acceptor to close the connection.
     pub fn send(&mut self, msg: &Pdu) -> Result<()> {
         self.buffer.clear();
         write_pdu(&mut self.buffer, msg).context(SendRequestSnafu)?;
@@ -973,11 +1597,89 @@ where
             }
             .fail();
         }
-        self.socket.write_all(&self.buffer).context(WireSendSnafu)
+        if let Some(event) = Event::sent(msg) {
+            self.transition(event)?;
+        }
+        self.pdu_observer.observe(Direction::Sent, &self.buffer);
+        self.socket.write_all(&self.buffer).context(WireSendSnafu)?;
+        if self.state.state() == State::AwaitingTransportClose {
+            self.await_transport_close();
+        }
+        Ok(())
     }
 
     /// Read a PDU message from the other intervenient.
+    ///
+    /// A PDU which is not expected in the current state of the association
+    /// is answered with an A-ABORT, resulting in an error.
     pub fn receive(&mut self) -> Result<Pdu> {
+        let pdu = match self.receive_pdu() {
+            Ok(pdu) => pdu,
+            Err(e @ Error::ConnectionClosed) => {
+                self.state.handle(Event::TransportClosed);
+                let _ = self.socket.close();
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+        let event = Event::received(&pdu);
+        let action = self.transition(event)?;
+        if let Some(abort) = action.abort_pdu(event) {
+            self.buffer.clear();
+            write_pdu(&mut self.buffer, &abort).context(SendRequestSnafu)?;
+            self.pdu_observer.observe(Direction::Sent, &self.buffer);
+            let _ = self.socket.write_all(&self.buffer);
+            self.await_transport_close();
+            return match pdu {
+                pdu @ Pdu::Unknown { .. } => UnknownResponseSnafu { pdu }.fail(),
+                pdu => UnexpectedResponseSnafu { pdu }.fail(),
+            };
+        }
+        if action.closes_transport() {
+            let _ = self.socket.close();
+        }
+        Ok(pdu)
+    }
+
+    /// Feed an event to the upper layer state machine,
+    /// failing if the event is not allowed in the current state.
+    fn transition(&mut self, event: Event) -> Result<Action> {
+        let state = self.state.state();
+        match self.state.handle(event) {
+            Some(action) => Ok(action),
+            None => InvalidStateSnafu { state, event }.fail(),
+        }
+    }
+
+    /// Wait for the acceptor to close the connection
+    /// for up to the ARTIM timeout, then close it.
+    fn await_transport_close(&mut self) {
+        if self
+            .socket
+            .set_read_timeout(Some(self.artim_timeout))
+            .is_ok()
+        {
+            let mut buf = [0; 1024];
+            let closed = loop {
+                match self.socket.read(&mut buf) {
+                    Ok(0) => break true,
+                    Ok(_) => continue,
+                    Err(_) => break false,
+                }
+            };
+            if closed {
+                self.state.handle(Event::TransportClosed);
+            } else {
+                self.state.handle(Event::ArtimExpired);
+            }
+        } else {
+            self.state.handle(Event::ArtimExpired);
+        }
+        let _ = self.socket.close();
+    }
+
+    /// Read the next PDU from the wire.
+    fn receive_pdu(&mut self) -> Result<Pdu> {
         use std::io::{BufRead, BufReader, Cursor};
 
         let mut reader = BufReader::new(&mut self.socket);
@@ -988,7 +1690,10 @@ where
                 .context(ReceiveResponseSnafu)?
             {
                 Some(pdu) => {
-                    self.read_buffer.advance(buf.position() as usize);
+                    let len = buf.position() as usize;
+                    self.pdu_observer
+                        .observe(Direction::Received, &self.read_buffer[..len]);
+                    self.read_buffer.advance(len);
                     return Ok(pdu);
                 }
                 None => {
@@ -1010,24 +1715,49 @@ where
 
     /// Gracefully terminate the association by exchanging release messages
     /// and then shutting down the TCP connection.
-    pub fn release(mut self) -> Result<()> {
-        let out = self.release_impl();
-        let _ = self.socket.shutdown(std::net::Shutdown::Both);
+    ///
+    /// DIMSE messages not yet retrieved
+    /// or still in transit from the acceptor are discarded;
+    /// use [`release_with`](Self::release_with) to receive them.
+    /// A release collision,
+    /// in which the acceptor requests a release at the same time,
+    /// is resolved as specified in the standard.
+    pub fn release(self) -> Result<()> {
+        self.release_with(|_| {})
+    }
+
+    /// Gracefully terminate the association by exchanging release messages
+    /// and then shutting down the TCP connection,
+    /// passing each DIMSE message not yet retrieved
+    /// or received from the acceptor while awaiting the release response
+    /// to `on_message`.
+    ///
+    /// These messages can no longer be answered on this association,
+    /// since no P-Data may be sent after requesting a release.
+    pub fn release_with<F>(mut self, mut on_message: F) -> Result<()>
+    where
+        F: FnMut(Message),
+    {
+        let out = self.release_impl(&mut on_message);
+        let _ = self.socket.close();
         out
     }
 
     /// Send an abort message and shut down the TCP connection,
     /// terminating the association.
+    ///
+    /// The acceptor is given up to the ARTIM timeout
+    /// to close the connection first.
     pub fn abort(mut self) -> Result<()> {
         let pdu = Pdu::AbortRQ {
             source: AbortRQSource::ServiceUser,
         };
         let out = self.send(&pdu);
-        let _ = self.socket.shutdown(std::net::Shutdown::Both);
+        let _ = self.socket.close();
         out
     }
 
-    /// Obtain access to the inner TCP stream
+    /// Obtain access to the inner stream
     /// connected to the association acceptor.
     ///
     /// This can be used to send the PDU in semantic fragments of the message,
@@ -1036,7 +1766,7 @@ where
     /// **Note:** reading and writing should be done with care
     /// to avoid inconsistencies in the association state.
     /// Do not call `send` and `receive` while not in a PDU boundary.
-    pub fn inner_stream(&mut self) -> &mut std::net::TcpStream {
+    pub fn inner_stream(&mut self) -> &mut S {
         &mut self.socket
     }
 
@@ -1045,15 +1775,13 @@ where
     ///
     /// Returns a writer which automatically
     /// splits the inner data into separate PDUs if necessary.
-    pub fn send_pdata(
-        &mut self,
-        presentation_context_id: u8,
-    ) -> PDataWriter<&mut std::net::TcpStream> {
+    pub fn send_pdata(&mut self, presentation_context_id: u8) -> PDataWriter<&mut S> {
         PDataWriter::new(
             &mut self.socket,
             presentation_context_id,
             self.acceptor_max_pdu_length,
         )
+        .with_observer(self.pdu_observer.clone())
     }
 
     /// Prepare a P-Data reader for receiving
@@ -1061,12 +1789,129 @@ where
     ///
     /// Returns a reader which automatically
     /// receives more data PDUs once the bytes collected are consumed.
-    pub fn receive_pdata(&mut self) -> PDataReader<&mut std::net::TcpStream> {
+    pub fn receive_pdata(&mut self) -> PDataReader<&mut S> {
         PDataReader::new(
             &mut self.socket,
             self.requestor_max_pdu_length,
             &mut self.read_buffer,
         )
+        .with_observer(self.pdu_observer.clone())
+    }
+
+    /// Send a DIMSE message to the association acceptor.
+    ///
+    /// The command is encoded in _Implicit VR Little Endian_,
+    /// whereas `data` must already be encoded
+    /// in the transfer syntax of the given presentation context.
+    /// The data set is split into multiple PDUs if necessary.
+    pub fn send_message(
+        &mut self,
+        presentation_context_id: u8,
+        command: &Command,
+        data: Option<&[u8]>,
+    ) -> Result<()> {
+        let (pdu, remaining) = command_pdu(
+            presentation_context_id,
+            command,
+            data,
+            self.acceptor_max_pdu_length,
+        );
+        self.send(&pdu)?;
+        if let Some(data) = remaining {
+            let mut writer = self.send_pdata(presentation_context_id);
+            writer.write_all(data).context(WireSendSnafu)?;
+            writer.finish().context(WireSendSnafu)?;
+        }
+        Ok(())
+    }
+
+    /// Send a DIMSE request to the association acceptor
+    /// without waiting for its response,
+    /// so that multiple requests may be outstanding at the same time.
+    ///
+    /// If the number of outstanding requests has reached
+    /// the maximum number of operations invoked
+    /// in the negotiated [asynchronous operations window](Self::async_operations_window),
+    /// messages are received and kept for later retrieval
+    /// until a request is complete.
+    /// Responses can then be retrieved
+    /// via [`receive_response`](Self::receive_response)
+    /// or [`receive_message`](Self::receive_message).
+    pub fn send_request(
+        &mut self,
+        presentation_context_id: u8,
+        command: &Command,
+        data: Option<&[u8]>,
+    ) -> Result<()> {
+        if let Some(message_id) = command.message_id() {
+            let max_operations = self.async_operations_window.max_operations_invoked as usize;
+            while max_operations != 0 && self.outstanding_requests.len() >= max_operations {
+                let message = self.read_message()?;
+                self.received_messages.push_back(message);
+            }
+            self.send_message(presentation_context_id, command, data)?;
+            self.outstanding_requests.push(message_id);
+            Ok(())
+        } else {
+            self.send_message(presentation_context_id, command, data)
+        }
+    }
+
+    /// Receive the next DIMSE message from the association acceptor,
+    /// comprising the command and the data set which follows it, if any.
+    ///
+    /// Messages already received while waiting for other responses
+    /// are retrieved first.
+    /// If the acceptor asks to release the association instead,
+    /// the release is confirmed and [`Error::Released`] is returned.
+    /// Receiving any other PDU results in an error.
+    pub fn receive_message(&mut self) -> Result<Message> {
+        if let Some(message) = self.received_messages.pop_front() {
+            return Ok(message);
+        }
+        self.read_message()
+    }
+
+    /// Receive the next response to the request with the given message ID.
+    ///
+    /// Other messages received in the meantime
+    /// are kept for later retrieval.
+    pub fn receive_response(&mut self, message_id: u16) -> Result<Message> {
+        loop {
+            if let Some(i) = self
+                .received_messages
+                .iter()
+                .position(|message| is_response_to(message, message_id))
+            {
+                return Ok(self.received_messages.remove(i).unwrap());
+            }
+            let message = self.read_message()?;
+            self.received_messages.push_back(message);
+        }
+    }
+
+    /// Read the next DIMSE message from the wire,
+    /// keeping track of the requests which are complete.
+    fn read_message(&mut self) -> Result<Message> {
+        loop {
+            if let Some(message) = self
+                .message_assembler
+                .next_message()
+                .context(ReadMessageSnafu)?
+            {
+                track_response(&mut self.outstanding_requests, &message);
+                return Ok(message);
+            }
+            match self.receive()? {
+                Pdu::PData { data } => self.message_assembler.push(data),
+                Pdu::ReleaseRQ => {
+                    self.send(&Pdu::ReleaseRP)?;
+                    return ReleasedSnafu.fail();
+                }
+                pdu @ Pdu::Unknown { .. } => return UnknownResponseSnafu { pdu }.fail(),
+                pdu => return UnexpectedResponseSnafu { pdu }.fail(),
+            }
+        }
     }
 
     /// Release implementation function,
@@ -1074,53 +1919,74 @@ where
     /// This is in a separate private function because
     /// terminating a connection should still close the connection
     /// if the exchange fails.
-    fn release_impl(&mut self) -> Result<()> {
+    fn release_impl(&mut self, on_message: &mut dyn FnMut(Message)) -> Result<()> {
+        self.received_messages.drain(..).for_each(&mut *on_message);
         let pdu = Pdu::ReleaseRQ;
         self.send(&pdu)?;
-        let pdu = self.receive()?;
 
-        match pdu {
-            Pdu::ReleaseRP => {}
-            pdu @ Pdu::AbortRQ { .. }
-            | pdu @ Pdu::AssociationAC { .. }
-            | pdu @ Pdu::AssociationRJ { .. }
-            | pdu @ Pdu::AssociationRQ { .. }
-            | pdu @ Pdu::PData { .. }
-            | pdu @ Pdu::ReleaseRQ { .. } => return UnexpectedResponseSnafu { pdu }.fail(),
-            pdu @ Pdu::Unknown { .. } => return UnknownResponseSnafu { pdu }.fail(),
+        loop {
+            match self.receive()? {
+                Pdu::ReleaseRP => return Ok(()),
+                // data still in transit from the acceptor
+                Pdu::PData { data } => {
+                    self.message_assembler.push(data);
+                    while let Some(message) = self
+                        .message_assembler
+                        .next_message()
+                        .context(ReadMessageSnafu)?
+                    {
+                        on_message(message);
+                    }
+                }
+                // release collision: the requestor responds first
+                Pdu::ReleaseRQ => self.send(&Pdu::ReleaseRP)?,
+                pdu @ Pdu::Unknown { .. } => return UnknownResponseSnafu { pdu }.fail(),
+                pdu => return UnexpectedResponseSnafu { pdu }.fail(),
+            }
         }
-        Ok(())
     }
 }
 
-/// Automatically release the association and shut down the connection.
+/// Automatically release the association if it is still established,
+/// and shut down the connection.
 impl<T> Drop for ClientAssociation<T>
 where
     T: CloseSocket,
     ClientAssociation<T>: Release,
 {
     fn drop(&mut self) {
-        let _ = self.release();
+        if self.state.state() == State::Established {
+            let _ = self.release();
+        }
         let _ = self.socket.close();
     }
 }
 
 #[cfg(feature = "async")]
 pub mod non_blocking {
-    use std::{convert::TryInto, future::Future, io::Cursor, time::Duration};
+    use std::{
+        collections::VecDeque, convert::TryInto, future::Future, io::Cursor, time::Duration,
+    };
 
     use crate::{
         association::{
             client::{
                 ConnectSnafu, ConnectionClosedSnafu, MissingAbstractSyntaxSnafu,
                 NoAcceptedPresentationContextsSnafu, ProtocolVersionMismatchSnafu,
-                ReceiveResponseSnafu, ReceiveSnafu, RejectedSnafu, SendRequestSnafu,
-                ToAddressSnafu, UnexpectedResponseSnafu, UnknownResponseSnafu, WireSendSnafu,
+                ReadMessageSnafu, ReceiveResponseSnafu, ReceiveSnafu, RejectedSnafu,
+                SendRequestSnafu, ToAddressSnafu, UnexpectedResponseSnafu, UnknownResponseSnafu,
+                WireSendSnafu,
             },
+            find_async_operations_window, find_user_identity_server_response,
+            observer::{Direction, ObserverHandle},
             pdata::non_blocking::{AsyncPDataWriter, PDataReader},
+            state::{Action, Event, Side, State, StateMachine},
+            NegotiatedContext, Roles,
         },
+        dimse::{command_pdu, Command, Message, MessageAssembler},
         pdu::{
-            AbortRQSource, AssociationAC, AssociationRQ, PresentationContextProposed,
+            AbortRQSource, AssociationAC, AssociationRQ, AsyncOperationsWindow,
+            PresentationContextProposed, PresentationContextResult,
             PresentationContextResultReason, ReadPduSnafu, UserVariableItem, DEFAULT_MAX_PDU,
             MAXIMUM_PDU_SIZE,
         },
@@ -1128,17 +1994,42 @@ pub mod non_blocking {
     };
 
     use super::{
-        ClientAssociation, ClientAssociationOptions, CloseSocket, Release, Result,
-        SendTooLongPduSnafu, TimeoutSnafu,
+        is_response_to, negotiated_contexts, track_response, ClientAssociationOptions, Error,
+        InvalidStateSnafu, ReleasedSnafu, Result, SendTooLongPduSnafu, TimeoutSnafu,
     };
+    #[cfg(feature = "async-tls")]
+    use super::{InvalidServerNameSnafu, MissingTlsConfigSnafu, TlsHandshakeSnafu};
     use bytes::{Buf, BytesMut};
+    #[cfg(feature = "async-tls")]
+    use rustls::pki_types::ServerName;
+    #[cfg(feature = "async-tls")]
+    use snafu::OptionExt;
     use snafu::{ensure, ResultExt};
-    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+    #[cfg(feature = "async-tls")]
+    use std::convert::TryFrom;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    /// A TLS stream over TCP,
+    /// as used by associations established via
+    /// [`establish_tls_async`](ClientAssociationOptions::establish_tls_async).
+    #[cfg(feature = "async-tls")]
+    pub type TlsStream = tokio_rustls::client::TlsStream<tokio::net::TcpStream>;
 
     pub async fn get_client_pdu_async<R: AsyncRead + Unpin>(
         reader: &mut R,
         max_pdu_length: u32,
         strict: bool,
+    ) -> Result<Pdu> {
+        read_client_pdu_async(reader, max_pdu_length, strict, &ObserverHandle::default()).await
+    }
+
+    /// Get a PDU from an asynchronous reader,
+    /// notifying the given observer.
+    async fn read_client_pdu_async<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        max_pdu_length: u32,
+        strict: bool,
+        observer: &ObserverHandle,
     ) -> Result<Pdu> {
         // receive response
         use tokio::io::AsyncReadExt;
@@ -1148,7 +2039,9 @@ pub mod non_blocking {
             let mut buf = Cursor::new(&read_buffer[..]);
             match read_pdu(&mut buf, max_pdu_length, strict).context(ReceiveResponseSnafu)? {
                 Some(pdu) => {
-                    read_buffer.advance(buf.position() as usize);
+                    let len = buf.position() as usize;
+                    observer.observe(Direction::Received, &read_buffer[..len]);
+                    read_buffer.advance(len);
                     break pdu;
                 }
                 None => {
@@ -1185,15 +2078,105 @@ pub mod non_blocking {
         async fn establish_impl_async<T>(
             self,
             ae_address: AeAddr<T>,
-        ) -> Result<ClientAssociation<tokio::net::TcpStream>>
+        ) -> Result<AsyncClientAssociation<tokio::net::TcpStream>>
+        where
+            T: tokio::net::ToSocketAddrs,
+        {
+            let socket = self.connect_async(&ae_address).await?;
+            self.negotiate_async(socket, ae_address.ae_title()).await
+        }
+
+        #[cfg(feature = "async-tls")]
+        async fn establish_tls_impl_async<T>(
+            self,
+            ae_address: AeAddr<T>,
+        ) -> Result<AsyncClientAssociation<TlsStream>>
         where
             T: tokio::net::ToSocketAddrs,
+        {
+            let tls_config = self.tls_config.clone().context(MissingTlsConfigSnafu)?;
+            let socket = self.connect_async(&ae_address).await?;
+            let server_name = match &self.server_name {
+                Some(name) => {
+                    ServerName::try_from(name.to_string()).context(InvalidServerNameSnafu {
+                        name: name.as_ref(),
+                    })?
+                }
+                None => {
+                    ServerName::IpAddress(socket.peer_addr().context(ConnectSnafu)?.ip().into())
+                }
+            };
+            let connector = tokio_rustls::TlsConnector::from(tls_config);
+            let stream = timeout(self.read_timeout, async {
+                connector
+                    .connect(server_name, socket)
+                    .await
+                    .context(TlsHandshakeSnafu)
+            })
+            .await?;
+            self.negotiate_async(stream, ae_address.ae_title()).await
+        }
+
+        /// Open the TCP connection to the given address.
+        async fn connect_async<T>(&self, ae_address: &AeAddr<T>) -> Result<tokio::net::TcpStream>
+        where
+            T: tokio::net::ToSocketAddrs,
+        {
+            // fail if no presentation contexts were provided: they represent intent,
+            // should not be omitted by the user
+            ensure!(
+                !self.presentation_contexts.is_empty(),
+                MissingAbstractSyntaxSnafu
+            );
+
+            if let Some(timeout) = self.connection_timeout {
+                let addresses = tokio::net::lookup_host(ae_address.socket_addr())
+                    .await
+                    .context(ToAddressSnafu)?;
+
+                let mut result: Result<tokio::net::TcpStream, std::io::Error> =
+                    Result::Err(std::io::Error::from(std::io::ErrorKind::AddrNotAvailable));
+
+                for address in addresses {
+                    result = match tokio::time::timeout(
+                        timeout,
+                        tokio::net::TcpStream::connect(&address),
+                    )
+                    .await
+                    {
+                        Ok(inner) => inner,
+                        Err(_) => result,
+                    };
+                    if result.is_ok() {
+                        break;
+                    }
+                }
+                result.context(ConnectSnafu)
+            } else {
+                tokio::net::TcpStream::connect(ae_address.socket_addr())
+                    .await
+                    .context(ConnectSnafu)
+            }
+        }
+
+        /// Request a new DICOM association through an open connection,
+        /// negotiating the presentation contexts in the process.
+        async fn negotiate_async<S>(
+            self,
+            mut socket: S,
+            ae_title: Option<&str>,
+        ) -> Result<AsyncClientAssociation<S>>
+        where
+            S: AsyncRead + AsyncWrite + Unpin,
         {
             let ClientAssociationOptions {
                 calling_ae_title,
                 called_ae_title,
                 application_context_name,
                 presentation_contexts,
+                role_selections,
+                async_operations_window,
+                common_extended_negotiations,
                 protocol_version,
                 max_pdu_length,
                 strict,
@@ -1202,20 +2185,16 @@ pub mod non_blocking {
                 kerberos_service_ticket,
                 saml_assertion,
                 jwt,
+                user_identity_positive_response,
                 read_timeout,
                 write_timeout,
-                connection_timeout,
+                artim_timeout,
+                pdu_observer,
+                ..
             } = self;
 
-            // fail if no presentation contexts were provided: they represent intent,
-            // should not be omitted by the user
-            ensure!(
-                !presentation_contexts.is_empty(),
-                MissingAbstractSyntaxSnafu
-            );
-
             // choose called AE title
-            let called_ae_title: &str = match (&called_ae_title, ae_address.ae_title()) {
+            let called_ae_title: &str = match (&called_ae_title, ae_title) {
                 (Some(aec), Some(_)) => {
                     tracing::warn!(
                         "Option `called_ae_title` overrides the AE title to `{}`",
@@ -1251,6 +2230,7 @@ pub mod non_blocking {
             ];
 
             if let Some(user_identity) = Self::determine_user_identity(
+                user_identity_positive_response,
                 username,
                 password,
                 kerberos_service_ticket,
@@ -1260,6 +2240,26 @@ pub mod non_blocking {
                 user_variables.push(UserVariableItem::UserIdentityItem(user_identity));
             }
 
+            user_variables.extend(
+                async_operations_window.map(UserVariableItem::AsyncOperationsWindowSubItem),
+            );
+            user_variables.extend(
+                role_selections
+                    .iter()
+                    .cloned()
+                    .map(UserVariableItem::RoleSelectionSubItem),
+            );
+            user_variables.extend(
+                common_extended_negotiations
+                    .into_iter()
+                    .map(UserVariableItem::SopClassCommonExtendedNegotiationSubItem),
+            );
+
+            let proposed_contexts: Vec<_> = presentation_contexts
+                .iter()
+                .map(|pc| (pc.id, pc.abstract_syntax.clone()))
+                .collect();
+
             let msg = Pdu::AssociationRQ(AssociationRQ {
                 protocol_version,
                 calling_ae_title: calling_ae_title.to_string(),
@@ -1268,41 +2268,17 @@ pub mod non_blocking {
                 presentation_contexts,
                 user_variables,
             });
-            let conn_result: Result<tokio::net::TcpStream> =
-                if let Some(timeout) = connection_timeout {
-                    let addresses = tokio::net::lookup_host(ae_address.socket_addr())
-                        .await
-                        .context(ToAddressSnafu)?;
-
-                    let mut result: Result<tokio::net::TcpStream, std::io::Error> =
-                        Result::Err(std::io::Error::from(std::io::ErrorKind::AddrNotAvailable));
 
-                    for address in addresses {
-                        result = match tokio::time::timeout(
-                            timeout,
-                            tokio::net::TcpStream::connect(&address),
-                        )
-                        .await
-                        {
-                            Ok(inner) => inner,
-                            Err(_) => result,
-                        };
-                        if result.is_ok() {
-                            break;
-                        }
-                    }
-                    result.context(ConnectSnafu)
-                } else {
-                    tokio::net::TcpStream::connect(ae_address.socket_addr())
-                        .await
-                        .context(ConnectSnafu)
-                };
+            // the transport connection is already open at this point
+            let mut state = StateMachine::new(Side::Requestor);
+            state.handle(Event::AssociateRequest);
+            state.handle(Event::TransportConnectConfirm);
 
-            let mut socket = conn_result?;
             let mut buffer: Vec<u8> = Vec::with_capacity(max_pdu_length as usize);
 
             // send request
             write_pdu(&mut buffer, &msg).context(SendRequestSnafu)?;
+            pdu_observer.observe(Direction::Sent, &buffer);
             timeout(write_timeout, async {
                 socket.write_all(&buffer).await.context(WireSendSnafu)?;
                 Ok(())
@@ -1310,7 +2286,7 @@ pub mod non_blocking {
             .await?;
             buffer.clear();
             let msg = timeout(read_timeout, async {
-                get_client_pdu_async(&mut socket, MAXIMUM_PDU_SIZE, strict).await
+                read_client_pdu_async(&mut socket, MAXIMUM_PDU_SIZE, strict, &pdu_observer).await
             })
             .await?;
 
@@ -1358,6 +2334,7 @@ pub mod non_blocking {
                                 source: AbortRQSource::ServiceUser,
                             },
                         );
+                        pdu_observer.observe(Direction::Sent, &buffer);
                         let _ = timeout(write_timeout, async {
                             socket.write_all(&buffer).await.context(WireSendSnafu)
                         })
@@ -1365,8 +2342,23 @@ pub mod non_blocking {
                         buffer.clear();
                         return NoAcceptedPresentationContextsSnafu.fail();
                     }
-                    Ok(ClientAssociation {
+                    let negotiated_contexts = negotiated_contexts(
+                        &proposed_contexts,
+                        &presentation_contexts,
+                        &role_selections,
+                        &user_variables,
+                    );
+                    // the acceptor should only reply to a proposed window
+                    let async_operations_window = async_operations_window
+                        .and(find_async_operations_window(&user_variables))
+                        .unwrap_or_default();
+                    state.handle(Event::AssociateAcReceived);
+                    Ok(AsyncClientAssociation {
                         presentation_contexts,
+                        negotiated_contexts,
+                        async_operations_window,
+                        outstanding_requests: Vec::new(),
+                        received_messages: VecDeque::new(),
                         requestor_max_pdu_length: max_pdu_length,
                         acceptor_max_pdu_length,
                         socket,
@@ -1374,8 +2366,13 @@ pub mod non_blocking {
                         strict,
                         read_timeout,
                         write_timeout,
+                        artim_timeout,
+                        state,
                         read_buffer: BytesMut::with_capacity(MAXIMUM_PDU_SIZE as usize),
-                        user_variables
+                        user_variables,
+                        message_id: 1,
+                        message_assembler: MessageAssembler::default(),
+                        pdu_observer,
                     })
                 }
                 Pdu::AssociationRJ(association_rj) => RejectedSnafu { association_rj }.fail(),
@@ -1391,6 +2388,7 @@ pub mod non_blocking {
                             source: AbortRQSource::ServiceUser,
                         },
                     );
+                    pdu_observer.observe(Direction::Sent, &buffer);
                     let _ = timeout(write_timeout, async {
                         socket.write_all(&buffer).await.context(WireSendSnafu)
                     })
@@ -1405,6 +2403,7 @@ pub mod non_blocking {
                             source: AbortRQSource::ServiceUser,
                         },
                     );
+                    pdu_observer.observe(Direction::Sent, &buffer);
                     let _ = timeout(write_timeout, async {
                         socket.write_all(&buffer).await.context(WireSendSnafu)
                     })
@@ -1420,7 +2419,7 @@ pub mod non_blocking {
         pub async fn establish_async<A: tokio::net::ToSocketAddrs>(
             self,
             address: A,
-        ) -> Result<ClientAssociation<tokio::net::TcpStream>> {
+        ) -> Result<AsyncClientAssociation<tokio::net::TcpStream>> {
             self.establish_impl_async(AeAddr::new_socket_addr(address))
                 .await
         }
@@ -1454,7 +2453,7 @@ pub mod non_blocking {
         pub async fn establish_with_async(
             self,
             ae_address: &str,
-        ) -> Result<ClientAssociation<tokio::net::TcpStream>> {
+        ) -> Result<AsyncClientAssociation<tokio::net::TcpStream>> {
             match ae_address.try_into() {
                 Ok(ae_address) => self.establish_impl_async(ae_address).await,
                 Err(_) => {
@@ -1463,13 +2462,239 @@ pub mod non_blocking {
                 }
             }
         }
+
+        /// Initiate the TCP connection to the given address,
+        /// perform a TLS handshake,
+        /// and request a new DICOM association over the secure connection,
+        /// negotiating the presentation contexts in the process.
+        ///
+        /// A TLS configuration must have been set
+        /// via [`tls_config`](Self::tls_config).
+        #[cfg(feature = "async-tls")]
+        pub async fn establish_tls_async<A: tokio::net::ToSocketAddrs>(
+            self,
+            address: A,
+        ) -> Result<AsyncClientAssociation<TlsStream>> {
+            self.establish_tls_impl_async(AeAddr::new_socket_addr(address))
+                .await
+        }
+
+        /// Initiate the TCP connection to the given address,
+        /// perform a TLS handshake,
+        /// and request a new DICOM association over the secure connection,
+        /// negotiating the presentation contexts in the process.
+        ///
+        /// This method allows you to specify the called AE title
+        /// alongside with the socket address.
+        /// See [`establish_with_async`](Self::establish_with_async)
+        /// for more details.
+        #[cfg(feature = "async-tls")]
+        #[allow(unreachable_patterns)]
+        pub async fn establish_with_tls_async(
+            self,
+            ae_address: &str,
+        ) -> Result<AsyncClientAssociation<TlsStream>> {
+            match ae_address.try_into() {
+                Ok(ae_address) => self.establish_tls_impl_async(ae_address).await,
+                Err(_) => {
+                    self.establish_tls_impl_async(AeAddr::new_socket_addr(ae_address))
+                        .await
+                }
+            }
+        }
+
+        /// Request a new DICOM association
+        /// over an already open connection to the association acceptor,
+        /// negotiating the presentation contexts in the process.
+        ///
+        /// The connection can be any bidirectional asynchronous byte stream,
+        /// such as a Unix domain socket or an in-memory
+        /// [duplex stream](tokio::io::duplex).
+        pub async fn establish_stream_async<S>(self, stream: S) -> Result<AsyncClientAssociation<S>>
+        where
+            S: AsyncRead + AsyncWrite + Unpin,
+        {
+            // fail if no presentation contexts were provided: they represent intent,
+            // should not be omitted by the user
+            ensure!(
+                !self.presentation_contexts.is_empty(),
+                MissingAbstractSyntaxSnafu
+            );
+            self.negotiate_async(stream, None).await
+        }
+    }
+
+    /// A DICOM upper level association from the perspective
+    /// of a requesting application entity,
+    /// working over an asynchronous stream.
+    ///
+    /// This is the non-blocking counterpart of
+    /// [`ClientAssociation`](super::ClientAssociation),
+    /// obtained via
+    /// [`establish_async`](ClientAssociationOptions::establish_async).
+    ///
+    /// When the value falls out of scope,
+    /// the program will automatically try to gracefully release the association
+    /// through a standard C-RELEASE message exchange,
+    /// then shut down the underlying connection.
+    ///
+    /// # Migrating from `ClientAssociation<tokio::net::TcpStream>`
+    ///
+    /// Before version 0.9,
+    /// `establish_async` returned a `ClientAssociation<tokio::net::TcpStream>`.
+    /// Associations over TCP are now of type
+    /// `AsyncClientAssociation<tokio::net::TcpStream>`,
+    /// with the same methods,
+    /// and the implementations of [`Release`](super::Release)
+    /// and [`CloseSocket`](super::CloseSocket)
+    /// for Tokio's `TcpStream` are gone:
+    /// call [`release`](Self::release) and await it,
+    /// or let the association fall out of scope.
+    #[derive(Debug)]
+    pub struct AsyncClientAssociation<S>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        /// The presentation contexts accorded with the acceptor application entity,
+        /// without the rejected ones.
+        presentation_contexts: Vec<PresentationContextResult>,
+        /// Details about the accepted presentation contexts
+        negotiated_contexts: Vec<NegotiatedContext>,
+        /// The negotiated asynchronous operations window
+        async_operations_window: AsyncOperationsWindow,
+        /// The message IDs of the requests awaiting a final response
+        outstanding_requests: Vec<u16>,
+        /// DIMSE messages received but not yet retrieved
+        received_messages: VecDeque<Message>,
+        /// The maximum PDU length that this application entity is expecting to receive
+        requestor_max_pdu_length: u32,
+        /// The maximum PDU length that the remote application entity accepts
+        acceptor_max_pdu_length: u32,
+        /// The stream to the other DICOM node
+        socket: S,
+        /// Buffer to assemble PDU before sending it on wire
+        buffer: Vec<u8>,
+        /// whether to receive PDUs in strict mode
+        strict: bool,
+        /// Timeout for individual socket Reads
+        read_timeout: Option<Duration>,
+        /// Timeout for individual socket Writes.
+        write_timeout: Option<Duration>,
+        /// Timeout for the peer to close the connection after the association ends
+        artim_timeout: Duration,
+        /// The upper layer protocol state machine
+        state: StateMachine,
+        /// Buffer to assemble PDU before parsing
+        read_buffer: BytesMut,
+        /// User variables that were taken from the server
+        user_variables: Vec<UserVariableItem>,
+        /// The message ID to use in the next DIMSE request
+        message_id: u16,
+        /// DIMSE message fragments received so far
+        message_assembler: MessageAssembler,
+        /// Observer of the PDUs sent and received
+        pdu_observer: ObserverHandle,
     }
 
-    impl ClientAssociation<tokio::net::TcpStream>
+    impl<S> AsyncClientAssociation<S>
     where
-        ClientAssociation<tokio::net::TcpStream>: Release,
+        S: AsyncRead + AsyncWrite + Unpin,
     {
+        /// Retrieve read timeout for the association
+        pub fn read_timeout(&self) -> Option<Duration> {
+            self.read_timeout
+        }
+
+        /// Retrieve write timeout for the association
+        pub fn write_timeout(&self) -> Option<Duration> {
+            self.write_timeout
+        }
+
+        /// Retrieve the current state of the upper layer protocol machine.
+        pub fn state(&self) -> State {
+            self.state.state()
+        }
+
+        /// Retrieve the list of negotiated presentation contexts.
+        pub fn presentation_contexts(&self) -> &[PresentationContextResult] {
+            &self.presentation_contexts
+        }
+
+        /// Obtain the abstract syntax of an accepted presentation context.
+        pub fn abstract_syntax(&self, presentation_context_id: u8) -> Option<&str> {
+            self.negotiated_contexts
+                .iter()
+                .find(|pc| pc.id == presentation_context_id)
+                .map(|pc| pc.abstract_syntax.as_str())
+        }
+
+        /// Obtain the roles which this node may take
+        /// in an accepted presentation context.
+        ///
+        /// When this node is allowed to take the SCP role,
+        /// it may receive requests from the association acceptor
+        /// through that presentation context.
+        pub fn roles(&self, presentation_context_id: u8) -> Option<Roles> {
+            self.negotiated_contexts
+                .iter()
+                .find(|pc| pc.id == presentation_context_id)
+                .map(|pc| pc.roles)
+        }
+
+        /// Retrieve the asynchronous operations window of the association,
+        /// from the perspective of this node.
+        pub fn async_operations_window(&self) -> AsyncOperationsWindow {
+            self.async_operations_window
+        }
+
+        /// Retrieve the message IDs of the requests sent via `send_request`
+        /// which are still awaiting a final response.
+        pub fn outstanding_requests(&self) -> &[u16] {
+            &self.outstanding_requests
+        }
+
+        /// Retrieve the maximum PDU length
+        /// admitted by the association acceptor.
+        pub fn acceptor_max_pdu_length(&self) -> u32 {
+            self.acceptor_max_pdu_length
+        }
+
+        /// Retrieve the maximum PDU length
+        /// that this application entity is expecting to receive.
+        pub fn requestor_max_pdu_length(&self) -> u32 {
+            self.requestor_max_pdu_length
+        }
+
+        /// Retrieve the user variables that were taken from the server.
+        pub fn user_variables(&self) -> &[UserVariableItem] {
+            &self.user_variables
+        }
+
+        /// Retrieve the server response to the user identity,
+        /// if the association acceptor sent one.
+        ///
+        /// The acceptor only responds if a positive response was requested
+        /// and the identity was verified.
+        pub fn user_identity_server_response(&self) -> Option<&[u8]> {
+            find_user_identity_server_response(&self.user_variables)
+        }
+
+        /// Obtain a new message ID for a DIMSE request
+        /// to be sent through this association.
+        ///
+        /// Message IDs start at 1 and are incremented on each call.
+        pub fn next_message_id(&mut self) -> u16 {
+            let message_id = self.message_id;
+            self.message_id = self.message_id.wrapping_add(1);
+            message_id
+        }
+
         /// Send a PDU message to the other intervenient.
+        ///
+        /// Fails without sending anything
+        /// if the PDU is not allowed in the current state of the association.
+        /// Sending an A-ABORT waits up to the ARTIM timeout
+        /// for the acceptor to close the connection.
         pub async fn send(&mut self, msg: &Pdu) -> Result<()> {
             self.buffer.clear();
             write_pdu(&mut self.buffer, msg).context(SendRequestSnafu)?;
@@ -1479,17 +2704,98 @@ pub mod non_blocking {
                 }
                 .fail();
             }
+            if let Some(event) = Event::sent(msg) {
+                self.transition(event)?;
+            }
+            self.pdu_observer.observe(Direction::Sent, &self.buffer);
             timeout(self.write_timeout, async {
                 self.socket
                     .write_all(&self.buffer)
                     .await
                     .context(WireSendSnafu)
             })
-            .await
+            .await?;
+            if self.state.state() == State::AwaitingTransportClose {
+                self.await_transport_close().await;
+            }
+            Ok(())
         }
 
         /// Read a PDU message from the other intervenient.
+        ///
+        /// A PDU which is not expected in the current state of the association
+        /// is answered with an A-ABORT, resulting in an error.
         pub async fn receive(&mut self) -> Result<Pdu> {
+            let pdu = match self.receive_pdu().await {
+                Ok(pdu) => pdu,
+                Err(e @ Error::ConnectionClosed) => {
+                    self.state.handle(Event::TransportClosed);
+                    let _ = self.socket.shutdown().await;
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            };
+            let event = Event::received(&pdu);
+            let action = self.transition(event)?;
+            if let Some(abort) = action.abort_pdu(event) {
+                self.buffer.clear();
+                write_pdu(&mut self.buffer, &abort).context(SendRequestSnafu)?;
+                self.pdu_observer.observe(Direction::Sent, &self.buffer);
+                let _ = timeout(self.write_timeout, async {
+                    self.socket
+                        .write_all(&self.buffer)
+                        .await
+                        .context(WireSendSnafu)
+                })
+                .await;
+                self.await_transport_close().await;
+                return match pdu {
+                    pdu @ Pdu::Unknown { .. } => UnknownResponseSnafu { pdu }.fail(),
+                    pdu => UnexpectedResponseSnafu { pdu }.fail(),
+                };
+            }
+            if action.closes_transport() {
+                let _ = self.socket.shutdown().await;
+            }
+            Ok(pdu)
+        }
+
+        /// Feed an event to the upper layer state machine,
+        /// failing if the event is not allowed in the current state.
+        fn transition(&mut self, event: Event) -> Result<Action> {
+            let state = self.state.state();
+            match self.state.handle(event) {
+                Some(action) => Ok(action),
+                None => InvalidStateSnafu { state, event }.fail(),
+            }
+        }
+
+        /// Wait for the acceptor to close the connection
+        /// for up to the ARTIM timeout, then close it.
+        async fn await_transport_close(&mut self) {
+            let mut buf = [0; 1024];
+            let socket = &mut self.socket;
+            let closed = tokio::time::timeout(self.artim_timeout, async {
+                loop {
+                    match socket.read(&mut buf).await {
+                        Ok(0) => break true,
+                        Ok(_) => continue,
+                        Err(_) => break false,
+                    }
+                }
+            })
+            .await
+            .unwrap_or(false);
+            if closed {
+                self.state.handle(Event::TransportClosed);
+            } else {
+                self.state.handle(Event::ArtimExpired);
+            }
+            let _ = self.socket.shutdown().await;
+        }
+
+        /// Read the next PDU from the wire.
+        async fn receive_pdu(&mut self) -> Result<Pdu> {
             timeout(self.read_timeout, async {
                 loop {
                     let mut buf = Cursor::new(&self.read_buffer[..]);
@@ -1497,7 +2803,10 @@ pub mod non_blocking {
                         .context(ReceiveResponseSnafu)?
                     {
                         Some(pdu) => {
-                            self.read_buffer.advance(buf.position() as usize);
+                            let len = buf.position() as usize;
+                            self.pdu_observer
+                                .observe(Direction::Received, &self.read_buffer[..len]);
+                            self.read_buffer.advance(len);
                             return Ok(pdu);
                         }
                         None => {
@@ -1518,18 +2827,43 @@ pub mod non_blocking {
         }
 
         /// Gracefully terminate the association by exchanging release messages
-        /// and then shutting down the TCP connection.
-        pub async fn release(mut self) -> Result<()> {
+        /// and then shutting down the connection.
+        ///
+        /// DIMSE messages not yet retrieved
+        /// or still in transit from the acceptor are discarded;
+        /// use [`release_with`](Self::release_with) to receive them.
+        /// A release collision,
+        /// in which the acceptor requests a release at the same time,
+        /// is resolved as specified in the standard.
+        pub async fn release(self) -> Result<()> {
+            self.release_with(|_| {}).await
+        }
+
+        /// Gracefully terminate the association by exchanging release messages
+        /// and then shutting down the connection,
+        /// passing each DIMSE message not yet retrieved
+        /// or received from the acceptor while awaiting the release response
+        /// to `on_message`.
+        ///
+        /// These messages can no longer be answered on this association,
+        /// since no P-Data may be sent after requesting a release.
+        pub async fn release_with<F>(mut self, mut on_message: F) -> Result<()>
+        where
+            F: FnMut(Message) + Send,
+        {
             timeout(self.write_timeout, async {
-                let out = self.release_impl().await;
+                let out = self.release_impl(&mut on_message).await;
                 let _ = self.socket.shutdown().await;
                 out
             })
             .await
         }
 
-        /// Send an abort message and shut down the TCP connection,
+        /// Send an abort message and shut down the connection,
         /// terminating the association.
+        ///
+        /// The acceptor is given up to the ARTIM timeout
+        /// to close the connection first.
         pub async fn abort(mut self) -> Result<()> {
             timeout(self.write_timeout, async {
                 let pdu = Pdu::AbortRQ {
@@ -1550,12 +2884,13 @@ pub mod non_blocking {
         pub async fn send_pdata(
             &mut self,
             presentation_context_id: u8,
-        ) -> AsyncPDataWriter<&mut tokio::net::TcpStream> {
+        ) -> AsyncPDataWriter<&mut S> {
             AsyncPDataWriter::new(
                 &mut self.socket,
                 presentation_context_id,
                 self.acceptor_max_pdu_length,
             )
+            .with_observer(self.pdu_observer.clone())
         }
 
         /// Prepare a P-Data reader for receiving
@@ -1563,13 +2898,135 @@ pub mod non_blocking {
         ///
         /// Returns a reader which automatically
         /// receives more data PDUs once the bytes collected are consumed.
-        #[cfg(feature = "async")]
-        pub fn receive_pdata(&mut self) -> PDataReader<&mut tokio::net::TcpStream> {
+        pub fn receive_pdata(&mut self) -> PDataReader<&mut S> {
             PDataReader::new(
                 &mut self.socket,
                 self.requestor_max_pdu_length,
                 &mut self.read_buffer,
             )
+            .with_observer(self.pdu_observer.clone())
+        }
+
+        /// Send a DIMSE message to the association acceptor.
+        ///
+        /// The command is encoded in _Implicit VR Little Endian_,
+        /// whereas `data` must already be encoded
+        /// in the transfer syntax of the given presentation context.
+        /// The data set is split into multiple PDUs if necessary.
+        pub async fn send_message(
+            &mut self,
+            presentation_context_id: u8,
+            command: &Command,
+            data: Option<&[u8]>,
+        ) -> Result<()> {
+            let (pdu, remaining) = command_pdu(
+                presentation_context_id,
+                command,
+                data,
+                self.acceptor_max_pdu_length,
+            );
+            self.send(&pdu).await?;
+            if let Some(data) = remaining {
+                let write_timeout = self.write_timeout;
+                let mut writer = self.send_pdata(presentation_context_id).await;
+                timeout(write_timeout, async {
+                    writer.write_all(data).await.context(WireSendSnafu)?;
+                    writer.finish().await.context(WireSendSnafu)
+                })
+                .await?;
+            }
+            Ok(())
+        }
+
+        /// Send a DIMSE request to the association acceptor
+        /// without waiting for its response,
+        /// so that multiple requests may be outstanding at the same time.
+        ///
+        /// If the number of outstanding requests has reached
+        /// the maximum number of operations invoked
+        /// in the negotiated [asynchronous operations window](Self::async_operations_window),
+        /// messages are received and kept for later retrieval
+        /// until a request is complete.
+        /// Responses can then be retrieved
+        /// via [`receive_response`](Self::receive_response)
+        /// or [`receive_message`](Self::receive_message).
+        pub async fn send_request(
+            &mut self,
+            presentation_context_id: u8,
+            command: &Command,
+            data: Option<&[u8]>,
+        ) -> Result<()> {
+            if let Some(message_id) = command.message_id() {
+                let max_operations = self.async_operations_window.max_operations_invoked as usize;
+                while max_operations != 0 && self.outstanding_requests.len() >= max_operations {
+                    let message = self.read_message().await?;
+                    self.received_messages.push_back(message);
+                }
+                self.send_message(presentation_context_id, command, data)
+                    .await?;
+                self.outstanding_requests.push(message_id);
+                Ok(())
+            } else {
+                self.send_message(presentation_context_id, command, data)
+                    .await
+            }
+        }
+
+        /// Receive the next DIMSE message from the association acceptor,
+        /// comprising the command and the data set which follows it, if any.
+        ///
+        /// Messages already received while waiting for other responses
+        /// are retrieved first.
+        /// If the acceptor asks to release the association instead,
+        /// the release is confirmed and [`Error::Released`] is returned.
+        /// Receiving any other PDU results in an error.
+        pub async fn receive_message(&mut self) -> Result<Message> {
+            if let Some(message) = self.received_messages.pop_front() {
+                return Ok(message);
+            }
+            self.read_message().await
+        }
+
+        /// Receive the next response to the request with the given message ID.
+        ///
+        /// Other messages received in the meantime
+        /// are kept for later retrieval.
+        pub async fn receive_response(&mut self, message_id: u16) -> Result<Message> {
+            loop {
+                if let Some(i) = self
+                    .received_messages
+                    .iter()
+                    .position(|message| is_response_to(message, message_id))
+                {
+                    return Ok(self.received_messages.remove(i).unwrap());
+                }
+                let message = self.read_message().await?;
+                self.received_messages.push_back(message);
+            }
+        }
+
+        /// Read the next DIMSE message from the wire,
+        /// keeping track of the requests which are complete.
+        async fn read_message(&mut self) -> Result<Message> {
+            loop {
+                if let Some(message) = self
+                    .message_assembler
+                    .next_message()
+                    .context(ReadMessageSnafu)?
+                {
+                    track_response(&mut self.outstanding_requests, &message);
+                    return Ok(message);
+                }
+                match self.receive().await? {
+                    Pdu::PData { data } => self.message_assembler.push(data),
+                    Pdu::ReleaseRQ => {
+                        self.send(&Pdu::ReleaseRP).await?;
+                        return ReleasedSnafu.fail();
+                    }
+                    pdu @ Pdu::Unknown { .. } => return UnknownResponseSnafu { pdu }.fail(),
+                    pdu => return UnexpectedResponseSnafu { pdu }.fail(),
+                }
+            }
         }
 
         /// Release implementation function,
@@ -1577,37 +3034,37 @@ pub mod non_blocking {
         /// This is in a separate private function because
         /// terminating a connection should still close the connection
         /// if the exchange fails.
-        async fn release_impl(&mut self) -> Result<()> {
+        async fn release_impl(
+            &mut self,
+            on_message: &mut (dyn FnMut(Message) + Send),
+        ) -> Result<()> {
+            self.received_messages.drain(..).for_each(&mut *on_message);
             let pdu = Pdu::ReleaseRQ;
             self.send(&pdu).await?;
-            use tokio::io::AsyncReadExt;
-            let mut read_buffer = BytesMut::with_capacity(MAXIMUM_PDU_SIZE as usize);
 
-            let pdu = loop {
-                if let Ok(Some(pdu)) = read_pdu(&mut read_buffer, MAXIMUM_PDU_SIZE, self.strict) {
-                    break pdu;
+            loop {
+                match self.receive().await? {
+                    Pdu::ReleaseRP => return Ok(()),
+                    // data still in transit from the acceptor
+                    Pdu::PData { data } => {
+                        self.message_assembler.push(data);
+                        while let Some(message) = self
+                            .message_assembler
+                            .next_message()
+                            .context(ReadMessageSnafu)?
+                        {
+                            on_message(message);
+                        }
+                    }
+                    // release collision: the requestor responds first
+                    Pdu::ReleaseRQ => self.send(&Pdu::ReleaseRP).await?,
+                    pdu @ Pdu::Unknown { .. } => return UnknownResponseSnafu { pdu }.fail(),
+                    pdu => return UnexpectedResponseSnafu { pdu }.fail(),
                 }
-                let recv = self
-                    .socket
-                    .read_buf(&mut read_buffer)
-                    .await
-                    .context(ReadPduSnafu)
-                    .context(ReceiveSnafu)?;
-                ensure!(recv > 0, ConnectionClosedSnafu);
-            };
-            match pdu {
-                Pdu::ReleaseRP => {}
-                pdu @ Pdu::AbortRQ { .. }
-                | pdu @ Pdu::AssociationAC { .. }
-                | pdu @ Pdu::AssociationRJ { .. }
-                | pdu @ Pdu::AssociationRQ { .. }
-                | pdu @ Pdu::PData { .. }
-                | pdu @ Pdu::ReleaseRQ { .. } => return UnexpectedResponseSnafu { pdu }.fail(),
-                pdu @ Pdu::Unknown { .. } => return UnknownResponseSnafu { pdu }.fail(),
             }
-            Ok(())
         }
-        /// Obtain access to the inner TCP stream
+
+        /// Obtain access to the inner stream
         /// connected to the association acceptor.
         ///
         /// This can be used to send the PDU in semantic fragments of the message,
@@ -1616,23 +3073,25 @@ pub mod non_blocking {
         /// **Note:** reading and writing should be done with care
         /// to avoid inconsistencies in the association state.
         /// Do not call `send` and `receive` while not in a PDU boundary.
-        pub fn inner_stream(&mut self) -> &mut tokio::net::TcpStream {
+        pub fn inner_stream(&mut self) -> &mut S {
             &mut self.socket
         }
     }
 
-    impl Release for ClientAssociation<tokio::net::TcpStream> {
-        fn release(&mut self) -> super::Result<()> {
-            tokio::task::block_in_place(move || {
-                tokio::runtime::Handle::current().block_on(async move { self.release_impl().await })
-            })
-        }
-    }
-    /// Automatically release the association and shut down the connection.
-    impl CloseSocket for tokio::net::TcpStream {
-        fn close(&mut self) -> std::io::Result<()> {
+    /// Automatically release the association if it is still established,
+    /// and shut down the connection.
+    impl<S> Drop for AsyncClientAssociation<S>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        fn drop(&mut self) {
             tokio::task::block_in_place(move || {
-                tokio::runtime::Handle::current().block_on(async move { self.shutdown().await })
+                tokio::runtime::Handle::current().block_on(async move {
+                    if self.state.state() == State::Established {
+                        let _ = self.release_impl(&mut |_| {}).await;
+                    }
+                    let _ = self.socket.shutdown().await;
+                })
             })
         }
     }
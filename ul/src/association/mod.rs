@@ -14,17 +14,187 @@
 //! a newly created [TCP stream][1] can be passed to
 //! a previously prepared [`ServerAssociationOptions`].
 //!
+//! With the `tls` feature,
+//! associations can also be established over a secure transport connection
+//! (see `establish_tls` in both option types),
+//! using the [rustls](https://crates.io/crates/rustls) TLS implementation.
+//!
+//! Associations are not tied to TCP:
+//! `establish_stream` (and `establish_stream_async`) in both option types
+//! negotiate an association over any other connected byte stream,
+//! such as a Unix domain socket or an in-memory pipe.
+//! Blocking streams need to implement [`CloseSocket`](client::CloseSocket).
+//!
+//! The PDUs exchanged in an association can be inspected or recorded
+//! through the [`observer`] module.
+//!
+//! Known peers can be kept in a [`PeerRegistry`](peers::PeerRegistry),
+//! which controls who may request associations
+//! and resolves AE titles to network addresses.
+//!
+//! Client associations can be kept open and reused
+//! across requests to the same destination
+//! with an [`AssociationPool`](pool::AssociationPool).
 //!
 //! [1]: std::net::TcpStream
 pub mod client;
+pub mod observer;
+pub mod peers;
+pub mod pool;
 pub mod server;
+pub mod state;
 
 mod uid;
 
 pub(crate) mod pdata;
 
+#[cfg(feature = "async")]
+pub use client::non_blocking::AsyncClientAssociation;
 pub use client::{ClientAssociation, ClientAssociationOptions};
 #[cfg(feature = "async")]
 pub use pdata::non_blocking::AsyncPDataWriter;
 pub use pdata::{PDataReader, PDataWriter};
+#[cfg(feature = "async")]
+pub use server::non_blocking::AsyncServerAssociation;
 pub use server::{ServerAssociation, ServerAssociationOptions};
+
+use crate::pdu::{AsyncOperationsWindow, RoleSelection, UserIdentity, UserVariableItem};
+
+/// The roles which the association requestor may take
+/// for the abstract syntax of a presentation context,
+/// as negotiated via SCP/SCU role selection (PS3.7 D.3.3.4).
+///
+/// Unless negotiated otherwise,
+/// the association requestor is the SCU
+/// and the association acceptor is the SCP.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Roles {
+    /// Whether the association requestor may act as an SCU,
+    /// and the acceptor as an SCP
+    pub scu: bool,
+    /// Whether the association requestor may act as an SCP,
+    /// and the acceptor as an SCU
+    pub scp: bool,
+}
+
+impl Default for Roles {
+    fn default() -> Self {
+        Roles {
+            scu: true,
+            scp: false,
+        }
+    }
+}
+
+/// Details about an accepted presentation context
+/// which are not conveyed by the A-ASSOCIATE-AC PDU.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct NegotiatedContext {
+    pub id: u8,
+    pub abstract_syntax: String,
+    pub roles: Roles,
+}
+
+/// Find the role selection sub-item for the given SOP class
+/// in a list of user variables.
+pub(crate) fn find_role_selection<'a>(
+    user_variables: &'a [UserVariableItem],
+    sop_class_uid: &str,
+) -> Option<&'a RoleSelection> {
+    user_variables.iter().find_map(|item| match item {
+        UserVariableItem::RoleSelectionSubItem(role_selection)
+            if role_selection.sop_class_uid == sop_class_uid =>
+        {
+            Some(role_selection)
+        }
+        _ => None,
+    })
+}
+
+/// Find the asynchronous operations window sub-item
+/// in a list of user variables.
+pub(crate) fn find_async_operations_window(
+    user_variables: &[UserVariableItem],
+) -> Option<AsyncOperationsWindow> {
+    user_variables.iter().find_map(|item| match item {
+        UserVariableItem::AsyncOperationsWindowSubItem(window) => Some(*window),
+        _ => None,
+    })
+}
+
+/// Find the user identity item in a list of user variables.
+pub(crate) fn find_user_identity(user_variables: &[UserVariableItem]) -> Option<&UserIdentity> {
+    user_variables.iter().find_map(|item| match item {
+        UserVariableItem::UserIdentityItem(user_identity) => Some(user_identity),
+        _ => None,
+    })
+}
+
+/// Find the user identity server response in a list of user variables.
+pub(crate) fn find_user_identity_server_response(
+    user_variables: &[UserVariableItem],
+) -> Option<&[u8]> {
+    user_variables.iter().find_map(|item| match item {
+        UserVariableItem::UserIdentityServerResponseItem(response) => Some(response.as_slice()),
+        _ => None,
+    })
+}
+
+/// Narrow down a proposed asynchronous operations window
+/// so that it does not exceed the given limits,
+/// where 0 stands for an unlimited number of operations.
+pub(crate) fn restrict_async_operations_window(
+    proposed: AsyncOperationsWindow,
+    limits: AsyncOperationsWindow,
+) -> AsyncOperationsWindow {
+    fn restrict(proposed: u16, limit: u16) -> u16 {
+        match (proposed, limit) {
+            (0, limit) => limit,
+            (proposed, 0) => proposed,
+            (proposed, limit) => proposed.min(limit),
+        }
+    }
+
+    AsyncOperationsWindow {
+        max_operations_invoked: restrict(
+            proposed.max_operations_invoked,
+            limits.max_operations_invoked,
+        ),
+        max_operations_performed: restrict(
+            proposed.max_operations_performed,
+            limits.max_operations_performed,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::restrict_async_operations_window;
+    use crate::pdu::AsyncOperationsWindow;
+
+    #[test]
+    fn restrict_window() {
+        let window = |max_operations_invoked, max_operations_performed| AsyncOperationsWindow {
+            max_operations_invoked,
+            max_operations_performed,
+        };
+
+        assert_eq!(
+            restrict_async_operations_window(window(8, 1), window(4, 4)),
+            window(4, 1)
+        );
+        // 0 means unlimited
+        assert_eq!(
+            restrict_async_operations_window(window(0, 0), window(16, 1)),
+            window(16, 1)
+        );
+        assert_eq!(
+            restrict_async_operations_window(window(10, 2), window(0, 0)),
+            window(10, 2)
+        );
+        assert_eq!(
+            restrict_async_operations_window(window(0, 3), window(0, 5)),
+            window(0, 3)
+        );
+    }
+}
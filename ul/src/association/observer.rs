@@ -0,0 +1,403 @@
+//! Observation of the PDUs exchanged in an association.
+//!
+//! A [`PduObserver`] can be attached to the association options
+//! on either side of an association
+//! (see `pdu_observer` in [`ClientAssociationOptions`](super::ClientAssociationOptions)
+//! and [`ServerAssociationOptions`](super::ServerAssociationOptions)).
+//! It is then called for every PDU sent and received,
+//! including those of association negotiation
+//! and the P-Data PDUs produced by P-Data readers and writers,
+//! with the time of the event and the PDU as encoded on the wire.
+//!
+//! [`CaptureWriter`] is an observer which records all PDUs to a capture file,
+//! which can be read back with [`CaptureReader`].
+//! Since the raw bytes of each PDU are kept,
+//! the PDUs sent by one of the nodes can be replayed to the other
+//! in order to reproduce an exchange.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use dicom_ul::association::ClientAssociationOptions;
+//! # use dicom_ul::association::observer::CaptureWriter;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let capture = Arc::new(CaptureWriter::create("association.pducap")?);
+//! let association = ClientAssociationOptions::new()
+//!     .with_abstract_syntax("1.2.840.10008.1.1")
+//!     .pdu_observer(capture)
+//!     .establish("129.168.0.5:104")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # Capture file format
+//!
+//! A capture file starts with the 8-byte magic code `DCMULCAP`,
+//! followed by the format version as a 16-bit big endian integer
+//! (currently 1).
+//! Then, for each PDU:
+//!
+//! - the direction of the PDU, 1 byte (0 if sent, 1 if received);
+//! - the time of the event in microseconds since the Unix epoch,
+//!   as a 64-bit big endian integer;
+//! - the length of the PDU in bytes, as a 32-bit big endian integer;
+//! - the PDU itself, including its header.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{BufWriter, ErrorKind, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::warn;
+
+use crate::pdu::{read_pdu, NoPduAvailableSnafu, Pdu, ReadError, MAXIMUM_PDU_SIZE};
+
+/// The magic code at the beginning of a capture file.
+const CAPTURE_MAGIC: &[u8; 8] = b"DCMULCAP";
+
+/// The version of the capture file format.
+const CAPTURE_VERSION: u16 = 1;
+
+/// Whether a PDU was sent or received by the local node.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// The PDU was sent to the peer
+    Sent,
+    /// The PDU was received from the peer
+    Received,
+}
+
+/// A PDU which was sent or received in an association.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PduRecord<'a> {
+    /// whether the PDU was sent or received
+    pub direction: Direction,
+    /// when the PDU was sent or received
+    pub timestamp: SystemTime,
+    /// the PDU as encoded on the wire, including its header
+    pub bytes: &'a [u8],
+}
+
+impl<'a> PduRecord<'a> {
+    /// Create a record of a PDU sent or received just now.
+    pub fn new(direction: Direction, bytes: &'a [u8]) -> Self {
+        PduRecord {
+            direction,
+            timestamp: SystemTime::now(),
+            bytes,
+        }
+    }
+
+    /// Decode the PDU.
+    pub fn pdu(&self) -> Result<Pdu, ReadError> {
+        decode(self.bytes)
+    }
+}
+
+/// An observer of the PDUs sent and received in an association.
+///
+/// Observers are shared between associations
+/// and may be called from multiple threads.
+/// Any closure taking a [`PduRecord`] is also an observer.
+pub trait PduObserver: Send + Sync {
+    /// Called for every PDU sent or received.
+    fn observe(&self, record: &PduRecord<'_>);
+}
+
+impl<F> PduObserver for F
+where
+    F: Fn(&PduRecord<'_>) + Send + Sync,
+{
+    fn observe(&self, record: &PduRecord<'_>) {
+        self(record)
+    }
+}
+
+/// The PDU observer of an association, if any.
+#[derive(Clone, Default)]
+pub(crate) struct ObserverHandle(Option<Arc<dyn PduObserver>>);
+
+impl ObserverHandle {
+    pub(crate) fn new(observer: Arc<dyn PduObserver>) -> Self {
+        ObserverHandle(Some(observer))
+    }
+
+    /// Notify the observer of a PDU sent or received just now.
+    pub(crate) fn observe(&self, direction: Direction, bytes: &[u8]) {
+        if let Some(observer) = &self.0 {
+            observer.observe(&PduRecord::new(direction, bytes));
+        }
+    }
+}
+
+impl fmt::Debug for ObserverHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("Some(PduObserver)"),
+            None => f.write_str("None"),
+        }
+    }
+}
+
+/// A PDU observer which writes every PDU to a capture file.
+///
+/// Each PDU is written and flushed as soon as it is observed,
+/// so that the capture remains usable
+/// even if the application terminates abruptly.
+/// Failures to write are logged and otherwise ignored.
+#[derive(Debug)]
+pub struct CaptureWriter<W> {
+    inner: Mutex<W>,
+}
+
+impl CaptureWriter<BufWriter<File>> {
+    /// Create a new capture file at the given path.
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        CaptureWriter::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W> CaptureWriter<W>
+where
+    W: Write,
+{
+    /// Start a capture in the given writer.
+    pub fn new(mut writer: W) -> std::io::Result<Self> {
+        writer.write_all(CAPTURE_MAGIC)?;
+        writer.write_all(&CAPTURE_VERSION.to_be_bytes())?;
+        writer.flush()?;
+        Ok(CaptureWriter {
+            inner: Mutex::new(writer),
+        })
+    }
+
+    /// Retrieve the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write_record(&self, record: &PduRecord<'_>) -> std::io::Result<()> {
+        let timestamp = record
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let mut writer = self
+            .inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        writer.write_all(&[match record.direction {
+            Direction::Sent => 0,
+            Direction::Received => 1,
+        }])?;
+        writer.write_all(&timestamp.to_be_bytes())?;
+        writer.write_all(&(record.bytes.len() as u32).to_be_bytes())?;
+        writer.write_all(record.bytes)?;
+        writer.flush()
+    }
+}
+
+impl<W> PduObserver for CaptureWriter<W>
+where
+    W: Write + Send,
+{
+    fn observe(&self, record: &PduRecord<'_>) {
+        if let Err(e) = self.write_record(record) {
+            warn!("Failed to write PDU to capture: {}", e);
+        }
+    }
+}
+
+/// A PDU read from a capture file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedPdu {
+    /// whether the PDU was sent or received by the node which captured it
+    pub direction: Direction,
+    /// when the PDU was sent or received
+    pub timestamp: SystemTime,
+    /// the PDU as encoded on the wire, including its header
+    pub bytes: Vec<u8>,
+}
+
+impl CapturedPdu {
+    /// Decode the PDU.
+    pub fn pdu(&self) -> Result<Pdu, ReadError> {
+        decode(&self.bytes)
+    }
+}
+
+/// A reader of capture files produced by a [`CaptureWriter`].
+///
+/// Iterating over this reader
+/// yields the PDUs in the order in which they were observed.
+///
+/// ```no_run
+/// # use dicom_ul::association::observer::{CaptureReader, Direction};
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// for record in CaptureReader::open("association.pducap")? {
+///     let record = record?;
+///     let arrow = match record.direction {
+///         Direction::Sent => "-->",
+///         Direction::Received => "<--",
+///     };
+///     println!("{} {}", arrow, record.pdu()?.short_description());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct CaptureReader<R> {
+    reader: R,
+}
+
+impl CaptureReader<std::io::BufReader<File>> {
+    /// Open a capture file at the given path.
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        CaptureReader::new(std::io::BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R> CaptureReader<R>
+where
+    R: Read,
+{
+    /// Start reading a capture from the given reader,
+    /// checking the capture file header.
+    pub fn new(mut reader: R) -> std::io::Result<Self> {
+        let mut header = [0; 10];
+        reader.read_exact(&mut header)?;
+        if &header[..8] != CAPTURE_MAGIC {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "not a PDU capture file",
+            ));
+        }
+        let version = u16::from_be_bytes([header[8], header[9]]);
+        if version != CAPTURE_VERSION {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported PDU capture version {}", version),
+            ));
+        }
+        Ok(CaptureReader { reader })
+    }
+
+    fn read_record(&mut self) -> std::io::Result<Option<CapturedPdu>> {
+        let mut direction = [0; 1];
+        match self.reader.read_exact(&mut direction) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let direction = match direction[0] {
+            0 => Direction::Sent,
+            1 => Direction::Received,
+            value => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid PDU direction {}", value),
+                ))
+            }
+        };
+        let mut timestamp = [0; 8];
+        self.reader.read_exact(&mut timestamp)?;
+        let timestamp = UNIX_EPOCH + Duration::from_micros(u64::from_be_bytes(timestamp));
+        let mut len = [0; 4];
+        self.reader.read_exact(&mut len)?;
+        let mut bytes = Vec::new();
+        (&mut self.reader)
+            .take(u32::from_be_bytes(len) as u64)
+            .read_to_end(&mut bytes)?;
+        if bytes.len() != u32::from_be_bytes(len) as usize {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        Ok(Some(CapturedPdu {
+            direction,
+            timestamp,
+            bytes,
+        }))
+    }
+}
+
+impl<R> Iterator for CaptureReader<R>
+where
+    R: Read,
+{
+    type Item = std::io::Result<CapturedPdu>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+fn decode(mut bytes: &[u8]) -> Result<Pdu, ReadError> {
+    // captured PDUs were already accepted once,
+    // so decode them leniently
+    read_pdu(&mut bytes, MAXIMUM_PDU_SIZE, false)
+        .transpose()
+        .unwrap_or_else(|| NoPduAvailableSnafu.fail())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write_pdu;
+
+    #[test]
+    fn capture_roundtrip() {
+        let mut release_rq = Vec::new();
+        write_pdu(&mut release_rq, &Pdu::ReleaseRQ).unwrap();
+        let mut release_rp = Vec::new();
+        write_pdu(&mut release_rp, &Pdu::ReleaseRP).unwrap();
+
+        let capture = CaptureWriter::new(Vec::new()).unwrap();
+        let sent = PduRecord::new(Direction::Sent, &release_rq);
+        let received = PduRecord::new(Direction::Received, &release_rp);
+        capture.observe(&sent);
+        capture.observe(&received);
+        let data = capture.into_inner();
+
+        let records = CaptureReader::new(&data[..])
+            .unwrap()
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, Direction::Sent);
+        assert_eq!(records[0].bytes, release_rq);
+        assert_eq!(records[0].pdu().unwrap(), Pdu::ReleaseRQ);
+        assert_eq!(records[1].direction, Direction::Received);
+        assert_eq!(records[1].pdu().unwrap(), Pdu::ReleaseRP);
+        // timestamps are kept with microsecond precision
+        let elapsed = sent.timestamp.duration_since(records[0].timestamp).unwrap();
+        assert!(elapsed < Duration::from_micros(1));
+    }
+
+    #[test]
+    fn capture_reader_rejects_other_files() {
+        let err = CaptureReader::new(&b"DICM\0\0\0\0\0\0"[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn capture_reader_rejects_truncated_records() {
+        let capture = CaptureWriter::new(Vec::new()).unwrap();
+        capture.observe(&PduRecord::new(
+            Direction::Sent,
+            &[0x05, 0, 0, 0, 0, 4, 0, 0, 0, 0],
+        ));
+        let mut data = capture.into_inner();
+        data.truncate(data.len() - 2);
+
+        let mut reader = CaptureReader::new(&data[..]).unwrap();
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -8,6 +8,8 @@ use tracing::warn;
 
 use crate::{pdu::PDU_HEADER_SIZE, read_pdu, Pdu};
 
+use super::observer::{Direction, ObserverHandle};
+
 /// Set up the P-Data PDU header for sending.
 fn setup_pdata_header(buffer: &mut [u8], is_last: bool) {
     let data_len = (buffer.len() - 12) as u32;
@@ -82,6 +84,7 @@ pub struct PDataWriter<W: Write> {
     buffer: Vec<u8>,
     stream: W,
     max_data_len: u32,
+    observer: ObserverHandle,
 }
 
 impl<W> PDataWriter<W>
@@ -119,9 +122,16 @@ where
             stream,
             max_data_len: max_data_length,
             buffer,
+            observer: ObserverHandle::default(),
         }
     }
 
+    /// Notify the given observer of each PDU sent.
+    pub(crate) fn with_observer(mut self, observer: ObserverHandle) -> Self {
+        self.observer = observer;
+        self
+    }
+
     /// Declare to have finished sending P-Data fragments,
     /// thus emitting the last P-Data fragment PDU.
     ///
@@ -135,6 +145,7 @@ where
         if !self.buffer.is_empty() {
             // send last PDU
             setup_pdata_header(&mut self.buffer, true);
+            self.observer.observe(Direction::Sent, &self.buffer);
             self.stream.write_all(&self.buffer[..])?;
             // clear buffer so that subsequent calls to `finish_impl`
             // do not send any more PDUs
@@ -151,6 +162,7 @@ where
         debug_assert!(self.buffer.len() >= 12);
         // send PDU now
         setup_pdata_header(&mut self.buffer, false);
+        self.observer.observe(Direction::Sent, &self.buffer);
         self.stream.write_all(&self.buffer)?;
 
         // back to just the header
@@ -242,6 +254,7 @@ pub struct PDataReader<'a, R> {
     max_data_length: u32,
     last_pdu: bool,
     read_buffer: &'a mut BytesMut,
+    observer: ObserverHandle,
 }
 
 impl<'a, R> PDataReader<'a, R> {
@@ -253,9 +266,30 @@ impl<'a, R> PDataReader<'a, R> {
             max_data_length,
             last_pdu: false,
             read_buffer: remaining,
+            observer: ObserverHandle::default(),
         }
     }
 
+    /// Notify the given observer of each PDU received.
+    pub(crate) fn with_observer(mut self, observer: ObserverHandle) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    /// Start with data set bytes which were already received,
+    /// `is_last` indicating whether they include the last fragment.
+    pub(crate) fn with_received(
+        mut self,
+        presentation_context_id: u8,
+        data: Vec<u8>,
+        is_last: bool,
+    ) -> Self {
+        self.presentation_context_id = Some(presentation_context_id);
+        self.buffer.extend(data);
+        self.last_pdu = is_last;
+        self
+    }
+
     /// Declare no intention to read more PDUs from the remote node.
     ///
     /// Attempting to read more bytes
@@ -285,7 +319,10 @@ where
                     .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
                 {
                     Some(pdu) => {
-                        self.read_buffer.advance(buf.position() as usize);
+                        let len = buf.position() as usize;
+                        self.observer
+                            .observe(Direction::Received, &self.read_buffer[..len]);
+                        self.read_buffer.advance(len);
                         break pdu;
                     }
                     None => {
@@ -358,7 +395,7 @@ pub mod non_blocking {
     use crate::{pdu::PDU_HEADER_SIZE, read_pdu, Pdu};
 
     pub use super::PDataReader;
-    use super::{calculate_max_data_len_single, setup_pdata_header};
+    use super::{calculate_max_data_len_single, setup_pdata_header, Direction, ObserverHandle};
 
     /// Enum representing state of the Async Writer
     enum WriteState {
@@ -421,6 +458,7 @@ pub mod non_blocking {
         stream: W,
         max_data_len: u32,
         state: WriteState,
+        observer: ObserverHandle,
     }
 
     #[cfg(feature = "async")]
@@ -460,9 +498,16 @@ pub mod non_blocking {
                 max_data_len: max_data_length,
                 buffer,
                 state: WriteState::Ready,
+                observer: ObserverHandle::default(),
             }
         }
 
+        /// Notify the given observer of each PDU sent.
+        pub(crate) fn with_observer(mut self, observer: ObserverHandle) -> Self {
+            self.observer = observer;
+            self
+        }
+
         /// Declare to have finished sending P-Data fragments,
         /// thus emitting the last P-Data fragment PDU.
         ///
@@ -476,6 +521,7 @@ pub mod non_blocking {
             if !self.buffer.is_empty() {
                 // send last PDU
                 setup_pdata_header(&mut self.buffer, true);
+                self.observer.observe(Direction::Sent, &self.buffer);
                 if let Err(e) = self.stream.write_all(&self.buffer[..]).await {
                     println!("Error: {:?}", e);
                 }
@@ -515,6 +561,7 @@ pub mod non_blocking {
                         self.buffer.extend(slice);
                         debug_assert_eq!(self.buffer.len(), total_len);
                         setup_pdata_header(&mut self.buffer, false);
+                        self.observer.observe(Direction::Sent, &self.buffer);
                         let this = self.get_mut();
                         // Attempt to send PDU on wire
                         match Pin::new(&mut this.stream).poll_write(cx, &this.buffer) {
@@ -611,6 +658,7 @@ pub mod non_blocking {
                     ref mut stream,
                     ref mut read_buffer,
                     ref max_data_length,
+                    ref observer,
                     ..
                 } = &mut *self;
                 let mut reader = BufReader::new(stream);
@@ -620,7 +668,9 @@ pub mod non_blocking {
                         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
                     {
                         Some(pdu) => {
-                            read_buffer.advance(buf.position() as usize);
+                            let len = buf.position() as usize;
+                            observer.observe(Direction::Received, &read_buffer[..len]);
+                            read_buffer.advance(len);
                             break pdu;
                         }
                         None => {
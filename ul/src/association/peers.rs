@@ -0,0 +1,625 @@
+//! Registry of known application entities.
+//!
+//! A [`PeerRegistry`] lists the DICOM nodes
+//! which a service class provider knows about,
+//! each identified by its AE title.
+//! For each peer, the registry may record
+//! the network address at which the peer accepts associations,
+//! the IP address ranges from which it may request associations,
+//! and the SOP classes which it may use.
+//!
+//! The registry implements [`AccessControl`],
+//! so that it can be used directly in [`ServerAssociationOptions`],
+//! and it can also resolve destination AE titles to network addresses,
+//! as needed by a C-MOVE SCP.
+//!
+//! With the `serde` feature,
+//! a registry can be loaded from a TOML or JSON file:
+//!
+//! ```toml
+//! [[peers]]
+//! ae_title = "STORE-SCU"
+//! allowed_addresses = ["10.0.0.0/8", "192.168.1.20"]
+//! sop_classes = ["1.2.840.10008.5.1.4.1.1.2"]
+//!
+//! [[peers]]
+//! ae_title = "WORKSTATION"
+//! address = "192.168.1.20:11112"
+//! ```
+//!
+//! [`ServerAssociationOptions`]: super::ServerAssociationOptions
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    iter::FromIterator,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use crate::{address::FullAeAddr, pdu::AssociationRJServiceUserReason};
+
+use super::{server::AccessControl, uid::trim_uid};
+use crate::pdu::UserIdentity;
+
+/// An error which may occur when parsing an [`IpRange`].
+#[derive(Debug, Snafu)]
+#[non_exhaustive]
+pub enum ParseIpRangeError {
+    /// Invalid IP address
+    #[snafu(display("invalid IP address in `{}`", range))]
+    InvalidAddress {
+        range: String,
+        source: std::net::AddrParseError,
+    },
+    /// Invalid prefix length
+    #[snafu(display("invalid prefix length in `{}`", range))]
+    InvalidPrefixLength { range: String },
+}
+
+/// A range of IP addresses,
+/// written either as a single address (`192.168.1.20`)
+/// or in CIDR notation (`10.0.0.0/8`, `fd00::/8`).
+///
+/// IPv4 addresses mapped to IPv6 (`::ffff:a.b.c.d`)
+/// are matched against IPv4 ranges.
+///
+/// # Example
+///
+/// ```
+/// # use dicom_ul::association::peers::IpRange;
+/// let range: IpRange = "10.0.0.0/8".parse()?;
+/// assert!(range.contains("10.1.2.3".parse()?));
+/// assert!(!range.contains("192.168.1.20".parse()?));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String"))]
+pub struct IpRange {
+    address: IpAddr,
+    prefix_length: u8,
+}
+
+impl IpRange {
+    /// Create a range containing only the given address.
+    pub fn single(address: IpAddr) -> Self {
+        IpRange {
+            address,
+            prefix_length: max_prefix_length(address),
+        }
+    }
+
+    /// Create a range of addresses sharing the given prefix.
+    ///
+    /// Returns `None` if the prefix length is larger than
+    /// the number of bits in the address.
+    pub fn new(address: IpAddr, prefix_length: u8) -> Option<Self> {
+        if prefix_length > max_prefix_length(address) {
+            return None;
+        }
+        Some(IpRange {
+            address,
+            prefix_length,
+        })
+    }
+
+    /// Retrieve the base address of the range.
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    /// Retrieve the number of leading bits shared by the addresses in the range.
+    pub fn prefix_length(&self) -> u8 {
+        self.prefix_length
+    }
+
+    /// Check whether the given address is in this range.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, normalize(address)) {
+            (IpAddr::V4(base), IpAddr::V4(address)) => {
+                let mask = mask_u32(self.prefix_length);
+                u32::from(base) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(base), IpAddr::V6(address)) => {
+                let mask = mask_u128(self.prefix_length);
+                u128::from(base) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+fn max_prefix_length(address: IpAddr) -> u8 {
+    match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn mask_u32(prefix_length: u8) -> u32 {
+    u32::MAX
+        .checked_shl(32 - u32::from(prefix_length))
+        .unwrap_or(0)
+}
+
+fn mask_u128(prefix_length: u8) -> u128 {
+    u128::MAX
+        .checked_shl(128 - u32::from(prefix_length))
+        .unwrap_or(0)
+}
+
+/// Turn IPv4-mapped IPv6 addresses into IPv4 addresses.
+fn normalize(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(address),
+        address => address,
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = ParseIpRangeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.split_once('/') {
+            Some((address, prefix_length)) => {
+                let address: IpAddr = address.parse().context(InvalidAddressSnafu { range: s })?;
+                let prefix_length = prefix_length
+                    .parse()
+                    .ok()
+                    .context(InvalidPrefixLengthSnafu { range: s })?;
+                IpRange::new(normalize(address), prefix_length)
+                    .context(InvalidPrefixLengthSnafu { range: s })
+            }
+            None => {
+                let address: IpAddr = s.parse().context(InvalidAddressSnafu { range: s })?;
+                Ok(IpRange::single(normalize(address)))
+            }
+        }
+    }
+}
+
+impl std::convert::TryFrom<String> for IpRange {
+    type Error = ParseIpRangeError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl std::fmt::Display for IpRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.prefix_length == max_prefix_length(self.address) {
+            write!(f, "{}", self.address)
+        } else {
+            write!(f, "{}/{}", self.address, self.prefix_length)
+        }
+    }
+}
+
+/// A known application entity.
+///
+/// # Example
+///
+/// ```
+/// # use dicom_ul::association::peers::Peer;
+/// let peer = Peer::new("STORE-SCU")
+///     .address("192.168.1.20:11112")
+///     .allow_address("192.168.1.0/24".parse()?)
+///     .with_sop_class("1.2.840.10008.5.1.4.1.1.2");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[non_exhaustive]
+pub struct Peer {
+    /// The application entity title of the peer
+    pub ae_title: String,
+    /// The network address (`host:port`)
+    /// at which the peer accepts associations,
+    /// if it does
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub address: Option<String>,
+    /// The IP address ranges from which the peer may request associations.
+    /// If empty, associations are accepted from any address.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub allowed_addresses: Vec<IpRange>,
+    /// The SOP classes which the peer may use.
+    /// If empty, all SOP classes supported by the acceptor may be used.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sop_classes: Vec<String>,
+}
+
+impl Peer {
+    /// Create a peer with the given AE title,
+    /// which may request associations from any address
+    /// and use any SOP class.
+    pub fn new(ae_title: impl Into<String>) -> Self {
+        Peer {
+            ae_title: ae_title.into(),
+            address: None,
+            allowed_addresses: Vec::new(),
+            sop_classes: Vec::new(),
+        }
+    }
+
+    /// Set the network address (`host:port`)
+    /// at which the peer accepts associations.
+    pub fn address(mut self, address: impl Into<String>) -> Self {
+        self.address = Some(address.into());
+        self
+    }
+
+    /// Allow the peer to request associations from the given address range.
+    ///
+    /// Once a range is allowed,
+    /// associations from addresses outside of all allowed ranges
+    /// are rejected.
+    pub fn allow_address(mut self, range: IpRange) -> Self {
+        self.allowed_addresses.push(range);
+        self
+    }
+
+    /// Allow the peer to use the given SOP class.
+    ///
+    /// Once a SOP class is allowed,
+    /// presentation contexts for any other SOP class are rejected.
+    pub fn with_sop_class(mut self, sop_class_uid: impl Into<String>) -> Self {
+        let sop_class_uid = sop_class_uid.into();
+        self.sop_classes
+            .push(trim_uid(Cow::from(sop_class_uid)).into_owned());
+        self
+    }
+
+    /// Check whether the peer may request associations
+    /// from the given address.
+    pub fn is_address_allowed(&self, address: IpAddr) -> bool {
+        self.allowed_addresses.is_empty()
+            || self
+                .allowed_addresses
+                .iter()
+                .any(|range| range.contains(address))
+    }
+
+    /// Check whether the peer may use the given SOP class.
+    pub fn is_sop_class_allowed(&self, sop_class_uid: &str) -> bool {
+        let sop_class_uid = trim_uid(Cow::from(sop_class_uid));
+        self.sop_classes.is_empty()
+            || self
+                .sop_classes
+                .iter()
+                .any(|uid| trim_uid(Cow::from(uid.as_str())) == sop_class_uid)
+    }
+}
+
+/// An error which may occur when loading a peer registry.
+#[cfg(feature = "serde")]
+#[derive(Debug, Snafu)]
+#[non_exhaustive]
+pub enum LoadError {
+    /// Could not read the peer registry file
+    ReadFile { source: std::io::Error },
+    /// Could not parse the peer registry as TOML
+    ParseToml { source: toml::de::Error },
+    /// Could not parse the peer registry as JSON
+    ParseJson { source: serde_json::Error },
+    /// Unrecognized peer registry file extension
+    #[snafu(display("unrecognized peer registry file extension (expected .toml or .json)"))]
+    UnknownFormat,
+}
+
+/// The contents of a peer registry file.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct PeerFile {
+    #[serde(default)]
+    peers: Vec<Peer>,
+}
+
+/// A registry of known application entities,
+/// indexed by AE title.
+///
+/// As an [`AccessControl`] implementation,
+/// association requests are only accepted
+/// if the calling AE title is in the registry,
+/// the request comes from one of the peer's allowed addresses,
+/// and presentation contexts are only accepted
+/// for the peer's allowed SOP classes.
+///
+/// # Example
+///
+/// ```
+/// # use dicom_ul::association::peers::{Peer, PeerRegistry};
+/// # use dicom_ul::ServerAssociationOptions;
+/// let registry = PeerRegistry::new()
+///     .with_peer(Peer::new("STORE-SCU").allow_address("10.0.0.0/8".parse()?))
+///     .with_peer(Peer::new("WORKSTATION").address("192.168.1.20:11112"));
+///
+/// let addr = registry.resolve("WORKSTATION").unwrap();
+/// assert_eq!(addr.socket_addr(), "192.168.1.20:11112");
+///
+/// let options = ServerAssociationOptions::new()
+///     .ae_access_control(registry)
+///     .with_abstract_syntax("1.2.840.10008.1.1");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct PeerRegistry {
+    peers: HashMap<String, Peer>,
+}
+
+impl PeerRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a peer to the registry,
+    /// replacing any previous peer with the same AE title.
+    pub fn with_peer(mut self, peer: Peer) -> Self {
+        self.insert(peer);
+        self
+    }
+
+    /// Add a peer to the registry,
+    /// returning the previous peer with the same AE title, if any.
+    pub fn insert(&mut self, peer: Peer) -> Option<Peer> {
+        self.peers.insert(peer.ae_title.trim().to_string(), peer)
+    }
+
+    /// Retrieve the peer with the given AE title.
+    pub fn get(&self, ae_title: &str) -> Option<&Peer> {
+        self.peers.get(ae_title.trim())
+    }
+
+    /// Iterate over all peers in the registry, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &Peer> {
+        self.peers.values()
+    }
+
+    /// Retrieve the number of peers in the registry.
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Check whether the registry is empty.
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Resolve an AE title to the full address of the peer,
+    /// such as the move destination of a C-MOVE request.
+    ///
+    /// Returns `None` if the peer is not known
+    /// or if it has no network address.
+    pub fn resolve(&self, ae_title: &str) -> Option<FullAeAddr<String>> {
+        let peer = self.get(ae_title)?;
+        let address = peer.address.as_ref()?;
+        Some(FullAeAddr::new(peer.ae_title.clone(), address.clone()))
+    }
+
+    /// Parse a registry from its TOML representation,
+    /// with one `[[peers]]` table per peer.
+    #[cfg(feature = "serde")]
+    pub fn from_toml_str(s: &str) -> Result<Self, LoadError> {
+        let file: PeerFile = toml::from_str(s).context(ParseTomlSnafu)?;
+        Ok(file.peers.into_iter().collect())
+    }
+
+    /// Parse a registry from its JSON representation,
+    /// an object with a `peers` array.
+    #[cfg(feature = "serde")]
+    pub fn from_json_str(s: &str) -> Result<Self, LoadError> {
+        let file: PeerFile = serde_json::from_str(s).context(ParseJsonSnafu)?;
+        Ok(file.peers.into_iter().collect())
+    }
+
+    /// Load a registry from a TOML or JSON file,
+    /// depending on the file extension.
+    #[cfg(feature = "serde")]
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        let parse = match extension.as_deref() {
+            Some("toml") => Self::from_toml_str,
+            Some("json") => Self::from_json_str,
+            _ => return UnknownFormatSnafu.fail(),
+        };
+        let contents = std::fs::read_to_string(path).context(ReadFileSnafu)?;
+        parse(&contents)
+    }
+}
+
+impl FromIterator<Peer> for PeerRegistry {
+    fn from_iter<I: IntoIterator<Item = Peer>>(iter: I) -> Self {
+        let mut registry = PeerRegistry::new();
+        for peer in iter {
+            registry.insert(peer);
+        }
+        registry
+    }
+}
+
+impl AccessControl for PeerRegistry {
+    fn check_access(
+        &self,
+        _this_ae_title: &str,
+        calling_ae_title: &str,
+        _called_ae_title: &str,
+        _user_identity: Option<&UserIdentity>,
+    ) -> Result<(), AssociationRJServiceUserReason> {
+        if self.get(calling_ae_title).is_some() {
+            Ok(())
+        } else {
+            Err(AssociationRJServiceUserReason::CallingAETitleNotRecognized)
+        }
+    }
+
+    fn check_peer_address(
+        &self,
+        calling_ae_title: &str,
+        peer_addr: Option<SocketAddr>,
+    ) -> Result<(), AssociationRJServiceUserReason> {
+        let peer = self
+            .get(calling_ae_title)
+            .ok_or(AssociationRJServiceUserReason::CallingAETitleNotRecognized)?;
+        if peer.allowed_addresses.is_empty() {
+            return Ok(());
+        }
+        match peer_addr {
+            Some(addr) if peer.is_address_allowed(addr.ip()) => Ok(()),
+            _ => Err(AssociationRJServiceUserReason::NoReasonGiven),
+        }
+    }
+
+    fn is_abstract_syntax_allowed(&self, calling_ae_title: &str, abstract_syntax: &str) -> bool {
+        self.get(calling_ae_title)
+            .is_some_and(|peer| peer.is_sop_class_allowed(abstract_syntax))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ip_ranges() {
+        let range: IpRange = "192.168.1.0/24".parse().unwrap();
+        assert_eq!(range.prefix_length(), 24);
+        assert!(range.contains("192.168.1.200".parse().unwrap()));
+        assert!(!range.contains("192.168.2.1".parse().unwrap()));
+        // IPv4-mapped IPv6 addresses
+        assert!(range.contains("::ffff:192.168.1.7".parse().unwrap()));
+        assert_eq!(range.to_string(), "192.168.1.0/24");
+
+        let range: IpRange = "10.0.0.1".parse().unwrap();
+        assert!(range.contains("10.0.0.1".parse().unwrap()));
+        assert!(!range.contains("10.0.0.2".parse().unwrap()));
+        assert_eq!(range.to_string(), "10.0.0.1");
+
+        let range: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(range.contains("8.8.8.8".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+
+        let range: IpRange = "fd00::/8".parse().unwrap();
+        assert!(range.contains("fd12:3456::1".parse().unwrap()));
+        assert!(!range.contains("fe80::1".parse().unwrap()));
+
+        assert!(matches!(
+            "10.0.0.0/33".parse::<IpRange>(),
+            Err(ParseIpRangeError::InvalidPrefixLength { .. })
+        ));
+        assert!(matches!(
+            "10.0.0.0/x".parse::<IpRange>(),
+            Err(ParseIpRangeError::InvalidPrefixLength { .. })
+        ));
+        assert!(matches!(
+            "localhost".parse::<IpRange>(),
+            Err(ParseIpRangeError::InvalidAddress { .. })
+        ));
+    }
+
+    #[test]
+    fn registry_access_control() {
+        let registry = PeerRegistry::new()
+            .with_peer(
+                Peer::new("STORE-SCU")
+                    .allow_address("10.0.0.0/8".parse().unwrap())
+                    .with_sop_class("1.2.840.10008.5.1.4.1.1.2"),
+            )
+            .with_peer(Peer::new("ANYWHERE").address("pacs.local:104"));
+
+        let addr = |s: &str| Some(s.parse::<SocketAddr>().unwrap());
+
+        assert_eq!(
+            registry.check_access("SCP", "UNKNOWN", "SCP", None),
+            Err(AssociationRJServiceUserReason::CallingAETitleNotRecognized)
+        );
+        assert_eq!(
+            registry.check_access("SCP", "STORE-SCU", "SCP", None),
+            Ok(())
+        );
+
+        assert_eq!(
+            registry.check_peer_address("STORE-SCU", addr("10.1.2.3:5000")),
+            Ok(())
+        );
+        assert!(registry
+            .check_peer_address("STORE-SCU", addr("192.168.1.1:5000"))
+            .is_err());
+        assert!(registry.check_peer_address("STORE-SCU", None).is_err());
+        assert_eq!(registry.check_peer_address("ANYWHERE", None), Ok(()));
+
+        assert!(registry.is_abstract_syntax_allowed("STORE-SCU", "1.2.840.10008.5.1.4.1.1.2\0"));
+        assert!(!registry.is_abstract_syntax_allowed("STORE-SCU", "1.2.840.10008.1.1"));
+        assert!(registry.is_abstract_syntax_allowed("ANYWHERE", "1.2.840.10008.1.1"));
+
+        assert_eq!(registry.resolve("STORE-SCU"), None);
+        assert_eq!(
+            registry.resolve("ANYWHERE"),
+            Some(FullAeAddr::new("ANYWHERE", "pacs.local:104".to_string()))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn load_registry() {
+        let from_toml = PeerRegistry::from_toml_str(
+            r#"
+            [[peers]]
+            ae_title = "STORE-SCU"
+            allowed_addresses = ["10.0.0.0/8", "192.168.1.20"]
+            sop_classes = ["1.2.840.10008.5.1.4.1.1.2"]
+
+            [[peers]]
+            ae_title = "WORKSTATION"
+            address = "192.168.1.20:11112"
+            "#,
+        )
+        .unwrap();
+        let from_json = PeerRegistry::from_json_str(
+            r#"{"peers": [
+                {
+                    "ae_title": "STORE-SCU",
+                    "allowed_addresses": ["10.0.0.0/8", "192.168.1.20"],
+                    "sop_classes": ["1.2.840.10008.5.1.4.1.1.2"]
+                },
+                {"ae_title": "WORKSTATION", "address": "192.168.1.20:11112"}
+            ]}"#,
+        )
+        .unwrap();
+
+        for registry in [from_toml, from_json] {
+            assert_eq!(registry.len(), 2);
+            assert_eq!(
+                registry.get("STORE-SCU"),
+                Some(
+                    &Peer::new("STORE-SCU")
+                        .allow_address("10.0.0.0/8".parse().unwrap())
+                        .allow_address("192.168.1.20".parse().unwrap())
+                        .with_sop_class("1.2.840.10008.5.1.4.1.1.2")
+                )
+            );
+            assert_eq!(
+                registry.resolve("WORKSTATION").unwrap().to_string(),
+                "WORKSTATION@192.168.1.20:11112"
+            );
+        }
+
+        assert!(matches!(
+            PeerRegistry::from_toml_str(
+                "[[peers]]\nae_title = \"A\"\nallowed_addresses = [\"nope\"]"
+            ),
+            Err(LoadError::ParseToml { .. })
+        ));
+        assert!(matches!(
+            PeerRegistry::load("peers.yaml"),
+            Err(LoadError::UnknownFormat)
+        ));
+    }
+}
@@ -0,0 +1,712 @@
+//! Client association pool.
+//!
+//! An [`AssociationPool`] keeps associations to other DICOM nodes open
+//! after use,
+//! so that subsequent requests to the same destination
+//! do not need to negotiate a new association.
+//! Idle associations are indexed by the destination's [`FullAeAddr`]
+//! and by the presentation contexts which they negotiated.
+//!
+//! When an association is requested,
+//! the pool hands out an idle association to the same destination
+//! which accepted all of the requested abstract syntaxes
+//! with one of the requested transfer syntaxes.
+//! If there is none,
+//! a new association is negotiated,
+//! proposing the requested presentation contexts
+//! together with those of an idle association to the same destination,
+//! which the new association then replaces.
+//!
+//! Before an idle association is handed out,
+//! the pool checks whether the peer has closed or aborted it in the meantime,
+//! in which case a new association is transparently established instead.
+//!
+//! The pool for associations established via
+//! [`establish_async`](super::ClientAssociationOptions::establish_async)
+//! is [`AsyncAssociationPool`](non_blocking::AsyncAssociationPool).
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use dicom_ul::association::pool::AssociationPool;
+//! # use dicom_ul::ClientAssociationOptions;
+//! # fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let pool = AssociationPool::new(
+//!     ClientAssociationOptions::new().calling_ae_title("ROUTER"),
+//! );
+//! let destination = "STORE-SCP@10.0.0.100:104".parse()?;
+//! for _ in 0..100 {
+//!     // reuses the same association every time
+//!     let mut association = pool.get(
+//!         &destination,
+//!         vec![("1.2.840.10008.5.1.4.1.1.2", vec!["1.2.840.10008.1.2.1"])],
+//!     )?;
+//!     // send C-STORE requests ...
+//!     # let _ = association.next_message_id();
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    net::TcpStream,
+    ops::{Deref, DerefMut},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tracing::debug;
+
+use crate::{
+    address::FullAeAddr,
+    pdu::{PresentationContextResult, PresentationContextResultReason},
+};
+
+use super::{
+    client::{ClientAssociation, ClientAssociationOptions, Result},
+    state::State,
+    uid::trim_uid,
+};
+
+/// The default maximum number of idle associations kept per destination.
+pub const DEFAULT_MAX_IDLE: usize = 4;
+
+/// The default time after which idle associations are released.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The maximum number of presentation contexts in an association request.
+const MAX_PRESENTATION_CONTEXTS: usize = 128;
+
+/// A list of presentation contexts to propose,
+/// as pairs of abstract syntax and transfer syntaxes.
+type Contexts = Vec<(String, Vec<String>)>;
+
+/// Collect and normalize the presentation contexts requested by the user.
+fn collect_contexts<I, T>(presentation_contexts: I) -> Contexts
+where
+    I: IntoIterator<Item = (T, Vec<T>)>,
+    T: Into<String>,
+{
+    presentation_contexts
+        .into_iter()
+        .map(|(abstract_syntax, transfer_syntaxes)| {
+            (
+                trim_uid(Cow::from(abstract_syntax.into())).into_owned(),
+                transfer_syntaxes
+                    .into_iter()
+                    .map(|ts| trim_uid(Cow::from(ts.into())).into_owned())
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+/// Merge the presentation contexts of an existing association
+/// into the requested ones,
+/// so that a new association can replace the existing one.
+fn merge_contexts(requested: &Contexts, existing: &Contexts) -> Contexts {
+    let mut merged = requested.clone();
+    for context in existing {
+        if !merged.contains(context) {
+            merged.push(context.clone());
+        }
+    }
+    merged
+}
+
+/// Common access to the negotiation outcome
+/// of blocking and non-blocking associations.
+trait Negotiated {
+    fn presentation_contexts(&self) -> &[PresentationContextResult];
+
+    fn abstract_syntax(&self, presentation_context_id: u8) -> Option<&str>;
+
+    /// Check whether all of the requested abstract syntaxes
+    /// were accepted with one of the requested transfer syntaxes.
+    fn covers(&self, contexts: &Contexts) -> bool {
+        contexts.iter().all(|(abstract_syntax, transfer_syntaxes)| {
+            self.presentation_contexts().iter().any(|pc| {
+                pc.reason == PresentationContextResultReason::Acceptance
+                    && self
+                        .abstract_syntax(pc.id)
+                        .map(|uid| trim_uid(Cow::from(uid)))
+                        .is_some_and(|uid| uid == abstract_syntax.as_str())
+                    && transfer_syntaxes
+                        .iter()
+                        .any(|ts| trim_uid(Cow::from(pc.transfer_syntax.as_str())) == ts.as_str())
+            })
+        })
+    }
+}
+
+impl Negotiated for ClientAssociation<TcpStream> {
+    fn presentation_contexts(&self) -> &[PresentationContextResult] {
+        ClientAssociation::presentation_contexts(self)
+    }
+
+    fn abstract_syntax(&self, presentation_context_id: u8) -> Option<&str> {
+        ClientAssociation::abstract_syntax(self, presentation_context_id)
+    }
+}
+
+/// An association waiting in the pool.
+struct Idle<A> {
+    association: A,
+    /// the presentation contexts proposed for the association
+    contexts: Contexts,
+    /// when the association was returned to the pool
+    since: Instant,
+}
+
+/// The outcome of looking for an idle association.
+enum Candidate<A> {
+    /// An association which covers the requested contexts
+    Reuse(Idle<A>),
+    /// An association to be replaced by a new one
+    Replace(Idle<A>),
+    /// No association to the destination
+    None,
+}
+
+/// The idle associations of a pool, by destination.
+struct IdleSet<A> {
+    max_idle: usize,
+    idle_timeout: Option<Duration>,
+    entries: Mutex<HashMap<FullAeAddr<String>, Vec<Idle<A>>>>,
+}
+
+impl<A: Negotiated> IdleSet<A> {
+    fn new() -> Self {
+        IdleSet {
+            max_idle: DEFAULT_MAX_IDLE,
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take an idle association for the given destination and contexts,
+    /// along with the expired associations to release.
+    fn take(&self, address: &FullAeAddr<String>, contexts: &Contexts) -> (Candidate<A>, Vec<A>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(idle) = entries.get_mut(address) else {
+            return (Candidate::None, Vec::new());
+        };
+
+        let mut expired = Vec::new();
+        if let Some(idle_timeout) = self.idle_timeout {
+            let mut i = 0;
+            while i < idle.len() {
+                if idle[i].since.elapsed() >= idle_timeout {
+                    expired.push(idle.remove(i).association);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+
+        // prefer the most recently used association
+        let candidate = if let Some(i) = idle
+            .iter()
+            .rposition(|entry| entry.association.covers(contexts))
+        {
+            Candidate::Reuse(idle.remove(i))
+        } else if let Some(i) = idle.iter().rposition(|entry| {
+            merge_contexts(contexts, &entry.contexts).len() <= MAX_PRESENTATION_CONTEXTS
+        }) {
+            Candidate::Replace(idle.remove(i))
+        } else {
+            Candidate::None
+        };
+
+        if idle.is_empty() {
+            entries.remove(address);
+        }
+        (candidate, expired)
+    }
+
+    /// Return an association to the pool,
+    /// giving back the association which no longer fits in it, if any.
+    fn put(&self, address: FullAeAddr<String>, association: A, contexts: Contexts) -> Option<A> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let idle = entries.entry(address).or_default();
+        idle.push(Idle {
+            association,
+            contexts,
+            since: Instant::now(),
+        });
+        if idle.len() > self.max_idle {
+            Some(idle.remove(0).association)
+        } else {
+            None
+        }
+    }
+
+    fn count(&self) -> usize {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.values().map(Vec::len).sum()
+    }
+
+    fn clear(&self) -> Vec<A> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .drain()
+            .flat_map(|(_, idle)| idle)
+            .map(|entry| entry.association)
+            .collect()
+    }
+}
+
+/// Check whether the peer has closed the connection of an idle association,
+/// or sent anything through it (such as an A-ABORT),
+/// without blocking.
+fn is_alive(association: &mut ClientAssociation<TcpStream>) -> bool {
+    if association.state() != State::Established {
+        return false;
+    }
+    let stream = association.inner_stream();
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut buf = [0; 1];
+    let alive = matches!(
+        stream.peek(&mut buf),
+        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock
+    );
+    stream.set_nonblocking(false).is_ok() && alive
+}
+
+/// A pool of client associations over TCP,
+/// reused across requests to the same destinations.
+///
+/// See the [module-level documentation](self) for more details.
+pub struct AssociationPool {
+    /// the options for establishing new associations
+    options: ClientAssociationOptions<'static>,
+    idle: IdleSet<ClientAssociation<TcpStream>>,
+}
+
+impl std::fmt::Debug for AssociationPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AssociationPool")
+            .field("options", &self.options)
+            .field("max_idle", &self.idle.max_idle)
+            .field("idle_timeout", &self.idle.idle_timeout)
+            .field("idle", &self.idle.count())
+            .finish()
+    }
+}
+
+impl AssociationPool {
+    /// Create a new association pool.
+    ///
+    /// New associations are established with the given options,
+    /// in addition to the presentation contexts
+    /// and the called AE title of each request.
+    pub fn new(options: ClientAssociationOptions<'static>) -> Self {
+        AssociationPool {
+            options,
+            idle: IdleSet::new(),
+        }
+    }
+
+    /// Set the maximum number of idle associations kept per destination.
+    /// When exceeded, the least recently used association is released.
+    ///
+    /// The default is [`DEFAULT_MAX_IDLE`].
+    pub fn max_idle(mut self, max_idle: usize) -> Self {
+        self.idle.max_idle = max_idle;
+        self
+    }
+
+    /// Set the time after which idle associations are released,
+    /// or `None` to keep them indefinitely.
+    ///
+    /// The default is [`DEFAULT_IDLE_TIMEOUT`].
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Obtain an association to the given destination
+    /// which accepted each of the given abstract syntaxes
+    /// with one of its transfer syntaxes,
+    /// reusing an idle association if possible.
+    ///
+    /// The association returns to the pool when dropped,
+    /// unless it is no longer established
+    /// or still awaits responses to its requests.
+    pub fn get<I, T>(
+        &self,
+        address: &FullAeAddr<String>,
+        presentation_contexts: I,
+    ) -> Result<PooledAssociation<'_>>
+    where
+        I: IntoIterator<Item = (T, Vec<T>)>,
+        T: Into<String>,
+    {
+        let contexts = collect_contexts(presentation_contexts);
+        let contexts = loop {
+            let (candidate, expired) = self.idle.take(address, &contexts);
+            drop(expired);
+            match candidate {
+                Candidate::Reuse(mut idle) => {
+                    if is_alive(&mut idle.association) {
+                        return Ok(PooledAssociation {
+                            pool: self,
+                            address: address.clone(),
+                            contexts: idle.contexts,
+                            association: Some(idle.association),
+                        });
+                    }
+                    debug!("Idle association to {} was lost, reconnecting", address);
+                    // take in the A-ABORT or the closed connection
+                    let _ = idle.association.receive();
+                }
+                Candidate::Replace(idle) => {
+                    debug!("Renegotiating association to {}", address);
+                    break merge_contexts(&contexts, &idle.contexts);
+                }
+                Candidate::None => break contexts,
+            }
+        };
+
+        let mut options = self
+            .options
+            .clone()
+            .called_ae_title(address.ae_title().to_string());
+        for (abstract_syntax, transfer_syntaxes) in &contexts {
+            options = options
+                .with_presentation_context(abstract_syntax.clone(), transfer_syntaxes.clone());
+        }
+        let association = options.establish(address.socket_addr().as_str())?;
+        Ok(PooledAssociation {
+            pool: self,
+            address: address.clone(),
+            contexts,
+            association: Some(association),
+        })
+    }
+
+    /// Retrieve the number of idle associations in the pool.
+    pub fn idle_count(&self) -> usize {
+        self.idle.count()
+    }
+
+    /// Release all idle associations.
+    pub fn clear(&self) {
+        for association in self.idle.clear() {
+            let _ = association.release();
+        }
+    }
+}
+
+/// An association obtained from an [`AssociationPool`],
+/// which returns to the pool when dropped.
+///
+/// It dereferences to the underlying [`ClientAssociation`].
+#[derive(Debug)]
+pub struct PooledAssociation<'a> {
+    pool: &'a AssociationPool,
+    address: FullAeAddr<String>,
+    contexts: Contexts,
+    association: Option<ClientAssociation<TcpStream>>,
+}
+
+impl PooledAssociation<'_> {
+    /// Retrieve the address of the association's destination.
+    pub fn address(&self) -> &FullAeAddr<String> {
+        &self.address
+    }
+
+    /// Take the association out of the pool.
+    pub fn into_inner(mut self) -> ClientAssociation<TcpStream> {
+        self.association.take().unwrap()
+    }
+
+    /// Gracefully release the association instead of returning it to the pool.
+    pub fn release(self) -> Result<()> {
+        self.into_inner().release()
+    }
+
+    /// Abort the association instead of returning it to the pool.
+    pub fn abort(self) -> Result<()> {
+        self.into_inner().abort()
+    }
+}
+
+impl Deref for PooledAssociation<'_> {
+    type Target = ClientAssociation<TcpStream>;
+
+    fn deref(&self) -> &Self::Target {
+        self.association.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledAssociation<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.association.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledAssociation<'_> {
+    fn drop(&mut self) {
+        let Some(association) = self.association.take() else {
+            return;
+        };
+        if association.state() == State::Established
+            && association.outstanding_requests().is_empty()
+        {
+            let contexts = std::mem::take(&mut self.contexts);
+            // released on drop
+            let _ = self
+                .pool
+                .idle
+                .put(self.address.clone(), association, contexts);
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+pub mod non_blocking {
+    //! Pool of non-blocking client associations.
+    use std::{
+        ops::{Deref, DerefMut},
+        time::Duration,
+    };
+
+    use tokio::net::TcpStream;
+    use tracing::debug;
+
+    use crate::{
+        address::FullAeAddr,
+        association::{
+            client::{non_blocking::AsyncClientAssociation, ClientAssociationOptions, Result},
+            state::State,
+        },
+        pdu::PresentationContextResult,
+    };
+
+    use super::{collect_contexts, merge_contexts, Candidate, Contexts, IdleSet, Negotiated};
+
+    impl Negotiated for AsyncClientAssociation<TcpStream> {
+        fn presentation_contexts(&self) -> &[PresentationContextResult] {
+            AsyncClientAssociation::presentation_contexts(self)
+        }
+
+        fn abstract_syntax(&self, presentation_context_id: u8) -> Option<&str> {
+            AsyncClientAssociation::abstract_syntax(self, presentation_context_id)
+        }
+    }
+
+    /// Check whether the peer has closed the connection of an idle association,
+    /// or sent anything through it (such as an A-ABORT),
+    /// without waiting.
+    async fn is_alive(association: &mut AsyncClientAssociation<TcpStream>) -> bool {
+        if association.state() != State::Established {
+            return false;
+        }
+        let mut buf = [0; 1];
+        tokio::time::timeout(Duration::ZERO, association.inner_stream().peek(&mut buf))
+            .await
+            .is_err()
+    }
+
+    /// A pool of non-blocking client associations over TCP,
+    /// reused across requests to the same destinations.
+    ///
+    /// See the [module-level documentation](super) for more details.
+    pub struct AsyncAssociationPool {
+        /// the options for establishing new associations
+        options: ClientAssociationOptions<'static>,
+        idle: IdleSet<AsyncClientAssociation<TcpStream>>,
+    }
+
+    impl std::fmt::Debug for AsyncAssociationPool {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("AsyncAssociationPool")
+                .field("options", &self.options)
+                .field("max_idle", &self.idle.max_idle)
+                .field("idle_timeout", &self.idle.idle_timeout)
+                .field("idle", &self.idle.count())
+                .finish()
+        }
+    }
+
+    impl AsyncAssociationPool {
+        /// Create a new association pool.
+        ///
+        /// New associations are established with the given options,
+        /// in addition to the presentation contexts
+        /// and the called AE title of each request.
+        pub fn new(options: ClientAssociationOptions<'static>) -> Self {
+            AsyncAssociationPool {
+                options,
+                idle: IdleSet::new(),
+            }
+        }
+
+        /// Set the maximum number of idle associations kept per destination.
+        /// When exceeded, the least recently used association is released.
+        ///
+        /// The default is [`DEFAULT_MAX_IDLE`](super::DEFAULT_MAX_IDLE).
+        pub fn max_idle(mut self, max_idle: usize) -> Self {
+            self.idle.max_idle = max_idle;
+            self
+        }
+
+        /// Set the time after which idle associations are released,
+        /// or `None` to keep them indefinitely.
+        ///
+        /// The default is [`DEFAULT_IDLE_TIMEOUT`](super::DEFAULT_IDLE_TIMEOUT).
+        pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+            self.idle.idle_timeout = idle_timeout;
+            self
+        }
+
+        /// Obtain an association to the given destination
+        /// which accepted each of the given abstract syntaxes
+        /// with one of its transfer syntaxes,
+        /// reusing an idle association if possible.
+        ///
+        /// The association returns to the pool when dropped,
+        /// unless it is no longer established
+        /// or still awaits responses to its requests.
+        pub async fn get<I, T>(
+            &self,
+            address: &FullAeAddr<String>,
+            presentation_contexts: I,
+        ) -> Result<AsyncPooledAssociation<'_>>
+        where
+            I: IntoIterator<Item = (T, Vec<T>)>,
+            T: Into<String>,
+        {
+            let contexts = collect_contexts(presentation_contexts);
+            let contexts = loop {
+                let (candidate, expired) = self.idle.take(address, &contexts);
+                for association in expired {
+                    let _ = association.release().await;
+                }
+                match candidate {
+                    Candidate::Reuse(mut idle) => {
+                        if is_alive(&mut idle.association).await {
+                            return Ok(AsyncPooledAssociation {
+                                pool: self,
+                                address: address.clone(),
+                                contexts: idle.contexts,
+                                association: Some(idle.association),
+                            });
+                        }
+                        debug!("Idle association to {} was lost, reconnecting", address);
+                        // take in the A-ABORT or the closed connection
+                        let _ = idle.association.receive().await;
+                    }
+                    Candidate::Replace(idle) => {
+                        debug!("Renegotiating association to {}", address);
+                        let contexts = merge_contexts(&contexts, &idle.contexts);
+                        let _ = idle.association.release().await;
+                        break contexts;
+                    }
+                    Candidate::None => break contexts,
+                }
+            };
+
+            let mut options = self
+                .options
+                .clone()
+                .called_ae_title(address.ae_title().to_string());
+            for (abstract_syntax, transfer_syntaxes) in &contexts {
+                options = options
+                    .with_presentation_context(abstract_syntax.clone(), transfer_syntaxes.clone());
+            }
+            let association = options
+                .establish_async(address.socket_addr().as_str())
+                .await?;
+            Ok(AsyncPooledAssociation {
+                pool: self,
+                address: address.clone(),
+                contexts,
+                association: Some(association),
+            })
+        }
+
+        /// Retrieve the number of idle associations in the pool.
+        pub fn idle_count(&self) -> usize {
+            self.idle.count()
+        }
+
+        /// Release all idle associations.
+        pub async fn clear(&self) {
+            for association in self.idle.clear() {
+                let _ = association.release().await;
+            }
+        }
+    }
+
+    /// An association obtained from an [`AsyncAssociationPool`],
+    /// which returns to the pool when dropped.
+    ///
+    /// It dereferences to the underlying [`AsyncClientAssociation`].
+    #[derive(Debug)]
+    pub struct AsyncPooledAssociation<'a> {
+        pool: &'a AsyncAssociationPool,
+        address: FullAeAddr<String>,
+        contexts: Contexts,
+        association: Option<AsyncClientAssociation<TcpStream>>,
+    }
+
+    impl AsyncPooledAssociation<'_> {
+        /// Retrieve the address of the association's destination.
+        pub fn address(&self) -> &FullAeAddr<String> {
+            &self.address
+        }
+
+        /// Take the association out of the pool.
+        pub fn into_inner(mut self) -> AsyncClientAssociation<TcpStream> {
+            self.association.take().unwrap()
+        }
+
+        /// Gracefully release the association
+        /// instead of returning it to the pool.
+        pub async fn release(self) -> Result<()> {
+            self.into_inner().release().await
+        }
+
+        /// Abort the association instead of returning it to the pool.
+        pub async fn abort(self) -> Result<()> {
+            self.into_inner().abort().await
+        }
+    }
+
+    impl Deref for AsyncPooledAssociation<'_> {
+        type Target = AsyncClientAssociation<TcpStream>;
+
+        fn deref(&self) -> &Self::Target {
+            self.association.as_ref().unwrap()
+        }
+    }
+
+    impl DerefMut for AsyncPooledAssociation<'_> {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            self.association.as_mut().unwrap()
+        }
+    }
+
+    impl Drop for AsyncPooledAssociation<'_> {
+        fn drop(&mut self) {
+            let Some(association) = self.association.take() else {
+                return;
+            };
+            if association.state() == State::Established
+                && association.outstanding_requests().is_empty()
+            {
+                let contexts = std::mem::take(&mut self.contexts);
+                // released on drop
+                let _ = self
+                    .pool
+                    .idle
+                    .put(self.address.clone(), association, contexts);
+            }
+        }
+    }
+}
@@ -5,28 +5,44 @@
 //! See [`ServerAssociationOptions`]
 //! for details and examples on how to create an association.
 use bytes::{Buf, BytesMut};
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read};
 use std::time::Duration;
 use std::{borrow::Cow, io::Cursor};
-use std::{io::Write, net::TcpStream};
+use std::{
+    io::Write,
+    net::{SocketAddr, TcpStream},
+};
+
+#[cfg(feature = "tls")]
+use snafu::OptionExt;
+use std::sync::Arc;
 
 use dicom_encoding::transfer_syntax::TransferSyntaxIndex;
 use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
 use snafu::{ensure, Backtrace, ResultExt, Snafu};
+use tracing::debug;
 
 use crate::{
+    dimse::{self, command_pdu, Command, Message, MessageAssembler, MessageCommand},
     pdu::{
         read_pdu, write_pdu, AbortRQServiceProviderReason, AbortRQSource, AssociationAC,
         AssociationRJ, AssociationRJResult, AssociationRJServiceUserReason, AssociationRJSource,
-        AssociationRQ, Pdu, PresentationContextResult, PresentationContextResultReason,
-        ReadPduSnafu, UserIdentity, UserVariableItem, DEFAULT_MAX_PDU, MAXIMUM_PDU_SIZE,
+        AssociationRQ, AsyncOperationsWindow, Pdu, PresentationContextProposed,
+        PresentationContextResult, PresentationContextResultReason, ReadPduSnafu, RoleSelection,
+        UserIdentity, UserVariableItem, DEFAULT_MAX_PDU, MAXIMUM_PDU_SIZE,
     },
     IMPLEMENTATION_CLASS_UID, IMPLEMENTATION_VERSION_NAME,
 };
 
 use super::{
+    client::CloseSocket,
+    find_async_operations_window, find_user_identity,
+    observer::{Direction, ObserverHandle, PduObserver},
     pdata::{PDataReader, PDataWriter},
+    restrict_async_operations_window,
+    state::{Action, Event, Side, State, StateMachine, DEFAULT_ARTIM_TIMEOUT},
     uid::trim_uid,
+    NegotiatedContext, Roles,
 };
 
 #[derive(Debug, Snafu)]
@@ -53,6 +69,7 @@ pub enum Error {
         source: crate::pdu::WriteError,
     },
     /// Failed to read from the wire
+    #[snafu(visibility(pub(crate)))]
     WireRead {
         source: std::io::Error,
         backtrace: Backtrace,
@@ -98,6 +115,16 @@ pub enum Error {
     #[snafu(display("Connection closed by peer"))]
     ConnectionClosed,
 
+    #[snafu(display("{} is not allowed in association state {}", event, state))]
+    #[non_exhaustive]
+    InvalidState {
+        /// the state of the association
+        state: State,
+        /// the event which was not allowed
+        event: Event,
+        backtrace: Backtrace,
+    },
+
     /// Could not set tcp read timeout
     SetReadTimeout {
         source: std::io::Error,
@@ -109,6 +136,31 @@ pub enum Error {
         source: std::io::Error,
         backtrace: Backtrace,
     },
+
+    /// failed to decode DIMSE message
+    #[non_exhaustive]
+    ReadMessage {
+        #[snafu(backtrace)]
+        source: crate::dimse::ReadError,
+    },
+
+    /// missing TLS server configuration
+    #[cfg(feature = "tls")]
+    MissingTlsConfig { backtrace: Backtrace },
+
+    /// could not create TLS session
+    #[cfg(feature = "tls")]
+    TlsSession {
+        source: rustls::Error,
+        backtrace: Backtrace,
+    },
+
+    /// TLS handshake failed
+    #[cfg(feature = "tls")]
+    TlsHandshake {
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -130,6 +182,64 @@ pub trait AccessControl {
         called_ae_title: &str,
         user_identity: Option<&UserIdentity>,
     ) -> Result<(), AssociationRJServiceUserReason>;
+
+    /// Verify the user identity presented by the association requestor,
+    /// such as a username and passcode, a Kerberos service ticket,
+    /// a SAML assertion, or a JSON web token.
+    ///
+    /// This is only called if the association request
+    /// contains a user identity,
+    /// after access was granted by [`check_access`](Self::check_access).
+    ///
+    /// Returns `Ok(Some(response))` if the identity was verified,
+    /// where `response` is the server response to the identity
+    /// (the Kerberos server ticket, the SAML response, or the JWT response,
+    /// and empty for the other identity types).
+    /// The response is sent back in the association acknowledgement
+    /// if the requestor asked for a positive response.
+    /// Returns `Ok(None)` to accept the identity without verifying it,
+    /// in which case no response is sent.
+    /// Otherwise, the association is rejected with the given reason.
+    ///
+    /// The default implementation accepts any identity without verifying it.
+    fn verify_user_identity(
+        &self,
+        calling_ae_title: &str,
+        user_identity: &UserIdentity,
+    ) -> Result<Option<Vec<u8>>, AssociationRJServiceUserReason> {
+        let _ = (calling_ae_title, user_identity);
+        Ok(None)
+    }
+
+    /// Obtain the decision of whether to accept an association request
+    /// from the given network address,
+    /// before [`check_access`](Self::check_access) is called.
+    ///
+    /// The address is `None` if the association is established
+    /// over a connection other than TCP,
+    /// such as with
+    /// [`establish_stream`](ServerAssociationOptions::establish_stream).
+    ///
+    /// The default implementation accepts any address.
+    fn check_peer_address(
+        &self,
+        calling_ae_title: &str,
+        peer_addr: Option<SocketAddr>,
+    ) -> Result<(), AssociationRJServiceUserReason> {
+        let _ = (calling_ae_title, peer_addr);
+        Ok(())
+    }
+
+    /// Check whether the association requestor may use the given abstract syntax.
+    ///
+    /// Presentation contexts with abstract syntaxes which are not allowed
+    /// are rejected as if the abstract syntax was not supported.
+    ///
+    /// The default implementation allows all abstract syntaxes.
+    fn is_abstract_syntax_allowed(&self, calling_ae_title: &str, abstract_syntax: &str) -> bool {
+        let _ = (calling_ae_title, abstract_syntax);
+        true
+    }
 }
 
 /// An access control rule that accepts any incoming association request.
@@ -169,6 +279,136 @@ impl AccessControl for AcceptCalledAeTitle {
     }
 }
 
+/// A transfer syntax acceptance policy
+/// for a group of abstract syntaxes.
+///
+/// When a presentation context is proposed for one of the abstract syntaxes,
+/// the first proposed transfer syntax in the policy's order of preference
+/// is accepted.
+/// Otherwise, unless only preferred transfer syntaxes are admitted,
+/// a transfer syntax is chosen as if there was no policy,
+/// never accepting one of the rejected transfer syntaxes.
+/// Preferred transfer syntaxes are accepted
+/// even if they were not registered in the association options,
+/// but they must still be supported by the transfer syntax registry.
+///
+/// A policy without abstract syntaxes applies to all of them.
+///
+/// # Example
+///
+/// ```
+/// # use dicom_ul::association::server::{ServerAssociationOptions, TransferSyntaxPolicy};
+/// let options = ServerAssociationOptions::new()
+///     .with_abstract_syntax("1.2.840.10008.5.1.4.1.1.2")
+///     .with_abstract_syntax("1.2.840.10008.5.1.4.1.1.1.2")
+///     .with_transfer_syntax("1.2.840.10008.1.2.1")
+///     .with_transfer_syntax("1.2.840.10008.1.2")
+///     // JPEG 2000 for CT images
+///     .with_transfer_syntax_policy(
+///         TransferSyntaxPolicy::new()
+///             .with_abstract_syntax("1.2.840.10008.5.1.4.1.1.2")
+///             .prefer("1.2.840.10008.1.2.4.90"),
+///     )
+///     // lossless for digital mammography
+///     .with_transfer_syntax_policy(
+///         TransferSyntaxPolicy::new()
+///             .with_abstract_syntax("1.2.840.10008.5.1.4.1.1.1.2")
+///             .prefer("1.2.840.10008.1.2.4.80")
+///             .prefer("1.2.840.10008.1.2.4.70")
+///             .reject("1.2.840.10008.1.2.4.91"),
+///     );
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TransferSyntaxPolicy {
+    /// the abstract syntaxes to which the policy applies
+    abstract_syntax_uids: Vec<String>,
+    /// the preferred transfer syntaxes, from most to least preferred
+    preferred_transfer_syntax_uids: Vec<String>,
+    /// the transfer syntaxes which are never accepted
+    rejected_transfer_syntax_uids: Vec<String>,
+    /// whether to accept only the preferred transfer syntaxes
+    preferred_only: bool,
+}
+
+impl TransferSyntaxPolicy {
+    /// Create a new policy which applies to all abstract syntaxes
+    /// and has no effect on the transfer syntaxes accepted.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply the policy to this abstract syntax.
+    pub fn with_abstract_syntax<T>(mut self, abstract_syntax_uid: T) -> Self
+    where
+        T: AsRef<str>,
+    {
+        self.abstract_syntax_uids
+            .push(trim_uid(abstract_syntax_uid.as_ref().into()).into_owned());
+        self
+    }
+
+    /// Prefer this transfer syntax
+    /// over the ones given later and over the remaining ones.
+    pub fn prefer<T>(mut self, transfer_syntax_uid: T) -> Self
+    where
+        T: AsRef<str>,
+    {
+        self.preferred_transfer_syntax_uids
+            .push(trim_uid(transfer_syntax_uid.as_ref().into()).into_owned());
+        self
+    }
+
+    /// Never accept this transfer syntax.
+    pub fn reject<T>(mut self, transfer_syntax_uid: T) -> Self
+    where
+        T: AsRef<str>,
+    {
+        self.rejected_transfer_syntax_uids
+            .push(trim_uid(transfer_syntax_uid.as_ref().into()).into_owned());
+        self
+    }
+
+    /// Override whether to accept only the preferred transfer syntaxes.
+    ///
+    /// The default is `false`.
+    pub fn preferred_only(mut self, preferred_only: bool) -> Self {
+        self.preferred_only = preferred_only;
+        self
+    }
+
+    /// Whether the policy applies to the given abstract syntax.
+    pub fn applies_to(&self, abstract_syntax_uid: &str) -> bool {
+        self.abstract_syntax_uids.is_empty()
+            || self
+                .abstract_syntax_uids
+                .iter()
+                .any(|uid| uid == &trim_uid(Cow::from(abstract_syntax_uid)))
+    }
+
+    /// Choose one of the preferred transfer syntaxes
+    /// out of the proposed ones.
+    fn choose_preferred<T>(&self, proposed: &[T]) -> Option<usize>
+    where
+        T: AsRef<str>,
+    {
+        self.preferred_transfer_syntax_uids
+            .iter()
+            .filter(|uid| !self.is_rejected(uid))
+            .find_map(|uid| {
+                proposed.iter().position(|ts| {
+                    trim_uid(Cow::from(ts.as_ref())) == uid.as_str() && is_supported(uid)
+                })
+            })
+    }
+
+    /// Whether the policy rejects the given transfer syntax.
+    fn is_rejected(&self, transfer_syntax_uid: &str) -> bool {
+        self.rejected_transfer_syntax_uids
+            .iter()
+            .any(|uid| uid == &trim_uid(Cow::from(transfer_syntax_uid)))
+    }
+}
+
 /// A DICOM association builder for an acceptor DICOM node,
 /// often taking the role of a service class provider (SCP).
 ///
@@ -286,6 +526,8 @@ pub struct ServerAssociationOptions<'a, A> {
     abstract_syntax_uids: Vec<Cow<'a, str>>,
     /// the list of requested transfer syntaxes
     transfer_syntax_uids: Vec<Cow<'a, str>>,
+    /// the transfer syntax policies, by order of precedence
+    transfer_syntax_policies: Vec<TransferSyntaxPolicy>,
     /// the expected protocol version
     protocol_version: u16,
     /// the maximum PDU length
@@ -294,8 +536,22 @@ pub struct ServerAssociationOptions<'a, A> {
     strict: bool,
     /// whether to accept unknown abstract syntaxes
     promiscuous: bool,
+    /// whether to accept unknown abstract syntaxes
+    /// by one of their related general SOP classes
+    accept_related_general_sop_classes: bool,
+    /// the roles which the association requestor may take, per SOP class
+    role_selections: Vec<RoleSelection>,
+    /// the limits of the asynchronous operations window, if supported
+    async_operations_window: Option<AsyncOperationsWindow>,
     /// Timeout for individual send/receive operations
     timeout: Option<std::time::Duration>,
+    /// The association request/reject/release timer (ARTIM) value
+    artim_timeout: Duration,
+    /// Observer of the PDUs sent and received
+    pdu_observer: ObserverHandle,
+    /// TLS server configuration
+    #[cfg(feature = "tls")]
+    tls_config: Option<Arc<rustls::ServerConfig>>,
 }
 
 impl Default for ServerAssociationOptions<'_, AcceptAny> {
@@ -306,11 +562,19 @@ impl Default for ServerAssociationOptions<'_, AcceptAny> {
             application_context_name: "1.2.840.10008.3.1.1.1".into(),
             abstract_syntax_uids: Vec::new(),
             transfer_syntax_uids: Vec::new(),
+            transfer_syntax_policies: Vec::new(),
             protocol_version: 1,
             max_pdu_length: DEFAULT_MAX_PDU,
             strict: true,
             promiscuous: false,
+            accept_related_general_sop_classes: false,
+            role_selections: Vec::new(),
+            async_operations_window: None,
             timeout: None,
+            artim_timeout: DEFAULT_ARTIM_TIMEOUT,
+            pdu_observer: ObserverHandle::default(),
+            #[cfg(feature = "tls")]
+            tls_config: None,
         }
     }
 }
@@ -356,12 +620,20 @@ where
             application_context_name,
             abstract_syntax_uids,
             transfer_syntax_uids,
+            transfer_syntax_policies,
             protocol_version,
             max_pdu_length,
             strict,
             promiscuous,
+            accept_related_general_sop_classes,
+            role_selections,
+            async_operations_window,
             ae_access_control: _,
             timeout,
+            artim_timeout,
+            pdu_observer,
+            #[cfg(feature = "tls")]
+            tls_config,
         } = self;
 
         ServerAssociationOptions {
@@ -370,11 +642,19 @@ where
             application_context_name,
             abstract_syntax_uids,
             transfer_syntax_uids,
+            transfer_syntax_policies,
             protocol_version,
             max_pdu_length,
             strict,
             promiscuous,
+            accept_related_general_sop_classes,
+            role_selections,
+            async_operations_window,
             timeout,
+            artim_timeout,
+            pdu_observer,
+            #[cfg(feature = "tls")]
+            tls_config,
         }
     }
 
@@ -410,6 +690,18 @@ where
         self
     }
 
+    /// Apply a transfer syntax policy
+    /// to the presentation contexts of some abstract syntaxes.
+    ///
+    /// Policies are checked in the order in which they were added,
+    /// and only the first one applying to an abstract syntax is used.
+    /// Abstract syntaxes without a policy accept the first transfer syntax proposed
+    /// which is in the list of transfer syntaxes of these options.
+    pub fn with_transfer_syntax_policy(mut self, policy: TransferSyntaxPolicy) -> Self {
+        self.transfer_syntax_policies.push(policy);
+        self
+    }
+
     /// Override the maximum expected PDU length.
     pub fn max_pdu_length(mut self, value: u32) -> Self {
         self.max_pdu_length = value;
@@ -431,6 +723,66 @@ where
         self
     }
 
+    /// Override whether to accept an abstract syntax
+    /// which is not in the list of abstract syntaxes,
+    /// as long as the association requestor declares it
+    /// via SOP class common extended negotiation
+    /// to be a specialization of one of the abstract syntaxes in the list.
+    ///
+    /// This allows, for instance, a storage SCP
+    /// to accept private or new storage SOP classes.
+    /// The default is `false`.
+    pub fn accept_related_general_sop_classes(mut self, accept: bool) -> Self {
+        self.accept_related_general_sop_classes = accept;
+        self
+    }
+
+    /// Accept SCP/SCU role selection for the given SOP class,
+    /// granting the association requestor
+    /// at most the SCU and SCP roles given here.
+    ///
+    /// Role selection proposals for other SOP classes are not answered,
+    /// in which case the association requestor acts as the SCU
+    /// and this node as the SCP.
+    /// This is needed, for instance, so that this node can send
+    /// C-STORE sub-operations of a C-GET request on the same association.
+    pub fn with_role_selection<T>(
+        mut self,
+        sop_class_uid: T,
+        scu_role: bool,
+        scp_role: bool,
+    ) -> Self
+    where
+        T: Into<Cow<'a, str>>,
+    {
+        self.role_selections.push(RoleSelection {
+            sop_class_uid: trim_uid(sop_class_uid.into()).into_owned(),
+            scu_role,
+            scp_role,
+        });
+        self
+    }
+
+    /// Support the negotiation of an asynchronous operations window,
+    /// admitting up to the given numbers of outstanding operations
+    /// invoked and performed by the association requestor.
+    /// A value of 0 admits an unlimited number of operations.
+    ///
+    /// The window is only negotiated if proposed by the association requestor.
+    /// Otherwise, or if this option is not set,
+    /// only one operation may be outstanding at a time.
+    pub fn async_operations_window(
+        mut self,
+        max_operations_invoked: u16,
+        max_operations_performed: u16,
+    ) -> Self {
+        self.async_operations_window = Some(AsyncOperationsWindow {
+            max_operations_invoked,
+            max_operations_performed,
+        });
+        self
+    }
+
     /// Set the timeout for the underlying TCP socket
     pub fn timeout(self, timeout: Duration) -> Self {
         Self {
@@ -439,20 +791,142 @@ where
         }
     }
 
+    /// Set the value of the ARTIM timer,
+    /// which is how long to wait for the association request
+    /// once the connection is accepted,
+    /// and how long to wait for the requestor to close the connection
+    /// after the association is released or aborted.
+    ///
+    /// The default is [`DEFAULT_ARTIM_TIMEOUT`].
+    pub fn artim_timeout(self, timeout: Duration) -> Self {
+        Self {
+            artim_timeout: timeout,
+            ..self
+        }
+    }
+
+    /// Set an observer to be called for every PDU
+    /// sent and received in the association,
+    /// such as a [`CaptureWriter`](super::observer::CaptureWriter).
+    pub fn pdu_observer(self, observer: Arc<dyn PduObserver>) -> Self {
+        Self {
+            pdu_observer: ObserverHandle::new(observer),
+            ..self
+        }
+    }
+
+    /// The read timeout to apply while waiting for the association request,
+    /// which is the ARTIM timeout unless a shorter timeout was set.
+    fn association_request_timeout(&self) -> Duration {
+        self.timeout.map_or(self.artim_timeout, |timeout| {
+            timeout.min(self.artim_timeout)
+        })
+    }
+
+    /// The timeout for individual send/receive operations, if any.
+    pub(crate) fn operation_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Whether a TLS server configuration was set.
+    #[cfg(feature = "tls")]
+    pub(crate) fn has_tls_config(&self) -> bool {
+        self.tls_config.is_some()
+    }
+
+    /// Set the TLS server configuration
+    /// for accepting associations over a secure transport connection
+    /// (see [`establish_tls`](Self::establish_tls)).
+    ///
+    /// For mutual authentication,
+    /// the configuration should also verify client certificates.
+    #[cfg(feature = "tls")]
+    pub fn tls_config(self, config: impl Into<Arc<rustls::ServerConfig>>) -> Self {
+        Self {
+            tls_config: Some(config.into()),
+            ..self
+        }
+    }
+
     /// Negotiate an association with the given TCP stream.
-    pub fn establish(&self, mut socket: TcpStream) -> Result<ServerAssociation<TcpStream>> {
+    pub fn establish(&self, socket: TcpStream) -> Result<ServerAssociation<TcpStream>> {
         ensure!(
             !self.abstract_syntax_uids.is_empty() || self.promiscuous,
             MissingAbstractSyntaxSnafu
         );
+        self.set_socket_timeouts(&socket)?;
+        let peer_addr = socket.peer_addr().ok();
+        self.negotiate(socket, peer_addr)
+    }
 
-        let max_pdu_length = self.max_pdu_length;
+    /// Negotiate an association over any open connection
+    /// to the association requestor.
+    ///
+    /// The connection can be any bidirectional byte stream,
+    /// such as a Unix domain socket or an in-memory pipe.
+    /// The [`timeout`](Self::timeout) option is not applied to the stream,
+    /// so it should be configured beforehand if needed.
+    pub fn establish_stream<S>(&self, stream: S) -> Result<ServerAssociation<S>>
+    where
+        S: Read + Write + CloseSocket,
+    {
+        ensure!(
+            !self.abstract_syntax_uids.is_empty() || self.promiscuous,
+            MissingAbstractSyntaxSnafu
+        );
+        self.negotiate(stream, None)
+    }
+
+    /// Perform a TLS handshake on the given TCP stream
+    /// and negotiate an association over the secure connection.
+    ///
+    /// A TLS configuration must have been set
+    /// via [`tls_config`](Self::tls_config).
+    #[cfg(feature = "tls")]
+    pub fn establish_tls(&self, socket: TcpStream) -> Result<ServerAssociation<TlsStream>> {
+        ensure!(
+            !self.abstract_syntax_uids.is_empty() || self.promiscuous,
+            MissingAbstractSyntaxSnafu
+        );
+        let tls_config = self.tls_config.clone().context(MissingTlsConfigSnafu)?;
+        self.set_socket_timeouts(&socket)?;
+        let peer_addr = socket.peer_addr().ok();
+
+        let connection = rustls::ServerConnection::new(tls_config).context(TlsSessionSnafu)?;
+        let mut stream = rustls::StreamOwned::new(connection, socket);
+        while stream.conn.is_handshaking() {
+            stream
+                .conn
+                .complete_io(&mut stream.sock)
+                .context(TlsHandshakeSnafu)?;
+        }
+        self.negotiate(stream, peer_addr)
+    }
+
+    fn set_socket_timeouts(&self, socket: &TcpStream) -> Result<()> {
         socket
             .set_read_timeout(self.timeout)
             .context(SetReadTimeoutSnafu)?;
         socket
             .set_write_timeout(self.timeout)
-            .context(SetWriteTimeoutSnafu)?;
+            .context(SetWriteTimeoutSnafu)
+    }
+
+    /// Negotiate an association through an open connection.
+    fn negotiate<S>(
+        &self,
+        mut socket: S,
+        peer_addr: Option<SocketAddr>,
+    ) -> Result<ServerAssociation<S>>
+    where
+        S: Read + Write + CloseSocket,
+    {
+        let max_pdu_length = self.max_pdu_length;
+        let mut state = StateMachine::new(Side::Acceptor);
+        state.handle(Event::TransportConnectIndication);
+        // start the ARTIM timer,
+        // streams without read timeouts wait for the request indefinitely
+        let _ = socket.set_read_timeout(Some(self.association_request_timeout()));
 
         let mut read_buffer = BytesMut::with_capacity(MAXIMUM_PDU_SIZE as usize);
         let mut reader = BufReader::new(&mut socket);
@@ -461,7 +935,10 @@ where
             let mut buf = Cursor::new(&read_buffer[..]);
             match read_pdu(&mut buf, MAXIMUM_PDU_SIZE, self.strict).context(ReceiveRequestSnafu)? {
                 Some(pdu) => {
-                    read_buffer.advance(buf.position() as usize);
+                    let len = buf.position() as usize;
+                    self.pdu_observer
+                        .observe(Direction::Received, &read_buffer[..len]);
+                    read_buffer.advance(len);
                     break pdu;
                 }
                 None => {
@@ -479,169 +956,335 @@ where
             read_buffer.extend_from_slice(&recv);
             ensure!(!recv.is_empty(), ConnectionClosedSnafu);
         };
+        // stop the ARTIM timer
+        let _ = socket.set_read_timeout(self.timeout);
+
+        let event = Event::received(&msg);
+        let action = state.handle(event);
         let mut buffer: Vec<u8> = Vec::with_capacity(max_pdu_length as usize);
         match msg {
-            Pdu::AssociationRQ(AssociationRQ {
-                protocol_version,
-                calling_ae_title,
-                called_ae_title,
-                application_context_name,
-                presentation_contexts,
-                user_variables,
-            }) => {
-                if protocol_version != self.protocol_version {
-                    write_pdu(
-                        &mut buffer,
-                        &Pdu::AssociationRJ(AssociationRJ {
-                            result: AssociationRJResult::Permanent,
-                            source: AssociationRJSource::ServiceUser(
-                                AssociationRJServiceUserReason::NoReasonGiven,
-                            ),
-                        }),
-                    )
-                    .context(SendResponseSnafu)?;
-                    socket.write_all(&buffer).context(WireSendSnafu)?;
-                    return RejectedSnafu.fail();
-                }
-
-                if application_context_name != self.application_context_name {
-                    write_pdu(
-                        &mut buffer,
-                        &Pdu::AssociationRJ(AssociationRJ {
-                            result: AssociationRJResult::Permanent,
-                            source: AssociationRJSource::ServiceUser(
-                                AssociationRJServiceUserReason::ApplicationContextNameNotSupported,
-                            ),
-                        }),
-                    )
-                    .context(SendResponseSnafu)?;
-                    socket.write_all(&buffer).context(WireSendSnafu)?;
-                    return RejectedSnafu.fail();
-                }
-
-                self.ae_access_control
-                    .check_access(
-                        &self.ae_title,
-                        &calling_ae_title,
-                        &called_ae_title,
-                        user_variables
-                            .iter()
-                            .find_map(|user_variable| match user_variable {
-                                UserVariableItem::UserIdentityItem(user_identity) => {
-                                    Some(user_identity)
-                                }
-                                _ => None,
-                            }),
-                    )
-                    .map(Ok)
-                    .unwrap_or_else(|reason| {
-                        write_pdu(
-                            &mut buffer,
-                            &Pdu::AssociationRJ(AssociationRJ {
-                                result: AssociationRJResult::Permanent,
-                                source: AssociationRJSource::ServiceUser(reason),
-                            }),
-                        )
-                        .context(SendResponseSnafu)?;
+            Pdu::AssociationRQ(association_rq) => {
+                let negotiated = match self.process_association_rq(association_rq, peer_addr) {
+                    Ok(negotiated) => negotiated,
+                    Err(association_rj) => {
+                        state.handle(Event::AssociateReject);
+                        write_pdu(&mut buffer, &Pdu::AssociationRJ(association_rj))
+                            .context(SendResponseSnafu)?;
+                        self.pdu_observer.observe(Direction::Sent, &buffer);
                         socket.write_all(&buffer).context(WireSendSnafu)?;
-                        RejectedSnafu.fail()
-                    })?;
-
-                // fetch requested maximum PDU length
-                let requestor_max_pdu_length = user_variables
-                    .iter()
-                    .find_map(|item| match item {
-                        UserVariableItem::MaxLength(len) => Some(*len),
-                        _ => None,
-                    })
-                    .unwrap_or(DEFAULT_MAX_PDU);
-
-                // treat 0 as the maximum size admitted by the standard
-                let requestor_max_pdu_length = if requestor_max_pdu_length == 0 {
-                    MAXIMUM_PDU_SIZE
-                } else {
-                    requestor_max_pdu_length
+                        return RejectedSnafu.fail();
+                    }
                 };
-
-                let presentation_contexts: Vec<_> = presentation_contexts
-                    .into_iter()
-                    .map(|pc| {
-                        if !self
-                            .abstract_syntax_uids
-                            .contains(&trim_uid(Cow::from(pc.abstract_syntax)))
-                            && !self.promiscuous
-                        {
-                            return PresentationContextResult {
-                                id: pc.id,
-                                reason: PresentationContextResultReason::AbstractSyntaxNotSupported,
-                                transfer_syntax: "1.2.840.10008.1.2".to_string(),
-                            };
-                        }
-
-                        let (transfer_syntax, reason) = self
-                            .choose_ts(pc.transfer_syntaxes)
-                            .map(|ts| (ts, PresentationContextResultReason::Acceptance))
-                            .unwrap_or_else(|| {
-                                (
-                                    "1.2.840.10008.1.2".to_string(),
-                                    PresentationContextResultReason::TransferSyntaxesNotSupported,
-                                )
-                            });
-
-                        PresentationContextResult {
-                            id: pc.id,
-                            reason,
-                            transfer_syntax,
-                        }
-                    })
-                    .collect();
-
-                write_pdu(
-                    &mut buffer,
-                    &Pdu::AssociationAC(AssociationAC {
-                        protocol_version: self.protocol_version,
-                        application_context_name,
-                        presentation_contexts: presentation_contexts.clone(),
-                        calling_ae_title: calling_ae_title.clone(),
-                        called_ae_title,
-                        user_variables: vec![
-                            UserVariableItem::MaxLength(max_pdu_length),
-                            UserVariableItem::ImplementationClassUID(
-                                IMPLEMENTATION_CLASS_UID.to_string(),
-                            ),
-                            UserVariableItem::ImplementationVersionName(
-                                IMPLEMENTATION_VERSION_NAME.to_string(),
-                            ),
-                        ],
-                    }),
-                )
-                .context(SendResponseSnafu)?;
+                state.handle(Event::AssociateAccept);
+                write_pdu(&mut buffer, &Pdu::AssociationAC(negotiated.association_ac))
+                    .context(SendResponseSnafu)?;
+                self.pdu_observer.observe(Direction::Sent, &buffer);
                 socket.write_all(&buffer).context(WireSendSnafu)?;
 
                 Ok(ServerAssociation {
-                    presentation_contexts,
-                    requestor_max_pdu_length,
+                    presentation_contexts: negotiated.presentation_contexts,
+                    negotiated_contexts: negotiated.negotiated_contexts,
+                    async_operations_window: negotiated.async_operations_window,
+                    requestor_max_pdu_length: negotiated.requestor_max_pdu_length,
                     acceptor_max_pdu_length: max_pdu_length,
                     socket,
-                    client_ae_title: calling_ae_title,
+                    client_ae_title: negotiated.client_ae_title,
+                    user_variables: negotiated.user_variables,
                     buffer,
                     strict: self.strict,
                     read_buffer: BytesMut::with_capacity(MAXIMUM_PDU_SIZE as usize),
-                    timeout: self.timeout,
+                    artim_timeout: self.artim_timeout,
+                    state,
+                    message_id: 1,
+                    message_assembler: MessageAssembler::default(),
+                    pdu_observer: self.pdu_observer.clone(),
                 })
             }
-            Pdu::ReleaseRQ => {
-                write_pdu(&mut buffer, &Pdu::ReleaseRP).context(SendResponseSnafu)?;
-                socket.write_all(&buffer).context(WireSendSnafu)?;
-                AbortedSnafu.fail()
+            Pdu::AbortRQ { .. } => AbortedSnafu.fail(),
+            pdu => {
+                // any other PDU is answered with an A-ABORT
+                if let Some(abort) = action.and_then(|action| action.abort_pdu(event)) {
+                    let _ = write_pdu(&mut buffer, &abort);
+                    self.pdu_observer.observe(Direction::Sent, &buffer);
+                    let _ = socket.write_all(&buffer);
+                }
+                match pdu {
+                    pdu @ Pdu::Unknown { .. } => UnknownRequestSnafu { pdu }.fail(),
+                    pdu => UnexpectedRequestSnafu { pdu }.fail(),
+                }
+            }
+        }
+    }
+
+    /// Decide on an association request,
+    /// producing either the A-ASSOCIATE-AC to send back
+    /// along with the negotiated association parameters,
+    /// or the A-ASSOCIATE-RJ to reject the association with.
+    fn process_association_rq(
+        &self,
+        association_rq: AssociationRQ,
+        peer_addr: Option<SocketAddr>,
+    ) -> std::result::Result<NegotiatedAssociation, AssociationRJ> {
+        let AssociationRQ {
+            protocol_version,
+            calling_ae_title,
+            called_ae_title,
+            application_context_name,
+            presentation_contexts,
+            user_variables,
+        } = association_rq;
+
+        if protocol_version != self.protocol_version {
+            return Err(AssociationRJ {
+                result: AssociationRJResult::Permanent,
+                source: AssociationRJSource::ServiceUser(
+                    AssociationRJServiceUserReason::NoReasonGiven,
+                ),
+            });
+        }
+
+        if application_context_name != self.application_context_name {
+            return Err(AssociationRJ {
+                result: AssociationRJResult::Permanent,
+                source: AssociationRJSource::ServiceUser(
+                    AssociationRJServiceUserReason::ApplicationContextNameNotSupported,
+                ),
+            });
+        }
+
+        let user_identity = find_user_identity(&user_variables);
+        let reject = |reason| AssociationRJ {
+            result: AssociationRJResult::Permanent,
+            source: AssociationRJSource::ServiceUser(reason),
+        };
+        self.ae_access_control
+            .check_peer_address(&calling_ae_title, peer_addr)
+            .map_err(reject)?;
+        self.ae_access_control
+            .check_access(
+                &self.ae_title,
+                &calling_ae_title,
+                &called_ae_title,
+                user_identity,
+            )
+            .map_err(reject)?;
+
+        // only give a server response if the identity was verified
+        let user_identity_response = match user_identity {
+            Some(user_identity) => self
+                .ae_access_control
+                .verify_user_identity(&calling_ae_title, user_identity)
+                .map_err(reject)?
+                .filter(|_| user_identity.positive_response_requested()),
+            None => None,
+        };
+
+        // fetch requested maximum PDU length
+        let requestor_max_pdu_length = user_variables
+            .iter()
+            .find_map(|item| match item {
+                UserVariableItem::MaxLength(len) => Some(*len),
+                _ => None,
+            })
+            .unwrap_or(DEFAULT_MAX_PDU);
+
+        // treat 0 as the maximum size admitted by the standard
+        let requestor_max_pdu_length = if requestor_max_pdu_length == 0 {
+            MAXIMUM_PDU_SIZE
+        } else {
+            requestor_max_pdu_length
+        };
+
+        // answer role selection proposals for the SOP classes configured,
+        // never granting more than what was proposed
+        let role_selections: Vec<RoleSelection> = user_variables
+            .iter()
+            .filter_map(|item| match item {
+                UserVariableItem::RoleSelectionSubItem(proposed) => {
+                    let sop_class_uid = trim_uid(Cow::from(proposed.sop_class_uid.as_str()));
+                    self.role_selections
+                        .iter()
+                        .find(|granted| granted.sop_class_uid == sop_class_uid)
+                        .map(|granted| RoleSelection {
+                            sop_class_uid: proposed.sop_class_uid.clone(),
+                            scu_role: proposed.scu_role && granted.scu_role,
+                            scp_role: proposed.scp_role && granted.scp_role,
+                        })
+                }
+                _ => None,
+            })
+            .collect();
+
+        let mut negotiated_contexts = Vec::new();
+        let presentation_contexts: Vec<_> = presentation_contexts
+            .into_iter()
+            .map(|pc| {
+                if !self.is_abstract_syntax_supported(&pc.abstract_syntax, &user_variables)
+                    || !self
+                        .ae_access_control
+                        .is_abstract_syntax_allowed(&calling_ae_title, &pc.abstract_syntax)
+                {
+                    return PresentationContextResult {
+                        id: pc.id,
+                        reason: PresentationContextResultReason::AbstractSyntaxNotSupported,
+                        transfer_syntax: "1.2.840.10008.1.2".to_string(),
+                    };
+                }
+
+                let PresentationContextProposed {
+                    id,
+                    abstract_syntax,
+                    transfer_syntaxes,
+                } = pc;
+                let (transfer_syntax, reason) = self
+                    .choose_ts_for(&abstract_syntax, transfer_syntaxes)
+                    .map(|ts| (ts, PresentationContextResultReason::Acceptance))
+                    .unwrap_or_else(|| {
+                        (
+                            "1.2.840.10008.1.2".to_string(),
+                            PresentationContextResultReason::TransferSyntaxesNotSupported,
+                        )
+                    });
+
+                if reason == PresentationContextResultReason::Acceptance {
+                    let roles = role_selections
+                        .iter()
+                        .find(|role_selection| {
+                            trim_uid(Cow::from(role_selection.sop_class_uid.as_str()))
+                                == trim_uid(Cow::from(abstract_syntax.as_str()))
+                        })
+                        .map(|role_selection| Roles {
+                            scu: role_selection.scu_role,
+                            scp: role_selection.scp_role,
+                        })
+                        .unwrap_or_default();
+                    negotiated_contexts.push(NegotiatedContext {
+                        id,
+                        abstract_syntax,
+                        roles,
+                    });
+                }
+
+                PresentationContextResult {
+                    id,
+                    reason,
+                    transfer_syntax,
+                }
+            })
+            .collect();
+
+        // answer the asynchronous operations window proposal, if supported
+        let async_operations_window =
+            find_async_operations_window(&user_variables).and_then(|proposed| {
+                self.async_operations_window
+                    .map(|limits| restrict_async_operations_window(proposed, limits))
+            });
+
+        let mut ac_user_variables = vec![
+            UserVariableItem::MaxLength(self.max_pdu_length),
+            UserVariableItem::ImplementationClassUID(IMPLEMENTATION_CLASS_UID.to_string()),
+            UserVariableItem::ImplementationVersionName(IMPLEMENTATION_VERSION_NAME.to_string()),
+        ];
+        ac_user_variables
+            .extend(async_operations_window.map(UserVariableItem::AsyncOperationsWindowSubItem));
+        ac_user_variables.extend(
+            role_selections
+                .into_iter()
+                .map(UserVariableItem::RoleSelectionSubItem),
+        );
+        ac_user_variables
+            .extend(user_identity_response.map(UserVariableItem::UserIdentityServerResponseItem));
+
+        Ok(NegotiatedAssociation {
+            association_ac: AssociationAC {
+                protocol_version: self.protocol_version,
+                application_context_name,
+                presentation_contexts: presentation_contexts.clone(),
+                calling_ae_title: calling_ae_title.clone(),
+                called_ae_title,
+                user_variables: ac_user_variables,
+            },
+            presentation_contexts,
+            negotiated_contexts,
+            async_operations_window: async_operations_window.unwrap_or_default(),
+            requestor_max_pdu_length,
+            client_ae_title: calling_ae_title,
+            user_variables,
+        })
+    }
+
+    /// Check whether the given abstract syntax can be accepted,
+    /// taking SOP class common extended negotiation into account.
+    fn is_abstract_syntax_supported(
+        &self,
+        abstract_syntax: &str,
+        user_variables: &[UserVariableItem],
+    ) -> bool {
+        if self.promiscuous
+            || self
+                .abstract_syntax_uids
+                .contains(&trim_uid(Cow::from(abstract_syntax)))
+        {
+            return true;
+        }
+        if !self.accept_related_general_sop_classes {
+            return false;
+        }
+        let abstract_syntax = trim_uid(Cow::from(abstract_syntax));
+        user_variables.iter().any(|item| match item {
+            UserVariableItem::SopClassCommonExtendedNegotiationSubItem(negotiation) => {
+                trim_uid(Cow::from(negotiation.sop_class_uid.as_str())) == abstract_syntax
+                    && negotiation
+                        .related_general_sop_class_uids
+                        .iter()
+                        .any(|uid| {
+                            self.abstract_syntax_uids
+                                .contains(&trim_uid(Cow::from(uid.as_str())))
+                        })
             }
-            pdu @ Pdu::AssociationAC { .. }
-            | pdu @ Pdu::AssociationRJ { .. }
-            | pdu @ Pdu::PData { .. }
-            | pdu @ Pdu::ReleaseRP
-            | pdu @ Pdu::AbortRQ { .. } => UnexpectedRequestSnafu { pdu }.fail(),
-            pdu @ Pdu::Unknown { .. } => UnknownRequestSnafu { pdu }.fail(),
+            _ => false,
+        })
+    }
+
+    /// Choose the transfer syntax of a presentation context
+    /// proposed for the given abstract syntax,
+    /// following the first transfer syntax policy which applies to it.
+    fn choose_ts_for(&self, abstract_syntax: &str, proposed: Vec<String>) -> Option<String> {
+        let policy = match self
+            .transfer_syntax_policies
+            .iter()
+            .find(|policy| policy.applies_to(abstract_syntax))
+        {
+            Some(policy) => policy,
+            None => return self.choose_ts(proposed),
+        };
+
+        if let Some(i) = policy.choose_preferred(&proposed) {
+            debug!(
+                "Accepting preferred transfer syntax {} for {}",
+                proposed[i], abstract_syntax
+            );
+            return proposed.into_iter().nth(i);
+        }
+        if policy.preferred_only {
+            debug!(
+                "Rejecting transfer syntaxes {:?} for {}: none of them is preferred",
+                proposed, abstract_syntax
+            );
+            return None;
+        }
+        let chosen = self.choose_ts(proposed.iter().filter(|ts| !policy.is_rejected(ts)));
+        match chosen {
+            Some(ts) => debug!(
+                "Accepting transfer syntax {} for {} (no preferred transfer syntax proposed)",
+                ts, abstract_syntax
+            ),
+            None => debug!(
+                "Rejecting transfer syntaxes {:?} for {}",
+                proposed, abstract_syntax
+            ),
         }
+        chosen.cloned()
     }
 
     /// From a sequence of transfer syntaxes,
@@ -671,6 +1314,24 @@ where
     }
 }
 
+/// The outcome of accepting an association request.
+struct NegotiatedAssociation {
+    /// the response to send to the association requestor
+    association_ac: AssociationAC,
+    /// the presentation context results
+    presentation_contexts: Vec<PresentationContextResult>,
+    /// details about the accepted presentation contexts
+    negotiated_contexts: Vec<NegotiatedContext>,
+    /// the negotiated asynchronous operations window
+    async_operations_window: AsyncOperationsWindow,
+    /// the maximum PDU length that the association requestor accepts
+    requestor_max_pdu_length: u32,
+    /// the application entity title of the association requestor
+    client_ae_title: String,
+    /// the user variables sent by the association requestor
+    user_variables: Vec<UserVariableItem>,
+}
+
 /// A DICOM upper level association from the perspective
 /// of an accepting application entity.
 ///
@@ -680,17 +1341,31 @@ where
 /// Sending large P-Data fragments may be easier through the P-Data sender
 /// abstraction (see [`send_pdata`](Self::send_pdata)).
 ///
+/// The association can be terminated by this node
+/// via [`release`](Self::release),
+/// [`graceful_shutdown`](Self::graceful_shutdown),
+/// or [`abort`](Self::abort).
 /// When the value falls out of scope,
-/// the program will shut down the underlying TCP connection.
+/// the program will shut down the underlying connection
+/// without notifying the requestor.
+///
+/// Associations established via `establish_async`
+/// are instead of type [`AsyncServerAssociation`](non_blocking::AsyncServerAssociation).
 #[derive(Debug)]
 pub struct ServerAssociation<S> {
     /// The accorded presentation contexts
     presentation_contexts: Vec<PresentationContextResult>,
+    /// Details about the accepted presentation contexts
+    negotiated_contexts: Vec<NegotiatedContext>,
+    /// The negotiated asynchronous operations window
+    async_operations_window: AsyncOperationsWindow,
+    /// User variables that were taken from the association request
+    user_variables: Vec<UserVariableItem>,
     /// The maximum PDU length that the remote application entity accepts
     requestor_max_pdu_length: u32,
     /// The maximum PDU length that this application entity is expecting to receive
     acceptor_max_pdu_length: u32,
-    /// The TCP stream to the other DICOM node
+    /// The stream to the other DICOM node
     socket: S,
     /// The application entity title of the other DICOM node
     client_ae_title: String,
@@ -700,8 +1375,16 @@ pub struct ServerAssociation<S> {
     strict: bool,
     /// Read buffer from the socket
     read_buffer: bytes::BytesMut,
-    /// Timeout for individual send/receive operations
-    timeout: Option<std::time::Duration>,
+    /// Timeout for the requestor to close the connection after the association ends
+    artim_timeout: Duration,
+    /// The upper layer protocol state machine
+    state: StateMachine,
+    /// The message ID to use in the next DIMSE request
+    message_id: u16,
+    /// DIMSE message fragments received so far
+    message_assembler: MessageAssembler,
+    /// Observer of the PDUs sent and received
+    pdu_observer: ObserverHandle,
 }
 
 impl<S> ServerAssociation<S> {
@@ -710,14 +1393,87 @@ impl<S> ServerAssociation<S> {
         &self.presentation_contexts
     }
 
+    /// Obtain the abstract syntax of an accepted presentation context.
+    pub fn abstract_syntax(&self, presentation_context_id: u8) -> Option<&str> {
+        self.negotiated_contexts
+            .iter()
+            .find(|pc| pc.id == presentation_context_id)
+            .map(|pc| pc.abstract_syntax.as_str())
+    }
+
+    /// Obtain the roles which the association requestor may take
+    /// in an accepted presentation context.
+    ///
+    /// When the requestor is allowed to take the SCP role,
+    /// this node may issue requests to it through that presentation context.
+    pub fn roles(&self, presentation_context_id: u8) -> Option<Roles> {
+        self.negotiated_contexts
+            .iter()
+            .find(|pc| pc.id == presentation_context_id)
+            .map(|pc| pc.roles)
+    }
+
+    /// Obtain the asynchronous operations window of the association,
+    /// from the perspective of the association requestor.
+    pub fn async_operations_window(&self) -> AsyncOperationsWindow {
+        self.async_operations_window
+    }
+
+    /// Retrieve the user variables that were taken from the association request.
+    ///
+    /// Besides the maximum PDU length and the implementation identification,
+    /// these may include extended negotiation sub-items,
+    /// such as SOP class common extended negotiation.
+    pub fn user_variables(&self) -> &[UserVariableItem] {
+        &self.user_variables
+    }
+
+    /// Retrieve the user identity presented by the association requestor,
+    /// if any.
+    ///
+    /// The identity was accepted by the
+    /// [access control policy](AccessControl::verify_user_identity).
+    pub fn user_identity(&self) -> Option<&UserIdentity> {
+        find_user_identity(&self.user_variables)
+    }
+
     /// Obtain the remote DICOM node's application entity title.
     pub fn client_ae_title(&self) -> &str {
         &self.client_ae_title
     }
+
+    /// Retrieve the current state of the upper layer protocol machine.
+    pub fn state(&self) -> State {
+        self.state.state()
+    }
+
+    /// Retrieve the ARTIM timeout of the association.
+    pub(crate) fn artim_timeout(&self) -> Duration {
+        self.artim_timeout
+    }
+
+    /// Obtain a new message ID for a DIMSE request
+    /// to be sent through this association,
+    /// such as C-STORE sub-operations or event reports.
+    ///
+    /// Message IDs start at 1 and are incremented on each call.
+    pub fn next_message_id(&mut self) -> u16 {
+        let message_id = self.message_id;
+        self.message_id = self.message_id.wrapping_add(1);
+        message_id
+    }
 }
 
-impl ServerAssociation<TcpStream> {
+impl<S> ServerAssociation<S>
+where
+    S: Read + Write + CloseSocket,
+{
     /// Send a PDU message to the other intervenient.
+    ///
+    /// Fails without sending anything
+    /// if the PDU is not allowed in the current state of the association.
+    /// Sending an A-RELEASE-RP or an A-ABORT waits up to the ARTIM timeout
+    /// for the requestor to close the connection.
     pub fn send(&mut self, msg: &Pdu) -> Result<()> {
         self.buffer.clear();
         write_pdu(&mut self.buffer, msg).context(SendSnafu)?;
@@ -727,11 +1483,89 @@ impl ServerAssociation<TcpStream> {
             }
             .fail();
         }
-        self.socket.write_all(&self.buffer).context(WireSendSnafu)
+        if let Some(event) = Event::sent(msg) {
+            self.transition(event)?;
+        }
+        self.pdu_observer.observe(Direction::Sent, &self.buffer);
+        self.socket.write_all(&self.buffer).context(WireSendSnafu)?;
+        if self.state.state() == State::AwaitingTransportClose {
+            self.await_transport_close();
+        }
+        Ok(())
     }
 
     /// Read a PDU message from the other intervenient.
+    ///
+    /// A PDU which is not expected in the current state of the association
+    /// is answered with an A-ABORT, resulting in an error.
     pub fn receive(&mut self) -> Result<Pdu> {
+        let pdu = match self.receive_pdu() {
+            Ok(pdu) => pdu,
+            Err(e @ Error::ConnectionClosed) => {
+                self.state.handle(Event::TransportClosed);
+                let _ = self.socket.close();
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+        let event = Event::received(&pdu);
+        let action = self.transition(event)?;
+        if let Some(abort) = action.abort_pdu(event) {
+            self.buffer.clear();
+            write_pdu(&mut self.buffer, &abort).context(SendSnafu)?;
+            self.pdu_observer.observe(Direction::Sent, &self.buffer);
+            let _ = self.socket.write_all(&self.buffer);
+            self.await_transport_close();
+            return match pdu {
+                pdu @ Pdu::Unknown { .. } => UnknownRequestSnafu { pdu }.fail(),
+                pdu => UnexpectedRequestSnafu { pdu }.fail(),
+            };
+        }
+        if action.closes_transport() {
+            let _ = self.socket.close();
+        }
+        Ok(pdu)
+    }
+
+    /// Feed an event to the upper layer state machine,
+    /// failing if the event is not allowed in the current state.
+    fn transition(&mut self, event: Event) -> Result<Action> {
+        let state = self.state.state();
+        match self.state.handle(event) {
+            Some(action) => Ok(action),
+            None => InvalidStateSnafu { state, event }.fail(),
+        }
+    }
+
+    /// Wait for the requestor to close the connection
+    /// for up to the ARTIM timeout, then close it.
+    fn await_transport_close(&mut self) {
+        if self
+            .socket
+            .set_read_timeout(Some(self.artim_timeout))
+            .is_ok()
+        {
+            let mut buf = [0; 1024];
+            let closed = loop {
+                match self.socket.read(&mut buf) {
+                    Ok(0) => break true,
+                    Ok(_) => continue,
+                    Err(_) => break false,
+                }
+            };
+            if closed {
+                self.state.handle(Event::TransportClosed);
+            } else {
+                self.state.handle(Event::ArtimExpired);
+            }
+        } else {
+            self.state.handle(Event::ArtimExpired);
+        }
+        let _ = self.socket.close();
+    }
+
+    /// Read the next PDU from the wire.
+    fn receive_pdu(&mut self) -> Result<Pdu> {
         use std::io::{BufRead, BufReader, Cursor};
 
         let mut reader = BufReader::new(&mut self.socket);
@@ -742,7 +1576,10 @@ impl ServerAssociation<TcpStream> {
                 .context(ReceiveRequestSnafu)?
             {
                 Some(pdu) => {
-                    self.read_buffer.advance(buf.position() as usize);
+                    let len = buf.position() as usize;
+                    self.pdu_observer
+                        .observe(Direction::Received, &self.read_buffer[..len]);
+                    self.read_buffer.advance(len);
                     return Ok(pdu);
                 }
                 None => {
@@ -763,8 +1600,11 @@ impl ServerAssociation<TcpStream> {
     }
 
     /// Send a provider initiated abort message
-    /// and shut down the TCP connection,
+    /// and shut down the connection,
     /// terminating the association.
+    ///
+    /// The requestor is given up to the ARTIM timeout
+    /// to close the connection first.
     pub fn abort(mut self) -> Result<()> {
         let pdu = Pdu::AbortRQ {
             source: AbortRQSource::ServiceProvider(
@@ -772,21 +1612,139 @@ impl ServerAssociation<TcpStream> {
             ),
         };
         let out = self.send(&pdu);
-        let _ = self.socket.shutdown(std::net::Shutdown::Both);
+        let _ = self.socket.close();
+        out
+    }
+
+    /// Gracefully terminate the association by exchanging release messages
+    /// and then shutting down the connection.
+    ///
+    /// DIMSE messages still in transit from the requestor are discarded;
+    /// use [`release_with`](Self::release_with) to receive them.
+    /// A release collision,
+    /// in which the requestor requests a release at the same time,
+    /// is resolved as specified in the standard.
+    pub fn release(self) -> Result<()> {
+        self.release_with(|_| {})
+    }
+
+    /// Gracefully terminate the association by exchanging release messages
+    /// and then shutting down the connection,
+    /// passing each DIMSE message received from the requestor
+    /// while awaiting the release response to `on_message`.
+    ///
+    /// These messages can no longer be answered on this association,
+    /// since no P-Data may be sent after requesting a release.
+    pub fn release_with<F>(mut self, mut on_message: F) -> Result<()>
+    where
+        F: FnMut(Message),
+    {
+        let out = self.release_impl(&mut on_message);
+        let _ = self.socket.close();
         out
     }
 
+    /// Gracefully terminate the association
+    /// after the DIMSE message currently being received, if any,
+    /// has been received in full.
+    ///
+    /// Each pending message is passed to `respond`,
+    /// and the response command returned, if any,
+    /// is sent through the same presentation context
+    /// before the association is [released](Self::release).
+    /// The rest of the message and the release response
+    /// are awaited for up to the ARTIM timeout,
+    /// so this is suitable for closing idle associations
+    /// once a [`receive_message`](Self::receive_message) times out.
+    pub fn graceful_shutdown<F>(mut self, mut respond: F) -> Result<()>
+    where
+        F: FnMut(Message) -> Option<Command>,
+    {
+        // not all streams support read timeouts
+        let _ = self.socket.set_read_timeout(Some(self.artim_timeout));
+        while let Some(message) = self.receive_pending_message()? {
+            let presentation_context_id = message.presentation_context_id;
+            if let Some(response) = respond(message) {
+                self.send_message(presentation_context_id, &response, None)?;
+            }
+        }
+        if self.state.state() != State::Established {
+            // the requestor released the association in the meantime
+            return Ok(());
+        }
+        self.release()
+    }
+
+    /// Receive the next DIMSE message
+    /// only if part of it has already been received.
+    ///
+    /// Returns `None` without reading from the connection otherwise.
+    pub fn receive_pending_message(&mut self) -> Result<Option<Message>> {
+        if self.message_assembler.is_empty() && self.read_buffer.is_empty() {
+            return Ok(None);
+        }
+        self.receive_message()
+    }
+
+    /// Receive the command set of the next DIMSE message
+    /// only if part of the message has already been received.
+    ///
+    /// Returns `None` without reading from the connection otherwise.
+    /// See [`receive_command`](Self::receive_command).
+    pub fn receive_pending_command(&mut self) -> Result<Option<MessageCommand>> {
+        if self.message_assembler.is_empty() && self.read_buffer.is_empty() {
+            return Ok(None);
+        }
+        self.receive_command()
+    }
+
+    /// Release implementation function,
+    /// which tries to send a release request and receive a release response,
+    /// passing on the messages still in transit from the requestor.
+    fn release_impl(&mut self, on_message: &mut dyn FnMut(Message)) -> Result<()> {
+        self.send(&Pdu::ReleaseRQ)?;
+
+        loop {
+            match self.receive()? {
+                // release collision: respond after the requestor
+                Pdu::ReleaseRP
+                    if self.state.state() == State::AcceptorCollisionAwaitingLocalResponse =>
+                {
+                    return self.send(&Pdu::ReleaseRP);
+                }
+                Pdu::ReleaseRP => return Ok(()),
+                // data still in transit from the requestor
+                Pdu::PData { data } => {
+                    self.message_assembler.push(data);
+                    while let Some(message) = self
+                        .message_assembler
+                        .next_message()
+                        .context(ReadMessageSnafu)?
+                    {
+                        on_message(message);
+                    }
+                }
+                // release collision: wait for the requestor's response
+                Pdu::ReleaseRQ => {}
+                Pdu::AbortRQ { .. } => return AbortedSnafu.fail(),
+                pdu @ Pdu::Unknown { .. } => return UnknownRequestSnafu { pdu }.fail(),
+                pdu => return UnexpectedRequestSnafu { pdu }.fail(),
+            }
+        }
+    }
+
     /// Prepare a P-Data writer for sending
     /// one or more data item PDUs.
     ///
     /// Returns a writer which automatically
     /// splits the inner data into separate PDUs if necessary.
-    pub fn send_pdata(&mut self, presentation_context_id: u8) -> PDataWriter<&mut TcpStream> {
+    pub fn send_pdata(&mut self, presentation_context_id: u8) -> PDataWriter<&mut S> {
         PDataWriter::new(
             &mut self.socket,
             presentation_context_id,
             self.requestor_max_pdu_length,
         )
+        .with_observer(self.pdu_observer.clone())
     }
 
     /// Prepare a P-Data reader for receiving
@@ -794,16 +1752,110 @@ impl ServerAssociation<TcpStream> {
     ///
     /// Returns a reader which automatically
     /// receives more data PDUs once the bytes collected are consumed.
-    pub fn receive_pdata(&mut self) -> PDataReader<&mut TcpStream> {
+    pub fn receive_pdata(&mut self) -> PDataReader<&mut S> {
+        PDataReader::new(
+            &mut self.socket,
+            self.acceptor_max_pdu_length,
+            &mut self.read_buffer,
+        )
+        .with_observer(self.pdu_observer.clone())
+    }
+
+    /// Send a DIMSE message to the association requester.
+    ///
+    /// The command is encoded in _Implicit VR Little Endian_,
+    /// whereas `data` must already be encoded
+    /// in the transfer syntax of the given presentation context.
+    /// The data set is split into multiple PDUs if necessary.
+    pub fn send_message(
+        &mut self,
+        presentation_context_id: u8,
+        command: &Command,
+        data: Option<&[u8]>,
+    ) -> Result<()> {
+        let (pdu, remaining) = command_pdu(
+            presentation_context_id,
+            command,
+            data,
+            self.requestor_max_pdu_length,
+        );
+        self.send(&pdu)?;
+        if let Some(data) = remaining {
+            let mut writer = self.send_pdata(presentation_context_id);
+            writer.write_all(data).context(WireSendSnafu)?;
+            writer.finish().context(WireSendSnafu)?;
+        }
+        Ok(())
+    }
+
+    /// Receive the next DIMSE message from the association requester,
+    /// comprising the command and the data set which follows it, if any.
+    ///
+    /// If the requester asks to release the association instead,
+    /// the release is confirmed and `None` is returned
+    /// once the requester closes the connection.
+    /// An abort from the requester results in an error.
+    pub fn receive_message(&mut self) -> Result<Option<Message>> {
+        self.receive_part(MessageAssembler::next_message)
+    }
+
+    /// Receive the command set of the next DIMSE message
+    /// from the association requester.
+    ///
+    /// If the message has a data set,
+    /// it must be read in full with [`receive_data_set`](Self::receive_data_set)
+    /// before receiving the next message.
+    /// This allows large data sets to be processed
+    /// without keeping them in memory.
+    /// Release and abort requests are handled
+    /// as in [`receive_message`](Self::receive_message).
+    pub fn receive_command(&mut self) -> Result<Option<MessageCommand>> {
+        self.receive_part(MessageAssembler::next_command)
+    }
+
+    /// Prepare a reader of the data set
+    /// of the message last received with
+    /// [`receive_command`](Self::receive_command).
+    ///
+    /// The data set is encoded
+    /// in the transfer syntax of the presentation context.
+    pub fn receive_data_set(&mut self, presentation_context_id: u8) -> PDataReader<'_, &mut S> {
+        let (data, is_last) = self
+            .message_assembler
+            .take_data_set_fragments(presentation_context_id);
         PDataReader::new(
             &mut self.socket,
             self.acceptor_max_pdu_length,
             &mut self.read_buffer,
         )
+        .with_observer(self.pdu_observer.clone())
+        .with_received(presentation_context_id, data, is_last)
+    }
+
+    /// Receive P-Data PDUs until the message assembler produces a result.
+    fn receive_part<T>(
+        &mut self,
+        mut next: impl FnMut(&mut MessageAssembler) -> dimse::Result<Option<T>>,
+    ) -> Result<Option<T>> {
+        loop {
+            if let Some(part) = next(&mut self.message_assembler).context(ReadMessageSnafu)? {
+                return Ok(Some(part));
+            }
+            match self.receive()? {
+                Pdu::PData { data } => self.message_assembler.push(data),
+                Pdu::ReleaseRQ => {
+                    self.send(&Pdu::ReleaseRP)?;
+                    return Ok(None);
+                }
+                Pdu::AbortRQ { .. } => return AbortedSnafu.fail(),
+                pdu @ Pdu::Unknown { .. } => return UnknownRequestSnafu { pdu }.fail(),
+                pdu => return UnexpectedRequestSnafu { pdu }.fail(),
+            }
+        }
     }
 
-    /// Obtain access to the inner TCP stream
-    /// connected to the association acceptor.
+    /// Obtain access to the inner stream
+    /// connected to the association requester.
     ///
     /// This can be used to send the PDU in semantic fragments of the message,
     /// thus using less memory.
@@ -811,11 +1863,30 @@ impl ServerAssociation<TcpStream> {
     /// **Note:** reading and writing should be done with care
     /// to avoid inconsistencies in the association state.
     /// Do not call `send` and `receive` while not in a PDU boundary.
-    pub fn inner_stream(&mut self) -> &mut TcpStream {
+    pub fn inner_stream(&mut self) -> &mut S {
         &mut self.socket
     }
 }
 
+/// A TLS stream over TCP,
+/// as used by associations established via
+/// [`establish_tls`](ServerAssociationOptions::establish_tls).
+#[cfg(feature = "tls")]
+pub type TlsStream = rustls::StreamOwned<rustls::ServerConnection, TcpStream>;
+
+#[cfg(feature = "tls")]
+impl CloseSocket for TlsStream {
+    fn close(&mut self) -> std::io::Result<()> {
+        self.conn.send_close_notify();
+        let _ = self.conn.complete_io(&mut self.sock);
+        self.sock.shutdown(std::net::Shutdown::Both)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+}
+
 /// Check that a transfer syntax repository
 /// supports the given transfer syntax,
 /// meaning that it can parse and decode DICOM data sets.
@@ -875,37 +1946,51 @@ where
 
 #[cfg(feature = "async")]
 pub mod non_blocking {
-    use std::{borrow::Cow, io::Cursor};
+    use std::{io::Cursor, net::SocketAddr, time::Duration};
 
     use bytes::{Buf, BytesMut};
+    #[cfg(feature = "async-tls")]
+    use snafu::OptionExt;
     use snafu::{ensure, ResultExt};
     use tokio::{
-        io::{AsyncReadExt, AsyncWriteExt},
+        io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
         net::TcpStream,
     };
 
     use super::{
-        AccessControl, Result, SendSnafu, SendTooLongPduSnafu, ServerAssociation,
+        AccessControl, Error, InvalidStateSnafu, Result, SendSnafu, SendTooLongPduSnafu,
         ServerAssociationOptions, WireSendSnafu,
     };
+    #[cfg(feature = "async-tls")]
+    use super::{MissingTlsConfigSnafu, TlsHandshakeSnafu};
     use crate::{
         association::{
+            find_user_identity,
+            observer::{Direction, ObserverHandle},
+            pdata::non_blocking::{AsyncPDataWriter, PDataReader},
             server::{
-                AbortedSnafu, ConnectionClosedSnafu, MissingAbstractSyntaxSnafu,
+                AbortedSnafu, ConnectionClosedSnafu, MissingAbstractSyntaxSnafu, ReadMessageSnafu,
                 ReceiveRequestSnafu, ReceiveSnafu, RejectedSnafu, SendResponseSnafu,
                 UnexpectedRequestSnafu, UnknownRequestSnafu, WireReadSnafu,
             },
-            uid::trim_uid,
+            state::{Action, Event, Side, State, StateMachine},
+            NegotiatedContext, Roles,
         },
+        dimse::{self, command_pdu, Command, Message, MessageAssembler, MessageCommand},
         pdu::{
-            AbortRQServiceProviderReason, AbortRQSource, AssociationAC, AssociationRJ,
-            AssociationRJResult, AssociationRJServiceUserReason, AssociationRJSource,
-            AssociationRQ, PresentationContextResult, PresentationContextResultReason,
-            ReadPduSnafu, UserVariableItem, DEFAULT_MAX_PDU, MAXIMUM_PDU_SIZE,
+            AbortRQServiceProviderReason, AbortRQSource, AsyncOperationsWindow,
+            PresentationContextResult, ReadPduSnafu, UserIdentity, UserVariableItem,
+            MAXIMUM_PDU_SIZE,
         },
-        read_pdu, write_pdu, Pdu, IMPLEMENTATION_CLASS_UID, IMPLEMENTATION_VERSION_NAME,
+        read_pdu, write_pdu, Pdu,
     };
 
+    /// A TLS stream over TCP,
+    /// as used by associations established via
+    /// [`establish_tls_async`](ServerAssociationOptions::establish_tls_async).
+    #[cfg(feature = "async-tls")]
+    pub type TlsStream = tokio_rustls::server::TlsStream<TcpStream>;
+
     impl<A> ServerAssociationOptions<'_, A>
     where
         A: AccessControl,
@@ -913,8 +1998,66 @@ pub mod non_blocking {
         /// Negotiate an association with the given TCP stream.
         pub async fn establish_async(
             &self,
-            mut socket: TcpStream,
-        ) -> Result<ServerAssociation<TcpStream>> {
+            socket: TcpStream,
+        ) -> Result<AsyncServerAssociation<TcpStream>> {
+            let peer_addr = socket.peer_addr().ok();
+            self.negotiate_async(socket, peer_addr).await
+        }
+
+        /// Perform a TLS handshake on the given TCP stream
+        /// and negotiate an association over the secure connection.
+        ///
+        /// A TLS configuration must have been set
+        /// via [`tls_config`](Self::tls_config).
+        #[cfg(feature = "async-tls")]
+        pub async fn establish_tls_async(
+            &self,
+            socket: TcpStream,
+        ) -> Result<AsyncServerAssociation<TlsStream>> {
+            ensure!(
+                !self.abstract_syntax_uids.is_empty() || self.promiscuous,
+                MissingAbstractSyntaxSnafu
+            );
+            let tls_config = self.tls_config.clone().context(MissingTlsConfigSnafu)?;
+            let peer_addr = socket.peer_addr().ok();
+            let handshake = tokio_rustls::TlsAcceptor::from(tls_config).accept(socket);
+            let stream = if let Some(timeout) = self.timeout {
+                tokio::time::timeout(timeout, handshake)
+                    .await
+                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::TimedOut, err))
+                    .context(WireReadSnafu)?
+            } else {
+                handshake.await
+            }
+            .context(TlsHandshakeSnafu)?;
+            self.negotiate_async(stream, peer_addr).await
+        }
+
+        /// Negotiate an association over any open connection
+        /// to the association requestor.
+        ///
+        /// The connection can be any bidirectional asynchronous byte stream,
+        /// such as a Unix domain socket or an in-memory
+        /// [duplex stream](tokio::io::duplex).
+        pub async fn establish_stream_async<S>(
+            &self,
+            stream: S,
+        ) -> Result<AsyncServerAssociation<S>>
+        where
+            S: AsyncRead + AsyncWrite + Unpin,
+        {
+            self.negotiate_async(stream, None).await
+        }
+
+        /// Negotiate an association through an open connection.
+        async fn negotiate_async<S>(
+            &self,
+            mut socket: S,
+            peer_addr: Option<SocketAddr>,
+        ) -> Result<AsyncServerAssociation<S>>
+        where
+            S: AsyncRead + AsyncWrite + Unpin,
+        {
             ensure!(
                 !self.abstract_syntax_uids.is_empty() || self.promiscuous,
                 MissingAbstractSyntaxSnafu
@@ -922,192 +2065,97 @@ pub mod non_blocking {
             let timeout = self.timeout;
             let task = async {
                 let max_pdu_length = self.max_pdu_length;
+                let mut state = StateMachine::new(Side::Acceptor);
+                state.handle(Event::TransportConnectIndication);
                 let mut read_buffer = BytesMut::with_capacity(MAXIMUM_PDU_SIZE as usize);
 
-                let pdu = loop {
-                    let mut buf = Cursor::new(&read_buffer[..]);
-                    match read_pdu(&mut buf, MAXIMUM_PDU_SIZE, self.strict)
-                        .context(ReceiveRequestSnafu)?
-                    {
-                        Some(pdu) => {
-                            read_buffer.advance(buf.position() as usize);
-                            break pdu;
-                        }
-                        None => {
-                            // Reset position
-                            buf.set_position(0)
+                // wait for the association request up to the ARTIM timeout
+                let pdu = tokio::time::timeout(self.artim_timeout, async {
+                    loop {
+                        let mut buf = Cursor::new(&read_buffer[..]);
+                        match read_pdu(&mut buf, MAXIMUM_PDU_SIZE, self.strict)
+                            .context(ReceiveRequestSnafu)?
+                        {
+                            Some(pdu) => {
+                                let len = buf.position() as usize;
+                                self.pdu_observer
+                                    .observe(Direction::Received, &read_buffer[..len]);
+                                read_buffer.advance(len);
+                                return Ok(pdu);
+                            }
+                            None => {
+                                // Reset position
+                                buf.set_position(0)
+                            }
                         }
+                        let recv = socket
+                            .read_buf(&mut read_buffer)
+                            .await
+                            .context(ReadPduSnafu)
+                            .context(ReceiveSnafu)?;
+                        ensure!(recv > 0, ConnectionClosedSnafu);
                     }
-                    let recv = socket
-                        .read_buf(&mut read_buffer)
-                        .await
-                        .context(ReadPduSnafu)
-                        .context(ReceiveSnafu)?;
-                    ensure!(recv > 0, ConnectionClosedSnafu);
-                };
+                })
+                .await
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::TimedOut, err))
+                .context(WireReadSnafu)??;
 
+                let event = Event::received(&pdu);
+                let action = state.handle(event);
                 let mut buffer: Vec<u8> = Vec::with_capacity(max_pdu_length as usize);
                 match pdu {
-                    Pdu::AssociationRQ(AssociationRQ {
-                        protocol_version,
-                        calling_ae_title,
-                        called_ae_title,
-                        application_context_name,
-                        presentation_contexts,
-                        user_variables,
-                    }) => {
-                        if protocol_version != self.protocol_version {
-                            write_pdu(
-                                &mut buffer,
-                                &Pdu::AssociationRJ(AssociationRJ {
-                                    result: AssociationRJResult::Permanent,
-                                    source: AssociationRJSource::ServiceUser(
-                                        AssociationRJServiceUserReason::NoReasonGiven,
-                                    ),
-                                }),
-                            )
-                            .context(SendResponseSnafu)?;
-                            socket.write_all(&buffer).await.context(WireSendSnafu)?;
-                            return RejectedSnafu.fail();
-                        }
-
-                        if application_context_name != self.application_context_name {
-                            write_pdu(
-                                &mut buffer,
-                                &Pdu::AssociationRJ(AssociationRJ {
-                                    result: AssociationRJResult::Permanent,
-                                    source: AssociationRJSource::ServiceUser(
-                                        AssociationRJServiceUserReason::ApplicationContextNameNotSupported,
-                                    ),
-                                }),
-                            )
-                            .context(SendResponseSnafu)?;
-                            socket.write_all(&buffer).await.context(WireSendSnafu)?;
-                            return RejectedSnafu.fail();
-                        }
-
-                        match self.ae_access_control.check_access(
-                            &self.ae_title,
-                            &calling_ae_title,
-                            &called_ae_title,
-                            user_variables
-                                .iter()
-                                .find_map(|user_variable| match user_variable {
-                                    UserVariableItem::UserIdentityItem(user_identity) => {
-                                        Some(user_identity)
-                                    }
-                                    _ => None,
-                                }),
-                        ) {
-                            Ok(()) => {}
-                            Err(reason) => {
-                                write_pdu(
-                                    &mut buffer,
-                                    &Pdu::AssociationRJ(AssociationRJ {
-                                        result: AssociationRJResult::Permanent,
-                                        source: AssociationRJSource::ServiceUser(reason),
-                                    }),
-                                )
-                                .context(SendResponseSnafu)?;
-                                socket.write_all(&buffer).await.context(WireSendSnafu)?;
-                                return Err(RejectedSnafu.build());
-                            }
-                        }
-
-                        // fetch requested maximum PDU length
-                        let requestor_max_pdu_length = user_variables
-                            .iter()
-                            .find_map(|item| match item {
-                                UserVariableItem::MaxLength(len) => Some(*len),
-                                _ => None,
-                            })
-                            .unwrap_or(DEFAULT_MAX_PDU);
-
-                        // treat 0 as the maximum size admitted by the standard
-                        let requestor_max_pdu_length = if requestor_max_pdu_length == 0 {
-                            MAXIMUM_PDU_SIZE
-                        } else {
-                            requestor_max_pdu_length
-                        };
-
-                        let presentation_contexts: Vec<_> = presentation_contexts
-                            .into_iter()
-                            .map(|pc| {
-                                if !self
-                                    .abstract_syntax_uids
-                                    .contains(&trim_uid(Cow::from(pc.abstract_syntax)))
-                                    && !self.promiscuous
-                                {
-                                    return PresentationContextResult {
-                                        id: pc.id,
-                                        reason: PresentationContextResultReason::AbstractSyntaxNotSupported,
-                                        transfer_syntax: "1.2.840.10008.1.2".to_string(),
-                                    };
-                                }
-
-                                let (transfer_syntax, reason) = self
-                                    .choose_ts(pc.transfer_syntaxes)
-                                    .map(|ts| (ts, PresentationContextResultReason::Acceptance))
-                                    .unwrap_or_else(|| {
-                                        (
-                                            "1.2.840.10008.1.2".to_string(),
-                                            PresentationContextResultReason::TransferSyntaxesNotSupported,
-                                        )
-                                    });
-
-                                PresentationContextResult {
-                                    id: pc.id,
-                                    reason,
-                                    transfer_syntax,
+                    Pdu::AssociationRQ(association_rq) => {
+                        let negotiated =
+                            match self.process_association_rq(association_rq, peer_addr) {
+                                Ok(negotiated) => negotiated,
+                                Err(association_rj) => {
+                                    state.handle(Event::AssociateReject);
+                                    write_pdu(&mut buffer, &Pdu::AssociationRJ(association_rj))
+                                        .context(SendResponseSnafu)?;
+                                    self.pdu_observer.observe(Direction::Sent, &buffer);
+                                    socket.write_all(&buffer).await.context(WireSendSnafu)?;
+                                    return RejectedSnafu.fail();
                                 }
-                            })
-                            .collect();
-
-                        write_pdu(
-                            &mut buffer,
-                            &Pdu::AssociationAC(AssociationAC {
-                                protocol_version: self.protocol_version,
-                                application_context_name,
-                                presentation_contexts: presentation_contexts.clone(),
-                                calling_ae_title: calling_ae_title.clone(),
-                                called_ae_title,
-                                user_variables: vec![
-                                    UserVariableItem::MaxLength(max_pdu_length),
-                                    UserVariableItem::ImplementationClassUID(
-                                        IMPLEMENTATION_CLASS_UID.to_string(),
-                                    ),
-                                    UserVariableItem::ImplementationVersionName(
-                                        IMPLEMENTATION_VERSION_NAME.to_string(),
-                                    ),
-                                ],
-                            }),
-                        )
-                        .context(SendResponseSnafu)?;
+                            };
+                        state.handle(Event::AssociateAccept);
+                        write_pdu(&mut buffer, &Pdu::AssociationAC(negotiated.association_ac))
+                            .context(SendResponseSnafu)?;
+                        self.pdu_observer.observe(Direction::Sent, &buffer);
                         socket.write_all(&buffer).await.context(WireSendSnafu)?;
 
-                        Ok(ServerAssociation {
-                            presentation_contexts,
-                            requestor_max_pdu_length,
+                        Ok(AsyncServerAssociation {
+                            presentation_contexts: negotiated.presentation_contexts,
+                            negotiated_contexts: negotiated.negotiated_contexts,
+                            async_operations_window: negotiated.async_operations_window,
+                            requestor_max_pdu_length: negotiated.requestor_max_pdu_length,
                             acceptor_max_pdu_length: max_pdu_length,
                             socket,
-                            client_ae_title: calling_ae_title,
+                            client_ae_title: negotiated.client_ae_title,
+                            user_variables: negotiated.user_variables,
                             buffer,
                             strict: self.strict,
                             read_buffer: BytesMut::with_capacity(MAXIMUM_PDU_SIZE as usize),
                             timeout,
+                            artim_timeout: self.artim_timeout,
+                            state,
+                            message_id: 1,
+                            message_assembler: MessageAssembler::default(),
+                            pdu_observer: self.pdu_observer.clone(),
                         })
                     }
-                    Pdu::ReleaseRQ => {
-                        write_pdu(&mut buffer, &Pdu::ReleaseRP).context(SendResponseSnafu)?;
-                        socket.write_all(&buffer).await.context(WireSendSnafu)?;
-                        AbortedSnafu.fail()
+                    Pdu::AbortRQ { .. } => AbortedSnafu.fail(),
+                    pdu => {
+                        // any other PDU is answered with an A-ABORT
+                        if let Some(abort) = action.and_then(|action| action.abort_pdu(event)) {
+                            let _ = write_pdu(&mut buffer, &abort);
+                            self.pdu_observer.observe(Direction::Sent, &buffer);
+                            let _ = socket.write_all(&buffer).await;
+                        }
+                        match pdu {
+                            pdu @ Pdu::Unknown { .. } => UnknownRequestSnafu { pdu }.fail(),
+                            pdu => UnexpectedRequestSnafu { pdu }.fail(),
+                        }
                     }
-                    pdu @ Pdu::AssociationAC { .. }
-                    | pdu @ Pdu::AssociationRJ { .. }
-                    | pdu @ Pdu::PData { .. }
-                    | pdu @ Pdu::ReleaseRP
-                    | pdu @ Pdu::AbortRQ { .. } => UnexpectedRequestSnafu { pdu }.fail(),
-                    pdu @ Pdu::Unknown { .. } => UnknownRequestSnafu { pdu }.fail(),
                 }
             };
             if let Some(timeout) = timeout {
@@ -1121,19 +2169,172 @@ pub mod non_blocking {
         }
     }
 
-    impl ServerAssociation<TcpStream> {
+    /// A DICOM upper level association from the perspective
+    /// of an accepting application entity,
+    /// working over an asynchronous stream.
+    ///
+    /// This is the non-blocking counterpart of
+    /// [`ServerAssociation`](super::ServerAssociation),
+    /// obtained via
+    /// [`establish_async`](ServerAssociationOptions::establish_async).
+    ///
+    /// When the value falls out of scope,
+    /// the program will shut down the underlying connection.
+    ///
+    /// Before version 0.9,
+    /// `establish_async` returned a `ServerAssociation<tokio::net::TcpStream>`.
+    /// Associations over TCP are now of type
+    /// `AsyncServerAssociation<tokio::net::TcpStream>`,
+    /// with the same methods.
+    #[derive(Debug)]
+    pub struct AsyncServerAssociation<S> {
+        /// The accorded presentation contexts
+        presentation_contexts: Vec<PresentationContextResult>,
+        /// Details about the accepted presentation contexts
+        negotiated_contexts: Vec<NegotiatedContext>,
+        /// The negotiated asynchronous operations window
+        async_operations_window: AsyncOperationsWindow,
+        /// User variables that were taken from the association request
+        user_variables: Vec<UserVariableItem>,
+        /// The maximum PDU length that the remote application entity accepts
+        requestor_max_pdu_length: u32,
+        /// The maximum PDU length that this application entity is expecting to receive
+        acceptor_max_pdu_length: u32,
+        /// The stream to the other DICOM node
+        socket: S,
+        /// The application entity title of the other DICOM node
+        client_ae_title: String,
+        /// write buffer to send fully assembled PDUs on wire
+        buffer: Vec<u8>,
+        /// whether to receive PDUs in strict mode
+        strict: bool,
+        /// Read buffer from the socket
+        read_buffer: bytes::BytesMut,
+        /// Timeout for individual send/receive operations
+        timeout: Option<std::time::Duration>,
+        /// Timeout for the requestor to close the connection after the association ends
+        artim_timeout: std::time::Duration,
+        /// The upper layer protocol state machine
+        state: StateMachine,
+        /// The message ID to use in the next DIMSE request
+        message_id: u16,
+        /// DIMSE message fragments received so far
+        message_assembler: MessageAssembler,
+        /// Observer of the PDUs sent and received
+        pdu_observer: ObserverHandle,
+    }
+
+    impl<S> AsyncServerAssociation<S> {
+        /// Obtain a view of the negotiated presentation contexts.
+        pub fn presentation_contexts(&self) -> &[PresentationContextResult] {
+            &self.presentation_contexts
+        }
+
+        /// Obtain the abstract syntax of an accepted presentation context.
+        pub fn abstract_syntax(&self, presentation_context_id: u8) -> Option<&str> {
+            self.negotiated_contexts
+                .iter()
+                .find(|pc| pc.id == presentation_context_id)
+                .map(|pc| pc.abstract_syntax.as_str())
+        }
+
+        /// Obtain the roles which the association requestor may take
+        /// in an accepted presentation context.
+        ///
+        /// When the requestor is allowed to take the SCP role,
+        /// this node may issue requests to it through that presentation context.
+        pub fn roles(&self, presentation_context_id: u8) -> Option<Roles> {
+            self.negotiated_contexts
+                .iter()
+                .find(|pc| pc.id == presentation_context_id)
+                .map(|pc| pc.roles)
+        }
+
+        /// Obtain the asynchronous operations window of the association,
+        /// from the perspective of the association requestor.
+        pub fn async_operations_window(&self) -> AsyncOperationsWindow {
+            self.async_operations_window
+        }
+
+        /// Retrieve the user variables that were taken from the association request.
+        ///
+        /// Besides the maximum PDU length and the implementation identification,
+        /// these may include extended negotiation sub-items,
+        /// such as SOP class common extended negotiation.
+        pub fn user_variables(&self) -> &[UserVariableItem] {
+            &self.user_variables
+        }
+
+        /// Retrieve the user identity presented by the association requestor,
+        /// if any.
+        ///
+        /// The identity was accepted by the
+        /// [access control policy](AccessControl::verify_user_identity).
+        pub fn user_identity(&self) -> Option<&UserIdentity> {
+            find_user_identity(&self.user_variables)
+        }
+
+        /// Obtain the remote DICOM node's application entity title.
+        pub fn client_ae_title(&self) -> &str {
+            &self.client_ae_title
+        }
+
+        /// Retrieve the current state of the upper layer protocol machine.
+        pub fn state(&self) -> State {
+            self.state.state()
+        }
+
+        /// Retrieve the ARTIM timeout of the association.
+        pub(crate) fn artim_timeout(&self) -> Duration {
+            self.artim_timeout
+        }
+
+        /// Obtain a new message ID for a DIMSE request
+        /// to be sent through this association,
+        /// such as C-STORE sub-operations or event reports.
+        ///
+        /// Message IDs start at 1 and are incremented on each call.
+        pub fn next_message_id(&mut self) -> u16 {
+            let message_id = self.message_id;
+            self.message_id = self.message_id.wrapping_add(1);
+            message_id
+        }
+    }
+
+    impl<S> AsyncServerAssociation<S>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
         /// Send a PDU message to the other intervenient.
+        ///
+        /// Fails without sending anything
+        /// if the PDU is not allowed in the current state of the association.
+        /// Sending an A-RELEASE-RP or an A-ABORT waits up to the ARTIM timeout
+        /// for the requestor to close the connection.
         pub async fn send(&mut self, msg: &Pdu) -> Result<()> {
+            self.buffer.clear();
+            write_pdu(&mut self.buffer, msg).context(SendSnafu)?;
+            if self.buffer.len() > self.requestor_max_pdu_length as usize {
+                return SendTooLongPduSnafu {
+                    length: self.buffer.len(),
+                }
+                .fail();
+            }
+            if let Some(event) = Event::sent(msg) {
+                self.transition(event)?;
+            }
+            self.write_buffer().await?;
+            if self.state.state() == State::AwaitingTransportClose {
+                self.await_transport_close().await;
+            }
+            Ok(())
+        }
+
+        /// Write the contents of the PDU buffer to the wire.
+        async fn write_buffer(&mut self) -> Result<()> {
+            self.pdu_observer.observe(Direction::Sent, &self.buffer);
             let timeout = self.timeout;
             let task = async {
-                self.buffer.clear();
-                write_pdu(&mut self.buffer, msg).context(SendSnafu)?;
-                if self.buffer.len() > self.requestor_max_pdu_length as usize {
-                    return SendTooLongPduSnafu {
-                        length: self.buffer.len(),
-                    }
-                    .fail();
-                }
                 self.socket
                     .write_all(&self.buffer)
                     .await
@@ -1150,16 +2351,85 @@ pub mod non_blocking {
         }
 
         /// Read a PDU message from the other intervenient.
+        ///
+        /// A PDU which is not expected in the current state of the association
+        /// is answered with an A-ABORT, resulting in an error.
         pub async fn receive(&mut self) -> Result<Pdu> {
+            let pdu = match self.receive_pdu().await {
+                Ok(pdu) => pdu,
+                Err(e @ Error::ConnectionClosed) => {
+                    self.state.handle(Event::TransportClosed);
+                    let _ = self.socket.shutdown().await;
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            };
+            let event = Event::received(&pdu);
+            let action = self.transition(event)?;
+            if let Some(abort) = action.abort_pdu(event) {
+                self.buffer.clear();
+                write_pdu(&mut self.buffer, &abort).context(SendSnafu)?;
+                let _ = self.write_buffer().await;
+                self.await_transport_close().await;
+                return match pdu {
+                    pdu @ Pdu::Unknown { .. } => UnknownRequestSnafu { pdu }.fail(),
+                    pdu => UnexpectedRequestSnafu { pdu }.fail(),
+                };
+            }
+            if action.closes_transport() {
+                let _ = self.socket.shutdown().await;
+            }
+            Ok(pdu)
+        }
+
+        /// Feed an event to the upper layer state machine,
+        /// failing if the event is not allowed in the current state.
+        fn transition(&mut self, event: Event) -> Result<Action> {
+            let state = self.state.state();
+            match self.state.handle(event) {
+                Some(action) => Ok(action),
+                None => InvalidStateSnafu { state, event }.fail(),
+            }
+        }
+
+        /// Wait for the requestor to close the connection
+        /// for up to the ARTIM timeout, then close it.
+        async fn await_transport_close(&mut self) {
+            let mut buf = [0; 1024];
+            let socket = &mut self.socket;
+            let closed = tokio::time::timeout(self.artim_timeout, async {
+                loop {
+                    match socket.read(&mut buf).await {
+                        Ok(0) => break true,
+                        Ok(_) => continue,
+                        Err(_) => break false,
+                    }
+                }
+            })
+            .await
+            .unwrap_or(false);
+            if closed {
+                self.state.handle(Event::TransportClosed);
+            } else {
+                self.state.handle(Event::ArtimExpired);
+            }
+            let _ = self.socket.shutdown().await;
+        }
+
+        /// Read the next PDU from the wire.
+        async fn receive_pdu(&mut self) -> Result<Pdu> {
             let timeout = self.timeout;
             let task = async {
                 loop {
                     let mut buf = Cursor::new(&self.read_buffer[..]);
-                    match read_pdu(&mut buf, self.requestor_max_pdu_length, self.strict)
+                    match read_pdu(&mut buf, self.acceptor_max_pdu_length, self.strict)
                         .context(ReceiveRequestSnafu)?
                     {
                         Some(pdu) => {
-                            self.read_buffer.advance(buf.position() as usize);
+                            let len = buf.position() as usize;
+                            self.pdu_observer
+                                .observe(Direction::Received, &self.read_buffer[..len]);
+                            self.read_buffer.advance(len);
                             return Ok(pdu);
                         }
                         None => {
@@ -1188,8 +2458,11 @@ pub mod non_blocking {
         }
 
         /// Send a provider initiated abort message
-        /// and shut down the TCP connection,
+        /// and shut down the connection,
         /// terminating the association.
+        ///
+        /// The requestor is given up to the ARTIM timeout
+        /// to close the connection first.
         pub async fn abort(mut self) -> Result<()> {
             let timeout = self.timeout;
             let task = async {
@@ -1212,7 +2485,243 @@ pub mod non_blocking {
             }
         }
 
-        pub fn inner_stream(&mut self) -> &mut TcpStream {
+        /// Gracefully terminate the association by exchanging release messages
+        /// and then shutting down the connection.
+        ///
+        /// DIMSE messages still in transit from the requestor are discarded;
+        /// use [`release_with`](Self::release_with) to receive them.
+        /// A release collision,
+        /// in which the requestor requests a release at the same time,
+        /// is resolved as specified in the standard.
+        pub async fn release(self) -> Result<()> {
+            self.release_with(|_| {}).await
+        }
+
+        /// Gracefully terminate the association by exchanging release messages
+        /// and then shutting down the connection,
+        /// passing each DIMSE message received from the requestor
+        /// while awaiting the release response to `on_message`.
+        ///
+        /// These messages can no longer be answered on this association,
+        /// since no P-Data may be sent after requesting a release.
+        pub async fn release_with<F>(mut self, mut on_message: F) -> Result<()>
+        where
+            F: FnMut(Message) + Send,
+        {
+            let out = self.release_impl(&mut on_message).await;
+            let _ = self.socket.shutdown().await;
+            out
+        }
+
+        /// Gracefully terminate the association
+        /// after the DIMSE message currently being received, if any,
+        /// has been received in full.
+        ///
+        /// Each pending message is passed to `respond`,
+        /// and the response command returned, if any,
+        /// is sent through the same presentation context
+        /// before the association is [released](Self::release).
+        /// The rest of the message and the release response
+        /// are awaited for up to the ARTIM timeout,
+        /// so this is suitable for closing idle associations
+        /// once a [`receive_message`](Self::receive_message) times out.
+        pub async fn graceful_shutdown<F>(mut self, mut respond: F) -> Result<()>
+        where
+            F: FnMut(Message) -> Option<Command>,
+        {
+            self.timeout = Some(self.artim_timeout);
+            while let Some(message) = self.receive_pending_message().await? {
+                let presentation_context_id = message.presentation_context_id;
+                if let Some(response) = respond(message) {
+                    self.send_message(presentation_context_id, &response, None)
+                        .await?;
+                }
+            }
+            if self.state.state() != State::Established {
+                // the requestor released the association in the meantime
+                return Ok(());
+            }
+            self.release().await
+        }
+
+        /// Receive the next DIMSE message
+        /// only if part of it has already been received.
+        ///
+        /// Returns `None` without reading from the connection otherwise.
+        pub async fn receive_pending_message(&mut self) -> Result<Option<Message>> {
+            if self.message_assembler.is_empty() && self.read_buffer.is_empty() {
+                return Ok(None);
+            }
+            self.receive_message().await
+        }
+
+        /// Receive the command set of the next DIMSE message
+        /// only if part of the message has already been received.
+        ///
+        /// Returns `None` without reading from the connection otherwise.
+        /// See [`receive_command`](Self::receive_command).
+        pub async fn receive_pending_command(&mut self) -> Result<Option<MessageCommand>> {
+            if self.message_assembler.is_empty() && self.read_buffer.is_empty() {
+                return Ok(None);
+            }
+            self.receive_command().await
+        }
+
+        /// Release implementation function,
+        /// which tries to send a release request and receive a release response,
+        /// passing on the messages still in transit from the requestor.
+        async fn release_impl(
+            &mut self,
+            on_message: &mut (dyn FnMut(Message) + Send),
+        ) -> Result<()> {
+            self.send(&Pdu::ReleaseRQ).await?;
+
+            loop {
+                match self.receive().await? {
+                    // release collision: respond after the requestor
+                    Pdu::ReleaseRP
+                        if self.state.state() == State::AcceptorCollisionAwaitingLocalResponse =>
+                    {
+                        return self.send(&Pdu::ReleaseRP).await;
+                    }
+                    Pdu::ReleaseRP => return Ok(()),
+                    // data still in transit from the requestor
+                    Pdu::PData { data } => {
+                        self.message_assembler.push(data);
+                        while let Some(message) = self
+                            .message_assembler
+                            .next_message()
+                            .context(ReadMessageSnafu)?
+                        {
+                            on_message(message);
+                        }
+                    }
+                    // release collision: wait for the requestor's response
+                    Pdu::ReleaseRQ => {}
+                    Pdu::AbortRQ { .. } => return AbortedSnafu.fail(),
+                    pdu @ Pdu::Unknown { .. } => return UnknownRequestSnafu { pdu }.fail(),
+                    pdu => return UnexpectedRequestSnafu { pdu }.fail(),
+                }
+            }
+        }
+
+        /// Send a DIMSE message to the association requester.
+        ///
+        /// The command is encoded in _Implicit VR Little Endian_,
+        /// whereas `data` must already be encoded
+        /// in the transfer syntax of the given presentation context.
+        /// The data set is split into multiple PDUs if necessary.
+        pub async fn send_message(
+            &mut self,
+            presentation_context_id: u8,
+            command: &Command,
+            data: Option<&[u8]>,
+        ) -> Result<()> {
+            let (pdu, remaining) = command_pdu(
+                presentation_context_id,
+                command,
+                data,
+                self.requestor_max_pdu_length,
+            );
+            self.send(&pdu).await?;
+            if let Some(data) = remaining {
+                let timeout = self.timeout;
+                let mut writer = AsyncPDataWriter::new(
+                    &mut self.socket,
+                    presentation_context_id,
+                    self.requestor_max_pdu_length,
+                )
+                .with_observer(self.pdu_observer.clone());
+                let task = async {
+                    writer.write_all(data).await?;
+                    writer.finish().await
+                };
+                if let Some(timeout) = timeout {
+                    tokio::time::timeout(timeout, task)
+                        .await
+                        .map_err(|err| std::io::Error::new(std::io::ErrorKind::TimedOut, err))
+                        .context(WireSendSnafu)?
+                        .context(WireSendSnafu)?;
+                } else {
+                    task.await.context(WireSendSnafu)?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Receive the next DIMSE message from the association requester,
+        /// comprising the command and the data set which follows it, if any.
+        ///
+        /// If the requester asks to release the association instead,
+        /// the release is confirmed and `None` is returned
+        /// once the requester closes the connection.
+        /// An abort from the requester results in an error.
+        pub async fn receive_message(&mut self) -> Result<Option<Message>> {
+            self.receive_part(MessageAssembler::next_message).await
+        }
+
+        /// Receive the command set of the next DIMSE message
+        /// from the association requester.
+        ///
+        /// If the message has a data set,
+        /// it must be read in full with [`receive_data_set`](Self::receive_data_set)
+        /// before receiving the next message.
+        /// This allows large data sets to be processed
+        /// without keeping them in memory.
+        /// Release and abort requests are handled
+        /// as in [`receive_message`](Self::receive_message).
+        pub async fn receive_command(&mut self) -> Result<Option<MessageCommand>> {
+            self.receive_part(MessageAssembler::next_command).await
+        }
+
+        /// Prepare an asynchronous reader of the data set
+        /// of the message last received with
+        /// [`receive_command`](Self::receive_command).
+        ///
+        /// The data set is encoded
+        /// in the transfer syntax of the presentation context.
+        pub fn receive_data_set(&mut self, presentation_context_id: u8) -> PDataReader<'_, &mut S> {
+            let (data, is_last) = self
+                .message_assembler
+                .take_data_set_fragments(presentation_context_id);
+            PDataReader::new(
+                &mut self.socket,
+                self.acceptor_max_pdu_length,
+                &mut self.read_buffer,
+            )
+            .with_observer(self.pdu_observer.clone())
+            .with_received(presentation_context_id, data, is_last)
+        }
+
+        /// Receive P-Data PDUs until the message assembler produces a result.
+        async fn receive_part<T>(
+            &mut self,
+            mut next: impl FnMut(&mut MessageAssembler) -> dimse::Result<Option<T>>,
+        ) -> Result<Option<T>> {
+            loop {
+                if let Some(part) = next(&mut self.message_assembler).context(ReadMessageSnafu)? {
+                    return Ok(Some(part));
+                }
+                match self.receive().await? {
+                    Pdu::PData { data } => self.message_assembler.push(data),
+                    Pdu::ReleaseRQ => {
+                        self.send(&Pdu::ReleaseRP).await?;
+                        return Ok(None);
+                    }
+                    Pdu::AbortRQ { .. } => return AbortedSnafu.fail(),
+                    pdu @ Pdu::Unknown { .. } => return UnknownRequestSnafu { pdu }.fail(),
+                    pdu => return UnexpectedRequestSnafu { pdu }.fail(),
+                }
+            }
+        }
+
+        /// Obtain access to the inner stream
+        /// connected to the association requester.
+        ///
+        /// **Note:** reading and writing should be done with care
+        /// to avoid inconsistencies in the association state.
+        /// Do not call `send` and `receive` while not in a PDU boundary.
+        pub fn inner_stream(&mut self) -> &mut S {
             &mut self.socket
         }
     }
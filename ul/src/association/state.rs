@@ -0,0 +1,757 @@
+//! DICOM Upper Layer protocol state machine.
+//!
+//! This module describes the association lifecycle
+//! as specified in [PS3.8 section 9.2][1]:
+//! the states of the protocol machine ([`State`]),
+//! the events which drive it ([`Event`]),
+//! and the actions to perform on each transition ([`Action`]).
+//!
+//! Both client and server associations,
+//! blocking and non-blocking,
+//! keep a [`StateMachine`] which they update
+//! on every PDU sent or received,
+//! so that PDUs which are not expected in the current state
+//! are answered with an A-ABORT.
+//!
+//! [1]: https://dicom.nema.org/medical/dicom/current/output/chtml/part08/sect_9.2.html
+use std::fmt;
+use std::time::Duration;
+
+use crate::pdu::{AbortRQServiceProviderReason, AbortRQSource, Pdu};
+
+/// The default value of the association request/reject/release timer (ARTIM).
+///
+/// This is the time to wait for the association request
+/// after a transport connection is accepted,
+/// and for the peer to close the transport connection
+/// after the association is released or aborted.
+pub const DEFAULT_ARTIM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A state of the upper layer protocol machine
+/// (PS3.8 Table 9-1).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum State {
+    /// Sta1: Idle
+    Idle,
+    /// Sta2: Transport connection open (awaiting A-ASSOCIATE-RQ PDU)
+    AwaitingAssociateRq,
+    /// Sta3: Awaiting local A-ASSOCIATE response primitive
+    AwaitingLocalAssociateResponse,
+    /// Sta4: Awaiting transport connection opening to complete
+    AwaitingTransportOpen,
+    /// Sta5: Awaiting A-ASSOCIATE-AC or A-ASSOCIATE-RJ PDU
+    AwaitingAssociateResponse,
+    /// Sta6: Association established and ready for data transfer
+    Established,
+    /// Sta7: Awaiting A-RELEASE-RP PDU
+    AwaitingReleaseRp,
+    /// Sta8: Awaiting local A-RELEASE response primitive
+    AwaitingLocalReleaseResponse,
+    /// Sta9: Release collision requestor side;
+    /// awaiting A-RELEASE response primitive
+    RequestorCollisionAwaitingLocalResponse,
+    /// Sta10: Release collision acceptor side;
+    /// awaiting A-RELEASE-RP PDU
+    AcceptorCollisionAwaitingReleaseRp,
+    /// Sta11: Release collision requestor side;
+    /// awaiting A-RELEASE-RP PDU
+    RequestorCollisionAwaitingReleaseRp,
+    /// Sta12: Release collision acceptor side;
+    /// awaiting A-RELEASE response primitive
+    AcceptorCollisionAwaitingLocalResponse,
+    /// Sta13: Awaiting transport connection close indication
+    /// (association no longer exists)
+    AwaitingTransportClose,
+}
+
+impl State {
+    /// All states, in the order of the standard.
+    pub const ALL: [State; 13] = [
+        State::Idle,
+        State::AwaitingAssociateRq,
+        State::AwaitingLocalAssociateResponse,
+        State::AwaitingTransportOpen,
+        State::AwaitingAssociateResponse,
+        State::Established,
+        State::AwaitingReleaseRp,
+        State::AwaitingLocalReleaseResponse,
+        State::RequestorCollisionAwaitingLocalResponse,
+        State::AcceptorCollisionAwaitingReleaseRp,
+        State::RequestorCollisionAwaitingReleaseRp,
+        State::AcceptorCollisionAwaitingLocalResponse,
+        State::AwaitingTransportClose,
+    ];
+
+    /// The number of this state in the standard (`Sta1` to `Sta13`).
+    pub fn number(self) -> u8 {
+        State::ALL.iter().position(|s| *s == self).unwrap() as u8 + 1
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Sta{}", self.number())
+    }
+}
+
+/// An event of the upper layer protocol machine
+/// (PS3.8 Table 9-2).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Event {
+    /// Evt1: A-ASSOCIATE request (local user)
+    AssociateRequest,
+    /// Evt2: Transport connection confirmation (local transport service)
+    TransportConnectConfirm,
+    /// Evt3: A-ASSOCIATE-AC PDU received on transport connection
+    AssociateAcReceived,
+    /// Evt4: A-ASSOCIATE-RJ PDU received on transport connection
+    AssociateRjReceived,
+    /// Evt5: Transport connection indication (local transport service)
+    TransportConnectIndication,
+    /// Evt6: A-ASSOCIATE-RQ PDU received on transport connection
+    AssociateRqReceived,
+    /// Evt7: A-ASSOCIATE response primitive (accept)
+    AssociateAccept,
+    /// Evt8: A-ASSOCIATE response primitive (reject)
+    AssociateReject,
+    /// Evt9: P-DATA request primitive
+    PDataRequest,
+    /// Evt10: P-DATA-TF PDU received on transport connection
+    PDataReceived,
+    /// Evt11: A-RELEASE request primitive
+    ReleaseRequest,
+    /// Evt12: A-RELEASE-RQ PDU received on transport connection
+    ReleaseRqReceived,
+    /// Evt13: A-RELEASE-RP PDU received on transport connection
+    ReleaseRpReceived,
+    /// Evt14: A-RELEASE response primitive
+    ReleaseResponse,
+    /// Evt15: A-ABORT request primitive
+    AbortRequest,
+    /// Evt16: A-ABORT PDU received on transport connection
+    AbortReceived,
+    /// Evt17: Transport connection closed indication (local transport service)
+    TransportClosed,
+    /// Evt18: ARTIM timer expired
+    ArtimExpired,
+    /// Evt19: Unrecognized or invalid PDU received
+    InvalidPduReceived,
+}
+
+impl Event {
+    /// All events, in the order of the standard.
+    pub const ALL: [Event; 19] = [
+        Event::AssociateRequest,
+        Event::TransportConnectConfirm,
+        Event::AssociateAcReceived,
+        Event::AssociateRjReceived,
+        Event::TransportConnectIndication,
+        Event::AssociateRqReceived,
+        Event::AssociateAccept,
+        Event::AssociateReject,
+        Event::PDataRequest,
+        Event::PDataReceived,
+        Event::ReleaseRequest,
+        Event::ReleaseRqReceived,
+        Event::ReleaseRpReceived,
+        Event::ReleaseResponse,
+        Event::AbortRequest,
+        Event::AbortReceived,
+        Event::TransportClosed,
+        Event::ArtimExpired,
+        Event::InvalidPduReceived,
+    ];
+
+    /// The number of this event in the standard (`Evt1` to `Evt19`).
+    pub fn number(self) -> u8 {
+        Event::ALL.iter().position(|e| *e == self).unwrap() as u8 + 1
+    }
+
+    /// The event of receiving the given PDU from the peer.
+    pub fn received(pdu: &Pdu) -> Event {
+        match pdu {
+            Pdu::AssociationRQ(_) => Event::AssociateRqReceived,
+            Pdu::AssociationAC(_) => Event::AssociateAcReceived,
+            Pdu::AssociationRJ(_) => Event::AssociateRjReceived,
+            Pdu::PData { .. } => Event::PDataReceived,
+            Pdu::ReleaseRQ => Event::ReleaseRqReceived,
+            Pdu::ReleaseRP => Event::ReleaseRpReceived,
+            Pdu::AbortRQ { .. } => Event::AbortReceived,
+            Pdu::Unknown { .. } => Event::InvalidPduReceived,
+        }
+    }
+
+    /// The local service primitive which results in sending the given PDU,
+    /// if there is one.
+    ///
+    /// A-ASSOCIATE-RQ PDUs are sent as part of the transport connection
+    /// confirmation, and unknown PDUs do not have a primitive,
+    /// so these return `None`.
+    pub fn sent(pdu: &Pdu) -> Option<Event> {
+        match pdu {
+            Pdu::AssociationAC(_) => Some(Event::AssociateAccept),
+            Pdu::AssociationRJ(_) => Some(Event::AssociateReject),
+            Pdu::PData { .. } => Some(Event::PDataRequest),
+            Pdu::ReleaseRQ => Some(Event::ReleaseRequest),
+            Pdu::ReleaseRP => Some(Event::ReleaseResponse),
+            Pdu::AbortRQ { .. } => Some(Event::AbortRequest),
+            Pdu::AssociationRQ(_) | Pdu::Unknown { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Evt{}", self.number())
+    }
+}
+
+/// An action of the upper layer protocol machine
+/// (PS3.8 Tables 9-6 to 9-9).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// AE-1: Issue transport connect request primitive
+    Ae1,
+    /// AE-2: Send A-ASSOCIATE-RQ PDU
+    Ae2,
+    /// AE-3: Issue A-ASSOCIATE confirmation (accept) primitive
+    Ae3,
+    /// AE-4: Issue A-ASSOCIATE confirmation (reject) primitive
+    /// and close transport connection
+    Ae4,
+    /// AE-5: Issue transport connection response primitive; start ARTIM timer
+    Ae5,
+    /// AE-6: Stop ARTIM timer and issue A-ASSOCIATE indication primitive
+    ///
+    /// An association request which is not acceptable
+    /// is then rejected through an A-ASSOCIATE response primitive (reject).
+    Ae6,
+    /// AE-7: Send A-ASSOCIATE-AC PDU
+    Ae7,
+    /// AE-8: Send A-ASSOCIATE-RJ PDU and start ARTIM timer
+    Ae8,
+    /// DT-1: Send P-DATA-TF PDU
+    Dt1,
+    /// DT-2: Send P-DATA indication primitive
+    Dt2,
+    /// AR-1: Send A-RELEASE-RQ PDU
+    Ar1,
+    /// AR-2: Issue A-RELEASE indication primitive
+    Ar2,
+    /// AR-3: Issue A-RELEASE confirmation primitive
+    /// and close transport connection
+    Ar3,
+    /// AR-4: Issue A-RELEASE-RP PDU and start ARTIM timer
+    Ar4,
+    /// AR-5: Stop ARTIM timer
+    Ar5,
+    /// AR-6: Issue P-DATA indication
+    Ar6,
+    /// AR-7: Issue P-DATA-TF PDU
+    Ar7,
+    /// AR-8: Issue A-RELEASE indication (release collision)
+    Ar8,
+    /// AR-9: Send A-RELEASE-RP PDU
+    Ar9,
+    /// AR-10: Issue A-RELEASE confirmation primitive
+    Ar10,
+    /// AA-1: Send A-ABORT PDU (service-user source)
+    /// and start (or restart) ARTIM timer
+    Aa1,
+    /// AA-2: Stop ARTIM timer if running and close transport connection
+    Aa2,
+    /// AA-3: Issue A-ABORT or A-P-ABORT indication
+    /// and close transport connection
+    Aa3,
+    /// AA-4: Issue A-P-ABORT indication primitive
+    Aa4,
+    /// AA-5: Stop ARTIM timer
+    Aa5,
+    /// AA-6: Ignore PDU
+    Aa6,
+    /// AA-7: Send A-ABORT PDU
+    Aa7,
+    /// AA-8: Send A-ABORT PDU (service-provider source),
+    /// issue an A-P-ABORT indication and start ARTIM timer
+    Aa8,
+}
+
+impl Action {
+    /// The state which the protocol machine enters after this action,
+    /// depending on whether it is on the association requestor side.
+    pub fn next_state(self, side: Side) -> State {
+        match self {
+            Action::Ae1 => State::AwaitingTransportOpen,
+            Action::Ae2 => State::AwaitingAssociateResponse,
+            Action::Ae3 | Action::Ae7 | Action::Dt1 | Action::Dt2 => State::Established,
+            Action::Ae5 => State::AwaitingAssociateRq,
+            Action::Ae6 => State::AwaitingLocalAssociateResponse,
+            Action::Ar1 | Action::Ar6 => State::AwaitingReleaseRp,
+            Action::Ar2 | Action::Ar7 => State::AwaitingLocalReleaseResponse,
+            Action::Ar8 => match side {
+                Side::Requestor => State::RequestorCollisionAwaitingLocalResponse,
+                Side::Acceptor => State::AcceptorCollisionAwaitingReleaseRp,
+            },
+            Action::Ar9 => State::RequestorCollisionAwaitingReleaseRp,
+            Action::Ar10 => State::AcceptorCollisionAwaitingLocalResponse,
+            Action::Ae8 | Action::Ar4 | Action::Aa1 | Action::Aa6 | Action::Aa7 | Action::Aa8 => {
+                State::AwaitingTransportClose
+            }
+            Action::Ae4
+            | Action::Ar3
+            | Action::Ar5
+            | Action::Aa2
+            | Action::Aa3
+            | Action::Aa4
+            | Action::Aa5 => State::Idle,
+        }
+    }
+
+    /// Whether this action closes the transport connection.
+    pub fn closes_transport(self) -> bool {
+        matches!(
+            self,
+            Action::Ae4 | Action::Ar3 | Action::Aa2 | Action::Aa3 | Action::Aa4
+        )
+    }
+
+    /// The A-ABORT PDU which the protocol machine sends by itself
+    /// as part of this action, in response to the given event.
+    ///
+    /// Returns `None` if the action does not send an A-ABORT,
+    /// or if the A-ABORT is requested by the local user
+    /// (event [`AbortRequest`](Event::AbortRequest)).
+    pub fn abort_pdu(self, event: Event) -> Option<Pdu> {
+        let source = match (self, event) {
+            (_, Event::AbortRequest) => return None,
+            (Action::Aa1, _) => AbortRQSource::ServiceUser,
+            (Action::Aa7, Event::InvalidPduReceived) | (Action::Aa8, Event::InvalidPduReceived) => {
+                AbortRQSource::ServiceProvider(AbortRQServiceProviderReason::UnrecognizedPdu)
+            }
+            (Action::Aa7, _) | (Action::Aa8, _) => {
+                AbortRQSource::ServiceProvider(AbortRQServiceProviderReason::UnexpectedPdu)
+            }
+            _ => return None,
+        };
+        Some(Pdu::AbortRQ { source })
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Action::Ae1 => "AE-1",
+            Action::Ae2 => "AE-2",
+            Action::Ae3 => "AE-3",
+            Action::Ae4 => "AE-4",
+            Action::Ae5 => "AE-5",
+            Action::Ae6 => "AE-6",
+            Action::Ae7 => "AE-7",
+            Action::Ae8 => "AE-8",
+            Action::Dt1 => "DT-1",
+            Action::Dt2 => "DT-2",
+            Action::Ar1 => "AR-1",
+            Action::Ar2 => "AR-2",
+            Action::Ar3 => "AR-3",
+            Action::Ar4 => "AR-4",
+            Action::Ar5 => "AR-5",
+            Action::Ar6 => "AR-6",
+            Action::Ar7 => "AR-7",
+            Action::Ar8 => "AR-8",
+            Action::Ar9 => "AR-9",
+            Action::Ar10 => "AR-10",
+            Action::Aa1 => "AA-1",
+            Action::Aa2 => "AA-2",
+            Action::Aa3 => "AA-3",
+            Action::Aa4 => "AA-4",
+            Action::Aa5 => "AA-5",
+            Action::Aa6 => "AA-6",
+            Action::Aa7 => "AA-7",
+            Action::Aa8 => "AA-8",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The side of the association which a protocol machine is on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Side {
+    /// The association requestor (usually the SCU)
+    Requestor,
+    /// The association acceptor (usually the SCP)
+    Acceptor,
+}
+
+/// Determine the action to perform on an event in the given state,
+/// as per the upper layer state transition table (PS3.8 Table 9-10).
+///
+/// Returns `None` if the event cannot happen in this state,
+/// such as a local service primitive issued at the wrong time.
+pub fn transition(state: State, event: Event) -> Option<Action> {
+    use self::{Action::*, Event::*, State::*};
+
+    let action = match (event, state) {
+        (AssociateRequest, Idle) => Ae1,
+        (TransportConnectConfirm, AwaitingTransportOpen) => Ae2,
+        (AssociateAcReceived, AwaitingAssociateResponse) => Ae3,
+        (AssociateRjReceived, AwaitingAssociateResponse) => Ae4,
+        (TransportConnectIndication, Idle) => Ae5,
+        (AssociateRqReceived, AwaitingAssociateRq) => Ae6,
+        (AssociateAccept, AwaitingLocalAssociateResponse) => Ae7,
+        (AssociateReject, AwaitingLocalAssociateResponse) => Ae8,
+        (PDataRequest, Established) => Dt1,
+        (PDataReceived, Established) => Dt2,
+        (ReleaseRequest, Established) => Ar1,
+        (ReleaseRqReceived, Established) => Ar2,
+        (ReleaseRpReceived, AwaitingReleaseRp)
+        | (ReleaseRpReceived, RequestorCollisionAwaitingReleaseRp) => Ar3,
+        (ReleaseResponse, AwaitingLocalReleaseResponse)
+        | (ReleaseResponse, AcceptorCollisionAwaitingLocalResponse) => Ar4,
+        (TransportClosed, AwaitingTransportClose) => Ar5,
+        (PDataReceived, AwaitingReleaseRp) => Ar6,
+        (PDataRequest, AwaitingLocalReleaseResponse) => Ar7,
+        (ReleaseRqReceived, AwaitingReleaseRp) => Ar8,
+        (ReleaseResponse, RequestorCollisionAwaitingLocalResponse) => Ar9,
+        (ReleaseRpReceived, AcceptorCollisionAwaitingReleaseRp) => Ar10,
+
+        (AbortRequest, AwaitingTransportOpen) => Aa2,
+        (AbortRequest, Idle)
+        | (AbortRequest, AwaitingAssociateRq)
+        | (AbortRequest, AwaitingTransportClose) => return None,
+        (AbortRequest, _) => Aa1,
+        (AbortReceived, AwaitingAssociateRq) | (AbortReceived, AwaitingTransportClose) => Aa2,
+        (AbortReceived, Idle) | (AbortReceived, AwaitingTransportOpen) => return None,
+        (AbortReceived, _) => Aa3,
+        (TransportClosed, AwaitingAssociateRq) => Aa5,
+        (TransportClosed, Idle) => return None,
+        (TransportClosed, _) => Aa4,
+        (ArtimExpired, AwaitingAssociateRq) | (ArtimExpired, AwaitingTransportClose) => Aa2,
+
+        // any other PDU received
+        (
+            AssociateAcReceived | AssociateRjReceived | AssociateRqReceived | PDataReceived
+            | ReleaseRqReceived | ReleaseRpReceived | InvalidPduReceived,
+            state,
+        ) => match state {
+            Idle | AwaitingTransportOpen => return None,
+            AwaitingAssociateRq => Aa1,
+            AwaitingTransportClose => match event {
+                AssociateRqReceived | InvalidPduReceived => Aa7,
+                _ => Aa6,
+            },
+            _ => Aa8,
+        },
+
+        _ => return None,
+    };
+    Some(action)
+}
+
+/// The upper layer protocol machine of one side of an association.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateMachine {
+    side: Side,
+    state: State,
+}
+
+impl StateMachine {
+    /// Create a new protocol machine in the idle state (Sta1).
+    pub fn new(side: Side) -> Self {
+        StateMachine {
+            side,
+            state: State::Idle,
+        }
+    }
+
+    /// The side of the association which this machine is on.
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    /// The current state of the machine.
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Process an event,
+    /// moving to the next state and returning the action to perform.
+    ///
+    /// Returns `None` and remains in the same state
+    /// if the event cannot happen in the current state.
+    pub fn handle(&mut self, event: Event) -> Option<Action> {
+        let action = transition(self.state, event)?;
+        self.state = action.next_state(self.side);
+        Some(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The state transition table in PS3.8 Table 9-10,
+    /// one row per event, one column per state (Sta1 to Sta13).
+    static TRANSITION_TABLE: [[&str; 13]; 19] = [
+        // Evt1
+        ["AE-1", "", "", "", "", "", "", "", "", "", "", "", ""],
+        // Evt2
+        ["", "", "", "AE-2", "", "", "", "", "", "", "", "", ""],
+        // Evt3
+        [
+            "", "AA-1", "AA-8", "", "AE-3", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8",
+            "AA-6",
+        ],
+        // Evt4
+        [
+            "", "AA-1", "AA-8", "", "AE-4", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8",
+            "AA-6",
+        ],
+        // Evt5
+        ["AE-5", "", "", "", "", "", "", "", "", "", "", "", ""],
+        // Evt6
+        [
+            "", "AE-6", "AA-8", "", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8",
+            "AA-7",
+        ],
+        // Evt7
+        ["", "", "AE-7", "", "", "", "", "", "", "", "", "", ""],
+        // Evt8
+        ["", "", "AE-8", "", "", "", "", "", "", "", "", "", ""],
+        // Evt9
+        ["", "", "", "", "", "DT-1", "", "AR-7", "", "", "", "", ""],
+        // Evt10
+        [
+            "", "AA-1", "AA-8", "", "AA-8", "DT-2", "AR-6", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8",
+            "AA-6",
+        ],
+        // Evt11
+        ["", "", "", "", "", "AR-1", "", "", "", "", "", "", ""],
+        // Evt12
+        [
+            "", "AA-1", "AA-8", "", "AA-8", "AR-2", "AR-8", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8",
+            "AA-6",
+        ],
+        // Evt13
+        [
+            "", "AA-1", "AA-8", "", "AA-8", "AA-8", "AR-3", "AA-8", "AA-8", "AR-10", "AR-3",
+            "AA-8", "AA-6",
+        ],
+        // Evt14
+        [
+            "", "", "", "", "", "", "", "AR-4", "AR-9", "", "", "AR-4", "",
+        ],
+        // Evt15
+        [
+            "", "", "AA-1", "AA-2", "AA-1", "AA-1", "AA-1", "AA-1", "AA-1", "AA-1", "AA-1", "AA-1",
+            "",
+        ],
+        // Evt16
+        [
+            "", "AA-2", "AA-3", "", "AA-3", "AA-3", "AA-3", "AA-3", "AA-3", "AA-3", "AA-3", "AA-3",
+            "AA-2",
+        ],
+        // Evt17
+        [
+            "", "AA-5", "AA-4", "AA-4", "AA-4", "AA-4", "AA-4", "AA-4", "AA-4", "AA-4", "AA-4",
+            "AA-4", "AR-5",
+        ],
+        // Evt18
+        ["", "AA-2", "", "", "", "", "", "", "", "", "", "", "AA-2"],
+        // Evt19
+        [
+            "", "AA-1", "AA-8", "", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8", "AA-8",
+            "AA-7",
+        ],
+    ];
+
+    #[test]
+    fn transitions_match_standard_table() {
+        for (event, row) in Event::ALL.iter().zip(TRANSITION_TABLE.iter()) {
+            for (state, expected) in State::ALL.iter().zip(row.iter()) {
+                let action = transition(*state, *event).map(|a| a.to_string());
+                let expected = Some(expected.to_string()).filter(|a| !a.is_empty());
+                assert_eq!(action, expected, "{} in {}", event, state);
+            }
+        }
+    }
+
+    #[test]
+    fn next_states() {
+        let cases = [
+            (Action::Ae1, 4),
+            (Action::Ae2, 5),
+            (Action::Ae3, 6),
+            (Action::Ae4, 1),
+            (Action::Ae5, 2),
+            (Action::Ae6, 3),
+            (Action::Ae7, 6),
+            (Action::Ae8, 13),
+            (Action::Dt1, 6),
+            (Action::Dt2, 6),
+            (Action::Ar1, 7),
+            (Action::Ar2, 8),
+            (Action::Ar3, 1),
+            (Action::Ar4, 13),
+            (Action::Ar5, 1),
+            (Action::Ar6, 7),
+            (Action::Ar7, 8),
+            (Action::Ar9, 11),
+            (Action::Ar10, 12),
+            (Action::Aa1, 13),
+            (Action::Aa2, 1),
+            (Action::Aa3, 1),
+            (Action::Aa4, 1),
+            (Action::Aa5, 1),
+            (Action::Aa6, 13),
+            (Action::Aa7, 13),
+            (Action::Aa8, 13),
+        ];
+        for (action, state) in cases.iter() {
+            for side in [Side::Requestor, Side::Acceptor].iter() {
+                assert_eq!(action.next_state(*side).number(), *state, "{}", action);
+            }
+        }
+        assert_eq!(Action::Ar8.next_state(Side::Requestor).number(), 9);
+        assert_eq!(Action::Ar8.next_state(Side::Acceptor).number(), 10);
+    }
+
+    #[test]
+    fn requestor_lifecycle() {
+        let mut machine = StateMachine::new(Side::Requestor);
+        let steps = [
+            (
+                Event::AssociateRequest,
+                Action::Ae1,
+                State::AwaitingTransportOpen,
+            ),
+            (
+                Event::TransportConnectConfirm,
+                Action::Ae2,
+                State::AwaitingAssociateResponse,
+            ),
+            (Event::AssociateAcReceived, Action::Ae3, State::Established),
+            (Event::PDataRequest, Action::Dt1, State::Established),
+            (Event::PDataReceived, Action::Dt2, State::Established),
+            (Event::ReleaseRequest, Action::Ar1, State::AwaitingReleaseRp),
+            (Event::ReleaseRpReceived, Action::Ar3, State::Idle),
+        ];
+        for (event, action, state) in steps.iter() {
+            assert_eq!(machine.handle(*event), Some(*action));
+            assert_eq!(machine.state(), *state);
+        }
+    }
+
+    #[test]
+    fn acceptor_lifecycle() {
+        let mut machine = StateMachine::new(Side::Acceptor);
+        let steps = [
+            (
+                Event::TransportConnectIndication,
+                Action::Ae5,
+                State::AwaitingAssociateRq,
+            ),
+            (
+                Event::AssociateRqReceived,
+                Action::Ae6,
+                State::AwaitingLocalAssociateResponse,
+            ),
+            (Event::AssociateAccept, Action::Ae7, State::Established),
+            (
+                Event::ReleaseRqReceived,
+                Action::Ar2,
+                State::AwaitingLocalReleaseResponse,
+            ),
+            (
+                Event::PDataRequest,
+                Action::Ar7,
+                State::AwaitingLocalReleaseResponse,
+            ),
+            (
+                Event::ReleaseResponse,
+                Action::Ar4,
+                State::AwaitingTransportClose,
+            ),
+            (Event::TransportClosed, Action::Ar5, State::Idle),
+        ];
+        for (event, action, state) in steps.iter() {
+            assert_eq!(machine.handle(*event), Some(*action));
+            assert_eq!(machine.state(), *state);
+        }
+    }
+
+    #[test]
+    fn release_collision() {
+        let mut requestor = StateMachine::new(Side::Requestor);
+        let mut acceptor = StateMachine::new(Side::Acceptor);
+        requestor.state = State::Established;
+        acceptor.state = State::Established;
+
+        // both sides request a release at the same time
+        assert_eq!(requestor.handle(Event::ReleaseRequest), Some(Action::Ar1));
+        assert_eq!(acceptor.handle(Event::ReleaseRequest), Some(Action::Ar1));
+        assert_eq!(
+            requestor.handle(Event::ReleaseRqReceived),
+            Some(Action::Ar8)
+        );
+        assert_eq!(acceptor.handle(Event::ReleaseRqReceived), Some(Action::Ar8));
+        assert_eq!(
+            requestor.state(),
+            State::RequestorCollisionAwaitingLocalResponse
+        );
+        assert_eq!(acceptor.state(), State::AcceptorCollisionAwaitingReleaseRp);
+
+        // the requestor responds first
+        assert_eq!(requestor.handle(Event::ReleaseResponse), Some(Action::Ar9));
+        assert_eq!(
+            acceptor.handle(Event::ReleaseRpReceived),
+            Some(Action::Ar10)
+        );
+        // then the acceptor
+        assert_eq!(acceptor.handle(Event::ReleaseResponse), Some(Action::Ar4));
+        assert_eq!(
+            requestor.handle(Event::ReleaseRpReceived),
+            Some(Action::Ar3)
+        );
+
+        assert_eq!(requestor.state(), State::Idle);
+        assert_eq!(acceptor.state(), State::AwaitingTransportClose);
+        assert_eq!(acceptor.handle(Event::TransportClosed), Some(Action::Ar5));
+        assert_eq!(acceptor.state(), State::Idle);
+    }
+
+    #[test]
+    fn invalid_events_keep_state() {
+        let mut machine = StateMachine::new(Side::Requestor);
+        assert_eq!(machine.handle(Event::PDataRequest), None);
+        assert_eq!(machine.state(), State::Idle);
+        machine.state = State::AwaitingTransportClose;
+        assert_eq!(machine.handle(Event::ReleaseRequest), None);
+        assert_eq!(machine.state(), State::AwaitingTransportClose);
+    }
+
+    #[test]
+    fn automatic_abort_pdus() {
+        assert_eq!(
+            Action::Aa8.abort_pdu(Event::ReleaseRpReceived),
+            Some(Pdu::AbortRQ {
+                source: AbortRQSource::ServiceProvider(AbortRQServiceProviderReason::UnexpectedPdu),
+            })
+        );
+        assert_eq!(
+            Action::Aa8.abort_pdu(Event::InvalidPduReceived),
+            Some(Pdu::AbortRQ {
+                source: AbortRQSource::ServiceProvider(
+                    AbortRQServiceProviderReason::UnrecognizedPdu
+                ),
+            })
+        );
+        assert_eq!(
+            Action::Aa1.abort_pdu(Event::PDataReceived),
+            Some(Pdu::AbortRQ {
+                source: AbortRQSource::ServiceUser,
+            })
+        );
+        assert_eq!(Action::Aa1.abort_pdu(Event::AbortRequest), None);
+        assert_eq!(Action::Dt2.abort_pdu(Event::PDataReceived), None);
+    }
+}
@@ -0,0 +1,1214 @@
+//! DICOM Message Service Element (DIMSE) module
+//!
+//! This module contains typed representations of the DIMSE commands
+//! defined in PS3.7 (C-ECHO, C-STORE, C-FIND, C-GET, C-MOVE, C-CANCEL
+//! and the DIMSE-N services),
+//! as well as functions for encoding and decoding command sets
+//! in the _Implicit VR Little Endian_ transfer syntax.
+//!
+//! Most applications will not need to use [`read_command`]
+//! and [`write_command`] directly.
+//! Established associations
+//! (see [`ClientAssociation`](crate::ClientAssociation)
+//! and [`ServerAssociation`](crate::ServerAssociation))
+//! provide `send_message` and `receive_message` methods,
+//! which take care of splitting and assembling
+//! the command and data set fragments in P-Data PDUs.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use dicom_ul::association::ClientAssociationOptions;
+//! # use dicom_ul::dimse::{CEchoRQ, Command};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut association = ClientAssociationOptions::new()
+//!     .with_abstract_syntax("1.2.840.10008.1.1")
+//!     .establish("129.168.0.5:104")?;
+//!
+//! let presentation_context_id = association.presentation_contexts()[0].id;
+//! let message_id = association.next_message_id();
+//! let command = Command::CEchoRQ(CEchoRQ {
+//!     message_id,
+//!     affected_sop_class_uid: "1.2.840.10008.1.1".to_string(),
+//! });
+//! association.send_message(presentation_context_id, &command, None)?;
+//!
+//! let response = association.receive_message()?;
+//! if let Some(status) = response.command.status() {
+//!     println!("C-ECHO status: {:04X}H", status.code());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::collections::VecDeque;
+
+use dicom_core::Tag;
+use snafu::{Backtrace, OptionExt, Snafu};
+
+use crate::pdu::{PDataValue, PDataValueType, Pdu};
+
+pub mod reader;
+pub mod writer;
+
+pub use reader::read_command;
+pub use writer::write_command;
+
+#[derive(Debug, Snafu)]
+#[non_exhaustive]
+pub enum ReadError {
+    #[snafu(display("command set ended unexpectedly"))]
+    UnexpectedEnd { backtrace: Backtrace },
+
+    #[snafu(display("element {} does not belong to the command group", tag))]
+    InvalidTag { tag: Tag, backtrace: Backtrace },
+
+    #[snafu(display("missing command element {}", tag))]
+    MissingElement { tag: Tag, backtrace: Backtrace },
+
+    #[snafu(display("invalid value in command element {}", tag))]
+    InvalidValue { tag: Tag, backtrace: Backtrace },
+
+    #[snafu(display("unknown command field {:04X}H", value))]
+    UnknownCommandField { value: u16, backtrace: Backtrace },
+
+    #[snafu(display(
+        "received a data set fragment (presentation context {}) before a command",
+        presentation_context_id
+    ))]
+    UnexpectedDataFragment {
+        presentation_context_id: u8,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "received a command fragment (presentation context {}) while receiving a data set",
+        presentation_context_id
+    ))]
+    UnexpectedCommandFragment {
+        presentation_context_id: u8,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "message fragment on presentation context {} interleaved with message on presentation context {}",
+        got,
+        expected
+    ))]
+    PresentationContextMismatch {
+        expected: u8,
+        got: u8,
+        backtrace: Backtrace,
+    },
+}
+
+pub type Result<T, E = ReadError> = std::result::Result<T, E>;
+
+/// Elements of the command group (0000,xxxx)
+/// which are recognized by this implementation.
+pub mod tags {
+    use dicom_core::Tag;
+
+    /// (0000,0000) UL Command Group Length
+    pub const COMMAND_GROUP_LENGTH: Tag = Tag(0x0000, 0x0000);
+    /// (0000,0002) UI Affected SOP Class UID
+    pub const AFFECTED_SOP_CLASS_UID: Tag = Tag(0x0000, 0x0002);
+    /// (0000,0003) UI Requested SOP Class UID
+    pub const REQUESTED_SOP_CLASS_UID: Tag = Tag(0x0000, 0x0003);
+    /// (0000,0100) US Command Field
+    pub const COMMAND_FIELD: Tag = Tag(0x0000, 0x0100);
+    /// (0000,0110) US Message ID
+    pub const MESSAGE_ID: Tag = Tag(0x0000, 0x0110);
+    /// (0000,0120) US Message ID Being Responded To
+    pub const MESSAGE_ID_BEING_RESPONDED_TO: Tag = Tag(0x0000, 0x0120);
+    /// (0000,0600) AE Move Destination
+    pub const MOVE_DESTINATION: Tag = Tag(0x0000, 0x0600);
+    /// (0000,0700) US Priority
+    pub const PRIORITY: Tag = Tag(0x0000, 0x0700);
+    /// (0000,0800) US Command Data Set Type
+    pub const COMMAND_DATA_SET_TYPE: Tag = Tag(0x0000, 0x0800);
+    /// (0000,0900) US Status
+    pub const STATUS: Tag = Tag(0x0000, 0x0900);
+    /// (0000,1000) UI Affected SOP Instance UID
+    pub const AFFECTED_SOP_INSTANCE_UID: Tag = Tag(0x0000, 0x1000);
+    /// (0000,1001) UI Requested SOP Instance UID
+    pub const REQUESTED_SOP_INSTANCE_UID: Tag = Tag(0x0000, 0x1001);
+    /// (0000,1002) US Event Type ID
+    pub const EVENT_TYPE_ID: Tag = Tag(0x0000, 0x1002);
+    /// (0000,1005) AT Attribute Identifier List
+    pub const ATTRIBUTE_IDENTIFIER_LIST: Tag = Tag(0x0000, 0x1005);
+    /// (0000,1008) US Action Type ID
+    pub const ACTION_TYPE_ID: Tag = Tag(0x0000, 0x1008);
+    /// (0000,1020) US Number of Remaining Sub-operations
+    pub const NUMBER_OF_REMAINING_SUBOPERATIONS: Tag = Tag(0x0000, 0x1020);
+    /// (0000,1021) US Number of Completed Sub-operations
+    pub const NUMBER_OF_COMPLETED_SUBOPERATIONS: Tag = Tag(0x0000, 0x1021);
+    /// (0000,1022) US Number of Failed Sub-operations
+    pub const NUMBER_OF_FAILED_SUBOPERATIONS: Tag = Tag(0x0000, 0x1022);
+    /// (0000,1023) US Number of Warning Sub-operations
+    pub const NUMBER_OF_WARNING_SUBOPERATIONS: Tag = Tag(0x0000, 0x1023);
+    /// (0000,1030) AE Move Originator Application Entity Title
+    pub const MOVE_ORIGINATOR_APPLICATION_ENTITY_TITLE: Tag = Tag(0x0000, 0x1030);
+    /// (0000,1031) US Move Originator Message ID
+    pub const MOVE_ORIGINATOR_MESSAGE_ID: Tag = Tag(0x0000, 0x1031);
+}
+
+/// The value of Command Data Set Type (0000,0800)
+/// indicating that no data set is present in the message.
+pub const NO_DATA_SET: u16 = 0x0101;
+
+/// The value of Command Data Set Type (0000,0800)
+/// written by this implementation
+/// when a data set is present in the message.
+pub const DATA_SET_PRESENT: u16 = 0x0000;
+
+/// The kind of DIMSE command,
+/// as identified by the Command Field (0000,0100).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CommandField {
+    CStoreRQ,
+    CStoreRSP,
+    CGetRQ,
+    CGetRSP,
+    CFindRQ,
+    CFindRSP,
+    CMoveRQ,
+    CMoveRSP,
+    CEchoRQ,
+    CEchoRSP,
+    NEventReportRQ,
+    NEventReportRSP,
+    NGetRQ,
+    NGetRSP,
+    NSetRQ,
+    NSetRSP,
+    NActionRQ,
+    NActionRSP,
+    NCreateRQ,
+    NCreateRSP,
+    NDeleteRQ,
+    NDeleteRSP,
+    CCancelRQ,
+}
+
+impl CommandField {
+    pub fn from_u16(value: u16) -> Option<Self> {
+        match value {
+            0x0001 => Some(CommandField::CStoreRQ),
+            0x8001 => Some(CommandField::CStoreRSP),
+            0x0010 => Some(CommandField::CGetRQ),
+            0x8010 => Some(CommandField::CGetRSP),
+            0x0020 => Some(CommandField::CFindRQ),
+            0x8020 => Some(CommandField::CFindRSP),
+            0x0021 => Some(CommandField::CMoveRQ),
+            0x8021 => Some(CommandField::CMoveRSP),
+            0x0030 => Some(CommandField::CEchoRQ),
+            0x8030 => Some(CommandField::CEchoRSP),
+            0x0100 => Some(CommandField::NEventReportRQ),
+            0x8100 => Some(CommandField::NEventReportRSP),
+            0x0110 => Some(CommandField::NGetRQ),
+            0x8110 => Some(CommandField::NGetRSP),
+            0x0120 => Some(CommandField::NSetRQ),
+            0x8120 => Some(CommandField::NSetRSP),
+            0x0130 => Some(CommandField::NActionRQ),
+            0x8130 => Some(CommandField::NActionRSP),
+            0x0140 => Some(CommandField::NCreateRQ),
+            0x8140 => Some(CommandField::NCreateRSP),
+            0x0150 => Some(CommandField::NDeleteRQ),
+            0x8150 => Some(CommandField::NDeleteRSP),
+            0x0FFF => Some(CommandField::CCancelRQ),
+            _ => None,
+        }
+    }
+
+    pub fn to_u16(self) -> u16 {
+        match self {
+            CommandField::CStoreRQ => 0x0001,
+            CommandField::CStoreRSP => 0x8001,
+            CommandField::CGetRQ => 0x0010,
+            CommandField::CGetRSP => 0x8010,
+            CommandField::CFindRQ => 0x0020,
+            CommandField::CFindRSP => 0x8020,
+            CommandField::CMoveRQ => 0x0021,
+            CommandField::CMoveRSP => 0x8021,
+            CommandField::CEchoRQ => 0x0030,
+            CommandField::CEchoRSP => 0x8030,
+            CommandField::NEventReportRQ => 0x0100,
+            CommandField::NEventReportRSP => 0x8100,
+            CommandField::NGetRQ => 0x0110,
+            CommandField::NGetRSP => 0x8110,
+            CommandField::NSetRQ => 0x0120,
+            CommandField::NSetRSP => 0x8120,
+            CommandField::NActionRQ => 0x0130,
+            CommandField::NActionRSP => 0x8130,
+            CommandField::NCreateRQ => 0x0140,
+            CommandField::NCreateRSP => 0x8140,
+            CommandField::NDeleteRQ => 0x0150,
+            CommandField::NDeleteRSP => 0x8150,
+            CommandField::CCancelRQ => 0x0FFF,
+        }
+    }
+
+    /// Whether this command field refers to a response message.
+    pub fn is_response(self) -> bool {
+        self.to_u16() & 0x8000 != 0
+    }
+}
+
+/// The priority of a DIMSE-C request operation (0000,0700).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Priority {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl Priority {
+    pub fn from_u16(value: u16) -> Option<Self> {
+        match value {
+            0x0002 => Some(Priority::Low),
+            0x0000 => Some(Priority::Medium),
+            0x0001 => Some(Priority::High),
+            _ => None,
+        }
+    }
+
+    pub fn to_u16(self) -> u16 {
+        match self {
+            Priority::Low => 0x0002,
+            Priority::Medium => 0x0000,
+            Priority::High => 0x0001,
+        }
+    }
+}
+
+/// The general category of a DIMSE status code.
+///
+/// See PS3.7 annex C for the status encoding rules.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum StatusType {
+    Success,
+    Warning,
+    Failure,
+    Cancel,
+    Pending,
+}
+
+/// A DIMSE response status code (0000,0900).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Status(pub u16);
+
+impl Status {
+    /// Success (0000H)
+    pub const SUCCESS: Status = Status(0x0000);
+    /// Cancel (FE00H):
+    /// the operation was terminated due to a cancel request
+    pub const CANCEL: Status = Status(0xFE00);
+    /// Pending (FF00H):
+    /// matches or sub-operations are continuing
+    pub const PENDING: Status = Status(0xFF00);
+    /// Pending (FF01H):
+    /// matches are continuing,
+    /// but one or more optional keys were not supported
+    pub const PENDING_WARNING: Status = Status(0xFF01);
+    /// Processing failure (0110H)
+    pub const PROCESSING_FAILURE: Status = Status(0x0110);
+    /// Refused: SOP class not supported (0122H)
+    pub const SOP_CLASS_NOT_SUPPORTED: Status = Status(0x0122);
+    /// No such action type (0123H)
+    pub const NO_SUCH_ACTION_TYPE: Status = Status(0x0123);
+    /// Unrecognized operation (0211H)
+    pub const UNRECOGNIZED_OPERATION: Status = Status(0x0211);
+    /// Refused: out of resources (A700H)
+    pub const OUT_OF_RESOURCES: Status = Status(0xA700);
+    /// Error: cannot understand (C000H)
+    pub const CANNOT_UNDERSTAND: Status = Status(0xC000);
+
+    /// Retrieve the status code.
+    pub fn code(self) -> u16 {
+        self.0
+    }
+
+    /// Obtain the general category of this status code.
+    pub fn status_type(self) -> StatusType {
+        match self.0 {
+            0x0000 => StatusType::Success,
+            0x0001 | 0x0107 | 0x0116 | 0xB000..=0xBFFF => StatusType::Warning,
+            0xFE00 => StatusType::Cancel,
+            0xFF00 | 0xFF01 => StatusType::Pending,
+            _ => StatusType::Failure,
+        }
+    }
+
+    pub fn is_success(self) -> bool {
+        self.status_type() == StatusType::Success
+    }
+
+    pub fn is_warning(self) -> bool {
+        self.status_type() == StatusType::Warning
+    }
+
+    pub fn is_failure(self) -> bool {
+        self.status_type() == StatusType::Failure
+    }
+
+    pub fn is_cancel(self) -> bool {
+        self.status_type() == StatusType::Cancel
+    }
+
+    pub fn is_pending(self) -> bool {
+        self.status_type() == StatusType::Pending
+    }
+}
+
+impl From<u16> for Status {
+    fn from(code: u16) -> Self {
+        Status(code)
+    }
+}
+
+/// C-ECHO-RQ message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CEchoRQ {
+    pub message_id: u16,
+    pub affected_sop_class_uid: String,
+}
+
+/// C-ECHO-RSP message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CEchoRSP {
+    pub message_id_being_responded_to: u16,
+    pub affected_sop_class_uid: String,
+    pub status: Status,
+}
+
+/// C-STORE-RQ message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CStoreRQ {
+    pub message_id: u16,
+    pub affected_sop_class_uid: String,
+    pub affected_sop_instance_uid: String,
+    pub priority: Priority,
+    /// the AE title of the C-MOVE SCU
+    /// which originated this C-STORE sub-operation
+    pub move_originator_ae_title: Option<String>,
+    /// the message ID of the C-MOVE request
+    /// which originated this C-STORE sub-operation
+    pub move_originator_message_id: Option<u16>,
+}
+
+/// C-STORE-RSP message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CStoreRSP {
+    pub message_id_being_responded_to: u16,
+    pub affected_sop_class_uid: String,
+    pub affected_sop_instance_uid: String,
+    pub status: Status,
+}
+
+/// C-FIND-RQ message command
+///
+/// The identifier is sent in the message's data set.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CFindRQ {
+    pub message_id: u16,
+    pub affected_sop_class_uid: String,
+    pub priority: Priority,
+}
+
+/// C-FIND-RSP message command
+///
+/// Pending responses carry a matching identifier in the data set.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CFindRSP {
+    pub message_id_being_responded_to: u16,
+    pub affected_sop_class_uid: String,
+    pub status: Status,
+}
+
+/// C-GET-RQ message command
+///
+/// The identifier is sent in the message's data set.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CGetRQ {
+    pub message_id: u16,
+    pub affected_sop_class_uid: String,
+    pub priority: Priority,
+}
+
+/// C-GET-RSP message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CGetRSP {
+    pub message_id_being_responded_to: u16,
+    pub affected_sop_class_uid: String,
+    pub status: Status,
+    pub sub_operations: SubOperations,
+}
+
+/// C-MOVE-RQ message command
+///
+/// The identifier is sent in the message's data set.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CMoveRQ {
+    pub message_id: u16,
+    pub affected_sop_class_uid: String,
+    pub priority: Priority,
+    /// the AE title of the storage destination
+    pub move_destination: String,
+}
+
+/// C-MOVE-RSP message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CMoveRSP {
+    pub message_id_being_responded_to: u16,
+    pub affected_sop_class_uid: String,
+    pub status: Status,
+    pub sub_operations: SubOperations,
+}
+
+/// The sub-operation counters of a C-GET or C-MOVE response.
+///
+/// Each counter is optional,
+/// as not all of them are present in every response.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SubOperations {
+    pub remaining: Option<u16>,
+    pub completed: Option<u16>,
+    pub failed: Option<u16>,
+    pub warning: Option<u16>,
+}
+
+/// C-CANCEL-RQ message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CCancelRQ {
+    /// the message ID of the operation to cancel
+    pub message_id_being_responded_to: u16,
+}
+
+/// N-EVENT-REPORT-RQ message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NEventReportRQ {
+    pub message_id: u16,
+    pub affected_sop_class_uid: String,
+    pub affected_sop_instance_uid: String,
+    pub event_type_id: u16,
+}
+
+/// N-EVENT-REPORT-RSP message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NEventReportRSP {
+    pub message_id_being_responded_to: u16,
+    pub affected_sop_class_uid: Option<String>,
+    pub affected_sop_instance_uid: Option<String>,
+    pub event_type_id: Option<u16>,
+    pub status: Status,
+}
+
+/// N-GET-RQ message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NGetRQ {
+    pub message_id: u16,
+    pub requested_sop_class_uid: String,
+    pub requested_sop_instance_uid: String,
+    /// the attributes to retrieve
+    /// (all attributes if empty)
+    pub attribute_identifier_list: Vec<Tag>,
+}
+
+/// N-GET-RSP message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NGetRSP {
+    pub message_id_being_responded_to: u16,
+    pub affected_sop_class_uid: Option<String>,
+    pub affected_sop_instance_uid: Option<String>,
+    pub status: Status,
+}
+
+/// N-SET-RQ message command
+///
+/// The modification list is sent in the message's data set.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NSetRQ {
+    pub message_id: u16,
+    pub requested_sop_class_uid: String,
+    pub requested_sop_instance_uid: String,
+}
+
+/// N-SET-RSP message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NSetRSP {
+    pub message_id_being_responded_to: u16,
+    pub affected_sop_class_uid: Option<String>,
+    pub affected_sop_instance_uid: Option<String>,
+    pub status: Status,
+}
+
+/// N-ACTION-RQ message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NActionRQ {
+    pub message_id: u16,
+    pub requested_sop_class_uid: String,
+    pub requested_sop_instance_uid: String,
+    pub action_type_id: u16,
+}
+
+/// N-ACTION-RSP message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NActionRSP {
+    pub message_id_being_responded_to: u16,
+    pub affected_sop_class_uid: Option<String>,
+    pub affected_sop_instance_uid: Option<String>,
+    pub action_type_id: Option<u16>,
+    pub status: Status,
+}
+
+/// N-CREATE-RQ message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NCreateRQ {
+    pub message_id: u16,
+    pub affected_sop_class_uid: String,
+    /// the SOP instance UID to create,
+    /// or `None` to let the performing DIMSE user assign one
+    pub affected_sop_instance_uid: Option<String>,
+}
+
+/// N-CREATE-RSP message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NCreateRSP {
+    pub message_id_being_responded_to: u16,
+    pub affected_sop_class_uid: Option<String>,
+    pub affected_sop_instance_uid: Option<String>,
+    pub status: Status,
+}
+
+/// N-DELETE-RQ message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NDeleteRQ {
+    pub message_id: u16,
+    pub requested_sop_class_uid: String,
+    pub requested_sop_instance_uid: String,
+}
+
+/// N-DELETE-RSP message command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NDeleteRSP {
+    pub message_id_being_responded_to: u16,
+    pub affected_sop_class_uid: Option<String>,
+    pub affected_sop_instance_uid: Option<String>,
+    pub status: Status,
+}
+
+/// A DIMSE command set.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Command {
+    CEchoRQ(CEchoRQ),
+    CEchoRSP(CEchoRSP),
+    CStoreRQ(CStoreRQ),
+    CStoreRSP(CStoreRSP),
+    CFindRQ(CFindRQ),
+    CFindRSP(CFindRSP),
+    CGetRQ(CGetRQ),
+    CGetRSP(CGetRSP),
+    CMoveRQ(CMoveRQ),
+    CMoveRSP(CMoveRSP),
+    CCancelRQ(CCancelRQ),
+    NEventReportRQ(NEventReportRQ),
+    NEventReportRSP(NEventReportRSP),
+    NGetRQ(NGetRQ),
+    NGetRSP(NGetRSP),
+    NSetRQ(NSetRQ),
+    NSetRSP(NSetRSP),
+    NActionRQ(NActionRQ),
+    NActionRSP(NActionRSP),
+    NCreateRQ(NCreateRQ),
+    NCreateRSP(NCreateRSP),
+    NDeleteRQ(NDeleteRQ),
+    NDeleteRSP(NDeleteRSP),
+}
+
+impl Command {
+    /// Obtain the command field of this command.
+    pub fn command_field(&self) -> CommandField {
+        match self {
+            Command::CEchoRQ(_) => CommandField::CEchoRQ,
+            Command::CEchoRSP(_) => CommandField::CEchoRSP,
+            Command::CStoreRQ(_) => CommandField::CStoreRQ,
+            Command::CStoreRSP(_) => CommandField::CStoreRSP,
+            Command::CFindRQ(_) => CommandField::CFindRQ,
+            Command::CFindRSP(_) => CommandField::CFindRSP,
+            Command::CGetRQ(_) => CommandField::CGetRQ,
+            Command::CGetRSP(_) => CommandField::CGetRSP,
+            Command::CMoveRQ(_) => CommandField::CMoveRQ,
+            Command::CMoveRSP(_) => CommandField::CMoveRSP,
+            Command::CCancelRQ(_) => CommandField::CCancelRQ,
+            Command::NEventReportRQ(_) => CommandField::NEventReportRQ,
+            Command::NEventReportRSP(_) => CommandField::NEventReportRSP,
+            Command::NGetRQ(_) => CommandField::NGetRQ,
+            Command::NGetRSP(_) => CommandField::NGetRSP,
+            Command::NSetRQ(_) => CommandField::NSetRQ,
+            Command::NSetRSP(_) => CommandField::NSetRSP,
+            Command::NActionRQ(_) => CommandField::NActionRQ,
+            Command::NActionRSP(_) => CommandField::NActionRSP,
+            Command::NCreateRQ(_) => CommandField::NCreateRQ,
+            Command::NCreateRSP(_) => CommandField::NCreateRSP,
+            Command::NDeleteRQ(_) => CommandField::NDeleteRQ,
+            Command::NDeleteRSP(_) => CommandField::NDeleteRSP,
+        }
+    }
+
+    /// Obtain the message ID of this command,
+    /// if it is a request other than C-CANCEL-RQ.
+    pub fn message_id(&self) -> Option<u16> {
+        match self {
+            Command::CEchoRQ(c) => Some(c.message_id),
+            Command::CStoreRQ(c) => Some(c.message_id),
+            Command::CFindRQ(c) => Some(c.message_id),
+            Command::CGetRQ(c) => Some(c.message_id),
+            Command::CMoveRQ(c) => Some(c.message_id),
+            Command::NEventReportRQ(c) => Some(c.message_id),
+            Command::NGetRQ(c) => Some(c.message_id),
+            Command::NSetRQ(c) => Some(c.message_id),
+            Command::NActionRQ(c) => Some(c.message_id),
+            Command::NCreateRQ(c) => Some(c.message_id),
+            Command::NDeleteRQ(c) => Some(c.message_id),
+            _ => None,
+        }
+    }
+
+    /// Obtain the ID of the message being responded to,
+    /// if this command is a response or a C-CANCEL-RQ.
+    pub fn message_id_being_responded_to(&self) -> Option<u16> {
+        match self {
+            Command::CEchoRSP(c) => Some(c.message_id_being_responded_to),
+            Command::CStoreRSP(c) => Some(c.message_id_being_responded_to),
+            Command::CFindRSP(c) => Some(c.message_id_being_responded_to),
+            Command::CGetRSP(c) => Some(c.message_id_being_responded_to),
+            Command::CMoveRSP(c) => Some(c.message_id_being_responded_to),
+            Command::CCancelRQ(c) => Some(c.message_id_being_responded_to),
+            Command::NEventReportRSP(c) => Some(c.message_id_being_responded_to),
+            Command::NGetRSP(c) => Some(c.message_id_being_responded_to),
+            Command::NSetRSP(c) => Some(c.message_id_being_responded_to),
+            Command::NActionRSP(c) => Some(c.message_id_being_responded_to),
+            Command::NCreateRSP(c) => Some(c.message_id_being_responded_to),
+            Command::NDeleteRSP(c) => Some(c.message_id_being_responded_to),
+            _ => None,
+        }
+    }
+
+    /// Obtain the status of this command,
+    /// if it is a response.
+    pub fn status(&self) -> Option<Status> {
+        match self {
+            Command::CEchoRSP(c) => Some(c.status),
+            Command::CStoreRSP(c) => Some(c.status),
+            Command::CFindRSP(c) => Some(c.status),
+            Command::CGetRSP(c) => Some(c.status),
+            Command::CMoveRSP(c) => Some(c.status),
+            Command::NEventReportRSP(c) => Some(c.status),
+            Command::NGetRSP(c) => Some(c.status),
+            Command::NSetRSP(c) => Some(c.status),
+            Command::NActionRSP(c) => Some(c.status),
+            Command::NCreateRSP(c) => Some(c.status),
+            Command::NDeleteRSP(c) => Some(c.status),
+            _ => None,
+        }
+    }
+}
+
+/// A full DIMSE message,
+/// comprising a command set and an optional data set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// the presentation context in which the message was conveyed
+    pub presentation_context_id: u8,
+    /// the command set
+    pub command: Command,
+    /// the encoded data set,
+    /// in the transfer syntax of the presentation context
+    pub data: Option<Vec<u8>>,
+}
+
+/// The command set of a DIMSE message,
+/// received ahead of its data set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageCommand {
+    /// the presentation context in which the message is conveyed
+    pub presentation_context_id: u8,
+    /// the command set
+    pub command: Command,
+    /// whether a data set follows the command set
+    pub has_data_set: bool,
+}
+
+/// Build the P-Data PDU which starts the transfer of a DIMSE message.
+///
+/// The data set is packed into the same PDU as the command
+/// if both fit in a PDU of `max_pdu_length` bytes.
+/// Otherwise, the data set is returned back
+/// so that it can be sent through a P-Data writer.
+pub(crate) fn command_pdu<'d>(
+    presentation_context_id: u8,
+    command: &Command,
+    data: Option<&'d [u8]>,
+    max_pdu_length: u32,
+) -> (Pdu, Option<&'d [u8]>) {
+    let command_data = write_command(command, data.is_some());
+    let mut pdu_data = vec![PDataValue {
+        presentation_context_id,
+        value_type: PDataValueType::Command,
+        is_last: true,
+        data: command_data,
+    }];
+
+    let remaining = match data {
+        Some(data) => {
+            // PDU header + 2 PDV headers
+            let total_len = 6 + 6 + pdu_data[0].data.len() + 6 + data.len();
+            if total_len <= max_pdu_length as usize {
+                pdu_data.push(PDataValue {
+                    presentation_context_id,
+                    value_type: PDataValueType::Data,
+                    is_last: true,
+                    data: data.to_vec(),
+                });
+                None
+            } else {
+                Some(data)
+            }
+        }
+        None => None,
+    };
+
+    (Pdu::PData { data: pdu_data }, remaining)
+}
+
+/// Incremental assembler of DIMSE messages
+/// from the P-Data values received in an association.
+#[derive(Debug, Default)]
+pub(crate) struct MessageAssembler {
+    /// P-Data values received but not yet processed
+    pending: VecDeque<PDataValue>,
+    /// the presentation context of the message in progress
+    presentation_context_id: Option<u8>,
+    /// command set fragments collected so far
+    command_data: Vec<u8>,
+    /// the decoded command, once all of its fragments arrive,
+    /// and whether a data set is expected
+    command: Option<(Command, bool)>,
+    /// data set fragments collected so far
+    data: Vec<u8>,
+}
+
+impl MessageAssembler {
+    /// Queue the P-Data values of a newly received PDU.
+    pub(crate) fn push(&mut self, data: Vec<PDataValue>) {
+        self.pending.extend(data);
+    }
+
+    /// Whether no part of a message has been received
+    /// since the last message was assembled.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.presentation_context_id.is_none()
+    }
+
+    /// Consume the pending P-Data values
+    /// until a full message is assembled.
+    ///
+    /// Returns `None` if more P-Data values are needed.
+    pub(crate) fn next_message(&mut self) -> Result<Option<Message>> {
+        Ok(self.assemble(true)?.map(|(message, _)| message))
+    }
+
+    /// Consume the pending P-Data values
+    /// until the command set of the next message is assembled,
+    /// leaving the fragments of its data set to
+    /// [`take_data_set_fragments`](Self::take_data_set_fragments).
+    ///
+    /// Returns `None` if more P-Data values are needed.
+    pub(crate) fn next_command(&mut self) -> Result<Option<MessageCommand>> {
+        Ok(self
+            .assemble(false)?
+            .map(|(message, has_data_set)| MessageCommand {
+                presentation_context_id: message.presentation_context_id,
+                command: message.command,
+                has_data_set,
+            }))
+    }
+
+    /// Take the data set fragments already received
+    /// in the given presentation context,
+    /// up to the last fragment of the data set.
+    ///
+    /// Returns the bytes collected
+    /// and whether the last fragment was among them.
+    pub(crate) fn take_data_set_fragments(
+        &mut self,
+        presentation_context_id: u8,
+    ) -> (Vec<u8>, bool) {
+        let mut data = Vec::new();
+        while let Some(pdv) = self.pending.front() {
+            if pdv.value_type != PDataValueType::Data
+                || pdv.presentation_context_id != presentation_context_id
+            {
+                break;
+            }
+            let pdv = self.pending.pop_front().unwrap();
+            data.extend(pdv.data);
+            if pdv.is_last {
+                return (data, true);
+            }
+        }
+        (data, false)
+    }
+
+    /// Assemble the next message,
+    /// along with whether it has a data set.
+    ///
+    /// Unless `with_data_set` is true,
+    /// the message is returned as soon as its command set is complete.
+    fn assemble(&mut self, with_data_set: bool) -> Result<Option<(Message, bool)>> {
+        while let Some(pdv) = self.pending.pop_front() {
+            let presentation_context_id = *self
+                .presentation_context_id
+                .get_or_insert(pdv.presentation_context_id);
+            if presentation_context_id != pdv.presentation_context_id {
+                self.reset();
+                return PresentationContextMismatchSnafu {
+                    expected: presentation_context_id,
+                    got: pdv.presentation_context_id,
+                }
+                .fail();
+            }
+
+            match (pdv.value_type, &self.command) {
+                (PDataValueType::Command, None) => {
+                    self.command_data.extend(pdv.data);
+                    if pdv.is_last {
+                        let (command, has_data_set) =
+                            read_command(&self.command_data).map_err(|e| {
+                                self.reset();
+                                e
+                            })?;
+                        self.command_data.clear();
+                        if !has_data_set || !with_data_set {
+                            self.presentation_context_id = None;
+                            return Ok(Some((
+                                Message {
+                                    presentation_context_id,
+                                    command,
+                                    data: None,
+                                },
+                                has_data_set,
+                            )));
+                        }
+                        self.command = Some((command, has_data_set));
+                    }
+                }
+                (PDataValueType::Data, Some(_)) => {
+                    self.data.extend(pdv.data);
+                    if pdv.is_last {
+                        let (command, _) = self.command.take().context(UnexpectedEndSnafu)?;
+                        self.presentation_context_id = None;
+                        return Ok(Some((
+                            Message {
+                                presentation_context_id,
+                                command,
+                                data: Some(std::mem::take(&mut self.data)),
+                            },
+                            true,
+                        )));
+                    }
+                }
+                (PDataValueType::Data, None) => {
+                    self.reset();
+                    return UnexpectedDataFragmentSnafu {
+                        presentation_context_id,
+                    }
+                    .fail();
+                }
+                (PDataValueType::Command, Some(_)) => {
+                    self.reset();
+                    return UnexpectedCommandFragmentSnafu {
+                        presentation_context_id,
+                    }
+                    .fail();
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Discard the message in progress,
+    /// so that the next P-Data value starts a new message.
+    fn reset(&mut self) {
+        self.presentation_context_id = None;
+        self.command = None;
+        self.command_data.clear();
+        self.data.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c_store_rq() -> Command {
+        Command::CStoreRQ(CStoreRQ {
+            message_id: 7,
+            affected_sop_class_uid: "1.2.840.10008.5.1.4.1.1.7".to_string(),
+            affected_sop_instance_uid: "2.25.1234".to_string(),
+            priority: Priority::Medium,
+            move_originator_ae_title: None,
+            move_originator_message_id: None,
+        })
+    }
+
+    #[test]
+    fn status_types() {
+        assert_eq!(Status::SUCCESS.status_type(), StatusType::Success);
+        assert_eq!(Status(0xB000).status_type(), StatusType::Warning);
+        assert_eq!(Status(0x0107).status_type(), StatusType::Warning);
+        assert_eq!(Status::PENDING.status_type(), StatusType::Pending);
+        assert_eq!(Status::PENDING_WARNING.status_type(), StatusType::Pending);
+        assert_eq!(Status::CANCEL.status_type(), StatusType::Cancel);
+        assert_eq!(Status(0xA700).status_type(), StatusType::Failure);
+        assert_eq!(Status(0xC000).status_type(), StatusType::Failure);
+    }
+
+    #[test]
+    fn command_field_roundtrip() {
+        for value in 0..=0xFFFF_u16 {
+            if let Some(field) = CommandField::from_u16(value) {
+                assert_eq!(field.to_u16(), value);
+            }
+        }
+        assert!(CommandField::CEchoRSP.is_response());
+        assert!(!CommandField::CCancelRQ.is_response());
+    }
+
+    #[test]
+    fn assemble_message_in_one_pdu() {
+        let command = c_store_rq();
+        let (pdu, rest) = command_pdu(1, &command, Some(&[1, 2, 3, 4]), 16_384);
+        assert_eq!(rest, None);
+        let data = match pdu {
+            Pdu::PData { data } => data,
+            _ => unreachable!(),
+        };
+        assert_eq!(data.len(), 2);
+
+        let mut assembler = MessageAssembler::default();
+        assembler.push(data);
+        let message = assembler.next_message().unwrap().unwrap();
+        assert_eq!(
+            message,
+            Message {
+                presentation_context_id: 1,
+                command,
+                data: Some(vec![1, 2, 3, 4]),
+            }
+        );
+        assert_eq!(assembler.next_message().unwrap(), None);
+    }
+
+    #[test]
+    fn assemble_fragmented_messages() {
+        let command = c_store_rq();
+        let command_data = write_command(&command, true);
+        let (head, tail) = command_data.split_at(10);
+
+        let mut assembler = MessageAssembler::default();
+        assembler.push(vec![PDataValue {
+            presentation_context_id: 3,
+            value_type: PDataValueType::Command,
+            is_last: false,
+            data: head.to_vec(),
+        }]);
+        assert_eq!(assembler.next_message().unwrap(), None);
+        assembler.push(vec![
+            PDataValue {
+                presentation_context_id: 3,
+                value_type: PDataValueType::Command,
+                is_last: true,
+                data: tail.to_vec(),
+            },
+            PDataValue {
+                presentation_context_id: 3,
+                value_type: PDataValueType::Data,
+                is_last: false,
+                data: vec![0xAA; 6],
+            },
+        ]);
+        assert_eq!(assembler.next_message().unwrap(), None);
+
+        // last data fragment followed by a second message
+        let echo = Command::CEchoRQ(CEchoRQ {
+            message_id: 8,
+            affected_sop_class_uid: "1.2.840.10008.1.1".to_string(),
+        });
+        assembler.push(vec![
+            PDataValue {
+                presentation_context_id: 3,
+                value_type: PDataValueType::Data,
+                is_last: true,
+                data: vec![0xBB; 2],
+            },
+            PDataValue {
+                presentation_context_id: 1,
+                value_type: PDataValueType::Command,
+                is_last: true,
+                data: write_command(&echo, false),
+            },
+        ]);
+
+        let message = assembler.next_message().unwrap().unwrap();
+        assert_eq!(message.presentation_context_id, 3);
+        assert_eq!(message.command, command);
+        assert_eq!(
+            message.data,
+            Some(vec![0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xBB, 0xBB])
+        );
+
+        let message = assembler.next_message().unwrap().unwrap();
+        assert_eq!(message.presentation_context_id, 1);
+        assert_eq!(message.command, echo);
+        assert_eq!(message.data, None);
+    }
+
+    #[test]
+    fn assemble_command_ahead_of_data_set() {
+        let command = c_store_rq();
+        let mut assembler = MessageAssembler::default();
+        assembler.push(vec![
+            PDataValue {
+                presentation_context_id: 3,
+                value_type: PDataValueType::Command,
+                is_last: true,
+                data: write_command(&command, true),
+            },
+            PDataValue {
+                presentation_context_id: 3,
+                value_type: PDataValueType::Data,
+                is_last: false,
+                data: vec![0xAA; 6],
+            },
+        ]);
+
+        assert_eq!(
+            assembler.next_command().unwrap(),
+            Some(MessageCommand {
+                presentation_context_id: 3,
+                command,
+                has_data_set: true,
+            })
+        );
+        assert_eq!(assembler.take_data_set_fragments(3), (vec![0xAA; 6], false));
+        assert!(assembler.is_empty());
+
+        assembler.push(vec![
+            PDataValue {
+                presentation_context_id: 3,
+                value_type: PDataValueType::Data,
+                is_last: true,
+                data: vec![0xBB; 2],
+            },
+            PDataValue {
+                presentation_context_id: 3,
+                value_type: PDataValueType::Data,
+                is_last: true,
+                data: vec![0xCC; 2],
+            },
+        ]);
+        assert_eq!(assembler.take_data_set_fragments(3), (vec![0xBB; 2], true));
+        assert!(!assembler.is_empty());
+    }
+
+    #[test]
+    fn data_before_command_is_an_error() {
+        let mut assembler = MessageAssembler::default();
+        assembler.push(vec![PDataValue {
+            presentation_context_id: 1,
+            value_type: PDataValueType::Data,
+            is_last: true,
+            data: vec![0; 4],
+        }]);
+        assert!(matches!(
+            assembler.next_message(),
+            Err(ReadError::UnexpectedDataFragment {
+                presentation_context_id: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn command_during_data_set_is_an_error() {
+        let mut assembler = MessageAssembler::default();
+        let (pdu, _) = command_pdu(1, &c_store_rq(), Some(&[0xAA; 4]), 16_384);
+        let Pdu::PData { data } = pdu else {
+            unreachable!()
+        };
+        // the command set, but only part of the data set
+        assembler.push(vec![
+            data[0].clone(),
+            PDataValue {
+                presentation_context_id: 1,
+                value_type: PDataValueType::Data,
+                is_last: false,
+                data: vec![0xAA; 2],
+            },
+        ]);
+        assert!(matches!(assembler.next_message(), Ok(None)));
+        assembler.push(vec![data[0].clone()]);
+        assert!(matches!(
+            assembler.next_message(),
+            Err(ReadError::UnexpectedCommandFragment {
+                presentation_context_id: 1,
+                ..
+            })
+        ));
+        assert!(assembler.is_empty());
+    }
+
+    #[test]
+    fn presentation_context_mismatch_discards_message() {
+        let mut assembler = MessageAssembler::default();
+        let (pdu, _) = command_pdu(1, &c_store_rq(), Some(&[0xAA; 4]), 16_384);
+        let Pdu::PData { data } = pdu else {
+            unreachable!()
+        };
+        let mut other = data[0].clone();
+        other.presentation_context_id = 3;
+        assembler.push(vec![data[0].clone(), other]);
+        assert!(matches!(
+            assembler.next_message(),
+            Err(ReadError::PresentationContextMismatch {
+                expected: 1,
+                got: 3,
+                ..
+            })
+        ));
+        assert!(assembler.is_empty());
+
+        // a new message can be assembled afterwards
+        assembler.push(data);
+        let message = assembler.next_message().unwrap().unwrap();
+        assert_eq!(message.presentation_context_id, 1);
+        assert_eq!(message.data.as_deref(), Some(&[0xAA; 4][..]));
+    }
+
+    #[test]
+    fn large_data_set_is_left_out_of_command_pdu() {
+        let command = c_store_rq();
+        let data = vec![0_u8; 20_000];
+        let (pdu, rest) = command_pdu(1, &command, Some(&data), 16_384);
+        assert_eq!(rest.map(|d| d.len()), Some(20_000));
+        match pdu {
+            Pdu::PData { data } => {
+                assert_eq!(data.len(), 1);
+                assert_eq!(data[0].value_type, PDataValueType::Command);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
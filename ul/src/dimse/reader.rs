@@ -0,0 +1,380 @@
+//! DIMSE command set reader module
+use std::collections::BTreeMap;
+
+use dicom_core::Tag;
+use snafu::{ensure, OptionExt};
+
+use super::{
+    tags, CCancelRQ, CEchoRQ, CEchoRSP, CFindRQ, CFindRSP, CGetRQ, CGetRSP, CMoveRQ, CMoveRSP,
+    CStoreRQ, CStoreRSP, Command, CommandField, InvalidTagSnafu, InvalidValueSnafu,
+    MissingElementSnafu, NActionRQ, NActionRSP, NCreateRQ, NCreateRSP, NDeleteRQ, NDeleteRSP,
+    NEventReportRQ, NEventReportRSP, NGetRQ, NGetRSP, NSetRQ, NSetRSP, Priority, Result, Status,
+    SubOperations, UnexpectedEndSnafu, UnknownCommandFieldSnafu, NO_DATA_SET,
+};
+
+/// The elements of a command set, indexed by tag.
+struct CommandSet<'a> {
+    elements: BTreeMap<Tag, &'a [u8]>,
+}
+
+impl<'a> CommandSet<'a> {
+    fn parse(mut data: &'a [u8]) -> Result<Self> {
+        let mut elements = BTreeMap::new();
+        while !data.is_empty() {
+            ensure!(data.len() >= 8, UnexpectedEndSnafu);
+            let group = u16::from_le_bytes([data[0], data[1]]);
+            let element = u16::from_le_bytes([data[2], data[3]]);
+            let len = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+            let tag = Tag(group, element);
+            ensure!(group == 0x0000, InvalidTagSnafu { tag });
+            data = &data[8..];
+            ensure!(data.len() >= len, UnexpectedEndSnafu);
+            elements.insert(tag, &data[..len]);
+            data = &data[len..];
+        }
+        Ok(CommandSet { elements })
+    }
+
+    fn us_opt(&self, tag: Tag) -> Result<Option<u16>> {
+        match self.elements.get(&tag) {
+            None => Ok(None),
+            Some(&&[a, b]) => Ok(Some(u16::from_le_bytes([a, b]))),
+            Some(_) => InvalidValueSnafu { tag }.fail(),
+        }
+    }
+
+    fn us(&self, tag: Tag) -> Result<u16> {
+        self.us_opt(tag)?.context(MissingElementSnafu { tag })
+    }
+
+    /// Read a textual value (UI or AE),
+    /// with trailing padding removed.
+    fn str_opt(&self, tag: Tag) -> Result<Option<String>> {
+        match self.elements.get(&tag) {
+            None => Ok(None),
+            Some(data) => {
+                let text = std::str::from_utf8(data)
+                    .ok()
+                    .context(InvalidValueSnafu { tag })?;
+                Ok(Some(text.trim_end_matches(['\0', ' ']).to_string()))
+            }
+        }
+    }
+
+    fn str(&self, tag: Tag) -> Result<String> {
+        self.str_opt(tag)?.context(MissingElementSnafu { tag })
+    }
+
+    fn at(&self, tag: Tag) -> Result<Vec<Tag>> {
+        match self.elements.get(&tag) {
+            None => Ok(Vec::new()),
+            Some(data) => {
+                ensure!(data.len() % 4 == 0, InvalidValueSnafu { tag });
+                Ok(data
+                    .chunks_exact(4)
+                    .map(|c| {
+                        Tag(
+                            u16::from_le_bytes([c[0], c[1]]),
+                            u16::from_le_bytes([c[2], c[3]]),
+                        )
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    fn priority(&self) -> Result<Priority> {
+        let tag = tags::PRIORITY;
+        match self.us_opt(tag)? {
+            // priority is not always given, assume medium
+            None => Ok(Priority::Medium),
+            Some(value) => Priority::from_u16(value).context(InvalidValueSnafu { tag }),
+        }
+    }
+
+    fn status(&self) -> Result<Status> {
+        self.us(tags::STATUS).map(Status)
+    }
+
+    fn sub_operations(&self) -> Result<SubOperations> {
+        Ok(SubOperations {
+            remaining: self.us_opt(tags::NUMBER_OF_REMAINING_SUBOPERATIONS)?,
+            completed: self.us_opt(tags::NUMBER_OF_COMPLETED_SUBOPERATIONS)?,
+            failed: self.us_opt(tags::NUMBER_OF_FAILED_SUBOPERATIONS)?,
+            warning: self.us_opt(tags::NUMBER_OF_WARNING_SUBOPERATIONS)?,
+        })
+    }
+}
+
+/// Decode a DIMSE command set encoded in _Implicit VR Little Endian_.
+///
+/// Returns the command
+/// alongside with whether a data set is expected to follow it,
+/// as declared by the Command Data Set Type (0000,0800).
+/// Command elements which are not part of the command's definition
+/// are ignored.
+pub fn read_command(data: &[u8]) -> Result<(Command, bool)> {
+    let set = CommandSet::parse(data)?;
+
+    let command_field = set.us(tags::COMMAND_FIELD)?;
+    let command_field =
+        CommandField::from_u16(command_field).context(UnknownCommandFieldSnafu {
+            value: command_field,
+        })?;
+    let has_data_set = set.us(tags::COMMAND_DATA_SET_TYPE)? != NO_DATA_SET;
+
+    let command = match command_field {
+        CommandField::CEchoRQ => Command::CEchoRQ(CEchoRQ {
+            message_id: set.us(tags::MESSAGE_ID)?,
+            affected_sop_class_uid: set.str(tags::AFFECTED_SOP_CLASS_UID)?,
+        }),
+        CommandField::CEchoRSP => Command::CEchoRSP(CEchoRSP {
+            message_id_being_responded_to: set.us(tags::MESSAGE_ID_BEING_RESPONDED_TO)?,
+            affected_sop_class_uid: set.str(tags::AFFECTED_SOP_CLASS_UID)?,
+            status: set.status()?,
+        }),
+        CommandField::CStoreRQ => Command::CStoreRQ(CStoreRQ {
+            message_id: set.us(tags::MESSAGE_ID)?,
+            affected_sop_class_uid: set.str(tags::AFFECTED_SOP_CLASS_UID)?,
+            affected_sop_instance_uid: set.str(tags::AFFECTED_SOP_INSTANCE_UID)?,
+            priority: set.priority()?,
+            move_originator_ae_title: set
+                .str_opt(tags::MOVE_ORIGINATOR_APPLICATION_ENTITY_TITLE)?,
+            move_originator_message_id: set.us_opt(tags::MOVE_ORIGINATOR_MESSAGE_ID)?,
+        }),
+        CommandField::CStoreRSP => Command::CStoreRSP(CStoreRSP {
+            message_id_being_responded_to: set.us(tags::MESSAGE_ID_BEING_RESPONDED_TO)?,
+            affected_sop_class_uid: set.str(tags::AFFECTED_SOP_CLASS_UID)?,
+            affected_sop_instance_uid: set.str(tags::AFFECTED_SOP_INSTANCE_UID)?,
+            status: set.status()?,
+        }),
+        CommandField::CFindRQ => Command::CFindRQ(CFindRQ {
+            message_id: set.us(tags::MESSAGE_ID)?,
+            affected_sop_class_uid: set.str(tags::AFFECTED_SOP_CLASS_UID)?,
+            priority: set.priority()?,
+        }),
+        CommandField::CFindRSP => Command::CFindRSP(CFindRSP {
+            message_id_being_responded_to: set.us(tags::MESSAGE_ID_BEING_RESPONDED_TO)?,
+            affected_sop_class_uid: set.str(tags::AFFECTED_SOP_CLASS_UID)?,
+            status: set.status()?,
+        }),
+        CommandField::CGetRQ => Command::CGetRQ(CGetRQ {
+            message_id: set.us(tags::MESSAGE_ID)?,
+            affected_sop_class_uid: set.str(tags::AFFECTED_SOP_CLASS_UID)?,
+            priority: set.priority()?,
+        }),
+        CommandField::CGetRSP => Command::CGetRSP(CGetRSP {
+            message_id_being_responded_to: set.us(tags::MESSAGE_ID_BEING_RESPONDED_TO)?,
+            affected_sop_class_uid: set.str(tags::AFFECTED_SOP_CLASS_UID)?,
+            status: set.status()?,
+            sub_operations: set.sub_operations()?,
+        }),
+        CommandField::CMoveRQ => Command::CMoveRQ(CMoveRQ {
+            message_id: set.us(tags::MESSAGE_ID)?,
+            affected_sop_class_uid: set.str(tags::AFFECTED_SOP_CLASS_UID)?,
+            priority: set.priority()?,
+            move_destination: set.str(tags::MOVE_DESTINATION)?,
+        }),
+        CommandField::CMoveRSP => Command::CMoveRSP(CMoveRSP {
+            message_id_being_responded_to: set.us(tags::MESSAGE_ID_BEING_RESPONDED_TO)?,
+            affected_sop_class_uid: set.str(tags::AFFECTED_SOP_CLASS_UID)?,
+            status: set.status()?,
+            sub_operations: set.sub_operations()?,
+        }),
+        CommandField::CCancelRQ => Command::CCancelRQ(CCancelRQ {
+            message_id_being_responded_to: set.us(tags::MESSAGE_ID_BEING_RESPONDED_TO)?,
+        }),
+        CommandField::NEventReportRQ => Command::NEventReportRQ(NEventReportRQ {
+            message_id: set.us(tags::MESSAGE_ID)?,
+            affected_sop_class_uid: set.str(tags::AFFECTED_SOP_CLASS_UID)?,
+            affected_sop_instance_uid: set.str(tags::AFFECTED_SOP_INSTANCE_UID)?,
+            event_type_id: set.us(tags::EVENT_TYPE_ID)?,
+        }),
+        CommandField::NEventReportRSP => Command::NEventReportRSP(NEventReportRSP {
+            message_id_being_responded_to: set.us(tags::MESSAGE_ID_BEING_RESPONDED_TO)?,
+            affected_sop_class_uid: set.str_opt(tags::AFFECTED_SOP_CLASS_UID)?,
+            affected_sop_instance_uid: set.str_opt(tags::AFFECTED_SOP_INSTANCE_UID)?,
+            event_type_id: set.us_opt(tags::EVENT_TYPE_ID)?,
+            status: set.status()?,
+        }),
+        CommandField::NGetRQ => Command::NGetRQ(NGetRQ {
+            message_id: set.us(tags::MESSAGE_ID)?,
+            requested_sop_class_uid: set.str(tags::REQUESTED_SOP_CLASS_UID)?,
+            requested_sop_instance_uid: set.str(tags::REQUESTED_SOP_INSTANCE_UID)?,
+            attribute_identifier_list: set.at(tags::ATTRIBUTE_IDENTIFIER_LIST)?,
+        }),
+        CommandField::NGetRSP => Command::NGetRSP(NGetRSP {
+            message_id_being_responded_to: set.us(tags::MESSAGE_ID_BEING_RESPONDED_TO)?,
+            affected_sop_class_uid: set.str_opt(tags::AFFECTED_SOP_CLASS_UID)?,
+            affected_sop_instance_uid: set.str_opt(tags::AFFECTED_SOP_INSTANCE_UID)?,
+            status: set.status()?,
+        }),
+        CommandField::NSetRQ => Command::NSetRQ(NSetRQ {
+            message_id: set.us(tags::MESSAGE_ID)?,
+            requested_sop_class_uid: set.str(tags::REQUESTED_SOP_CLASS_UID)?,
+            requested_sop_instance_uid: set.str(tags::REQUESTED_SOP_INSTANCE_UID)?,
+        }),
+        CommandField::NSetRSP => Command::NSetRSP(NSetRSP {
+            message_id_being_responded_to: set.us(tags::MESSAGE_ID_BEING_RESPONDED_TO)?,
+            affected_sop_class_uid: set.str_opt(tags::AFFECTED_SOP_CLASS_UID)?,
+            affected_sop_instance_uid: set.str_opt(tags::AFFECTED_SOP_INSTANCE_UID)?,
+            status: set.status()?,
+        }),
+        CommandField::NActionRQ => Command::NActionRQ(NActionRQ {
+            message_id: set.us(tags::MESSAGE_ID)?,
+            requested_sop_class_uid: set.str(tags::REQUESTED_SOP_CLASS_UID)?,
+            requested_sop_instance_uid: set.str(tags::REQUESTED_SOP_INSTANCE_UID)?,
+            action_type_id: set.us(tags::ACTION_TYPE_ID)?,
+        }),
+        CommandField::NActionRSP => Command::NActionRSP(NActionRSP {
+            message_id_being_responded_to: set.us(tags::MESSAGE_ID_BEING_RESPONDED_TO)?,
+            affected_sop_class_uid: set.str_opt(tags::AFFECTED_SOP_CLASS_UID)?,
+            affected_sop_instance_uid: set.str_opt(tags::AFFECTED_SOP_INSTANCE_UID)?,
+            action_type_id: set.us_opt(tags::ACTION_TYPE_ID)?,
+            status: set.status()?,
+        }),
+        CommandField::NCreateRQ => Command::NCreateRQ(NCreateRQ {
+            message_id: set.us(tags::MESSAGE_ID)?,
+            affected_sop_class_uid: set.str(tags::AFFECTED_SOP_CLASS_UID)?,
+            affected_sop_instance_uid: set.str_opt(tags::AFFECTED_SOP_INSTANCE_UID)?,
+        }),
+        CommandField::NCreateRSP => Command::NCreateRSP(NCreateRSP {
+            message_id_being_responded_to: set.us(tags::MESSAGE_ID_BEING_RESPONDED_TO)?,
+            affected_sop_class_uid: set.str_opt(tags::AFFECTED_SOP_CLASS_UID)?,
+            affected_sop_instance_uid: set.str_opt(tags::AFFECTED_SOP_INSTANCE_UID)?,
+            status: set.status()?,
+        }),
+        CommandField::NDeleteRQ => Command::NDeleteRQ(NDeleteRQ {
+            message_id: set.us(tags::MESSAGE_ID)?,
+            requested_sop_class_uid: set.str(tags::REQUESTED_SOP_CLASS_UID)?,
+            requested_sop_instance_uid: set.str(tags::REQUESTED_SOP_INSTANCE_UID)?,
+        }),
+        CommandField::NDeleteRSP => Command::NDeleteRSP(NDeleteRSP {
+            message_id_being_responded_to: set.us(tags::MESSAGE_ID_BEING_RESPONDED_TO)?,
+            affected_sop_class_uid: set.str_opt(tags::AFFECTED_SOP_CLASS_UID)?,
+            affected_sop_instance_uid: set.str_opt(tags::AFFECTED_SOP_INSTANCE_UID)?,
+            status: set.status()?,
+        }),
+    };
+
+    Ok((command, has_data_set))
+}
+
+#[cfg(test)]
+mod tests {
+    use dicom_core::Tag;
+
+    use super::read_command;
+    use crate::dimse::{
+        write_command, CCancelRQ, CEchoRSP, CFindRSP, CMoveRQ, CMoveRSP, CStoreRQ, Command,
+        NActionRSP, NCreateRQ, NEventReportRQ, NGetRQ, Priority, ReadError, Status, SubOperations,
+    };
+
+    #[test]
+    fn roundtrip_commands() {
+        let commands = vec![
+            Command::CEchoRSP(CEchoRSP {
+                message_id_being_responded_to: 1,
+                affected_sop_class_uid: "1.2.840.10008.1.1".to_string(),
+                status: Status::SUCCESS,
+            }),
+            Command::CStoreRQ(CStoreRQ {
+                message_id: 2,
+                affected_sop_class_uid: "1.2.840.10008.5.1.4.1.1.2".to_string(),
+                affected_sop_instance_uid: "2.25.1".to_string(),
+                priority: Priority::High,
+                move_originator_ae_title: Some("MOVE-SCU".to_string()),
+                move_originator_message_id: Some(9),
+            }),
+            Command::CFindRSP(CFindRSP {
+                message_id_being_responded_to: 3,
+                affected_sop_class_uid: "1.2.840.10008.5.1.4.1.2.2.1".to_string(),
+                status: Status::PENDING,
+            }),
+            Command::CMoveRQ(CMoveRQ {
+                message_id: 4,
+                affected_sop_class_uid: "1.2.840.10008.5.1.4.1.2.2.2".to_string(),
+                priority: Priority::Low,
+                move_destination: "STORE-SCP".to_string(),
+            }),
+            Command::CMoveRSP(CMoveRSP {
+                message_id_being_responded_to: 4,
+                affected_sop_class_uid: "1.2.840.10008.5.1.4.1.2.2.2".to_string(),
+                status: Status::PENDING,
+                sub_operations: SubOperations {
+                    remaining: Some(3),
+                    completed: Some(1),
+                    failed: Some(0),
+                    warning: Some(0),
+                },
+            }),
+            Command::CCancelRQ(CCancelRQ {
+                message_id_being_responded_to: 4,
+            }),
+            Command::NEventReportRQ(NEventReportRQ {
+                message_id: 5,
+                affected_sop_class_uid: "1.2.840.10008.1.20.1".to_string(),
+                affected_sop_instance_uid: "1.2.840.10008.1.20.1.1".to_string(),
+                event_type_id: 1,
+            }),
+            Command::NGetRQ(NGetRQ {
+                message_id: 6,
+                requested_sop_class_uid: "1.2.840.10008.5.1.1.16".to_string(),
+                requested_sop_instance_uid: "1.2.840.10008.5.1.1.17".to_string(),
+                attribute_identifier_list: vec![Tag(0x2110, 0x0010), Tag(0x2110, 0x0020)],
+            }),
+            Command::NActionRSP(NActionRSP {
+                message_id_being_responded_to: 7,
+                affected_sop_class_uid: None,
+                affected_sop_instance_uid: None,
+                action_type_id: Some(1),
+                status: Status(0x0110),
+            }),
+            Command::NCreateRQ(NCreateRQ {
+                message_id: 8,
+                affected_sop_class_uid: "1.2.840.10008.3.1.2.3.3".to_string(),
+                affected_sop_instance_uid: None,
+            }),
+        ];
+
+        for (i, command) in commands.into_iter().enumerate() {
+            let has_data_set = i % 2 == 0;
+            let data = write_command(&command, has_data_set);
+            let (decoded, decoded_has_data_set) = read_command(&data).unwrap();
+            assert_eq!(decoded, command);
+            assert_eq!(decoded_has_data_set, has_data_set);
+        }
+    }
+
+    #[test]
+    fn reject_truncated_command() {
+        let data = write_command(
+            &Command::CCancelRQ(CCancelRQ {
+                message_id_being_responded_to: 1,
+            }),
+            false,
+        );
+        assert!(matches!(
+            read_command(&data[..data.len() - 1]),
+            Err(ReadError::UnexpectedEnd { .. })
+        ));
+    }
+
+    #[test]
+    fn reject_missing_element() {
+        #[rustfmt::skip]
+        let data: &[u8] = &[
+            // (0000,0100) US command field: 0030H (C-ECHO-RQ)
+            0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x30, 0x00,
+            // (0000,0800) US command data set type: 0101H
+            0x00, 0x00, 0x00, 0x08, 0x02, 0x00, 0x00, 0x00, 0x01, 0x01,
+        ];
+        assert!(matches!(
+            read_command(data),
+            Err(ReadError::MissingElement {
+                tag: Tag(0x0000, 0x0110),
+                ..
+            })
+        ));
+    }
+}
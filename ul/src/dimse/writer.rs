@@ -0,0 +1,404 @@
+//! DIMSE command set writer module
+//!
+//! Command sets are always encoded in
+//! _Implicit VR Little Endian_,
+//! with the Command Group Length (0000,0000) element first.
+use dicom_core::Tag;
+
+use super::{tags, Command, SubOperations, DATA_SET_PRESENT, NO_DATA_SET};
+
+/// Accumulator of command elements before encoding.
+struct CommandSetWriter {
+    elements: Vec<(Tag, Vec<u8>)>,
+}
+
+impl CommandSetWriter {
+    fn new(command_field: u16, has_data_set: bool) -> Self {
+        let mut writer = CommandSetWriter {
+            elements: Vec::with_capacity(10),
+        };
+        writer.us(tags::COMMAND_FIELD, command_field);
+        writer.us(
+            tags::COMMAND_DATA_SET_TYPE,
+            if has_data_set {
+                DATA_SET_PRESENT
+            } else {
+                NO_DATA_SET
+            },
+        );
+        writer
+    }
+
+    /// Add an unsigned short (US) element.
+    fn us(&mut self, tag: Tag, value: u16) {
+        self.elements.push((tag, value.to_le_bytes().to_vec()));
+    }
+
+    /// Add an optional unsigned short (US) element.
+    fn us_opt(&mut self, tag: Tag, value: Option<u16>) {
+        if let Some(value) = value {
+            self.us(tag, value);
+        }
+    }
+
+    /// Add a unique identifier (UI) element,
+    /// padded with a trailing null character if necessary.
+    fn ui(&mut self, tag: Tag, value: &str) {
+        let mut data = value.as_bytes().to_vec();
+        if data.len() % 2 == 1 {
+            data.push(b'\0');
+        }
+        self.elements.push((tag, data));
+    }
+
+    /// Add an optional unique identifier (UI) element.
+    fn ui_opt(&mut self, tag: Tag, value: Option<&str>) {
+        if let Some(value) = value {
+            self.ui(tag, value);
+        }
+    }
+
+    /// Add an application entity (AE) element,
+    /// padded with a trailing space if necessary.
+    fn ae(&mut self, tag: Tag, value: &str) {
+        let mut data = value.as_bytes().to_vec();
+        if data.len() % 2 == 1 {
+            data.push(b' ');
+        }
+        self.elements.push((tag, data));
+    }
+
+    /// Add an attribute tag (AT) element.
+    fn at(&mut self, tag: Tag, value: &[Tag]) {
+        let mut data = Vec::with_capacity(value.len() * 4);
+        for t in value {
+            data.extend(t.group().to_le_bytes());
+            data.extend(t.element().to_le_bytes());
+        }
+        self.elements.push((tag, data));
+    }
+
+    fn sub_operations(&mut self, sub_operations: &SubOperations) {
+        self.us_opt(
+            tags::NUMBER_OF_REMAINING_SUBOPERATIONS,
+            sub_operations.remaining,
+        );
+        self.us_opt(
+            tags::NUMBER_OF_COMPLETED_SUBOPERATIONS,
+            sub_operations.completed,
+        );
+        self.us_opt(tags::NUMBER_OF_FAILED_SUBOPERATIONS, sub_operations.failed);
+        self.us_opt(
+            tags::NUMBER_OF_WARNING_SUBOPERATIONS,
+            sub_operations.warning,
+        );
+    }
+
+    /// Encode all elements in ascending tag order,
+    /// preceded by the command group length.
+    fn finish(mut self) -> Vec<u8> {
+        self.elements.sort_by_key(|(tag, _)| *tag);
+        let group_length: usize = self.elements.iter().map(|(_, data)| 8 + data.len()).sum();
+
+        let mut out = Vec::with_capacity(12 + group_length);
+        write_element(
+            &mut out,
+            tags::COMMAND_GROUP_LENGTH,
+            &(group_length as u32).to_le_bytes(),
+        );
+        for (tag, data) in &self.elements {
+            write_element(&mut out, *tag, data);
+        }
+        out
+    }
+}
+
+fn write_element(out: &mut Vec<u8>, tag: Tag, data: &[u8]) {
+    out.extend(tag.group().to_le_bytes());
+    out.extend(tag.element().to_le_bytes());
+    out.extend((data.len() as u32).to_le_bytes());
+    out.extend(data);
+}
+
+/// Encode a DIMSE command set in _Implicit VR Little Endian_.
+///
+/// `has_data_set` declares whether a data set
+/// will follow the command in the same message,
+/// which is recorded in the Command Data Set Type (0000,0800).
+pub fn write_command(command: &Command, has_data_set: bool) -> Vec<u8> {
+    let mut w = CommandSetWriter::new(command.command_field().to_u16(), has_data_set);
+
+    match command {
+        Command::CEchoRQ(c) => {
+            w.us(tags::MESSAGE_ID, c.message_id);
+            w.ui(tags::AFFECTED_SOP_CLASS_UID, &c.affected_sop_class_uid);
+        }
+        Command::CEchoRSP(c) => {
+            w.us(
+                tags::MESSAGE_ID_BEING_RESPONDED_TO,
+                c.message_id_being_responded_to,
+            );
+            w.ui(tags::AFFECTED_SOP_CLASS_UID, &c.affected_sop_class_uid);
+            w.us(tags::STATUS, c.status.code());
+        }
+        Command::CStoreRQ(c) => {
+            w.us(tags::MESSAGE_ID, c.message_id);
+            w.ui(tags::AFFECTED_SOP_CLASS_UID, &c.affected_sop_class_uid);
+            w.ui(
+                tags::AFFECTED_SOP_INSTANCE_UID,
+                &c.affected_sop_instance_uid,
+            );
+            w.us(tags::PRIORITY, c.priority.to_u16());
+            if let Some(ae_title) = &c.move_originator_ae_title {
+                w.ae(tags::MOVE_ORIGINATOR_APPLICATION_ENTITY_TITLE, ae_title);
+            }
+            w.us_opt(
+                tags::MOVE_ORIGINATOR_MESSAGE_ID,
+                c.move_originator_message_id,
+            );
+        }
+        Command::CStoreRSP(c) => {
+            w.us(
+                tags::MESSAGE_ID_BEING_RESPONDED_TO,
+                c.message_id_being_responded_to,
+            );
+            w.ui(tags::AFFECTED_SOP_CLASS_UID, &c.affected_sop_class_uid);
+            w.ui(
+                tags::AFFECTED_SOP_INSTANCE_UID,
+                &c.affected_sop_instance_uid,
+            );
+            w.us(tags::STATUS, c.status.code());
+        }
+        Command::CFindRQ(c) => {
+            w.us(tags::MESSAGE_ID, c.message_id);
+            w.ui(tags::AFFECTED_SOP_CLASS_UID, &c.affected_sop_class_uid);
+            w.us(tags::PRIORITY, c.priority.to_u16());
+        }
+        Command::CFindRSP(c) => {
+            w.us(
+                tags::MESSAGE_ID_BEING_RESPONDED_TO,
+                c.message_id_being_responded_to,
+            );
+            w.ui(tags::AFFECTED_SOP_CLASS_UID, &c.affected_sop_class_uid);
+            w.us(tags::STATUS, c.status.code());
+        }
+        Command::CGetRQ(c) => {
+            w.us(tags::MESSAGE_ID, c.message_id);
+            w.ui(tags::AFFECTED_SOP_CLASS_UID, &c.affected_sop_class_uid);
+            w.us(tags::PRIORITY, c.priority.to_u16());
+        }
+        Command::CGetRSP(c) => {
+            w.us(
+                tags::MESSAGE_ID_BEING_RESPONDED_TO,
+                c.message_id_being_responded_to,
+            );
+            w.ui(tags::AFFECTED_SOP_CLASS_UID, &c.affected_sop_class_uid);
+            w.us(tags::STATUS, c.status.code());
+            w.sub_operations(&c.sub_operations);
+        }
+        Command::CMoveRQ(c) => {
+            w.us(tags::MESSAGE_ID, c.message_id);
+            w.ui(tags::AFFECTED_SOP_CLASS_UID, &c.affected_sop_class_uid);
+            w.us(tags::PRIORITY, c.priority.to_u16());
+            w.ae(tags::MOVE_DESTINATION, &c.move_destination);
+        }
+        Command::CMoveRSP(c) => {
+            w.us(
+                tags::MESSAGE_ID_BEING_RESPONDED_TO,
+                c.message_id_being_responded_to,
+            );
+            w.ui(tags::AFFECTED_SOP_CLASS_UID, &c.affected_sop_class_uid);
+            w.us(tags::STATUS, c.status.code());
+            w.sub_operations(&c.sub_operations);
+        }
+        Command::CCancelRQ(c) => {
+            w.us(
+                tags::MESSAGE_ID_BEING_RESPONDED_TO,
+                c.message_id_being_responded_to,
+            );
+        }
+        Command::NEventReportRQ(c) => {
+            w.us(tags::MESSAGE_ID, c.message_id);
+            w.ui(tags::AFFECTED_SOP_CLASS_UID, &c.affected_sop_class_uid);
+            w.ui(
+                tags::AFFECTED_SOP_INSTANCE_UID,
+                &c.affected_sop_instance_uid,
+            );
+            w.us(tags::EVENT_TYPE_ID, c.event_type_id);
+        }
+        Command::NEventReportRSP(c) => {
+            w.us(
+                tags::MESSAGE_ID_BEING_RESPONDED_TO,
+                c.message_id_being_responded_to,
+            );
+            w.ui_opt(
+                tags::AFFECTED_SOP_CLASS_UID,
+                c.affected_sop_class_uid.as_deref(),
+            );
+            w.ui_opt(
+                tags::AFFECTED_SOP_INSTANCE_UID,
+                c.affected_sop_instance_uid.as_deref(),
+            );
+            w.us_opt(tags::EVENT_TYPE_ID, c.event_type_id);
+            w.us(tags::STATUS, c.status.code());
+        }
+        Command::NGetRQ(c) => {
+            w.us(tags::MESSAGE_ID, c.message_id);
+            w.ui(tags::REQUESTED_SOP_CLASS_UID, &c.requested_sop_class_uid);
+            w.ui(
+                tags::REQUESTED_SOP_INSTANCE_UID,
+                &c.requested_sop_instance_uid,
+            );
+            if !c.attribute_identifier_list.is_empty() {
+                w.at(
+                    tags::ATTRIBUTE_IDENTIFIER_LIST,
+                    &c.attribute_identifier_list,
+                );
+            }
+        }
+        Command::NGetRSP(c) => {
+            w.us(
+                tags::MESSAGE_ID_BEING_RESPONDED_TO,
+                c.message_id_being_responded_to,
+            );
+            w.ui_opt(
+                tags::AFFECTED_SOP_CLASS_UID,
+                c.affected_sop_class_uid.as_deref(),
+            );
+            w.ui_opt(
+                tags::AFFECTED_SOP_INSTANCE_UID,
+                c.affected_sop_instance_uid.as_deref(),
+            );
+            w.us(tags::STATUS, c.status.code());
+        }
+        Command::NSetRQ(c) => {
+            w.us(tags::MESSAGE_ID, c.message_id);
+            w.ui(tags::REQUESTED_SOP_CLASS_UID, &c.requested_sop_class_uid);
+            w.ui(
+                tags::REQUESTED_SOP_INSTANCE_UID,
+                &c.requested_sop_instance_uid,
+            );
+        }
+        Command::NSetRSP(c) => {
+            w.us(
+                tags::MESSAGE_ID_BEING_RESPONDED_TO,
+                c.message_id_being_responded_to,
+            );
+            w.ui_opt(
+                tags::AFFECTED_SOP_CLASS_UID,
+                c.affected_sop_class_uid.as_deref(),
+            );
+            w.ui_opt(
+                tags::AFFECTED_SOP_INSTANCE_UID,
+                c.affected_sop_instance_uid.as_deref(),
+            );
+            w.us(tags::STATUS, c.status.code());
+        }
+        Command::NActionRQ(c) => {
+            w.us(tags::MESSAGE_ID, c.message_id);
+            w.ui(tags::REQUESTED_SOP_CLASS_UID, &c.requested_sop_class_uid);
+            w.ui(
+                tags::REQUESTED_SOP_INSTANCE_UID,
+                &c.requested_sop_instance_uid,
+            );
+            w.us(tags::ACTION_TYPE_ID, c.action_type_id);
+        }
+        Command::NActionRSP(c) => {
+            w.us(
+                tags::MESSAGE_ID_BEING_RESPONDED_TO,
+                c.message_id_being_responded_to,
+            );
+            w.ui_opt(
+                tags::AFFECTED_SOP_CLASS_UID,
+                c.affected_sop_class_uid.as_deref(),
+            );
+            w.ui_opt(
+                tags::AFFECTED_SOP_INSTANCE_UID,
+                c.affected_sop_instance_uid.as_deref(),
+            );
+            w.us_opt(tags::ACTION_TYPE_ID, c.action_type_id);
+            w.us(tags::STATUS, c.status.code());
+        }
+        Command::NCreateRQ(c) => {
+            w.us(tags::MESSAGE_ID, c.message_id);
+            w.ui(tags::AFFECTED_SOP_CLASS_UID, &c.affected_sop_class_uid);
+            w.ui_opt(
+                tags::AFFECTED_SOP_INSTANCE_UID,
+                c.affected_sop_instance_uid.as_deref(),
+            );
+        }
+        Command::NCreateRSP(c) => {
+            w.us(
+                tags::MESSAGE_ID_BEING_RESPONDED_TO,
+                c.message_id_being_responded_to,
+            );
+            w.ui_opt(
+                tags::AFFECTED_SOP_CLASS_UID,
+                c.affected_sop_class_uid.as_deref(),
+            );
+            w.ui_opt(
+                tags::AFFECTED_SOP_INSTANCE_UID,
+                c.affected_sop_instance_uid.as_deref(),
+            );
+            w.us(tags::STATUS, c.status.code());
+        }
+        Command::NDeleteRQ(c) => {
+            w.us(tags::MESSAGE_ID, c.message_id);
+            w.ui(tags::REQUESTED_SOP_CLASS_UID, &c.requested_sop_class_uid);
+            w.ui(
+                tags::REQUESTED_SOP_INSTANCE_UID,
+                &c.requested_sop_instance_uid,
+            );
+        }
+        Command::NDeleteRSP(c) => {
+            w.us(
+                tags::MESSAGE_ID_BEING_RESPONDED_TO,
+                c.message_id_being_responded_to,
+            );
+            w.ui_opt(
+                tags::AFFECTED_SOP_CLASS_UID,
+                c.affected_sop_class_uid.as_deref(),
+            );
+            w.ui_opt(
+                tags::AFFECTED_SOP_INSTANCE_UID,
+                c.affected_sop_instance_uid.as_deref(),
+            );
+            w.us(tags::STATUS, c.status.code());
+        }
+    }
+
+    w.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_command;
+    use crate::dimse::{CEchoRQ, Command};
+
+    #[test]
+    fn write_c_echo_rq() {
+        let command = Command::CEchoRQ(CEchoRQ {
+            message_id: 1,
+            affected_sop_class_uid: "1.2.840.10008.1.1".to_string(),
+        });
+
+        let data = write_command(&command, false);
+
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            // (0000,0000) UL command group length: 56
+            0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00,
+            // (0000,0002) UI affected SOP class UID: "1.2.840.10008.1.1\0"
+            0x00, 0x00, 0x02, 0x00, 0x12, 0x00, 0x00, 0x00,
+            b'1', b'.', b'2', b'.', b'8', b'4', b'0', b'.', b'1',
+            b'0', b'0', b'0', b'8', b'.', b'1', b'.', b'1', 0x00,
+            // (0000,0100) US command field: 0030H
+            0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x30, 0x00,
+            // (0000,0110) US message ID: 1
+            0x00, 0x00, 0x10, 0x01, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00,
+            // (0000,0800) US command data set type: 0101H
+            0x00, 0x00, 0x00, 0x08, 0x02, 0x00, 0x00, 0x00, 0x01, 0x01,
+        ];
+        assert_eq!(data, expected);
+    }
+}
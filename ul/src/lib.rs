@@ -16,14 +16,34 @@
 //!   comprises abstractions for establishing and negotiating associations
 //!   between application entities,
 //!   via the upper layer protocol by TCP.
+//! - The [`dimse`] module
+//!   provides typed DICOM message service element commands,
+//!   which can be sent and received through an established association.
+//! - The [`scp`] module
+//!   provides a server framework for service class providers,
+//!   which dispatches incoming requests to service handlers.
+//! - The `mock` module (requires the `mock` feature)
+//!   provides scripted service class providers and users
+//!   for testing applications without a real DICOM node.
 //!
 //! ## Features
 //! * `async`: Enables a fully async implementation of the upper layer protocol.
 //!   See [`ClientAssociationOptions`] and [`ServerAssociationOptions`] for details
+//! * `tls`: Enables associations over TLS secure transport connections,
+//!   including mutual authentication with client certificates,
+//!   via [rustls](https://crates.io/crates/rustls).
+//! * `async-tls`: Enables TLS secure transport for the async implementation.
+//! * `mock`: Enables the `mock` module with scripted DICOM nodes for testing.
+//! * `serde`: Enables loading a [peer registry](association::peers::PeerRegistry)
+//!   from TOML or JSON files.
 
 pub mod address;
 pub mod association;
+pub mod dimse;
+#[cfg(feature = "mock")]
+pub mod mock;
 pub mod pdu;
+pub mod scp;
 
 /// The current implementation class UID generically referring to DICOM-rs.
 ///
@@ -47,3 +67,8 @@ pub use association::server::{ServerAssociation, ServerAssociationOptions};
 pub use pdu::read_pdu;
 pub use pdu::write_pdu;
 pub use pdu::Pdu;
+
+/// The TLS library used for secure transport,
+/// so that its configuration types can be named without a direct dependency.
+#[cfg(feature = "tls")]
+pub use rustls;
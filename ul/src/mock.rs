@@ -0,0 +1,759 @@
+//! Scripted DICOM nodes for testing
+//!
+//! This module provides an in-process mock service class provider
+//! ([`MockScp`]) and service class user ([`MockScu`]),
+//! so that applications can be tested against a DICOM peer
+//! which behaves in a predictable way,
+//! without a real PACS.
+//! It is only available with the `mock` feature,
+//! which is meant to be enabled in development dependencies.
+//!
+//! The mock SCP accepts associations with the configured presentation contexts
+//! and answers each DIMSE request with the next [`MockResponse`] in its script.
+//! Responses can carry any status and data set,
+//! be delayed or withheld,
+//! or be replaced by an abort or a release of the association.
+//! Everything that happens is recorded as [`MockEvent`]s,
+//! which can be inspected after the test.
+//!
+//! ```no_run
+//! # use dicom_ul::mock::{MockEvent, MockResponse, MockScp};
+//! # use dicom_ul::dimse::Status;
+//! # use std::time::Duration;
+//! # fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let scp = MockScp::new()
+//!     .with_abstract_syntax("1.2.840.10008.5.1.4.1.1.2")
+//!     // the first C-STORE request fails,
+//!     .respond(MockResponse::status(Status::PROCESSING_FAILURE))
+//!     // the second one takes its time,
+//!     .respond(MockResponse::success().delay(Duration::from_millis(500)))
+//!     // and the third one is aborted
+//!     .respond(MockResponse::abort())
+//!     .spawn()?;
+//!
+//! // ... run the SCU under test against `scp.addr()` ...
+//!
+//! let events = scp.shutdown();
+//! assert!(events.contains(&MockEvent::Aborted));
+//! # Ok(())
+//! # }
+//! ```
+use std::{
+    collections::VecDeque,
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use crate::{
+    association::{
+        client::{self, ClientAssociation, ClientAssociationOptions},
+        server::{self, AcceptAny, ServerAssociation, ServerAssociationOptions},
+    },
+    dimse::{Command, Message, Status},
+    scp::{response_command, server::is_timeout, VERIFICATION_SOP_CLASS},
+};
+
+/// How often the mock SCP checks for a shutdown request.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The default AE title of the mock SCP.
+pub const MOCK_SCP_AE_TITLE: &str = "MOCK-SCP";
+
+/// The default AE title of the mock SCU.
+pub const MOCK_SCU_AE_TITLE: &str = "MOCK-SCU";
+
+#[derive(Debug, Snafu)]
+#[non_exhaustive]
+pub enum Error {
+    /// failed to bind the mock SCP to a local port
+    Bind { source: std::io::Error },
+
+    /// could not establish association
+    Establish { source: client::Error },
+
+    /// no presentation context accepted for abstract syntax {abstract_syntax}
+    NoPresentationContext { abstract_syntax: String },
+
+    /// failed to send request
+    SendRequest { source: client::Error },
+
+    /// failed to receive response
+    ReceiveResponse { source: client::Error },
+
+    /// failed to terminate association
+    Terminate { source: client::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Something which happened in a mock node,
+/// in the order of occurrence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MockEvent {
+    /// An association was established with the given peer
+    Established {
+        /// the AE title of the association requestor
+        calling_ae_title: String,
+    },
+    /// An association could not be established
+    NotEstablished,
+    /// A DIMSE message was received
+    Received(Message),
+    /// The association was released, by either node
+    Released,
+    /// The association was aborted, by either node
+    Aborted,
+    /// The association ended because of an error,
+    /// such as the peer closing the connection
+    Failed,
+}
+
+/// The behavior of the mock SCP in response to a DIMSE request.
+///
+/// The response command is built from the request,
+/// so that it refers to the same message ID and SOP class,
+/// but otherwise has no operation-specific attributes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockResponse {
+    delay: Duration,
+    action: ResponseAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ResponseAction {
+    Respond {
+        pending: Vec<Vec<u8>>,
+        status: Status,
+        data: Option<Vec<u8>>,
+    },
+    NoResponse,
+    Abort,
+    Release,
+}
+
+impl MockResponse {
+    /// Respond with the given status.
+    pub fn status(status: Status) -> Self {
+        MockResponse {
+            delay: Duration::ZERO,
+            action: ResponseAction::Respond {
+                pending: Vec::new(),
+                status,
+                data: None,
+            },
+        }
+    }
+
+    /// Respond with the status _success_.
+    ///
+    /// This is what the mock SCP does
+    /// when no other default response is configured.
+    pub fn success() -> Self {
+        Self::status(Status::SUCCESS)
+    }
+
+    /// Do not respond to the request,
+    /// so that the requestor eventually times out.
+    pub fn no_response() -> Self {
+        MockResponse {
+            delay: Duration::ZERO,
+            action: ResponseAction::NoResponse,
+        }
+    }
+
+    /// Abort the association instead of responding.
+    pub fn abort() -> Self {
+        MockResponse {
+            delay: Duration::ZERO,
+            action: ResponseAction::Abort,
+        }
+    }
+
+    /// Release the association instead of responding.
+    pub fn release() -> Self {
+        MockResponse {
+            delay: Duration::ZERO,
+            action: ResponseAction::Release,
+        }
+    }
+
+    /// Wait for the given amount of time before responding.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Send a pending response with the given data set
+    /// before the final response,
+    /// as in the matches of a C-FIND operation.
+    ///
+    /// Has no effect on responses which do not respond.
+    pub fn with_pending(mut self, data: impl Into<Vec<u8>>) -> Self {
+        if let ResponseAction::Respond { pending, .. } = &mut self.action {
+            pending.push(data.into());
+        }
+        self
+    }
+
+    /// Attach the given data set to the final response.
+    ///
+    /// Has no effect on responses which do not respond.
+    pub fn with_data(mut self, data: impl Into<Vec<u8>>) -> Self {
+        if let ResponseAction::Respond { data: d, .. } = &mut self.action {
+            *d = Some(data.into());
+        }
+        self
+    }
+}
+
+/// A mock service class provider which follows a script.
+///
+/// Associations are served one at a time
+/// in a background thread, started with [`spawn`](Self::spawn).
+/// Each DIMSE request received,
+/// across all associations,
+/// is answered with the next response in the script,
+/// or with the default response once the script is exhausted.
+/// Messages which are not requests, such as C-CANCEL,
+/// are only recorded.
+#[derive(Debug)]
+pub struct MockScp {
+    options: ServerAssociationOptions<'static, AcceptAny>,
+    script: VecDeque<MockResponse>,
+    default_response: MockResponse,
+}
+
+impl Default for MockScp {
+    fn default() -> Self {
+        MockScp {
+            options: ServerAssociationOptions::new()
+                .ae_title(MOCK_SCP_AE_TITLE)
+                .with_abstract_syntax(VERIFICATION_SOP_CLASS),
+            script: VecDeque::new(),
+            default_response: MockResponse::success(),
+        }
+    }
+}
+
+impl MockScp {
+    /// Create a mock SCP with the AE title [`MOCK_SCP_AE_TITLE`],
+    /// which accepts the verification SOP class
+    /// in addition to the abstract syntaxes added later.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the options for negotiating associations.
+    ///
+    /// The verification SOP class is not added to these options.
+    pub fn with_options(mut self, options: ServerAssociationOptions<'static, AcceptAny>) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Define the AE title of the mock SCP.
+    pub fn ae_title(mut self, ae_title: impl Into<String>) -> Self {
+        self.options = self.options.ae_title(ae_title.into());
+        self
+    }
+
+    /// Accept presentation contexts with the given abstract syntax.
+    pub fn with_abstract_syntax(mut self, abstract_syntax_uid: impl Into<String>) -> Self {
+        self.options = self
+            .options
+            .with_abstract_syntax(abstract_syntax_uid.into());
+        self
+    }
+
+    /// Accept the given transfer syntax,
+    /// in addition to the uncompressed transfer syntaxes
+    /// accepted by default.
+    pub fn with_transfer_syntax(mut self, transfer_syntax_uid: impl Into<String>) -> Self {
+        self.options = self
+            .options
+            .with_transfer_syntax(transfer_syntax_uid.into());
+        self
+    }
+
+    /// Append a response to the script.
+    pub fn respond(mut self, response: MockResponse) -> Self {
+        self.script.push_back(response);
+        self
+    }
+
+    /// Define the response to requests received after the script is exhausted.
+    ///
+    /// The default is [`MockResponse::success`].
+    pub fn default_response(mut self, response: MockResponse) -> Self {
+        self.default_response = response;
+        self
+    }
+
+    /// Start serving associations in a background thread,
+    /// listening on an ephemeral port of the loopback interface.
+    pub fn spawn(self) -> Result<MockScpHandle> {
+        let listener = TcpListener::bind("localhost:0").context(BindSnafu)?;
+        let addr = listener.local_addr().context(BindSnafu)?;
+        listener.set_nonblocking(true).context(BindSnafu)?;
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let events = events.clone();
+            let shutdown = shutdown.clone();
+            std::thread::spawn(move || self.serve(listener, &events, &shutdown))
+        };
+
+        Ok(MockScpHandle {
+            addr,
+            events,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    fn serve(
+        mut self,
+        listener: TcpListener,
+        events: &Mutex<Vec<MockEvent>>,
+        shutdown: &AtomicBool,
+    ) {
+        let record = |event| events.lock().unwrap().push(event);
+
+        while !shutdown.load(Ordering::SeqCst) {
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(_) => {
+                    std::thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+            };
+            let _ = stream.set_nonblocking(false);
+            match self.options.establish(stream) {
+                Ok(association) => {
+                    record(MockEvent::Established {
+                        calling_ae_title: association.client_ae_title().to_string(),
+                    });
+                    record(self.serve_association(association, &record, shutdown));
+                }
+                Err(_) => record(MockEvent::NotEstablished),
+            }
+        }
+    }
+
+    /// Serve an association until it ends,
+    /// returning the event describing how it ended.
+    fn serve_association(
+        &mut self,
+        mut association: ServerAssociation<TcpStream>,
+        record: &impl Fn(MockEvent),
+        shutdown: &AtomicBool,
+    ) -> MockEvent {
+        let _ = association
+            .inner_stream()
+            .set_read_timeout(Some(POLL_INTERVAL));
+        loop {
+            let message = match association.receive_message() {
+                Ok(Some(message)) => message,
+                Ok(None) => return MockEvent::Released,
+                Err(server::Error::Aborted { .. }) => return MockEvent::Aborted,
+                Err(e) if is_timeout(&e) => {
+                    if shutdown.load(Ordering::SeqCst) {
+                        let _ = association.abort();
+                        return MockEvent::Aborted;
+                    }
+                    continue;
+                }
+                Err(_) => return MockEvent::Failed,
+            };
+            record(MockEvent::Received(message.clone()));
+
+            let final_response = match response_command(&message.command, Status::SUCCESS) {
+                Some(command) => command,
+                None => continue,
+            };
+            let response = self
+                .script
+                .pop_front()
+                .unwrap_or_else(|| self.default_response.clone());
+            std::thread::sleep(response.delay);
+            match response.action {
+                ResponseAction::Respond {
+                    pending,
+                    status,
+                    data,
+                } => {
+                    let pc_id = message.presentation_context_id;
+                    for data in pending {
+                        let command = response_command(&message.command, Status::PENDING)
+                            .unwrap_or_else(|| final_response.clone());
+                        if association
+                            .send_message(pc_id, &command, Some(&data))
+                            .is_err()
+                        {
+                            return MockEvent::Failed;
+                        }
+                    }
+                    let command =
+                        response_command(&message.command, status).unwrap_or(final_response);
+                    if association
+                        .send_message(pc_id, &command, data.as_deref())
+                        .is_err()
+                    {
+                        return MockEvent::Failed;
+                    }
+                }
+                ResponseAction::NoResponse => {}
+                ResponseAction::Abort => {
+                    let _ = association.abort();
+                    return MockEvent::Aborted;
+                }
+                ResponseAction::Release => {
+                    return match association.release() {
+                        Ok(()) => MockEvent::Released,
+                        Err(_) => MockEvent::Failed,
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// A handle to a running [`MockScp`].
+///
+/// The mock SCP is shut down when the handle is dropped.
+#[derive(Debug)]
+pub struct MockScpHandle {
+    addr: SocketAddr,
+    events: Arc<Mutex<Vec<MockEvent>>>,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockScpHandle {
+    /// The socket address on which the mock SCP is listening.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Obtain a copy of the events recorded so far.
+    pub fn events(&self) -> Vec<MockEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Obtain a copy of the DIMSE messages received so far.
+    pub fn received(&self) -> Vec<Message> {
+        received(&self.events.lock().unwrap())
+    }
+
+    /// Stop the mock SCP and return all events recorded.
+    ///
+    /// An association still in progress is aborted.
+    pub fn shutdown(mut self) -> Vec<MockEvent> {
+        self.shutdown.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+impl Drop for MockScpHandle {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Collect the messages in the given events.
+fn received(events: &[MockEvent]) -> Vec<Message> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            MockEvent::Received(message) => Some(message.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+enum ScuStep {
+    Send {
+        abstract_syntax: String,
+        command: Command,
+        data: Option<Vec<u8>>,
+    },
+    Delay(Duration),
+    Abort,
+}
+
+/// A mock service class user which follows a script.
+///
+/// When [run](Self::run),
+/// the mock SCU establishes an association,
+/// sends each request in the script,
+/// waiting for all of its responses,
+/// and then releases the association
+/// unless the script ends with an abort.
+#[derive(Debug, Clone)]
+pub struct MockScu {
+    options: ClientAssociationOptions<'static>,
+    script: Vec<ScuStep>,
+}
+
+impl Default for MockScu {
+    fn default() -> Self {
+        MockScu {
+            options: ClientAssociationOptions::new().calling_ae_title(MOCK_SCU_AE_TITLE),
+            script: Vec::new(),
+        }
+    }
+}
+
+impl MockScu {
+    /// Create a mock SCU with the AE title [`MOCK_SCU_AE_TITLE`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the options for negotiating the association.
+    pub fn with_options(mut self, options: ClientAssociationOptions<'static>) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Define the AE title of the mock SCU.
+    pub fn calling_ae_title(mut self, ae_title: impl Into<String>) -> Self {
+        self.options = self.options.calling_ae_title(ae_title.into());
+        self
+    }
+
+    /// Define the AE title of the peer.
+    pub fn called_ae_title(mut self, ae_title: impl Into<String>) -> Self {
+        self.options = self.options.called_ae_title(ae_title.into());
+        self
+    }
+
+    /// Propose a presentation context
+    /// with the given abstract syntax and transfer syntaxes.
+    pub fn with_presentation_context(
+        mut self,
+        abstract_syntax_uid: impl Into<String>,
+        transfer_syntax_uids: Vec<String>,
+    ) -> Self {
+        self.options = self
+            .options
+            .with_presentation_context(abstract_syntax_uid.into(), transfer_syntax_uids);
+        self
+    }
+
+    /// Append a request to the script,
+    /// to be sent in the first accepted presentation context
+    /// of the given abstract syntax.
+    pub fn send(
+        mut self,
+        abstract_syntax_uid: impl Into<String>,
+        command: Command,
+        data: Option<Vec<u8>>,
+    ) -> Self {
+        self.script.push(ScuStep::Send {
+            abstract_syntax: abstract_syntax_uid.into(),
+            command,
+            data,
+        });
+        self
+    }
+
+    /// Append a pause to the script.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.script.push(ScuStep::Delay(delay));
+        self
+    }
+
+    /// Append an abort of the association to the script,
+    /// ending it.
+    pub fn abort(mut self) -> Self {
+        self.script.push(ScuStep::Abort);
+        self
+    }
+
+    /// Run the script against the node at the given address,
+    /// returning the responses received
+    /// and how the association ended.
+    ///
+    /// Fails if the association cannot be established
+    /// or ends unexpectedly.
+    pub fn run<A: ToSocketAddrs>(self, address: A) -> Result<Vec<MockEvent>> {
+        let mut association = self.options.establish(address).context(EstablishSnafu)?;
+        let mut events = Vec::new();
+
+        for step in self.script {
+            match step {
+                ScuStep::Send {
+                    abstract_syntax,
+                    command,
+                    data,
+                } => {
+                    let pc_id = presentation_context_id(&association, &abstract_syntax)?;
+                    association
+                        .send_message(pc_id, &command, data.as_deref())
+                        .context(SendRequestSnafu)?;
+                    loop {
+                        let response = association
+                            .receive_message()
+                            .context(ReceiveResponseSnafu)?;
+                        let pending = response.command.status().is_some_and(Status::is_pending);
+                        events.push(MockEvent::Received(response));
+                        if !pending {
+                            break;
+                        }
+                    }
+                }
+                ScuStep::Delay(delay) => std::thread::sleep(delay),
+                ScuStep::Abort => {
+                    association.abort().context(TerminateSnafu)?;
+                    events.push(MockEvent::Aborted);
+                    return Ok(events);
+                }
+            }
+        }
+        association.release().context(TerminateSnafu)?;
+        events.push(MockEvent::Released);
+        Ok(events)
+    }
+}
+
+/// Find the first accepted presentation context of an abstract syntax.
+fn presentation_context_id(
+    association: &ClientAssociation<TcpStream>,
+    abstract_syntax: &str,
+) -> Result<u8> {
+    association
+        .presentation_contexts()
+        .iter()
+        .map(|pc| pc.id)
+        .find(|&id| association.abstract_syntax(id) == Some(abstract_syntax))
+        .context(NoPresentationContextSnafu { abstract_syntax })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dimse::{CEchoRQ, CFindRQ, Priority};
+
+    static IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+    static STUDY_ROOT_FIND: &str = "1.2.840.10008.5.1.4.1.2.2.1";
+
+    fn echo_rq(message_id: u16) -> Command {
+        Command::CEchoRQ(CEchoRQ {
+            message_id,
+            affected_sop_class_uid: VERIFICATION_SOP_CLASS.to_string(),
+        })
+    }
+
+    fn statuses(events: &[MockEvent]) -> Vec<Option<Status>> {
+        received(events)
+            .iter()
+            .map(|message| message.command.status())
+            .collect()
+    }
+
+    #[test]
+    fn mock_scp_follows_script() {
+        let scp = MockScp::new()
+            .with_abstract_syntax(STUDY_ROOT_FIND)
+            .respond(MockResponse::status(Status::PROCESSING_FAILURE))
+            .respond(
+                MockResponse::success()
+                    .with_pending(vec![1, 2])
+                    .with_pending(vec![3, 4]),
+            )
+            .spawn()
+            .unwrap();
+
+        let scu_events = MockScu::new()
+            .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE.to_string()])
+            .with_presentation_context(STUDY_ROOT_FIND, vec![IMPLICIT_VR_LE.to_string()])
+            .send(VERIFICATION_SOP_CLASS, echo_rq(1), None)
+            .send(
+                STUDY_ROOT_FIND,
+                Command::CFindRQ(CFindRQ {
+                    message_id: 2,
+                    affected_sop_class_uid: STUDY_ROOT_FIND.to_string(),
+                    priority: Priority::Medium,
+                }),
+                Some(vec![0; 8]),
+            )
+            // script exhausted, default response
+            .send(VERIFICATION_SOP_CLASS, echo_rq(3), None)
+            .run(scp.addr())
+            .unwrap();
+
+        assert_eq!(
+            statuses(&scu_events),
+            vec![
+                Some(Status::PROCESSING_FAILURE),
+                Some(Status::PENDING),
+                Some(Status::PENDING),
+                Some(Status::SUCCESS),
+                Some(Status::SUCCESS),
+            ]
+        );
+        let responses = received(&scu_events);
+        assert_eq!(responses[1].data.as_deref(), Some(&[1, 2][..]));
+        assert_eq!(responses[2].data.as_deref(), Some(&[3, 4][..]));
+        assert_eq!(scu_events.last(), Some(&MockEvent::Released));
+
+        let scp_events = scp.shutdown();
+        assert_eq!(
+            scp_events.first(),
+            Some(&MockEvent::Established {
+                calling_ae_title: MOCK_SCU_AE_TITLE.to_string()
+            })
+        );
+        let requests: Vec<_> = received(&scp_events)
+            .into_iter()
+            .map(|message| message.command.message_id())
+            .collect();
+        assert_eq!(requests, vec![Some(1), Some(2), Some(3)]);
+        assert_eq!(scp_events.last(), Some(&MockEvent::Released));
+    }
+
+    #[test]
+    fn mock_scp_aborts() {
+        let scp = MockScp::new()
+            .respond(MockResponse::abort().delay(Duration::from_millis(20)))
+            .spawn()
+            .unwrap();
+
+        let scu = MockScu::new()
+            .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE.to_string()])
+            .send(VERIFICATION_SOP_CLASS, echo_rq(1), None);
+        let err = scu.clone().run(scp.addr()).unwrap_err();
+        assert!(matches!(err, Error::ReceiveResponse { .. }), "{:?}", err);
+
+        // the next association gets the default response
+        let events = scu.run(scp.addr()).unwrap();
+        assert_eq!(statuses(&events), vec![Some(Status::SUCCESS)]);
+
+        let events = scp.shutdown();
+        assert!(matches!(
+            events.as_slice(),
+            [
+                MockEvent::Established { .. },
+                MockEvent::Received(_),
+                MockEvent::Aborted,
+                MockEvent::Established { .. },
+                MockEvent::Received(_),
+                MockEvent::Released,
+            ]
+        ));
+    }
+}
@@ -71,7 +71,7 @@ pub enum ReadError {
         backtrace: Backtrace,
     },
 
-    #[snafu(display("No PDU available"))]
+    #[snafu(display("No PDU available"), visibility(pub(crate)))]
     NoPduAvailable { backtrace: Backtrace },
 
     #[snafu(display("Could not read PDU"), visibility(pub(crate)))]
@@ -463,6 +463,71 @@ pub enum UserVariableItem {
     ImplementationVersionName(String),
     SopClassExtendedNegotiationSubItem(String, Vec<u8>),
     UserIdentityItem(UserIdentity),
+    /// User Identity server response (PS3.7 D.3.3.7.2),
+    /// sent by the association acceptor
+    /// if a positive response to the user identity was requested
+    UserIdentityServerResponseItem(Vec<u8>),
+    RoleSelectionSubItem(RoleSelection),
+    AsyncOperationsWindowSubItem(AsyncOperationsWindow),
+    SopClassCommonExtendedNegotiationSubItem(SopClassCommonExtendedNegotiation),
+}
+
+/// SCP/SCU Role Selection sub-item (PS3.7 D.3.3.4).
+///
+/// In an association request,
+/// the flags indicate the roles which the association requestor supports
+/// for the given SOP class.
+/// In an association acknowledgement,
+/// they indicate whether the acceptor accepted each of the proposed roles.
+#[derive(Clone, Eq, PartialEq, PartialOrd, Hash, Debug)]
+pub struct RoleSelection {
+    /// The SOP class or meta SOP class UID
+    pub sop_class_uid: String,
+    /// Whether the association requestor takes the SCU role
+    pub scu_role: bool,
+    /// Whether the association requestor takes the SCP role
+    pub scp_role: bool,
+}
+
+/// SOP Class Common Extended Negotiation sub-item (PS3.7 D.3.3.6).
+///
+/// Sent by the association requestor only,
+/// it describes a SOP class which the association acceptor may not know of,
+/// so that it can be accepted in the terms of a more general SOP class.
+#[derive(Clone, Eq, PartialEq, PartialOrd, Hash, Debug)]
+pub struct SopClassCommonExtendedNegotiation {
+    /// The SOP class UID
+    pub sop_class_uid: String,
+    /// The UID of the service class to which the SOP class belongs
+    pub service_class_uid: String,
+    /// The UIDs of the general SOP classes which the SOP class specializes
+    pub related_general_sop_class_uids: Vec<String>,
+}
+
+/// Asynchronous Operations Window sub-item (PS3.7 D.3.3.3).
+///
+/// Both numbers are seen from the perspective of the association requestor.
+/// A value of 0 means that the number of outstanding operations is unlimited.
+/// When this sub-item is not negotiated,
+/// only one operation may be outstanding in each direction,
+/// as given by the [`Default`] implementation.
+#[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Hash, Debug)]
+pub struct AsyncOperationsWindow {
+    /// The maximum number of outstanding operations
+    /// which the association requestor may invoke
+    pub max_operations_invoked: u16,
+    /// The maximum number of outstanding operations
+    /// which the association requestor may perform
+    pub max_operations_performed: u16,
+}
+
+impl Default for AsyncOperationsWindow {
+    fn default() -> Self {
+        AsyncOperationsWindow {
+            max_operations_invoked: 1,
+            max_operations_performed: 1,
+        }
+    }
 }
 
 #[derive(Clone, Eq, PartialEq, PartialOrd, Hash, Debug)]
@@ -760,6 +760,72 @@ fn read_pdu_variable(mut buf: impl Buf, codec: &dyn TextCodec) -> Result<Option<
                             implementation_class_uid,
                         ));
                     }
+                    0x53 => {
+                        // Asynchronous Operations Window Sub-Item Structure
+
+                        // 5-6 - Maximum-number-operations-invoked - This field shall contain
+                        // the Maximum-number-operations-invoked as defined for the Association-
+                        // requester in Section D.3.3.3. It shall be encoded as an unsigned
+                        // binary number.
+                        // 7-8 - Maximum-number-operations-performed - This field shall contain
+                        // the Maximum-number-operations-performed as defined for the
+                        // Association-requester in Section D.3.3.3. It shall be encoded as an
+                        // unsigned binary number.
+                        if bytes.remaining() < 4 {
+                            return Ok(None);
+                        }
+                        let max_operations_invoked = bytes.get_u16();
+                        let max_operations_performed = bytes.get_u16();
+                        user_variables.push(UserVariableItem::AsyncOperationsWindowSubItem(
+                            AsyncOperationsWindow {
+                                max_operations_invoked,
+                                max_operations_performed,
+                            },
+                        ));
+                    }
+                    0x54 => {
+                        // SCP/SCU Role Selection Sub-Item Structure
+
+                        // 5-6 - UID-length - The UID-length shall be the number of bytes from the
+                        // first byte of the following field to the last byte of the SOP-class-uid
+                        // field. It shall be encoded as an unsigned binary number.
+                        if bytes.remaining() < 2 {
+                            return Ok(None);
+                        }
+                        let uid_length = bytes.get_u16();
+
+                        // 7 - xxx - SOP-class-uid - This variable field shall contain the
+                        // SOP Class or Meta SOP Class UID which may be used to define the
+                        // roles in the Presentation Contexts.
+                        if bytes.remaining() < uid_length as usize {
+                            return Ok(None);
+                        }
+                        let sop_class_uid = codec
+                            .decode(bytes.copy_to_bytes(uid_length as usize).as_ref())
+                            .context(DecodeTextSnafu {
+                                field: "SOP-class-uid",
+                            })?
+                            .trim()
+                            .to_string();
+
+                        // xxx - SCU-role - In a request, 0 means non-support of the SCU role
+                        // and 1 means support of the SCU role. In a response, 0 means
+                        // rejection and 1 means acceptance of the proposed SCU role.
+                        // xxx - SCP-role - The same, for the SCP role.
+                        if bytes.remaining() < 2 {
+                            return Ok(None);
+                        }
+                        let scu_role = bytes.get_u8();
+                        let scp_role = bytes.get_u8();
+
+                        user_variables.push(UserVariableItem::RoleSelectionSubItem(
+                            RoleSelection {
+                                sop_class_uid,
+                                scu_role: scu_role == 1,
+                                scp_role: scp_role == 1,
+                            },
+                        ));
+                    }
                     0x55 => {
                         // Implementation Version Name Structure
 
@@ -823,6 +889,93 @@ fn read_pdu_variable(mut buf: impl Buf, codec: &dyn TextCodec) -> Result<Option<
                             data.to_vec(),
                         ));
                     }
+                    0x57 => {
+                        // SOP Class Common Extended Negotiation Sub-Item Structure
+
+                        // 5-6 - SOP-class-uid-length
+                        if bytes.remaining() < 2 {
+                            return Ok(None);
+                        }
+                        let sop_class_uid_length = bytes.get_u16();
+
+                        // 7-xxx - SOP-class-uid - The SOP Class identifier
+                        // encoded as a UID as defined in PS3.5.
+                        if bytes.remaining() < sop_class_uid_length as usize {
+                            return Ok(None);
+                        }
+                        let sop_class_uid = codec
+                            .decode(bytes.copy_to_bytes(sop_class_uid_length as usize).as_ref())
+                            .context(DecodeTextSnafu {
+                                field: "SOP-class-uid",
+                            })?
+                            .trim()
+                            .to_string();
+
+                        // xxx-xxx - Service-class-uid-length
+                        if bytes.remaining() < 2 {
+                            return Ok(None);
+                        }
+                        let service_class_uid_length = bytes.get_u16();
+
+                        // xxx-xxx - Service-class-uid - The SOP Class UID of the Service Class
+                        // to which the SOP Class belongs.
+                        if bytes.remaining() < service_class_uid_length as usize {
+                            return Ok(None);
+                        }
+                        let service_class_uid = codec
+                            .decode(
+                                bytes
+                                    .copy_to_bytes(service_class_uid_length as usize)
+                                    .as_ref(),
+                            )
+                            .context(DecodeTextSnafu {
+                                field: "Service-class-uid",
+                            })?
+                            .trim()
+                            .to_string();
+
+                        // xxx-xxx - Related-general-sop-class-identification-length
+                        if bytes.remaining() < 2 {
+                            return Ok(None);
+                        }
+                        let related_length = bytes.get_u16();
+
+                        // xxx-xxx - Related-general-sop-class-identification - A sequence of
+                        // sub-items, each with a 2-byte UID length followed by the UID of a
+                        // Related General SOP Class.
+                        if bytes.remaining() < related_length as usize {
+                            return Ok(None);
+                        }
+                        let mut related = bytes.copy_to_bytes(related_length as usize);
+                        let mut related_general_sop_class_uids = Vec::new();
+                        while related.has_remaining() {
+                            if related.remaining() < 2 {
+                                return Ok(None);
+                            }
+                            let uid_length = related.get_u16();
+                            if related.remaining() < uid_length as usize {
+                                return Ok(None);
+                            }
+                            let uid = codec
+                                .decode(related.copy_to_bytes(uid_length as usize).as_ref())
+                                .context(DecodeTextSnafu {
+                                    field: "Related-general-sop-class-uid",
+                                })?
+                                .trim()
+                                .to_string();
+                            related_general_sop_class_uids.push(uid);
+                        }
+
+                        user_variables.push(
+                            UserVariableItem::SopClassCommonExtendedNegotiationSubItem(
+                                SopClassCommonExtendedNegotiation {
+                                    sop_class_uid,
+                                    service_class_uid,
+                                    related_general_sop_class_uids,
+                                },
+                            ),
+                        );
+                    }
                     0x58 => {
                         // User Identity Negotiation
 
@@ -878,6 +1031,24 @@ fn read_pdu_variable(mut buf: impl Buf, codec: &dyn TextCodec) -> Result<Option<
                             }
                         }
                     }
+                    0x59 => {
+                        // User Identity Negotiation (server response)
+
+                        // 5-6 - Server-response-length
+                        if bytes.remaining() < 2 {
+                            return Ok(None);
+                        }
+                        let server_response_length = bytes.get_u16();
+
+                        // 7-n - Server-response
+                        if bytes.remaining() < server_response_length as usize {
+                            return Ok(None);
+                        }
+                        let server_response = bytes.copy_to_bytes(server_response_length as usize);
+                        user_variables.push(UserVariableItem::UserIdentityServerResponseItem(
+                            server_response.to_vec(),
+                        ));
+                    }
                     _ => {
                         if bytes.remaining() < item_length as usize {
                             return Ok(None);
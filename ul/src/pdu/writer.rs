@@ -995,6 +995,159 @@ fn write_pdu_variable_user_variables(
                     })
                     .context(WriteChunkSnafu { name: "Sub-item" })?;
                 }
+                UserVariableItem::SopClassCommonExtendedNegotiationSubItem(negotiation) => {
+                    // 1 - Item-type - 57H
+                    writer
+                        .write_u8(0x57)
+                        .context(WriteFieldSnafu { field: "Item-type" })?;
+
+                    // 2 - Sub-item-version - This field shall be sent with a value 00H.
+                    writer.write_u8(0x00).context(WriteFieldSnafu {
+                        field: "Sub-item-version",
+                    })?;
+
+                    write_chunk_u16(writer, |writer| {
+                        // 5-6 - SOP-class-uid-length
+                        write_chunk_u16(writer, |writer| {
+                            // 7-xxx - SOP-class-uid
+                            writer
+                                .write_all(&codec.encode(&negotiation.sop_class_uid).context(
+                                    EncodeFieldSnafu {
+                                        field: "SOP-class-uid",
+                                    },
+                                )?)
+                                .context(WriteFieldSnafu {
+                                    field: "SOP-class-uid",
+                                })
+                        })
+                        .context(WriteChunkSnafu {
+                            name: "SOP-class-uid",
+                        })?;
+
+                        // xxx-xxx - Service-class-uid-length
+                        write_chunk_u16(writer, |writer| {
+                            // xxx-xxx - Service-class-uid
+                            writer
+                                .write_all(&codec.encode(&negotiation.service_class_uid).context(
+                                    EncodeFieldSnafu {
+                                        field: "Service-class-uid",
+                                    },
+                                )?)
+                                .context(WriteFieldSnafu {
+                                    field: "Service-class-uid",
+                                })
+                        })
+                        .context(WriteChunkSnafu {
+                            name: "Service-class-uid",
+                        })?;
+
+                        // xxx-xxx - Related-general-sop-class-identification-length
+                        write_chunk_u16(writer, |writer| {
+                            for uid in &negotiation.related_general_sop_class_uids {
+                                // Related-general-sop-class-uid-length
+                                write_chunk_u16(writer, |writer| {
+                                    // Related-general-sop-class-uid
+                                    writer
+                                        .write_all(&codec.encode(uid).context(
+                                            EncodeFieldSnafu {
+                                                field: "Related-general-sop-class-uid",
+                                            },
+                                        )?)
+                                        .context(WriteFieldSnafu {
+                                            field: "Related-general-sop-class-uid",
+                                        })
+                                })
+                                .context(WriteChunkSnafu {
+                                    name: "Related-general-sop-class-uid",
+                                })?;
+                            }
+                            Ok(())
+                        })
+                        .context(WriteChunkSnafu {
+                            name: "Related-general-sop-class-identification",
+                        })
+                    })
+                    .context(WriteChunkSnafu {
+                        name: "Item-length",
+                    })?;
+                }
+                UserVariableItem::AsyncOperationsWindowSubItem(window) => {
+                    // 1 - Item-type - 53H
+                    writer
+                        .write_u8(0x53)
+                        .context(WriteFieldSnafu { field: "Item-type" })?;
+
+                    // 2 - Reserved - This reserved field shall be sent with a value 00H but not
+                    // tested to this value when received.
+                    writer
+                        .write_u8(0x00)
+                        .context(WriteReservedSnafu { bytes: 1_u32 })?;
+
+                    write_chunk_u16(writer, |writer| {
+                        // 5-6 - Maximum-number-operations-invoked
+                        writer
+                            .write_u16::<BigEndian>(window.max_operations_invoked)
+                            .context(WriteFieldSnafu {
+                                field: "Maximum-number-operations-invoked",
+                            })?;
+
+                        // 7-8 - Maximum-number-operations-performed
+                        writer
+                            .write_u16::<BigEndian>(window.max_operations_performed)
+                            .context(WriteFieldSnafu {
+                                field: "Maximum-number-operations-performed",
+                            })
+                    })
+                    .context(WriteChunkSnafu {
+                        name: "Item-length",
+                    })?;
+                }
+                UserVariableItem::RoleSelectionSubItem(role_selection) => {
+                    // 1 - Item-type - 54H
+                    writer
+                        .write_u8(0x54)
+                        .context(WriteFieldSnafu { field: "Item-type" })?;
+
+                    // 2 - Reserved - This reserved field shall be sent with a value 00H but not
+                    // tested to this value when received.
+                    writer
+                        .write_u8(0x00)
+                        .context(WriteReservedSnafu { bytes: 1_u32 })?;
+
+                    write_chunk_u16(writer, |writer| {
+                        // 5-6 - UID-length
+                        write_chunk_u16(writer, |writer| {
+                            // 7-xxx - SOP-class-uid - This variable field shall contain the
+                            // SOP Class or Meta SOP Class UID which may be used to define the
+                            // roles in the Presentation Contexts.
+                            writer
+                                .write_all(&codec.encode(&role_selection.sop_class_uid).context(
+                                    EncodeFieldSnafu {
+                                        field: "SOP-class-uid",
+                                    },
+                                )?)
+                                .context(WriteFieldSnafu {
+                                    field: "SOP-class-uid",
+                                })
+                        })
+                        .context(WriteChunkSnafu {
+                            name: "SOP-class-uid",
+                        })?;
+
+                        // xxx - SCU-role
+                        writer
+                            .write_u8(role_selection.scu_role as u8)
+                            .context(WriteFieldSnafu { field: "SCU-role" })?;
+
+                        // xxx - SCP-role
+                        writer
+                            .write_u8(role_selection.scp_role as u8)
+                            .context(WriteFieldSnafu { field: "SCP-role" })
+                    })
+                    .context(WriteChunkSnafu {
+                        name: "Item-length",
+                    })?;
+                }
                 UserVariableItem::UserIdentityItem(user_identity) => {
                     // 1 - Item-type - 58H
                     writer
@@ -1059,6 +1212,35 @@ fn write_pdu_variable_user_variables(
                         name: "Item-length",
                     })?;
                 }
+                UserVariableItem::UserIdentityServerResponseItem(server_response) => {
+                    // 1 - Item-type - 59H
+                    writer
+                        .write_u8(0x59)
+                        .context(WriteFieldSnafu { field: "Item-type" })?;
+
+                    // 2 - Reserved - This reserved field shall be sent with a value 00H but not
+                    // tested to this value when received.
+                    writer
+                        .write_u8(0x00)
+                        .context(WriteReservedSnafu { bytes: 1_u32 })?;
+
+                    // 3-4 - Item-length
+                    write_chunk_u16(writer, |writer| {
+                        // 5-6 - Server-response-length
+                        write_chunk_u16(writer, |writer| {
+                            // 7-n - Server-response
+                            writer.write_all(server_response).context(WriteFieldSnafu {
+                                field: "Server-response",
+                            })
+                        })
+                        .context(WriteChunkSnafu {
+                            name: "Server-response",
+                        })
+                    })
+                    .context(WriteChunkSnafu {
+                        name: "Item-length",
+                    })?;
+                }
                 UserVariableItem::Unknown(item_type, data) => {
                     writer
                         .write_u8(*item_type)
@@ -0,0 +1,1218 @@
+//! Service class provider framework
+//!
+//! This module provides the building blocks for implementing
+//! DICOM service class providers (SCPs)
+//! without handling associations and PDUs directly.
+//!
+//! Services are implemented through handler traits,
+//! such as [`EchoHandler`], [`StoreHandler`], [`FindHandler`],
+//! [`MoveHandler`], [`GetHandler`], and [`ActionHandler`],
+//! which are registered per SOP class in a [`ServiceRegistry`].
+//! An [`ScpServer`] then accepts associations concurrently
+//! and dispatches each incoming DIMSE request to the respective handler,
+//! sending back its responses.
+//!
+//! ```no_run
+//! # use std::io::Read;
+//! # use dicom_ul::scp::{RequestContext, ScpServer, ServiceRegistry};
+//! # use dicom_ul::dimse::{CStoreRQ, Status};
+//! # use dicom_ul::ServerAssociationOptions;
+//! # fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let services = ServiceRegistry::new()
+//!     .with_echo(|_: &RequestContext<'_>| Status::SUCCESS)
+//!     .with_store(
+//!         "1.2.840.10008.5.1.4.1.1.2",
+//!         |_: &RequestContext<'_>, rq: &CStoreRQ, data: &mut dyn Read| {
+//!             match std::io::copy(data, &mut std::io::sink()) {
+//!                 Ok(len) => {
+//!                     println!("received {} ({} bytes)", rq.affected_sop_instance_uid, len);
+//!                     Status::SUCCESS
+//!                 }
+//!                 Err(_) => Status::PROCESSING_FAILURE,
+//!             }
+//!         },
+//!     );
+//! let options = ServerAssociationOptions::new().ae_title("MY-SCP");
+//!
+//! let listener = std::net::TcpListener::bind("0.0.0.0:11111")?;
+//! ScpServer::new(options, services)
+//!     .max_associations(8)
+//!     .serve(listener)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Data sets are passed to and from the handlers
+//! encoded in the transfer syntax of the presentation context
+//! through which the request was received,
+//! which is available in the [`RequestContext`].
+//! The data sets of C-STORE requests are read by the handlers
+//! as they are received,
+//! so that instances do not need to be kept in memory.
+//!
+//! C-FIND, C-MOVE and C-GET requests are answered
+//! while the operation is carried out:
+//! the handlers send matches and report progress
+//! through a [`FindOperation`], [`RetrieveOperation`] or [`GetOperation`],
+//! which also tell whether the requestor has canceled the operation.
+//! N-ACTION handlers may send event reports
+//! once the request has been answered,
+//! such as the results of storage commitment requests.
+//!
+//! Requests are handled without state kept across them,
+//! other than the [association ID](RequestContext::association_id),
+//! by which handlers can tell which requests belong together.
+//! An [`AssociationEndHandler`] is notified when each association ends.
+//!
+//! Handlers are blocking.
+//! With `serve_async`, they run in tokio's blocking tasks,
+//! so they do not hold up the other associations.
+use std::{
+    collections::HashMap,
+    fmt,
+    io::Read,
+    sync::{mpsc, Arc},
+};
+
+use crate::{
+    association::server::Error as AssociationError,
+    dimse::{
+        CEchoRQ, CEchoRSP, CFindRQ, CFindRSP, CGetRQ, CGetRSP, CMoveRQ, CMoveRSP, CStoreRQ,
+        CStoreRSP, Command, NActionRQ, NActionRSP, NCreateRSP, NDeleteRSP, NEventReportRSP,
+        NGetRSP, NSetRSP, Status, SubOperations,
+    },
+    pdu::PresentationContextResult,
+};
+
+pub mod server;
+
+pub use server::{ScpServer, ShutdownHandle};
+
+/// The Verification SOP class UID
+pub const VERIFICATION_SOP_CLASS: &str = "1.2.840.10008.1.1";
+
+/// Details about the association and presentation context
+/// through which a DIMSE request was received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RequestContext<'a> {
+    /// an identifier of the association,
+    /// unique among the associations served in the process
+    pub association_id: u64,
+    /// the application entity title of the association requestor
+    pub calling_ae_title: &'a str,
+    /// the presentation context of the request
+    pub presentation_context_id: u8,
+    /// the abstract syntax of the presentation context
+    pub abstract_syntax: &'a str,
+    /// the transfer syntax of the presentation context,
+    /// in which data sets are encoded
+    pub transfer_syntax: &'a str,
+}
+
+/// An owned copy of a [`RequestContext`],
+/// so that the association is not borrowed while the request is handled.
+#[derive(Debug, Clone)]
+pub(crate) struct OwnedRequestContext {
+    association_id: u64,
+    calling_ae_title: String,
+    presentation_context_id: u8,
+    abstract_syntax: String,
+    transfer_syntax: String,
+}
+
+impl OwnedRequestContext {
+    /// Create the context of a request
+    /// received through one of the given presentation contexts.
+    ///
+    /// Returns `None` if the presentation context was not accepted.
+    pub(crate) fn new(
+        association_id: u64,
+        calling_ae_title: &str,
+        presentation_contexts: &[PresentationContextResult],
+        abstract_syntax: Option<&str>,
+        presentation_context_id: u8,
+    ) -> Option<Self> {
+        let transfer_syntax = presentation_contexts
+            .iter()
+            .find(|pc| pc.id == presentation_context_id)?
+            .transfer_syntax
+            .clone();
+        Some(OwnedRequestContext {
+            association_id,
+            calling_ae_title: calling_ae_title.to_string(),
+            presentation_context_id,
+            abstract_syntax: abstract_syntax?.to_string(),
+            transfer_syntax,
+        })
+    }
+
+    pub(crate) fn get(&self) -> RequestContext<'_> {
+        RequestContext {
+            association_id: self.association_id,
+            calling_ae_title: &self.calling_ae_title,
+            presentation_context_id: self.presentation_context_id,
+            abstract_syntax: &self.abstract_syntax,
+            transfer_syntax: &self.transfer_syntax,
+        }
+    }
+}
+
+/// A handler of C-ECHO requests (verification service).
+///
+/// Implemented for closures taking the request context.
+pub trait EchoHandler: Send + Sync {
+    /// Handle a C-ECHO request,
+    /// returning the status of the response.
+    fn echo(&self, context: &RequestContext<'_>) -> Status;
+}
+
+impl<F> EchoHandler for F
+where
+    F: Fn(&RequestContext<'_>) -> Status + Send + Sync,
+{
+    fn echo(&self, context: &RequestContext<'_>) -> Status {
+        self(context)
+    }
+}
+
+/// A handler of C-STORE requests (storage service).
+///
+/// Implemented for closures taking the request context,
+/// the request command, and a reader of the encoded data set.
+pub trait StoreHandler: Send + Sync {
+    /// Handle a C-STORE request,
+    /// reading its data set as it is received,
+    /// and return the status of the response.
+    ///
+    /// The part of the data set which is not read is discarded.
+    /// A read error means that the data set could not be received in full.
+    fn store(
+        &self,
+        context: &RequestContext<'_>,
+        request: &CStoreRQ,
+        data: &mut dyn Read,
+    ) -> Status;
+}
+
+impl<F> StoreHandler for F
+where
+    F: Fn(&RequestContext<'_>, &CStoreRQ, &mut dyn Read) -> Status + Send + Sync,
+{
+    fn store(
+        &self,
+        context: &RequestContext<'_>,
+        request: &CStoreRQ,
+        data: &mut dyn Read,
+    ) -> Status {
+        self(context, request, data)
+    }
+}
+
+/// An ongoing C-FIND operation,
+/// through which its handler sends the matches found.
+pub trait FindOperation {
+    /// Check whether the association requestor
+    /// has asked to cancel the operation,
+    /// without waiting for a message if none was sent.
+    ///
+    /// Also returns `true` once the association can no longer be used.
+    fn is_canceled(&mut self) -> bool;
+
+    /// Send the encoded identifier of a match in a pending response.
+    fn send_match(&mut self, identifier: &[u8]) -> Result<(), AssociationError>;
+}
+
+/// A handler of C-FIND requests (query service).
+///
+/// Implemented for closures taking the request context,
+/// the request command, the encoded identifier, and the ongoing operation.
+pub trait FindHandler: Send + Sync {
+    /// Handle a C-FIND request with the given identifier,
+    /// sending each match through the operation as it is found,
+    /// and return the status of the final response.
+    ///
+    /// Cancel requests should be checked before each match is sent,
+    /// in which case the operation ends with the status _cancel_ (FE00H).
+    fn find(
+        &self,
+        context: &RequestContext<'_>,
+        request: &CFindRQ,
+        identifier: &[u8],
+        operation: &mut dyn FindOperation,
+    ) -> Status;
+}
+
+impl<F> FindHandler for F
+where
+    F: Fn(&RequestContext<'_>, &CFindRQ, &[u8], &mut dyn FindOperation) -> Status + Send + Sync,
+{
+    fn find(
+        &self,
+        context: &RequestContext<'_>,
+        request: &CFindRQ,
+        identifier: &[u8],
+        operation: &mut dyn FindOperation,
+    ) -> Status {
+        self(context, request, identifier, operation)
+    }
+}
+
+/// An accepted presentation context
+/// through which instances can be sent in C-STORE sub-operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageContext {
+    /// the presentation context ID
+    pub id: u8,
+    /// the storage SOP class
+    pub abstract_syntax: String,
+    /// the transfer syntax in which data sets are encoded
+    pub transfer_syntax: String,
+}
+
+/// The final response to a C-MOVE or C-GET request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetrieveOutcome {
+    /// the status of the response
+    pub status: Status,
+    /// the number of sub-operations completed, failed, and with warnings
+    pub sub_operations: SubOperations,
+    /// the encoded identifier of the response,
+    /// such as one with the Failed SOP Instance UID List
+    pub identifier: Option<Vec<u8>>,
+}
+
+impl RetrieveOutcome {
+    /// A response with the given status,
+    /// without sub-operations or identifier,
+    /// such as when the request is refused.
+    pub fn status(status: Status) -> Self {
+        RetrieveOutcome {
+            status,
+            sub_operations: SubOperations::default(),
+            identifier: None,
+        }
+    }
+}
+
+/// An ongoing C-MOVE or C-GET operation,
+/// through which its handler reports progress.
+pub trait RetrieveOperation {
+    /// Check whether the association requestor
+    /// has asked to cancel the operation,
+    /// without waiting for a message if none was sent.
+    ///
+    /// Also returns `true` once the association can no longer be used.
+    fn is_canceled(&mut self) -> bool;
+
+    /// Report the progress of the sub-operations in a pending response.
+    fn report(&mut self, sub_operations: SubOperations) -> Result<(), AssociationError>;
+}
+
+/// An ongoing C-GET operation,
+/// whose C-STORE sub-operations are sent
+/// through the same association as the request.
+pub trait GetOperation: RetrieveOperation {
+    /// The accepted presentation contexts
+    /// in which the association requestor took the SCP role.
+    fn storage_contexts(&self) -> Vec<StorageContext>;
+
+    /// Obtain a new message ID for a C-STORE request.
+    fn next_message_id(&mut self) -> u16;
+
+    /// Send a C-STORE request with the given data set
+    /// and wait for its response, returning its status.
+    ///
+    /// A cancel request received in the meantime
+    /// is reported by [`is_canceled`](RetrieveOperation::is_canceled).
+    fn store(
+        &mut self,
+        presentation_context_id: u8,
+        request: CStoreRQ,
+        data: &[u8],
+    ) -> Result<Status, AssociationError>;
+}
+
+/// A handler of C-MOVE requests (retrieve service to a third node).
+///
+/// Implemented for closures taking the request context,
+/// the request command, the encoded identifier, and the ongoing operation.
+pub trait MoveHandler: Send + Sync {
+    /// Handle a C-MOVE request with the given identifier,
+    /// sending the instances requested to the move destination,
+    /// and return the final response.
+    ///
+    /// Progress should be reported after each sub-operation,
+    /// and cancel requests checked before each of them.
+    fn move_instances(
+        &self,
+        context: &RequestContext<'_>,
+        request: &CMoveRQ,
+        identifier: &[u8],
+        operation: &mut dyn RetrieveOperation,
+    ) -> RetrieveOutcome;
+}
+
+impl<F> MoveHandler for F
+where
+    F: Fn(&RequestContext<'_>, &CMoveRQ, &[u8], &mut dyn RetrieveOperation) -> RetrieveOutcome
+        + Send
+        + Sync,
+{
+    fn move_instances(
+        &self,
+        context: &RequestContext<'_>,
+        request: &CMoveRQ,
+        identifier: &[u8],
+        operation: &mut dyn RetrieveOperation,
+    ) -> RetrieveOutcome {
+        self(context, request, identifier, operation)
+    }
+}
+
+/// A handler of C-GET requests (retrieve service to the requestor).
+///
+/// Implemented for closures taking the request context,
+/// the request command, the encoded identifier, and the ongoing operation.
+pub trait GetHandler: Send + Sync {
+    /// Handle a C-GET request with the given identifier,
+    /// storing the instances requested through the operation,
+    /// and return the final response.
+    ///
+    /// Progress should be reported after each sub-operation,
+    /// and cancel requests checked before each of them.
+    fn get(
+        &self,
+        context: &RequestContext<'_>,
+        request: &CGetRQ,
+        identifier: &[u8],
+        operation: &mut dyn GetOperation,
+    ) -> RetrieveOutcome;
+}
+
+impl<F> GetHandler for F
+where
+    F: Fn(&RequestContext<'_>, &CGetRQ, &[u8], &mut dyn GetOperation) -> RetrieveOutcome
+        + Send
+        + Sync,
+{
+    fn get(
+        &self,
+        context: &RequestContext<'_>,
+        request: &CGetRQ,
+        identifier: &[u8],
+        operation: &mut dyn GetOperation,
+    ) -> RetrieveOutcome {
+        self(context, request, identifier, operation)
+    }
+}
+
+/// An event to report to the association requestor
+/// in an N-EVENT-REPORT request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventReport {
+    /// the SOP class of the event
+    pub affected_sop_class_uid: String,
+    /// the SOP instance of the event
+    pub affected_sop_instance_uid: String,
+    /// the type of event
+    pub event_type_id: u16,
+    /// the encoded event information, if any
+    pub data: Option<Vec<u8>>,
+}
+
+/// The event reports to send once an N-ACTION request is answered.
+#[derive(Debug, Default)]
+pub struct EventReports {
+    queued: Vec<(EventReport, mpsc::Sender<Status>)>,
+}
+
+impl EventReports {
+    /// Send an event report to the association requestor
+    /// through the presentation context of the N-ACTION request,
+    /// after the response to the request.
+    ///
+    /// The status of the N-EVENT-REPORT response
+    /// is passed to the returned receiver once it arrives.
+    /// The receiver is disconnected instead
+    /// if the report could not be sent
+    /// or if the association ended before the response.
+    pub fn send(&mut self, report: EventReport) -> mpsc::Receiver<Status> {
+        let (response, received) = mpsc::channel();
+        self.queued.push((report, response));
+        received
+    }
+}
+
+/// A handler of N-ACTION requests.
+///
+/// Implemented for closures taking the request context,
+/// the request command, the encoded action information,
+/// and the event reports to send.
+pub trait ActionHandler: Send + Sync {
+    /// Handle an N-ACTION request with the given action information,
+    /// returning the status of the response.
+    ///
+    /// Event reports sent through `events`
+    /// follow the response to the request.
+    fn action(
+        &self,
+        context: &RequestContext<'_>,
+        request: &NActionRQ,
+        data: &[u8],
+        events: &mut EventReports,
+    ) -> Status;
+}
+
+impl<F> ActionHandler for F
+where
+    F: Fn(&RequestContext<'_>, &NActionRQ, &[u8], &mut EventReports) -> Status + Send + Sync,
+{
+    fn action(
+        &self,
+        context: &RequestContext<'_>,
+        request: &NActionRQ,
+        data: &[u8],
+        events: &mut EventReports,
+    ) -> Status {
+        self(context, request, data, events)
+    }
+}
+
+/// Details about an association which has ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AssociationContext<'a> {
+    /// the identifier of the association,
+    /// as in the [`RequestContext`] of its requests
+    pub association_id: u64,
+    /// the application entity title of the association requestor
+    pub calling_ae_title: &'a str,
+}
+
+/// A handler notified when an association ends,
+/// whether it was released or aborted.
+///
+/// Implemented for closures taking the association context.
+pub trait AssociationEndHandler: Send + Sync {
+    /// Take note of the end of an association,
+    /// after all of its requests were handled.
+    fn association_ended(&self, context: &AssociationContext<'_>);
+}
+
+impl<F> AssociationEndHandler for F
+where
+    F: Fn(&AssociationContext<'_>) + Send + Sync,
+{
+    fn association_ended(&self, context: &AssociationContext<'_>) {
+        self(context)
+    }
+}
+
+/// The association in which a request is handled,
+/// through which the operations are carried out
+/// and the responses are sent.
+pub(crate) trait Exchange: FindOperation + GetOperation {
+    /// Send a response to the request being handled.
+    fn respond(&mut self, command: Command, data: Option<&[u8]>) -> Result<(), AssociationError>;
+
+    /// Send an event report through the presentation context of the request,
+    /// passing the status of its response to the given channel once received.
+    fn report_event(
+        &mut self,
+        report: EventReport,
+        response: mpsc::Sender<Status>,
+    ) -> Result<(), AssociationError>;
+}
+
+/// A set of service handlers, registered per SOP class,
+/// to which incoming DIMSE requests are dispatched.
+///
+/// Requests for SOP classes without a registered handler
+/// are refused with the status _SOP class not supported_ (0122H),
+/// except for C-STORE requests if a [default store handler](Self::with_default_store) is set,
+/// and request types which are not supported at all
+/// are refused with the status _unrecognized operation_ (0211H).
+#[derive(Clone, Default)]
+pub struct ServiceRegistry {
+    echo: Option<Arc<dyn EchoHandler>>,
+    store: HashMap<String, Arc<dyn StoreHandler>>,
+    default_store: Option<Arc<dyn StoreHandler>>,
+    find: HashMap<String, Arc<dyn FindHandler>>,
+    move_: HashMap<String, Arc<dyn MoveHandler>>,
+    get: HashMap<String, Arc<dyn GetHandler>>,
+    action: HashMap<String, Arc<dyn ActionHandler>>,
+    association_end: Option<Arc<dyn AssociationEndHandler>>,
+}
+
+impl fmt::Debug for ServiceRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceRegistry")
+            .field("echo", &self.echo.is_some())
+            .field("store", &self.store.keys().collect::<Vec<_>>())
+            .field("default_store", &self.default_store.is_some())
+            .field("find", &self.find.keys().collect::<Vec<_>>())
+            .field("move", &self.move_.keys().collect::<Vec<_>>())
+            .field("get", &self.get.keys().collect::<Vec<_>>())
+            .field("action", &self.action.keys().collect::<Vec<_>>())
+            .field("association_end", &self.association_end.is_some())
+            .finish()
+    }
+}
+
+impl ServiceRegistry {
+    /// Create an empty service registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the handler of the verification service.
+    pub fn with_echo<H>(mut self, handler: H) -> Self
+    where
+        H: EchoHandler + 'static,
+    {
+        self.echo = Some(Arc::new(handler));
+        self
+    }
+
+    /// Register a storage service handler for the given SOP class.
+    pub fn with_store<T, H>(mut self, sop_class_uid: T, handler: H) -> Self
+    where
+        T: Into<String>,
+        H: StoreHandler + 'static,
+    {
+        self.store.insert(sop_class_uid.into(), Arc::new(handler));
+        self
+    }
+
+    /// Register a storage service handler
+    /// for the SOP classes without a handler of their own,
+    /// such as those accepted by a promiscuous SCP.
+    ///
+    /// These SOP classes are not added to the abstract syntaxes.
+    pub fn with_default_store<H>(mut self, handler: H) -> Self
+    where
+        H: StoreHandler + 'static,
+    {
+        self.default_store = Some(Arc::new(handler));
+        self
+    }
+
+    /// Register a query service handler for the given SOP class
+    /// (the query/retrieve information model).
+    pub fn with_find<T, H>(mut self, sop_class_uid: T, handler: H) -> Self
+    where
+        T: Into<String>,
+        H: FindHandler + 'static,
+    {
+        self.find.insert(sop_class_uid.into(), Arc::new(handler));
+        self
+    }
+
+    /// Register a C-MOVE retrieve service handler for the given SOP class
+    /// (the query/retrieve information model).
+    pub fn with_move<T, H>(mut self, sop_class_uid: T, handler: H) -> Self
+    where
+        T: Into<String>,
+        H: MoveHandler + 'static,
+    {
+        self.move_.insert(sop_class_uid.into(), Arc::new(handler));
+        self
+    }
+
+    /// Register a C-GET retrieve service handler for the given SOP class
+    /// (the query/retrieve information model).
+    pub fn with_get<T, H>(mut self, sop_class_uid: T, handler: H) -> Self
+    where
+        T: Into<String>,
+        H: GetHandler + 'static,
+    {
+        self.get.insert(sop_class_uid.into(), Arc::new(handler));
+        self
+    }
+
+    /// Register an N-ACTION handler for the given SOP class.
+    pub fn with_action<T, H>(mut self, sop_class_uid: T, handler: H) -> Self
+    where
+        T: Into<String>,
+        H: ActionHandler + 'static,
+    {
+        self.action.insert(sop_class_uid.into(), Arc::new(handler));
+        self
+    }
+
+    /// Register the handler notified when an association ends.
+    pub fn with_association_end<H>(mut self, handler: H) -> Self
+    where
+        H: AssociationEndHandler + 'static,
+    {
+        self.association_end = Some(Arc::new(handler));
+        self
+    }
+
+    /// Iterate over the SOP classes with a registered handler,
+    /// which should be accepted as abstract syntaxes.
+    pub fn abstract_syntaxes(&self) -> impl Iterator<Item = &str> {
+        self.echo
+            .as_ref()
+            .map(|_| VERIFICATION_SOP_CLASS)
+            .into_iter()
+            .chain(self.store.keys().map(|uid| uid.as_str()))
+            .chain(self.find.keys().map(|uid| uid.as_str()))
+            .chain(self.move_.keys().map(|uid| uid.as_str()))
+            .chain(self.get.keys().map(|uid| uid.as_str()))
+            .chain(self.action.keys().map(|uid| uid.as_str()))
+    }
+
+    /// Notify the association end handler, if any.
+    pub(crate) fn association_ended(&self, context: &AssociationContext<'_>) {
+        if let Some(handler) = &self.association_end {
+            handler.association_ended(context);
+        }
+    }
+
+    /// Handle a C-STORE request received in the given context,
+    /// reading its data set, and return the status of the response.
+    pub(crate) fn store(
+        &self,
+        context: &RequestContext<'_>,
+        request: &CStoreRQ,
+        data: &mut dyn Read,
+    ) -> Status {
+        self.store
+            .get(request.affected_sop_class_uid.as_str())
+            .or(self.default_store.as_ref())
+            .map(|handler| handler.store(context, request, data))
+            .unwrap_or(Status::SOP_CLASS_NOT_SUPPORTED)
+    }
+
+    /// Handle a DIMSE request received in the given context
+    /// with its data set, if any,
+    /// sending back the responses through the exchange.
+    ///
+    /// Responses and cancel requests received
+    /// are not answered.
+    /// C-STORE requests without a data set
+    /// are refused with the status _cannot understand_ (C000H).
+    pub(crate) fn handle<E>(
+        &self,
+        context: &RequestContext<'_>,
+        command: &Command,
+        data: Option<&[u8]>,
+        exchange: &mut E,
+    ) -> Result<(), AssociationError>
+    where
+        E: Exchange,
+    {
+        let identifier = data.unwrap_or_default();
+        match command {
+            Command::CEchoRQ(rq) => {
+                let status = self
+                    .echo
+                    .as_ref()
+                    .map(|handler| handler.echo(context))
+                    .unwrap_or(Status::SOP_CLASS_NOT_SUPPORTED);
+                exchange.respond(echo_response(rq, status), None)
+            }
+            Command::CStoreRQ(rq) => {
+                let status = match data {
+                    Some(mut data) => self.store(context, rq, &mut data),
+                    None => Status::CANNOT_UNDERSTAND,
+                };
+                exchange.respond(store_response(rq, status), None)
+            }
+            Command::CFindRQ(rq) => {
+                let status = match self.find.get(rq.affected_sop_class_uid.as_str()) {
+                    Some(handler) => handler.find(context, rq, identifier, exchange),
+                    None => Status::SOP_CLASS_NOT_SUPPORTED,
+                };
+                exchange.respond(find_response(rq, status), None)
+            }
+            Command::CMoveRQ(rq) => {
+                let outcome = match self.move_.get(rq.affected_sop_class_uid.as_str()) {
+                    Some(handler) => handler.move_instances(context, rq, identifier, exchange),
+                    None => RetrieveOutcome::status(Status::SOP_CLASS_NOT_SUPPORTED),
+                };
+                let response = retrieve_response(command, outcome.status, outcome.sub_operations)
+                    .expect("response to a retrieve request");
+                exchange.respond(response, outcome.identifier.as_deref())
+            }
+            Command::CGetRQ(rq) => {
+                let outcome = match self.get.get(rq.affected_sop_class_uid.as_str()) {
+                    Some(handler) => handler.get(context, rq, identifier, exchange),
+                    None => RetrieveOutcome::status(Status::SOP_CLASS_NOT_SUPPORTED),
+                };
+                let response = retrieve_response(command, outcome.status, outcome.sub_operations)
+                    .expect("response to a retrieve request");
+                exchange.respond(response, outcome.identifier.as_deref())
+            }
+            Command::NActionRQ(rq) => {
+                let mut events = EventReports::default();
+                let status = match self.action.get(rq.requested_sop_class_uid.as_str()) {
+                    Some(handler) => handler.action(context, rq, identifier, &mut events),
+                    None => Status::SOP_CLASS_NOT_SUPPORTED,
+                };
+                let response =
+                    response_command(command, status).expect("response to an N-ACTION request");
+                exchange.respond(response, None)?;
+                // reports which are not sent drop their channel
+                for (report, response) in events.queued {
+                    exchange.report_event(report, response)?;
+                }
+                Ok(())
+            }
+            command => match response_command(command, Status::UNRECOGNIZED_OPERATION) {
+                Some(response) => exchange.respond(response, None),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+fn echo_response(rq: &CEchoRQ, status: Status) -> Command {
+    Command::CEchoRSP(CEchoRSP {
+        message_id_being_responded_to: rq.message_id,
+        affected_sop_class_uid: rq.affected_sop_class_uid.clone(),
+        status,
+    })
+}
+
+fn store_response(rq: &CStoreRQ, status: Status) -> Command {
+    Command::CStoreRSP(CStoreRSP {
+        message_id_being_responded_to: rq.message_id,
+        affected_sop_class_uid: rq.affected_sop_class_uid.clone(),
+        affected_sop_instance_uid: rq.affected_sop_instance_uid.clone(),
+        status,
+    })
+}
+
+fn find_response(rq: &CFindRQ, status: Status) -> Command {
+    Command::CFindRSP(CFindRSP {
+        message_id_being_responded_to: rq.message_id,
+        affected_sop_class_uid: rq.affected_sop_class_uid.clone(),
+        status,
+    })
+}
+
+/// Build a response to a C-MOVE or C-GET request
+/// with the given status and sub-operation counts.
+///
+/// Returns `None` if the command is not a retrieve request.
+pub(crate) fn retrieve_response(
+    command: &Command,
+    status: Status,
+    sub_operations: SubOperations,
+) -> Option<Command> {
+    match command {
+        Command::CGetRQ(rq) => Some(Command::CGetRSP(CGetRSP {
+            message_id_being_responded_to: rq.message_id,
+            affected_sop_class_uid: rq.affected_sop_class_uid.clone(),
+            status,
+            sub_operations,
+        })),
+        Command::CMoveRQ(rq) => Some(Command::CMoveRSP(CMoveRSP {
+            message_id_being_responded_to: rq.message_id,
+            affected_sop_class_uid: rq.affected_sop_class_uid.clone(),
+            status,
+            sub_operations,
+        })),
+        _ => None,
+    }
+}
+
+/// Build a response to the given request with the given status,
+/// without any other attributes specific to the operation.
+///
+/// Returns `None` if the command is not a request
+/// or does not expect a response.
+pub fn response_command(command: &Command, status: Status) -> Option<Command> {
+    let response = match command {
+        Command::CEchoRQ(rq) => echo_response(rq, status),
+        Command::CStoreRQ(rq) => store_response(rq, status),
+        Command::CFindRQ(rq) => find_response(rq, status),
+        Command::CGetRQ(_) | Command::CMoveRQ(_) => {
+            return retrieve_response(command, status, SubOperations::default())
+        }
+        Command::NEventReportRQ(rq) => Command::NEventReportRSP(NEventReportRSP {
+            message_id_being_responded_to: rq.message_id,
+            affected_sop_class_uid: Some(rq.affected_sop_class_uid.clone()),
+            affected_sop_instance_uid: Some(rq.affected_sop_instance_uid.clone()),
+            event_type_id: Some(rq.event_type_id),
+            status,
+        }),
+        Command::NGetRQ(rq) => Command::NGetRSP(NGetRSP {
+            message_id_being_responded_to: rq.message_id,
+            affected_sop_class_uid: Some(rq.requested_sop_class_uid.clone()),
+            affected_sop_instance_uid: Some(rq.requested_sop_instance_uid.clone()),
+            status,
+        }),
+        Command::NSetRQ(rq) => Command::NSetRSP(NSetRSP {
+            message_id_being_responded_to: rq.message_id,
+            affected_sop_class_uid: Some(rq.requested_sop_class_uid.clone()),
+            affected_sop_instance_uid: Some(rq.requested_sop_instance_uid.clone()),
+            status,
+        }),
+        Command::NActionRQ(rq) => Command::NActionRSP(NActionRSP {
+            message_id_being_responded_to: rq.message_id,
+            affected_sop_class_uid: Some(rq.requested_sop_class_uid.clone()),
+            affected_sop_instance_uid: Some(rq.requested_sop_instance_uid.clone()),
+            action_type_id: Some(rq.action_type_id),
+            status,
+        }),
+        Command::NCreateRQ(rq) => Command::NCreateRSP(NCreateRSP {
+            message_id_being_responded_to: rq.message_id,
+            affected_sop_class_uid: Some(rq.affected_sop_class_uid.clone()),
+            affected_sop_instance_uid: rq.affected_sop_instance_uid.clone(),
+            status,
+        }),
+        Command::NDeleteRQ(rq) => Command::NDeleteRSP(NDeleteRSP {
+            message_id_being_responded_to: rq.message_id,
+            affected_sop_class_uid: Some(rq.requested_sop_class_uid.clone()),
+            affected_sop_instance_uid: Some(rq.requested_sop_instance_uid.clone()),
+            status,
+        }),
+        _ => return None,
+    };
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dimse::{CCancelRQ, CGetRQ, NActionRQ, Priority};
+
+    static CT_IMAGE_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.2";
+    static STUDY_ROOT_FIND: &str = "1.2.840.10008.5.1.4.1.2.2.1";
+    static STORAGE_COMMITMENT: &str = "1.2.840.10008.1.20.1";
+
+    fn context() -> RequestContext<'static> {
+        RequestContext {
+            association_id: 1,
+            calling_ae_title: "SCU",
+            presentation_context_id: 1,
+            abstract_syntax: CT_IMAGE_STORAGE,
+            transfer_syntax: "1.2.840.10008.1.2",
+        }
+    }
+
+    /// An exchange which records the responses sent,
+    /// with the requestor canceling after the given number of them.
+    #[derive(Default)]
+    struct Responses {
+        sent: Vec<(Command, Option<Vec<u8>>)>,
+        reports: Vec<(EventReport, mpsc::Sender<Status>)>,
+        cancel_after: Option<usize>,
+    }
+
+    impl FindOperation for Responses {
+        fn is_canceled(&mut self) -> bool {
+            self.cancel_after
+                .is_some_and(|cancel_after| self.sent.len() >= cancel_after)
+        }
+
+        fn send_match(&mut self, identifier: &[u8]) -> Result<(), AssociationError> {
+            self.sent.push((
+                find_response(&find_rq(), Status::PENDING),
+                Some(identifier.to_vec()),
+            ));
+            Ok(())
+        }
+    }
+
+    impl RetrieveOperation for Responses {
+        fn is_canceled(&mut self) -> bool {
+            FindOperation::is_canceled(self)
+        }
+
+        fn report(&mut self, _: SubOperations) -> Result<(), AssociationError> {
+            unreachable!("no retrieve handlers in the registry")
+        }
+    }
+
+    impl GetOperation for Responses {
+        fn storage_contexts(&self) -> Vec<StorageContext> {
+            Vec::new()
+        }
+
+        fn next_message_id(&mut self) -> u16 {
+            1
+        }
+
+        fn store(&mut self, _: u8, _: CStoreRQ, _: &[u8]) -> Result<Status, AssociationError> {
+            unreachable!("no retrieve handlers in the registry")
+        }
+    }
+
+    impl Exchange for Responses {
+        fn respond(
+            &mut self,
+            command: Command,
+            data: Option<&[u8]>,
+        ) -> Result<(), AssociationError> {
+            self.sent.push((command, data.map(|data| data.to_vec())));
+            Ok(())
+        }
+
+        fn report_event(
+            &mut self,
+            report: EventReport,
+            response: mpsc::Sender<Status>,
+        ) -> Result<(), AssociationError> {
+            self.reports.push((report, response));
+            Ok(())
+        }
+    }
+
+    /// Handle a request, returning the responses sent.
+    fn handle(
+        registry: &ServiceRegistry,
+        command: Command,
+        data: Option<&[u8]>,
+    ) -> Vec<(Command, Option<Vec<u8>>)> {
+        let mut responses = Responses::default();
+        registry
+            .handle(&context(), &command, data, &mut responses)
+            .unwrap();
+        responses.sent
+    }
+
+    fn store_rq(sop_class_uid: &str) -> Command {
+        Command::CStoreRQ(CStoreRQ {
+            message_id: 5,
+            affected_sop_class_uid: sop_class_uid.to_string(),
+            affected_sop_instance_uid: "2.25.1".to_string(),
+            priority: Priority::Medium,
+            move_originator_ae_title: None,
+            move_originator_message_id: None,
+        })
+    }
+
+    fn action_rq(action_type_id: u16) -> Command {
+        Command::NActionRQ(NActionRQ {
+            message_id: 9,
+            requested_sop_class_uid: STORAGE_COMMITMENT.to_string(),
+            requested_sop_instance_uid: "1.2.840.10008.1.20.1.1".to_string(),
+            action_type_id,
+        })
+    }
+
+    fn find_rq() -> CFindRQ {
+        CFindRQ {
+            message_id: 6,
+            affected_sop_class_uid: STUDY_ROOT_FIND.to_string(),
+            priority: Priority::Medium,
+        }
+    }
+
+    fn registry() -> ServiceRegistry {
+        ServiceRegistry::new()
+            .with_echo(|_: &RequestContext<'_>| Status::SUCCESS)
+            .with_store(
+                CT_IMAGE_STORAGE,
+                |_: &RequestContext<'_>, _: &CStoreRQ, data: &mut dyn Read| {
+                    let mut first = [0; 1];
+                    match data.read(&mut first) {
+                        Ok(1) => Status::SUCCESS,
+                        _ => Status::PROCESSING_FAILURE,
+                    }
+                },
+            )
+            .with_find(
+                STUDY_ROOT_FIND,
+                |_: &RequestContext<'_>,
+                 _: &CFindRQ,
+                 identifier: &[u8],
+                 operation: &mut dyn FindOperation| {
+                    for _ in 0..2 {
+                        if operation.is_canceled() {
+                            return Status::CANCEL;
+                        }
+                        operation.send_match(identifier).unwrap();
+                    }
+                    Status::SUCCESS
+                },
+            )
+            .with_action(
+                STORAGE_COMMITMENT,
+                |_: &RequestContext<'_>, rq: &NActionRQ, data: &[u8], events: &mut EventReports| {
+                    if rq.action_type_id != 1 {
+                        return Status::NO_SUCH_ACTION_TYPE;
+                    }
+                    events.send(EventReport {
+                        affected_sop_class_uid: STORAGE_COMMITMENT.to_string(),
+                        affected_sop_instance_uid: rq.requested_sop_instance_uid.clone(),
+                        event_type_id: 1,
+                        data: Some(data.to_vec()),
+                    });
+                    Status::SUCCESS
+                },
+            )
+    }
+
+    #[test]
+    fn abstract_syntaxes() {
+        let registry = registry();
+        let mut uids: Vec<_> = registry.abstract_syntaxes().collect();
+        uids.sort_unstable();
+        assert_eq!(
+            uids,
+            vec![
+                VERIFICATION_SOP_CLASS,
+                STORAGE_COMMITMENT,
+                CT_IMAGE_STORAGE,
+                STUDY_ROOT_FIND
+            ]
+        );
+        assert_eq!(ServiceRegistry::new().abstract_syntaxes().count(), 0);
+    }
+
+    #[test]
+    fn dispatch_store() {
+        let registry = registry();
+        let responses = handle(&registry, store_rq(CT_IMAGE_STORAGE), Some(&[1, 2, 3, 4]));
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].0.status(), Some(Status::SUCCESS));
+        assert_eq!(responses[0].0.message_id_being_responded_to(), Some(5));
+
+        let responses = handle(&registry, store_rq(CT_IMAGE_STORAGE), Some(&[]));
+        assert_eq!(responses[0].0.status(), Some(Status::PROCESSING_FAILURE));
+
+        // no handler for MR image storage
+        let responses = handle(
+            &registry,
+            store_rq("1.2.840.10008.5.1.4.1.1.4"),
+            Some(&[1, 2]),
+        );
+        assert_eq!(
+            responses[0].0.status(),
+            Some(Status::SOP_CLASS_NOT_SUPPORTED)
+        );
+
+        // no data set to store
+        let responses = handle(&registry, store_rq(CT_IMAGE_STORAGE), None);
+        assert_eq!(responses[0].0.status(), Some(Status::CANNOT_UNDERSTAND));
+
+        // any other SOP class goes to the default handler
+        let registry = registry.with_default_store(
+            |_: &RequestContext<'_>, _: &CStoreRQ, _: &mut dyn Read| Status::OUT_OF_RESOURCES,
+        );
+        let responses = handle(
+            &registry,
+            store_rq("1.2.840.10008.5.1.4.1.1.4"),
+            Some(&[1, 2]),
+        );
+        assert_eq!(responses[0].0.status(), Some(Status::OUT_OF_RESOURCES));
+        let responses = handle(&registry, store_rq(CT_IMAGE_STORAGE), Some(&[1, 2]));
+        assert_eq!(responses[0].0.status(), Some(Status::SUCCESS));
+    }
+
+    #[test]
+    fn dispatch_action() {
+        let registry = registry();
+        let mut responses = Responses::default();
+        registry
+            .handle(&context(), &action_rq(1), Some(&[3; 4]), &mut responses)
+            .unwrap();
+        assert_eq!(responses.sent.len(), 1);
+        assert!(matches!(responses.sent[0].0, Command::NActionRSP(_)));
+        assert_eq!(responses.sent[0].0.status(), Some(Status::SUCCESS));
+        assert_eq!(responses.sent[0].0.message_id_being_responded_to(), Some(9));
+        assert_eq!(responses.reports.len(), 1);
+        let (report, _) = &responses.reports[0];
+        assert_eq!(report.event_type_id, 1);
+        assert_eq!(report.data.as_deref(), Some(&[3; 4][..]));
+
+        // refused without event reports
+        let mut responses = Responses::default();
+        registry
+            .handle(&context(), &action_rq(2), None, &mut responses)
+            .unwrap();
+        assert_eq!(
+            responses.sent[0].0.status(),
+            Some(Status::NO_SUCH_ACTION_TYPE)
+        );
+        assert!(responses.reports.is_empty());
+    }
+
+    #[test]
+    fn dispatch_find() {
+        let registry = registry();
+        let find = Command::CFindRQ(find_rq());
+        let responses = handle(&registry, find.clone(), Some(&[9; 8]));
+        let statuses: Vec<_> = responses.iter().map(|(c, _)| c.status()).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                Some(Status::PENDING),
+                Some(Status::PENDING),
+                Some(Status::SUCCESS)
+            ]
+        );
+        assert_eq!(responses[0].1, Some(vec![9; 8]));
+        assert_eq!(responses[2].1, None);
+
+        // canceled after the first match
+        let mut responses = Responses {
+            cancel_after: Some(1),
+            ..Default::default()
+        };
+        registry
+            .handle(&context(), &find, Some(&[9; 8]), &mut responses)
+            .unwrap();
+        let statuses: Vec<_> = responses.sent.iter().map(|(c, _)| c.status()).collect();
+        assert_eq!(statuses, vec![Some(Status::PENDING), Some(Status::CANCEL)]);
+
+        let responses = handle(&ServiceRegistry::new(), find, None);
+        assert_eq!(responses.len(), 1);
+        assert_eq!(
+            responses[0].0.status(),
+            Some(Status::SOP_CLASS_NOT_SUPPORTED)
+        );
+    }
+
+    #[test]
+    fn dispatch_unsupported() {
+        let registry = registry();
+        let responses = handle(
+            &registry,
+            Command::CGetRQ(CGetRQ {
+                message_id: 7,
+                affected_sop_class_uid: "1.2.840.10008.5.1.4.1.2.2.3".to_string(),
+                priority: Priority::Medium,
+            }),
+            Some(&[]),
+        );
+        assert_eq!(responses.len(), 1);
+        assert!(matches!(responses[0].0, Command::CGetRSP(_)));
+        assert_eq!(
+            responses[0].0.status(),
+            Some(Status::SOP_CLASS_NOT_SUPPORTED)
+        );
+
+        let responses = handle(
+            &registry,
+            Command::NGetRQ(crate::dimse::NGetRQ {
+                message_id: 8,
+                requested_sop_class_uid: "1.2.840.10008.5.1.4.33".to_string(),
+                requested_sop_instance_uid: "2.25.2".to_string(),
+                attribute_identifier_list: Vec::new(),
+            }),
+            None,
+        );
+        assert_eq!(responses.len(), 1);
+        assert_eq!(
+            responses[0].0.status(),
+            Some(Status::UNRECOGNIZED_OPERATION)
+        );
+
+        // nothing to respond to
+        let responses = handle(
+            &registry,
+            Command::CCancelRQ(CCancelRQ {
+                message_id_being_responded_to: 6,
+            }),
+            None,
+        );
+        assert!(responses.is_empty());
+    }
+}
@@ -0,0 +1,1479 @@
+//! Multi-association SCP server
+//!
+//! See [`ScpServer`] for details.
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use snafu::{Backtrace, ResultExt, Snafu};
+use tracing::{debug, info, warn};
+
+use crate::{
+    association::{
+        client::CloseSocket,
+        server::{self, AccessControl, ServerAssociation, ServerAssociationOptions, WireReadSnafu},
+        state::State,
+        Roles,
+    },
+    dimse::{
+        CStoreRQ, Command, Message, MessageCommand, NEventReportRQ, NEventReportRSP, Status,
+        SubOperations,
+    },
+    pdu::{PresentationContextResult, PresentationContextResultReason},
+};
+
+use super::{
+    find_response, retrieve_response, store_response, AssociationContext, EventReport, Exchange,
+    FindOperation, GetOperation, OwnedRequestContext, RetrieveOperation, ServiceRegistry,
+    StorageContext,
+};
+
+/// The default maximum number of associations served at the same time.
+pub const DEFAULT_MAX_ASSOCIATIONS: usize = 32;
+
+/// How often idle connections and listeners check for a shutdown request.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait for a cancel request
+/// when checking for one during an operation.
+const CANCEL_POLL_TIMEOUT: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Snafu)]
+#[non_exhaustive]
+pub enum Error {
+    /// failed to configure the listener
+    ConfigureListener {
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+    /// TLS associations cannot be served asynchronously
+    /// without the `async-tls` feature
+    #[cfg(feature = "tls")]
+    AsyncTlsUnsupported { backtrace: Backtrace },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A handle for requesting an [`ScpServer`] to shut down.
+///
+/// Once requested,
+/// the server stops accepting new associations,
+/// and each ongoing association is released
+/// after the DIMSE message currently being received is handled.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    requested: Arc<AtomicBool>,
+    #[cfg(feature = "async")]
+    notify: Arc<tokio::sync::Notify>,
+}
+
+impl ShutdownHandle {
+    /// Request the server to shut down.
+    pub fn shutdown(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        #[cfg(feature = "async")]
+        self.notify.notify_waiters();
+    }
+
+    /// Whether a shutdown has been requested.
+    pub fn is_shutdown(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Wait until a shutdown is requested.
+    #[cfg(feature = "async")]
+    async fn requested(&self) {
+        loop {
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.is_shutdown() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// A DICOM server which accepts associations concurrently,
+/// dispatching the DIMSE requests received
+/// to the handlers in a [`ServiceRegistry`].
+///
+/// Associations are negotiated with the given [`ServerAssociationOptions`],
+/// to which the SOP classes of the registered services
+/// are added as abstract syntaxes.
+/// Once the maximum number of associations is reached,
+/// new connections are only accepted after an ongoing association ends.
+///
+/// The server runs until a shutdown is requested
+/// through its [shutdown handle](Self::shutdown_handle).
+/// Each association is then released gracefully,
+/// so that the message currently in transit is still handled.
+/// The same happens to associations
+/// which remain idle for longer than the [idle timeout](Self::idle_timeout).
+///
+/// Associations are established over TLS
+/// if the association options have a TLS configuration.
+///
+/// With the `async` feature,
+/// [`serve_async`](Self::serve_async) serves associations
+/// as tasks in a tokio runtime instead of threads,
+/// running the handlers in blocking tasks.
+#[derive(Debug)]
+pub struct ScpServer<A> {
+    options: Arc<ServerAssociationOptions<'static, A>>,
+    services: Arc<ServiceRegistry>,
+    max_associations: usize,
+    idle_timeout: Option<Duration>,
+    shutdown: ShutdownHandle,
+}
+
+impl<A> ScpServer<A>
+where
+    A: AccessControl,
+{
+    /// Create a new server with the given association options and services.
+    pub fn new(
+        mut options: ServerAssociationOptions<'static, A>,
+        services: ServiceRegistry,
+    ) -> Self {
+        for uid in services.abstract_syntaxes() {
+            options = options.with_abstract_syntax(uid.to_string());
+        }
+        ScpServer {
+            options: Arc::new(options),
+            services: Arc::new(services),
+            max_associations: DEFAULT_MAX_ASSOCIATIONS,
+            idle_timeout: None,
+            shutdown: ShutdownHandle::default(),
+        }
+    }
+
+    /// Override the maximum number of associations served at the same time.
+    pub fn max_associations(mut self, max_associations: usize) -> Self {
+        self.max_associations = max_associations.max(1);
+        self
+    }
+
+    /// Release associations which do not receive any message
+    /// for the given amount of time.
+    ///
+    /// By default, idle associations are kept indefinitely.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Obtain a handle for shutting down the server.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Accept and serve associations from the given listener
+    /// until a shutdown is requested,
+    /// each in a separate thread.
+    ///
+    /// Returns once all ongoing associations have ended.
+    /// Failures in individual associations are logged
+    /// and do not stop the server.
+    pub fn serve(&self, listener: TcpListener) -> Result<()>
+    where
+        A: Send + Sync,
+    {
+        // accept without blocking so that shutdown requests are noticed
+        listener
+            .set_nonblocking(true)
+            .context(ConfigureListenerSnafu)?;
+        let active = (Mutex::new(0_usize), Condvar::new());
+
+        std::thread::scope(|scope| {
+            while !self.shutdown.is_shutdown() {
+                {
+                    let (count, ended) = &active;
+                    let mut count = count.lock().unwrap();
+                    while *count >= self.max_associations && !self.shutdown.is_shutdown() {
+                        count = ended.wait_timeout(count, POLL_INTERVAL).unwrap().0;
+                    }
+                }
+                if self.shutdown.is_shutdown() {
+                    break;
+                }
+
+                let (stream, peer_addr) = match listener.accept() {
+                    Ok(connection) => connection,
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(POLL_INTERVAL);
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("Failed to accept connection: {}", e);
+                        continue;
+                    }
+                };
+                if let Err(e) = stream.set_nonblocking(false) {
+                    warn!("Failed to configure connection with {}: {}", peer_addr, e);
+                    continue;
+                }
+
+                *active.0.lock().unwrap() += 1;
+                let active = &active;
+                scope.spawn(move || {
+                    self.serve_connection(stream, peer_addr);
+                    let (count, ended) = active;
+                    *count.lock().unwrap() -= 1;
+                    ended.notify_one();
+                });
+            }
+        });
+        Ok(())
+    }
+
+    /// Establish an association over a new connection
+    /// and serve it until it ends, logging the outcome.
+    fn serve_connection(&self, stream: TcpStream, peer_addr: SocketAddr) {
+        #[cfg(feature = "tls")]
+        if self.options.has_tls_config() {
+            return self.serve_established(self.options.establish_tls(stream), peer_addr);
+        }
+        self.serve_established(self.options.establish(stream), peer_addr)
+    }
+
+    fn serve_established<S>(
+        &self,
+        established: server::Result<ServerAssociation<S>>,
+        peer_addr: SocketAddr,
+    ) where
+        S: Read + Write + CloseSocket,
+    {
+        match established {
+            Ok(association) => {
+                info!(
+                    "New association from {} ({})",
+                    association.client_ae_title(),
+                    peer_addr
+                );
+                if let Err(e) = self.serve_association(association) {
+                    warn!(
+                        "Association with {} ended with an error: {}",
+                        peer_addr,
+                        snafu::Report::from_error(e)
+                    );
+                }
+            }
+            Err(e) => {
+                warn!(
+                    "Could not establish association with {}: {}",
+                    peer_addr,
+                    snafu::Report::from_error(e)
+                );
+            }
+        }
+    }
+
+    /// Serve an association which was already established,
+    /// until it is released or aborted.
+    ///
+    /// This can be used to serve associations over other kinds of streams.
+    /// The read timeout of the stream is changed in the process:
+    /// it is kept short while waiting for requests,
+    /// and set to the timeout of the association options
+    /// while a request is handled.
+    /// The association end handler is notified once it ends.
+    pub fn serve_association<S>(&self, association: ServerAssociation<S>) -> server::Result<()>
+    where
+        S: Read + Write + CloseSocket,
+    {
+        let mut session = Session::new();
+        let calling_ae_title = association.client_ae_title().to_string();
+        let result = self.serve_session(association, &mut session);
+        let association_id = session.id;
+        // event reports without a response are no longer awaited
+        drop(session);
+        self.services.association_ended(&AssociationContext {
+            association_id,
+            calling_ae_title: &calling_ae_title,
+        });
+        result
+    }
+
+    fn serve_session<S>(
+        &self,
+        mut association: ServerAssociation<S>,
+        session: &mut Session,
+    ) -> server::Result<()>
+    where
+        S: Read + Write + CloseSocket,
+    {
+        let mut last_activity = Instant::now();
+
+        loop {
+            if self.shutdown.is_shutdown() {
+                debug!(
+                    "Shutting down association with {}",
+                    association.client_ae_title()
+                );
+                return self.shutdown_association(association, session);
+            }
+            // wake up regularly to check for shutdown requests
+            let _ = association
+                .inner_stream()
+                .set_read_timeout(Some(POLL_INTERVAL));
+            match association.receive_command() {
+                Ok(Some(message)) => {
+                    self.dispatch(&mut association, session, message)?;
+                    last_activity = Instant::now();
+                }
+                Ok(None) => {
+                    info!(
+                        "Released association with {}",
+                        association.client_ae_title()
+                    );
+                    return Ok(());
+                }
+                Err(e) if is_timeout(&e) => {
+                    if matches!(self.idle_timeout, Some(timeout) if last_activity.elapsed() >= timeout)
+                    {
+                        info!(
+                            "Releasing idle association with {}",
+                            association.client_ae_title()
+                        );
+                        return self.shutdown_association(association, session);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Handle the message in transit, if any,
+    /// and then release the association.
+    fn shutdown_association<S>(
+        &self,
+        mut association: ServerAssociation<S>,
+        session: &mut Session,
+    ) -> server::Result<()>
+    where
+        S: Read + Write + CloseSocket,
+    {
+        let artim_timeout = association.artim_timeout();
+        loop {
+            let _ = association
+                .inner_stream()
+                .set_read_timeout(Some(artim_timeout));
+            match association.receive_pending_command()? {
+                Some(message) => self.dispatch(&mut association, session, message)?,
+                None => break,
+            }
+        }
+        if association.state() != State::Established {
+            return Ok(());
+        }
+        association.release_with(unanswered)
+    }
+
+    /// Dispatch a request received in an association to the services,
+    /// passing on its data set, if any,
+    /// and send back their responses.
+    fn dispatch<S>(
+        &self,
+        association: &mut ServerAssociation<S>,
+        session: &mut Session,
+        message: MessageCommand,
+    ) -> server::Result<()>
+    where
+        S: Read + Write + CloseSocket,
+    {
+        let MessageCommand {
+            presentation_context_id,
+            command,
+            has_data_set,
+        } = message;
+        // data sets may take longer than the poll interval to arrive
+        let read_timeout = self.options.operation_timeout();
+        let _ = association.inner_stream().set_read_timeout(read_timeout);
+
+        let Some(context) = OwnedRequestContext::new(
+            session.id,
+            association.client_ae_title(),
+            association.presentation_contexts(),
+            association.abstract_syntax(presentation_context_id),
+            presentation_context_id,
+        ) else {
+            warn!(
+                "Ignoring message in unknown presentation context {}",
+                presentation_context_id
+            );
+            if has_data_set {
+                discard_data_set(association, presentation_context_id)?;
+            }
+            return Ok(());
+        };
+
+        match command {
+            Command::CStoreRQ(rq) if has_data_set => {
+                let mut data = association.receive_data_set(presentation_context_id);
+                let status = self.services.store(&context.get(), &rq, &mut data);
+                // receive what the handler did not read
+                std::io::copy(&mut data, &mut std::io::sink()).context(WireReadSnafu)?;
+                association.send_message(
+                    presentation_context_id,
+                    &store_response(&rq, status),
+                    None,
+                )
+            }
+            Command::NEventReportRSP(rsp) => {
+                if has_data_set {
+                    discard_data_set(association, presentation_context_id)?;
+                }
+                session.on_event_report_response(&rsp);
+                Ok(())
+            }
+            command => {
+                let data = if has_data_set {
+                    let mut data = Vec::new();
+                    association
+                        .receive_data_set(presentation_context_id)
+                        .read_to_end(&mut data)
+                        .context(WireReadSnafu)?;
+                    Some(data)
+                } else {
+                    None
+                };
+                let mut operation = Operation {
+                    association,
+                    state: OperationState::new(&command, session),
+                    presentation_context_id,
+                    read_timeout,
+                };
+                self.services
+                    .handle(&context.get(), &command, data.as_deref(), &mut operation)
+            }
+        }
+    }
+}
+
+/// Receive the data set of a message which is not handled.
+fn discard_data_set<S>(
+    association: &mut ServerAssociation<S>,
+    presentation_context_id: u8,
+) -> server::Result<()>
+where
+    S: Read + Write + CloseSocket,
+{
+    std::io::copy(
+        &mut association.receive_data_set(presentation_context_id),
+        &mut std::io::sink(),
+    )
+    .context(WireReadSnafu)?;
+    Ok(())
+}
+
+/// The source of association IDs.
+static NEXT_ASSOCIATION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The state of an association kept across the requests handled in it.
+struct Session {
+    id: u64,
+    /// the channels for the responses to the event reports sent,
+    /// by message ID of their request
+    pending_events: HashMap<u16, mpsc::Sender<Status>>,
+}
+
+impl Session {
+    fn new() -> Self {
+        Session {
+            id: NEXT_ASSOCIATION_ID.fetch_add(1, Ordering::Relaxed),
+            pending_events: HashMap::new(),
+        }
+    }
+
+    /// Pass on the response to an event report.
+    fn on_event_report_response(&mut self, rsp: &NEventReportRSP) {
+        match self
+            .pending_events
+            .remove(&rsp.message_id_being_responded_to)
+        {
+            Some(response) => {
+                // the handler may no longer wait for it
+                let _ = response.send(rsp.status);
+            }
+            None => warn!(
+                "Ignoring N-EVENT-REPORT response to unknown request {}",
+                rsp.message_id_being_responded_to
+            ),
+        }
+    }
+}
+
+/// The progress of the operation of a request,
+/// which depends on the messages received in the meantime.
+struct OperationState<'a> {
+    request: &'a Command,
+    session: &'a mut Session,
+    canceled: bool,
+    /// whether the association can no longer be used
+    closed: bool,
+}
+
+impl<'a> OperationState<'a> {
+    fn new(request: &'a Command, session: &'a mut Session) -> Self {
+        OperationState {
+            request,
+            session,
+            canceled: false,
+            closed: false,
+        }
+    }
+
+    /// Whether the operation should stop.
+    fn is_over(&self) -> bool {
+        self.canceled || self.closed
+    }
+
+    /// Take note of a message received during the operation.
+    fn on_message(&mut self, message: Message) {
+        match message.command {
+            Command::CCancelRQ(rq)
+                if Some(rq.message_id_being_responded_to) == self.request.message_id() =>
+            {
+                debug!("{:?} operation canceled", self.request.command_field());
+                self.canceled = true;
+            }
+            Command::NEventReportRSP(rsp) => self.session.on_event_report_response(&rsp),
+            command => {
+                warn!(
+                    "Ignoring unexpected {:?} during {:?} operation",
+                    command.command_field(),
+                    self.request.command_field()
+                );
+            }
+        }
+    }
+
+    /// Take note of the association ending during the operation.
+    fn on_end(&mut self, error: Option<server::Error>) {
+        match error {
+            Some(e) => warn!(
+                "Association ended during {:?} operation: {}",
+                self.request.command_field(),
+                snafu::Report::from_error(e)
+            ),
+            None => info!(
+                "Association released during {:?} operation",
+                self.request.command_field()
+            ),
+        }
+        self.closed = true;
+    }
+
+    /// The pending response to a C-FIND request for a match.
+    fn match_response(&self) -> Command {
+        match self.request {
+            Command::CFindRQ(rq) => find_response(rq, Status::PENDING),
+            _ => panic!("matches are only sent for C-FIND requests"),
+        }
+    }
+
+    /// The pending response to a C-MOVE or C-GET request
+    /// with the progress of its sub-operations.
+    fn progress_response(&self, sub_operations: SubOperations) -> Command {
+        retrieve_response(self.request, Status::PENDING, sub_operations)
+            .expect("progress is only reported for retrieve requests")
+    }
+
+    /// Take note of an event report sent with the given message ID,
+    /// whose response is to be passed on to the given channel.
+    fn on_event_report(&mut self, message_id: u16, response: mpsc::Sender<Status>) {
+        self.session.pending_events.insert(message_id, response);
+    }
+}
+
+/// The N-EVENT-REPORT request of an event report.
+fn event_report_request(message_id: u16, report: &EventReport) -> Command {
+    Command::NEventReportRQ(NEventReportRQ {
+        message_id,
+        affected_sop_class_uid: report.affected_sop_class_uid.clone(),
+        affected_sop_instance_uid: report.affected_sop_instance_uid.clone(),
+        event_type_id: report.event_type_id,
+    })
+}
+
+/// A request being handled in a blocking association,
+/// through which its operation is carried out.
+struct Operation<'a, S> {
+    association: &'a mut ServerAssociation<S>,
+    state: OperationState<'a>,
+    presentation_context_id: u8,
+    /// the read timeout to restore after checking for cancel requests
+    read_timeout: Option<Duration>,
+}
+
+impl<S> Operation<'_, S>
+where
+    S: Read + Write + CloseSocket,
+{
+    fn is_canceled(&mut self) -> bool {
+        if self.state.is_over() {
+            return true;
+        }
+        // without read timeouts, the association could only block
+        if self
+            .association
+            .inner_stream()
+            .set_read_timeout(Some(CANCEL_POLL_TIMEOUT))
+            .is_err()
+        {
+            return false;
+        }
+        let received = self.association.receive_message();
+        let _ = self
+            .association
+            .inner_stream()
+            .set_read_timeout(self.read_timeout);
+        match received {
+            Ok(Some(message)) => self.state.on_message(message),
+            Ok(None) => self.state.on_end(None),
+            Err(e) if is_timeout(&e) => {}
+            Err(e) => self.state.on_end(Some(e)),
+        }
+        self.state.is_over()
+    }
+
+    /// Send a message through the presentation context of the request.
+    fn send(&mut self, command: &Command, data: Option<&[u8]>) -> server::Result<()> {
+        let sent = self
+            .association
+            .send_message(self.presentation_context_id, command, data);
+        self.state.closed |= sent.is_err();
+        sent
+    }
+}
+
+impl<S> FindOperation for Operation<'_, S>
+where
+    S: Read + Write + CloseSocket,
+{
+    fn is_canceled(&mut self) -> bool {
+        Operation::is_canceled(self)
+    }
+
+    fn send_match(&mut self, identifier: &[u8]) -> server::Result<()> {
+        let command = self.state.match_response();
+        self.send(&command, Some(identifier))
+    }
+}
+
+impl<S> RetrieveOperation for Operation<'_, S>
+where
+    S: Read + Write + CloseSocket,
+{
+    fn is_canceled(&mut self) -> bool {
+        Operation::is_canceled(self)
+    }
+
+    fn report(&mut self, sub_operations: SubOperations) -> server::Result<()> {
+        let command = self.state.progress_response(sub_operations);
+        self.send(&command, None)
+    }
+}
+
+impl<S> GetOperation for Operation<'_, S>
+where
+    S: Read + Write + CloseSocket,
+{
+    fn storage_contexts(&self) -> Vec<StorageContext> {
+        storage_contexts(
+            self.association.presentation_contexts(),
+            |id| self.association.abstract_syntax(id),
+            |id| self.association.roles(id),
+        )
+    }
+
+    fn next_message_id(&mut self) -> u16 {
+        self.association.next_message_id()
+    }
+
+    fn store(
+        &mut self,
+        presentation_context_id: u8,
+        request: CStoreRQ,
+        data: &[u8],
+    ) -> server::Result<Status> {
+        let message_id = request.message_id;
+        if let Err(e) = self.association.send_message(
+            presentation_context_id,
+            &Command::CStoreRQ(request),
+            Some(data),
+        ) {
+            self.state.closed = true;
+            return Err(e);
+        }
+        loop {
+            match self.association.receive_message() {
+                Ok(Some(Message {
+                    command: Command::CStoreRSP(rsp),
+                    ..
+                })) if rsp.message_id_being_responded_to == message_id => return Ok(rsp.status),
+                Ok(Some(message)) => self.state.on_message(message),
+                Ok(None) => {
+                    self.state.closed = true;
+                    return Err(server::Error::ConnectionClosed);
+                }
+                Err(e) if is_timeout(&e) => {}
+                Err(e) => {
+                    self.state.closed = true;
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+impl<S> Exchange for Operation<'_, S>
+where
+    S: Read + Write + CloseSocket,
+{
+    fn respond(&mut self, command: Command, data: Option<&[u8]>) -> server::Result<()> {
+        self.send(&command, data)
+    }
+
+    fn report_event(
+        &mut self,
+        report: EventReport,
+        response: mpsc::Sender<Status>,
+    ) -> server::Result<()> {
+        let message_id = self.association.next_message_id();
+        self.send(
+            &event_report_request(message_id, &report),
+            report.data.as_deref(),
+        )?;
+        self.state.on_event_report(message_id, response);
+        Ok(())
+    }
+}
+
+/// The accepted presentation contexts
+/// in which the association requestor took the SCP role.
+fn storage_contexts<'a>(
+    presentation_contexts: &'a [PresentationContextResult],
+    abstract_syntax: impl Fn(u8) -> Option<&'a str>,
+    roles: impl Fn(u8) -> Option<Roles>,
+) -> Vec<StorageContext> {
+    presentation_contexts
+        .iter()
+        .filter(|pc| pc.reason == PresentationContextResultReason::Acceptance)
+        .filter(|pc| roles(pc.id).is_some_and(|roles| roles.scp))
+        .filter_map(|pc| {
+            Some(StorageContext {
+                id: pc.id,
+                abstract_syntax: abstract_syntax(pc.id)?.to_string(),
+                transfer_syntax: pc.transfer_syntax.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Whether the error is due to a read timing out.
+pub(crate) fn is_timeout(error: &server::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            return matches!(
+                e.kind(),
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+            );
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// Report a message which arrived after the release request,
+/// when it can no longer be answered.
+pub(crate) fn unanswered(message: Message) {
+    warn!(
+        "Could not answer {:?} received while releasing the association",
+        message.command.command_field()
+    );
+}
+
+#[cfg(feature = "async")]
+pub mod non_blocking {
+    use std::{
+        io::Read,
+        net::SocketAddr,
+        sync::{mpsc as sync_mpsc, Arc},
+    };
+
+    use snafu::ResultExt;
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWrite},
+        net::{TcpListener, TcpStream},
+        sync::{mpsc, oneshot, Semaphore},
+        task::{JoinHandle, JoinSet},
+    };
+    use tracing::{debug, info, warn};
+
+    use crate::{
+        association::{
+            server::{self, AccessControl, WireReadSnafu},
+            state::State,
+            AsyncServerAssociation,
+        },
+        dimse::{CStoreRQ, Command, Message, MessageCommand, Status, SubOperations},
+        scp::{
+            store_response, AssociationContext, EventReport, Exchange, FindOperation, GetOperation,
+            OwnedRequestContext, RetrieveOperation, StorageContext,
+        },
+    };
+
+    use super::{
+        event_report_request, storage_contexts, OperationState, Result, ScpServer, Session,
+        CANCEL_POLL_TIMEOUT,
+    };
+
+    /// The maximum size of the chunks
+    /// in which data sets are passed on to store handlers.
+    const DATA_SET_CHUNK_SIZE: usize = 16 * 1024;
+
+    /// How many chunks of a data set can be received
+    /// before the store handler reads them.
+    const DATA_SET_CHUNKS: usize = 4;
+
+    impl<A> ScpServer<A>
+    where
+        A: AccessControl + Send + Sync + 'static,
+    {
+        /// Accept and serve associations from the given listener
+        /// until a shutdown is requested,
+        /// each in a separate tokio task.
+        ///
+        /// Returns once all ongoing associations have ended.
+        /// Failures in individual associations are logged
+        /// and do not stop the server.
+        pub async fn serve_async(&self, listener: TcpListener) -> Result<()> {
+            #[cfg(all(feature = "tls", not(feature = "async-tls")))]
+            if self.options.has_tls_config() {
+                return super::AsyncTlsUnsupportedSnafu.fail();
+            }
+            let slots = Arc::new(Semaphore::new(self.max_associations));
+            let mut tasks = JoinSet::new();
+
+            loop {
+                let permit = tokio::select! {
+                    permit = slots.clone().acquire_owned() => permit.expect("semaphore is never closed"),
+                    _ = self.shutdown.requested() => break,
+                };
+                let (stream, peer_addr) = tokio::select! {
+                    connection = listener.accept() => match connection {
+                        Ok(connection) => connection,
+                        Err(e) => {
+                            warn!("Failed to accept connection: {}", e);
+                            continue;
+                        }
+                    },
+                    _ = self.shutdown.requested() => break,
+                };
+                // reap the tasks which have already ended
+                while tasks.try_join_next().is_some() {}
+
+                let server = self.task_handle();
+                tasks.spawn(async move {
+                    server.serve_connection_async(stream, peer_addr).await;
+                    drop(permit);
+                });
+            }
+
+            while tasks.join_next().await.is_some() {}
+            Ok(())
+        }
+
+        /// Establish an association over a new connection
+        /// and serve it until it ends, logging the outcome.
+        async fn serve_connection_async(&self, stream: TcpStream, peer_addr: SocketAddr) {
+            #[cfg(feature = "async-tls")]
+            if self.options.has_tls_config() {
+                let established = self.options.establish_tls_async(stream).await;
+                return self.serve_established_async(established, peer_addr).await;
+            }
+            let established = self.options.establish_async(stream).await;
+            self.serve_established_async(established, peer_addr).await
+        }
+
+        async fn serve_established_async<S>(
+            &self,
+            established: server::Result<AsyncServerAssociation<S>>,
+            peer_addr: SocketAddr,
+        ) where
+            S: AsyncRead + AsyncWrite + Unpin,
+        {
+            match established {
+                Ok(association) => {
+                    info!(
+                        "New association from {} ({})",
+                        association.client_ae_title(),
+                        peer_addr
+                    );
+                    if let Err(e) = self.serve_association_async(association).await {
+                        warn!(
+                            "Association with {} ended with an error: {}",
+                            peer_addr,
+                            snafu::Report::from_error(e)
+                        );
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        "Could not establish association with {}: {}",
+                        peer_addr,
+                        snafu::Report::from_error(e)
+                    );
+                }
+            }
+        }
+
+        /// Create a copy of the server for use in a separate task.
+        fn task_handle(&self) -> ScpServer<A> {
+            ScpServer {
+                options: self.options.clone(),
+                services: self.services.clone(),
+                max_associations: self.max_associations,
+                idle_timeout: self.idle_timeout,
+                shutdown: self.shutdown.clone(),
+            }
+        }
+    }
+
+    impl<A> ScpServer<A> {
+        /// Serve an association which was already established,
+        /// until it is released or aborted.
+        ///
+        /// This can be used to serve associations over other kinds of streams.
+        /// The handlers run in blocking tasks,
+        /// to which the data sets of C-STORE requests
+        /// are passed on as they are received.
+        /// The association end handler is notified once it ends.
+        pub async fn serve_association_async<S>(
+            &self,
+            association: AsyncServerAssociation<S>,
+        ) -> server::Result<()>
+        where
+            S: AsyncRead + AsyncWrite + Unpin,
+        {
+            let mut session = Session::new();
+            let calling_ae_title = association.client_ae_title().to_string();
+            let result = self.serve_session_async(association, &mut session).await;
+            let association_id = session.id;
+            // event reports without a response are no longer awaited
+            drop(session);
+            let services = self.services.clone();
+            join(tokio::task::spawn_blocking(move || {
+                services.association_ended(&AssociationContext {
+                    association_id,
+                    calling_ae_title: &calling_ae_title,
+                })
+            }))
+            .await;
+            result
+        }
+
+        async fn serve_session_async<S>(
+            &self,
+            mut association: AsyncServerAssociation<S>,
+            session: &mut Session,
+        ) -> server::Result<()>
+        where
+            S: AsyncRead + AsyncWrite + Unpin,
+        {
+            loop {
+                let idle = async {
+                    match self.idle_timeout {
+                        Some(timeout) => tokio::time::sleep(timeout).await,
+                        None => std::future::pending().await,
+                    }
+                };
+                let message = tokio::select! {
+                    biased;
+                    _ = self.shutdown.requested() => {
+                        debug!("Shutting down association with {}", association.client_ae_title());
+                        return self.shutdown_association_async(association, session).await;
+                    }
+                    _ = idle => {
+                        info!("Releasing idle association with {}", association.client_ae_title());
+                        return self.shutdown_association_async(association, session).await;
+                    }
+                    message = association.receive_command() => message?,
+                };
+                match message {
+                    Some(message) => {
+                        self.dispatch_async(&mut association, session, message)
+                            .await?
+                    }
+                    None => {
+                        info!(
+                            "Released association with {}",
+                            association.client_ae_title()
+                        );
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        /// Handle the message in transit, if any,
+        /// and then release the association.
+        async fn shutdown_association_async<S>(
+            &self,
+            mut association: AsyncServerAssociation<S>,
+            session: &mut Session,
+        ) -> server::Result<()>
+        where
+            S: AsyncRead + AsyncWrite + Unpin,
+        {
+            let artim_timeout = association.artim_timeout();
+            while let Ok(message) =
+                tokio::time::timeout(artim_timeout, association.receive_pending_command()).await
+            {
+                match message? {
+                    Some(message) => {
+                        self.dispatch_async(&mut association, session, message)
+                            .await?
+                    }
+                    None => break,
+                }
+            }
+            if association.state() != State::Established {
+                return Ok(());
+            }
+            association.release_with(super::unanswered).await
+        }
+
+        /// Dispatch a request received in an association to the services,
+        /// running the handler in a blocking task
+        /// and carrying out its operation on its behalf.
+        async fn dispatch_async<S>(
+            &self,
+            association: &mut AsyncServerAssociation<S>,
+            session: &mut Session,
+            message: MessageCommand,
+        ) -> server::Result<()>
+        where
+            S: AsyncRead + AsyncWrite + Unpin,
+        {
+            let MessageCommand {
+                presentation_context_id,
+                command,
+                has_data_set,
+            } = message;
+            let Some(context) = OwnedRequestContext::new(
+                session.id,
+                association.client_ae_title(),
+                association.presentation_contexts(),
+                association.abstract_syntax(presentation_context_id),
+                presentation_context_id,
+            ) else {
+                warn!(
+                    "Ignoring message in unknown presentation context {}",
+                    presentation_context_id
+                );
+                if has_data_set {
+                    discard_data_set(association, presentation_context_id).await?;
+                }
+                return Ok(());
+            };
+
+            let services = self.services.clone();
+            match command {
+                Command::CStoreRQ(rq) if has_data_set => {
+                    let (chunks, received) = mpsc::channel(DATA_SET_CHUNKS);
+                    let request = rq.clone();
+                    let handler = tokio::task::spawn_blocking(move || {
+                        let mut data = DataSetReader::new(received);
+                        services.store(&context.get(), &request, &mut data)
+                    });
+                    forward_data_set(
+                        association.receive_data_set(presentation_context_id),
+                        chunks,
+                    )
+                    .await?;
+                    let status = join(handler).await;
+                    association
+                        .send_message(presentation_context_id, &store_response(&rq, status), None)
+                        .await
+                }
+                Command::NEventReportRSP(rsp) => {
+                    if has_data_set {
+                        discard_data_set(association, presentation_context_id).await?;
+                    }
+                    session.on_event_report_response(&rsp);
+                    Ok(())
+                }
+                command => {
+                    let data = if has_data_set {
+                        let mut data = Vec::new();
+                        association
+                            .receive_data_set(presentation_context_id)
+                            .read_to_end(&mut data)
+                            .await
+                            .context(WireReadSnafu)?;
+                        Some(data)
+                    } else {
+                        None
+                    };
+                    let (calls, mut requested) = mpsc::channel(1);
+                    let proxy = OperationProxy {
+                        calls,
+                        storage_contexts: storage_contexts(
+                            association.presentation_contexts(),
+                            |id| association.abstract_syntax(id),
+                            |id| association.roles(id),
+                        ),
+                    };
+                    let request = command.clone();
+                    let handler = tokio::task::spawn_blocking(move || {
+                        let mut proxy = proxy;
+                        services.handle(&context.get(), &request, data.as_deref(), &mut proxy)
+                    });
+
+                    let mut operation = AsyncOperation {
+                        association,
+                        state: OperationState::new(&command, session),
+                        presentation_context_id,
+                    };
+                    // the calls end when the handler returns
+                    while let Some(call) = requested.recv().await {
+                        operation.call(call).await;
+                    }
+                    join(handler).await
+                }
+            }
+        }
+    }
+
+    /// Receive the data set of a message which is not handled.
+    async fn discard_data_set<S>(
+        association: &mut AsyncServerAssociation<S>,
+        presentation_context_id: u8,
+    ) -> server::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        tokio::io::copy(
+            &mut association.receive_data_set(presentation_context_id),
+            &mut tokio::io::sink(),
+        )
+        .await
+        .context(WireReadSnafu)?;
+        Ok(())
+    }
+
+    /// Wait for a handler running in a blocking task,
+    /// resuming its panic if it panicked.
+    async fn join<T>(handler: JoinHandle<T>) -> T {
+        match handler.await {
+            Ok(value) => value,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(e) => panic!("handler task did not run: {}", e),
+        }
+    }
+
+    /// Pass on a data set to a store handler as it is received.
+    ///
+    /// An empty chunk marks the end of the data set.
+    /// Once the handler stops reading,
+    /// the rest of the data set is still received and discarded.
+    async fn forward_data_set<R>(
+        mut data: R,
+        chunks: mpsc::Sender<std::io::Result<Vec<u8>>>,
+    ) -> server::Result<()>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut chunks = Some(chunks);
+        let mut buf = vec![0; DATA_SET_CHUNK_SIZE];
+        loop {
+            let received = data.read(&mut buf).await;
+            if let Some(sender) = &chunks {
+                let chunk = match &received {
+                    Ok(len) => Ok(buf[..*len].to_vec()),
+                    Err(e) => Err(std::io::Error::new(e.kind(), e.to_string())),
+                };
+                if sender.send(chunk).await.is_err() {
+                    chunks = None;
+                }
+            }
+            if received.context(WireReadSnafu)? == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// A reader of a data set being received in an asynchronous association,
+    /// for store handlers running in a blocking task.
+    struct DataSetReader {
+        chunks: mpsc::Receiver<std::io::Result<Vec<u8>>>,
+        chunk: Vec<u8>,
+        position: usize,
+        ended: bool,
+    }
+
+    impl DataSetReader {
+        fn new(chunks: mpsc::Receiver<std::io::Result<Vec<u8>>>) -> Self {
+            DataSetReader {
+                chunks,
+                chunk: Vec::new(),
+                position: 0,
+                ended: false,
+            }
+        }
+    }
+
+    impl Read for DataSetReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            while self.position == self.chunk.len() {
+                if self.ended {
+                    return Ok(0);
+                }
+                match self.chunks.blocking_recv() {
+                    Some(Ok(chunk)) => {
+                        self.ended = chunk.is_empty();
+                        self.chunk = chunk;
+                        self.position = 0;
+                    }
+                    Some(Err(e)) => return Err(e),
+                    // the association task stopped before the end of the data set
+                    None => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                }
+            }
+            let len = buf.len().min(self.chunk.len() - self.position);
+            buf[..len].copy_from_slice(&self.chunk[self.position..][..len]);
+            self.position += len;
+            Ok(len)
+        }
+    }
+
+    /// A call from a handler running in a blocking task
+    /// to the association task, with a channel for its result.
+    enum Call {
+        IsCanceled(oneshot::Sender<bool>),
+        SendMatch(Vec<u8>, oneshot::Sender<server::Result<()>>),
+        Report(SubOperations, oneshot::Sender<server::Result<()>>),
+        NextMessageId(oneshot::Sender<u16>),
+        Store(
+            u8,
+            CStoreRQ,
+            Vec<u8>,
+            oneshot::Sender<server::Result<Status>>,
+        ),
+        Respond(
+            Command,
+            Option<Vec<u8>>,
+            oneshot::Sender<server::Result<()>>,
+        ),
+        ReportEvent(
+            EventReport,
+            sync_mpsc::Sender<Status>,
+            oneshot::Sender<server::Result<()>>,
+        ),
+    }
+
+    /// The operation of a handler running in a blocking task,
+    /// which is carried out by the association task.
+    struct OperationProxy {
+        calls: mpsc::Sender<Call>,
+        storage_contexts: Vec<StorageContext>,
+    }
+
+    impl OperationProxy {
+        /// Make a call to the association task and wait for its result,
+        /// which is `None` if the task is no longer running.
+        fn call<T>(&self, call: impl FnOnce(oneshot::Sender<T>) -> Call) -> Option<T> {
+            let (result, received) = oneshot::channel();
+            self.calls.blocking_send(call(result)).ok()?;
+            received.blocking_recv().ok()
+        }
+    }
+
+    impl FindOperation for OperationProxy {
+        fn is_canceled(&mut self) -> bool {
+            self.call(Call::IsCanceled).unwrap_or(true)
+        }
+
+        fn send_match(&mut self, identifier: &[u8]) -> server::Result<()> {
+            self.call(|result| Call::SendMatch(identifier.to_vec(), result))
+                .unwrap_or(Err(server::Error::ConnectionClosed))
+        }
+    }
+
+    impl RetrieveOperation for OperationProxy {
+        fn is_canceled(&mut self) -> bool {
+            FindOperation::is_canceled(self)
+        }
+
+        fn report(&mut self, sub_operations: SubOperations) -> server::Result<()> {
+            self.call(|result| Call::Report(sub_operations, result))
+                .unwrap_or(Err(server::Error::ConnectionClosed))
+        }
+    }
+
+    impl GetOperation for OperationProxy {
+        fn storage_contexts(&self) -> Vec<StorageContext> {
+            self.storage_contexts.clone()
+        }
+
+        fn next_message_id(&mut self) -> u16 {
+            // the ID is of no use once the association is gone
+            self.call(Call::NextMessageId).unwrap_or_default()
+        }
+
+        fn store(
+            &mut self,
+            presentation_context_id: u8,
+            request: CStoreRQ,
+            data: &[u8],
+        ) -> server::Result<Status> {
+            self.call(|result| Call::Store(presentation_context_id, request, data.to_vec(), result))
+                .unwrap_or(Err(server::Error::ConnectionClosed))
+        }
+    }
+
+    impl Exchange for OperationProxy {
+        fn respond(&mut self, command: Command, data: Option<&[u8]>) -> server::Result<()> {
+            self.call(|result| Call::Respond(command, data.map(|data| data.to_vec()), result))
+                .unwrap_or(Err(server::Error::ConnectionClosed))
+        }
+
+        fn report_event(
+            &mut self,
+            report: EventReport,
+            response: sync_mpsc::Sender<Status>,
+        ) -> server::Result<()> {
+            self.call(|result| Call::ReportEvent(report, response, result))
+                .unwrap_or(Err(server::Error::ConnectionClosed))
+        }
+    }
+
+    /// A request being handled in an asynchronous association,
+    /// whose operation is carried out on behalf of its handler.
+    struct AsyncOperation<'a, S> {
+        association: &'a mut AsyncServerAssociation<S>,
+        state: OperationState<'a>,
+        presentation_context_id: u8,
+    }
+
+    impl<S> AsyncOperation<'_, S>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        /// Carry out a call from the handler.
+        async fn call(&mut self, call: Call) {
+            // the handler only stops waiting for the result if it panics
+            match call {
+                Call::IsCanceled(result) => {
+                    let _ = result.send(self.is_canceled().await);
+                }
+                Call::SendMatch(identifier, result) => {
+                    let command = self.state.match_response();
+                    let _ = result.send(self.send(&command, Some(&identifier)).await);
+                }
+                Call::Report(sub_operations, result) => {
+                    let command = self.state.progress_response(sub_operations);
+                    let _ = result.send(self.send(&command, None).await);
+                }
+                Call::NextMessageId(result) => {
+                    let _ = result.send(self.association.next_message_id());
+                }
+                Call::Store(presentation_context_id, request, data, result) => {
+                    let _ = result.send(self.store(presentation_context_id, request, &data).await);
+                }
+                Call::Respond(command, data, result) => {
+                    let _ = result.send(self.send(&command, data.as_deref()).await);
+                }
+                Call::ReportEvent(report, response, result) => {
+                    let message_id = self.association.next_message_id();
+                    let sent = self
+                        .send(
+                            &event_report_request(message_id, &report),
+                            report.data.as_deref(),
+                        )
+                        .await;
+                    if sent.is_ok() {
+                        self.state.on_event_report(message_id, response);
+                    }
+                    let _ = result.send(sent);
+                }
+            }
+        }
+
+        async fn is_canceled(&mut self) -> bool {
+            if self.state.is_over() {
+                return true;
+            }
+            let received =
+                tokio::time::timeout(CANCEL_POLL_TIMEOUT, self.association.receive_message()).await;
+            match received {
+                Ok(Ok(Some(message))) => self.state.on_message(message),
+                Ok(Ok(None)) => self.state.on_end(None),
+                Ok(Err(e)) => self.state.on_end(Some(e)),
+                Err(_) => {}
+            }
+            self.state.is_over()
+        }
+
+        /// Send a message through the presentation context of the request.
+        async fn send(&mut self, command: &Command, data: Option<&[u8]>) -> server::Result<()> {
+            let sent = self
+                .association
+                .send_message(self.presentation_context_id, command, data)
+                .await;
+            self.state.closed |= sent.is_err();
+            sent
+        }
+
+        /// Send a C-STORE request and wait for its response.
+        async fn store(
+            &mut self,
+            presentation_context_id: u8,
+            request: CStoreRQ,
+            data: &[u8],
+        ) -> server::Result<Status> {
+            let message_id = request.message_id;
+            if let Err(e) = self
+                .association
+                .send_message(
+                    presentation_context_id,
+                    &Command::CStoreRQ(request),
+                    Some(data),
+                )
+                .await
+            {
+                self.state.closed = true;
+                return Err(e);
+            }
+            loop {
+                match self.association.receive_message().await {
+                    Ok(Some(Message {
+                        command: Command::CStoreRSP(rsp),
+                        ..
+                    })) if rsp.message_id_being_responded_to == message_id => return Ok(rsp.status),
+                    Ok(Some(message)) => self.state.on_message(message),
+                    Ok(None) => {
+                        self.state.closed = true;
+                        return Err(server::Error::ConnectionClosed);
+                    }
+                    Err(e) => {
+                        self.state.closed = true;
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use dicom_ul::{
+    association::client::ClientAssociationOptions, association::server::ServerAssociationOptions,
+    dimse::Status, pdu::AsyncOperationsWindow,
+};
+
+use std::net::SocketAddr;
+
+mod common;
+
+use common::{echo_rq, echo_rsp, Result, IMPLICIT_VR_LE, VERIFICATION_SOP_CLASS};
+
+static SCU_AE_TITLE: &str = "ASYNC-OPS-SCU";
+static SCP_AE_TITLE: &str = "ASYNC-OPS-SCP";
+
+/// Spawn an SCP which admits up to 4 outstanding operations,
+/// only responding to C-ECHO requests after receiving 3 of them.
+fn spawn_scp() -> Result<(std::thread::JoinHandle<Result<()>>, SocketAddr)> {
+    let listener = std::net::TcpListener::bind("localhost:0")?;
+    let addr = listener.local_addr()?;
+    let scp = ServerAssociationOptions::new()
+        .accept_called_ae_title()
+        .ae_title(SCP_AE_TITLE)
+        .with_abstract_syntax(VERIFICATION_SOP_CLASS)
+        .async_operations_window(4, 1);
+
+    let h = std::thread::spawn(move || -> Result<()> {
+        let (stream, _addr) = listener.accept()?;
+        let mut association = scp.establish(stream)?;
+
+        assert_eq!(
+            association.async_operations_window(),
+            AsyncOperationsWindow {
+                max_operations_invoked: 3,
+                max_operations_performed: 1,
+            }
+        );
+
+        let mut message_ids = Vec::new();
+        for _ in 0..3 {
+            let message = association.receive_message()?.expect("expected C-ECHO-RQ");
+            message_ids.push(message.command.message_id().expect("expected a request"));
+        }
+        assert_eq!(message_ids, vec![1, 2, 3]);
+
+        // respond in reverse order
+        for message_id in message_ids.into_iter().rev() {
+            association.send_message(1, &echo_rsp(message_id), None)?;
+        }
+
+        // one more request
+        let message = association.receive_message()?.expect("expected C-ECHO-RQ");
+        assert_eq!(message.command.message_id(), Some(4));
+        association.send_message(1, &echo_rsp(4), None)?;
+
+        // release
+        assert_eq!(association.receive_message()?, None);
+
+        Ok(())
+    });
+    Ok((h, addr))
+}
+
+/// Send multiple C-ECHO requests without waiting for their responses.
+#[test]
+fn scu_scp_async_operations() {
+    let (scp_handle, scp_addr) = spawn_scp().unwrap();
+
+    let mut association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .async_operations_window(3, 1)
+        .establish(scp_addr)
+        .unwrap();
+
+    assert_eq!(
+        association.async_operations_window(),
+        AsyncOperationsWindow {
+            max_operations_invoked: 3,
+            max_operations_performed: 1,
+        }
+    );
+
+    for _ in 0..3 {
+        let message_id = association.next_message_id();
+        association
+            .send_request(1, &echo_rq(message_id), None)
+            .unwrap();
+    }
+    assert_eq!(association.outstanding_requests(), &[1, 2, 3]);
+
+    // the window is full,
+    // so this waits for the first response to arrive
+    let message_id = association.next_message_id();
+    association
+        .send_request(1, &echo_rq(message_id), None)
+        .unwrap();
+    assert_eq!(association.outstanding_requests(), &[1, 2, 4]);
+
+    for message_id in 1..=4 {
+        let response = association.receive_response(message_id).unwrap();
+        assert_eq!(
+            response.command.message_id_being_responded_to(),
+            Some(message_id)
+        );
+        assert_eq!(response.command.status(), Some(Status::SUCCESS));
+    }
+    assert!(association.outstanding_requests().is_empty());
+
+    association
+        .release()
+        .expect("did not have a peaceful release");
+
+    scp_handle
+        .join()
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
+
+/// Without a window proposal,
+/// only one operation may be outstanding at a time.
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread")]
+async fn scu_scp_default_async_operations_window() {
+    let listener = tokio::net::TcpListener::bind("localhost:0").await.unwrap();
+    let scp_addr = listener.local_addr().unwrap();
+    let scp = ServerAssociationOptions::new()
+        .accept_called_ae_title()
+        .ae_title(SCP_AE_TITLE)
+        .with_abstract_syntax(VERIFICATION_SOP_CLASS)
+        .async_operations_window(0, 0);
+
+    let scp_handle = tokio::spawn(async move {
+        let (stream, _addr) = listener.accept().await?;
+        let mut association = scp.establish_async(stream).await?;
+        assert_eq!(
+            association.async_operations_window(),
+            AsyncOperationsWindow::default()
+        );
+
+        for message_id in 1..=2 {
+            let message = association
+                .receive_message()
+                .await?
+                .expect("expected C-ECHO-RQ");
+            assert_eq!(message.command.message_id(), Some(message_id));
+            association
+                .send_message(1, &echo_rsp(message_id), None)
+                .await?;
+        }
+
+        // release
+        assert_eq!(association.receive_message().await?, None);
+
+        Result::Ok(())
+    });
+
+    let mut association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .establish_async(scp_addr)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        association.async_operations_window(),
+        AsyncOperationsWindow::default()
+    );
+
+    association
+        .send_request(1, &echo_rq(1), None)
+        .await
+        .unwrap();
+    // waits for the response to the first request
+    association
+        .send_request(1, &echo_rq(2), None)
+        .await
+        .unwrap();
+    assert_eq!(association.outstanding_requests(), &[2]);
+
+    let response = association.receive_message().await.unwrap();
+    assert_eq!(response.command.message_id_being_responded_to(), Some(1));
+    let response = association.receive_response(2).await.unwrap();
+    assert_eq!(response.command.message_id_being_responded_to(), Some(2));
+    assert!(association.outstanding_requests().is_empty());
+
+    association
+        .release()
+        .await
+        .expect("did not have a peaceful release");
+
+    scp_handle
+        .await
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
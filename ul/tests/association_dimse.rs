@@ -0,0 +1,373 @@
+use dicom_ul::{
+    association::client::ClientAssociationOptions,
+    association::server::ServerAssociationOptions,
+    dimse::{CEchoRQ, CEchoRSP, CStoreRQ, CStoreRSP, Command, MessageCommand, Priority, Status},
+};
+
+use std::{io::Read, net::SocketAddr};
+
+mod common;
+
+use common::{
+    data_set, store_rq, Result, CT_IMAGE_STORAGE_SOP_CLASS, IMPLICIT_VR_LE, SOP_INSTANCE_UID,
+    VERIFICATION_SOP_CLASS,
+};
+
+static SCU_AE_TITLE: &str = "DIMSE-SCU";
+static SCP_AE_TITLE: &str = "DIMSE-SCP";
+
+fn spawn_scp() -> Result<(std::thread::JoinHandle<Result<()>>, SocketAddr)> {
+    let listener = std::net::TcpListener::bind("localhost:0")?;
+    let addr = listener.local_addr()?;
+    let scp = ServerAssociationOptions::new()
+        .accept_called_ae_title()
+        .ae_title(SCP_AE_TITLE)
+        .with_abstract_syntax(VERIFICATION_SOP_CLASS)
+        .with_abstract_syntax(CT_IMAGE_STORAGE_SOP_CLASS);
+
+    let h = std::thread::spawn(move || -> Result<()> {
+        let (stream, _addr) = listener.accept()?;
+        let mut association = scp.establish(stream)?;
+
+        // C-ECHO
+        let message = association.receive_message()?.expect("expected C-ECHO-RQ");
+        assert_eq!(message.presentation_context_id, 1);
+        assert_eq!(message.data, None);
+        let Command::CEchoRQ(rq) = message.command else {
+            panic!("unexpected command {:?}", message.command);
+        };
+        association.send_message(
+            message.presentation_context_id,
+            &Command::CEchoRSP(CEchoRSP {
+                message_id_being_responded_to: rq.message_id,
+                affected_sop_class_uid: rq.affected_sop_class_uid,
+                status: Status::SUCCESS,
+            }),
+            None,
+        )?;
+
+        // C-STORE
+        let message = association.receive_message()?.expect("expected C-STORE-RQ");
+        assert_eq!(message.presentation_context_id, 3);
+        assert_eq!(message.data, Some(data_set()));
+        let Command::CStoreRQ(rq) = message.command else {
+            panic!("unexpected command {:?}", message.command);
+        };
+        assert_eq!(rq.affected_sop_instance_uid, SOP_INSTANCE_UID);
+        association.send_message(
+            message.presentation_context_id,
+            &Command::CStoreRSP(CStoreRSP {
+                message_id_being_responded_to: rq.message_id,
+                affected_sop_class_uid: rq.affected_sop_class_uid,
+                affected_sop_instance_uid: rq.affected_sop_instance_uid,
+                status: Status::SUCCESS,
+            }),
+            None,
+        )?;
+
+        // release
+        assert_eq!(association.receive_message()?, None);
+
+        Ok(())
+    });
+    Ok((h, addr))
+}
+
+#[cfg(feature = "async")]
+async fn spawn_scp_async() -> Result<(tokio::task::JoinHandle<Result<()>>, SocketAddr)> {
+    let listener = tokio::net::TcpListener::bind("localhost:0").await?;
+    let addr = listener.local_addr()?;
+    let scp = ServerAssociationOptions::new()
+        .accept_called_ae_title()
+        .ae_title(SCP_AE_TITLE)
+        .with_abstract_syntax(VERIFICATION_SOP_CLASS)
+        .with_abstract_syntax(CT_IMAGE_STORAGE_SOP_CLASS);
+
+    let h = tokio::spawn(async move {
+        let (stream, _addr) = listener.accept().await?;
+        let mut association = scp.establish_async(stream).await?;
+
+        // C-STORE
+        let message = association
+            .receive_message()
+            .await?
+            .expect("expected C-STORE-RQ");
+        assert_eq!(message.data, Some(data_set()));
+        let Command::CStoreRQ(rq) = message.command else {
+            panic!("unexpected command {:?}", message.command);
+        };
+        association
+            .send_message(
+                message.presentation_context_id,
+                &Command::CStoreRSP(CStoreRSP {
+                    message_id_being_responded_to: rq.message_id,
+                    affected_sop_class_uid: rq.affected_sop_class_uid,
+                    affected_sop_instance_uid: rq.affected_sop_instance_uid,
+                    status: Status(0xB000),
+                }),
+                None,
+            )
+            .await?;
+
+        // release
+        assert_eq!(association.receive_message().await?, None);
+
+        Ok(())
+    });
+    Ok((h, addr))
+}
+
+/// Receive the data sets of C-STORE requests
+/// separately from their command sets.
+#[test]
+fn scu_scp_dimse_stream_data_set() {
+    let listener = std::net::TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let scp_handle = std::thread::spawn(move || -> Result<()> {
+        let (stream, _addr) = listener.accept()?;
+        let mut association = ServerAssociationOptions::new()
+            .ae_title(SCP_AE_TITLE)
+            .with_abstract_syntax(CT_IMAGE_STORAGE_SOP_CLASS)
+            .establish(stream)?;
+
+        // a small data set in the same PDU as the command,
+        // then a data set spanning multiple PDUs
+        for expected in [vec![0x55; 64], data_set()] {
+            let MessageCommand {
+                presentation_context_id,
+                command,
+                has_data_set,
+            } = association.receive_command()?.expect("expected C-STORE-RQ");
+            assert!(has_data_set);
+            let Command::CStoreRQ(rq) = command else {
+                panic!("unexpected command {:?}", command);
+            };
+            let mut data = Vec::new();
+            association
+                .receive_data_set(presentation_context_id)
+                .read_to_end(&mut data)?;
+            assert_eq!(data, expected);
+
+            association.send_message(
+                presentation_context_id,
+                &Command::CStoreRSP(CStoreRSP {
+                    message_id_being_responded_to: rq.message_id,
+                    affected_sop_class_uid: rq.affected_sop_class_uid,
+                    affected_sop_instance_uid: rq.affected_sop_instance_uid,
+                    status: Status::SUCCESS,
+                }),
+                None,
+            )?;
+        }
+
+        // release
+        assert_eq!(association.receive_command()?, None);
+        Ok(())
+    });
+
+    let mut association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(CT_IMAGE_STORAGE_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .establish(addr)
+        .unwrap();
+
+    for data in [vec![0x55; 64], data_set()] {
+        let message_id = association.next_message_id();
+        association
+            .send_message(1, &store_rq(message_id), Some(&data))
+            .unwrap();
+        let response = association.receive_message().unwrap();
+        assert_eq!(
+            response.command.message_id_being_responded_to(),
+            Some(message_id)
+        );
+        assert_eq!(response.command.status(), Some(Status::SUCCESS));
+    }
+
+    association
+        .release()
+        .expect("did not have a peaceful release");
+
+    scp_handle
+        .join()
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread")]
+async fn scu_scp_dimse_stream_data_set_async() {
+    use tokio::io::AsyncReadExt;
+
+    let listener = tokio::net::TcpListener::bind("localhost:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let scp_handle = tokio::spawn(async move {
+        let (stream, _addr) = listener.accept().await?;
+        let mut association = ServerAssociationOptions::new()
+            .ae_title(SCP_AE_TITLE)
+            .with_abstract_syntax(CT_IMAGE_STORAGE_SOP_CLASS)
+            .establish_async(stream)
+            .await?;
+
+        let message = association
+            .receive_command()
+            .await?
+            .expect("expected C-STORE-RQ");
+        assert!(message.has_data_set);
+        let mut data = Vec::new();
+        association
+            .receive_data_set(message.presentation_context_id)
+            .read_to_end(&mut data)
+            .await?;
+        assert_eq!(data, data_set());
+
+        let Command::CStoreRQ(rq) = message.command else {
+            panic!("unexpected command {:?}", message.command);
+        };
+        association
+            .send_message(
+                message.presentation_context_id,
+                &Command::CStoreRSP(CStoreRSP {
+                    message_id_being_responded_to: rq.message_id,
+                    affected_sop_class_uid: rq.affected_sop_class_uid,
+                    affected_sop_instance_uid: rq.affected_sop_instance_uid,
+                    status: Status::SUCCESS,
+                }),
+                None,
+            )
+            .await?;
+
+        // release
+        assert_eq!(association.receive_command().await?, None);
+        Result::Ok(())
+    });
+
+    let mut association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(CT_IMAGE_STORAGE_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .establish_async(addr)
+        .await
+        .unwrap();
+
+    let message_id = association.next_message_id();
+    association
+        .send_message(1, &store_rq(message_id), Some(&data_set()))
+        .await
+        .unwrap();
+    let response = association.receive_message().await.unwrap();
+    assert_eq!(response.command.status(), Some(Status::SUCCESS));
+
+    association
+        .release()
+        .await
+        .expect("did not have a peaceful release");
+
+    scp_handle
+        .await
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
+
+/// Send a C-ECHO and a C-STORE request through typed DIMSE messages.
+#[test]
+fn scu_scp_dimse_test() {
+    let (scp_handle, scp_addr) = spawn_scp().unwrap();
+
+    let mut association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .with_presentation_context(CT_IMAGE_STORAGE_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .establish(scp_addr)
+        .unwrap();
+
+    let message_id = association.next_message_id();
+    association
+        .send_message(
+            1,
+            &Command::CEchoRQ(CEchoRQ {
+                message_id,
+                affected_sop_class_uid: VERIFICATION_SOP_CLASS.to_string(),
+            }),
+            None,
+        )
+        .unwrap();
+    let response = association.receive_message().unwrap();
+    assert_eq!(response.command.message_id_being_responded_to(), Some(1));
+    assert_eq!(response.command.status(), Some(Status::SUCCESS));
+
+    let message_id = association.next_message_id();
+    assert_eq!(message_id, 2);
+    association
+        .send_message(
+            3,
+            &Command::CStoreRQ(CStoreRQ {
+                message_id,
+                affected_sop_class_uid: CT_IMAGE_STORAGE_SOP_CLASS.to_string(),
+                affected_sop_instance_uid: SOP_INSTANCE_UID.to_string(),
+                priority: Priority::Medium,
+                move_originator_ae_title: None,
+                move_originator_message_id: None,
+            }),
+            Some(&data_set()),
+        )
+        .unwrap();
+    let response = association.receive_message().unwrap();
+    assert_eq!(response.command.message_id_being_responded_to(), Some(2));
+    assert_eq!(response.command.status(), Some(Status::SUCCESS));
+
+    association
+        .release()
+        .expect("did not have a peaceful release");
+
+    scp_handle
+        .join()
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread")]
+async fn scu_scp_dimse_test_async() {
+    let (scp_handle, scp_addr) = spawn_scp_async().await.unwrap();
+
+    let mut association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(CT_IMAGE_STORAGE_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .establish_async(scp_addr)
+        .await
+        .unwrap();
+
+    let message_id = association.next_message_id();
+    association
+        .send_message(
+            1,
+            &Command::CStoreRQ(CStoreRQ {
+                message_id,
+                affected_sop_class_uid: CT_IMAGE_STORAGE_SOP_CLASS.to_string(),
+                affected_sop_instance_uid: SOP_INSTANCE_UID.to_string(),
+                priority: Priority::Medium,
+                move_originator_ae_title: None,
+                move_originator_message_id: None,
+            }),
+            Some(&data_set()),
+        )
+        .await
+        .unwrap();
+    let response = association.receive_message().await.unwrap();
+    assert_eq!(response.command.message_id_being_responded_to(), Some(1));
+    assert!(response.command.status().unwrap().is_warning());
+
+    association
+        .release()
+        .await
+        .expect("did not have a peaceful release");
+
+    scp_handle
+        .await
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
@@ -0,0 +1,135 @@
+//! Full SCU/SCP conversations over in-process streams,
+//! without binding any ports.
+use dicom_ul::{
+    association::client::ClientAssociationOptions,
+    association::server::ServerAssociationOptions,
+    association::state::State,
+    dimse::{Command, Status},
+};
+
+mod common;
+
+use common::{data_set, echo_rq, respond, store_rq, Result};
+
+static SCU_AE_TITLE: &str = "DUPLEX-SCU";
+static SCP_AE_TITLE: &str = "DUPLEX-SCP";
+
+fn scp_options() -> ServerAssociationOptions<'static, dicom_ul::association::server::AcceptAny> {
+    common::scp_options(SCP_AE_TITLE)
+}
+
+fn scu_options() -> ClientAssociationOptions<'static> {
+    common::scu_options(SCU_AE_TITLE, SCP_AE_TITLE)
+}
+
+#[cfg(unix)]
+#[test]
+fn scu_scp_over_unix_socket_pair() {
+    let (scu_stream, scp_stream) = std::os::unix::net::UnixStream::pair().unwrap();
+
+    let scp_handle = std::thread::spawn(move || -> Result<()> {
+        let mut association = scp_options().establish_stream(scp_stream)?;
+        assert_eq!(association.client_ae_title(), SCU_AE_TITLE);
+        while let Some(message) = association.receive_message()? {
+            if let Command::CStoreRQ(_) = message.command {
+                assert_eq!(message.data, Some(data_set()));
+            }
+            let response = respond(message.command);
+            association.send_message(message.presentation_context_id, &response, None)?;
+        }
+        assert_eq!(association.state(), State::Idle);
+        Ok(())
+    });
+
+    let mut association = scu_options().establish_stream(scu_stream).unwrap();
+    assert_eq!(association.state(), State::Established);
+
+    let message_id = association.next_message_id();
+    association
+        .send_message(1, &echo_rq(message_id), None)
+        .unwrap();
+    let response = association.receive_message().unwrap();
+    assert_eq!(
+        response.command.message_id_being_responded_to(),
+        Some(message_id)
+    );
+    assert_eq!(response.command.status(), Some(Status::SUCCESS));
+
+    let message_id = association.next_message_id();
+    association
+        .send_message(3, &store_rq(message_id), Some(&data_set()))
+        .unwrap();
+    let response = association.receive_message().unwrap();
+    assert_eq!(
+        response.command.message_id_being_responded_to(),
+        Some(message_id)
+    );
+    assert_eq!(response.command.status(), Some(Status::SUCCESS));
+
+    association
+        .release()
+        .expect("did not have a peaceful release");
+
+    scp_handle
+        .join()
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread")]
+async fn scu_scp_over_duplex_stream_async() {
+    let (scu_stream, scp_stream) = tokio::io::duplex(64 * 1024);
+
+    let scp_handle = tokio::spawn(async move {
+        let mut association = scp_options().establish_stream_async(scp_stream).await?;
+        assert_eq!(association.client_ae_title(), SCU_AE_TITLE);
+        while let Some(message) = association.receive_message().await? {
+            if let Command::CStoreRQ(_) = message.command {
+                assert_eq!(message.data, Some(data_set()));
+            }
+            let response = respond(message.command);
+            association
+                .send_message(message.presentation_context_id, &response, None)
+                .await?;
+        }
+        assert_eq!(association.state(), State::Idle);
+        Result::Ok(())
+    });
+
+    let mut association = scu_options()
+        .establish_stream_async(scu_stream)
+        .await
+        .unwrap();
+    assert_eq!(association.state(), State::Established);
+
+    let message_id = association.next_message_id();
+    association
+        .send_message(1, &echo_rq(message_id), None)
+        .await
+        .unwrap();
+    let response = association.receive_message().await.unwrap();
+    assert_eq!(response.command.status(), Some(Status::SUCCESS));
+
+    let message_id = association.next_message_id();
+    association
+        .send_message(3, &store_rq(message_id), Some(&data_set()))
+        .await
+        .unwrap();
+    let response = association.receive_message().await.unwrap();
+    assert_eq!(
+        response.command.message_id_being_responded_to(),
+        Some(message_id)
+    );
+    assert_eq!(response.command.status(), Some(Status::SUCCESS));
+
+    association
+        .release()
+        .await
+        .expect("did not have a peaceful release");
+
+    scp_handle
+        .await
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
@@ -1,12 +1,17 @@
+#![cfg(any(feature = "async", feature = "mock"))]
+#[cfg(feature = "mock")]
+use dicom_ul::mock::{MockEvent, MockScp};
 use dicom_ul::{
     association::client::ClientAssociationOptions,
-    pdu::{Pdu, PresentationContextResult, PresentationContextResultReason},
+    pdu::{PresentationContextResult, PresentationContextResultReason},
 };
+#[cfg(feature = "async")]
+use dicom_ul::{association::server::ServerAssociationOptions, pdu::Pdu};
 
+#[cfg(feature = "async")]
 use std::net::SocketAddr;
 
-use dicom_ul::association::server::ServerAssociationOptions;
-
+#[cfg(feature = "async")]
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
 
 static SCU_AE_TITLE: &str = "ECHO-SCU";
@@ -18,44 +23,6 @@ static JPEG_BASELINE: &str = "1.2.840.10008.1.2.4.50";
 static VERIFICATION_SOP_CLASS: &str = "1.2.840.10008.1.1";
 static DIGITAL_MG_STORAGE_SOP_CLASS: &str = "1.2.840.10008.5.1.4.1.1.1.2";
 
-fn spawn_scp() -> Result<(std::thread::JoinHandle<Result<()>>, SocketAddr)> {
-    let listener = std::net::TcpListener::bind("localhost:0")?;
-    let addr = listener.local_addr()?;
-    let scp = ServerAssociationOptions::new()
-        .accept_called_ae_title()
-        .ae_title(SCP_AE_TITLE)
-        .with_abstract_syntax(VERIFICATION_SOP_CLASS);
-
-    let h = std::thread::spawn(move || -> Result<()> {
-        let (stream, _addr) = listener.accept()?;
-        let mut association = scp.establish(stream)?;
-
-        assert_eq!(
-            association.presentation_contexts(),
-            &[
-                PresentationContextResult {
-                    id: 1,
-                    reason: PresentationContextResultReason::Acceptance,
-                    transfer_syntax: IMPLICIT_VR_LE.to_string(),
-                },
-                PresentationContextResult {
-                    id: 3,
-                    reason: PresentationContextResultReason::AbstractSyntaxNotSupported,
-                    transfer_syntax: IMPLICIT_VR_LE.to_string(),
-                }
-            ],
-        );
-
-        // handle one release request
-        let pdu = association.receive()?;
-        assert_eq!(pdu, Pdu::ReleaseRQ);
-        association.send(&Pdu::ReleaseRP)?;
-
-        Ok(())
-    });
-    Ok((h, addr))
-}
-
 #[cfg(feature = "async")]
 async fn spawn_scp_async() -> Result<(tokio::task::JoinHandle<Result<()>>, SocketAddr)> {
     let listener = tokio::net::TcpListener::bind("localhost:0").await?;
@@ -95,10 +62,15 @@ async fn spawn_scp_async() -> Result<(tokio::task::JoinHandle<Result<()>>, Socke
     Ok((h, addr))
 }
 
-/// Run an SCP and an SCU concurrently, negotiate an association and release it.
+/// Negotiate an association with a mock SCP and release it.
+#[cfg(feature = "mock")]
 #[test]
 fn scu_scp_association_test() {
-    let (scp_handle, scp_addr) = spawn_scp().unwrap();
+    let scp = MockScp::new()
+        .ae_title(SCP_AE_TITLE)
+        .with_abstract_syntax(VERIFICATION_SOP_CLASS)
+        .spawn()
+        .unwrap();
 
     let association = ClientAssociationOptions::new()
         .calling_ae_title(SCU_AE_TITLE)
@@ -108,17 +80,31 @@ fn scu_scp_association_test() {
             DIGITAL_MG_STORAGE_SOP_CLASS,
             vec![IMPLICIT_VR_LE, EXPLICIT_VR_LE, JPEG_BASELINE],
         )
-        .establish(scp_addr)
+        .establish(scp.addr())
         .unwrap();
 
+    assert_eq!(
+        association.presentation_contexts(),
+        &[PresentationContextResult {
+            id: 1,
+            reason: PresentationContextResultReason::Acceptance,
+            transfer_syntax: IMPLICIT_VR_LE.to_string(),
+        }],
+    );
+
     association
         .release()
         .expect("did not have a peaceful release");
 
-    scp_handle
-        .join()
-        .expect("SCP panicked")
-        .expect("Error at the SCP");
+    assert_eq!(
+        scp.shutdown(),
+        vec![
+            MockEvent::Established {
+                calling_ae_title: SCU_AE_TITLE.to_string(),
+            },
+            MockEvent::Released,
+        ],
+    );
 }
 
 #[cfg(feature = "async")]
@@ -0,0 +1,126 @@
+use dicom_ul::{
+    association::client::ClientAssociationOptions,
+    pdu::{
+        Pdu, PresentationContextResult, PresentationContextResultReason,
+        SopClassCommonExtendedNegotiation, UserVariableItem,
+    },
+};
+
+use std::net::SocketAddr;
+
+use dicom_ul::association::server::ServerAssociationOptions;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+static SCU_AE_TITLE: &str = "STORE-SCU";
+static SCP_AE_TITLE: &str = "STORE-SCP";
+
+static IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+static STORAGE_SERVICE_CLASS: &str = "1.2.840.10008.4.2";
+static CT_IMAGE_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.2";
+static MR_IMAGE_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.4";
+static PRIVATE_CT_STORAGE: &str = "1.3.6.1.4.1.9590.100.1.2.1";
+static PRIVATE_MR_STORAGE: &str = "1.3.6.1.4.1.9590.100.1.2.2";
+
+fn spawn_scp() -> Result<(std::thread::JoinHandle<Result<()>>, SocketAddr)> {
+    let listener = std::net::TcpListener::bind("localhost:0")?;
+    let addr = listener.local_addr()?;
+    let scp = ServerAssociationOptions::new()
+        .accept_called_ae_title()
+        .ae_title(SCP_AE_TITLE)
+        .with_abstract_syntax(CT_IMAGE_STORAGE)
+        .accept_related_general_sop_classes(true);
+
+    let h = std::thread::spawn(move || -> Result<()> {
+        let (stream, _addr) = listener.accept()?;
+        let mut association = scp.establish(stream)?;
+
+        assert_eq!(
+            association.presentation_contexts(),
+            &[
+                PresentationContextResult {
+                    id: 1,
+                    reason: PresentationContextResultReason::Acceptance,
+                    transfer_syntax: IMPLICIT_VR_LE.to_string(),
+                },
+                // MR image storage is not supported
+                PresentationContextResult {
+                    id: 3,
+                    reason: PresentationContextResultReason::AbstractSyntaxNotSupported,
+                    transfer_syntax: IMPLICIT_VR_LE.to_string(),
+                },
+            ],
+        );
+        assert_eq!(association.abstract_syntax(1), Some(PRIVATE_CT_STORAGE));
+
+        let negotiations: Vec<_> = association
+            .user_variables()
+            .iter()
+            .filter_map(|item| match item {
+                UserVariableItem::SopClassCommonExtendedNegotiationSubItem(negotiation) => {
+                    Some(negotiation)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            negotiations,
+            vec![
+                &SopClassCommonExtendedNegotiation {
+                    sop_class_uid: PRIVATE_CT_STORAGE.to_string(),
+                    service_class_uid: STORAGE_SERVICE_CLASS.to_string(),
+                    related_general_sop_class_uids: vec![CT_IMAGE_STORAGE.to_string()],
+                },
+                &SopClassCommonExtendedNegotiation {
+                    sop_class_uid: PRIVATE_MR_STORAGE.to_string(),
+                    service_class_uid: STORAGE_SERVICE_CLASS.to_string(),
+                    related_general_sop_class_uids: vec![MR_IMAGE_STORAGE.to_string()],
+                },
+            ]
+        );
+
+        // handle one release request
+        let pdu = association.receive()?;
+        assert_eq!(pdu, Pdu::ReleaseRQ);
+        association.send(&Pdu::ReleaseRP)?;
+
+        Ok(())
+    });
+    Ok((h, addr))
+}
+
+/// Accept a private SOP class by its related general SOP class.
+#[test]
+fn scu_scp_common_extended_negotiation() {
+    let (scp_handle, scp_addr) = spawn_scp().unwrap();
+
+    let association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(PRIVATE_CT_STORAGE, vec![IMPLICIT_VR_LE])
+        .with_presentation_context(PRIVATE_MR_STORAGE, vec![IMPLICIT_VR_LE])
+        .with_sop_class_common_extended_negotiation(
+            PRIVATE_CT_STORAGE,
+            STORAGE_SERVICE_CLASS,
+            vec![CT_IMAGE_STORAGE],
+        )
+        .with_sop_class_common_extended_negotiation(
+            PRIVATE_MR_STORAGE,
+            STORAGE_SERVICE_CLASS,
+            vec![MR_IMAGE_STORAGE],
+        )
+        .establish(scp_addr)
+        .unwrap();
+
+    assert_eq!(association.presentation_contexts().len(), 1);
+    assert_eq!(association.abstract_syntax(1), Some(PRIVATE_CT_STORAGE));
+
+    association
+        .release()
+        .expect("did not have a peaceful release");
+
+    scp_handle
+        .join()
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
@@ -0,0 +1,131 @@
+//! Observation and capture of the PDUs exchanged in an association.
+use dicom_ul::{
+    association::observer::{CaptureReader, CaptureWriter, Direction, PduRecord},
+    dimse::{CStoreRQ, CStoreRSP, Command, Priority, Status},
+    pdu::Pdu,
+    ClientAssociationOptions, ServerAssociationOptions,
+};
+use std::{
+    net::TcpListener,
+    sync::{Arc, Mutex},
+};
+
+static IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+static CT_IMAGE_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.2";
+
+/// A short description of a PDU, for comparing exchanges.
+fn kind(pdu: &Pdu) -> &'static str {
+    match pdu {
+        Pdu::AssociationRQ(_) => "A-ASSOCIATE-RQ",
+        Pdu::AssociationAC(_) => "A-ASSOCIATE-AC",
+        Pdu::PData { .. } => "P-DATA-TF",
+        Pdu::ReleaseRQ => "A-RELEASE-RQ",
+        Pdu::ReleaseRP => "A-RELEASE-RP",
+        _ => "other",
+    }
+}
+
+#[test]
+fn observe_and_capture_association() {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let scu_handle = std::thread::spawn(move || {
+        let observed = Arc::new(Mutex::new(Vec::new()));
+        let observer = observed.clone();
+        let mut association = ClientAssociationOptions::new()
+            .with_presentation_context(CT_IMAGE_STORAGE, vec![IMPLICIT_VR_LE])
+            .max_pdu_length(16_384)
+            .pdu_observer(Arc::new(move |record: &PduRecord<'_>| {
+                let pdu = record.pdu().unwrap();
+                observer
+                    .lock()
+                    .unwrap()
+                    .push((record.direction, kind(&pdu)));
+            }))
+            .establish(addr)
+            .unwrap();
+
+        let store = Command::CStoreRQ(CStoreRQ {
+            message_id: association.next_message_id(),
+            affected_sop_class_uid: CT_IMAGE_STORAGE.to_string(),
+            affected_sop_instance_uid: "2.25.1234".to_string(),
+            priority: Priority::Medium,
+            move_originator_ae_title: None,
+            move_originator_message_id: None,
+        });
+        association
+            .send_message(1, &store, Some(&[0x55; 40_000]))
+            .unwrap();
+        let response = association.receive_message().unwrap();
+        assert_eq!(response.command.status(), Some(Status::SUCCESS));
+        association.release().unwrap();
+
+        let observed = observed.lock().unwrap().clone();
+        observed
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    let capture = Arc::new(CaptureWriter::new(Vec::new()).unwrap());
+    let mut association = ServerAssociationOptions::new()
+        .with_abstract_syntax(CT_IMAGE_STORAGE)
+        .max_pdu_length(16_384)
+        .pdu_observer(capture.clone())
+        .establish(stream)
+        .unwrap();
+    let message = association.receive_message().unwrap().unwrap();
+    assert_eq!(message.data.as_deref(), Some(&[0x55; 40_000][..]));
+    let response = Command::CStoreRSP(CStoreRSP {
+        message_id_being_responded_to: 1,
+        affected_sop_class_uid: CT_IMAGE_STORAGE.to_string(),
+        affected_sop_instance_uid: "2.25.1234".to_string(),
+        status: Status::SUCCESS,
+    });
+    association
+        .send_message(message.presentation_context_id, &response, None)
+        .unwrap();
+    assert_eq!(association.receive_message().unwrap(), None);
+    drop(association);
+
+    let scu_observed = scu_handle.join().unwrap();
+    let capture = Arc::try_unwrap(capture).unwrap().into_inner();
+    let scp_observed: Vec<_> = CaptureReader::new(&capture[..])
+        .unwrap()
+        .map(|captured| {
+            let captured = captured.unwrap();
+            (captured.direction, kind(&captured.pdu().unwrap()))
+        })
+        .collect();
+
+    // the exchange is seen the same way from both sides,
+    // with the data set split into several P-Data PDUs
+    let flip = |(direction, kind): (Direction, &'static str)| {
+        let direction = match direction {
+            Direction::Sent => Direction::Received,
+            Direction::Received => Direction::Sent,
+        };
+        (direction, kind)
+    };
+    assert_eq!(
+        scp_observed,
+        scu_observed.iter().copied().map(flip).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        scu_observed.first(),
+        Some(&(Direction::Sent, "A-ASSOCIATE-RQ"))
+    );
+    assert_eq!(scu_observed[1], (Direction::Received, "A-ASSOCIATE-AC"));
+    let sent_pdata = scu_observed
+        .iter()
+        .filter(|&&pdu| pdu == (Direction::Sent, "P-DATA-TF"))
+        .count();
+    assert!(sent_pdata >= 3, "{:?}", scu_observed);
+    assert_eq!(
+        &scu_observed[scu_observed.len() - 3..],
+        &[
+            (Direction::Received, "P-DATA-TF"),
+            (Direction::Sent, "A-RELEASE-RQ"),
+            (Direction::Received, "A-RELEASE-RP"),
+        ]
+    );
+}
@@ -0,0 +1,106 @@
+//! Access control on the association acceptor with a peer registry.
+use dicom_ul::{
+    association::{
+        client,
+        peers::{Peer, PeerRegistry},
+    },
+    pdu::{AssociationRJServiceUserReason, AssociationRJSource},
+    ClientAssociationOptions, ServerAssociationOptions,
+};
+use std::net::{SocketAddr, TcpListener};
+
+static VERIFICATION_SOP_CLASS: &str = "1.2.840.10008.1.1";
+static CT_IMAGE_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.2";
+static IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+
+fn spawn_scp(
+    registry: PeerRegistry,
+    connections: usize,
+) -> (std::thread::JoinHandle<()>, SocketAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let options = ServerAssociationOptions::new()
+        .ae_access_control(registry)
+        .with_abstract_syntax(VERIFICATION_SOP_CLASS)
+        .with_abstract_syntax(CT_IMAGE_STORAGE);
+
+    let handle = std::thread::spawn(move || {
+        for _ in 0..connections {
+            let (stream, _) = listener.accept().unwrap();
+            if let Ok(mut association) = options.establish(stream) {
+                assert_eq!(association.receive_message().unwrap(), None);
+            }
+        }
+    });
+    (handle, addr)
+}
+
+fn scu_options(calling_ae_title: &str) -> ClientAssociationOptions<'_> {
+    ClientAssociationOptions::new()
+        .calling_ae_title(calling_ae_title)
+        .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .with_presentation_context(CT_IMAGE_STORAGE, vec![IMPLICIT_VR_LE])
+}
+
+fn rejection_reason(
+    result: client::Result<client::ClientAssociation<std::net::TcpStream>>,
+) -> AssociationRJServiceUserReason {
+    match result {
+        Err(client::Error::Rejected { association_rj, .. }) => match association_rj.source {
+            AssociationRJSource::ServiceUser(reason) => reason,
+            source => panic!("unexpected rejection source: {:?}", source),
+        },
+        Err(e) => panic!("unexpected error: {:?}", e),
+        Ok(_) => panic!("association should be rejected"),
+    }
+}
+
+#[test]
+fn scp_restricts_peer_addresses() {
+    let registry = PeerRegistry::new()
+        .with_peer(Peer::new("LOCAL-SCU").allow_address("127.0.0.0/8".parse().unwrap()))
+        .with_peer(Peer::new("REMOTE-SCU").allow_address("10.0.0.0/8".parse().unwrap()));
+    let (scp_handle, addr) = spawn_scp(registry, 3);
+
+    let association = scu_options("LOCAL-SCU").establish(addr).unwrap();
+    association.release().unwrap();
+
+    assert_eq!(
+        rejection_reason(scu_options("REMOTE-SCU").establish(addr)),
+        AssociationRJServiceUserReason::NoReasonGiven
+    );
+    assert_eq!(
+        rejection_reason(scu_options("STRANGER").establish(addr)),
+        AssociationRJServiceUserReason::CallingAETitleNotRecognized
+    );
+
+    scp_handle.join().unwrap();
+}
+
+#[test]
+fn scp_restricts_peer_sop_classes() {
+    let registry = PeerRegistry::new()
+        .with_peer(Peer::new("ECHO-SCU").with_sop_class(VERIFICATION_SOP_CLASS))
+        .with_peer(Peer::new("ANY-SCU"));
+    let (scp_handle, addr) = spawn_scp(registry, 2);
+
+    let association = scu_options("ECHO-SCU").establish(addr).unwrap();
+    let abstract_syntaxes: Vec<_> = association
+        .presentation_contexts()
+        .iter()
+        .filter_map(|pc| association.abstract_syntax(pc.id))
+        .collect();
+    assert_eq!(abstract_syntaxes, vec![VERIFICATION_SOP_CLASS]);
+    association.release().unwrap();
+
+    let association = scu_options("ANY-SCU").establish(addr).unwrap();
+    let accepted = association
+        .presentation_contexts()
+        .iter()
+        .filter_map(|pc| association.abstract_syntax(pc.id))
+        .count();
+    assert_eq!(accepted, 2);
+    association.release().unwrap();
+
+    scp_handle.join().unwrap();
+}
@@ -0,0 +1,189 @@
+//! Reuse and reconnection of pooled client associations.
+use dicom_ul::{
+    association::pool::AssociationPool,
+    dimse::{CEchoRQ, CEchoRSP, Command, Status},
+    ClientAssociationOptions, FullAeAddr, ServerAssociationOptions,
+};
+use std::{
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+static IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+static EXPLICIT_VR_LE: &str = "1.2.840.10008.1.2.1";
+static VERIFICATION_SOP_CLASS: &str = "1.2.840.10008.1.1";
+static CT_IMAGE_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.2";
+
+/// How long the SCP waits for a request before aborting the association.
+const SCP_IDLE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Spawn an SCP which answers C-ECHO requests
+/// and aborts associations left idle for too long,
+/// returning its address and a counter of accepted connections.
+fn spawn_scp() -> (FullAeAddr<String>, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counter = connections.clone();
+    let options = ServerAssociationOptions::new()
+        .ae_title("POOL-SCP")
+        .with_abstract_syntax(VERIFICATION_SOP_CLASS)
+        .with_abstract_syntax(CT_IMAGE_STORAGE)
+        .timeout(SCP_IDLE_TIMEOUT);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let options = options.clone();
+            counter.fetch_add(1, Ordering::SeqCst);
+            std::thread::spawn(move || {
+                let Ok(mut association) = options.establish(stream.unwrap()) else {
+                    return;
+                };
+                loop {
+                    match association.receive_message() {
+                        Ok(Some(message)) => {
+                            let response = Command::CEchoRSP(CEchoRSP {
+                                message_id_being_responded_to: message
+                                    .command
+                                    .message_id()
+                                    .unwrap(),
+                                affected_sop_class_uid: VERIFICATION_SOP_CLASS.to_string(),
+                                status: Status::SUCCESS,
+                            });
+                            association
+                                .send_message(message.presentation_context_id, &response, None)
+                                .unwrap();
+                        }
+                        Ok(None) => break,
+                        Err(_) => {
+                            let _ = association.abort();
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    (FullAeAddr::new("POOL-SCP", addr.to_string()), connections)
+}
+
+fn echo(association: &mut dicom_ul::ClientAssociation<std::net::TcpStream>) {
+    let pc_id = association
+        .presentation_contexts()
+        .iter()
+        .find(|pc| association.abstract_syntax(pc.id) == Some(VERIFICATION_SOP_CLASS))
+        .unwrap()
+        .id;
+    let message_id = association.next_message_id();
+    let echo = Command::CEchoRQ(CEchoRQ {
+        message_id,
+        affected_sop_class_uid: VERIFICATION_SOP_CLASS.to_string(),
+    });
+    association.send_request(pc_id, &echo, None).unwrap();
+    let response = association.receive_response(message_id).unwrap();
+    assert_eq!(response.command.status(), Some(Status::SUCCESS));
+}
+
+#[test]
+fn pool_reuses_and_reconnects_associations() {
+    let (address, connections) = spawn_scp();
+    let pool = AssociationPool::new(ClientAssociationOptions::new());
+    let verification = || vec![(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])];
+
+    for _ in 0..3 {
+        let mut association = pool.get(&address, verification()).unwrap();
+        echo(&mut association);
+    }
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+    assert_eq!(pool.idle_count(), 1);
+
+    // a new SOP class needs a new association,
+    // which also covers the previous one
+    {
+        let association = pool
+            .get(&address, vec![(CT_IMAGE_STORAGE, vec![IMPLICIT_VR_LE])])
+            .unwrap();
+        assert_eq!(association.presentation_contexts().len(), 2);
+        assert_eq!(pool.idle_count(), 0);
+    }
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+    let mut association = pool.get(&address, verification()).unwrap();
+    echo(&mut association);
+    drop(association);
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+
+    // a transfer syntax which was not accepted also needs a new association
+    let association = pool
+        .get(
+            &address,
+            vec![(VERIFICATION_SOP_CLASS, vec![EXPLICIT_VR_LE])],
+        )
+        .unwrap();
+    assert_eq!(association.presentation_contexts().len(), 3);
+    drop(association);
+    assert_eq!(connections.load(Ordering::SeqCst), 3);
+
+    // the SCP aborts the idle association
+    std::thread::sleep(SCP_IDLE_TIMEOUT * 2);
+    let mut association = pool.get(&address, verification()).unwrap();
+    echo(&mut association);
+    assert_eq!(connections.load(Ordering::SeqCst), 4);
+
+    // associations taken out of the pool do not return
+    association.release().unwrap();
+    assert_eq!(pool.idle_count(), 0);
+}
+
+#[test]
+fn pool_releases_associations_after_idle_timeout() {
+    let (address, connections) = spawn_scp();
+    let pool = AssociationPool::new(ClientAssociationOptions::new())
+        .idle_timeout(Some(Duration::from_millis(50)));
+    let verification = || vec![(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])];
+
+    drop(pool.get(&address, verification()).unwrap());
+    assert_eq!(pool.idle_count(), 1);
+    std::thread::sleep(Duration::from_millis(100));
+    let mut association = pool.get(&address, verification()).unwrap();
+    echo(&mut association);
+    drop(association);
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+    assert_eq!(pool.idle_count(), 1);
+    pool.clear();
+    assert_eq!(pool.idle_count(), 0);
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread")]
+async fn async_pool_reuses_and_reconnects_associations() {
+    use dicom_ul::association::pool::non_blocking::AsyncAssociationPool;
+
+    let (address, connections) = spawn_scp();
+    let pool = AsyncAssociationPool::new(ClientAssociationOptions::new());
+    let verification = || vec![(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])];
+
+    for _ in 0..3 {
+        let mut association = pool.get(&address, verification()).await.unwrap();
+        let message_id = association.next_message_id();
+        let echo = Command::CEchoRQ(CEchoRQ {
+            message_id,
+            affected_sop_class_uid: VERIFICATION_SOP_CLASS.to_string(),
+        });
+        let pc_id = association.presentation_contexts()[0].id;
+        association.send_request(pc_id, &echo, None).await.unwrap();
+        let response = association.receive_response(message_id).await.unwrap();
+        assert_eq!(response.command.status(), Some(Status::SUCCESS));
+    }
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+    // the SCP aborts the idle association
+    tokio::time::sleep(SCP_IDLE_TIMEOUT * 2).await;
+    let association = pool.get(&address, verification()).await.unwrap();
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+    association.release().await.unwrap();
+    assert_eq!(pool.idle_count(), 0);
+}
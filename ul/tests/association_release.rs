@@ -0,0 +1,309 @@
+//! Release of associations requested by the association acceptor.
+use bytes::BytesMut;
+use dicom_ul::{
+    association::{
+        client::{self, get_client_pdu, ClientAssociationOptions},
+        server::{self, ServerAssociationOptions},
+        state::State,
+    },
+    dimse::{write_command, Status},
+    pdu::{
+        write_pdu, AssociationRQ, PDataValue, PDataValueType, Pdu, PresentationContextProposed,
+        UserVariableItem, MAXIMUM_PDU_SIZE,
+    },
+};
+use std::{
+    io::Write,
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+mod common;
+
+use common::{
+    echo_rq, echo_rsp, respond, store_rq, Result, IMPLICIT_VR_LE, VERIFICATION_SOP_CLASS,
+};
+
+static SCU_AE_TITLE: &str = "RELEASE-SCU";
+static SCP_AE_TITLE: &str = "RELEASE-SCP";
+
+fn scp_options() -> ServerAssociationOptions<'static, server::AcceptAny> {
+    common::scp_options(SCP_AE_TITLE).artim_timeout(Duration::from_secs(5))
+}
+
+fn scu_options() -> ClientAssociationOptions<'static> {
+    common::scu_options(SCU_AE_TITLE, SCP_AE_TITLE)
+}
+
+fn pdata(value_type: PDataValueType, data: Vec<u8>) -> Pdu {
+    Pdu::PData {
+        data: vec![PDataValue {
+            presentation_context_id: 3,
+            value_type,
+            is_last: true,
+            data,
+        }],
+    }
+}
+
+/// The requestor confirms a release requested by the acceptor.
+#[test]
+fn scp_release() {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let scu_handle = std::thread::spawn(move || -> Result<()> {
+        let mut association = scu_options().establish(addr)?;
+        let message_id = association.next_message_id();
+        association.send_message(1, &echo_rq(message_id), None)?;
+        let response = association.receive_message()?;
+        assert_eq!(response.command.status(), Some(Status::SUCCESS));
+
+        let err = association.receive_message().unwrap_err();
+        assert!(matches!(err, client::Error::Released { .. }), "{:?}", err);
+        assert_eq!(association.state(), State::Idle);
+        Ok(())
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    let mut association = scp_options().establish(stream).unwrap();
+    let message = association.receive_message().unwrap().unwrap();
+    association
+        .send_message(
+            message.presentation_context_id,
+            &respond(message.command),
+            None,
+        )
+        .unwrap();
+
+    association
+        .release()
+        .expect("did not have a peaceful release");
+
+    scu_handle
+        .join()
+        .expect("SCU panicked")
+        .expect("Error at the SCU");
+}
+
+/// Messages which arrive while the acceptor awaits the release response
+/// are passed on instead of discarded.
+#[test]
+fn scp_release_with_messages_in_transit() {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (sent_tx, sent_rx) = std::sync::mpsc::channel();
+    let scu_handle = std::thread::spawn(move || -> Result<()> {
+        let mut association = scu_options().establish(addr)?;
+        let message_id = association.next_message_id();
+        association.send_message(1, &echo_rq(message_id), None)?;
+        sent_tx.send(()).unwrap();
+
+        let err = association.receive_message().unwrap_err();
+        assert!(matches!(err, client::Error::Released { .. }), "{:?}", err);
+        Ok(())
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    let association = scp_options().establish(stream).unwrap();
+    // request the release only once the requestor is done establishing
+    sent_rx.recv().unwrap();
+    let mut received = Vec::new();
+    association
+        .release_with(|message| received.push(message.command))
+        .expect("did not have a peaceful release");
+    assert_eq!(received, vec![echo_rq(1)]);
+
+    scu_handle
+        .join()
+        .expect("SCU panicked")
+        .expect("Error at the SCU");
+}
+
+/// Responses which arrive while the requestor awaits the release response
+/// are passed on instead of discarded.
+#[test]
+fn scu_release_with_messages_in_transit() {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let scp_handle = std::thread::spawn(move || -> Result<()> {
+        let (stream, _) = listener.accept()?;
+        let mut association = scp_options().establish(stream)?;
+        let message = association.receive_message()?.unwrap();
+        association.send_message(
+            message.presentation_context_id,
+            &respond(message.command),
+            None,
+        )?;
+        assert!(association.receive_message()?.is_none());
+        Ok(())
+    });
+
+    let mut association = scu_options().establish(addr).unwrap();
+    let message_id = association.next_message_id();
+    association
+        .send_message(1, &echo_rq(message_id), None)
+        .unwrap();
+    let mut received = Vec::new();
+    association
+        .release_with(|message| received.push(message.command))
+        .expect("did not have a peaceful release");
+    assert_eq!(received, vec![echo_rsp(message_id)]);
+
+    scp_handle
+        .join()
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
+
+/// A release collision on the acceptor side
+/// goes through Sta10 and Sta12 before the association is released.
+#[test]
+fn scp_release_collision() {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let scu_handle = std::thread::spawn(move || -> Result<()> {
+        let mut stream = TcpStream::connect(addr)?;
+        let mut read_buffer = BytesMut::new();
+        let send = |stream: &mut TcpStream, pdu: &Pdu| -> Result<()> {
+            let mut buffer = Vec::new();
+            write_pdu(&mut buffer, pdu)?;
+            stream.write_all(&buffer)?;
+            Ok(())
+        };
+        send(
+            &mut stream,
+            &Pdu::AssociationRQ(AssociationRQ {
+                protocol_version: 1,
+                calling_ae_title: SCU_AE_TITLE.to_string(),
+                called_ae_title: SCP_AE_TITLE.to_string(),
+                application_context_name: "1.2.840.10008.3.1.1.1".to_string(),
+                presentation_contexts: vec![PresentationContextProposed {
+                    id: 1,
+                    abstract_syntax: VERIFICATION_SOP_CLASS.to_string(),
+                    transfer_syntaxes: vec![IMPLICIT_VR_LE.to_string()],
+                }],
+                user_variables: vec![UserVariableItem::MaxLength(16_384)],
+            }),
+        )?;
+        let ac = get_client_pdu(&mut stream, &mut read_buffer, MAXIMUM_PDU_SIZE, true)?;
+        assert!(matches!(ac, Pdu::AssociationAC(_)), "{:?}", ac);
+
+        let rq = get_client_pdu(&mut stream, &mut read_buffer, MAXIMUM_PDU_SIZE, true)?;
+        assert_eq!(rq, Pdu::ReleaseRQ);
+        // release collision: the requestor had also requested a release
+        send(&mut stream, &Pdu::ReleaseRQ)?;
+        // the requestor responds first
+        send(&mut stream, &Pdu::ReleaseRP)?;
+        let rp = get_client_pdu(&mut stream, &mut read_buffer, MAXIMUM_PDU_SIZE, true)?;
+        assert_eq!(rp, Pdu::ReleaseRP);
+        Ok(())
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    let association = scp_options().establish(stream).unwrap();
+    association
+        .release()
+        .expect("release collision should be resolved");
+
+    scu_handle
+        .join()
+        .expect("SCU panicked")
+        .expect("Error at the SCU");
+}
+
+/// An association which becomes idle in the middle of a message
+/// is only released after that message is received and responded to.
+#[test]
+fn scp_graceful_shutdown_after_idle_timeout() {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let scu_handle = std::thread::spawn(move || -> Result<()> {
+        let mut association = scu_options().establish(addr)?;
+        let message_id = association.next_message_id();
+        // send the command now and the data set later
+        association.send(&pdata(
+            PDataValueType::Command,
+            write_command(&store_rq(message_id), true),
+        ))?;
+        std::thread::sleep(Duration::from_millis(600));
+        association.send(&pdata(PDataValueType::Data, vec![0x55; 64]))?;
+
+        let response = association.receive_message()?;
+        assert_eq!(
+            response.command.message_id_being_responded_to(),
+            Some(message_id)
+        );
+        assert_eq!(response.command.status(), Some(Status::SUCCESS));
+
+        let err = association.receive_message().unwrap_err();
+        assert!(matches!(err, client::Error::Released { .. }), "{:?}", err);
+        Ok(())
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    let mut association = scp_options()
+        .timeout(Duration::from_millis(200))
+        .establish(stream)
+        .unwrap();
+
+    // the data set does not arrive in time
+    assert!(association.receive_message().is_err());
+    assert_eq!(association.state(), State::Established);
+
+    let mut received = Vec::new();
+    association
+        .graceful_shutdown(|message| {
+            received.push(message.data.clone());
+            Some(respond(message.command))
+        })
+        .expect("did not have a graceful shutdown");
+    assert_eq!(received, vec![Some(vec![0x55; 64])]);
+
+    scu_handle
+        .join()
+        .expect("SCU panicked")
+        .expect("Error at the SCU");
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread")]
+async fn scp_graceful_shutdown_async() {
+    let listener = tokio::net::TcpListener::bind("localhost:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let scu_handle = tokio::spawn(async move {
+        let mut association = scu_options().establish_async(addr).await?;
+        let message_id = association.next_message_id();
+        association
+            .send_message(1, &echo_rq(message_id), None)
+            .await?;
+        let response = association.receive_message().await?;
+        assert_eq!(response.command.status(), Some(Status::SUCCESS));
+
+        let err = association.receive_message().await.unwrap_err();
+        assert!(matches!(err, client::Error::Released { .. }), "{:?}", err);
+        Result::Ok(())
+    });
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut association = scp_options().establish_async(stream).await.unwrap();
+    let message = association.receive_message().await.unwrap().unwrap();
+    association
+        .send_message(
+            message.presentation_context_id,
+            &respond(message.command),
+            None,
+        )
+        .await
+        .unwrap();
+
+    // nothing is pending, so the association is released right away
+    association
+        .graceful_shutdown(|message| panic!("unexpected message {:?}", message))
+        .await
+        .expect("did not have a graceful shutdown");
+
+    scu_handle
+        .await
+        .expect("SCU panicked")
+        .expect("Error at the SCU");
+}
@@ -0,0 +1,158 @@
+use dicom_ul::{
+    association::{client::ClientAssociationOptions, Roles},
+    pdu::Pdu,
+};
+
+use std::net::SocketAddr;
+
+use dicom_ul::association::server::ServerAssociationOptions;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+static SCU_AE_TITLE: &str = "GET-SCU";
+static SCP_AE_TITLE: &str = "GET-SCP";
+
+static IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+static STUDY_ROOT_GET: &str = "1.2.840.10008.5.1.4.1.2.2.3";
+static CT_IMAGE_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.2";
+static MR_IMAGE_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.4";
+
+/// Spawn an SCP which grants the SCP role
+/// to the association requestor for CT image storage only.
+fn spawn_scp() -> Result<(std::thread::JoinHandle<Result<()>>, SocketAddr)> {
+    let listener = std::net::TcpListener::bind("localhost:0")?;
+    let addr = listener.local_addr()?;
+    let scp = ServerAssociationOptions::new()
+        .accept_called_ae_title()
+        .ae_title(SCP_AE_TITLE)
+        .with_abstract_syntax(STUDY_ROOT_GET)
+        .with_abstract_syntax(CT_IMAGE_STORAGE)
+        .with_abstract_syntax(MR_IMAGE_STORAGE)
+        .with_role_selection(CT_IMAGE_STORAGE, false, true);
+
+    let h = std::thread::spawn(move || -> Result<()> {
+        let (stream, _addr) = listener.accept()?;
+        let mut association = scp.establish(stream)?;
+
+        assert_eq!(association.abstract_syntax(1), Some(STUDY_ROOT_GET));
+        assert_eq!(association.roles(1), Some(Roles::default()));
+        assert_eq!(association.abstract_syntax(3), Some(CT_IMAGE_STORAGE));
+        assert_eq!(
+            association.roles(3),
+            Some(Roles {
+                scu: false,
+                scp: true
+            })
+        );
+        // role selection proposed, but not configured in the SCP
+        assert_eq!(association.abstract_syntax(5), Some(MR_IMAGE_STORAGE));
+        assert_eq!(association.roles(5), Some(Roles::default()));
+        assert_eq!(association.roles(7), None);
+
+        // handle one release request
+        let pdu = association.receive()?;
+        assert_eq!(pdu, Pdu::ReleaseRQ);
+        association.send(&Pdu::ReleaseRP)?;
+
+        Ok(())
+    });
+    Ok((h, addr))
+}
+
+/// Negotiate SCP/SCU role selection and check the roles on both ends.
+#[test]
+fn scu_scp_role_selection() {
+    let (scp_handle, scp_addr) = spawn_scp().unwrap();
+
+    let association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(STUDY_ROOT_GET, vec![IMPLICIT_VR_LE])
+        .with_presentation_context(CT_IMAGE_STORAGE, vec![IMPLICIT_VR_LE])
+        .with_presentation_context(MR_IMAGE_STORAGE, vec![IMPLICIT_VR_LE])
+        .with_role_selection(CT_IMAGE_STORAGE, false, true)
+        .with_role_selection(MR_IMAGE_STORAGE, false, true)
+        .establish(scp_addr)
+        .unwrap();
+
+    assert_eq!(association.abstract_syntax(1), Some(STUDY_ROOT_GET));
+    assert_eq!(association.roles(1), Some(Roles::default()));
+    assert_eq!(association.abstract_syntax(3), Some(CT_IMAGE_STORAGE));
+    assert_eq!(
+        association.roles(3),
+        Some(Roles {
+            scu: false,
+            scp: true
+        })
+    );
+    assert_eq!(association.roles(5), Some(Roles::default()));
+
+    association
+        .release()
+        .expect("did not have a peaceful release");
+
+    scp_handle
+        .join()
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread")]
+async fn scu_scp_role_selection_async() {
+    let listener = tokio::net::TcpListener::bind("localhost:0").await.unwrap();
+    let scp_addr = listener.local_addr().unwrap();
+    let scp = ServerAssociationOptions::new()
+        .accept_called_ae_title()
+        .ae_title(SCP_AE_TITLE)
+        .with_abstract_syntax(CT_IMAGE_STORAGE)
+        .with_role_selection(CT_IMAGE_STORAGE, true, true);
+
+    let scp_handle = tokio::spawn(async move {
+        let (stream, _addr) = listener.accept().await?;
+        let mut association = scp.establish_async(stream).await?;
+
+        // only the SCP role was proposed
+        assert_eq!(
+            association.roles(1),
+            Some(Roles {
+                scu: false,
+                scp: true
+            })
+        );
+
+        let pdu = association.receive().await?;
+        assert_eq!(pdu, Pdu::ReleaseRQ);
+        association.send(&Pdu::ReleaseRP).await?;
+
+        Result::Ok(())
+    });
+
+    let association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(CT_IMAGE_STORAGE, vec![IMPLICIT_VR_LE])
+        .with_role_selection(CT_IMAGE_STORAGE, false, true)
+        .establish_async(scp_addr)
+        .await
+        .unwrap();
+
+    assert_eq!(association.abstract_syntax(1), Some(CT_IMAGE_STORAGE));
+    assert_eq!(
+        association.roles(1),
+        Some(Roles {
+            scu: false,
+            scp: true
+        })
+    );
+
+    association
+        .release()
+        .await
+        .expect("did not have a peaceful release");
+
+    scp_handle
+        .await
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
@@ -0,0 +1,293 @@
+//! Conformance tests of the upper layer state machine
+//! against peers which misbehave or collide on release.
+use bytes::BytesMut;
+use dicom_ul::{
+    association::{
+        client::{get_client_pdu, ClientAssociationOptions},
+        server::{self, ServerAssociationOptions},
+        state::State,
+    },
+    pdu::{
+        write_pdu, AbortRQServiceProviderReason, AbortRQSource, AssociationAC, AssociationRQ, Pdu,
+        PresentationContextProposed, PresentationContextResult, PresentationContextResultReason,
+        UserVariableItem, MAXIMUM_PDU_SIZE,
+    },
+};
+use std::{
+    io::Write,
+    net::{SocketAddr, TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+mod common;
+
+use common::{Result, IMPLICIT_VR_LE, VERIFICATION_SOP_CLASS};
+
+static SCU_AE_TITLE: &str = "STATE-SCU";
+static SCP_AE_TITLE: &str = "STATE-SCP";
+
+fn send_raw(stream: &mut TcpStream, pdu: &Pdu) -> Result<()> {
+    let mut buffer = Vec::new();
+    write_pdu(&mut buffer, pdu)?;
+    stream.write_all(&buffer)?;
+    Ok(())
+}
+
+fn receive_raw(stream: &mut TcpStream, read_buffer: &mut BytesMut) -> Result<Pdu> {
+    Ok(get_client_pdu(stream, read_buffer, MAXIMUM_PDU_SIZE, true)?)
+}
+
+fn association_rq() -> Pdu {
+    Pdu::AssociationRQ(AssociationRQ {
+        protocol_version: 1,
+        calling_ae_title: SCU_AE_TITLE.to_string(),
+        called_ae_title: SCP_AE_TITLE.to_string(),
+        application_context_name: "1.2.840.10008.3.1.1.1".to_string(),
+        presentation_contexts: vec![PresentationContextProposed {
+            id: 1,
+            abstract_syntax: VERIFICATION_SOP_CLASS.to_string(),
+            transfer_syntaxes: vec![IMPLICIT_VR_LE.to_string()],
+        }],
+        user_variables: vec![UserVariableItem::MaxLength(16_384)],
+    })
+}
+
+fn association_ac() -> Pdu {
+    Pdu::AssociationAC(AssociationAC {
+        protocol_version: 1,
+        calling_ae_title: SCU_AE_TITLE.to_string(),
+        called_ae_title: SCP_AE_TITLE.to_string(),
+        application_context_name: "1.2.840.10008.3.1.1.1".to_string(),
+        presentation_contexts: vec![PresentationContextResult {
+            id: 1,
+            reason: PresentationContextResultReason::Acceptance,
+            transfer_syntax: IMPLICIT_VR_LE.to_string(),
+        }],
+        user_variables: vec![UserVariableItem::MaxLength(16_384)],
+    })
+}
+
+fn scp_options() -> ServerAssociationOptions<'static, server::AcceptAny> {
+    common::scp_options(SCP_AE_TITLE).artim_timeout(Duration::from_secs(5))
+}
+
+/// Spawn a requestor which establishes an association
+/// and then sends the given PDU,
+/// returning the PDU which the acceptor replied with.
+fn spawn_raw_scu(addr: SocketAddr, pdu: Pdu) -> std::thread::JoinHandle<Result<Pdu>> {
+    std::thread::spawn(move || -> Result<Pdu> {
+        let mut stream = TcpStream::connect(addr)?;
+        let mut read_buffer = BytesMut::new();
+        send_raw(&mut stream, &association_rq())?;
+        let ac = receive_raw(&mut stream, &mut read_buffer)?;
+        assert!(matches!(ac, Pdu::AssociationAC(_)), "{:?}", ac);
+
+        send_raw(&mut stream, &pdu)?;
+        let reply = receive_raw(&mut stream, &mut read_buffer)?;
+        // close the connection like a compliant requestor would
+        drop(stream);
+        Ok(reply)
+    })
+}
+
+/// A-RELEASE-RP in an established association (Sta6, Evt13)
+/// is answered with an A-ABORT (AA-8).
+#[test]
+fn scp_aborts_on_unexpected_pdu() {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let scu_handle = spawn_raw_scu(listener.local_addr().unwrap(), Pdu::ReleaseRP);
+
+    let (stream, _) = listener.accept().unwrap();
+    let mut association = scp_options().establish(stream).unwrap();
+    assert_eq!(association.state(), State::Established);
+
+    let err = association.receive().unwrap_err();
+    assert!(
+        matches!(err, server::Error::UnexpectedRequest { .. }),
+        "{:?}",
+        err
+    );
+    assert_eq!(association.state(), State::Idle);
+
+    let reply = scu_handle.join().unwrap().unwrap();
+    assert_eq!(
+        reply,
+        Pdu::AbortRQ {
+            source: AbortRQSource::ServiceProvider(AbortRQServiceProviderReason::UnexpectedPdu),
+        }
+    );
+}
+
+/// An unrecognized PDU (Evt19) is answered with an A-ABORT (AA-8).
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread")]
+async fn scp_aborts_on_unknown_pdu_async() {
+    let listener = tokio::net::TcpListener::bind("localhost:0").await.unwrap();
+    let scu_handle = spawn_raw_scu(
+        listener.local_addr().unwrap(),
+        Pdu::Unknown {
+            pdu_type: 0x7F,
+            data: vec![0; 4],
+        },
+    );
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut association = scp_options().establish_async(stream).await.unwrap();
+    assert_eq!(association.state(), State::Established);
+
+    let err = association.receive().await.unwrap_err();
+    assert!(
+        matches!(err, server::Error::UnknownRequest { .. }),
+        "{:?}",
+        err
+    );
+    assert_eq!(association.state(), State::Idle);
+
+    let reply = scu_handle.join().unwrap().unwrap();
+    assert_eq!(
+        reply,
+        Pdu::AbortRQ {
+            source: AbortRQSource::ServiceProvider(AbortRQServiceProviderReason::UnrecognizedPdu),
+        }
+    );
+}
+
+/// The acceptor gives up on a connection
+/// which does not send an association request
+/// once the ARTIM timer expires (Sta2, Evt18).
+#[test]
+fn scp_artim_expires_without_association_request() {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let scu_handle = std::thread::spawn(move || -> Result<()> {
+        let mut stream = TcpStream::connect(addr)?;
+        let mut read_buffer = BytesMut::new();
+        // nothing is sent, the acceptor eventually closes the connection
+        assert!(receive_raw(&mut stream, &mut read_buffer).is_err());
+        Ok(())
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    let start = Instant::now();
+    let result = scp_options()
+        .artim_timeout(Duration::from_millis(200))
+        .establish(stream);
+    assert!(result.is_err());
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    scu_handle.join().unwrap().unwrap();
+}
+
+/// Spawn an acceptor which requests a release
+/// at the same time as the requestor.
+fn spawn_colliding_scp() -> Result<(std::thread::JoinHandle<Result<()>>, SocketAddr)> {
+    let listener = TcpListener::bind("localhost:0")?;
+    let addr = listener.local_addr()?;
+    let h = std::thread::spawn(move || -> Result<()> {
+        let (mut stream, _) = listener.accept()?;
+        let mut read_buffer = BytesMut::new();
+        let rq = receive_raw(&mut stream, &mut read_buffer)?;
+        assert!(matches!(rq, Pdu::AssociationRQ(_)), "{:?}", rq);
+        send_raw(&mut stream, &association_ac())?;
+
+        assert_eq!(receive_raw(&mut stream, &mut read_buffer)?, Pdu::ReleaseRQ);
+        // release collision: the acceptor had also requested a release
+        send_raw(&mut stream, &Pdu::ReleaseRQ)?;
+        // the requestor responds first
+        assert_eq!(receive_raw(&mut stream, &mut read_buffer)?, Pdu::ReleaseRP);
+        send_raw(&mut stream, &Pdu::ReleaseRP)?;
+        Ok(())
+    });
+    Ok((h, addr))
+}
+
+/// A release collision on the requestor side
+/// goes through Sta9 and Sta11 before the association is released.
+#[test]
+fn scu_release_collision() {
+    let (scp_handle, scp_addr) = spawn_colliding_scp().unwrap();
+
+    let association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .establish(scp_addr)
+        .unwrap();
+    assert_eq!(association.state(), State::Established);
+
+    association
+        .release()
+        .expect("release collision should be resolved");
+
+    scp_handle
+        .join()
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread")]
+async fn scu_release_collision_async() {
+    let (scp_handle, scp_addr) = spawn_colliding_scp().unwrap();
+
+    let association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .establish_async(scp_addr)
+        .await
+        .unwrap();
+    assert_eq!(association.state(), State::Established);
+
+    association
+        .release()
+        .await
+        .expect("release collision should be resolved");
+
+    scp_handle
+        .join()
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
+
+/// Local primitives which are not valid in the current state
+/// are refused without sending anything.
+#[test]
+fn scu_refuses_invalid_primitive() {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let scp_handle = std::thread::spawn(move || -> Result<()> {
+        let (mut stream, _) = listener.accept()?;
+        let mut read_buffer = BytesMut::new();
+        receive_raw(&mut stream, &mut read_buffer)?;
+        send_raw(&mut stream, &association_ac())?;
+        assert_eq!(receive_raw(&mut stream, &mut read_buffer)?, Pdu::ReleaseRQ);
+        send_raw(&mut stream, &Pdu::ReleaseRP)?;
+        Ok(())
+    });
+
+    let mut association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .establish(addr)
+        .unwrap();
+
+    // A-RELEASE response primitive in Sta6
+    let err = association.send(&Pdu::ReleaseRP).unwrap_err();
+    assert!(
+        matches!(
+            err,
+            dicom_ul::association::client::Error::InvalidState {
+                state: State::Established,
+                ..
+            }
+        ),
+        "{:?}",
+        err
+    );
+    assert_eq!(association.state(), State::Established);
+
+    association.release().unwrap();
+    scp_handle.join().unwrap().unwrap();
+}
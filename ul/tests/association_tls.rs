@@ -0,0 +1,274 @@
+//! Associations over TLS secure transport connections,
+//! using the test certificates in `tests/tls`.
+//!
+//! The certificates for `localhost` (SCP) and `tls-scu` (SCU)
+//! were issued by a test certificate authority whose key was discarded.
+#![cfg(feature = "tls")]
+use dicom_ul::{
+    association::client::ClientAssociationOptions,
+    association::server::ServerAssociationOptions,
+    dimse::{CEchoRQ, CEchoRSP, Command, Status},
+    rustls::{
+        self,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        ClientConfig, RootCertStore, ServerConfig,
+    },
+};
+
+use std::{net::SocketAddr, sync::Arc};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+static SCU_AE_TITLE: &str = "TLS-SCU";
+static SCP_AE_TITLE: &str = "TLS-SCP";
+
+static IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+static VERIFICATION_SOP_CLASS: &str = "1.2.840.10008.1.1";
+
+/// A certificate authority
+/// and the certificates issued by it for the SCP and SCU.
+struct Pki {
+    ca: CertificateDer<'static>,
+    server: (CertificateDer<'static>, PrivateKeyDer<'static>),
+    client: (CertificateDer<'static>, PrivateKeyDer<'static>),
+}
+
+fn pki() -> Result<Pki> {
+    let identity = |cert: &[u8], key: &[u8]| -> Result<_> {
+        Ok((
+            CertificateDer::from_pem_slice(cert)?,
+            PrivateKeyDer::from_pem_slice(key)?,
+        ))
+    };
+    Ok(Pki {
+        ca: CertificateDer::from_pem_slice(include_bytes!("tls/ca.pem"))?,
+        server: identity(include_bytes!("tls/scp.pem"), include_bytes!("tls/scp.key"))?,
+        client: identity(include_bytes!("tls/scu.pem"), include_bytes!("tls/scu.key"))?,
+    })
+}
+
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn roots(pki: &Pki) -> Result<Arc<RootCertStore>> {
+    let mut roots = RootCertStore::empty();
+    roots.add(pki.ca.clone())?;
+    Ok(Arc::new(roots))
+}
+
+/// TLS server configuration requiring a client certificate issued by the CA
+fn server_config(pki: &Pki) -> Result<ServerConfig> {
+    let verifier = WebPkiClientVerifier::builder_with_provider(roots(pki)?, provider()).build()?;
+    let (cert, key) = &pki.server;
+    Ok(ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(vec![cert.clone()], key.clone_key())?)
+}
+
+fn client_config(pki: &Pki, with_certificate: bool) -> Result<ClientConfig> {
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots(pki)?);
+    if with_certificate {
+        let (cert, key) = &pki.client;
+        Ok(builder.with_client_auth_cert(vec![cert.clone()], key.clone_key())?)
+    } else {
+        Ok(builder.with_no_client_auth())
+    }
+}
+
+fn echo_request(message_id: u16) -> Command {
+    Command::CEchoRQ(CEchoRQ {
+        message_id,
+        affected_sop_class_uid: VERIFICATION_SOP_CLASS.to_string(),
+    })
+}
+
+fn spawn_scp(pki: &Pki) -> Result<(std::thread::JoinHandle<Result<()>>, SocketAddr)> {
+    let listener = std::net::TcpListener::bind("localhost:0")?;
+    let addr = listener.local_addr()?;
+    let scp = ServerAssociationOptions::new()
+        .accept_called_ae_title()
+        .ae_title(SCP_AE_TITLE)
+        .with_abstract_syntax(VERIFICATION_SOP_CLASS)
+        .tls_config(server_config(pki)?);
+
+    let h = std::thread::spawn(move || -> Result<()> {
+        let (stream, _addr) = listener.accept()?;
+        let mut association = scp.establish_tls(stream)?;
+
+        let message = association.receive_message()?.expect("expected C-ECHO-RQ");
+        let Command::CEchoRQ(rq) = message.command else {
+            panic!("unexpected command {:?}", message.command);
+        };
+        association.send_message(
+            message.presentation_context_id,
+            &Command::CEchoRSP(CEchoRSP {
+                message_id_being_responded_to: rq.message_id,
+                affected_sop_class_uid: rq.affected_sop_class_uid,
+                status: Status::SUCCESS,
+            }),
+            None,
+        )?;
+
+        // release
+        assert_eq!(association.receive_message()?, None);
+
+        Ok(())
+    });
+    Ok((h, addr))
+}
+
+/// Run a C-ECHO over a mutually authenticated TLS connection.
+#[test]
+fn scu_scp_tls_echo() {
+    let pki = pki().unwrap();
+    let (scp_handle, scp_addr) = spawn_scp(&pki).unwrap();
+
+    let mut association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .tls_config(client_config(&pki, true).unwrap())
+        .server_name("localhost")
+        .establish_tls(scp_addr)
+        .unwrap();
+
+    let message_id = association.next_message_id();
+    association
+        .send_message(1, &echo_request(message_id), None)
+        .unwrap();
+    let response = association.receive_message().unwrap();
+    assert_eq!(
+        response.command.message_id_being_responded_to(),
+        Some(message_id)
+    );
+    assert_eq!(response.command.status(), Some(Status::SUCCESS));
+
+    association
+        .release()
+        .expect("did not have a peaceful release");
+
+    scp_handle
+        .join()
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
+
+/// The SCP should refuse a client which does not present a certificate.
+#[test]
+fn scu_without_certificate_is_rejected() {
+    let pki = pki().unwrap();
+    let (scp_handle, scp_addr) = spawn_scp(&pki).unwrap();
+
+    let result = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .tls_config(client_config(&pki, false).unwrap())
+        .server_name("localhost")
+        .establish_tls(scp_addr);
+    assert!(result.is_err());
+
+    let scp_result = scp_handle.join().expect("SCP panicked");
+    assert!(scp_result.is_err());
+}
+
+/// The SCU should refuse a server whose certificate
+/// does not match the expected server name.
+#[test]
+fn scu_rejects_server_name_mismatch() {
+    let pki = pki().unwrap();
+    let (_scp_handle, scp_addr) = spawn_scp(&pki).unwrap();
+
+    let result = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .tls_config(client_config(&pki, true).unwrap())
+        .server_name("pacs.example.com")
+        .establish_tls(scp_addr);
+    assert!(matches!(
+        result,
+        Err(dicom_ul::association::client::Error::TlsHandshake { .. })
+    ));
+}
+
+#[cfg(feature = "async-tls")]
+async fn spawn_scp_async(pki: &Pki) -> Result<(tokio::task::JoinHandle<Result<()>>, SocketAddr)> {
+    let listener = tokio::net::TcpListener::bind("localhost:0").await?;
+    let addr = listener.local_addr()?;
+    let scp = ServerAssociationOptions::new()
+        .accept_called_ae_title()
+        .ae_title(SCP_AE_TITLE)
+        .with_abstract_syntax(VERIFICATION_SOP_CLASS)
+        .tls_config(server_config(pki)?);
+
+    let h = tokio::spawn(async move {
+        let (stream, _addr) = listener.accept().await?;
+        let mut association = scp.establish_tls_async(stream).await?;
+
+        let message = association
+            .receive_message()
+            .await?
+            .expect("expected C-ECHO-RQ");
+        let Command::CEchoRQ(rq) = message.command else {
+            panic!("unexpected command {:?}", message.command);
+        };
+        association
+            .send_message(
+                message.presentation_context_id,
+                &Command::CEchoRSP(CEchoRSP {
+                    message_id_being_responded_to: rq.message_id,
+                    affected_sop_class_uid: rq.affected_sop_class_uid,
+                    status: Status::SUCCESS,
+                }),
+                None,
+            )
+            .await?;
+
+        // release
+        assert_eq!(association.receive_message().await?, None);
+
+        Ok(())
+    });
+    Ok((h, addr))
+}
+
+#[cfg(feature = "async-tls")]
+#[tokio::test(flavor = "multi_thread")]
+async fn scu_scp_tls_echo_async() {
+    let pki = pki().unwrap();
+    let (scp_handle, scp_addr) = spawn_scp_async(&pki).await.unwrap();
+
+    let mut association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .tls_config(client_config(&pki, true).unwrap())
+        .server_name("localhost")
+        .establish_tls_async(scp_addr)
+        .await
+        .unwrap();
+
+    let message_id = association.next_message_id();
+    association
+        .send_message(1, &echo_request(message_id), None)
+        .await
+        .unwrap();
+    let response = association.receive_message().await.unwrap();
+    assert_eq!(response.command.status(), Some(Status::SUCCESS));
+
+    association
+        .release()
+        .await
+        .expect("did not have a peaceful release");
+
+    scp_handle
+        .await
+        .expect("SCP panicked")
+        .expect("Error at the SCP");
+}
@@ -0,0 +1,128 @@
+//! Transfer syntax policies on the association acceptor.
+use dicom_ul::{
+    association::server::TransferSyntaxPolicy,
+    pdu::{PresentationContextResult, PresentationContextResultReason},
+    ClientAssociationOptions, ServerAssociationOptions,
+};
+use std::net::TcpListener;
+
+mod common;
+
+use common::{CT_IMAGE_STORAGE_SOP_CLASS as CT_IMAGE_STORAGE, IMPLICIT_VR_LE};
+
+static EXPLICIT_VR_LE: &str = "1.2.840.10008.1.2.1";
+static JPEG_LOSSLESS_SV1: &str = "1.2.840.10008.1.2.4.70";
+static JPEG_LS_LOSSLESS: &str = "1.2.840.10008.1.2.4.80";
+static JPEG_2000_LOSSLESS: &str = "1.2.840.10008.1.2.4.90";
+static JPEG_2000: &str = "1.2.840.10008.1.2.4.91";
+
+static MR_IMAGE_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.4";
+static DIGITAL_MAMMOGRAPHY_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.1.2";
+static US_IMAGE_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.6.1";
+static SECONDARY_CAPTURE_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.7";
+
+fn scp_options() -> ServerAssociationOptions<'static, dicom_ul::association::server::AcceptAny> {
+    ServerAssociationOptions::new()
+        .ae_title("POLICY-SCP")
+        .with_abstract_syntax(CT_IMAGE_STORAGE)
+        .with_abstract_syntax(MR_IMAGE_STORAGE)
+        .with_abstract_syntax(DIGITAL_MAMMOGRAPHY_STORAGE)
+        .with_abstract_syntax(US_IMAGE_STORAGE)
+        .with_abstract_syntax(SECONDARY_CAPTURE_STORAGE)
+        .with_transfer_syntax(EXPLICIT_VR_LE)
+        .with_transfer_syntax(IMPLICIT_VR_LE)
+        .with_transfer_syntax(JPEG_2000_LOSSLESS)
+        // JPEG 2000 only for CT and MR
+        .with_transfer_syntax_policy(
+            TransferSyntaxPolicy::new()
+                .with_abstract_syntax(CT_IMAGE_STORAGE)
+                .with_abstract_syntax(MR_IMAGE_STORAGE)
+                .prefer(JPEG_2000_LOSSLESS),
+        )
+        // lossless for mammography
+        .with_transfer_syntax_policy(
+            TransferSyntaxPolicy::new()
+                .with_abstract_syntax(DIGITAL_MAMMOGRAPHY_STORAGE)
+                .prefer(JPEG_LS_LOSSLESS)
+                .prefer(JPEG_LOSSLESS_SV1)
+                .reject(JPEG_2000),
+        )
+        .with_transfer_syntax_policy(
+            TransferSyntaxPolicy::new()
+                .with_abstract_syntax(SECONDARY_CAPTURE_STORAGE)
+                .prefer(JPEG_LS_LOSSLESS)
+                .preferred_only(true),
+        )
+        // everything else
+        .with_transfer_syntax_policy(TransferSyntaxPolicy::new().reject(JPEG_2000_LOSSLESS))
+}
+
+fn accepted(id: u8, transfer_syntax: &str) -> PresentationContextResult {
+    PresentationContextResult {
+        id,
+        reason: PresentationContextResultReason::Acceptance,
+        transfer_syntax: transfer_syntax.to_string(),
+    }
+}
+
+fn rejected(id: u8) -> PresentationContextResult {
+    PresentationContextResult {
+        id,
+        reason: PresentationContextResultReason::TransferSyntaxesNotSupported,
+        transfer_syntax: IMPLICIT_VR_LE.to_string(),
+    }
+}
+
+#[test]
+fn scp_transfer_syntax_policy() {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let scu_handle = std::thread::spawn(move || {
+        let association = ClientAssociationOptions::new()
+            .with_presentation_context(
+                CT_IMAGE_STORAGE,
+                vec![IMPLICIT_VR_LE, EXPLICIT_VR_LE, JPEG_2000_LOSSLESS],
+            )
+            .with_presentation_context(MR_IMAGE_STORAGE, vec![IMPLICIT_VR_LE])
+            .with_presentation_context(
+                DIGITAL_MAMMOGRAPHY_STORAGE,
+                vec![JPEG_2000, EXPLICIT_VR_LE, JPEG_LOSSLESS_SV1],
+            )
+            .with_presentation_context(DIGITAL_MAMMOGRAPHY_STORAGE, vec![JPEG_2000, EXPLICIT_VR_LE])
+            .with_presentation_context(US_IMAGE_STORAGE, vec![JPEG_2000_LOSSLESS])
+            .with_presentation_context(US_IMAGE_STORAGE, vec![JPEG_2000_LOSSLESS, EXPLICIT_VR_LE])
+            .with_presentation_context(SECONDARY_CAPTURE_STORAGE, vec![EXPLICIT_VR_LE])
+            .establish(addr)
+            .unwrap();
+        let presentation_contexts = association.presentation_contexts().to_vec();
+        association.release().unwrap();
+        presentation_contexts
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    let mut association = scp_options().establish(stream).unwrap();
+    let expected = vec![
+        // CT: JPEG 2000 preferred
+        accepted(1, JPEG_2000_LOSSLESS),
+        // MR: no preferred transfer syntax proposed
+        accepted(3, IMPLICIT_VR_LE),
+        // MG: JPEG lossless preferred, JPEG 2000 rejected
+        accepted(5, JPEG_LOSSLESS_SV1),
+        accepted(7, EXPLICIT_VR_LE),
+        // US: JPEG 2000 rejected
+        rejected(9),
+        accepted(11, EXPLICIT_VR_LE),
+        // SC: only JPEG-LS accepted
+        rejected(13),
+    ];
+    assert_eq!(association.presentation_contexts(), &expected[..]);
+    assert_eq!(association.receive_message().unwrap(), None);
+
+    let client_view = scu_handle.join().unwrap();
+    let expected_accepted: Vec<_> = expected
+        .into_iter()
+        .filter(|pc| pc.reason == PresentationContextResultReason::Acceptance)
+        .collect();
+    assert_eq!(client_view, expected_accepted);
+}
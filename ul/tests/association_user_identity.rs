@@ -0,0 +1,195 @@
+//! User identity negotiation on the association acceptor.
+use dicom_ul::{
+    association::{
+        client,
+        server::{AccessControl, ServerAssociationOptions},
+    },
+    pdu::{
+        AssociationRJResult, AssociationRJServiceUserReason, AssociationRJSource, UserIdentity,
+        UserIdentityType,
+    },
+    ClientAssociationOptions,
+};
+use std::net::{SocketAddr, TcpListener};
+
+static VERIFICATION_SOP_CLASS: &str = "1.2.840.10008.1.1";
+static IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+
+/// Accepts the user `admin` with the password `secret`,
+/// or the JWT `token`, to which it responds with `refreshed`.
+struct Credentials;
+
+impl AccessControl for Credentials {
+    fn check_access(
+        &self,
+        _this_ae_title: &str,
+        _calling_ae_title: &str,
+        _called_ae_title: &str,
+        user_identity: Option<&UserIdentity>,
+    ) -> Result<(), AssociationRJServiceUserReason> {
+        match user_identity {
+            Some(_) => Ok(()),
+            None => Err(AssociationRJServiceUserReason::NoReasonGiven),
+        }
+    }
+
+    fn verify_user_identity(
+        &self,
+        _calling_ae_title: &str,
+        user_identity: &UserIdentity,
+    ) -> Result<Option<Vec<u8>>, AssociationRJServiceUserReason> {
+        match user_identity.identity_type() {
+            UserIdentityType::UsernamePassword
+                if user_identity.primary_field() == b"admin"
+                    && user_identity.secondary_field() == b"secret" =>
+            {
+                Ok(Some(Vec::new()))
+            }
+            UserIdentityType::Jwt if user_identity.primary_field() == b"token" => {
+                Ok(Some(b"refreshed".to_vec()))
+            }
+            _ => Err(AssociationRJServiceUserReason::NoReasonGiven),
+        }
+    }
+}
+
+fn spawn_scp(
+    connections: usize,
+) -> (
+    std::thread::JoinHandle<Vec<Option<UserIdentity>>>,
+    SocketAddr,
+) {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let options = ServerAssociationOptions::new()
+        .ae_access_control(Credentials)
+        .with_abstract_syntax(VERIFICATION_SOP_CLASS);
+
+    let handle = std::thread::spawn(move || {
+        (0..connections)
+            .map(|_| {
+                let (stream, _) = listener.accept().unwrap();
+                match options.establish(stream) {
+                    Ok(mut association) => {
+                        let user_identity = association.user_identity().cloned();
+                        assert_eq!(association.receive_message().unwrap(), None);
+                        user_identity
+                    }
+                    Err(_) => None,
+                }
+            })
+            .collect()
+    });
+    (handle, addr)
+}
+
+fn scu_options() -> ClientAssociationOptions<'static> {
+    ClientAssociationOptions::new()
+        .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])
+}
+
+fn assert_rejected(result: client::Result<client::ClientAssociation<std::net::TcpStream>>) {
+    match result {
+        Err(client::Error::Rejected { association_rj, .. }) => {
+            assert_eq!(association_rj.result, AssociationRJResult::Permanent);
+            assert!(matches!(
+                association_rj.source,
+                AssociationRJSource::ServiceUser(_)
+            ));
+        }
+        Err(e) => panic!("unexpected error: {:?}", e),
+        Ok(_) => panic!("association should be rejected"),
+    }
+}
+
+#[test]
+fn scp_verifies_user_identity() {
+    let (scp_handle, addr) = spawn_scp(5);
+
+    // positive response requested
+    let association = scu_options()
+        .username_password("admin", "secret")
+        .user_identity_positive_response(true)
+        .establish(addr)
+        .unwrap();
+    assert_eq!(association.user_identity_server_response(), Some(&[][..]));
+    association.release().unwrap();
+
+    // no positive response requested
+    let association = scu_options()
+        .username_password("admin", "secret")
+        .establish(addr)
+        .unwrap();
+    assert_eq!(association.user_identity_server_response(), None);
+    association.release().unwrap();
+
+    let association = scu_options()
+        .jwt("token")
+        .user_identity_positive_response(true)
+        .establish(addr)
+        .unwrap();
+    assert_eq!(
+        association.user_identity_server_response(),
+        Some(&b"refreshed"[..])
+    );
+    association.release().unwrap();
+
+    // wrong password
+    assert_rejected(
+        scu_options()
+            .username_password("admin", "guess")
+            .establish(addr),
+    );
+    // no identity
+    assert_rejected(scu_options().establish(addr));
+
+    let identities = scp_handle.join().unwrap();
+    let identity_types: Vec<_> = identities
+        .iter()
+        .map(|identity| identity.as_ref().map(|identity| identity.identity_type()))
+        .collect();
+    assert_eq!(
+        identity_types,
+        vec![
+            Some(UserIdentityType::UsernamePassword),
+            Some(UserIdentityType::UsernamePassword),
+            Some(UserIdentityType::Jwt),
+            None,
+            None,
+        ]
+    );
+    assert!(identities[0]
+        .as_ref()
+        .unwrap()
+        .positive_response_requested());
+}
+
+#[test]
+fn user_identity_server_response_is_not_sent_unverified() {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let scp_handle = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        // identities are accepted without verification by default
+        let mut association = ServerAssociationOptions::new()
+            .with_abstract_syntax(VERIFICATION_SOP_CLASS)
+            .establish(stream)
+            .unwrap();
+        assert_eq!(
+            association
+                .user_identity()
+                .map(|identity| identity.primary_field()),
+            Some(b"someone".to_vec())
+        );
+        assert_eq!(association.receive_message().unwrap(), None);
+    });
+
+    let association = scu_options()
+        .username("someone")
+        .user_identity_positive_response(true)
+        .establish(addr)
+        .unwrap();
+    assert_eq!(association.user_identity_server_response(), None);
+    association.release().unwrap();
+    scp_handle.join().unwrap();
+}
@@ -0,0 +1,85 @@
+//! Fixtures shared by the association tests.
+//!
+//! Each test crate only uses some of them.
+#![allow(dead_code)]
+use dicom_ul::{
+    association::{
+        client::ClientAssociationOptions,
+        server::{AcceptAny, ServerAssociationOptions},
+    },
+    dimse::{CEchoRQ, CEchoRSP, CStoreRQ, CStoreRSP, Command, Priority, Status},
+};
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+pub static IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+pub static VERIFICATION_SOP_CLASS: &str = "1.2.840.10008.1.1";
+pub static CT_IMAGE_STORAGE_SOP_CLASS: &str = "1.2.840.10008.5.1.4.1.1.2";
+pub static SOP_INSTANCE_UID: &str = "2.25.114108447335338453424416592151380727402";
+
+/// A data set larger than the maximum PDU length,
+/// so that it has to be split into multiple P-Data PDUs.
+pub fn data_set() -> Vec<u8> {
+    (0..40_000_u32).map(|i| (i * 7) as u8).collect()
+}
+
+/// An acceptor of verification and CT image storage.
+pub fn scp_options(ae_title: &'static str) -> ServerAssociationOptions<'static, AcceptAny> {
+    ServerAssociationOptions::new()
+        .ae_title(ae_title)
+        .with_abstract_syntax(VERIFICATION_SOP_CLASS)
+        .with_abstract_syntax(CT_IMAGE_STORAGE_SOP_CLASS)
+}
+
+/// A requestor of verification and CT image storage
+/// in implicit VR little endian.
+pub fn scu_options(
+    calling_ae_title: &'static str,
+    called_ae_title: &'static str,
+) -> ClientAssociationOptions<'static> {
+    ClientAssociationOptions::new()
+        .calling_ae_title(calling_ae_title)
+        .called_ae_title(called_ae_title)
+        .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .with_presentation_context(CT_IMAGE_STORAGE_SOP_CLASS, vec![IMPLICIT_VR_LE])
+}
+
+pub fn echo_rq(message_id: u16) -> Command {
+    Command::CEchoRQ(CEchoRQ {
+        message_id,
+        affected_sop_class_uid: VERIFICATION_SOP_CLASS.to_string(),
+    })
+}
+
+pub fn echo_rsp(message_id: u16) -> Command {
+    Command::CEchoRSP(CEchoRSP {
+        message_id_being_responded_to: message_id,
+        affected_sop_class_uid: VERIFICATION_SOP_CLASS.to_string(),
+        status: Status::SUCCESS,
+    })
+}
+
+pub fn store_rq(message_id: u16) -> Command {
+    Command::CStoreRQ(CStoreRQ {
+        message_id,
+        affected_sop_class_uid: CT_IMAGE_STORAGE_SOP_CLASS.to_string(),
+        affected_sop_instance_uid: SOP_INSTANCE_UID.to_string(),
+        priority: Priority::Medium,
+        move_originator_ae_title: None,
+        move_originator_message_id: None,
+    })
+}
+
+/// Produce the response to a request received by the SCP.
+pub fn respond(command: Command) -> Command {
+    match command {
+        Command::CEchoRQ(rq) => echo_rsp(rq.message_id),
+        Command::CStoreRQ(rq) => Command::CStoreRSP(CStoreRSP {
+            message_id_being_responded_to: rq.message_id,
+            affected_sop_class_uid: rq.affected_sop_class_uid,
+            affected_sop_instance_uid: rq.affected_sop_instance_uid,
+            status: Status::SUCCESS,
+        }),
+        command => panic!("unexpected command {:?}", command),
+    }
+}
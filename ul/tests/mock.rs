@@ -0,0 +1,84 @@
+//! Error paths of a service class user, tested against a mock SCP.
+#![cfg(feature = "mock")]
+use dicom_ul::{
+    association::client::ClientAssociationOptions,
+    dimse::{CEchoRQ, Command, Status},
+    mock::{MockEvent, MockResponse, MockScp},
+};
+use std::time::Duration;
+
+static IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+static VERIFICATION_SOP_CLASS: &str = "1.2.840.10008.1.1";
+
+/// Send a C-ECHO request and wait for its status,
+/// retrying once in a new association
+/// if the first one does not succeed.
+fn echo_with_retry(addr: std::net::SocketAddr) -> Vec<Result<Status, String>> {
+    let mut attempts = Vec::new();
+    for _ in 0..2 {
+        let outcome = (|| {
+            let mut association = ClientAssociationOptions::new()
+                .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])
+                .read_timeout(Duration::from_millis(300))
+                .establish(addr)
+                .map_err(|e| e.to_string())?;
+            let echo = Command::CEchoRQ(CEchoRQ {
+                message_id: association.next_message_id(),
+                affected_sop_class_uid: VERIFICATION_SOP_CLASS.to_string(),
+            });
+            association
+                .send_message(1, &echo, None)
+                .map_err(|e| e.to_string())?;
+            let response = association.receive_message().map_err(|e| e.to_string())?;
+            let _ = association.release();
+            response
+                .command
+                .status()
+                .ok_or_else(|| "no status".to_string())
+        })();
+        let success = outcome == Ok(Status::SUCCESS);
+        attempts.push(outcome);
+        if success {
+            break;
+        }
+    }
+    attempts
+}
+
+#[test]
+fn scu_retries_after_timeout() {
+    let scp = MockScp::new()
+        .respond(MockResponse::no_response())
+        .spawn()
+        .unwrap();
+
+    let attempts = echo_with_retry(scp.addr());
+    assert_eq!(attempts.len(), 2, "{:?}", attempts);
+    assert!(attempts[0].is_err());
+    assert_eq!(attempts[1], Ok(Status::SUCCESS));
+
+    let events = scp.shutdown();
+    assert_eq!(
+        events
+            .iter()
+            .filter(|event| matches!(event, MockEvent::Established { .. }))
+            .count(),
+        2
+    );
+    assert_eq!(events.last(), Some(&MockEvent::Released));
+}
+
+#[test]
+fn scu_retries_after_abort() {
+    let scp = MockScp::new()
+        .respond(MockResponse::abort())
+        .respond(MockResponse::status(Status::PROCESSING_FAILURE))
+        .spawn()
+        .unwrap();
+
+    let attempts = echo_with_retry(scp.addr());
+    assert_eq!(attempts.len(), 2, "{:?}", attempts);
+    assert!(attempts[0].is_err());
+    assert_eq!(attempts[1], Ok(Status::PROCESSING_FAILURE));
+    assert_eq!(scp.received().len(), 2);
+}
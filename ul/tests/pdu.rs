@@ -1,8 +1,10 @@
 use dicom_ul::pdu::reader::read_pdu;
 use dicom_ul::pdu::writer::write_pdu;
 use dicom_ul::pdu::{
-    AssociationRQ, PDataValue, PDataValueType, Pdu, PresentationContextProposed, UserIdentity,
-    UserIdentityType, UserVariableItem, DEFAULT_MAX_PDU,
+    AssociationAC, AssociationRQ, AsyncOperationsWindow, PDataValue, PDataValueType, Pdu,
+    PresentationContextProposed, PresentationContextResult, PresentationContextResultReason,
+    RoleSelection, SopClassCommonExtendedNegotiation, UserIdentity, UserIdentityType,
+    UserVariableItem, DEFAULT_MAX_PDU,
 };
 use matches::matches;
 use std::io::Cursor;
@@ -40,6 +42,16 @@ fn can_read_write_associate_rq() -> Result<(), Box<dyn std::error::Error>> {
                 b"MyUsername".to_vec(),
                 b"MyPassword".to_vec(),
             )),
+            UserVariableItem::SopClassCommonExtendedNegotiationSubItem(
+                SopClassCommonExtendedNegotiation {
+                    sop_class_uid: "abstract 2".to_string(),
+                    service_class_uid: "service class".to_string(),
+                    related_general_sop_class_uids: vec![
+                        "related 1".to_string(),
+                        "related 2".to_string(),
+                    ],
+                },
+            ),
         ],
     };
 
@@ -73,7 +85,7 @@ fn can_read_write_associate_rq() -> Result<(), Box<dyn std::error::Error>> {
         assert_eq!(presentation_contexts[1].transfer_syntaxes.len(), 2);
         assert_eq!(presentation_contexts[1].transfer_syntaxes[0], "transfer 3");
         assert_eq!(presentation_contexts[1].transfer_syntaxes[1], "transfer 4");
-        assert_eq!(user_variables.len(), 5);
+        assert_eq!(user_variables.len(), 6);
         assert!(matches!(
             &user_variables[0],
             UserVariableItem::ImplementationClassUID(u) if u == "class uid"
@@ -95,6 +107,12 @@ fn can_read_write_associate_rq() -> Result<(), Box<dyn std::error::Error>> {
             user_identity.primary_field() == [77,121,85,115,101,114,110,97,109,101] &&
             user_identity.secondary_field() == [77,121,80,97,115,115,119,111,114,100]
         ));
+        assert!(matches!(&user_variables[5],
+            UserVariableItem::SopClassCommonExtendedNegotiationSubItem(negotiation)
+            if negotiation.sop_class_uid == "abstract 2" &&
+            negotiation.service_class_uid == "service class" &&
+            negotiation.related_general_sop_class_uids == ["related 1", "related 2"]
+        ));
     } else {
         panic!("invalid pdu type");
     }
@@ -176,8 +194,78 @@ fn can_read_write_primary_field_only_user_identity() -> Result<(), Box<dyn std::
             if !user_identity.positive_response_requested() &&
             user_identity.identity_type() == UserIdentityType::Username &&
             user_identity.primary_field() == [77,121,85,115,101,114,110,97,109,101] &&
-            user_identity.secondary_field() == []
+            user_identity.secondary_field().is_empty()
+        ));
+    } else {
+        panic!("invalid pdu type");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn can_read_write_negotiation_sub_items() -> Result<(), Box<dyn std::error::Error>> {
+    let association_ac = AssociationAC {
+        protocol_version: 1,
+        calling_ae_title: "calling ae".to_string(),
+        called_ae_title: "called ae".to_string(),
+        application_context_name: "application context name".to_string(),
+        presentation_contexts: vec![PresentationContextResult {
+            id: 1,
+            reason: PresentationContextResultReason::Acceptance,
+            transfer_syntax: "transfer 1".to_string(),
+        }],
+        user_variables: vec![
+            UserVariableItem::MaxLength(16384),
+            UserVariableItem::AsyncOperationsWindowSubItem(AsyncOperationsWindow {
+                max_operations_invoked: 8,
+                max_operations_performed: 0,
+            }),
+            UserVariableItem::RoleSelectionSubItem(RoleSelection {
+                sop_class_uid: "1.2.840.10008.5.1.4.1.1.2".to_string(),
+                scu_role: false,
+                scp_role: true,
+            }),
+            UserVariableItem::RoleSelectionSubItem(RoleSelection {
+                sop_class_uid: "1.2.840.10008.5.1.4.1.1.4".to_string(),
+                scu_role: true,
+                scp_role: true,
+            }),
+            UserVariableItem::UserIdentityServerResponseItem(b"ticket".to_vec()),
+        ],
+    };
+
+    let mut bytes = vec![0u8; 0];
+    write_pdu(&mut bytes, &association_ac.into())?;
+
+    let result = read_pdu(&mut Cursor::new(&bytes), DEFAULT_MAX_PDU, true)?.unwrap();
+
+    if let Pdu::AssociationAC(AssociationAC { user_variables, .. }) = result {
+        assert_eq!(user_variables.len(), 5);
+        assert!(matches!(user_variables[0], UserVariableItem::MaxLength(l) if l == 16384));
+        assert!(matches!(
+            user_variables[1],
+            UserVariableItem::AsyncOperationsWindowSubItem(AsyncOperationsWindow {
+                max_operations_invoked: 8,
+                max_operations_performed: 0,
+            })
+        ));
+        assert!(matches!(&user_variables[2],
+            UserVariableItem::RoleSelectionSubItem(role_selection)
+            if role_selection.sop_class_uid == "1.2.840.10008.5.1.4.1.1.2" &&
+            !role_selection.scu_role &&
+            role_selection.scp_role
         ));
+        assert!(matches!(&user_variables[3],
+            UserVariableItem::RoleSelectionSubItem(role_selection)
+            if role_selection.sop_class_uid == "1.2.840.10008.5.1.4.1.1.4" &&
+            role_selection.scu_role &&
+            role_selection.scp_role
+        ));
+        assert_eq!(
+            user_variables[4],
+            UserVariableItem::UserIdentityServerResponseItem(b"ticket".to_vec())
+        );
     } else {
         panic!("invalid pdu type");
     }
@@ -0,0 +1,696 @@
+//! Service class providers built with the SCP server framework.
+use dicom_ul::{
+    association::client::{self, ClientAssociationOptions},
+    dimse::{
+        CCancelRQ, CEchoRQ, CFindRQ, CGetRQ, CStoreRQ, CStoreRSP, Command, NActionRQ,
+        NEventReportRSP, Priority, Status, SubOperations,
+    },
+    scp::{
+        AssociationContext, EventReport, EventReports, FindOperation, GetOperation, RequestContext,
+        RetrieveOutcome, ScpServer, ServiceRegistry,
+    },
+    ServerAssociationOptions,
+};
+use std::{
+    io::Read,
+    net::{SocketAddr, TcpListener},
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+static SCU_AE_TITLE: &str = "FRAMEWORK-SCU";
+static SCP_AE_TITLE: &str = "FRAMEWORK-SCP";
+
+static IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+static VERIFICATION_SOP_CLASS: &str = "1.2.840.10008.1.1";
+static CT_IMAGE_STORAGE_SOP_CLASS: &str = "1.2.840.10008.5.1.4.1.1.2";
+static STUDY_ROOT_FIND_SOP_CLASS: &str = "1.2.840.10008.5.1.4.1.2.2.1";
+static STUDY_ROOT_GET_SOP_CLASS: &str = "1.2.840.10008.5.1.4.1.2.2.3";
+static STORAGE_COMMITMENT_SOP_CLASS: &str = "1.2.840.10008.1.20.1";
+static STORAGE_COMMITMENT_SOP_INSTANCE: &str = "1.2.840.10008.1.20.1.1";
+
+/// The SOP instance UIDs of the instances stored so far.
+type Stored = Arc<Mutex<Vec<String>>>;
+
+fn services(stored: Stored) -> ServiceRegistry {
+    ServiceRegistry::new()
+        .with_echo(|_: &RequestContext<'_>| Status::SUCCESS)
+        .with_store(
+            CT_IMAGE_STORAGE_SOP_CLASS,
+            move |context: &RequestContext<'_>, rq: &CStoreRQ, data: &mut dyn Read| {
+                assert_eq!(context.calling_ae_title, SCU_AE_TITLE);
+                assert_eq!(context.transfer_syntax, IMPLICIT_VR_LE);
+                let mut buf = Vec::new();
+                data.read_to_end(&mut buf).unwrap();
+                assert_eq!(buf, &[0x55; 1024][..]);
+                stored
+                    .lock()
+                    .unwrap()
+                    .push(rq.affected_sop_instance_uid.clone());
+                Status::SUCCESS
+            },
+        )
+        .with_find(
+            STUDY_ROOT_FIND_SOP_CLASS,
+            |_: &RequestContext<'_>,
+             _: &CFindRQ,
+             identifier: &[u8],
+             operation: &mut dyn FindOperation| {
+                // an identifier of 1 byte asks for matches until canceled
+                let count = if identifier.len() == 1 { 1000 } else { 2 };
+                for _ in 0..count {
+                    if operation.is_canceled() {
+                        return Status::CANCEL;
+                    }
+                    operation.send_match(identifier).unwrap();
+                    if count > 2 {
+                        std::thread::sleep(Duration::from_millis(5));
+                    }
+                }
+                Status::SUCCESS
+            },
+        )
+}
+
+fn scu_options() -> ClientAssociationOptions<'static> {
+    ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(VERIFICATION_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .with_presentation_context(CT_IMAGE_STORAGE_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .with_presentation_context(STUDY_ROOT_FIND_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .with_presentation_context(STUDY_ROOT_GET_SOP_CLASS, vec![IMPLICIT_VR_LE])
+}
+
+fn scp_server(stored: Stored) -> ScpServer<dicom_ul::association::server::AcceptAny> {
+    ScpServer::new(
+        ServerAssociationOptions::new().ae_title(SCP_AE_TITLE),
+        services(stored),
+    )
+}
+
+/// Start a server in a separate thread.
+fn spawn_scp(
+    server: ScpServer<dicom_ul::association::server::AcceptAny>,
+) -> (
+    std::thread::JoinHandle<()>,
+    dicom_ul::scp::ShutdownHandle,
+    SocketAddr,
+) {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+    let handle = std::thread::spawn(move || server.serve(listener).unwrap());
+    (handle, shutdown, addr)
+}
+
+#[test]
+fn scp_server_dispatches_requests() {
+    let stored = Stored::default();
+    let (scp_handle, shutdown, addr) = spawn_scp(scp_server(stored.clone()));
+
+    let mut association = scu_options().establish(addr).unwrap();
+    // presentation contexts are proposed in order with odd IDs,
+    // and C-GET is not among the registered services
+    let (echo_pc, store_pc, find_pc) = (1, 3, 5);
+    assert_eq!(
+        association
+            .presentation_contexts()
+            .iter()
+            .map(|pc| pc.id)
+            .collect::<Vec<_>>(),
+        vec![echo_pc, store_pc, find_pc]
+    );
+
+    let message_id = association.next_message_id();
+    let echo = Command::CEchoRQ(CEchoRQ {
+        message_id,
+        affected_sop_class_uid: VERIFICATION_SOP_CLASS.to_string(),
+    });
+    association.send_message(echo_pc, &echo, None).unwrap();
+    let response = association.receive_message().unwrap();
+    assert_eq!(response.command.status(), Some(Status::SUCCESS));
+
+    let message_id = association.next_message_id();
+    let store = Command::CStoreRQ(CStoreRQ {
+        message_id,
+        affected_sop_class_uid: CT_IMAGE_STORAGE_SOP_CLASS.to_string(),
+        affected_sop_instance_uid: "2.25.1234".to_string(),
+        priority: Priority::Medium,
+        move_originator_ae_title: None,
+        move_originator_message_id: None,
+    });
+    association
+        .send_message(store_pc, &store, Some(&[0x55; 1024]))
+        .unwrap();
+    let response = association.receive_message().unwrap();
+    assert_eq!(
+        response.command.message_id_being_responded_to(),
+        Some(message_id)
+    );
+    assert_eq!(response.command.status(), Some(Status::SUCCESS));
+
+    let message_id = association.next_message_id();
+    let find = Command::CFindRQ(CFindRQ {
+        message_id,
+        affected_sop_class_uid: STUDY_ROOT_FIND_SOP_CLASS.to_string(),
+        priority: Priority::Medium,
+    });
+    association
+        .send_message(find_pc, &find, Some(&[1, 2, 3, 4]))
+        .unwrap();
+    let mut matches = Vec::new();
+    loop {
+        let response = association.receive_message().unwrap();
+        match response.command.status() {
+            Some(Status::PENDING) => matches.push(response.data.unwrap()),
+            status => {
+                assert_eq!(status, Some(Status::SUCCESS));
+                break;
+            }
+        }
+    }
+    assert_eq!(matches, vec![vec![1, 2, 3, 4]; 2]);
+
+    // an operation without a handler for its SOP class is refused
+    let message_id = association.next_message_id();
+    let get = Command::CGetRQ(CGetRQ {
+        message_id,
+        affected_sop_class_uid: STUDY_ROOT_GET_SOP_CLASS.to_string(),
+        priority: Priority::Medium,
+    });
+    association
+        .send_message(find_pc, &get, Some(&[1, 2, 3, 4]))
+        .unwrap();
+    let response = association.receive_message().unwrap();
+    assert!(matches!(response.command, Command::CGetRSP(_)));
+    assert_eq!(
+        response.command.status(),
+        Some(Status::SOP_CLASS_NOT_SUPPORTED)
+    );
+
+    association.release().unwrap();
+    assert_eq!(*stored.lock().unwrap(), vec!["2.25.1234".to_string()]);
+
+    shutdown.shutdown();
+    scp_handle.join().expect("SCP panicked");
+}
+
+/// Send a C-FIND request which matches until canceled,
+/// and cancel it after the first match.
+/// Returns the number of matches received and the final status.
+fn find_canceled(addr: SocketAddr) -> (usize, Option<Status>) {
+    let mut association = scu_options().establish(addr).unwrap();
+    let find_pc = 5;
+    let message_id = association.next_message_id();
+    let find = Command::CFindRQ(CFindRQ {
+        message_id,
+        affected_sop_class_uid: STUDY_ROOT_FIND_SOP_CLASS.to_string(),
+        priority: Priority::Medium,
+    });
+    association
+        .send_message(find_pc, &find, Some(&[1]))
+        .unwrap();
+    let mut matches = 0;
+    let status = loop {
+        let response = association.receive_message().unwrap();
+        match response.command.status() {
+            Some(Status::PENDING) => {
+                if matches == 0 {
+                    let cancel = Command::CCancelRQ(CCancelRQ {
+                        message_id_being_responded_to: message_id,
+                    });
+                    association.send_message(find_pc, &cancel, None).unwrap();
+                }
+                matches += 1;
+            }
+            status => break status,
+        }
+    };
+    association.release().unwrap();
+    (matches, status)
+}
+
+/// A C-CANCEL request received during a C-FIND operation
+/// stops it before the next match.
+#[test]
+fn scp_server_cancels_find() {
+    let (scp_handle, shutdown, addr) = spawn_scp(scp_server(Stored::default()));
+
+    let (matches, status) = find_canceled(addr);
+    assert_eq!(status, Some(Status::CANCEL));
+    assert!(matches < 1000, "{} matches after cancel", matches);
+
+    shutdown.shutdown();
+    scp_handle.join().expect("SCP panicked");
+}
+
+/// Serve C-GET requests by storing three instances,
+/// reporting the progress after each one.
+fn get_server() -> ScpServer<dicom_ul::association::server::AcceptAny> {
+    let services = ServiceRegistry::new().with_get(
+        STUDY_ROOT_GET_SOP_CLASS,
+        |_: &RequestContext<'_>, rq: &CGetRQ, _: &[u8], operation: &mut dyn GetOperation| {
+            let pc = operation
+                .storage_contexts()
+                .into_iter()
+                .find(|pc| pc.abstract_syntax == CT_IMAGE_STORAGE_SOP_CLASS)
+                .unwrap();
+            let (mut remaining, mut completed, mut failed) = (3, 0, 0);
+            let sub_operations = |remaining, completed, failed| SubOperations {
+                remaining,
+                completed: Some(completed),
+                failed: Some(failed),
+                warning: Some(0),
+            };
+            for i in 0..3 {
+                if operation.is_canceled() {
+                    return RetrieveOutcome {
+                        status: Status::CANCEL,
+                        sub_operations: sub_operations(Some(remaining), completed, failed),
+                        identifier: None,
+                    };
+                }
+                let store = CStoreRQ {
+                    message_id: operation.next_message_id(),
+                    affected_sop_class_uid: CT_IMAGE_STORAGE_SOP_CLASS.to_string(),
+                    affected_sop_instance_uid: format!("2.25.{}", i),
+                    priority: rq.priority,
+                    move_originator_ae_title: None,
+                    move_originator_message_id: None,
+                };
+                let status = operation.store(pc.id, store, &[0x55; 1024]).unwrap();
+                remaining -= 1;
+                if status.is_success() {
+                    completed += 1;
+                } else {
+                    failed += 1;
+                }
+                operation
+                    .report(sub_operations(Some(remaining), completed, failed))
+                    .unwrap();
+            }
+            RetrieveOutcome {
+                status: if failed > 0 {
+                    Status(0xB000)
+                } else {
+                    Status::SUCCESS
+                },
+                sub_operations: sub_operations(None, completed, failed),
+                identifier: None,
+            }
+        },
+    );
+    let options = ServerAssociationOptions::new()
+        .ae_title(SCP_AE_TITLE)
+        .with_abstract_syntax(CT_IMAGE_STORAGE_SOP_CLASS)
+        .with_role_selection(CT_IMAGE_STORAGE_SOP_CLASS, true, true);
+    ScpServer::new(options, services)
+}
+
+/// Send a C-GET request and answer its C-STORE sub-operations
+/// with the given statuses,
+/// sending a C-CANCEL request before answering the first one if asked to.
+/// Returns the sub-operations of the pending responses
+/// and the final response.
+fn get(addr: SocketAddr, statuses: &[Status], cancel: bool) -> (Vec<SubOperations>, Command) {
+    let mut association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(STUDY_ROOT_GET_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .with_presentation_context(CT_IMAGE_STORAGE_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .with_role_selection(CT_IMAGE_STORAGE_SOP_CLASS, false, true)
+        .establish(addr)
+        .unwrap();
+    let message_id = association.next_message_id();
+    let get = Command::CGetRQ(CGetRQ {
+        message_id,
+        affected_sop_class_uid: STUDY_ROOT_GET_SOP_CLASS.to_string(),
+        priority: Priority::Medium,
+    });
+    association.send_message(1, &get, Some(&[1, 2])).unwrap();
+
+    let mut statuses = statuses.iter();
+    let mut pending = Vec::new();
+    let response = loop {
+        let message = association.receive_message().unwrap();
+        match message.command {
+            Command::CStoreRQ(rq) => {
+                assert_eq!(message.data.as_deref(), Some(&[0x55; 1024][..]));
+                if cancel && pending.is_empty() {
+                    let cancel = Command::CCancelRQ(CCancelRQ {
+                        message_id_being_responded_to: message_id,
+                    });
+                    association.send_message(1, &cancel, None).unwrap();
+                }
+                let rsp = Command::CStoreRSP(CStoreRSP {
+                    message_id_being_responded_to: rq.message_id,
+                    affected_sop_class_uid: rq.affected_sop_class_uid,
+                    affected_sop_instance_uid: rq.affected_sop_instance_uid,
+                    status: *statuses.next().unwrap(),
+                });
+                association
+                    .send_message(message.presentation_context_id, &rsp, None)
+                    .unwrap();
+            }
+            Command::CGetRSP(rsp) if rsp.status == Status::PENDING => {
+                pending.push(rsp.sub_operations);
+            }
+            command => break command,
+        }
+    };
+    association.release().unwrap();
+    (pending, response)
+}
+
+/// C-GET sub-operations are carried out in the same association,
+/// with a pending response after each one.
+#[test]
+fn scp_server_retrieves_instances() {
+    let (scp_handle, shutdown, addr) = spawn_scp(get_server());
+
+    let statuses = [Status::SUCCESS, Status::PROCESSING_FAILURE, Status::SUCCESS];
+    let (pending, response) = get(addr, &statuses, false);
+    assert_eq!(
+        pending.iter().map(|s| s.remaining).collect::<Vec<_>>(),
+        vec![Some(2), Some(1), Some(0)]
+    );
+    let Command::CGetRSP(rsp) = response else {
+        panic!("unexpected {:?}", response);
+    };
+    assert_eq!(rsp.status, Status(0xB000));
+    assert_eq!(rsp.sub_operations.completed, Some(2));
+    assert_eq!(rsp.sub_operations.failed, Some(1));
+
+    shutdown.shutdown();
+    scp_handle.join().expect("SCP panicked");
+}
+
+/// A C-CANCEL request received during a C-GET operation
+/// stops it after the current sub-operation.
+#[test]
+fn scp_server_cancels_retrieve() {
+    let (scp_handle, shutdown, addr) = spawn_scp(get_server());
+
+    let (pending, response) = get(addr, &[Status::SUCCESS], true);
+    assert_eq!(pending.len(), 1);
+    let Command::CGetRSP(rsp) = response else {
+        panic!("unexpected {:?}", response);
+    };
+    assert_eq!(rsp.status, Status::CANCEL);
+    assert_eq!(rsp.sub_operations.remaining, Some(2));
+    assert_eq!(rsp.sub_operations.completed, Some(1));
+
+    shutdown.shutdown();
+    scp_handle.join().expect("SCP panicked");
+}
+
+/// Associations are released when the server shuts down.
+#[test]
+fn scp_server_shutdown_releases_associations() {
+    let (scp_handle, shutdown, addr) = spawn_scp(scp_server(Stored::default()));
+
+    let mut association = scu_options().establish(addr).unwrap();
+    shutdown.shutdown();
+    let err = association.receive_message().unwrap_err();
+    assert!(matches!(err, client::Error::Released { .. }), "{:?}", err);
+
+    scp_handle.join().expect("SCP panicked");
+}
+
+/// Associations without any activity are released after the idle timeout.
+#[test]
+fn scp_server_releases_idle_associations() {
+    let server = scp_server(Stored::default()).idle_timeout(Duration::from_millis(300));
+    let (scp_handle, shutdown, addr) = spawn_scp(server);
+
+    let mut association = scu_options().establish(addr).unwrap();
+    let err = association.receive_message().unwrap_err();
+    assert!(matches!(err, client::Error::Released { .. }), "{:?}", err);
+
+    shutdown.shutdown();
+    scp_handle.join().expect("SCP panicked");
+}
+
+/// Connections beyond the maximum number of associations
+/// wait for an ongoing association to end.
+#[test]
+fn scp_server_limits_associations() {
+    let server = scp_server(Stored::default()).max_associations(1);
+    let (scp_handle, shutdown, addr) = spawn_scp(server);
+
+    let first = scu_options().establish(addr).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let second_handle = std::thread::spawn(move || {
+        let second = scu_options().establish(addr).unwrap();
+        tx.send(()).unwrap();
+        second.release().unwrap();
+    });
+
+    assert!(rx.recv_timeout(Duration::from_millis(500)).is_err());
+    first.release().unwrap();
+    rx.recv_timeout(Duration::from_secs(5))
+        .expect("second association should be accepted");
+    second_handle.join().unwrap();
+
+    shutdown.shutdown();
+    scp_handle.join().expect("SCP panicked");
+}
+
+/// What happened to the event reports of an association
+/// and to the association itself.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    /// the status of an event report response, if received
+    Reported(u64, Option<Status>),
+    Ended(u64),
+}
+
+/// Serve N-ACTION requests by sending an event report
+/// with the action information,
+/// passing on what happened to it and to the association.
+fn event_server(
+    outcomes: mpsc::Sender<Outcome>,
+) -> ScpServer<dicom_ul::association::server::AcceptAny> {
+    let ended = Mutex::new(outcomes.clone());
+    let outcomes = Mutex::new(outcomes);
+    let services = ServiceRegistry::new()
+        .with_action(
+            STORAGE_COMMITMENT_SOP_CLASS,
+            move |context: &RequestContext<'_>,
+                  rq: &NActionRQ,
+                  data: &[u8],
+                  events: &mut EventReports| {
+                let response = events.send(EventReport {
+                    affected_sop_class_uid: rq.requested_sop_class_uid.clone(),
+                    affected_sop_instance_uid: rq.requested_sop_instance_uid.clone(),
+                    event_type_id: 1,
+                    data: Some(data.to_vec()),
+                });
+                let association_id = context.association_id;
+                let outcomes = outcomes.lock().unwrap().clone();
+                // the report is only sent after the response
+                std::thread::spawn(move || {
+                    let status = response.recv().ok();
+                    outcomes
+                        .send(Outcome::Reported(association_id, status))
+                        .unwrap();
+                });
+                Status::SUCCESS
+            },
+        )
+        .with_association_end(move |context: &AssociationContext<'_>| {
+            assert_eq!(context.calling_ae_title, SCU_AE_TITLE);
+            ended
+                .lock()
+                .unwrap()
+                .send(Outcome::Ended(context.association_id))
+                .unwrap();
+        });
+    ScpServer::new(
+        ServerAssociationOptions::new().ae_title(SCP_AE_TITLE),
+        services,
+    )
+}
+
+/// Send an N-ACTION request and respond to its event report,
+/// unless the association is aborted right after the N-ACTION response.
+fn request_event_report(addr: SocketAddr, respond: bool) {
+    let mut association = ClientAssociationOptions::new()
+        .calling_ae_title(SCU_AE_TITLE)
+        .called_ae_title(SCP_AE_TITLE)
+        .with_presentation_context(STORAGE_COMMITMENT_SOP_CLASS, vec![IMPLICIT_VR_LE])
+        .establish(addr)
+        .unwrap();
+    let pc_id = association.presentation_contexts()[0].id;
+    let message_id = association.next_message_id();
+    let action = Command::NActionRQ(NActionRQ {
+        message_id,
+        requested_sop_class_uid: STORAGE_COMMITMENT_SOP_CLASS.to_string(),
+        requested_sop_instance_uid: STORAGE_COMMITMENT_SOP_INSTANCE.to_string(),
+        action_type_id: 1,
+    });
+    association
+        .send_message(pc_id, &action, Some(&[0x20; 16]))
+        .unwrap();
+    let response = association.receive_message().unwrap();
+    assert!(matches!(response.command, Command::NActionRSP(_)));
+    assert_eq!(response.command.status(), Some(Status::SUCCESS));
+    if !respond {
+        association.abort().unwrap();
+        return;
+    }
+
+    let report = association.receive_message().unwrap();
+    let Command::NEventReportRQ(rq) = report.command else {
+        panic!("expected N-EVENT-REPORT request, got {:?}", report.command);
+    };
+    assert_eq!(
+        rq.affected_sop_instance_uid,
+        STORAGE_COMMITMENT_SOP_INSTANCE
+    );
+    assert_eq!(report.data.as_deref(), Some(&[0x20; 16][..]));
+    let response = Command::NEventReportRSP(NEventReportRSP {
+        message_id_being_responded_to: rq.message_id,
+        affected_sop_class_uid: Some(rq.affected_sop_class_uid),
+        affected_sop_instance_uid: Some(rq.affected_sop_instance_uid),
+        event_type_id: Some(rq.event_type_id),
+        status: Status::SUCCESS,
+    });
+    association
+        .send_message(report.presentation_context_id, &response, None)
+        .unwrap();
+    association.release().unwrap();
+}
+
+/// Check the outcomes of one association requesting an event report.
+fn check_event_outcomes(outcomes: &mpsc::Receiver<Outcome>, responded: bool) {
+    let timeout = Duration::from_secs(5);
+    let mut outcomes = vec![
+        outcomes.recv_timeout(timeout).unwrap(),
+        outcomes.recv_timeout(timeout).unwrap(),
+    ];
+    outcomes.sort_by_key(|outcome| matches!(outcome, Outcome::Ended(_)));
+    let Outcome::Ended(association_id) = outcomes[1] else {
+        panic!("association did not end: {:?}", outcomes);
+    };
+    let status = responded.then_some(Status::SUCCESS);
+    assert_eq!(outcomes[0], Outcome::Reported(association_id, status));
+}
+
+/// Event reports follow the response to an N-ACTION request,
+/// and the association end handler is notified once the association ends.
+#[test]
+fn scp_server_reports_events() {
+    let (sender, outcomes) = mpsc::channel();
+    let (scp_handle, shutdown, addr) = spawn_scp(event_server(sender));
+
+    request_event_report(addr, true);
+    check_event_outcomes(&outcomes, true);
+
+    // the report is left without a response
+    request_event_report(addr, false);
+    check_event_outcomes(&outcomes, false);
+
+    shutdown.shutdown();
+    scp_handle.join().expect("SCP panicked");
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread")]
+async fn scp_server_async() {
+    let stored = Stored::default();
+    let server = scp_server(stored.clone());
+    let shutdown = server.shutdown_handle();
+    let listener = tokio::net::TcpListener::bind("localhost:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let scp_handle = tokio::spawn(async move { server.serve_async(listener).await.unwrap() });
+
+    let mut association = scu_options().establish_async(addr).await.unwrap();
+    let message_id = association.next_message_id();
+    let store = Command::CStoreRQ(CStoreRQ {
+        message_id,
+        affected_sop_class_uid: CT_IMAGE_STORAGE_SOP_CLASS.to_string(),
+        affected_sop_instance_uid: "2.25.5678".to_string(),
+        priority: Priority::Medium,
+        move_originator_ae_title: None,
+        move_originator_message_id: None,
+    });
+    association
+        .send_message(3, &store, Some(&[0x55; 1024]))
+        .await
+        .unwrap();
+    let response = association.receive_message().await.unwrap();
+    assert_eq!(response.command.status(), Some(Status::SUCCESS));
+    assert_eq!(*stored.lock().unwrap(), vec!["2.25.5678".to_string()]);
+
+    // an idle association is released on shutdown
+    shutdown.shutdown();
+    let err = association.receive_message().await.unwrap_err();
+    assert!(matches!(err, client::Error::Released { .. }), "{:?}", err);
+
+    scp_handle.await.expect("SCP panicked");
+}
+
+/// Retrieve and cancel requests are served in asynchronous associations
+/// while the handlers run in blocking tasks.
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread")]
+async fn scp_server_async_operations() {
+    let listener = tokio::net::TcpListener::bind("localhost:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = get_server();
+    let shutdown = server.shutdown_handle();
+    let scp_handle = tokio::spawn(async move { server.serve_async(listener).await.unwrap() });
+
+    let statuses = [Status::SUCCESS, Status::SUCCESS, Status::SUCCESS];
+    let (pending, response) = tokio::task::spawn_blocking(move || get(addr, &statuses, false))
+        .await
+        .unwrap();
+    assert_eq!(pending.len(), 3);
+    assert_eq!(response.status(), Some(Status::SUCCESS));
+
+    let (pending, response) = tokio::task::spawn_blocking(move || get(addr, &statuses, true))
+        .await
+        .unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(response.status(), Some(Status::CANCEL));
+
+    shutdown.shutdown();
+    scp_handle.await.expect("SCP panicked");
+
+    let listener = tokio::net::TcpListener::bind("localhost:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = scp_server(Stored::default());
+    let shutdown = server.shutdown_handle();
+    let scp_handle = tokio::spawn(async move { server.serve_async(listener).await.unwrap() });
+
+    let (matches, status) = tokio::task::spawn_blocking(move || find_canceled(addr))
+        .await
+        .unwrap();
+    assert_eq!(status, Some(Status::CANCEL));
+    assert!(matches < 1000, "{} matches after cancel", matches);
+
+    shutdown.shutdown();
+    scp_handle.await.expect("SCP panicked");
+
+    let listener = tokio::net::TcpListener::bind("localhost:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (sender, outcomes) = mpsc::channel();
+    let server = event_server(sender);
+    let shutdown = server.shutdown_handle();
+    let scp_handle = tokio::spawn(async move { server.serve_async(listener).await.unwrap() });
+
+    tokio::task::spawn_blocking(move || {
+        request_event_report(addr, true);
+        check_event_outcomes(&outcomes, true);
+        request_event_report(addr, false);
+        check_event_outcomes(&outcomes, false);
+    })
+    .await
+    .unwrap();
+
+    shutdown.shutdown();
+    scp_handle.await.expect("SCP panicked");
+}